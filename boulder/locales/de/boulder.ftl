@@ -0,0 +1,12 @@
+# Deutsche Meldungen von boulder
+
+error = Fehler
+
+sharing-upstreams = { $count ->
+    [one] { $count } Quelle wird mit dem Build-Container geteilt
+   *[other] { $count } Quellen werden mit dem Build-Container geteilt
+}
+packaging = Paketierung
+
+diagnostics-none-new = Keine neuen Diagnosen seit dem vorherigen Build
+diagnostics-new = Neu seit dem vorherigen Build:
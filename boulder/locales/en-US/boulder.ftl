@@ -0,0 +1,14 @@
+# Messages of the boulder CLI, see crates/i18n
+#
+# Every message must be defined here, translations fall back to it.
+
+error = Error
+
+sharing-upstreams = { $count ->
+    [one] Sharing { $count } upstream with the build container
+   *[other] Sharing { $count } upstreams with the build container
+}
+packaging = Packaging
+
+diagnostics-none-new = No new diagnostics since the previous build
+diagnostics-new = New since the previous build:
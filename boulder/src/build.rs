@@ -11,7 +11,10 @@ use std::{
 };
 
 use itertools::Itertools;
-use moss::runtime;
+use moss::{
+    runtime,
+    signature::{self, SigningKey},
+};
 use nix::{
     sys::signal::Signal,
     unistd::{getpgrp, setpgid, Pid},
@@ -23,10 +26,13 @@ use stone_recipe::{
 use thiserror::Error;
 use tui::Styled;
 
+pub mod diagnostics;
+pub mod hardening;
+pub mod host;
 pub mod job;
 pub mod pgo;
 mod root;
-mod upstream;
+pub mod upstream;
 
 use self::job::Job;
 use crate::{
@@ -105,7 +111,27 @@ impl Builder {
         })
     }
 
-    pub fn setup(&self, timing: &mut Timing, initialize_timer: timing::Timer, update_repos: bool) -> Result<(), Error> {
+    /// Key packages are signed with, if configured for the build profile
+    ///
+    /// Read ahead of packaging, as it's usually not available within the container
+    pub fn signing_key(&self) -> Result<Option<SigningKey>, Error> {
+        let profiles = profile::Manager::new(&self.env);
+
+        profiles
+            .signing_key(&self.profile)?
+            .map(SigningKey::read)
+            .transpose()
+            .map_err(Error::SigningKey)
+    }
+
+    /// Prepare the rootfs & upstreams for building, returning a [`host::Report`]
+    /// of the environment the build runs in
+    pub fn setup(
+        &self,
+        timing: &mut Timing,
+        initialize_timer: timing::Timer,
+        update_repos: bool,
+    ) -> Result<host::Report, Error> {
         // Remove old artifacts
         util::recreate_dir(&self.paths.artefacts().host).map_err(Error::RecreateArtefactsDir)?;
 
@@ -118,15 +144,13 @@ impl Builder {
         let repos = profiles.repositories(&self.profile)?.clone();
 
         // Populate rootfs
-        root::populate(self, repos, timing, initialize_timer, update_repos)?;
+        let host = root::populate(self, repos, timing, initialize_timer, update_repos)?;
 
         let timer = timing.begin(timing::Kind::Fetch);
 
         // Sync (fetch & share) upstreams to rootfs
         upstream::sync(&self.recipe, &self.paths)?;
 
-        timing.finish(timer);
-
         drop(rt);
         // We want to ensure no threads exist before
         // cloning into container. Sometimes a deadlock
@@ -139,7 +163,12 @@ impl Builder {
         // it occurred within 10 attempts.
         thread::sleep(Duration::from_millis(50));
 
-        Ok(())
+        // Run upstream hooks in the container
+        upstream::transform(&self.recipe, &self.paths)?;
+
+        timing.finish(timer);
+
+        Ok(host)
     }
 
     pub fn build(&self, timing: &mut Timing) -> Result<(), Error> {
@@ -153,6 +182,8 @@ impl Builder {
         let pgid = getpgrp();
         ::container::set_term_fg(pgid)?;
 
+        let mut diagnostics = diagnostics::Summary::default();
+
         for (i, target) in self.targets.iter().enumerate() {
             println!("{}", build_target_prefix(target.build_target, i));
 
@@ -228,7 +259,7 @@ impl Builder {
                                 let script_path = "/tmp/script";
                                 std::fs::write(script_path, content).unwrap();
 
-                                let result = logged(*phase, is_pgo, "/bin/sh", |command| {
+                                let result = logged(*phase, is_pgo, &mut diagnostics, "/bin/sh", |command| {
                                     command
                                         .arg(script_path)
                                         .env_clear()
@@ -266,6 +297,8 @@ impl Builder {
 
         println!();
 
+        diagnostics.save(&self.paths.build().guest.join(diagnostics::FILENAME))?;
+
         Ok(())
     }
 }
@@ -296,6 +329,7 @@ pub fn phase_prefix(phase: job::Phase, is_pgo: bool, i: usize) -> String {
 fn logged(
     phase: job::Phase,
     is_pgo: bool,
+    diagnostics: &mut diagnostics::Summary,
     command: &str,
     f: impl FnOnce(&mut process::Command) -> &mut process::Command,
 ) -> Result<process::ExitStatus, io::Error> {
@@ -317,13 +351,16 @@ fn logged(
 
     let result = child.wait()?;
 
-    let _ = stdout_log.join();
-    let _ = stderr_log.join();
+    for log in [stdout_log, stderr_log] {
+        if let Ok(summary) = log.join() {
+            diagnostics.merge(summary);
+        }
+    }
 
     Ok(result)
 }
 
-fn log<R>(phase: job::Phase, is_pgo: bool, pipe: R) -> thread::JoinHandle<()>
+fn log<R>(phase: job::Phase, is_pgo: bool, pipe: R) -> thread::JoinHandle<diagnostics::Summary>
 where
     R: io::Read + Send + 'static,
 {
@@ -335,10 +372,14 @@ where
         let tag = format!("{}{pgo}{kind}", "│".dim());
 
         let mut lines = io::BufReader::new(pipe).lines();
+        let mut diagnostics = diagnostics::Summary::default();
 
         while let Some(Ok(line)) = lines.next() {
             println!("{tag} {line}");
+            diagnostics.add(&line);
         }
+
+        diagnostics
     })
 }
 
@@ -457,10 +498,14 @@ pub enum Error {
     Signal(Signal),
     #[error("stopped by unknown signal")]
     UnknownSignal,
+    #[error("diagnostics")]
+    Diagnostics(#[from] diagnostics::Error),
     #[error("nix")]
     Nix(#[from] nix::Error),
     #[error("io")]
     Io(#[from] io::Error),
     #[error("recreate artefacts dir")]
     RecreateArtefactsDir(#[source] io::Error),
+    #[error("signing key")]
+    SigningKey(#[source] signature::Error),
 }
@@ -0,0 +1,282 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Classify notable lines of build output (compiler warnings & errors,
+//! deprecation notices and missing optional dependencies) so each build
+//! can be summarized & compared against the previous build of the recipe
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tui::Styled;
+
+use crate::{messages, util, Paths, Recipe};
+
+/// Name of the summary written to the build dir of the current build
+pub const FILENAME: &str = "diagnostics.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, strum::Display)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum Kind {
+    Error,
+    Warning,
+    Deprecation,
+    MissingDependency,
+}
+
+impl Kind {
+    /// Classify a single line of build output
+    pub fn classify(line: &str) -> Option<Self> {
+        static PATTERNS: OnceLock<Vec<(Kind, Regex)>> = OnceLock::new();
+
+        let patterns = PATTERNS.get_or_init(|| {
+            [
+                // meson, cmake & compiler `-Wdeprecated-*` warnings
+                (
+                    Kind::Deprecation,
+                    r"(?i)^DEPRECATION:|CMake Deprecation Warning|\bdeprecated\b.*\bwarning\b|\bwarning\b.*\bdeprecated\b",
+                ),
+                // meson, cmake & pkg-config lookups which didn't find anything
+                (
+                    Kind::MissingDependency,
+                    r"(?i)\bdependency\b.*\bfound: NO\b|Could NOT find \S+|No package '[^']+' found",
+                ),
+                (
+                    Kind::Error,
+                    r"(?i)(^|: )(fatal )?error(\[E\d+\])?: |^CMake Error\b|^ERROR: ",
+                ),
+                (Kind::Warning, r"(?i)(^|: )warning(\[\w+\])?: |^CMake Warning\b|^WARNING: "),
+            ]
+            .into_iter()
+            .map(|(kind, pattern)| (kind, Regex::new(pattern).expect("valid regex")))
+            .collect()
+        });
+
+        patterns
+            .iter()
+            .find_map(|(kind, pattern)| pattern.is_match(line).then_some(*kind))
+    }
+}
+
+/// A classified line of build output
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub kind: Kind,
+    pub message: String,
+    /// Number of times the message occurred throughout the build
+    pub count: usize,
+}
+
+/// All [`Diagnostic`]s of a single build
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Summary {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Summary {
+    /// Classify `line` and record it if it's notable
+    pub fn add(&mut self, line: &str) {
+        let line = strip_ansi(line);
+        let message = line.trim();
+
+        let Some(kind) = Kind::classify(message) else {
+            return;
+        };
+
+        if let Some(existing) = self
+            .diagnostics
+            .iter_mut()
+            .find(|d| d.kind == kind && d.message == message)
+        {
+            existing.count += 1;
+        } else {
+            self.diagnostics.push(Diagnostic {
+                kind,
+                message: message.to_string(),
+                count: 1,
+            });
+        }
+    }
+
+    /// Merge the diagnostics of `other` into this summary
+    pub fn merge(&mut self, other: Summary) {
+        for diagnostic in other.diagnostics {
+            if let Some(existing) = self
+                .diagnostics
+                .iter_mut()
+                .find(|d| d.kind == diagnostic.kind && d.message == diagnostic.message)
+            {
+                existing.count += diagnostic.count;
+            } else {
+                self.diagnostics.push(diagnostic);
+            }
+        }
+    }
+
+    /// Number of unique diagnostics of each [`Kind`]
+    pub fn counts(&self) -> BTreeMap<Kind, usize> {
+        self.diagnostics.iter().fold(BTreeMap::new(), |mut counts, d| {
+            *counts.entry(d.kind).or_default() += 1;
+            counts
+        })
+    }
+
+    /// Diagnostics which don't occur in `previous`
+    ///
+    /// Messages are compared without line & column numbers so
+    /// unrelated changes to a source file don't mark its warnings
+    /// as new
+    pub fn introduced_since<'a>(&'a self, previous: &Summary) -> Vec<&'a Diagnostic> {
+        let known = previous
+            .diagnostics
+            .iter()
+            .map(|d| (d.kind, normalize(&d.message)))
+            .collect::<Vec<_>>();
+
+        self.diagnostics
+            .iter()
+            .filter(|d| !known.contains(&(d.kind, normalize(&d.message))))
+            .collect()
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let content = fs::read(path).map_err(|e| Error::Read(path.to_path_buf(), e))?;
+        Ok(serde_json::from_slice(&content)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        if let Some(parent) = path.parent() {
+            util::ensure_dir_exists(parent).map_err(|e| Error::Write(parent.to_path_buf(), e))?;
+        }
+        fs::write(path, serde_json::to_vec_pretty(self)?).map_err(|e| Error::Write(path.to_path_buf(), e))
+    }
+
+    /// Print the summary, listing all diagnostics introduced since `previous`
+    pub fn print(&self, previous: Option<&Summary>) {
+        let counts = self.counts();
+
+        if counts.is_empty() {
+            println!("{} no warnings", "Diagnostics".bold());
+            return;
+        }
+
+        println!(
+            "{} {}",
+            "Diagnostics".bold(),
+            counts
+                .iter()
+                .map(|(kind, count)| format!("{count} {kind}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        let Some(previous) = previous else {
+            return;
+        };
+
+        let introduced = self.introduced_since(previous);
+
+        if introduced.is_empty() {
+            println!("{}", messages::get("diagnostics-none-new"));
+            return;
+        }
+
+        println!("{}", messages::get("diagnostics-new"));
+        for diagnostic in introduced {
+            println!(" {} {}", format!("{}", diagnostic.kind).yellow(), diagnostic.message);
+        }
+    }
+}
+
+/// Print the [`Summary`] of the build that just ran inside the container
+/// against the previous build of the same recipe, then record it as the
+/// new baseline
+pub fn report(paths: &Paths, recipe: &Recipe) -> Result<(), Error> {
+    let summary = Summary::load(&paths.build().host.join(FILENAME))?;
+
+    let history = paths.diagnostics().join(format!("{}.json", recipe.parsed.source.name));
+    // Missing or unreadable history just means there's nothing to compare against
+    let previous = Summary::load(&history).ok();
+
+    summary.print(previous.as_ref());
+    summary.save(&history)
+}
+
+fn strip_ansi(line: &str) -> std::borrow::Cow<'_, str> {
+    static ANSI: OnceLock<Regex> = OnceLock::new();
+
+    ANSI.get_or_init(|| Regex::new(r"\x1b\[[0-9;]*[A-Za-z]").expect("valid regex"))
+        .replace_all(line, "")
+}
+
+/// Remove `:line:col` locations from a message
+fn normalize(message: &str) -> String {
+    static LOCATION: OnceLock<Regex> = OnceLock::new();
+
+    LOCATION
+        .get_or_init(|| Regex::new(r":\d+(:\d+)?(:|\b)").expect("valid regex"))
+        .replace_all(message, ":")
+        .into_owned()
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("read diagnostics {0:?}")]
+    Read(PathBuf, #[source] io::Error),
+    #[error("write diagnostics {0:?}")]
+    Write(PathBuf, #[source] io::Error),
+    #[error("json")]
+    Json(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn classify_and_diff() {
+        let mut previous = Summary::default();
+        previous.add("../src/main.c:10:5: warning: unused variable 'x' [-Wunused-variable]");
+
+        let mut summary = Summary::default();
+        for line in [
+            "../src/main.c:12:5: warning: unused variable 'x' [-Wunused-variable]",
+            "../src/util.c:3:1: warning: implicit declaration of function 'foo' [-Wimplicit-function-declaration]",
+            "../src/util.c:8:2: warning: 'bar' is deprecated [-Wdeprecated-declarations]",
+            "\x1b[1mfoo.c:1:1: \x1b[31merror: \x1b[0mexpected ';'",
+            "Run-time dependency libsystemd found: NO (tried pkgconfig)",
+            "-- Could NOT find Doxygen (missing: DOXYGEN_EXECUTABLE)",
+            "checking for gcc... gcc",
+            "CC main.o",
+        ] {
+            summary.add(line);
+        }
+
+        let kinds = summary.diagnostics.iter().map(|d| d.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                Kind::Warning,
+                Kind::Warning,
+                Kind::Deprecation,
+                Kind::Error,
+                Kind::MissingDependency,
+                Kind::MissingDependency,
+            ]
+        );
+        assert_eq!(summary.diagnostics[3].message, "foo.c:1:1: error: expected ';'");
+
+        // The moved `unused variable` warning isn't new
+        let introduced = summary.introduced_since(&previous);
+        assert_eq!(introduced.len(), 5);
+        assert!(introduced.iter().all(|d| !d.message.contains("unused variable")));
+    }
+}
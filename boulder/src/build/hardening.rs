@@ -0,0 +1,204 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Centrally managed hardening features
+//!
+//! Architecture macros declare which tuning groups are `hardeningGroups`
+//! (i.e. fortify, stack clash & control flow protection). These are always
+//! enabled, unless a recipe disables them through `hardening` along with a
+//! justification, which is recorded in the build manifest.
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use stone_recipe::Tuning;
+use thiserror::Error;
+use tui::Styled;
+
+use crate::{architecture::BuildTarget, Macros, Recipe};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case", tag = "status")]
+pub enum Status {
+    Enabled,
+    /// Disabled by the recipe, for the given reason
+    Disabled {
+        reason: String,
+    },
+}
+
+/// Status of each hardening feature for a build target
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct Report {
+    pub features: BTreeMap<String, Status>,
+}
+
+impl Report {
+    /// Resolve the hardening features of `target` for `recipe`
+    pub fn new(target: BuildTarget, recipe: &Recipe, macros: &Macros) -> Result<Self, Error> {
+        let features = groups(target, macros)
+            .iter()
+            .map(|group| {
+                // Tuning can't carry a justification, so it mustn't disable hardening
+                if recipe
+                    .parsed
+                    .tuning
+                    .iter()
+                    .any(|kv| &kv.key == group && matches!(kv.value, Tuning::Disable))
+                {
+                    return Err(Error::MissingJustification(group.clone()));
+                }
+
+                let status = match recipe.parsed.hardening.iter().find(|kv| &kv.key == group) {
+                    Some(toggle) if !toggle.value.enabled => {
+                        let reason = toggle
+                            .value
+                            .reason
+                            .clone()
+                            .filter(|reason| !reason.trim().is_empty())
+                            .ok_or_else(|| Error::MissingJustification(group.clone()))?;
+
+                        Status::Disabled { reason }
+                    }
+                    _ => Status::Enabled,
+                };
+
+                Ok((group.clone(), status))
+            })
+            .collect::<Result<_, Error>>()?;
+
+        Ok(Self { features })
+    }
+
+    /// Print the report as part of the post-build summary
+    pub fn print(&self, target: BuildTarget) {
+        let enabled = self
+            .features
+            .iter()
+            .filter(|(_, status)| matches!(status, Status::Enabled))
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+
+        let enabled = if enabled.is_empty() {
+            "none".to_string()
+        } else {
+            enabled.join(", ")
+        };
+        println!("{} {target}: {enabled}", "Hardening".bold());
+
+        for (name, status) in &self.features {
+            match status {
+                Status::Enabled => {}
+                Status::Disabled { reason } => {
+                    println!(" {} {name}: {reason}", "disabled".yellow());
+                }
+            }
+        }
+    }
+}
+
+/// Hardening groups of `target`, falling back to those of `base`
+fn groups(target: BuildTarget, macros: &Macros) -> &[String] {
+    let build_target = target.to_string();
+
+    for arch in [&build_target, "base"] {
+        if let Some(groups) = macros.arch.get(arch).and_then(|m| m.hardening_groups.as_deref()) {
+            return groups;
+        }
+    }
+
+    &[]
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("hardening feature {0} can only be disabled under `hardening`, with a reason")]
+    MissingJustification(String),
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use chrono::Utc;
+
+    use super::*;
+    use crate::Architecture;
+
+    fn arch_macros(content: &str) -> stone_recipe::Macros {
+        stone_recipe::macros::from_slice(content.as_bytes()).unwrap()
+    }
+
+    fn recipe(content: &str) -> Recipe {
+        let source = format!(
+            "name: nano\nversion: 8.0\nrelease: 1\nhomepage: https://nano-editor.org\nlicense: GPL-3.0-or-later\n{content}"
+        );
+
+        Recipe {
+            path: PathBuf::from("stone.yaml"),
+            parsed: stone_recipe::from_str(&source).unwrap(),
+            source,
+            build_time: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn resolve() {
+        let macros = Macros {
+            arch: [
+                (
+                    "base".to_string(),
+                    arch_macros("hardeningGroups: [fortify, stack-clash]"),
+                ),
+                (
+                    "x86_64".to_string(),
+                    arch_macros("hardeningGroups: [cf-protection, fortify, stack-clash]"),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+            actions: vec![],
+        };
+        let x86_64 = BuildTarget::Native(Architecture::X86_64);
+        let emul32 = BuildTarget::Emul32(Architecture::X86_64);
+
+        let recipe = recipe(
+            "hardening:\n  - cf-protection:\n      enabled: false\n      reason: JIT\n  - fortify:\n      enabled: true\n",
+        );
+
+        let report = Report::new(x86_64, &recipe, &macros).unwrap();
+        assert_eq!(
+            report.features,
+            [
+                (
+                    "cf-protection".to_string(),
+                    Status::Disabled {
+                        reason: "JIT".to_string()
+                    }
+                ),
+                ("fortify".to_string(), Status::Enabled),
+                ("stack-clash".to_string(), Status::Enabled),
+            ]
+            .into_iter()
+            .collect()
+        );
+
+        // Falls back to base
+        let report = Report::new(emul32, &recipe, &macros).unwrap();
+        assert_eq!(report.features.len(), 2);
+
+        let unjustified = self::recipe("hardening:\n  - stack-clash:\n      enabled: false\n");
+        assert!(matches!(
+            Report::new(x86_64, &unjustified, &macros),
+            Err(Error::MissingJustification(name)) if name == "stack-clash"
+        ));
+
+        // Tuning bypassing the justification is refused just the same
+        let tuned = self::recipe("tuning:\n  - fortify: false\n");
+        assert!(matches!(
+            Report::new(x86_64, &tuned, &macros),
+            Err(Error::MissingJustification(name)) if name == "fortify"
+        ));
+    }
+}
@@ -0,0 +1,145 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Report of the host environment a build ran on, embedded into the
+//! build manifest so differences between builders can be diagnosed
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::Path,
+};
+
+use moss::repository;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::architecture;
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Report {
+    pub architecture: String,
+    pub kernel: String,
+    pub kernel_version: String,
+    pub cpu_features: Vec<String>,
+    pub boulder_version: String,
+    pub moss_version: String,
+    /// Index revision of each profile repository the rootfs was resolved against
+    pub repositories: BTreeMap<String, String>,
+    /// Sha256 digest of the build root image, see [`image_digest`]
+    pub rootfs: String,
+}
+
+impl Report {
+    /// Report on the host, building within the freshly populated `rootfs`
+    pub fn collect(revisions: &BTreeMap<repository::Id, String>, rootfs: &Path) -> io::Result<Self> {
+        let repositories = revisions
+            .iter()
+            .map(|(id, revision)| (id.to_string(), revision.clone()))
+            .collect::<BTreeMap<_, _>>();
+
+        // moss is linked into boulder, so they're always built from the same tree
+        let version = serpent_buildinfo::get_full_version();
+
+        Ok(Self {
+            architecture: architecture::host().to_string(),
+            kernel: read_proc("sys/kernel/osrelease"),
+            kernel_version: read_proc("sys/kernel/version"),
+            cpu_features: cpu_features(),
+            boulder_version: version.clone(),
+            moss_version: version,
+            repositories,
+            rootfs: image_digest(rootfs)?,
+        })
+    }
+}
+
+/// Hex encoded sha256 digest of the image at `root`, covering the relative path,
+/// type & permissions of everything within it, along with the contents of files
+/// and the targets of symlinks. Owners are left out, as rootless builds remap them
+pub fn image_digest(root: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    hash_dir(root, root, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+fn hash_dir(root: &Path, dir: &Path, hasher: &mut Sha256) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+
+    for path in entries {
+        let metadata = fs::symlink_metadata(&path)?;
+
+        hasher.update(path.strip_prefix(root).unwrap_or(&path).as_os_str().as_bytes());
+        hasher.update([0]);
+        hasher.update(metadata.mode().to_le_bytes());
+
+        if metadata.is_dir() {
+            hash_dir(root, &path, hasher)?;
+        } else if metadata.is_symlink() {
+            hasher.update(fs::read_link(&path)?.as_os_str().as_bytes());
+        } else if metadata.is_file() {
+            hasher.update(metadata.len().to_le_bytes());
+            io::copy(&mut fs::File::open(&path)?, hasher)?;
+        }
+        hasher.update([0]);
+    }
+
+    Ok(())
+}
+
+fn read_proc(path: &str) -> String {
+    fs::read_to_string(format!("/proc/{path}"))
+        .map(|value| value.trim().to_string())
+        .unwrap_or_default()
+}
+
+/// CPU feature flags of the first processor, i.e. `flags` on x86 and `Features` on arm
+fn cpu_features() -> Vec<String> {
+    let cpuinfo = read_proc("cpuinfo");
+
+    let mut features = cpuinfo
+        .lines()
+        .find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            matches!(key.trim(), "flags" | "Features").then_some(value)
+        })
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    features.sort();
+
+    features
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    use super::*;
+
+    #[test]
+    fn image_digests() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("usr/bin")).unwrap();
+        fs::write(root.join("usr/bin/tool"), "#!/bin/sh\n").unwrap();
+        symlink("tool", root.join("usr/bin/alias")).unwrap();
+
+        let digest = image_digest(root).unwrap();
+        assert_eq!(image_digest(root).unwrap(), digest);
+
+        fs::set_permissions(root.join("usr/bin/tool"), fs::Permissions::from_mode(0o755)).unwrap();
+        let executable = image_digest(root).unwrap();
+        assert_ne!(executable, digest);
+
+        fs::write(root.join("usr/bin/tool"), "#!/bin/bash\n").unwrap();
+        assert_ne!(image_digest(root).unwrap(), executable);
+    }
+}
@@ -12,7 +12,7 @@ use stone_recipe::{script, tuning, Script, Upstream};
 use thiserror::Error;
 
 pub use self::phase::Phase;
-use crate::build::pgo;
+use crate::build::{hardening, pgo};
 use crate::{architecture::BuildTarget, util, Macros, Paths, Recipe};
 
 mod phase;
@@ -105,6 +105,8 @@ pub enum Error {
     Script(#[from] script::Error),
     #[error("tuning")]
     Tuning(#[from] tuning::Error),
+    #[error("hardening")]
+    Hardening(#[from] hardening::Error),
     #[error("io")]
     Io(#[from] io::Error),
 }
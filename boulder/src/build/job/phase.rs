@@ -12,7 +12,7 @@ use stone_recipe::{
 };
 use tui::Styled;
 
-use crate::build::pgo;
+use crate::build::{hardening, pgo};
 use crate::{architecture::BuildTarget, util, Macros, Paths, Recipe};
 
 use super::{work_dir, Error};
@@ -301,6 +301,18 @@ fn add_tuning(
         }
     }
 
+    // Hardening is on unless the recipe disabled it, keeping any option it chose
+    for (group, status) in hardening::Report::new(target, recipe, macros)?.features {
+        match status {
+            hardening::Status::Enabled => {
+                if !recipe.parsed.tuning.iter().any(|kv| kv.key == group) {
+                    tuning.enable(group, None)?;
+                }
+            }
+            hardening::Status::Disabled { .. } => tuning.disable(group)?,
+        }
+    }
+
     if let Some(stage) = pgo_stage {
         match stage {
             pgo::Stage::One => tuning.enable("pgostage1", None)?,
@@ -10,7 +10,7 @@ use thiserror::Error;
 use moss::{repository, runtime, Installation};
 use stone_recipe::{tuning::Toolchain, Upstream};
 
-use crate::build::Builder;
+use crate::build::{host, Builder};
 use crate::{container, timing, util, Timing};
 
 pub fn populate(
@@ -19,7 +19,7 @@ pub fn populate(
     timing: &mut Timing,
     initialize_timer: timing::Timer,
     update_repos: bool,
-) -> Result<(), Error> {
+) -> Result<host::Report, Error> {
     let packages = packages(builder);
 
     let rootfs = builder.paths.rootfs().host;
@@ -43,13 +43,16 @@ pub fn populate(
     timing.finish(initialize_timer);
 
     // Install packages
-    let install_timing = moss_client.install(&packages, true)?;
+    let install_timing = moss_client.install(&packages, true, None)?;
 
     timing.record(timing::Populate::Resolve, install_timing.resolve);
     timing.record(timing::Populate::Fetch, install_timing.fetch);
     timing.record(timing::Populate::Blit, install_timing.blit);
 
-    Ok(())
+    Ok(host::Report::collect(
+        &moss_client.index_revisions()?,
+        &builder.paths.rootfs().host,
+    )?)
 }
 
 pub fn clean(builder: &Builder) -> Result<(), Error> {
@@ -10,15 +10,16 @@ use std::{
 };
 
 use futures::{stream, StreamExt, TryStreamExt};
+use hash::{Algorithm, Digest};
 use moss::runtime;
 use nix::unistd::{linkat, LinkatFlags};
-use sha2::{Digest, Sha256};
 use thiserror::Error;
-use tokio::io::AsyncWriteExt;
 use tui::{MultiProgress, ProgressBar, ProgressStyle, Styled};
 use url::Url;
 
-use crate::{util, Paths, Recipe};
+use crate::{container, messages, util, Paths, Recipe};
+
+pub mod cache;
 
 /// Cache all upstreams from the provided [`Recipe`] and make them available
 /// in the guest rootfs.
@@ -32,10 +33,13 @@ pub fn sync(recipe: &Recipe, paths: &Paths) -> Result<(), Error> {
         .collect::<Result<Vec<_>, _>>()?;
 
     println!();
-    println!("Sharing {} upstream(s) with the build container", upstreams.len());
+    println!(
+        "{}",
+        messages::format("sharing-upstreams", &[("count", upstreams.len().into())])
+    );
     println!();
 
-    let mp = MultiProgress::new();
+    let mp = MultiProgress::with_draw_target(tui::draw_target());
     let tp = mp.add(
         ProgressBar::new(upstreams.len() as u64).with_style(
             ProgressStyle::with_template("\n|{bar:20.cyan/blue}| {pos}/{len}")
@@ -99,6 +103,163 @@ pub fn sync(recipe: &Recipe, paths: &Paths) -> Result<(), Error> {
     Ok(())
 }
 
+/// Run the hook of each upstream declaring one in the sandbox, sharing its output
+/// in place of the fetched upstream.
+///
+/// Hooks read the fetched upstream from `$UPSTREAM` and write the transformed one
+/// to `$OUTPUT`. The output is cached by the hash of the upstream & the hook along
+/// with its own hash, so each transform only runs once.
+///
+/// This clones into a container, so must only be called once upstreams are
+/// [`sync`]'d & no other threads are running.
+pub fn transform(recipe: &Recipe, paths: &Paths) -> Result<(), Error> {
+    let hooked = recipe
+        .parsed
+        .upstreams
+        .iter()
+        .cloned()
+        .map(Upstream::from_recipe)
+        .filter_map(|upstream| match upstream {
+            Ok(Upstream::Plain(plain)) if plain.hook.is_some() => Some(Ok(plain)),
+            Ok(_) => None,
+            Err(error) => Some(Err(error)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if hooked.is_empty() {
+        return Ok(());
+    }
+
+    let upstream_dir = paths.guest_host_path(&paths.upstreams());
+
+    for plain in hooked {
+        let transformed = Transformed::new(paths, &plain);
+
+        let was_cached = transformed.is_valid()?;
+        if !was_cached {
+            transformed.run(paths, &plain)?;
+        }
+
+        // Replace the shared upstream with its transformed output
+        let target = upstream_dir.join(plain.name());
+        fs::remove_file(&target)?;
+        util::hardlink_or_copy(&transformed.path, &target)?;
+
+        let cached_tag = was_cached
+            .then_some(format!("{}", " (cached)".dim()))
+            .unwrap_or_default();
+        println!("{} {}{}", "Transformed".green(), plain.name().bold(), cached_tag);
+    }
+
+    println!();
+
+    Ok(())
+}
+
+/// Cached output of an upstream's hook, keyed by the hash of the
+/// upstream & the hook. A sidecar records the hash of the output.
+struct Transformed {
+    path: PathBuf,
+    sidecar: PathBuf,
+}
+
+impl Transformed {
+    fn new(paths: &Paths, plain: &Plain) -> Self {
+        let mut hasher = Digest::new(Algorithm::Sha256);
+        hasher.update(plain.hash.0.as_bytes());
+        hasher.update(b"\n");
+        hasher.update(plain.hook.as_deref().unwrap_or_default().as_bytes());
+        let key = hasher.finalize_hex();
+
+        let path = paths
+            .upstreams()
+            .host
+            .join("transformed")
+            .join(&key[..5])
+            .join(&key[key.len() - 5..])
+            .join(&key);
+        let sidecar = path.with_file_name(format!("{key}.sha256"));
+
+        Self { path, sidecar }
+    }
+
+    /// Whether the output is cached & still matches its recorded hash
+    fn is_valid(&self) -> Result<bool, Error> {
+        if !self.path.exists() || !self.sidecar.exists() {
+            return Ok(false);
+        }
+
+        let expected = fs::read_to_string(&self.sidecar)?;
+
+        Ok(hash_file(&self.path)? == expected.trim())
+    }
+
+    /// Run the hook in the sandbox & cache its output
+    fn run(&self, paths: &Paths, plain: &Plain) -> Result<(), Error> {
+        let name = plain.name();
+        let hook = plain.hook.as_deref().unwrap_or_default();
+
+        // Scratch space within the build dir, which is bound into the container
+        let build = paths.build();
+        let (host_dir, guest_dir) = (build.host.join("hook"), build.guest.join("hook"));
+        util::recreate_dir(&host_dir.join("work"))?;
+        fs::write(host_dir.join("hook.sh"), hook)?;
+
+        let upstream = paths.upstreams().guest.join(name);
+        let output = guest_dir.join("output");
+
+        container::exec(paths, false, || {
+            let status = std::process::Command::new("/bin/sh")
+                .arg("-e")
+                .arg(guest_dir.join("hook.sh"))
+                .env_clear()
+                .env("HOME", &guest_dir)
+                .env("PATH", "/usr/bin:/usr/sbin")
+                .env("UPSTREAM", &upstream)
+                .env("OUTPUT", &output)
+                .current_dir(guest_dir.join("work"))
+                .status()?;
+
+            if status.success() {
+                Ok(())
+            } else {
+                Err(io::Error::other(format!("hook of {name} failed with {status}")))
+            }
+        })?;
+
+        let output = host_dir.join("output");
+        if !output.is_file() {
+            return Err(Error::MissingHookOutput(name.to_string()));
+        }
+
+        if let Some(parent) = self.path.parent() {
+            util::ensure_dir_exists(parent)?;
+        }
+        // The output is only trusted once its sidecar exists, so write that last
+        let partial_path = self.path.with_extension("tmp");
+        if fs::rename(&output, &partial_path).is_err() {
+            fs::copy(&output, &partial_path)?;
+        }
+        let hash = hash_file(&partial_path)?;
+        fs::rename(&partial_path, &self.path)?;
+
+        let partial_sidecar = self.sidecar.with_extension("sha256.tmp");
+        fs::write(&partial_sidecar, hash)?;
+        fs::rename(partial_sidecar, &self.sidecar)?;
+
+        fs::remove_dir_all(&host_dir)?;
+
+        Ok(())
+    }
+}
+
+fn hash_file(path: &Path) -> Result<String, Error> {
+    let mut hasher = Digest::new(Algorithm::Sha256);
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+
+    Ok(hasher.finalize_hex())
+}
+
 #[derive(Clone)]
 enum Installed {
     Plain {
@@ -153,10 +314,17 @@ pub enum Upstream {
 impl Upstream {
     pub fn from_recipe(upstream: stone_recipe::Upstream) -> Result<Self, Error> {
         match upstream {
-            stone_recipe::Upstream::Plain { uri, hash, rename, .. } => Ok(Self::Plain(Plain {
+            stone_recipe::Upstream::Plain {
+                uri,
+                hash,
+                rename,
+                hook,
+                ..
+            } => Ok(Self::Plain(Plain {
                 uri,
                 hash: hash.parse()?,
                 rename,
+                hook,
             })),
             stone_recipe::Upstream::Git {
                 uri, ref_id, staging, ..
@@ -205,6 +373,7 @@ pub struct Plain {
     uri: Url,
     hash: Hash,
     rename: Option<String>,
+    hook: Option<String>,
 }
 
 impl Plain {
@@ -216,22 +385,8 @@ impl Plain {
         }
     }
 
-    fn path(&self, paths: &Paths) -> PathBuf {
-        // Type safe guaranteed to be >= 5 bytes
-        let hash = &self.hash.0;
-
-        paths
-            .upstreams()
-            .host
-            .join("fetched")
-            .join(&hash[..5])
-            .join(&hash[hash.len() - 5..])
-            .join(hash)
-    }
-
     async fn fetch(&self, paths: &Paths, pb: &ProgressBar) -> Result<Installed, Error> {
         use moss::request;
-        use tokio::fs;
 
         pb.set_style(
             ProgressStyle::with_template(" {spinner} {wide_msg} {binary_bytes_per_sec:>.dim} ")
@@ -240,13 +395,21 @@ impl Plain {
         );
 
         let name = self.name();
-        let path = self.path(paths);
+        let cache = cache::Cache::new(&paths.upstreams().host);
+        // Type safe guaranteed to be >= 5 bytes
+        let path = cache.path(&self.hash.0);
 
         if let Some(parent) = path.parent().map(Path::to_path_buf) {
             runtime::unblock(move || util::ensure_dir_exists(&parent)).await?;
         }
 
         if path.exists() {
+            runtime::unblock({
+                let (hash, name, uri) = (self.hash.0.clone(), name.to_string(), self.uri.clone());
+                move || cache.record_use(&hash, &name, &uri)
+            })
+            .await?;
+
             return Ok(Installed::Plain {
                 name: name.to_string(),
                 path,
@@ -254,31 +417,25 @@ impl Plain {
             });
         }
 
-        let mut stream = request::get(self.uri.clone()).await?;
-
-        let mut hasher = Sha256::new();
-        let mut out = fs::File::create(&path).await?;
-
-        while let Some(chunk) = stream.next().await {
-            let bytes = &chunk?;
-            pb.inc(bytes.len() as u64);
-            hasher.update(bytes);
-            out.write_all(bytes).await?;
-        }
-
-        out.flush().await?;
-
-        let hash = hex::encode(hasher.finalize());
-
-        if hash != self.hash.0 {
-            fs::remove_file(&path).await?;
+        // Only verified downloads are moved into the cache, which
+        // trusts anything at `path`
+        let verified = match request::get_verified(self.uri.clone(), Some(&self.hash.0), &path, |n| pb.inc(n)).await {
+            Ok(verified) => verified,
+            Err(request::Error::HashMismatch { expected, actual, .. }) => {
+                return Err(Error::HashMismatch {
+                    name: name.to_string(),
+                    expected,
+                    got: actual,
+                });
+            }
+            Err(error) => return Err(error.into()),
+        };
 
-            return Err(Error::HashMismatch {
-                name: name.to_string(),
-                expected: self.hash.0.clone(),
-                got: hash,
-            });
-        }
+        runtime::unblock({
+            let (name, uri) = (name.to_string(), self.uri.clone());
+            move || cache.record_fetch(&verified.hash, &name, &uri, verified.size)
+        })
+        .await?;
 
         Ok(Installed::Plain {
             name: name.to_string(),
@@ -444,8 +601,14 @@ pub enum Error {
         expected: String,
         got: String,
     },
+    #[error("hook of {0} didn't write its $OUTPUT")]
+    MissingHookOutput(String),
+    #[error("container")]
+    Container(#[from] container::Error),
     #[error("request")]
     Request(#[from] moss::request::Error),
+    #[error("upstream cache")]
+    Cache(#[from] cache::Error),
     #[error("io")]
     Io(#[from] io::Error),
 }
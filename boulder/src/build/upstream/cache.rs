@@ -0,0 +1,292 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Sidecar metadata for the plain upstream cache
+//!
+//! Plain upstreams are cached by hash alone. Next to each cached file, a
+//! `<hash>.meta.json` sidecar records the urls it was requested under,
+//! when it was fetched, last verified & last used, so the cache can be
+//! audited and pruned. Git upstreams aren't tracked.
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use hash::{Algorithm, Digest};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use url::Url;
+
+use crate::util;
+
+const SIDECAR_SUFFIX: &str = ".meta.json";
+
+/// Metadata of a single cached upstream
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub hash: String,
+    /// File name the upstream was last shared as
+    pub name: String,
+    /// All urls the upstream was requested under, the original first
+    pub urls: Vec<Url>,
+    pub size: u64,
+    #[serde(with = "timestamp")]
+    pub fetched: DateTime<Utc>,
+    #[serde(with = "timestamp")]
+    pub last_verified: DateTime<Utc>,
+    #[serde(with = "timestamp")]
+    pub last_used: DateTime<Utc>,
+}
+
+/// A cached file and / or its sidecar, either of which may be missing
+#[derive(Debug, Clone)]
+pub struct Cached {
+    pub hash: String,
+    pub path: PathBuf,
+    pub exists: bool,
+    pub entry: Option<Entry>,
+}
+
+/// The plain upstream cache, rooted at `<upstreams>/fetched`
+#[derive(Debug, Clone)]
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new(upstreams_dir: &Path) -> Self {
+        Self {
+            dir: upstreams_dir.join("fetched"),
+        }
+    }
+
+    /// Path of the cached file for `hash`, which must be at least 5 bytes
+    pub fn path(&self, hash: &str) -> PathBuf {
+        self.dir.join(&hash[..5]).join(&hash[hash.len() - 5..]).join(hash)
+    }
+
+    fn sidecar_path(&self, hash: &str) -> PathBuf {
+        self.path(hash).with_file_name(format!("{hash}{SIDECAR_SUFFIX}"))
+    }
+
+    pub fn entry(&self, hash: &str) -> Result<Option<Entry>, Error> {
+        let path = self.sidecar_path(hash);
+
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read(&path)?;
+        Ok(Some(serde_json::from_slice(&content)?))
+    }
+
+    /// Atomically write the sidecar of `entry`
+    pub fn save(&self, entry: &Entry) -> Result<(), Error> {
+        let path = self.sidecar_path(&entry.hash);
+        let staging = path.with_extension("json.tmp");
+
+        if let Some(parent) = path.parent() {
+            util::ensure_dir_exists(parent)?;
+        }
+
+        fs::write(&staging, serde_json::to_vec_pretty(entry)?)?;
+        fs::rename(staging, path)?;
+
+        Ok(())
+    }
+
+    /// Record a freshly fetched & verified upstream
+    pub fn record_fetch(&self, hash: &str, name: &str, url: &Url, size: u64) -> Result<Entry, Error> {
+        let now = Utc::now();
+
+        let mut entry = self.entry(hash)?.unwrap_or_else(|| Entry {
+            hash: hash.to_string(),
+            name: name.to_string(),
+            urls: vec![],
+            size,
+            fetched: now,
+            last_verified: now,
+            last_used: now,
+        });
+
+        entry.name = name.to_string();
+        entry.size = size;
+        entry.fetched = now;
+        entry.last_verified = now;
+        entry.last_used = now;
+        if !entry.urls.contains(url) {
+            entry.urls.push(url.clone());
+        }
+
+        self.save(&entry)?;
+
+        Ok(entry)
+    }
+
+    /// Record use of an already cached upstream, tracking any new `url`.
+    /// Files cached before sidecars existed are adopted using their mtime
+    pub fn record_use(&self, hash: &str, name: &str, url: &Url) -> Result<Entry, Error> {
+        let entry = match self.entry(hash)? {
+            Some(mut entry) => {
+                entry.name = name.to_string();
+                entry.last_used = Utc::now();
+                if !entry.urls.contains(url) {
+                    entry.urls.push(url.clone());
+                }
+                entry
+            }
+            None => {
+                let metadata = fs::metadata(self.path(hash))?;
+                let modified = metadata
+                    .modified()
+                    .map(DateTime::<Utc>::from)
+                    .unwrap_or_else(|_| Utc::now());
+
+                Entry {
+                    hash: hash.to_string(),
+                    name: name.to_string(),
+                    urls: vec![url.clone()],
+                    size: metadata.len(),
+                    fetched: modified,
+                    last_verified: modified,
+                    last_used: Utc::now(),
+                }
+            }
+        };
+
+        self.save(&entry)?;
+
+        Ok(entry)
+    }
+
+    /// All cached files and sidecars, sorted by hash
+    pub fn list(&self) -> Result<Vec<Cached>, Error> {
+        if !self.dir.exists() {
+            return Ok(vec![]);
+        }
+
+        let mut hashes = util::enumerate_files(&self.dir, |path| !path.extension().is_some_and(|ext| ext == "tmp"))?
+            .into_iter()
+            .filter_map(|path| {
+                let name = path.file_name()?.to_str()?;
+                Some(name.strip_suffix(SIDECAR_SUFFIX).unwrap_or(name).to_string())
+            })
+            .filter(|hash| hash.len() >= 5)
+            .collect::<Vec<_>>();
+        hashes.sort();
+        hashes.dedup();
+
+        hashes
+            .into_iter()
+            .map(|hash| {
+                let path = self.path(&hash);
+                let entry = self.entry(&hash)?;
+
+                Ok(Cached {
+                    exists: path.exists(),
+                    hash,
+                    path,
+                    entry,
+                })
+            })
+            .collect()
+    }
+
+    /// Rehash the cached file of `hash`, bumping `last_verified`
+    /// of its sidecar when it still matches
+    pub fn verify(&self, hash: &str) -> Result<bool, Error> {
+        let mut file = fs::File::open(self.path(hash))?;
+
+        let mut hasher = Digest::new(Algorithm::Sha256);
+        io::copy(&mut file, &mut hasher)?;
+
+        if hasher.finalize_hex() != hash {
+            return Ok(false);
+        }
+
+        if let Some(mut entry) = self.entry(hash)? {
+            entry.last_verified = Utc::now();
+            self.save(&entry)?;
+        }
+
+        Ok(true)
+    }
+
+    /// Remove the cached file of `hash` and its sidecar
+    pub fn remove(&self, hash: &str) -> Result<(), Error> {
+        for path in [self.path(hash), self.sidecar_path(hash)] {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// (De)serialize timestamps as RFC 3339 for a readable audit trail
+mod timestamp {
+    use chrono::{DateTime, Utc};
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(timestamp: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&timestamp.to_rfc3339())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        DateTime::parse_from_rfc3339(&value)
+            .map(|timestamp| timestamp.with_timezone(&Utc))
+            .map_err(de::Error::custom)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("io")]
+    Io(#[from] io::Error),
+    #[error("sidecar json")]
+    Json(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sidecar() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let cache = Cache::new(dir);
+
+        let content = b"upstream";
+        let mut hasher = Digest::new(Algorithm::Sha256);
+        hasher.update(content);
+        let hash = hasher.finalize_hex();
+
+        let path = cache.path(&hash);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, content).unwrap();
+
+        let original = "https://example.com/a.tar.xz".parse::<Url>().unwrap();
+        let renamed = "https://mirror.example.com/b.tar.xz".parse::<Url>().unwrap();
+
+        // Adopts files cached prior to sidecars
+        let adopted = cache.record_use(&hash, "a.tar.xz", &original).unwrap();
+        assert_eq!(adopted.size, content.len() as u64);
+
+        let entry = cache.record_use(&hash, "b.tar.xz", &renamed).unwrap();
+        assert_eq!(entry.urls, vec![original, renamed]);
+        assert_eq!(cache.entry(&hash).unwrap(), Some(entry));
+
+        assert!(cache.verify(&hash).unwrap());
+
+        let listed = cache.list().unwrap();
+        assert_eq!(listed.len(), 1);
+        assert!(listed[0].exists && listed[0].entry.is_some());
+
+        cache.remove(&hash).unwrap();
+        assert!(cache.list().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,296 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Compute which recipes of a recipe tree need rebuilding for a set of
+//! changes, and in which order they can be built.
+//!
+//! Build dependencies are matched against the package names each recipe
+//! produces (including sub-packages), both as plain names and `name(..)`
+//! providers. Other provider kinds such as `pkgconfig(..)` can only be
+//! resolved from a repository index and are ignored.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+};
+
+use dag::Dag;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{recipe, util};
+
+/// A single recipe within a recipe tree
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// Source name of the recipe
+    pub name: String,
+    /// Directory of the recipe, relative to the tree root
+    pub path: PathBuf,
+    /// All package names produced by the recipe
+    pub packages: BTreeSet<String>,
+    /// Build & check dependencies across all build profiles
+    pub build_deps: BTreeSet<String>,
+}
+
+impl Entry {
+    fn new(path: PathBuf, parsed: &recipe::Parsed) -> Self {
+        let name = parsed.source.name.clone();
+
+        let packages = parsed
+            .sub_packages
+            .iter()
+            .map(|sub| sub.key.replace("%(name)", &name))
+            .chain(Some(name.clone()))
+            .collect();
+        let build_deps = Some(&parsed.build)
+            .into_iter()
+            .chain(parsed.profiles.iter().map(|profile| &profile.value))
+            .flat_map(|build| build.build_deps.iter().chain(&build.check_deps))
+            .cloned()
+            .collect();
+
+        Self {
+            name,
+            path,
+            packages,
+            build_deps,
+        }
+    }
+}
+
+/// All recipes of a recipe tree
+#[derive(Debug, Clone, Default)]
+pub struct Tree {
+    pub root: PathBuf,
+    pub recipes: Vec<Entry>,
+}
+
+impl Tree {
+    /// Load every `stone.yaml` recipe found below `root`
+    pub fn load(root: impl Into<PathBuf>) -> Result<Self, Error> {
+        let root = root.into();
+
+        let mut recipes = util::enumerate_files(&root, |path| path.ends_with("stone.yaml"))
+            .map_err(Error::Enumerate)?
+            .into_iter()
+            // Skip anything within hidden dirs such as `.git`
+            .filter(|path| {
+                !path
+                    .strip_prefix(&root)
+                    .unwrap_or(path)
+                    .components()
+                    .any(|c| c.as_os_str().to_string_lossy().starts_with('.'))
+            })
+            .map(|path| {
+                let source = fs::read_to_string(&path).map_err(|e| Error::Read(path.clone(), e))?;
+                let parsed = stone_recipe::from_str(&source).map_err(|e| Error::Decode(path.clone(), e))?;
+                let dir = path
+                    .parent()
+                    .and_then(|dir| dir.strip_prefix(&root).ok())
+                    .unwrap_or(Path::new(""))
+                    .to_path_buf();
+
+                Ok(Entry::new(dir, &parsed))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        recipes.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(Self { root, recipes })
+    }
+
+    /// Names of all recipes touched by the git diff `range`
+    pub fn changed(&self, range: &str) -> Result<BTreeSet<String>, Error> {
+        let output = process::Command::new("git")
+            .args(["diff", "--name-only", "--relative", range])
+            .current_dir(&self.root)
+            .output()
+            .map_err(Error::Git)?;
+
+        if !output.status.success() {
+            return Err(Error::GitDiff(
+                range.to_string(),
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|file| self.owner(Path::new(file)))
+            .map(|entry| entry.name.clone())
+            .collect())
+    }
+
+    /// The recipe whose directory is the closest ancestor of `file`
+    fn owner(&self, file: &Path) -> Option<&Entry> {
+        self.recipes
+            .iter()
+            .filter(|entry| file.starts_with(&entry.path))
+            .max_by_key(|entry| entry.path.components().count())
+    }
+
+    /// Names of the recipes providing each build dependency of `entry`
+    fn dependencies(&self, entry: &Entry) -> BTreeSet<&str> {
+        entry
+            .build_deps
+            .iter()
+            .map(|dep| {
+                dep.strip_prefix("name(")
+                    .and_then(|dep| dep.strip_suffix(')'))
+                    .unwrap_or(dep)
+            })
+            .filter_map(|dep| self.recipes.iter().find(|other| other.packages.contains(dep)))
+            .filter(|other| other.name != entry.name)
+            .map(|other| other.name.as_str())
+            .collect()
+    }
+
+    /// Compute the [`Manifest`] of recipes needing a rebuild when
+    /// the `changed` recipes are modified, refusing cyclic build dependencies
+    pub fn manifest(&self, changed: &BTreeSet<String>) -> Result<Manifest, Error> {
+        let dependencies = self
+            .recipes
+            .iter()
+            .map(|entry| (entry.name.as_str(), self.dependencies(entry)))
+            .collect::<BTreeMap<_, _>>();
+
+        // Edges point from a recipe to the recipes which build against it
+        let mut graph = Dag::new();
+        for (name, deps) in &dependencies {
+            let node = graph.add_node_or_get_index(*name);
+            for dep in deps {
+                let dep_node = graph.add_node_or_get_index(*dep);
+                // Dependencies are unique, so an edge is only refused if it closes a cycle
+                if !graph.add_edge(dep_node, node) {
+                    return Err(Error::Cycle(dep.to_string(), name.to_string()));
+                }
+            }
+        }
+
+        let starting = changed
+            .iter()
+            .map(String::as_str)
+            .filter(|name| graph.node_exists(name))
+            .collect::<Vec<_>>();
+        let rebuild = graph.subgraph(&starting);
+
+        // Each recipe is built one stage after the last of its dependencies
+        let mut stages = BTreeMap::<&str, usize>::new();
+        for name in rebuild.topo() {
+            let stage = dependencies[name]
+                .iter()
+                .filter_map(|dep| stages.get(dep))
+                .max()
+                .map_or(0, |stage| stage + 1);
+            stages.insert(name, stage);
+        }
+
+        let mut jobs = self
+            .recipes
+            .iter()
+            .filter_map(|entry| {
+                let stage = *stages.get(entry.name.as_str())?;
+
+                Some(Job {
+                    name: entry.name.clone(),
+                    recipe: entry.path.clone(),
+                    stage,
+                    changed: changed.contains(&entry.name),
+                })
+            })
+            .collect::<Vec<_>>();
+        jobs.sort_by(|a, b| a.stage.cmp(&b.stage).then_with(|| a.name.cmp(&b.name)));
+
+        Ok(Manifest { jobs })
+    }
+}
+
+/// CI job matrix, usable as-is for i.e. a GitHub Actions `matrix`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Manifest {
+    #[serde(rename = "include")]
+    pub jobs: Vec<Job>,
+}
+
+/// A single recipe build within the [`Manifest`], in build order
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Job {
+    pub name: String,
+    pub recipe: PathBuf,
+    /// Jobs of the same stage have no dependencies between
+    /// each other and can be built in parallel
+    pub stage: usize,
+    /// Recipe was changed directly, rather than depending on a changed recipe
+    pub changed: bool,
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("enumerate recipes")]
+    Enumerate(#[source] io::Error),
+    #[error("read recipe {0:?}")]
+    Read(PathBuf, #[source] io::Error),
+    #[error("decode recipe {0:?}")]
+    Decode(PathBuf, #[source] stone_recipe::Error),
+    #[error("run git")]
+    Git(#[source] io::Error),
+    #[error("git diff {0}: {1}")]
+    GitDiff(String, String),
+    #[error("build dependency cycle: {1} depends on {0}, which depends on it in turn")]
+    Cycle(String, String),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(name: &str, packages: &[&str], build_deps: &[&str]) -> Entry {
+        Entry {
+            name: name.into(),
+            path: PathBuf::from(name),
+            packages: packages.iter().map(ToString::to_string).collect(),
+            build_deps: build_deps.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    #[test]
+    fn rebuilds_reverse_build_deps_in_order() {
+        let tree = Tree {
+            root: PathBuf::new(),
+            recipes: vec![
+                entry("zlib", &["zlib", "zlib-devel"], &[]),
+                entry("libpng", &["libpng", "libpng-devel"], &["zlib-devel"]),
+                entry("gtk", &["gtk"], &["name(libpng-devel)", "zlib-devel"]),
+                entry("nano", &["nano"], &["pkgconfig(ncursesw)"]),
+            ],
+        };
+
+        let manifest = tree.manifest(&BTreeSet::from(["zlib".to_string()])).unwrap();
+        let jobs = manifest
+            .jobs
+            .iter()
+            .map(|job| (job.name.as_str(), job.stage, job.changed))
+            .collect::<Vec<_>>();
+
+        assert_eq!(jobs, vec![("zlib", 0, true), ("libpng", 1, false), ("gtk", 2, false)]);
+
+        let manifest = tree.manifest(&BTreeSet::from(["nano".to_string()])).unwrap();
+        assert_eq!(manifest.jobs.len(), 1);
+    }
+
+    #[test]
+    fn refuses_cycles() {
+        let tree = Tree {
+            root: PathBuf::new(),
+            recipes: vec![
+                entry("gcc", &["gcc"], &["glibc-devel"]),
+                entry("glibc", &["glibc-devel"], &["gcc"]),
+            ],
+        };
+
+        let result = tree.manifest(&BTreeSet::from(["gcc".to_string()]));
+        assert!(matches!(result, Err(Error::Cycle(..))));
+    }
+}
@@ -5,12 +5,17 @@ use std::path::PathBuf;
 
 use boulder::{env, Env};
 use clap::{Args, Parser};
+use moss::request;
 use thiserror::Error;
 
 mod build;
 mod chroot;
+mod ci_manifest;
+mod doctor;
 mod profile;
 mod recipe;
+mod recipe_test;
+mod upstream;
 mod version;
 
 #[derive(Debug, Parser)]
@@ -39,14 +44,30 @@ pub struct Global {
     pub data_dir: Option<PathBuf>,
     #[arg(long, global = true)]
     pub moss_root: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Report progress as plain lines rather than redrawn bars, as when not on a terminal",
+        global = true
+    )]
+    pub no_progress: bool,
+    #[arg(
+        long,
+        help = "Limit the aggregate download rate in bytes per second, i.e. 2M",
+        global = true
+    )]
+    pub limit_rate: Option<request::Rate>,
 }
 
 #[derive(Debug, clap::Subcommand)]
 pub enum Subcommand {
     Build(build::Command),
     Chroot(chroot::Command),
+    CiManifest(ci_manifest::Command),
+    Doctor(doctor::Command),
     Profile(profile::Command),
     Recipe(recipe::Command),
+    RecipeTest(recipe_test::Command),
+    Upstream(upstream::Command),
     Version(version::Command),
 }
 
@@ -61,13 +82,22 @@ pub fn process() -> Result<(), Error> {
         }
     }
 
+    tui::set_plain(global.no_progress);
+
     let env = Env::new(global.cache_dir, global.config_dir, global.data_dir, global.moss_root)?;
 
+    request::configure(&env.config.load::<request::Settings>())?;
+    request::set_rate_limit(global.limit_rate);
+
     match subcommand {
         Subcommand::Build(command) => build::handle(command, env)?,
         Subcommand::Chroot(command) => chroot::handle(command, env)?,
+        Subcommand::CiManifest(command) => ci_manifest::handle(command)?,
+        Subcommand::Doctor(command) => doctor::handle(command, env)?,
         Subcommand::Profile(command) => profile::handle(command, env)?,
         Subcommand::Recipe(command) => recipe::handle(command, env)?,
+        Subcommand::RecipeTest(command) => recipe_test::handle(command, env)?,
+        Subcommand::Upstream(command) => upstream::handle(command, env)?,
         Subcommand::Version(command) => version::handle(command),
     }
 
@@ -104,10 +134,20 @@ pub enum Error {
     Build(#[from] build::Error),
     #[error("chroot")]
     Chroot(#[from] chroot::Error),
+    #[error("ci manifest")]
+    CiManifest(#[from] ci_manifest::Error),
+    #[error("doctor")]
+    Doctor(#[from] doctor::Error),
     #[error("profile")]
     Profile(#[from] profile::Error),
     #[error("env")]
     Env(#[from] env::Error),
+    #[error("network")]
+    Network(#[from] request::Error),
     #[error("recipe")]
     Recipe(#[from] recipe::Error),
+    #[error("recipe test")]
+    RecipeTest(#[from] recipe_test::Error),
+    #[error("upstream")]
+    Upstream(#[from] upstream::Error),
 }
@@ -6,7 +6,7 @@ use std::io;
 use std::num::NonZeroU64;
 use std::path::PathBuf;
 
-use boulder::build::{self, Builder};
+use boulder::build::{self, diagnostics, hardening, Builder};
 use boulder::package::Packager;
 use boulder::{container, package, profile, timing, Env, Timing};
 use chrono::Local;
@@ -64,7 +64,9 @@ pub fn handle(command: Command, env: Env) -> Result<(), Error> {
     }
 
     let builder = Builder::new(&recipe_path, env, profile, ccache, output)?;
-    builder.setup(&mut timing, timer, update)?;
+    let host = builder.setup(&mut timing, timer, update)?;
+
+    let signing_key = builder.signing_key()?;
 
     let paths = &builder.paths;
     let networking = builder.recipe.parsed.options.networking;
@@ -78,8 +80,10 @@ pub fn handle(command: Command, env: Env) -> Result<(), Error> {
             &builder.recipe,
             &builder.macros,
             &builder.targets,
+            &host,
             build_release,
-        )?;
+        )?
+        .with_signing_key(signing_key.as_ref());
         packager.package(&mut timing)?;
 
         timing.print_table();
@@ -87,6 +91,12 @@ pub fn handle(command: Command, env: Env) -> Result<(), Error> {
         Ok(())
     })?;
 
+    diagnostics::report(paths, &builder.recipe)?;
+
+    for target in &builder.targets {
+        hardening::Report::new(target.build_target, &builder.recipe, &builder.macros)?.print(target.build_target);
+    }
+
     // Copy artefacts to host recipe dir
     package::sync_artefacts(paths).map_err(Error::SyncArtefacts)?;
 
@@ -104,6 +114,10 @@ pub enum Error {
     MissingOutput(PathBuf),
     #[error("build recipe")]
     Build(#[from] build::Error),
+    #[error("build diagnostics")]
+    Diagnostics(#[from] diagnostics::Error),
+    #[error("hardening")]
+    Hardening(#[from] hardening::Error),
     #[error("package artifacts")]
     Package(#[from] package::Error),
     #[error("sync artefacts")]
@@ -0,0 +1,51 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::path::PathBuf;
+
+use boulder::ci;
+use clap::{Parser, ValueEnum};
+use thiserror::Error;
+
+#[derive(Debug, Parser)]
+#[command(about = "Generate a CI job matrix of recipes to rebuild for a git diff range")]
+pub struct Command {
+    #[arg(help = "Git diff range to compute changes from, i.e. origin/main..HEAD")]
+    range: String,
+    #[arg(short, long, default_value = ".", help = "Root of the recipe tree")]
+    tree: PathBuf,
+    #[arg(short, long, value_enum, default_value_t = Format::Json, help = "Output format")]
+    format: Format,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Format {
+    Json,
+    Yaml,
+}
+
+pub fn handle(command: Command) -> Result<(), Error> {
+    let Command { range, tree, format } = command;
+
+    let tree = ci::Tree::load(tree)?;
+    let changed = tree.changed(&range)?;
+    let manifest = tree.manifest(&changed)?;
+
+    match format {
+        Format::Json => println!("{}", serde_json::to_string_pretty(&manifest)?),
+        Format::Yaml => print!("{}", serde_yaml::to_string(&manifest)?),
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("ci")]
+    Ci(#[from] ci::Error),
+    #[error("serialize json")]
+    Json(#[from] serde_json::Error),
+    #[error("serialize yaml")]
+    Yaml(#[from] serde_yaml::Error),
+}
@@ -0,0 +1,51 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use boulder::{
+    doctor::{self, Status},
+    Env,
+};
+use clap::Parser;
+use thiserror::Error;
+use tui::Styled;
+
+#[derive(Debug, Parser)]
+#[command(about = "Check the host is able to run builds")]
+pub struct Command {}
+
+pub fn handle(_command: Command, env: Env) -> Result<(), Error> {
+    let checks = doctor::run(&env);
+
+    for check in &checks {
+        match &check.status {
+            Status::Ok(found) => println!(
+                "{} {} {}",
+                "✓".green(),
+                check.name.as_str().bold(),
+                found.as_str().dim()
+            ),
+            Status::Warning { problem, fix } => {
+                println!("{} {} {problem}", "!".yellow(), check.name.as_str().bold());
+                println!("  {} {fix}", "Fix".dim());
+            }
+            Status::Failed { problem, fix } => {
+                println!("{} {} {problem}", "✗".red(), check.name.as_str().bold());
+                println!("  {} {fix}", "Fix".dim());
+            }
+        }
+    }
+
+    let failed = checks.iter().filter(|check| check.status.is_failed()).count();
+    if failed > 0 {
+        return Err(Error::Failed(failed));
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("{0} check(s) failed")]
+    Failed(usize),
+}
@@ -2,7 +2,7 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::{collections::BTreeMap, io};
+use std::{collections::BTreeMap, io, path::PathBuf};
 
 use clap::Parser;
 use itertools::Itertools;
@@ -37,6 +37,8 @@ pub enum Subcommand {
         long_help = "repository to add to profile\n\nExample: --repo name=volatile,uri=https://dev.serpentos.com/volatile/x86_64/stone.index,priority=100"
         )]
         repos: Vec<(repository::Id, Repository)>,
+        #[arg(long, help = "sign packages built with this profile using the key at this path")]
+        signing_key: Option<PathBuf>,
     },
     #[command(about = "Update a profiles repositories")]
     Update {
@@ -71,6 +73,8 @@ fn parse_repository(s: &str) -> Result<(repository::Id, Repository), String> {
             description: String::default(),
             uri,
             priority: repository::Priority::new(priority),
+            quota: None,
+            trusted_keys: vec![],
         },
     ))
 }
@@ -82,7 +86,11 @@ pub fn handle(command: Command, env: Env) -> Result<(), Error> {
 
     match command.subcommand {
         Subcommand::List => list(manager),
-        Subcommand::Add { name, repos } => add(&env, manager, name, repos),
+        Subcommand::Add {
+            name,
+            repos,
+            signing_key,
+        } => add(&env, manager, name, repos, signing_key),
         Subcommand::Update { profile } => update(&env, manager, &profile),
     }
 }
@@ -103,6 +111,10 @@ pub fn list(manager: profile::Manager) -> Result<(), Error> {
         {
             println!(" - {} = {} [{}]", id, repo.uri, repo.priority);
         }
+
+        if let Some(key) = &profile.signing_key {
+            println!(" signed with {key:?}");
+        }
     }
 
     Ok(())
@@ -113,6 +125,7 @@ pub fn add<'a>(
     mut manager: profile::Manager<'a>,
     name: String,
     repos: Vec<(repository::Id, Repository)>,
+    signing_key: Option<PathBuf>,
 ) -> Result<(), Error> {
     let id = profile::Id::new(name);
 
@@ -120,6 +133,7 @@ pub fn add<'a>(
         id.clone(),
         Profile {
             repositories: repository::Map::with(repos),
+            signing_key,
         },
     )?;
 
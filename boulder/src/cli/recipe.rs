@@ -11,13 +11,13 @@ use std::{
 use boulder::{
     architecture,
     draft::{self, Drafter},
-    macros, recipe, Env, Macros,
+    lint, macros, recipe, Env, Macros,
 };
 use clap::Parser;
 use futures::StreamExt;
+use hash::{Algorithm, Digest};
 use itertools::Itertools;
 use moss::{request, runtime};
-use sha2::{Digest, Sha256};
 use thiserror::Error;
 use tokio::io::AsyncWriteExt;
 use tui::{
@@ -86,6 +86,15 @@ pub enum Subcommand {
         )]
         overwrite: bool,
     },
+    #[command(about = "Check recipes for reproducibility hazards")]
+    Lint {
+        #[arg(
+            default_value = "./stone.yaml",
+            help = "Recipe files to check",
+            long_help = "Recipe files to check. Reviewed lines can be suppressed with a `# lint-ignore: <rule>` comment at their end or on the line above"
+        )]
+        recipes: Vec<PathBuf>,
+    },
     #[command(about = "Print macro definitions")]
     Macros {
         #[arg(name = "macro", help = "Print definition and example for the provided macro")]
@@ -116,6 +125,7 @@ pub fn handle(command: Command, env: Env) -> Result<(), Error> {
             version,
             upstreams,
         } => update(recipe, overwrite, version, upstreams),
+        Subcommand::Lint { recipes } => lint(recipes),
         Subcommand::Macros { _macro } => macros(_macro, env),
     }
 }
@@ -224,7 +234,7 @@ fn update(recipe: Option<PathBuf>, overwrite: bool, version: String, upstreams:
     // Needed to fetch
     let _guard = runtime::init();
 
-    let mpb = MultiProgress::new();
+    let mpb = MultiProgress::with_draw_target(tui::draw_target());
 
     // Add all update operations
     let mut updater = yaml::Updater::new();
@@ -287,7 +297,7 @@ async fn fetch_hash(uri: Url, mpb: &MultiProgress) -> Result<String, Error> {
 
     let mut stream = request::get(uri).await?;
 
-    let mut hasher = Sha256::new();
+    let mut hasher = Digest::new(Algorithm::Sha256);
     // Discard bytes
     let mut out = tokio::io::sink();
 
@@ -302,7 +312,7 @@ async fn fetch_hash(uri: Url, mpb: &MultiProgress) -> Result<String, Error> {
 
     out.flush().await.map_err(Error::FetchIo)?;
 
-    let hash = hex::encode(hasher.finalize());
+    let hash = hasher.finalize_hex();
 
     pb.finish();
     mpb.remove(&pb);
@@ -310,6 +320,38 @@ async fn fetch_hash(uri: Url, mpb: &MultiProgress) -> Result<String, Error> {
     Ok(hash)
 }
 
+fn lint(recipes: Vec<PathBuf>) -> Result<(), Error> {
+    let mut total = 0;
+
+    for recipe in recipes {
+        let path = recipe::resolve_path(&recipe).map_err(Error::ResolvePath)?;
+        let input = fs::read_to_string(&path).map_err(Error::Read)?;
+        let parsed: recipe::Parsed = serde_yaml::from_str(&input)?;
+
+        let findings = lint::lint(&parsed);
+
+        for finding in &findings {
+            println!(
+                "{}: {}:{}: {} {}",
+                recipe.display(),
+                finding.step,
+                finding.line,
+                format!("[{}]", finding.rule).yellow(),
+                finding.rule.description()
+            );
+            println!("  {}", finding.text.as_str().dim());
+        }
+
+        total += findings.len();
+    }
+
+    if total > 0 {
+        return Err(Error::LintFindings(total));
+    }
+
+    Ok(())
+}
+
 fn macros(_macro: Option<String>, env: Env) -> Result<(), Error> {
     let macros = Macros::load(&env)?;
 
@@ -399,6 +441,8 @@ pub enum Error {
     UpstreamMismatch(usize, &'static str, &'static str),
     #[error("load macros")]
     LoadMacros(#[from] macros::Error),
+    #[error("{0} reproducibility hazard(s) found")]
+    LintFindings(usize),
     #[error("Macro doesn't exist: {0}")]
     MacroNotFound(String),
     #[error("resolve recipe path")]
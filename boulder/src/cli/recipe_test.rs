@@ -0,0 +1,121 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::num::NonZeroU64;
+use std::path::PathBuf;
+
+use boulder::build::{self, Builder};
+use boulder::expectation::{self, Emitted, Expectations};
+use boulder::package::Packager;
+use boulder::{container, package, profile, timing, Env, Timing};
+use clap::Parser;
+use thiserror::Error;
+use tui::Styled;
+
+#[derive(Debug, Parser)]
+#[command(
+    about = "Build a recipe and assert its packages against an expectation file",
+    long_about = "Build a recipe and assert its packages against an expectation file\n\n\
+                  Emitted stones are checked in place and never copied next to the recipe."
+)]
+pub struct Command {
+    #[arg(short, long, default_value = "default-x86_64")]
+    profile: profile::Id,
+    #[arg(
+        short,
+        long,
+        default_value = "false",
+        help = "Update profile repositories before building"
+    )]
+    update: bool,
+    #[arg(
+        short,
+        long,
+        help = "Path to expectation file, defaults to stone.expect.yaml next to the recipe"
+    )]
+    expect: Option<PathBuf>,
+    #[arg(default_value = "./stone.yaml", help = "Path to recipe file")]
+    recipe: PathBuf,
+}
+
+pub fn handle(command: Command, env: Env) -> Result<(), Error> {
+    let Command {
+        profile,
+        update,
+        expect,
+        recipe: recipe_path,
+    } = command;
+
+    let recipe_dir = recipe_path
+        .parent()
+        .map(|dir| {
+            if dir.as_os_str().is_empty() {
+                ".".into()
+            } else {
+                dir.to_owned()
+            }
+        })
+        .unwrap_or_else(|| ".".into());
+    let expect = expect.unwrap_or_else(|| recipe_dir.join("stone.expect.yaml"));
+
+    // Fail before spending time on a build
+    let expectations = Expectations::load(&expect).map_err(|error| Error::LoadExpectations(expect.clone(), error))?;
+
+    let mut timing = Timing::default();
+    let timer = timing.begin(timing::Kind::Initialize);
+
+    let builder = Builder::new(&recipe_path, env, profile, false, recipe_dir)?;
+    let host = builder.setup(&mut timing, timer, update)?;
+
+    let paths = &builder.paths;
+    let networking = builder.recipe.parsed.options.networking;
+
+    container::exec::<Error>(paths, networking, || {
+        builder.build(&mut timing)?;
+
+        let packager = Packager::new(
+            &builder.paths,
+            &builder.recipe,
+            &builder.macros,
+            &builder.targets,
+            &host,
+            NonZeroU64::MIN,
+        )?;
+        packager.package(&mut timing)?;
+
+        Ok(())
+    })?;
+
+    let emitted = Emitted::read_dir(&paths.artefacts().host)?;
+    let mismatches = expectations.check(&emitted);
+
+    println!();
+
+    if mismatches.is_empty() {
+        println!("{} {} package(s) match {expect:?}", "Passed".green(), emitted.len());
+        return Ok(());
+    }
+
+    for mismatch in &mismatches {
+        println!("{} {mismatch}", "Mismatch".red());
+    }
+
+    Err(Error::Failed(mismatches.len(), expect))
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("load expectations {0:?}")]
+    LoadExpectations(PathBuf, #[source] expectation::Error),
+    #[error("{0} expectation(s) in {1:?} not met")]
+    Failed(usize, PathBuf),
+    #[error("read emitted packages")]
+    Expectation(#[from] expectation::Error),
+    #[error("build recipe")]
+    Build(#[from] build::Error),
+    #[error("package artifacts")]
+    Package(#[from] package::Error),
+    #[error("container")]
+    Container(#[from] container::Error),
+}
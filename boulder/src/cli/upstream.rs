@@ -0,0 +1,143 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use boulder::{
+    build::upstream::cache::{self, Cache},
+    Env,
+};
+use chrono::{Duration, Utc};
+use clap::Parser;
+use thiserror::Error;
+use tui::{HumanBytes, Styled};
+
+#[derive(Debug, Parser)]
+#[command(about = "Inspect and maintain the upstream cache")]
+pub struct Command {
+    #[command(subcommand)]
+    subcommand: Subcommand,
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum Subcommand {
+    #[command(about = "List cached plain upstreams")]
+    List,
+    #[command(about = "Rehash cached plain upstreams, reporting any corruption")]
+    Verify,
+    #[command(about = "Remove cached plain upstreams that are corrupt, orphaned or unused")]
+    Prune {
+        #[arg(long, default_value = "90", help = "Remove upstreams not used for this many days")]
+        unused_days: u32,
+        #[arg(long, default_value = "false", help = "Only report what would be removed")]
+        dry_run: bool,
+    },
+}
+
+pub fn handle(command: Command, env: Env) -> Result<(), Error> {
+    let cache = Cache::new(&env.cache_dir.join("upstreams"));
+
+    match command.subcommand {
+        Subcommand::List => list(&cache),
+        Subcommand::Verify => verify(&cache),
+        Subcommand::Prune { unused_days, dry_run } => prune(&cache, unused_days, dry_run),
+    }
+}
+
+fn list(cache: &Cache) -> Result<(), Error> {
+    for cached in cache.list()? {
+        let Some(entry) = cached.entry else {
+            println!("{} {}", cached.hash.bold(), "(untracked)".dim());
+            continue;
+        };
+
+        let missing = (!cached.exists).then_some(" (missing)").unwrap_or_default();
+
+        println!("{} {}{}", entry.name.bold(), HumanBytes(entry.size), missing.red());
+        println!("  {} {}", "Hash".dim(), entry.hash);
+        for url in &entry.urls {
+            println!("  {} {url}", "Url".dim());
+        }
+        println!("  {} {}", "Fetched".dim(), entry.fetched.to_rfc3339());
+        println!("  {} {}", "Verified".dim(), entry.last_verified.to_rfc3339());
+        println!("  {} {}", "Used".dim(), entry.last_used.to_rfc3339());
+    }
+
+    Ok(())
+}
+
+fn verify(cache: &Cache) -> Result<(), Error> {
+    let mut corrupt = 0;
+
+    for cached in cache.list()? {
+        let name = cached
+            .entry
+            .as_ref()
+            .map_or(cached.hash.as_str(), |entry| entry.name.as_str());
+
+        if !cached.exists {
+            println!("{} {name} has metadata but no cached file", "Orphaned".yellow());
+        } else if cache.verify(&cached.hash)? {
+            let untracked = cached.entry.is_none().then_some(" (untracked)").unwrap_or_default();
+            println!("{} {name}{}", "Verified".green(), untracked.dim());
+        } else {
+            corrupt += 1;
+            println!("{} {name}", "Corrupt".red());
+            if let Some(entry) = &cached.entry {
+                for url in &entry.urls {
+                    println!("  {} {url}", "Url".dim());
+                }
+            }
+        }
+    }
+
+    if corrupt > 0 {
+        return Err(Error::Corrupt(corrupt));
+    }
+
+    Ok(())
+}
+
+fn prune(cache: &Cache, unused_days: u32, dry_run: bool) -> Result<(), Error> {
+    let cutoff = Utc::now() - Duration::days(unused_days.into());
+
+    let mut freed = 0;
+
+    for cached in cache.list()? {
+        let reason = match &cached.entry {
+            _ if !cached.exists => "orphaned",
+            Some(entry) if entry.last_used < cutoff => "unused",
+            _ if !cache.verify(&cached.hash)? => "corrupt",
+            _ => continue,
+        };
+
+        let name = cached
+            .entry
+            .as_ref()
+            .map_or(cached.hash.as_str(), |entry| entry.name.as_str());
+        freed += cached
+            .entry
+            .as_ref()
+            .filter(|_| cached.exists)
+            .map_or(0, |entry| entry.size);
+
+        if !dry_run {
+            cache.remove(&cached.hash)?;
+        }
+
+        let verb = if dry_run { "Would remove" } else { "Removed" };
+        println!("{} {name} ({reason})", verb.red());
+    }
+
+    let verb = if dry_run { "Would free" } else { "Freed" };
+    println!("{verb} {}", HumanBytes(freed));
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("{0} cached upstream(s) are corrupt, run `boulder upstream prune` to remove them")]
+    Corrupt(usize),
+    #[error("upstream cache")]
+    Cache(#[from] cache::Error),
+}
@@ -0,0 +1,313 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Checks that the host is able to run builds
+//!
+//! Each [`Check`] reports what it found along with how to fix it, so
+//! problems surface up front rather than halfway through a build.
+
+use std::{
+    env, fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use futures::StreamExt;
+use moss::{client::space, request, runtime};
+use nix::unistd::{access, AccessFlags};
+use tui::HumanBytes;
+
+use crate::{profile, util, Env};
+
+/// Binaries which builds rely on being present on the host
+const REQUIRED_BINARIES: &[(&str, &str)] = &[("git", "fetching git upstreams")];
+
+/// Free space below which builds are likely to fail
+const MIN_FREE_SPACE: u64 = 2 * 1024 * 1024 * 1024;
+/// Free space below which larger builds may fail
+const LOW_FREE_SPACE: u64 = 20 * 1024 * 1024 * 1024;
+
+/// How long to wait on a repository before considering it unreachable
+const REPOSITORY_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Outcome of a single check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    Ok(String),
+    /// Builds can run, but may be degraded
+    Warning {
+        problem: String,
+        fix: String,
+    },
+    /// Builds won't be able to run
+    Failed {
+        problem: String,
+        fix: String,
+    },
+}
+
+impl Status {
+    fn warning(problem: impl ToString, fix: impl ToString) -> Self {
+        Self::Warning {
+            problem: problem.to_string(),
+            fix: fix.to_string(),
+        }
+    }
+
+    fn failed(problem: impl ToString, fix: impl ToString) -> Self {
+        Self::Failed {
+            problem: problem.to_string(),
+            fix: fix.to_string(),
+        }
+    }
+
+    pub fn is_failed(&self) -> bool {
+        matches!(self, Self::Failed { .. })
+    }
+}
+
+/// A named check & its outcome
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+}
+
+impl Check {
+    fn new(name: impl ToString, status: Status) -> Self {
+        Self {
+            name: name.to_string(),
+            status,
+        }
+    }
+}
+
+/// Run all checks against the host & the configuration of `env`
+pub fn run(env: &Env) -> Vec<Check> {
+    let mut checks = vec![Check::new("User namespaces", user_namespaces())];
+
+    checks.extend(
+        REQUIRED_BINARIES
+            .iter()
+            .map(|(name, purpose)| Check::new(format!("Binary {name}"), binary(name, purpose))),
+    );
+
+    checks.push(Check::new("Cgroup delegation", cgroup_delegation()));
+    checks.push(Check::new("Overlayfs", overlayfs()));
+
+    for (name, dir) in [("cache", &env.cache_dir), ("moss root", &env.moss_dir)] {
+        checks.push(Check::new(format!("Disk space ({name})"), disk_space(dir)));
+    }
+
+    checks.extend(repositories(env));
+
+    checks
+}
+
+fn user_namespaces() -> Status {
+    if read_number("/proc/sys/user/max_user_namespaces") == Some(0) {
+        return Status::failed(
+            "user namespaces are disabled",
+            "set the `user.max_user_namespaces` sysctl to a non-zero value",
+        );
+    }
+
+    // Root can always create them
+    if util::is_root() {
+        return Status::Ok("available".into());
+    }
+
+    if read_number("/proc/sys/kernel/unprivileged_userns_clone") == Some(0) {
+        return Status::failed(
+            "unprivileged user namespaces are disabled",
+            "set the `kernel.unprivileged_userns_clone` sysctl to 1, or run boulder as root",
+        );
+    }
+
+    if read_number("/proc/sys/kernel/apparmor_restrict_unprivileged_userns") == Some(1) {
+        return Status::failed(
+            "AppArmor restricts unprivileged user namespaces",
+            "add an AppArmor profile permitting `userns` for boulder, or set the \
+             `kernel.apparmor_restrict_unprivileged_userns` sysctl to 0",
+        );
+    }
+
+    Status::Ok("available".into())
+}
+
+fn binary(name: &str, purpose: &str) -> Status {
+    match find_binary(name) {
+        Some(path) => Status::Ok(path.display().to_string()),
+        None => Status::failed(
+            format!("`{name}` wasn't found in $PATH, it's required for {purpose}"),
+            format!("install `{name}` on the host"),
+        ),
+    }
+}
+
+fn find_binary(name: &str) -> Option<PathBuf> {
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(name))
+        .find(|path| {
+            path.metadata()
+                .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        })
+}
+
+fn cgroup_delegation() -> Status {
+    if !Path::new("/sys/fs/cgroup/cgroup.controllers").exists() {
+        return Status::warning(
+            "the unified (v2) cgroup hierarchy isn't mounted",
+            "boot with `systemd.unified_cgroup_hierarchy=1` to allow limiting build resources",
+        );
+    }
+
+    let contents = fs::read_to_string("/proc/self/cgroup").unwrap_or_default();
+    let Some(cgroup) = unified_cgroup(&contents) else {
+        return Status::warning(
+            "couldn't determine the current cgroup",
+            "ensure /proc is mounted with access to /proc/self/cgroup",
+        );
+    };
+
+    let path = Path::new("/sys/fs/cgroup").join(cgroup.trim_start_matches('/'));
+
+    if access(&path.join("cgroup.subtree_control"), AccessFlags::W_OK).is_ok() {
+        Status::Ok(format!("{} is delegated", path.display()))
+    } else {
+        Status::warning(
+            format!("{} isn't delegated to this user", path.display()),
+            "run boulder within a delegated scope, i.e. `systemd-run --user --scope -p Delegate=yes boulder ...`",
+        )
+    }
+}
+
+/// Path of the v2 cgroup from the contents of `/proc/<pid>/cgroup`
+fn unified_cgroup(contents: &str) -> Option<&str> {
+    contents.lines().find_map(|line| line.strip_prefix("0::"))
+}
+
+fn overlayfs() -> Status {
+    let supported =
+        fs::read_to_string("/proc/filesystems").is_ok_and(|contents| supports_filesystem(&contents, "overlay"));
+
+    if supported {
+        Status::Ok("supported".into())
+    } else {
+        Status::warning(
+            "the kernel doesn't support overlayfs",
+            "load the `overlay` kernel module, i.e. `modprobe overlay`",
+        )
+    }
+}
+
+fn supports_filesystem(filesystems: &str, name: &str) -> bool {
+    filesystems
+        .lines()
+        .any(|line| line.split_whitespace().last() == Some(name))
+}
+
+fn disk_space(dir: &Path) -> Status {
+    let free = match space::available(dir) {
+        Ok(free) => free,
+        Err(error) => {
+            return Status::failed(
+                format!("couldn't query {}: {error}", dir.display()),
+                "ensure the directory exists & is accessible",
+            )
+        }
+    };
+
+    let found = format!("{} free in {}", HumanBytes(free), dir.display());
+
+    if free < MIN_FREE_SPACE {
+        Status::failed(
+            found,
+            "free up space or point boulder elsewhere with --cache-dir / --moss-root",
+        )
+    } else if free < LOW_FREE_SPACE {
+        Status::warning(found, "larger builds may run out of space, consider freeing some up")
+    } else {
+        Status::Ok(found)
+    }
+}
+
+/// Check each repository of every profile can be reached
+fn repositories(env: &Env) -> Vec<Check> {
+    let manager = profile::Manager::new(env);
+
+    if manager.profiles.is_empty() {
+        return vec![Check::new(
+            "Profiles",
+            Status::failed("no profiles are configured", "add one with `boulder profile add`"),
+        )];
+    }
+
+    let _guard = runtime::init();
+
+    manager
+        .profiles
+        .iter()
+        .flat_map(|(id, profile)| {
+            profile.repositories.iter().map(move |(repo, repository)| {
+                let status = match runtime::block_on(reachable(repository.uri.clone())) {
+                    Ok(()) => Status::Ok(repository.uri.to_string()),
+                    Err(error) => Status::failed(
+                        format!("{} is unreachable: {error}", repository.uri),
+                        format!("check the network, or update the uri with `boulder profile add {id} ...`"),
+                    ),
+                };
+
+                Check::new(format!("Repository {repo} ({id})"), status)
+            })
+        })
+        .collect()
+}
+
+/// Fetch the start of the resource at `uri`
+async fn reachable(uri: url::Url) -> Result<(), String> {
+    let fetch = async {
+        let mut stream = request::get(uri).await?;
+        stream.next().await.transpose()?;
+        Ok::<_, request::Error>(())
+    };
+
+    match tokio::time::timeout(REPOSITORY_TIMEOUT, fetch).await {
+        Ok(result) => result.map_err(|error| {
+            // Include the underlying cause, i.e. the io or connection error
+            let mut message = error.to_string();
+            let mut source = std::error::Error::source(&error);
+            while let Some(error) = source {
+                message.push_str(&format!(": {error}"));
+                source = error.source();
+            }
+            message
+        }),
+        Err(_) => Err(format!("timed out after {}s", REPOSITORY_TIMEOUT.as_secs())),
+    }
+}
+
+fn read_number(path: &str) -> Option<u64> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_proc() {
+        assert_eq!(
+            unified_cgroup("1:name=systemd:/user.slice\n0::/user.slice/user-1000.slice/session-2.scope\n"),
+            Some("/user.slice/user-1000.slice/session-2.scope")
+        );
+        assert_eq!(unified_cgroup("12:cpuset:/\n"), None);
+
+        let filesystems = "nodev\tsysfs\nnodev\toverlay\n\text4\n";
+        assert!(supports_filesystem(filesystems, "overlay"));
+        assert!(supports_filesystem(filesystems, "ext4"));
+        assert!(!supports_filesystem(filesystems, "btrfs"));
+    }
+}
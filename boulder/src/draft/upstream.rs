@@ -1,8 +1,8 @@
 use std::{io, path::Path, process::ExitStatus, time::Duration};
 
 use futures::{stream, StreamExt, TryStreamExt};
+use hash::{Algorithm, Digest};
 use moss::{environment, request, runtime};
-use sha2::{Digest, Sha256};
 use thiserror::Error;
 use tokio::{
     fs::{self, File},
@@ -23,7 +23,7 @@ pub struct Upstream {
 pub fn fetch_and_extract(upstreams: &[Url], extract_root: &Path) -> Result<Vec<Upstream>, Error> {
     util::recreate_dir(extract_root)?;
 
-    let mpb = MultiProgress::new();
+    let mpb = MultiProgress::with_draw_target(tui::draw_target());
 
     let ret = runtime::block_on(
         stream::iter(upstreams)
@@ -68,7 +68,7 @@ async fn fetch(url: &Url, output: &Path) -> Result<String, Error> {
 
     let mut file = File::create(&output).await?;
 
-    let mut hasher = Sha256::new();
+    let mut hasher = Digest::new(Algorithm::Sha256);
 
     while let Some(bytes) = stream.next().await {
         let mut bytes = bytes?;
@@ -78,7 +78,7 @@ async fn fetch(url: &Url, output: &Path) -> Result<String, Error> {
 
     file.flush().await?;
 
-    let hash = hex::encode(hasher.finalize());
+    let hash = hasher.finalize_hex();
 
     Ok(hash)
 }
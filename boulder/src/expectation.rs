@@ -0,0 +1,284 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Assert the packages emitted by a build against an expectation file
+//!
+//! Expectation files map each package name the recipe should emit to
+//! what is expected of it:
+//!
+//! ```yaml
+//! nano:
+//!   files:
+//!     - /usr/bin/nano
+//!   providers:
+//!     - binary(nano)
+//!   dependencies:
+//!     - soname(libc.so.6(x86_64))
+//! nano-docs: {}
+//! ```
+//!
+//! The emitted package names must match exactly. Listed `files` must be
+//! present, other files are ignored. When given, `providers` and
+//! `dependencies` must match exactly.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs, io,
+    path::Path,
+};
+
+use moss::package::{Meta, MissingMetaFieldError};
+use serde::Deserialize;
+use stone::read::PayloadKind;
+use thiserror::Error;
+
+use crate::util;
+
+/// Expectations of a single package
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Expected {
+    /// Paths which must be shipped by the package
+    pub files: BTreeSet<String>,
+    /// The exact providers of the package, if checked
+    pub providers: Option<BTreeSet<String>>,
+    /// The exact dependencies of the package, if checked
+    pub dependencies: Option<BTreeSet<String>>,
+}
+
+/// Expectations for all packages emitted by a recipe, keyed by package name
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Expectations(pub BTreeMap<String, Expected>);
+
+impl Expectations {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let content = fs::read_to_string(path)?;
+        Ok(serde_yaml::from_str(&content)?)
+    }
+
+    /// Compare `emitted` packages to these expectations, returning
+    /// all mismatches found
+    pub fn check(&self, emitted: &BTreeMap<String, Emitted>) -> Vec<Mismatch> {
+        let mut mismatches = vec![];
+
+        for name in emitted.keys().filter(|name| !self.0.contains_key(*name)) {
+            mismatches.push(Mismatch::UnexpectedPackage(name.clone()));
+        }
+
+        for (name, expected) in &self.0 {
+            let Some(package) = emitted.get(name) else {
+                mismatches.push(Mismatch::MissingPackage(name.clone()));
+                continue;
+            };
+
+            for path in expected.files.difference(&package.files) {
+                mismatches.push(Mismatch::MissingFile {
+                    package: name.clone(),
+                    path: path.clone(),
+                });
+            }
+
+            if let Some(providers) = &expected.providers {
+                mismatches.extend(compare(name, Field::Providers, providers, &package.providers));
+            }
+            if let Some(dependencies) = &expected.dependencies {
+                mismatches.extend(compare(name, Field::Dependencies, dependencies, &package.dependencies));
+            }
+        }
+
+        mismatches
+    }
+}
+
+fn compare<'a>(
+    package: &'a str,
+    field: Field,
+    expected: &'a BTreeSet<String>,
+    emitted: &'a BTreeSet<String>,
+) -> impl Iterator<Item = Mismatch> + 'a {
+    let missing = expected.difference(emitted).map(move |value| Mismatch::Missing {
+        package: package.to_string(),
+        field,
+        value: value.clone(),
+    });
+    let unexpected = emitted.difference(expected).map(move |value| Mismatch::Unexpected {
+        package: package.to_string(),
+        field,
+        value: value.clone(),
+    });
+
+    missing.chain(unexpected)
+}
+
+/// The relevant contents of an emitted package stone
+#[derive(Debug, Clone, Default)]
+pub struct Emitted {
+    pub files: BTreeSet<String>,
+    pub providers: BTreeSet<String>,
+    pub dependencies: BTreeSet<String>,
+}
+
+impl Emitted {
+    /// Read all package stones in `dir`, keyed by package name
+    pub fn read_dir(dir: &Path) -> Result<BTreeMap<String, Self>, Error> {
+        let stones = util::enumerate_files(dir, |path| path.extension().is_some_and(|ext| ext == "stone"))?;
+
+        let mut emitted = BTreeMap::new();
+
+        for path in stones {
+            let (name, package) = Self::read(&path)?;
+            emitted.insert(name, package);
+        }
+
+        Ok(emitted)
+    }
+
+    fn read(path: &Path) -> Result<(String, Self), Error> {
+        let mut file = fs::File::open(path)?;
+        let mut reader = stone::read(&mut file)?;
+
+        let mut meta = None;
+        let mut files = BTreeSet::new();
+
+        for payload in reader.payloads()? {
+            match payload? {
+                PayloadKind::Meta(payload) => meta = Some(Meta::from_stone_payload(&payload.body)?),
+                PayloadKind::Layout(payload) => {
+                    files.extend(
+                        payload
+                            .body
+                            .iter()
+                            .filter(|layout| !matches!(layout.entry, stone::payload::layout::Entry::Directory(_)))
+                            .map(|layout| format!("/usr/{}", layout.entry.target())),
+                    );
+                }
+                _ => {}
+            }
+        }
+
+        let meta = meta.ok_or(Error::MissingMetaPayload)?;
+
+        Ok((
+            meta.name.to_string(),
+            Self {
+                files,
+                providers: meta.providers.iter().map(ToString::to_string).collect(),
+                dependencies: meta.dependencies.iter().map(ToString::to_string).collect(),
+            },
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum Field {
+    Providers,
+    Dependencies,
+}
+
+/// A single deviation from the [`Expectations`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    MissingPackage(String),
+    UnexpectedPackage(String),
+    MissingFile {
+        package: String,
+        path: String,
+    },
+    Missing {
+        package: String,
+        field: Field,
+        value: String,
+    },
+    Unexpected {
+        package: String,
+        field: Field,
+        value: String,
+    },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mismatch::MissingPackage(name) => write!(f, "{name}: package was not emitted"),
+            Mismatch::UnexpectedPackage(name) => write!(f, "{name}: package was not expected"),
+            Mismatch::MissingFile { package, path } => write!(f, "{package}: missing file {path}"),
+            Mismatch::Missing { package, field, value } => write!(f, "{package}: missing {field} entry {value}"),
+            Mismatch::Unexpected { package, field, value } => {
+                write!(f, "{package}: unexpected {field} entry {value}")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("io")]
+    Io(#[from] io::Error),
+    #[error("parse expectations")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("stone read")]
+    StoneRead(#[from] stone::read::Error),
+    #[error("meta payload missing")]
+    MissingMetaPayload,
+    #[error(transparent)]
+    MissingMetaField(#[from] MissingMetaFieldError),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn set(values: &[&str]) -> BTreeSet<String> {
+        values.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn check() {
+        let expectations: Expectations = serde_yaml::from_str(
+            "
+nano:
+  files: [/usr/bin/nano, /usr/bin/rnano]
+  dependencies: [soname(libc.so.6(x86_64))]
+nano-docs: {}
+",
+        )
+        .unwrap();
+
+        let emitted = BTreeMap::from([
+            (
+                "nano".to_string(),
+                Emitted {
+                    files: set(&["/usr/bin/nano", "/usr/share/nano/c.nanorc"]),
+                    providers: set(&["binary(nano)"]),
+                    dependencies: set(&["soname(libncursesw.so.6(x86_64))"]),
+                },
+            ),
+            ("nano-devel".to_string(), Emitted::default()),
+        ]);
+
+        let mismatches = expectations.check(&emitted);
+
+        assert_eq!(
+            mismatches,
+            vec![
+                Mismatch::UnexpectedPackage("nano-devel".into()),
+                Mismatch::MissingFile {
+                    package: "nano".into(),
+                    path: "/usr/bin/rnano".into()
+                },
+                Mismatch::Missing {
+                    package: "nano".into(),
+                    field: Field::Dependencies,
+                    value: "soname(libc.so.6(x86_64))".into()
+                },
+                Mismatch::Unexpected {
+                    package: "nano".into(),
+                    field: Field::Dependencies,
+                    value: "soname(libncursesw.so.6(x86_64))".into()
+                },
+                Mismatch::MissingPackage("nano-docs".into()),
+            ]
+        );
+    }
+}
@@ -11,10 +11,15 @@ pub use self::timing::Timing;
 
 pub mod architecture;
 pub mod build;
+pub mod ci;
 pub mod container;
+pub mod doctor;
 pub mod draft;
 pub mod env;
+pub mod expectation;
+pub mod lint;
 pub mod macros;
+pub mod messages;
 pub mod package;
 pub mod paths;
 pub mod profile;
@@ -0,0 +1,319 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Static checks of recipe build steps for common reproducibility hazards
+//!
+//! Steps are checked line by line, so a reviewed line can be suppressed with a
+//! `# lint-ignore: <rule>, ...` comment at its end or on the line above it.
+//! Omitting the rules (`# lint-ignore`) suppresses all of them.
+
+use std::{collections::BTreeSet, sync::OnceLock};
+
+use regex::Regex;
+use stone_recipe::{Build, Recipe};
+use strum::IntoEnumIterator;
+
+/// Marker of a suppression comment
+const SUPPRESSION: &str = "lint-ignore";
+
+/// A reproducibility hazard
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, strum::Display, strum::EnumIter)]
+#[strum(serialize_all = "kebab-case")]
+pub enum Rule {
+    /// The current date ends up in the output
+    Date,
+    /// `find` output is consumed in filesystem order
+    UnsortedFind,
+    /// Content is downloaded while building
+    Network,
+    /// The build host's name ends up in the output
+    Hostname,
+}
+
+impl Rule {
+    /// Why the rule matched & what to do instead
+    pub fn description(&self) -> &'static str {
+        match self {
+            Rule::Date => "`date` differs between builds, use `$SOURCE_DATE_EPOCH` instead",
+            Rule::UnsortedFind => "`find` lists files in filesystem order, pipe it through `sort`",
+            Rule::Network => "downloads during the build aren't pinned, add them as upstreams",
+            Rule::Hostname => "the hostname differs between build hosts",
+        }
+    }
+
+    fn patterns(&self) -> &'static [Regex] {
+        static PATTERNS: OnceLock<Vec<(Rule, Vec<Regex>)>> = OnceLock::new();
+
+        let patterns = PATTERNS.get_or_init(|| {
+            // Commands start a line or follow a separator, substitution or pipe
+            let command = |name: &str| format!(r"(^|[\s;&|(`]){name}($|[\s;&|)`])");
+
+            [
+                (Rule::Date, vec![command("date")]),
+                (
+                    Rule::UnsortedFind,
+                    vec![
+                        // Piped into another command
+                        r"(^|[\s;&|(`])find\s[^|]*\|".to_string(),
+                        // Substituted into the command line
+                        r"(\$\(|`)\s*find\s".to_string(),
+                    ],
+                ),
+                (
+                    Rule::Network,
+                    vec![
+                        command("(curl|wget)"),
+                        r"\bgit\s+(clone|fetch|pull|submodule\s+update)\b".to_string(),
+                        r"\bpip3?\s+(install|download)\b".to_string(),
+                        r"\bnpm\s+(install|ci)\b".to_string(),
+                        r"\bgo\s+(get|mod\s+download)\b".to_string(),
+                    ],
+                ),
+                (
+                    Rule::Hostname,
+                    vec![
+                        command("hostname"),
+                        r"\buname\s+(-\w*[an]|--all|--nodename)\b".to_string(),
+                        r"\$\{?HOSTNAME\b".to_string(),
+                    ],
+                ),
+            ]
+            .into_iter()
+            .map(|(rule, patterns)| {
+                let patterns = patterns
+                    .iter()
+                    .map(|pattern| Regex::new(pattern).expect("valid regex"))
+                    .collect();
+                (rule, patterns)
+            })
+            .collect()
+        });
+
+        patterns
+            .iter()
+            .find_map(|(rule, patterns)| (rule == self).then_some(patterns.as_slice()))
+            .unwrap_or_default()
+    }
+
+    /// Whether `code` has this hazard, ignoring known safe uses
+    fn matches(&self, code: &str) -> bool {
+        if !self.patterns().iter().any(|pattern| pattern.is_match(code)) {
+            return false;
+        }
+
+        match self {
+            Rule::Date => !code.contains("SOURCE_DATE_EPOCH"),
+            Rule::UnsortedFind => !code.contains("sort"),
+            Rule::Network => !code.contains("--no-index") && !code.contains("--offline"),
+            Rule::Hostname => true,
+        }
+    }
+}
+
+/// A rule matched by a line of a build step
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub rule: Rule,
+    /// Build step, prefixed by its profile if not the default one
+    pub step: String,
+    /// Line within the step, starting at 1
+    pub line: usize,
+    pub text: String,
+}
+
+/// Check all build steps of `recipe`, including those of its profiles
+pub fn lint(recipe: &Recipe) -> Vec<Finding> {
+    Some((None, &recipe.build))
+        .into_iter()
+        .chain(
+            recipe
+                .profiles
+                .iter()
+                .map(|profile| (Some(profile.key.as_str()), &profile.value)),
+        )
+        .flat_map(|(profile, build)| {
+            steps(build).flat_map(move |(step, script)| {
+                let step = match profile {
+                    Some(profile) => format!("{profile}/{step}"),
+                    None => step.to_string(),
+                };
+                lint_script(&step, script)
+            })
+        })
+        .collect()
+}
+
+fn steps(build: &Build) -> impl Iterator<Item = (&'static str, &str)> {
+    [
+        ("environment", &build.environment),
+        ("setup", &build.setup),
+        ("build", &build.build),
+        ("install", &build.install),
+        ("check", &build.check),
+        ("workload", &build.workload),
+    ]
+    .into_iter()
+    .filter_map(|(step, script)| Some((step, script.as_deref()?)))
+}
+
+fn lint_script(step: &str, script: &str) -> Vec<Finding> {
+    let mut findings = vec![];
+    // Suppressed by a comment on the line above
+    let mut pending = Suppressed::default();
+    // Line continuations are checked as a single line
+    let mut continued: Option<(usize, String)> = None;
+
+    for (number, line) in script.lines().enumerate() {
+        let (start, line) = match continued.take() {
+            Some((start, previous)) => (start, format!("{previous} {}", line.trim())),
+            None => (number + 1, line.trim().to_string()),
+        };
+
+        if let Some(stripped) = line.strip_suffix('\\') {
+            continued = Some((start, stripped.trim_end().to_string()));
+            continue;
+        }
+
+        let (code, comment) = split_comment(&line);
+        let suppressed = pending.merge(comment.map(Suppressed::parse).unwrap_or_default());
+
+        if code.trim().is_empty() {
+            pending = suppressed;
+            continue;
+        }
+        pending = Suppressed::default();
+
+        findings.extend(
+            Rule::iter()
+                .filter(|rule| !suppressed.contains(*rule) && rule.matches(code))
+                .map(|rule| Finding {
+                    rule,
+                    step: step.to_string(),
+                    line: start,
+                    text: line.clone(),
+                }),
+        );
+    }
+
+    findings
+}
+
+/// Rules suppressed by a comment
+#[derive(Debug, Clone, Default)]
+enum Suppressed {
+    #[default]
+    None,
+    All,
+    Rules(BTreeSet<String>),
+}
+
+impl Suppressed {
+    fn parse(comment: &str) -> Self {
+        let Some(rest) = comment.trim().strip_prefix(SUPPRESSION) else {
+            return Self::None;
+        };
+
+        match rest.trim().strip_prefix(':') {
+            Some(rules) => Self::Rules(rules.split(',').map(|rule| rule.trim().to_string()).collect()),
+            None if rest.trim().is_empty() => Self::All,
+            None => Self::None,
+        }
+    }
+
+    fn merge(self, other: Self) -> Self {
+        match (self, other) {
+            (Self::All, _) | (_, Self::All) => Self::All,
+            (Self::None, other) | (other, Self::None) => other,
+            (Self::Rules(a), Self::Rules(b)) => Self::Rules(a.into_iter().chain(b).collect()),
+        }
+    }
+
+    fn contains(&self, rule: Rule) -> bool {
+        match self {
+            Self::None => false,
+            Self::All => true,
+            Self::Rules(rules) => rules.contains(&rule.to_string()),
+        }
+    }
+}
+
+/// Split a shell line into its code & trailing comment, if any
+fn split_comment(line: &str) -> (&str, Option<&str>) {
+    let mut quote = None;
+    let mut previous = None;
+
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (None, '\'' | '"') if previous != Some('\\') => quote = Some(c),
+            (Some(q), c) if c == q && previous != Some('\\') => quote = None,
+            (None, '#') if previous.map_or(true, char::is_whitespace) => {
+                return (&line[..i], Some(&line[i + 1..]));
+            }
+            _ => {}
+        }
+        previous = Some(c);
+    }
+
+    (line, None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rules(script: &str) -> Vec<(Rule, usize)> {
+        lint_script("build", script)
+            .into_iter()
+            .map(|finding| (finding.rule, finding.line))
+            .collect()
+    }
+
+    #[test]
+    fn hazards() {
+        assert_eq!(rules("echo \"built $(date)\" > BUILD"), vec![(Rule::Date, 1)]);
+        assert_eq!(rules("date -u -d @$SOURCE_DATE_EPOCH +%Y"), vec![]);
+        assert_eq!(rules("%make update-date"), vec![]);
+
+        assert_eq!(
+            rules("find . -name '*.o' | xargs ar rcs libfoo.a"),
+            vec![(Rule::UnsortedFind, 1)]
+        );
+        assert_eq!(
+            rules("for f in $(find . -type f); do\n  cat $f\ndone"),
+            vec![(Rule::UnsortedFind, 1)]
+        );
+        assert_eq!(rules("find . -name '*.o' | sort | xargs ar rcs libfoo.a"), vec![]);
+        assert_eq!(rules("find . -name '*.la' -delete"), vec![]);
+        assert_eq!(
+            rules("find . -type f \\\n  | xargs sha256sum"),
+            vec![(Rule::UnsortedFind, 1)]
+        );
+
+        assert_eq!(
+            rules("%cmake_ninja\ncurl -LO https://example.com/data.tar\ngit clone https://example.com/foo"),
+            vec![(Rule::Network, 2), (Rule::Network, 3)]
+        );
+        assert_eq!(rules("pip install --no-index --find-links dist foo"), vec![]);
+
+        assert_eq!(rules("echo \"host: $HOSTNAME\" > info"), vec![(Rule::Hostname, 1)]);
+        assert_eq!(
+            rules("sed -i \"s/@HOST@/$(uname -n)/\" config.h"),
+            vec![(Rule::Hostname, 1)]
+        );
+        assert_eq!(rules("uname -m"), vec![]);
+    }
+
+    #[test]
+    fn suppression() {
+        assert_eq!(rules("date > stamp # lint-ignore: date"), vec![]);
+        assert_eq!(rules("date > stamp # lint-ignore: network"), vec![(Rule::Date, 1)]);
+        assert_eq!(
+            rules("# lint-ignore\necho $(date) $HOSTNAME\ndate"),
+            vec![(Rule::Date, 3)]
+        );
+        assert_eq!(rules("# lint-ignore: date, hostname\necho $(date) $HOSTNAME"), vec![]);
+        // Not a comment within quotes
+        assert_eq!(rules("echo '# lint-ignore' $(date)"), vec![(Rule::Date, 1)]);
+    }
+}
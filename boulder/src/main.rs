@@ -4,6 +4,7 @@
 
 use std::error::Error;
 
+use boulder::messages;
 use tui::Styled;
 
 mod cli;
@@ -18,7 +19,7 @@ fn main() {
 fn report_error(error: cli::Error) {
     let sources = sources(&error);
     let error = sources.join(": ");
-    eprintln!("{}: {error}", "Error".red());
+    eprintln!("{}: {error}", messages::get("error").red());
 }
 
 fn sources(error: &cli::Error) -> Vec<String> {
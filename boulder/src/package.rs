@@ -5,26 +5,36 @@ use std::collections::{btree_map, BTreeMap};
 use std::{fs, io, num::NonZeroU64};
 
 use itertools::Itertools;
+use moss::signature::SigningKey;
 use thiserror::Error;
 
 use stone::write::digest;
 use stone_recipe::{script, Package};
 
-use crate::{build, container, timing, util, Macros, Paths, Recipe, Timing};
+use crate::{
+    build::{self, hardening, host},
+    container, timing, util, Macros, Paths, Recipe, Timing,
+};
 
 use self::collect::Collector;
 use self::emit::emit;
+use self::normalize::Normalizer;
 
 mod analysis;
 mod collect;
 mod emit;
+mod normalize;
 
 pub struct Packager<'a> {
     paths: &'a Paths,
     recipe: &'a Recipe,
     packages: BTreeMap<String, Package>,
     collector: Collector,
+    host: &'a host::Report,
+    /// Hardening features of each build target, recorded in the manifest
+    hardening: BTreeMap<String, hardening::Report>,
     build_release: NonZeroU64,
+    signing_key: Option<&'a SigningKey>,
 }
 
 impl<'a> Packager<'a> {
@@ -33,6 +43,7 @@ impl<'a> Packager<'a> {
         recipe: &'a Recipe,
         macros: &'a Macros,
         targets: &'a [build::Target],
+        host: &'a host::Report,
         build_release: NonZeroU64,
     ) -> Result<Self, Error> {
         let mut collector = Collector::new(paths.install().guest);
@@ -48,15 +59,31 @@ impl<'a> Packager<'a> {
         // package paths to [`Collector`]
         let packages = resolve_packages(arches, macros, recipe, &mut collector)?;
 
+        let hardening = targets
+            .iter()
+            .map(|target| {
+                let report = hardening::Report::new(target.build_target, recipe, macros)?;
+                Ok((target.build_target.to_string(), report))
+            })
+            .collect::<Result<_, Error>>()?;
+
         Ok(Self {
             paths,
             recipe,
             collector,
             packages,
+            host,
+            hardening,
             build_release,
+            signing_key: None,
         })
     }
 
+    /// Sign the emitted stones with `signing_key`
+    pub fn with_signing_key(self, signing_key: Option<&'a SigningKey>) -> Self {
+        Self { signing_key, ..self }
+    }
+
     pub fn package(&self, timing: &mut Timing) -> Result<(), Error> {
         // Hasher used for calculating file digests
         let mut hasher = digest::Hasher::new();
@@ -79,6 +106,12 @@ impl<'a> Packager<'a> {
 
         let timer = timing.begin(timing::Kind::Emit);
 
+        // Normalize ownership, modes & timestamps of all included paths
+        let normalizer = Normalizer::new(self.recipe);
+        for info in analysis.buckets.values_mut().flat_map(|bucket| bucket.paths.iter_mut()) {
+            normalizer.apply(info).map_err(Error::Normalize)?;
+        }
+
         // Combine the package definition with the analysis results
         // for that package. We will use this to emit the package stones & manifests.
         //
@@ -101,7 +134,15 @@ impl<'a> Packager<'a> {
             .collect::<Vec<_>>();
 
         // Emit package stones and manifest files to artefact directory
-        emit(self.paths, self.recipe, &packages).map_err(Error::Emit)?;
+        emit(
+            self.paths,
+            self.recipe,
+            self.host,
+            &self.hardening,
+            &packages,
+            self.signing_key,
+        )
+        .map_err(Error::Emit)?;
 
         timing.finish(timer);
 
@@ -176,6 +217,8 @@ fn resolve_packages(
                     .chain(prev.paths)
                     .sorted_by_key(|p| p.path.clone())
                     .collect();
+                package.triggers.transaction.extend(prev.triggers.transaction);
+                package.triggers.system.extend(prev.triggers.system);
 
                 packages.insert(name, package);
             }
@@ -225,10 +268,14 @@ pub fn sync_artefacts(paths: &Paths) -> Result<(), io::Error> {
 pub enum Error {
     #[error("script")]
     Script(#[from] script::Error),
+    #[error("hardening")]
+    Hardening(#[from] hardening::Error),
     #[error("collect install paths")]
     CollectPaths(#[source] collect::Error),
     #[error("analyzing paths")]
     Analysis(#[source] analysis::BoxError),
+    #[error("normalize paths")]
+    Normalize(#[source] io::Error),
     #[error("emit packages")]
     Emit(#[from] emit::Error),
     #[error("container")]
@@ -54,7 +54,7 @@ impl<'a> Chain<'a> {
 
         let mut queue = paths.into_iter().collect::<VecDeque<_>>();
 
-        let pb = ProgressBar::new(queue.len() as u64)
+        let pb = ProgressBar::with_draw_target(Some(queue.len() as u64), tui::draw_target())
             .with_message("Analyzing")
             .with_style(
                 ProgressStyle::with_template("\n|{bar:20.red/blue}| {pos}/{len} {wide_msg}")
@@ -23,14 +23,19 @@ pub struct Rule {
 
 impl Rule {
     pub fn matches(&self, path: &str) -> bool {
-        self.pattern == path
-            || path.starts_with(&self.pattern)
-            || Pattern::new(&self.pattern)
-                .map(|pattern| pattern.matches(path))
-                .unwrap_or_default()
+        matches(&self.pattern, path)
     }
 }
 
+/// Whether `path` matches `pattern` exactly, as a prefix or as a glob
+pub fn matches(pattern: &str, path: &str) -> bool {
+    pattern == path
+        || path.starts_with(pattern)
+        || Pattern::new(pattern)
+            .map(|pattern| pattern.matches(path))
+            .unwrap_or_default()
+}
+
 #[derive(Debug)]
 pub struct Collector {
     /// Rules stored in order of
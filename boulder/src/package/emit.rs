@@ -2,6 +2,7 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 use std::{
+    collections::{BTreeMap, BTreeSet},
     fs::{self, File},
     io::{self, Write},
     num::NonZeroU64,
@@ -9,13 +10,21 @@ use std::{
 };
 
 use itertools::Itertools;
-use moss::{package::Meta, Dependency, Provider};
+use moss::{
+    package::{Meta, Trigger, TriggerScope},
+    signature::SigningKey,
+    Dependency, Provider,
+};
 use thiserror::Error;
 use tui::{ProgressBar, ProgressStyle, Styled};
 
 use self::manifest::Manifest;
 use super::analysis;
-use crate::{architecture, util, Architecture, Paths, Recipe};
+use crate::{
+    architecture,
+    build::{hardening, host},
+    messages, util, Architecture, Paths, Recipe,
+};
 
 mod manifest;
 
@@ -88,11 +97,38 @@ impl<'a> Package<'a> {
                 .iter()
                 .filter_map(|name| Provider::from_name(name).ok())
                 .collect(),
+            replaces: self
+                .definition
+                .replaces
+                .iter()
+                .filter_map(|name| Provider::from_name(name).ok())
+                .collect(),
             uri: None,
             hash: None,
             download_size: None,
+            installed_size: None,
+            deltas: Default::default(),
+            triggers: self.triggers(),
         }
     }
+
+    /// Triggers declared by the package definition, validated when the recipe is loaded
+    fn triggers(&self) -> BTreeSet<Trigger> {
+        let declared = &self.definition.triggers;
+
+        declared
+            .transaction
+            .iter()
+            .map(|value| (TriggerScope::Transaction, value))
+            .chain(declared.system.iter().map(|value| (TriggerScope::System, value)))
+            .filter_map(|(scope, value)| {
+                Some(Trigger {
+                    scope,
+                    definition: serde_yaml::to_string(value).ok()?,
+                })
+            })
+            .collect()
+    }
 }
 
 impl<'a> PartialEq for Package<'a> {
@@ -115,17 +151,24 @@ impl<'a> Ord for Package<'a> {
     }
 }
 
-pub fn emit(paths: &Paths, recipe: &Recipe, packages: &[Package]) -> Result<(), Error> {
-    let mut manifest = Manifest::new(paths, recipe, architecture::host());
+pub fn emit(
+    paths: &Paths,
+    recipe: &Recipe,
+    host: &host::Report,
+    hardening: &BTreeMap<String, hardening::Report>,
+    packages: &[Package],
+    signing_key: Option<&SigningKey>,
+) -> Result<(), Error> {
+    let mut manifest = Manifest::new(paths, recipe, architecture::host(), host, hardening);
 
-    println!("Packaging");
+    println!("{}", messages::get("packaging"));
 
     for package in packages {
         if !package.is_dbginfo() {
             manifest.add_package(package);
         }
 
-        emit_package(paths, package)?;
+        emit_package(paths, package, signing_key)?;
     }
 
     manifest.write_binary()?;
@@ -136,7 +179,7 @@ pub fn emit(paths: &Paths, recipe: &Recipe, packages: &[Package]) -> Result<(),
     Ok(())
 }
 
-fn emit_package(paths: &Paths, package: &Package) -> Result<(), Error> {
+fn emit_package(paths: &Paths, package: &Package, signing_key: Option<&SigningKey>) -> Result<(), Error> {
     let filename = package.filename();
 
     // Sort all files by size, largest to smallest
@@ -149,7 +192,7 @@ fn emit_package(paths: &Paths, package: &Package) -> Result<(), Error> {
         .collect::<Vec<_>>();
     let total_file_size = sorted_files.iter().map(|p| p.size).sum();
 
-    let pb = ProgressBar::new(total_file_size)
+    let pb = ProgressBar::with_draw_target(Some(total_file_size), tui::draw_target())
         .with_message(format!("Generating {filename}"))
         .with_style(
             ProgressStyle::with_template(" {spinner} |{percent:>3}%| {wide_msg} {binary_bytes_per_sec:>.dim} ")
@@ -167,6 +210,9 @@ fn emit_package(paths: &Paths, package: &Package) -> Result<(), Error> {
 
     // Create stone binary writer
     let mut writer = stone::Writer::new(&mut out_file, stone::header::v1::FileType::Binary)?;
+    if let Some(key) = signing_key {
+        writer = writer.with_signer(key.signer());
+    }
 
     // Add metadata
     {
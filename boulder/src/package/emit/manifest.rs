@@ -2,11 +2,18 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::{collections::BTreeSet, io, path::PathBuf};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io,
+    path::PathBuf,
+};
 
 use thiserror::Error;
 
-use crate::{Architecture, Paths, Recipe};
+use crate::{
+    build::{hardening, host},
+    Architecture, Paths, Recipe,
+};
 
 use super::Package;
 
@@ -17,13 +24,21 @@ mod json;
 pub struct Manifest<'a> {
     recipe: &'a Recipe,
     arch: Architecture,
+    host: &'a host::Report,
+    hardening: &'a BTreeMap<String, hardening::Report>,
     output_dir: PathBuf,
     build_deps: BTreeSet<String>,
     packages: BTreeSet<&'a Package<'a>>,
 }
 
 impl<'a> Manifest<'a> {
-    pub fn new(paths: &Paths, recipe: &'a Recipe, arch: Architecture) -> Self {
+    pub fn new(
+        paths: &Paths,
+        recipe: &'a Recipe,
+        arch: Architecture,
+        host: &'a host::Report,
+        hardening: &'a BTreeMap<String, hardening::Report>,
+    ) -> Self {
         let output_dir = paths.artefacts().guest;
 
         let build_deps = recipe
@@ -39,6 +54,8 @@ impl<'a> Manifest<'a> {
             recipe,
             output_dir,
             arch,
+            host,
+            hardening,
             build_deps,
             packages: BTreeSet::new(),
         }
@@ -60,6 +77,8 @@ impl<'a> Manifest<'a> {
         json::write(
             &self.output_dir.join(format!("manifest.{}.jsonc", self.arch)),
             self.recipe,
+            self.host,
+            self.hardening,
             &self.packages,
             &self.build_deps,
         )
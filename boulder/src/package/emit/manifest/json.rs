@@ -13,11 +13,17 @@ use itertools::Itertools;
 use serde::Serialize;
 
 use super::Error;
-use crate::{package::emit, Recipe};
+use crate::{
+    build::{hardening, host},
+    package::emit,
+    Recipe,
+};
 
 pub fn write(
     path: &Path,
     recipe: &Recipe,
+    host: &host::Report,
+    hardening: &BTreeMap<String, hardening::Report>,
     packages: &BTreeSet<&emit::Package>,
     build_deps: &BTreeSet<String>,
 ) -> Result<(), Error> {
@@ -59,7 +65,9 @@ pub fn write(
         .collect();
 
     let content = Content {
-        manifest_version: "0.2".to_string(),
+        hardening,
+        host,
+        manifest_version: "0.3".to_string(),
         packages,
         source_name: recipe.parsed.source.name.clone(),
         source_release: recipe.parsed.source.release.to_string(),
@@ -84,7 +92,11 @@ pub fn write(
 
 #[derive(Serialize)]
 #[serde(rename_all = "kebab-case")]
-struct Content {
+struct Content<'a> {
+    /// Hardening features of each build target
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    hardening: &'a BTreeMap<String, hardening::Report>,
+    host: &'a host::Report,
     manifest_version: String,
     packages: BTreeMap<String, Package>,
     source_name: String,
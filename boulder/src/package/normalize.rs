@@ -0,0 +1,159 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Normalize ownership, modes & timestamps of packaged paths, regardless of
+//! how the build created them.
+//!
+//! Every path is owned by `root:root` and has the recipe `umask` applied, with
+//! setuid, setgid & sticky bits cleared. Recipes may declare `permissions` for
+//! paths that need anything else, i.e. a setuid binary.
+
+use std::{io, os::unix::fs::MetadataExt, path::Path};
+
+use chrono::{DateTime, Utc};
+use nix::{
+    libc::{S_ISGID, S_ISUID, S_ISVTX},
+    sys::{
+        stat::{utimensat, UtimensatFlags},
+        time::TimeSpec,
+    },
+};
+use stone::payload::{layout, Layout};
+use stone_recipe::Permission;
+
+use super::collect::{self, PathInfo};
+use crate::Recipe;
+
+/// Mode bits which are never kept unless explicitly requested
+const SPECIAL_BITS: u32 = S_ISUID | S_ISGID | S_ISVTX;
+
+/// Mask of the permission bits within a mode, excluding the file type
+const PERMISSION_BITS: u32 = 0o7777;
+
+#[derive(Debug)]
+pub struct Normalizer {
+    umask: u32,
+    /// Recipe exceptions, highest priority last
+    exceptions: Vec<(String, Permission)>,
+    /// Latest modification time, from `SOURCE_DATE_EPOCH`
+    timestamp: DateTime<Utc>,
+}
+
+impl Normalizer {
+    pub fn new(recipe: &Recipe) -> Self {
+        Self {
+            umask: recipe.parsed.options.umask,
+            exceptions: recipe
+                .parsed
+                .permissions
+                .iter()
+                .map(|entry| (entry.key.clone(), entry.value.clone()))
+                .collect(),
+            timestamp: recipe.build_time,
+        }
+    }
+
+    fn exception(&self, path: &str) -> Option<&Permission> {
+        self.exceptions
+            .iter()
+            .rev()
+            .find_map(|(pattern, permission)| collect::matches(pattern, path).then_some(permission))
+    }
+
+    /// Normalized copy of `layout` for the path at `target_path`
+    pub fn layout(&self, target_path: &Path, layout: &Layout) -> Layout {
+        let exception = self.exception(target_path.to_str().unwrap_or_default());
+
+        let file_type = layout.mode & !PERMISSION_BITS;
+        let permissions = match (&layout.entry, exception.and_then(|e| e.mode)) {
+            (_, Some(mode)) => mode & PERMISSION_BITS,
+            // Symlink permissions are meaningless, keep them as is
+            (layout::Entry::Symlink(..), None) => layout.mode & PERMISSION_BITS,
+            (_, None) => layout.mode & PERMISSION_BITS & !self.umask & !SPECIAL_BITS,
+        };
+
+        Layout {
+            uid: exception.and_then(|e| e.uid).unwrap_or(0),
+            gid: exception.and_then(|e| e.gid).unwrap_or(0),
+            mode: file_type | permissions,
+            tag: layout.tag,
+            entry: layout.entry.clone(),
+        }
+    }
+
+    /// Normalize the layout of `info` and clamp its modification
+    /// time on disk to the build time
+    pub fn apply(&self, info: &mut PathInfo) -> io::Result<()> {
+        info.layout = self.layout(&info.target_path, &info.layout);
+        self.clamp_mtime(&info.path)
+    }
+
+    fn clamp_mtime(&self, path: &Path) -> io::Result<()> {
+        let timestamp = self.timestamp.timestamp();
+        if path.symlink_metadata()?.mtime() <= timestamp {
+            return Ok(());
+        }
+
+        let time = TimeSpec::new(timestamp, 0);
+        utimensat(None, path, &time, &time, UtimensatFlags::NoFollowSymlink)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nix::libc::{S_IFDIR, S_IFLNK, S_IFREG};
+
+    use super::*;
+
+    fn layout(mode: u32, entry: layout::Entry) -> Layout {
+        Layout {
+            uid: 1000,
+            gid: 1000,
+            mode,
+            tag: 0,
+            entry,
+        }
+    }
+
+    #[test]
+    fn normalize() {
+        let normalizer = Normalizer {
+            umask: 0o022,
+            exceptions: vec![(
+                "/usr/bin/sudo".to_string(),
+                Permission {
+                    mode: Some(0o4755),
+                    ..Default::default()
+                },
+            )],
+            timestamp: Utc::now(),
+        };
+
+        let regular = |mode, name: &str| layout(S_IFREG | mode, layout::Entry::Regular(0, name.to_string()));
+
+        let normalized = normalizer.layout(Path::new("/usr/bin/nano"), &regular(0o6777, "bin/nano"));
+        assert_eq!((normalized.uid, normalized.gid), (0, 0));
+        assert_eq!(normalized.mode, S_IFREG | 0o755);
+
+        let normalized = normalizer.layout(Path::new("/usr/bin/sudo"), &regular(0o755, "bin/sudo"));
+        assert_eq!(normalized.mode, S_IFREG | 0o4755);
+
+        let dir = layout(S_IFDIR | 0o1777, layout::Entry::Directory("share/tmp".to_string()));
+        assert_eq!(
+            normalizer.layout(Path::new("/usr/share/tmp"), &dir).mode,
+            S_IFDIR | 0o755
+        );
+
+        let symlink = layout(
+            S_IFLNK | 0o777,
+            layout::Entry::Symlink("nano".to_string(), "bin/rnano".to_string()),
+        );
+        assert_eq!(
+            normalizer.layout(Path::new("/usr/bin/rnano"), &symlink).mode,
+            S_IFLNK | 0o777
+        );
+    }
+}
@@ -104,6 +104,11 @@ impl Paths {
         }
     }
 
+    /// Diagnostics summary of the last build of each recipe, only used on the host
+    pub fn diagnostics(&self) -> PathBuf {
+        self.host_root.join("diagnostics")
+    }
+
     /// For the provided [`Mapping`], return the guest
     /// path as it lives on the host fs
     ///
@@ -4,7 +4,10 @@
 
 use derive_more::Display;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 use thiserror::Error;
 
 use config::Config;
@@ -39,6 +42,9 @@ impl From<String> for Id {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Profile {
     pub repositories: repository::Map,
+    /// Key packages built with this profile are signed with, see [`moss::signature::SigningKey`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_key: Option<PathBuf>,
 }
 
 /// A map of profiles
@@ -110,6 +116,13 @@ impl<'a> Manager<'a> {
             .ok_or_else(|| Error::MissingProfile(profile.clone()))
     }
 
+    pub fn signing_key(&self, profile: &Id) -> Result<Option<&Path>, Error> {
+        self.profiles
+            .get(profile)
+            .map(|profile| profile.signing_key.as_deref())
+            .ok_or_else(|| Error::MissingProfile(profile.clone()))
+    }
+
     pub fn save_profile(&mut self, id: Id, profile: Profile) -> Result<(), Error> {
         // Save config
         let map = Map::with([(id.clone(), profile.clone())]);
@@ -28,6 +28,7 @@ impl Recipe {
         let path = resolve_path(path)?;
         let source = fs::read_to_string(&path)?;
         let parsed = stone_recipe::from_str(&source)?;
+        validate_triggers(&parsed)?;
         let build_time = resolve_build_time(&path);
 
         Ok(Self {
@@ -110,6 +111,35 @@ pub fn resolve_path(path: impl AsRef<Path>) -> Result<PathBuf, Error> {
     fs::canonicalize(&path).map_err(|_| Error::MissingRecipe(path))
 }
 
+/// Ensure triggers declared by packages are well formed, as moss
+/// skips any which aren't when they're installed
+fn validate_triggers(recipe: &Parsed) -> Result<(), Error> {
+    let packages = Some((recipe.source.name.as_str(), &recipe.package))
+        .into_iter()
+        .chain(recipe.sub_packages.iter().map(|kv| (kv.key.as_str(), &kv.value)));
+
+    for (package, definition) in packages {
+        let declared = definition
+            .triggers
+            .transaction
+            .iter()
+            .chain(&definition.triggers.system);
+
+        for value in declared {
+            let invalid = |reason: String| Error::InvalidTrigger {
+                package: package.to_string(),
+                reason,
+            };
+
+            let trigger = serde_yaml::from_value::<triggers::format::Trigger>(value.clone())
+                .map_err(|error| invalid(error.to_string()))?;
+            triggers::Collection::new([&trigger]).map_err(|error| invalid(error.to_string()))?;
+        }
+    }
+
+    Ok(())
+}
+
 fn resolve_build_time(path: &Path) -> DateTime<Utc> {
     // Propagate SOURCE_DATE_EPOCH if set
     if let Ok(epoch_env) = env::var("SOURCE_DATE_EPOCH") {
@@ -149,4 +179,6 @@ pub enum Error {
     Load(#[from] io::Error),
     #[error("decode recipe")]
     Decode(#[from] stone_recipe::Error),
+    #[error("invalid trigger in package {package}: {reason}")]
+    InvalidTrigger { package: String, reason: String },
 }
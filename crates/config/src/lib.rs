@@ -68,6 +68,15 @@ impl Manager {
         configs
     }
 
+    /// Load the config previously saved as `name`, ignoring all other layers
+    pub fn load_saved<T: Config>(&self, name: impl fmt::Display) -> Option<T> {
+        let domain = T::domain();
+
+        let path = self.scope.save_dir(&domain).join(format!("{name}.{EXTENSION}"));
+
+        read_config(path)
+    }
+
     pub fn save<T: Config + Serialize>(&self, name: impl fmt::Display, config: &T) -> Result<(), SaveError> {
         let domain = T::domain();
 
@@ -123,7 +132,7 @@ fn enumerate_paths(entry: Entry, resolve: Resolve<'_>, domain: &str) -> Vec<Path
         }
         Entry::Directory => {
             if let Ok(read_dir) = fs::read_dir(resolve.dir(domain)) {
-                read_dir
+                let mut paths = read_dir
                     .flatten()
                     .filter_map(|entry| {
                         let path = entry.path();
@@ -135,7 +144,11 @@ fn enumerate_paths(entry: Entry, resolve: Resolve<'_>, domain: &str) -> Vec<Path
                             None
                         }
                     })
-                    .collect()
+                    .collect::<Vec<_>>();
+
+                // Layered in a stable order, so later files can override earlier ones
+                paths.sort();
+                paths
             } else {
                 vec![]
             }
@@ -58,7 +58,8 @@ where
         }
     }
 
-    /// Add an edge from a to b
+    /// Add an edge from a to b. Returns false if it already exists
+    /// or would introduce a cycle, leaving the graph unchanged
     pub fn add_edge(&mut self, a: NodeIndex, b: NodeIndex) -> bool {
         let a_node = &self.0[a];
 
@@ -0,0 +1,38 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use hash::{Algorithm, Digest};
+use strum::IntoEnumIterator;
+
+/// Roughly the download size of a large transaction, verified in 64KiB chunks
+/// as downloads are streamed to disk
+const SIZE: usize = 256 * 1024 * 1024;
+const CHUNK: usize = 64 * 1024;
+
+fn verify(algorithm: Algorithm, data: &[u8]) -> Vec<u8> {
+    let mut digest = Digest::new(algorithm);
+    for chunk in data.chunks(CHUNK) {
+        digest.update(chunk);
+    }
+    digest.finalize()
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let data = (0..SIZE).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("verify");
+    group.throughput(Throughput::Bytes(SIZE as u64));
+    group.sample_size(10);
+
+    for algorithm in Algorithm::iter() {
+        let id = BenchmarkId::new(algorithm.to_string(), algorithm.acceleration());
+        group.bench_with_input(id, &data, |b, data| b.iter(|| verify(algorithm, black_box(data))));
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
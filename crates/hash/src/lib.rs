@@ -0,0 +1,245 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Digest computation behind a common [`Hasher`] interface
+//!
+//! Each [`Algorithm`] dispatches at runtime to the fastest implementation
+//! supported by the running CPU (i.e. SHA-NI / ARMv8 SHA2 extensions for
+//! sha256, AVX-512 / AVX2 / SSE4.1 / NEON for blake3), falling back to a
+//! portable implementation otherwise.
+
+use std::{fmt, io};
+
+use xxhash_rust::xxh3::Xxh3;
+
+/// Supported digest algorithms
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, strum::Display, strum::EnumString, strum::EnumIter)]
+#[strum(serialize_all = "kebab-case")]
+pub enum Algorithm {
+    Sha256,
+    Blake3,
+    #[strum(serialize = "xxh3-128")]
+    Xxh3_128,
+}
+
+impl Algorithm {
+    /// Create a new streaming [`Hasher`] for this algorithm
+    pub fn hasher(self) -> Box<dyn Hasher> {
+        match self {
+            Algorithm::Sha256 => Box::new(<sha2::Sha256 as sha2::Digest>::new()),
+            Algorithm::Blake3 => Box::new(blake3::Hasher::new()),
+            Algorithm::Xxh3_128 => Box::new(Xxh3::new()),
+        }
+    }
+
+    /// Digest `bytes` in one go
+    pub fn digest(self, bytes: &[u8]) -> Vec<u8> {
+        let mut hasher = self.hasher();
+        hasher.update(bytes);
+        hasher.finalize()
+    }
+
+    /// Hardware acceleration used for this algorithm on the running CPU
+    pub fn acceleration(self) -> Acceleration {
+        match self {
+            Algorithm::Sha256 => sha256_acceleration(),
+            Algorithm::Blake3 => simd_acceleration(),
+            // xxh3 vectorization is selected at compile time
+            Algorithm::Xxh3_128 if cfg!(target_feature = "avx2") => Acceleration::Avx2,
+            Algorithm::Xxh3_128 if cfg!(target_feature = "sse2") => Acceleration::Sse2,
+            Algorithm::Xxh3_128 if cfg!(target_feature = "neon") => Acceleration::Neon,
+            Algorithm::Xxh3_128 => Acceleration::Portable,
+        }
+    }
+}
+
+/// CPU extension an [`Algorithm`] is accelerated by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display)]
+#[strum(serialize_all = "kebab-case")]
+pub enum Acceleration {
+    #[strum(serialize = "sha-ni")]
+    ShaNi,
+    #[strum(serialize = "armv8-sha2")]
+    ArmSha2,
+    #[strum(serialize = "avx-512")]
+    Avx512,
+    Avx2,
+    #[strum(serialize = "sse4.1")]
+    Sse41,
+    Sse2,
+    Neon,
+    Portable,
+}
+
+/// A streaming digest computation
+pub trait Hasher: Send {
+    fn update(&mut self, bytes: &[u8]);
+
+    fn finalize(self: Box<Self>) -> Vec<u8>;
+}
+
+impl Hasher for sha2::Sha256 {
+    fn update(&mut self, bytes: &[u8]) {
+        sha2::Digest::update(self, bytes);
+    }
+
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        sha2::Digest::finalize(*self).to_vec()
+    }
+}
+
+impl Hasher for blake3::Hasher {
+    fn update(&mut self, bytes: &[u8]) {
+        blake3::Hasher::update(self, bytes);
+    }
+
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        blake3::Hasher::finalize(&self).as_bytes().to_vec()
+    }
+}
+
+impl Hasher for Xxh3 {
+    fn update(&mut self, bytes: &[u8]) {
+        Xxh3::update(self, bytes);
+    }
+
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        self.digest128().to_be_bytes().to_vec()
+    }
+}
+
+/// A [`Hasher`] for a known [`Algorithm`], which can also be
+/// used as an [`io::Write`] sink to i.e. [`io::copy`] into
+pub struct Digest {
+    algorithm: Algorithm,
+    hasher: Box<dyn Hasher>,
+}
+
+impl Digest {
+    pub fn new(algorithm: Algorithm) -> Self {
+        Self {
+            algorithm,
+            hasher: algorithm.hasher(),
+        }
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        self.hasher.update(bytes);
+    }
+
+    pub fn finalize(self) -> Vec<u8> {
+        self.hasher.finalize()
+    }
+
+    /// Finalize as a lowercase hex string
+    pub fn finalize_hex(self) -> String {
+        hex::encode(self.finalize())
+    }
+}
+
+impl fmt::Debug for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Digest").field("algorithm", &self.algorithm).finish()
+    }
+}
+
+impl io::Write for Digest {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn sha256_acceleration() -> Acceleration {
+    // Same detection `sha2` performs before selecting its SHA-NI backend
+    if std::arch::is_x86_feature_detected!("sha")
+        && std::arch::is_x86_feature_detected!("sse2")
+        && std::arch::is_x86_feature_detected!("ssse3")
+        && std::arch::is_x86_feature_detected!("sse4.1")
+    {
+        Acceleration::ShaNi
+    } else {
+        Acceleration::Portable
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+fn sha256_acceleration() -> Acceleration {
+    if std::arch::is_aarch64_feature_detected!("sha2") {
+        Acceleration::ArmSha2
+    } else {
+        Acceleration::Portable
+    }
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn sha256_acceleration() -> Acceleration {
+    Acceleration::Portable
+}
+
+#[cfg(target_arch = "x86_64")]
+fn simd_acceleration() -> Acceleration {
+    if std::arch::is_x86_feature_detected!("avx512f") && std::arch::is_x86_feature_detected!("avx512vl") {
+        Acceleration::Avx512
+    } else if std::arch::is_x86_feature_detected!("avx2") {
+        Acceleration::Avx2
+    } else if std::arch::is_x86_feature_detected!("sse4.1") {
+        Acceleration::Sse41
+    } else {
+        Acceleration::Sse2
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+fn simd_acceleration() -> Acceleration {
+    Acceleration::Neon
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn simd_acceleration() -> Acceleration {
+    Acceleration::Portable
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn known_digests() {
+        let cases = [
+            (
+                Algorithm::Sha256,
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            (
+                Algorithm::Blake3,
+                "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85",
+            ),
+            (Algorithm::Xxh3_128, "06b05ab6733a618578af5f94892f3950"),
+        ];
+
+        for (algorithm, expected) in cases {
+            assert_eq!(hex::encode(algorithm.digest(b"abc")), expected, "{algorithm}");
+
+            // Streaming in pieces matches the one-shot digest
+            let mut digest = Digest::new(algorithm);
+            digest.write_all(b"a").unwrap();
+            digest.write_all(b"bc").unwrap();
+            assert_eq!(digest.finalize_hex(), expected, "{algorithm}");
+        }
+
+        assert_eq!("xxh3-128".parse::<Algorithm>().unwrap(), Algorithm::Xxh3_128);
+    }
+}
@@ -0,0 +1,215 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Translated messages for the moss & boulder CLIs
+//!
+//! Each CLI embeds [Fluent](https://projectfluent.org) catalogs, one per
+//! language, and looks messages up by id within a [`Catalog`]. Messages
+//! missing from the catalog of the user's [`locale`] fall back to `en-US`,
+//! which must define every message.
+//!
+//! Only what the CLIs say to the user interactively is translated, i.e. the
+//! summaries & prompts of moss installing, removing or syncing packages, its
+//! dry-run plans and the build summary of boulder. Error messages, logs and machine readable output stay
+//! in English so they can be searched for & quoted in bug reports.
+//!
+//! # Example
+//! ```
+//!     let catalog = i18n::Catalog::new(
+//!         &[("en-US", "sharing-upstreams = Sharing { $count } upstream(s)\n")],
+//!         &"en-US".parse().unwrap(),
+//!     );
+//!     assert_eq!(
+//!         catalog.format("sharing-upstreams", &[("count", 2.into())]),
+//!         "Sharing 2 upstream(s)"
+//!     );
+//! ```
+
+use std::env;
+
+use fluent_bundle::{concurrent::FluentBundle, FluentArgs, FluentResource};
+
+pub use fluent_bundle::FluentValue as Value;
+pub use unic_langid::LanguageIdentifier;
+
+/// Language every catalog is written in first
+pub const FALLBACK: &str = "en-US";
+
+/// The messages of one CLI, in the user's language where translated
+pub struct Catalog {
+    /// Most preferred first, ending with [`FALLBACK`]
+    bundles: Vec<FluentBundle<FluentResource>>,
+}
+
+impl Catalog {
+    /// Load the `(language, source)` catalogs relevant to `locale`, preferring
+    /// an exact match over one for the same language, i.e. `de` for `de-AT`
+    pub fn new(catalogs: &[(&str, &str)], locale: &LanguageIdentifier) -> Self {
+        let fallback = FALLBACK.parse::<LanguageIdentifier>().expect("valid fallback language");
+
+        let exact = catalogs
+            .iter()
+            .find(|(language, _)| parse(language) == Some(locale.clone()));
+        let language = catalogs.iter().find(|(language, _)| {
+            parse(language).is_some_and(|language| language.language == locale.language && language.region.is_none())
+        });
+        let fallback = catalogs
+            .iter()
+            .find(|(language, _)| parse(language) == Some(fallback.clone()));
+
+        let mut chosen = vec![];
+        for (language, source) in [exact, language, fallback].into_iter().flatten() {
+            if !chosen.contains(&(language, source)) {
+                chosen.push((language, source));
+            }
+        }
+
+        let bundles = chosen
+            .into_iter()
+            .filter_map(|(language, source)| {
+                // Broken translations mustn't take the CLI down, the fallback covers them
+                let resource = FluentResource::try_new(source.to_string()).unwrap_or_else(|(resource, _)| resource);
+
+                let mut bundle = FluentBundle::new_concurrent(vec![parse(language)?]);
+                // Unicode isolation marks around arguments garble terminal output
+                bundle.set_use_isolating(false);
+                bundle.add_resource(resource).ok()?;
+
+                Some(bundle)
+            })
+            .collect();
+
+        Self { bundles }
+    }
+
+    /// The message `id`, or `id` itself if no catalog defines it
+    pub fn get(&self, id: &str) -> String {
+        self.format(id, &[])
+    }
+
+    /// The message `id` with its `$variables` substituted from `args`,
+    /// or `id` itself if no catalog defines it
+    pub fn format(&self, id: &str, args: &[(&str, Value<'_>)]) -> String {
+        let mut fluent_args = FluentArgs::new();
+        for (name, value) in args {
+            fluent_args.set(*name, value.clone());
+        }
+
+        for bundle in &self.bundles {
+            let Some(pattern) = bundle.get_message(id).and_then(|message| message.value()) else {
+                continue;
+            };
+
+            let mut errors = vec![];
+            let formatted = bundle.format_pattern(pattern, Some(&fluent_args), &mut errors);
+            if errors.is_empty() {
+                return formatted.into_owned();
+            }
+        }
+
+        id.to_string()
+    }
+}
+
+/// The user's language for messages, from the first of `LC_ALL`,
+/// `LC_MESSAGES` and `LANG` that is set, or [`FALLBACK`]
+pub fn locale() -> LanguageIdentifier {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .into_iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .and_then(|value| parse(&value))
+        .unwrap_or_else(|| FALLBACK.parse().expect("valid fallback language"))
+}
+
+/// Parse a POSIX locale such as `de_DE.UTF-8@euro` as a language identifier,
+/// or `None` for the untranslated `C` & `POSIX` locales
+fn parse(locale: &str) -> Option<LanguageIdentifier> {
+    let language = locale.split(['.', '@']).next().unwrap_or_default();
+
+    if matches!(language, "" | "C" | "POSIX") {
+        return None;
+    }
+
+    language.replace('_', "-").parse().ok()
+}
+
+/// Ids of the messages defined by the catalog `source`, i.e. to check
+/// that translations don't define messages missing from [`FALLBACK`]
+pub fn message_ids(source: &str) -> Vec<&str> {
+    source
+        .lines()
+        // Messages start unindented, unlike continuations, comments & terms
+        .filter(|line| line.starts_with(|c: char| c.is_ascii_alphabetic()))
+        .filter_map(|line| line.split_once('='))
+        .map(|(id, _)| id.trim())
+        .collect()
+}
+
+/// Assert that every translation within `catalogs` parses and only defines
+/// messages of the first, [`FALLBACK`] catalog. Meant for the tests of each CLI
+pub fn assert_catalogs(catalogs: &[(&str, &str)]) {
+    let (fallback, translations) = catalogs.split_first().expect("a fallback catalog");
+    assert_eq!(fallback.0, FALLBACK, "the first catalog must be the fallback");
+
+    let known = message_ids(fallback.1);
+
+    for (language, source) in catalogs {
+        assert!(parse(language).is_some(), "{language} isn't a language identifier");
+        if let Err((_, errors)) = FluentResource::try_new(source.to_string()) {
+            panic!("{language} catalog doesn't parse: {errors:?}");
+        }
+    }
+
+    for (language, source) in translations {
+        for id in message_ids(source) {
+            assert!(known.contains(&id), "{language} translates unknown message {id}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CATALOGS: &[(&str, &str)] = &[
+        ("en-US", "greeting = Hello { $name }\nfarewell = Goodbye\n"),
+        ("de", "greeting = Hallo { $name }\n"),
+    ];
+
+    #[test]
+    fn fallback() {
+        let german = Catalog::new(CATALOGS, &"de-AT".parse().unwrap());
+        assert_eq!(german.format("greeting", &[("name", "moss".into())]), "Hallo moss");
+        assert_eq!(german.get("farewell"), "Goodbye");
+        assert_eq!(german.get("missing"), "missing");
+
+        let french = Catalog::new(CATALOGS, &"fr".parse().unwrap());
+        assert_eq!(french.format("greeting", &[("name", "moss".into())]), "Hello moss");
+    }
+
+    #[test]
+    fn posix_locales() {
+        assert_eq!(parse("de_DE.UTF-8@euro"), "de-DE".parse().ok());
+        assert_eq!(parse("pt_BR"), "pt-BR".parse().ok());
+        assert_eq!(parse("C.UTF-8"), None);
+        assert_eq!(parse("POSIX"), None);
+    }
+
+    #[test]
+    fn ids() {
+        assert_eq!(message_ids(CATALOGS[0].1), vec!["greeting", "farewell"]);
+    }
+
+    #[test]
+    fn catalogs() {
+        assert_catalogs(CATALOGS);
+    }
+
+    #[test]
+    #[should_panic(expected = "de translates unknown message welcome")]
+    fn unknown_translation() {
+        assert_catalogs(&[CATALOGS[0], ("de", "welcome = Willkommen\n")]);
+    }
+}
@@ -0,0 +1,82 @@
+/*
+ * SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+ *
+ * SPDX-License-Identifier: MPL-2.0
+ */
+
+/*
+ * C ABI of moss
+ *
+ * Functions returning handles return NULL on failure, functions returning
+ * int return 0 on success and -1 on failure. The reason of the last failure
+ * on the calling thread is available from moss_last_error().
+ *
+ * Check moss_abi_version() against MOSS_ABI_VERSION before anything else,
+ * it's bumped whenever the ABI changes incompatibly.
+ */
+
+#ifndef MOSS_H
+#define MOSS_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define MOSS_ABI_VERSION 2
+
+typedef struct MossClient MossClient;
+typedef struct MossPackageList MossPackageList;
+
+/* A package, valid until the list it was taken from is freed */
+typedef struct MossPackage {
+    const char *id;
+    const char *name;
+    const char *version;
+    uint64_t release;
+    const char *summary;
+    bool installed;
+} MossPackage;
+
+typedef enum MossStage {
+    MOSS_STAGE_RESOLVING = 0,
+    MOSS_STAGE_FETCHING = 1,
+    MOSS_STAGE_APPLYING = 2,
+} MossStage;
+
+/*
+ * Each stage is reported as it begins with a NULL package. While fetching,
+ * it's reported again as each package is fetched & unpacked, along with the
+ * number of packages completed out of total. Calls may come from other
+ * threads, but never concurrently.
+ */
+typedef void (*MossProgress)(MossStage stage, const char *package, size_t completed, size_t total, void *user_data);
+
+uint32_t moss_abi_version(void);
+
+/* Valid until the next failing call on the same thread */
+const char *moss_last_error(void);
+
+MossClient *moss_client_open(const char *root);
+void moss_client_free(MossClient *client);
+
+MossPackageList *moss_list_installed(MossClient *client);
+MossPackageList *moss_search(MossClient *client, const char *keyword, bool installed_only);
+
+size_t moss_package_list_len(const MossPackageList *list);
+const MossPackage *moss_package_list_get(const MossPackageList *list, size_t index);
+void moss_package_list_free(MossPackageList *list);
+
+int moss_refresh(MossClient *client);
+
+/* progress may be NULL */
+int moss_install(MossClient *client, const char *const *names, size_t count, MossProgress progress, void *user_data);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* MOSS_H */
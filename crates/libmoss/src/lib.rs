@@ -0,0 +1,437 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! C ABI of moss, declared in `include/moss.h`
+//!
+//! A deliberately small surface for installers & frontends written in C, C++
+//! or Python: querying installed packages, searching repositories and
+//! installing packages. Handles are opaque, functions returning them return
+//! null on failure and functions returning `int` return 0 on success and -1
+//! on failure, with the reason available from [`moss_last_error`].
+//!
+//! [`MOSS_ABI_VERSION`] is bumped whenever the ABI changes incompatibly.
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_int, c_void, CStr, CString},
+    panic::{self, AssertUnwindSafe},
+    ptr, slice,
+    sync::{Arc, Mutex},
+};
+
+use moss::{
+    client::{self, install},
+    environment, installation,
+    package::{self, Keyword},
+    runtime, Client, Installation, Package,
+};
+use thiserror::Error;
+
+/// Version of the ABI, see [`moss_abi_version`]
+pub const MOSS_ABI_VERSION: u32 = 2;
+
+thread_local! {
+    /// Reason of the last failure on this thread
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// The moss runtime & number of open clients using it, so it's
+/// torn down along with the last client
+static RUNTIME: Mutex<Option<(runtime::Guard, usize)>> = Mutex::new(None);
+
+/// An open installation, see [`moss_client_open`]
+pub struct MossClient {
+    client: Client,
+}
+
+/// A package, borrowed from the [`MossPackageList`] it was taken from
+#[repr(C)]
+pub struct MossPackage {
+    pub id: *const c_char,
+    pub name: *const c_char,
+    pub version: *const c_char,
+    pub release: u64,
+    pub summary: *const c_char,
+    pub installed: bool,
+}
+
+/// Packages returned by a query
+pub struct MossPackageList {
+    packages: Vec<MossPackage>,
+    /// Owns the strings `packages` point to
+    _strings: Vec<CString>,
+}
+
+/// Stage of [`moss_install`], reported to its [`MossProgress`] callback
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MossStage {
+    Resolving = 0,
+    Fetching = 1,
+    Applying = 2,
+}
+
+/// Progress callback of [`moss_install`], called with the `user_data` it was passed
+///
+/// Each stage is reported as it begins with a null `package`. While fetching, it's
+/// reported again as each `package` is fetched & unpacked, along with the number of
+/// packages `completed` out of `total`. Calls may come from other threads, but never
+/// concurrently
+pub type MossProgress = Option<
+    unsafe extern "C" fn(
+        stage: MossStage,
+        package: *const c_char,
+        completed: usize,
+        total: usize,
+        user_data: *mut c_void,
+    ),
+>;
+
+/// A [`MossProgress`] callback along with its `user_data`
+struct Progress {
+    callback: unsafe extern "C" fn(MossStage, *const c_char, usize, usize, *mut c_void),
+    user_data: *mut c_void,
+}
+
+// Calls are serialized by the mutex it's wrapped in, see `moss_install`
+unsafe impl Send for Progress {}
+
+impl Progress {
+    fn report(&self, stage: MossStage, package: Option<&str>, completed: usize, total: usize) {
+        let package = package.and_then(|name| CString::new(name).ok());
+        let package = package.as_ref().map_or(ptr::null(), |name| name.as_ptr());
+
+        unsafe { (self.callback)(stage, package, completed, total, self.user_data) }
+    }
+}
+
+/// Version of the ABI this library implements
+#[no_mangle]
+pub extern "C" fn moss_abi_version() -> u32 {
+    MOSS_ABI_VERSION
+}
+
+/// Reason of the last failure on the calling thread, or null if there was none.
+/// Valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn moss_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |error| error.as_ptr()))
+}
+
+/// Open the installation at `root`, i.e. `/`
+///
+/// # Safety
+///
+/// `root` must be a valid nul-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn moss_client_open(root: *const c_char) -> *mut MossClient {
+    call(ptr::null_mut(), || {
+        let root = string(root, "root")?;
+        let installation = Installation::open(root)?;
+
+        // Progress is reported to callers instead of drawn on their terminal
+        tui::set_quiet(true);
+
+        let mut runtime = RUNTIME.lock().unwrap_or_else(|error| error.into_inner());
+        let client = match runtime.as_mut() {
+            Some((_, clients)) => {
+                let client = Client::new(environment::NAME, installation)?;
+                *clients += 1;
+                client
+            }
+            None => {
+                let guard = runtime::init();
+                let client = Client::new(environment::NAME, installation)?;
+                *runtime = Some((guard, 1));
+                client
+            }
+        };
+
+        Ok(Box::into_raw(Box::new(MossClient { client })))
+    })
+}
+
+/// Close a client returned by [`moss_client_open`]
+///
+/// # Safety
+///
+/// `client` must be null or a client which wasn't freed yet
+#[no_mangle]
+pub unsafe extern "C" fn moss_client_free(client: *mut MossClient) {
+    if client.is_null() {
+        return;
+    }
+    drop(Box::from_raw(client));
+
+    let mut runtime = RUNTIME.lock().unwrap_or_else(|error| error.into_inner());
+    if let Some((_, clients)) = runtime.as_mut() {
+        *clients -= 1;
+        if *clients == 0 {
+            *runtime = None;
+        }
+    }
+}
+
+/// All installed packages, sorted by name
+///
+/// # Safety
+///
+/// `client` must be a valid client
+#[no_mangle]
+pub unsafe extern "C" fn moss_list_installed(client: *mut MossClient) -> *mut MossPackageList {
+    call(ptr::null_mut(), || {
+        let client = client_mut(client)?;
+        let packages = client.query_installed(package::Flags::default());
+
+        Ok(Box::into_raw(Box::new(MossPackageList::new(packages))))
+    })
+}
+
+/// Installed & available packages whose name, providers, summary or
+/// description contain `keyword`, or only installed ones if `installed_only`
+///
+/// # Safety
+///
+/// `client` must be a valid client and `keyword` a valid nul-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn moss_search(
+    client: *mut MossClient,
+    keyword: *const c_char,
+    installed_only: bool,
+) -> *mut MossPackageList {
+    call(ptr::null_mut(), || {
+        let client = client_mut(client)?;
+        let keyword = Keyword::substring(string(keyword, "keyword")?);
+        let flags = if installed_only {
+            package::Flags::new().with_installed()
+        } else {
+            package::Flags::new()
+        };
+
+        let packages = client.registry.by_keyword(&keyword, flags).collect();
+
+        Ok(Box::into_raw(Box::new(MossPackageList::new(packages))))
+    })
+}
+
+/// Number of packages in `list`
+///
+/// # Safety
+///
+/// `list` must be a valid list
+#[no_mangle]
+pub unsafe extern "C" fn moss_package_list_len(list: *const MossPackageList) -> usize {
+    list.as_ref().map_or(0, |list| list.packages.len())
+}
+
+/// The package at `index` of `list`, or null if it's out of bounds
+///
+/// # Safety
+///
+/// `list` must be a valid list
+#[no_mangle]
+pub unsafe extern "C" fn moss_package_list_get(list: *const MossPackageList, index: usize) -> *const MossPackage {
+    list.as_ref()
+        .and_then(|list| list.packages.get(index))
+        .map_or(ptr::null(), |package| package as *const _)
+}
+
+/// Free a list returned by a query, along with its packages
+///
+/// # Safety
+///
+/// `list` must be null or a list which wasn't freed yet
+#[no_mangle]
+pub unsafe extern "C" fn moss_package_list_free(list: *mut MossPackageList) {
+    if !list.is_null() {
+        drop(Box::from_raw(list));
+    }
+}
+
+/// Refresh the indices of all repositories
+///
+/// # Safety
+///
+/// `client` must be a valid client
+#[no_mangle]
+pub unsafe extern "C" fn moss_refresh(client: *mut MossClient) -> c_int {
+    call(-1, || {
+        let client = client_mut(client)?;
+        runtime::block_on(client.refresh_repositories())?;
+
+        Ok(0)
+    })
+}
+
+/// Install the `count` packages `names` and their dependencies as a new
+/// state, reporting to `progress` (if not null) as described by [`MossProgress`]
+///
+/// # Safety
+///
+/// `client` must be a valid client and `names` point to `count`
+/// valid nul-terminated strings
+#[no_mangle]
+pub unsafe extern "C" fn moss_install(
+    client: *mut MossClient,
+    names: *const *const c_char,
+    count: usize,
+    progress: MossProgress,
+    user_data: *mut c_void,
+) -> c_int {
+    call(-1, || {
+        let client = client_mut(client)?;
+        if names.is_null() && count > 0 {
+            return Err(Error::Null("names"));
+        }
+        let names = if count > 0 {
+            slice::from_raw_parts(names, count)
+                .iter()
+                .map(|name| string(*name, "name"))
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            vec![]
+        };
+        let progress = progress.map(|callback| Arc::new(Mutex::new(Progress { callback, user_data })));
+        let report = |stage, package: Option<&str>, completed, total| {
+            if let Some(progress) = &progress {
+                let progress = progress.lock().unwrap_or_else(|error| error.into_inner());
+                progress.report(stage, package, completed, total);
+            }
+        };
+
+        report(MossStage::Resolving, None, 0, 0);
+        let resolution = install::resolve(client, &names)?;
+        if resolution.missing.is_empty() {
+            return Ok(0);
+        }
+        let selections = install::selections(client, &resolution)?;
+
+        report(MossStage::Fetching, None, 0, resolution.missing.len());
+        client.on_cached(progress.clone().map(|progress| {
+            Arc::new(move |package: &Package, completed: usize, total: usize| {
+                let progress = progress.lock().unwrap_or_else(|error| error.into_inner());
+                progress.report(
+                    MossStage::Fetching,
+                    Some(&package.meta.name.to_string()),
+                    completed,
+                    total,
+                );
+            }) as client::OnCached
+        }));
+        let fetched = client.fetch_transaction(&resolution.missing, &selections, "Install");
+        client.on_cached(None);
+        fetched?;
+
+        report(MossStage::Applying, None, 0, 0);
+        client.new_state(&selections, "Install")?;
+
+        // Later calls must see the new state
+        client.installation.reload_active_state();
+
+        Ok(0)
+    })
+}
+
+impl MossPackageList {
+    fn new(packages: Vec<Package>) -> Self {
+        let mut strings = vec![];
+        let mut string = |value: &str| {
+            // Interior nuls can't be represented, so are dropped
+            let value = CString::new(value.replace('\0', "")).unwrap_or_default();
+            let ptr = value.as_ptr();
+            strings.push(value);
+            ptr
+        };
+
+        let packages = packages
+            .iter()
+            .map(|package| MossPackage {
+                id: string(package.id.as_ref()),
+                name: string(&package.meta.name.to_string()),
+                version: string(&package.meta.version_identifier),
+                release: package.meta.source_release,
+                summary: string(&package.meta.summary),
+                installed: package.flags.installed,
+            })
+            .collect();
+
+        Self {
+            packages,
+            _strings: strings,
+        }
+    }
+}
+
+/// Run `f`, recording its failure (or panic, which mustn't
+/// unwind into C) and returning `failed` instead
+fn call<T>(failed: T, f: impl FnOnce() -> Result<T, Error>) -> T {
+    let error = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => return value,
+        Ok(Err(error)) => error,
+        Err(_) => Error::Panic,
+    };
+
+    let mut message = error.to_string();
+    let mut source = std::error::Error::source(&error);
+    while let Some(error) = source {
+        message.push_str(&format!(": {error}"));
+        source = error.source();
+    }
+
+    LAST_ERROR.with(|last| *last.borrow_mut() = CString::new(message.replace('\0', "")).ok());
+
+    failed
+}
+
+unsafe fn client_mut<'a>(client: *mut MossClient) -> Result<&'a mut Client, Error> {
+    client
+        .as_mut()
+        .map(|client| &mut client.client)
+        .ok_or(Error::Null("client"))
+}
+
+unsafe fn string<'a>(value: *const c_char, name: &'static str) -> Result<&'a str, Error> {
+    if value.is_null() {
+        return Err(Error::Null(name));
+    }
+
+    CStr::from_ptr(value).to_str().map_err(|_| Error::Utf8(name))
+}
+
+#[derive(Debug, Error)]
+enum Error {
+    #[error("{0} is null")]
+    Null(&'static str),
+    #[error("{0} is not valid UTF-8")]
+    Utf8(&'static str),
+    #[error("panicked")]
+    Panic,
+    #[error("installation")]
+    Installation(#[from] installation::Error),
+    #[error("client")]
+    Client(#[from] client::Error),
+    #[error("install")]
+    Install(#[from] install::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn failures_set_last_error() {
+        assert_eq!(moss_abi_version(), MOSS_ABI_VERSION);
+
+        unsafe {
+            assert!(moss_client_open(ptr::null()).is_null());
+            assert_eq!(CStr::from_ptr(moss_last_error()).to_str().unwrap(), "root is null");
+
+            assert_eq!(moss_refresh(ptr::null_mut()), -1);
+            assert_eq!(CStr::from_ptr(moss_last_error()).to_str().unwrap(), "client is null");
+
+            assert_eq!(moss_package_list_len(ptr::null()), 0);
+            assert!(moss_package_list_get(ptr::null(), 0).is_null());
+        }
+    }
+}
@@ -28,6 +28,16 @@ fn read<R: Read + Seek>(reader: R) {
     }
 }
 
+fn read_streamed(path: impl AsRef<Path>) {
+    let mut stream = stone::stream_payloads(BufReader::new(File::open(path).unwrap()), 8).unwrap();
+    let payloads = stream.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+    let mut stone = stream.into_reader();
+
+    if let Some(content) = payloads.iter().find_map(stone::read::PayloadKind::content) {
+        stone.unpack_content(content, &mut sink()).unwrap();
+    }
+}
+
 fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("read unbuffered", |b| {
         b.iter(|| read_unbuffered(black_box("../test/bash-completion-2.11-1-1-x86_64.stone")))
@@ -35,6 +45,9 @@ fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("read buffered", |b| {
         b.iter(|| read_buffered(black_box("../test/bash-completion-2.11-1-1-x86_64.stone")))
     });
+    c.bench_function("read streamed", |b| {
+        b.iter(|| read_streamed(black_box("../test/bash-completion-2.11-1-1-x86_64.stone")))
+    });
 }
 
 criterion_group!(benches, criterion_benchmark);
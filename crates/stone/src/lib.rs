@@ -11,7 +11,7 @@ pub mod write;
 
 pub use self::header::Header;
 pub use self::payload::Payload;
-pub use self::read::{read, read_bytes, Reader};
+pub use self::read::{read, read_bytes, stream_payloads, Reader};
 pub use self::write::Writer;
 
 pub trait ReadExt: Read {
@@ -178,4 +178,139 @@ mod test {
             out_stone.len()
         );
     }
+
+    #[test]
+    fn roundtrip_compression() {
+        let in_stone = include_bytes!("../../../test/bash-completion-2.11-1-1-x86_64.stone");
+
+        let mut reader = read_bytes(in_stone).unwrap();
+        let payloads = reader
+            .payloads()
+            .unwrap()
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .unwrap();
+        let meta = payloads.iter().find_map(read::PayloadKind::meta).unwrap();
+        let indices = payloads.iter().find_map(read::PayloadKind::index).unwrap();
+        let content = payloads.iter().find_map(read::PayloadKind::content).unwrap();
+
+        let mut content_buffer = vec![];
+        reader.unpack_content(content, &mut content_buffer).unwrap();
+
+        for compression in [
+            payload::Compression::None,
+            payload::Compression::Lz4,
+            payload::Compression::Xz,
+        ] {
+            let mut out_stone = vec![];
+            let mut temp_content_buffer: Vec<u8> = vec![];
+            let mut writer = Writer::new(&mut out_stone, header::v1::FileType::Binary)
+                .unwrap()
+                .with_compression(compression)
+                .unwrap()
+                .with_content(Cursor::new(&mut temp_content_buffer), None, 1)
+                .unwrap();
+
+            writer.add_payload(meta.body.as_slice()).unwrap();
+            for index in &indices.body {
+                let mut bytes = &content_buffer[index.start as usize..index.end as usize];
+                writer.add_content(&mut bytes).unwrap();
+            }
+            writer.finalize().unwrap();
+
+            let mut rt_reader = read_bytes(&out_stone).unwrap();
+            let rt_payloads = rt_reader
+                .payloads()
+                .unwrap()
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .unwrap();
+            assert!(rt_payloads.iter().all(|p| p.header().compression == compression));
+
+            let rt_meta = rt_payloads.iter().find_map(read::PayloadKind::meta).unwrap();
+            let rt_content = rt_payloads.iter().find_map(read::PayloadKind::content).unwrap();
+            assert_eq!(rt_meta.body, meta.body);
+
+            let mut rt_content_buffer = vec![];
+            rt_reader.unpack_content(rt_content, &mut rt_content_buffer).unwrap();
+            assert_eq!(rt_content_buffer, content_buffer);
+        }
+    }
+
+    #[test]
+    fn unknown_records() {
+        let meta = |tag, value: &str| payload::Meta {
+            tag,
+            kind: payload::meta::Kind::String(value.to_string()),
+        };
+        let records = [
+            meta(payload::meta::Tag::Name, "nano"),
+            meta(payload::meta::Tag::Summary, "from a newer stone"),
+            meta(payload::meta::Tag::Version, "8.0"),
+        ];
+
+        // Meta records with a tag this stone doesn't know are skipped
+        let mut bytes = vec![];
+        payload::encode_records(&mut bytes, &records).unwrap();
+        let tag = payload::Record::size(&records[0]) + 4;
+        bytes[tag..tag + 2].copy_from_slice(&999u16.to_be_bytes());
+        let decoded = payload::decode_records::<payload::Meta, _>(bytes.as_slice(), records.len()).unwrap();
+        assert_eq!(decoded, vec![records[0].clone(), records[2].clone()]);
+
+        // ... as are payloads of a kind it doesn't know
+        let mut writer = Writer::new(vec![], header::v1::FileType::Binary).unwrap();
+        writer.add_payload(&records[..1]).unwrap();
+        writer.add_payload(&records[1..]).unwrap();
+        let mut out_stone = writer.finalize().unwrap();
+        out_stone[Header::SIZE + payload::Header::SIZE - 2] = 99;
+
+        let payloads = read_bytes(&out_stone)
+            .unwrap()
+            .payloads()
+            .unwrap()
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0].meta().unwrap().body, records[1..]);
+    }
+
+    #[test]
+    fn signed() {
+        // Not a real signature, just the digest it was handed
+        let signer = |digest: &[u8; 32]| payload::Signature {
+            algorithm: payload::signature::Algorithm::Ed25519,
+            public_key: vec![7; 32],
+            signature: digest.to_vec(),
+        };
+
+        let mut writer = Writer::new(vec![], header::v1::FileType::Binary)
+            .unwrap()
+            .with_signer(signer)
+            .with_content(Cursor::new(vec![]), None, 1)
+            .unwrap();
+        writer.add_content(&mut b"signed content".as_slice()).unwrap();
+        let out_stone = writer.finalize().unwrap();
+
+        let mut reader = read_bytes(&out_stone).unwrap();
+        let signed = reader.signed().unwrap().expect("signed stone");
+        assert_eq!(signed.signatures.len(), 1);
+        assert_eq!(signed.signatures[0].public_key, vec![7; 32]);
+        assert_eq!(signed.signatures[0].signature, signed.digest);
+
+        // Signature payload is decoded like any other
+        let payloads = reader
+            .payloads()
+            .unwrap()
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .unwrap();
+        assert!(payloads.last().unwrap().signature().is_some());
+
+        // Tampering with any signed byte changes the digest
+        let mut tampered = out_stone.clone();
+        tampered[40] ^= 1;
+        let tampered = read_bytes(&tampered).unwrap().signed().unwrap().expect("still signed");
+        assert_eq!(tampered.signatures[0].signature, signed.digest);
+        assert_ne!(tampered.digest, signed.digest);
+
+        let mut unsigned = read_bytes(include_bytes!("../../../test/bash-completion-2.11-1-1-x86_64.stone")).unwrap();
+        assert!(unsigned.signed().unwrap().is_none());
+    }
 }
@@ -2,7 +2,7 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 
 use super::{DecodeError, EncodeError, Record};
 use crate::{ReadExt, WriteExt};
@@ -132,6 +132,22 @@ pub enum Tag {
     SourcePath = 19,
     // Ref/commit of the upstream source
     SourceRef = 20,
+    // Format version of a repository index
+    RepositoryFormat = 21,
+    // Oldest moss release which can read the repository index
+    MinimumVersion = 22,
+    // Delta package published for a repository index entry
+    Delta = 23,
+    // Hash of the package a delta package applies to
+    DeltaFrom = 24,
+    // Provider superseded by this package, i.e. a former package name
+    Replaces = 25,
+    // Transaction trigger shipped by this package, as its YAML definition
+    TransactionTrigger = 26,
+    // System trigger shipped by this package, as its YAML definition
+    SystemTrigger = 27,
+    // Repository index specific (size of the unpacked content)
+    InstalledSize = 28,
 }
 
 /// Helper to decode a dependency's encoded kind
@@ -154,8 +170,11 @@ fn decode_dependency(i: u8) -> Result<Dependency, DecodeError> {
 impl Record for Meta {
     fn decode<R: Read>(mut reader: R) -> Result<Self, DecodeError> {
         let length = reader.read_u32()?;
+        let tag = reader.read_u16()?;
+        let kind = reader.read_u8()?;
+        let _padding = reader.read_array::<1>()?;
 
-        let tag = match reader.read_u16()? {
+        let tag = match tag {
             1 => Tag::Name,
             2 => Tag::Architecture,
             3 => Tag::Version,
@@ -176,12 +195,21 @@ impl Record for Meta {
             18 => Tag::SourceURI,
             19 => Tag::SourcePath,
             20 => Tag::SourceRef,
-            t => return Err(DecodeError::UnknownMetaTag(t)),
+            21 => Tag::RepositoryFormat,
+            22 => Tag::MinimumVersion,
+            23 => Tag::Delta,
+            24 => Tag::DeltaFrom,
+            25 => Tag::Replaces,
+            26 => Tag::TransactionTrigger,
+            27 => Tag::SystemTrigger,
+            28 => Tag::InstalledSize,
+            t => {
+                // Consume the value so records written by a newer stone can be skipped
+                io::copy(&mut (&mut reader).take(length as u64), &mut io::sink())?;
+                return Err(DecodeError::UnknownMetaTag(t));
+            }
         };
 
-        let kind = reader.read_u8()?;
-        let _padding = reader.read_array::<1>()?;
-
         // Remove null terminated byte from string
         let sanitize = |s: String| s.trim_end_matches('\0').to_string();
 
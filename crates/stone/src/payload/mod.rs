@@ -6,6 +6,7 @@ mod attribute;
 mod index;
 pub mod layout;
 pub mod meta;
+pub mod signature;
 
 use std::io::{self, Read, Write};
 
@@ -15,6 +16,7 @@ pub use self::attribute::Attribute;
 pub use self::index::Index;
 pub use self::layout::Layout;
 pub use self::meta::Meta;
+pub use self::signature::Signature;
 use crate::{ReadExt, WriteExt};
 
 #[repr(u8)]
@@ -32,8 +34,12 @@ pub enum Kind {
     Attributes = 5,
     // For Writer interim
     Dumb = 6,
+    // Signatures over the preceding payloads, always the last payload
+    Signature = 7,
 }
 
+/// Compression of a payload, selected per payload by its [`Header`] so readers
+/// can pick the codec without any change to the container format
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Compression {
@@ -41,6 +47,10 @@ pub enum Compression {
     None = 1,
     // Payload uses ZSTD compression
     Zstd = 2,
+    // Payload uses LZ4 frame compression, trading size for decompression speed
+    Lz4 = 3,
+    // Payload uses XZ compression, trading compression speed for size
+    Xz = 4,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -64,20 +74,25 @@ impl Header {
         let checksum = reader.read_array()?;
         let num_records = reader.read_u32()? as usize;
         let version = reader.read_u16()?;
+        let kind = reader.read_u8()?;
+        let compression = reader.read_u8()?;
 
-        let kind = match reader.read_u8()? {
+        let kind = match kind {
             1 => Kind::Meta,
             2 => Kind::Content,
             3 => Kind::Layout,
             4 => Kind::Index,
             5 => Kind::Attributes,
             6 => Kind::Dumb,
+            7 => Kind::Signature,
             k => return Err(DecodeError::UnknownKind(k)),
         };
 
-        let compression = match reader.read_u8()? {
+        let compression = match compression {
             1 => Compression::None,
             2 => Compression::Zstd,
+            3 => Compression::Lz4,
+            4 => Compression::Xz,
             d => return Err(DecodeError::UnknownCompression(d)),
         };
 
@@ -115,7 +130,12 @@ pub fn decode_records<T: Record, R: Read>(mut reader: R, num_records: usize) ->
     let mut records = Vec::with_capacity(num_records);
 
     for _ in 0..num_records {
-        records.push(T::decode(&mut reader)?);
+        match T::decode(&mut reader) {
+            Ok(record) => records.push(record),
+            // Written by a newer stone, the record was consumed & can be skipped
+            Err(DecodeError::UnknownMetaTag(_)) => continue,
+            Err(error) => return Err(error),
+        }
     }
 
     Ok(records)
@@ -152,6 +172,8 @@ pub enum DecodeError {
     UnknownFileType(u8),
     #[error("Unknown dependency type: {0}")]
     UnknownDependency(u8),
+    #[error("Unknown signature algorithm: {0}")]
+    UnknownSignatureAlgorithm(u8),
     #[error("io")]
     Io(#[from] io::Error),
 }
@@ -0,0 +1,60 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::io::{Read, Write};
+
+use super::{DecodeError, EncodeError, Record};
+use crate::{ReadExt, WriteExt};
+
+/// Algorithm a [`Signature`] was made with
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// Ed25519, with a 32 byte public key & 64 byte signature
+    Ed25519 = 1,
+}
+
+/// A SignatureRecord signs the SHA-256 digest of every byte of the stone
+/// preceding the signature payload, which must therefore be the last one.
+/// The public key is included so readers can match it to their trusted keys
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub algorithm: Algorithm,
+    pub public_key: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl Record for Signature {
+    fn decode<R: Read>(mut reader: R) -> Result<Self, DecodeError> {
+        let algorithm = match reader.read_u8()? {
+            1 => Algorithm::Ed25519,
+            a => return Err(DecodeError::UnknownSignatureAlgorithm(a)),
+        };
+        let key_length = reader.read_u16()?;
+        let signature_length = reader.read_u16()?;
+
+        let public_key = reader.read_vec(key_length as usize)?;
+        let signature = reader.read_vec(signature_length as usize)?;
+
+        Ok(Self {
+            algorithm,
+            public_key,
+            signature,
+        })
+    }
+
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<(), EncodeError> {
+        writer.write_u8(self.algorithm as u8)?;
+        writer.write_u16(self.public_key.len() as u16)?;
+        writer.write_u16(self.signature.len() as u16)?;
+        writer.write_all(&self.public_key)?;
+        writer.write_all(&self.signature)?;
+
+        Ok(())
+    }
+
+    fn size(&self) -> usize {
+        1 + 2 + 2 + self.public_key.len() + self.signature.len()
+    }
+}
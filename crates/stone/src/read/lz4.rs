@@ -0,0 +1,60 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Decoding of the [LZ4 frame format](https://github.com/lz4/lz4/blob/dev/doc/lz4_Frame_format.md)
+
+use std::io::{Read, Result};
+
+use lz4_flex::frame::FrameDecoder;
+
+pub struct Lz4<R: Read> {
+    decoder: FrameDecoder<R>,
+}
+
+impl<R: Read> Lz4<R> {
+    pub fn new(reader: R) -> Result<Self> {
+        Ok(Self {
+            decoder: FrameDecoder::new(reader),
+        })
+    }
+}
+
+impl<R: Read> Read for Lz4<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.decoder.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// `lz4` CLI output, with linked blocks & a content checksum
+    const FRAME: [u8; 49] = [
+        0x04, 0x22, 0x4d, 0x18, 0x64, 0x40, 0xa7, 0x1e, 0x00, 0x00, 0x00, 0xff, 0x04, 0x6d, 0x6f, 0x73, 0x73, 0x20,
+        0x62, 0x6f, 0x75, 0x6c, 0x64, 0x65, 0x72, 0x20, 0x73, 0x74, 0x6f, 0x6e, 0x65, 0x20, 0x13, 0x00, 0x6d, 0x50,
+        0x74, 0x6f, 0x6e, 0x65, 0x20, 0x00, 0x00, 0x00, 0x00, 0xb7, 0x65, 0xc1, 0x4c,
+    ];
+
+    #[test]
+    fn decode_frame() {
+        let mut decoded = String::new();
+        Lz4::new(FRAME.as_slice())
+            .unwrap()
+            .read_to_string(&mut decoded)
+            .unwrap();
+
+        assert_eq!(decoded, "moss boulder stone ".repeat(8));
+    }
+
+    #[test]
+    fn corrupt_frame() {
+        // Content checksum no longer matches
+        let mut frame = FRAME;
+        frame[14] ^= 1;
+
+        let mut decoded = vec![];
+        assert!(Lz4::new(frame.as_slice()).unwrap().read_to_end(&mut decoded).is_err());
+    }
+}
@@ -2,16 +2,24 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
+use sha2::{Digest, Sha256};
 use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
 use thiserror::Error;
 
-use crate::payload::{Attribute, Compression, Index, Layout, Meta};
-use crate::{header, Payload};
+use crate::payload::{Attribute, Compression, Index, Layout, Meta, Signature};
+use crate::{header, Payload, ReadExt};
 use crate::{payload, Header};
 
+use self::lz4::Lz4;
+use self::xz::Xz;
 use self::zstd::Zstd;
 
+pub use self::stream::{stream_payloads, PayloadStream};
+
 mod digest;
+mod lz4;
+mod stream;
+mod xz;
 mod zstd;
 
 pub fn read<R: Read + Seek>(mut reader: R) -> Result<Reader<R>, Error> {
@@ -63,6 +71,62 @@ impl<R: Read + Seek> Reader<R> {
 
         Ok(())
     }
+
+    /// The signatures of the stone & the SHA-256 digest they sign, if it's signed
+    ///
+    /// Only headers are read to find the signature payload, so this doesn't
+    /// decode or decompress any other payload
+    pub fn signed(&mut self) -> Result<Option<Signed>, Error> {
+        let num_payloads = self.header.num_payloads();
+        self.reader.seek(SeekFrom::Start(Header::SIZE as u64))?;
+
+        for i in 0..num_payloads {
+            let offset = self.reader.stream_position()?;
+            let header = match payload::Header::decode(&mut self.reader) {
+                Ok(header) => header,
+                Err(payload::DecodeError::UnknownKind(_)) => {
+                    skip_payload(&mut self.reader, offset)?;
+                    continue;
+                }
+                Err(error) => return Err(error.into()),
+            };
+
+            if header.kind != payload::Kind::Signature {
+                self.reader.seek(SeekFrom::Current(header.stored_size as i64))?;
+                continue;
+            }
+
+            // Anything after the signature payload isn't covered by it
+            if i + 1 != num_payloads {
+                return Err(Error::UnsignedPayloads);
+            }
+
+            self.reader.seek(SeekFrom::Start(offset))?;
+            let Some(PayloadKind::Signature(signatures)) = PayloadKind::decode(&mut self.reader, &mut self.hasher)?
+            else {
+                unreachable!("signature payload");
+            };
+
+            self.reader.seek(SeekFrom::Start(0))?;
+            let mut hasher = Sha256::new();
+            io::copy(&mut (&mut self.reader).take(offset), &mut hasher)?;
+
+            return Ok(Some(Signed {
+                signatures: signatures.body,
+                digest: hasher.finalize().into(),
+            }));
+        }
+
+        Ok(None)
+    }
+}
+
+/// Signatures of a stone, see [`Reader::signed`]
+#[derive(Debug, Clone)]
+pub struct Signed {
+    pub signatures: Vec<Signature>,
+    /// SHA-256 digest of the signed bytes
+    pub digest: [u8; 32],
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -73,6 +137,8 @@ pub struct Content {
 enum PayloadReader<R: Read> {
     Plain(R),
     Zstd(Zstd<R>),
+    Lz4(Lz4<R>),
+    Xz(Xz<R>),
 }
 
 impl<R: Read> PayloadReader<R> {
@@ -80,6 +146,8 @@ impl<R: Read> PayloadReader<R> {
         Ok(match compression {
             Compression::None => PayloadReader::Plain(reader),
             Compression::Zstd => PayloadReader::Zstd(Zstd::new(reader)?),
+            Compression::Lz4 => PayloadReader::Lz4(Lz4::new(reader)?),
+            Compression::Xz => PayloadReader::Xz(Xz::new(reader)?),
         })
     }
 }
@@ -89,6 +157,8 @@ impl<R: Read> Read for PayloadReader<R> {
         match self {
             PayloadReader::Plain(reader) => reader.read(buf),
             PayloadReader::Zstd(reader) => reader.read(buf),
+            PayloadReader::Lz4(reader) => reader.read(buf),
+            PayloadReader::Xz(reader) => reader.read(buf),
         }
     }
 }
@@ -100,10 +170,13 @@ pub enum PayloadKind {
     Layout(Payload<Vec<Layout>>),
     Index(Payload<Vec<Index>>),
     Content(Payload<Content>),
+    Signature(Payload<Vec<Signature>>),
 }
 
 impl PayloadKind {
     fn decode<R: Read + Seek>(mut reader: R, hasher: &mut digest::Hasher) -> Result<Option<Self>, Error> {
+        let offset = reader.stream_position()?;
+
         match payload::Header::decode(&mut reader) {
             Ok(header) => {
                 hasher.reset();
@@ -113,31 +186,19 @@ impl PayloadKind {
                 let payload = match header.kind {
                     payload::Kind::Meta => PayloadKind::Meta(Payload {
                         header,
-                        body: payload::decode_records(
-                            PayloadReader::new(&mut framed, header.compression)?,
-                            header.num_records,
-                        )?,
+                        body: decode_records(&mut framed, &header)?,
                     }),
                     payload::Kind::Layout => PayloadKind::Layout(Payload {
                         header,
-                        body: payload::decode_records(
-                            PayloadReader::new(&mut framed, header.compression)?,
-                            header.num_records,
-                        )?,
+                        body: decode_records(&mut framed, &header)?,
                     }),
                     payload::Kind::Index => PayloadKind::Index(Payload {
                         header,
-                        body: payload::decode_records(
-                            PayloadReader::new(&mut framed, header.compression)?,
-                            header.num_records,
-                        )?,
+                        body: decode_records(&mut framed, &header)?,
                     }),
                     payload::Kind::Attributes => PayloadKind::Attributes(Payload {
                         header,
-                        body: payload::decode_records(
-                            PayloadReader::new(&mut framed, header.compression)?,
-                            header.num_records,
-                        )?,
+                        body: decode_records(&mut framed, &header)?,
                     }),
                     payload::Kind::Content => {
                         let offset = reader.stream_position()?;
@@ -150,6 +211,10 @@ impl PayloadKind {
                             body: Content { offset },
                         })
                     }
+                    payload::Kind::Signature => PayloadKind::Signature(Payload {
+                        header,
+                        body: decode_records(&mut framed, &header)?,
+                    }),
                     payload::Kind::Dumb => unimplemented!("??"),
                 };
 
@@ -161,10 +226,26 @@ impl PayloadKind {
                 Ok(Some(payload))
             }
             Err(payload::DecodeError::Io(error)) if error.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            // Written by a newer stone, so skip it rather than failing to read the rest
+            Err(payload::DecodeError::UnknownKind(_)) => {
+                skip_payload(&mut reader, offset)?;
+                Ok(None)
+            }
             Err(error) => Err(Error::PayloadDecode(error)),
         }
     }
 
+    pub fn header(&self) -> &payload::Header {
+        match self {
+            Self::Meta(payload) => &payload.header,
+            Self::Attributes(payload) => &payload.header,
+            Self::Layout(payload) => &payload.header,
+            Self::Index(payload) => &payload.header,
+            Self::Content(payload) => &payload.header,
+            Self::Signature(payload) => &payload.header,
+        }
+    }
+
     pub fn meta(&self) -> Option<&Payload<Vec<Meta>>> {
         if let Self::Meta(meta) = self {
             Some(meta)
@@ -204,6 +285,36 @@ impl PayloadKind {
             None
         }
     }
+
+    pub fn signature(&self) -> Option<&Payload<Vec<Signature>>> {
+        if let Self::Signature(signatures) = self {
+            Some(signatures)
+        } else {
+            None
+        }
+    }
+}
+
+/// Seek past the payload starting at `offset`, whatever its kind
+fn skip_payload<R: Read + Seek>(reader: &mut R, offset: u64) -> Result<(), Error> {
+    reader.seek(SeekFrom::Start(offset))?;
+    let stored_size = reader.read_u64()?;
+    reader.seek(SeekFrom::Start(offset + payload::Header::SIZE as u64 + stored_size))?;
+
+    Ok(())
+}
+
+/// Decode the records of a payload, consuming the remainder of its frame
+fn decode_records<T: payload::Record, R: Read>(framed: &mut R, header: &payload::Header) -> Result<Vec<T>, Error> {
+    let records = payload::decode_records(
+        PayloadReader::new(&mut *framed, header.compression)?,
+        header.num_records,
+    )?;
+
+    // Decoders may stop short of trailing frame data, i.e. the lz4 end mark
+    io::copy(framed, &mut io::sink())?;
+
+    Ok(records)
 }
 
 fn validate_checksum(hasher: &digest::Hasher, header: &payload::Header) -> Result<(), Error> {
@@ -227,6 +338,8 @@ pub enum Error {
     PayloadDecode(#[from] payload::DecodeError),
     #[error("payload checksum mismatch: got {got:02x}, expected {expected:02x}")]
     PayloadChecksum { got: u64, expected: u64 },
+    #[error("payloads follow the signature payload, so aren't covered by it")]
+    UnsignedPayloads,
     #[error("io")]
     Io(#[from] io::Error),
 }
@@ -0,0 +1,140 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Payload decoding on a background thread, so the next payload is
+//! decoded while the consumer is busy with the previous one
+
+use std::{
+    io::{Read, Seek},
+    panic,
+    sync::mpsc,
+    thread,
+};
+
+use super::{read, Error, PayloadKind, Reader};
+use crate::Header;
+
+/// Read the stone from `reader`, decoding its payloads on a background thread
+/// at most `read_ahead` payloads ahead of the consumer, bounding memory use
+pub fn stream_payloads<R>(reader: R, read_ahead: usize) -> Result<PayloadStream<R>, Error>
+where
+    R: Read + Seek + Send + 'static,
+{
+    Ok(read(reader)?.stream_payloads(read_ahead))
+}
+
+impl<R> Reader<R>
+where
+    R: Read + Seek + Send + 'static,
+{
+    /// Decode payloads on a background thread, see [`stream_payloads`]
+    pub fn stream_payloads(self, read_ahead: usize) -> PayloadStream<R> {
+        let header = self.header;
+        let (sender, receiver) = mpsc::sync_channel(read_ahead);
+
+        let decoder = thread::spawn(move || {
+            let mut reader = self;
+
+            match reader.payloads() {
+                Ok(payloads) => {
+                    for payload in payloads {
+                        // Consumer hung up, no need to decode the rest
+                        if sender.send(payload).is_err() {
+                            break;
+                        }
+                    }
+                }
+                Err(error) => {
+                    let _ = sender.send(Err(error));
+                }
+            }
+
+            reader
+        });
+
+        PayloadStream {
+            header,
+            receiver,
+            decoder,
+        }
+    }
+}
+
+/// Iterator over payloads decoded by a background thread
+pub struct PayloadStream<R> {
+    pub header: Header,
+    receiver: mpsc::Receiver<Result<PayloadKind, Error>>,
+    decoder: thread::JoinHandle<Reader<R>>,
+}
+
+impl<R> PayloadStream<R> {
+    /// Stop decoding and return the underlying [`Reader`], i.e.
+    /// to [unpack](Reader::unpack_content) the content payload
+    pub fn into_reader(self) -> Reader<R> {
+        // Unblocks the decoder if it's waiting on us
+        drop(self.receiver);
+
+        match self.decoder.join() {
+            Ok(reader) => reader,
+            Err(panic) => panic::resume_unwind(panic),
+        }
+    }
+}
+
+impl<R> Iterator for PayloadStream<R> {
+    type Item = Result<PayloadKind, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.receiver.recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const BASH_COMPLETION: &[u8] = include_bytes!("../../../../test/bash-completion-2.11-1-1-x86_64.stone");
+
+    #[test]
+    fn matches_sequential_read() {
+        let mut sequential = read(Cursor::new(BASH_COMPLETION)).unwrap();
+        let expected = sequential
+            .payloads()
+            .unwrap()
+            .map(|payload| *payload.unwrap().header())
+            .collect::<Vec<_>>();
+
+        for read_ahead in [0, 1, 8] {
+            let stream = stream_payloads(Cursor::new(BASH_COMPLETION), read_ahead).unwrap();
+            let headers = stream.map(|payload| *payload.unwrap().header()).collect::<Vec<_>>();
+
+            assert_eq!(headers, expected);
+        }
+    }
+
+    #[test]
+    fn unpack_after_stream() {
+        let mut stream = stream_payloads(Cursor::new(BASH_COMPLETION), 1).unwrap();
+        let payloads = stream.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+        let mut reader = stream.into_reader();
+
+        let content = payloads.iter().find_map(PayloadKind::content).unwrap();
+        let mut unpacked = vec![];
+        reader.unpack_content(content, &mut unpacked).unwrap();
+
+        assert_eq!(unpacked.len() as u64, content.header.plain_size);
+    }
+
+    #[test]
+    fn early_drop() {
+        let mut stream = stream_payloads(Cursor::new(BASH_COMPLETION), 0).unwrap();
+        assert!(stream.next().is_some());
+
+        // Must not deadlock on the decoder waiting to send the next payload
+        let reader = stream.into_reader();
+        assert_eq!(reader.header, read(Cursor::new(BASH_COMPLETION)).unwrap().header);
+    }
+}
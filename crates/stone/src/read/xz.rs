@@ -0,0 +1,25 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::io::{Read, Result};
+
+use xz2::read::XzDecoder;
+
+pub struct Xz<R: Read> {
+    decoder: XzDecoder<R>,
+}
+
+impl<R: Read> Xz<R> {
+    pub fn new(reader: R) -> Result<Self> {
+        Ok(Self {
+            decoder: XzDecoder::new(reader),
+        })
+    }
+}
+
+impl<R: Read> Read for Xz<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.decoder.read(buf)
+    }
+}
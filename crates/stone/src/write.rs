@@ -2,29 +2,87 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
+//! Writing stones
+//!
+//! A [`Writer`] encodes payloads as they're added, compressing them with the
+//! selected [`Compression`]. Files added to a writer [`with_content`] are
+//! compressed into a single content payload, staged in a scratch buffer until
+//! the stone is finalized, and indexed by the digest [`add_content`] returns.
+//! Stones written [`with_signer`] end with a signature payload over all others.
+//!
+//! ```
+//! use std::io::Cursor;
+//!
+//! use stone::{
+//!     header::v1::FileType,
+//!     payload::{layout, meta, Compression, Layout, Meta},
+//!     Writer,
+//! };
+//!
+//! let mut writer = Writer::new(vec![], FileType::Binary)?
+//!     .with_compression(Compression::Zstd)?
+//!     .with_content(Cursor::new(vec![]), None, 1)?;
+//!
+//! writer.add_payload(
+//!     [Meta {
+//!         tag: meta::Tag::Name,
+//!         kind: meta::Kind::String("hello".into()),
+//!     }]
+//!     .as_slice(),
+//! )?;
+//!
+//! let digest = writer.add_content(&mut b"echo hello".as_slice())?;
+//! writer.add_payload(
+//!     [Layout {
+//!         uid: 0,
+//!         gid: 0,
+//!         mode: 0o100755,
+//!         tag: 0,
+//!         entry: layout::Entry::Regular(digest, "bin/hello".into()),
+//!     }]
+//!     .as_slice(),
+//! )?;
+//!
+//! let bytes = writer.finalize()?;
+//! assert_eq!(stone::read_bytes(&bytes)?.payloads()?.count(), 4);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [`with_content`]: Writer::with_content
+//! [`add_content`]: Writer::add_content
+//! [`with_signer`]: Writer::with_signer
+
+use sha2::{Digest, Sha256};
 use std::io::{self, Read, Seek, SeekFrom, Write};
 use thiserror::Error;
 
 use crate::{
     header,
-    payload::{self, Attribute, Index, Layout, Meta},
+    payload::{self, Attribute, Compression, Index, Layout, Meta, Signature},
     Header,
 };
 
 pub mod digest;
+mod lz4;
+mod xz;
 mod zstd;
 
+/// Builds a stone of the given [`header::v1::FileType`], see the [module docs](self)
 pub struct Writer<W, T = ()> {
     writer: W,
     content: T,
     file_type: header::v1::FileType,
     payloads: Vec<EncodedPayload>,
     payload_hasher: digest::Hasher,
-    // TODO: Allow plain encoding?
-    encoder: zstd::Encoder,
+    encoder: Encoder,
+    signer: Option<Signer>,
 }
 
+/// Signs the SHA-256 digest of a stone, see [`Writer::with_signer`]
+pub type Signer = Box<dyn Fn(&[u8; 32]) -> Signature + Send>;
+
 impl<W: Write> Writer<W, ()> {
+    /// Write a stone to `writer`, with zstd compressed payloads
     pub fn new(writer: W, file_type: header::v1::FileType) -> Result<Self, Error> {
         Ok(Self {
             writer,
@@ -32,10 +90,26 @@ impl<W: Write> Writer<W, ()> {
             file_type,
             payloads: vec![],
             payload_hasher: digest::Hasher::new(),
-            encoder: zstd::Encoder::new()?,
+            encoder: Encoder::new(Compression::Zstd)?,
+            signer: None,
         })
     }
 
+    /// Sign the stone with `signer`, which is passed the SHA-256 digest of all
+    /// bytes preceding the signature payload once finalized
+    pub fn with_signer(mut self, signer: impl Fn(&[u8; 32]) -> Signature + Send + 'static) -> Self {
+        self.signer = Some(Box::new(signer));
+        self
+    }
+
+    /// Compress all payloads added from here on, including content, with
+    /// `compression` rather than zstd
+    pub fn with_compression(mut self, compression: Compression) -> Result<Self, Error> {
+        self.encoder = Encoder::new(compression)?;
+        Ok(self)
+    }
+
+    /// Encode a meta, attributes or layout payload
     pub fn add_payload<'a>(&mut self, payload: impl Into<Payload<'a>>) -> Result<(), Error> {
         self.payloads.push(encode_payload(
             payload.into().into(),
@@ -45,13 +119,17 @@ impl<W: Write> Writer<W, ()> {
         Ok(())
     }
 
+    /// Add a content payload, staging compressed content in `buffer` until finalized
+    ///
+    /// `pledged_size` is the total size of all content, if known up front, and
+    /// `num_workers` the number of threads compressing it, where supported
     pub fn with_content<B>(
         self,
         buffer: B,
         pledged_size: Option<u64>,
         num_workers: u32,
     ) -> Result<Writer<W, Content<B>>, Error> {
-        let mut encoder = zstd::Encoder::new()?;
+        let mut encoder = Encoder::new(self.encoder.compression())?;
         encoder.set_pledged_size(pledged_size)?;
         encoder.set_num_workers(num_workers)?;
 
@@ -70,11 +148,20 @@ impl<W: Write> Writer<W, ()> {
             payloads: self.payloads,
             payload_hasher: self.payload_hasher,
             encoder: self.encoder,
+            signer: self.signer,
         })
     }
 
-    pub fn finalize(mut self) -> Result<(), Error> {
-        finalize::<_, io::Empty>(&mut self.writer, self.file_type, self.payloads, None)
+    /// Write the header & all payloads, returning the underlying writer
+    pub fn finalize(mut self) -> Result<W, Error> {
+        finalize::<_, io::Empty>(
+            &mut self.writer,
+            self.file_type,
+            self.payloads,
+            None,
+            self.signer.map(|signer| (signer, self.payload_hasher, self.encoder)),
+        )?;
+        Ok(self.writer)
     }
 }
 
@@ -83,6 +170,7 @@ where
     W: Write,
     B: Read + Write + Seek,
 {
+    /// Encode a meta, attributes or layout payload
     pub fn add_payload<'a>(&mut self, payload: impl Into<Payload<'a>>) -> Result<(), Error> {
         self.payloads.push(encode_payload(
             payload.into().into(),
@@ -92,7 +180,9 @@ where
         Ok(())
     }
 
-    pub fn add_content<R: Read>(&mut self, content: &mut R) -> Result<(), Error> {
+    /// Add the contents of a file, returning its digest which
+    /// [`Layout`] entries of the file refer to
+    pub fn add_content<R: Read>(&mut self, content: &mut R) -> Result<u128, Error> {
         // Reset index hasher for this file
         self.content.index_hasher.reset();
 
@@ -107,15 +197,15 @@ where
         // Bytes -> index digest -> compression -> buffer checksum -> buffer
         let mut payload_checksum_writer =
             digest::Writer::new(&mut self.content.buffer, &mut self.content.buffer_hasher);
-        let mut zstd_writer = zstd::Writer::new(&mut payload_checksum_writer, &mut self.content.encoder);
-        let mut index_digest_writer = digest::Writer::new(&mut zstd_writer, &mut self.content.index_hasher);
+        let mut encoding_writer = self.content.encoder.writer(&mut payload_checksum_writer);
+        let mut index_digest_writer = digest::Writer::new(&mut encoding_writer, &mut self.content.index_hasher);
 
         io::copy(content, &mut index_digest_writer)?;
 
         // Add plain bytes
         self.content.plain_size += index_digest_writer.bytes as u64;
 
-        zstd_writer.flush()?;
+        encoding_writer.flush()?;
 
         // Add compressed bytes
         self.content.stored_size += payload_checksum_writer.bytes as u64;
@@ -129,17 +219,18 @@ where
         // Add index data
         self.content.indices.push(Index { start, end, digest });
 
-        Ok(())
+        Ok(digest)
     }
 
-    pub fn finalize(mut self) -> Result<(), Error> {
+    /// Write the header, all payloads & the content, returning the underlying writer
+    pub fn finalize(mut self) -> Result<W, Error> {
         // Finish frame & get content payload checksum
         let checksum = {
             let mut writer = digest::Writer::new(&mut self.content.buffer, &mut self.content.buffer_hasher);
             self.content.encoder.finish(&mut writer)?;
             writer.flush()?;
             self.content.stored_size += writer.bytes as u64;
-            self.content.buffer_hasher.digest()
+            (self.content.buffer_hasher.digest(), self.content.encoder.compression())
         };
 
         // Add index payloads
@@ -154,7 +245,10 @@ where
             self.file_type,
             self.payloads,
             Some((self.content, checksum)),
-        )
+            self.signer.map(|signer| (signer, self.payload_hasher, self.encoder)),
+        )?;
+
+        Ok(self.writer)
     }
 }
 
@@ -169,7 +263,7 @@ pub struct Content<B> {
     /// Used to generate compressed digest of file
     /// contents used for content payload header
     buffer_hasher: digest::Hasher,
-    encoder: zstd::Encoder,
+    encoder: Encoder,
 }
 
 struct EncodedPayload {
@@ -201,6 +295,7 @@ enum InnerPayload<'a> {
     Attributes(&'a [Attribute]),
     Layout(&'a [Layout]),
     Index(&'a [Index]),
+    Signature(&'a [Signature]),
 }
 
 impl<'a> InnerPayload<'a> {
@@ -210,6 +305,7 @@ impl<'a> InnerPayload<'a> {
             InnerPayload::Attributes(records) => payload::records_total_size(records),
             InnerPayload::Layout(records) => payload::records_total_size(records),
             InnerPayload::Index(records) => payload::records_total_size(records),
+            InnerPayload::Signature(records) => payload::records_total_size(records),
         }
     }
 
@@ -219,6 +315,7 @@ impl<'a> InnerPayload<'a> {
             InnerPayload::Attributes(payload) => payload.len(),
             InnerPayload::Layout(payload) => payload.len(),
             InnerPayload::Index(payload) => payload.len(),
+            InnerPayload::Signature(payload) => payload.len(),
         }
     }
 
@@ -228,6 +325,7 @@ impl<'a> InnerPayload<'a> {
             InnerPayload::Attributes(records) => payload::encode_records(writer, records)?,
             InnerPayload::Layout(records) => payload::encode_records(writer, records)?,
             InnerPayload::Index(records) => payload::encode_records(writer, records)?,
+            InnerPayload::Signature(records) => payload::encode_records(writer, records)?,
         }
         Ok(())
     }
@@ -238,6 +336,7 @@ impl<'a> InnerPayload<'a> {
             InnerPayload::Attributes(_) => payload::Kind::Attributes,
             InnerPayload::Layout(_) => payload::Kind::Layout,
             InnerPayload::Index(_) => payload::Kind::Index,
+            InnerPayload::Signature(_) => payload::Kind::Signature,
         }
     }
 }
@@ -263,24 +362,25 @@ impl<'a> From<&'a [Layout]> for Payload<'a> {
 fn encode_payload(
     payload: InnerPayload,
     hasher: &mut digest::Hasher,
-    encoder: &mut zstd::Encoder,
+    encoder: &mut Encoder,
 ) -> Result<EncodedPayload, Error> {
     // Reset hasher (it's used across all payloads)
     hasher.reset();
     // Set pledged size
     encoder.set_pledged_size(Some(payload.pledged_size() as u64))?;
 
+    let compression = encoder.compression();
     let mut content = vec![];
 
-    // Checksum is on compressed body so we wrap it inside zstd writer
+    // Checksum is on compressed body so we wrap it inside the encoding writer
     let mut hashed_writer = digest::Writer::new(&mut content, hasher);
-    let mut zstd_writer = zstd::Writer::new(&mut hashed_writer, encoder);
+    let mut encoding_writer = encoder.writer(&mut hashed_writer);
 
-    payload.encode(&mut zstd_writer)?;
+    payload.encode(&mut encoding_writer)?;
 
-    let plain_size = zstd_writer.plain_bytes as u64;
+    let plain_size = encoding_writer.plain_bytes() as u64;
 
-    zstd_writer.finish()?;
+    encoding_writer.finish()?;
 
     let stored_size = hashed_writer.bytes as u64;
 
@@ -291,7 +391,7 @@ fn encode_payload(
         num_records: payload.num_records(),
         version: 1,
         kind: payload.kind(),
-        compression: payload::Compression::Zstd,
+        compression,
     };
 
     Ok(EncodedPayload { header, content })
@@ -301,23 +401,32 @@ fn finalize<W: Write, B: Read + Seek>(
     writer: &mut W,
     file_type: header::v1::FileType,
     payloads: Vec<EncodedPayload>,
-    content: Option<(Content<B>, u64)>,
+    content: Option<(Content<B>, (u64, Compression))>,
+    signer: Option<(Signer, digest::Hasher, Encoder)>,
 ) -> Result<(), Error> {
+    let num_payloads = payloads.len() + usize::from(content.is_some()) + usize::from(signer.is_some());
+
+    // Everything up to the signature payload is signed
+    let mut signed = SigningWriter {
+        inner: &mut *writer,
+        hasher: signer.is_some().then(Sha256::new),
+    };
+
     // Write header
     Header::V1(header::v1::Header {
-        num_payloads: payloads.len() as u16 + content.is_some().then_some(1).unwrap_or_default(),
+        num_payloads: num_payloads as u16,
         file_type,
     })
-    .encode(writer)?;
+    .encode(&mut signed)?;
 
     // Write each payload header + content
     for payload in payloads {
-        payload.header.encode(writer)?;
-        writer.write_all(&payload.content)?;
+        payload.header.encode(&mut signed)?;
+        signed.write_all(&payload.content)?;
     }
 
     // Write content payload header + buffer
-    if let Some((mut content, checksum)) = content {
+    if let Some((mut content, (checksum, compression))) = content {
         payload::Header {
             stored_size: content.stored_size,
             plain_size: content.plain_size,
@@ -325,12 +434,21 @@ fn finalize<W: Write, B: Read + Seek>(
             num_records: 0,
             version: 1,
             kind: payload::Kind::Content,
-            compression: payload::Compression::Zstd,
+            compression,
         }
-        .encode(writer)?;
+        .encode(&mut signed)?;
         // Seek to beginning & copy content buffer
         content.buffer.seek(SeekFrom::Start(0))?;
-        io::copy(&mut content.buffer, writer)?;
+        io::copy(&mut content.buffer, &mut signed)?;
+    }
+
+    // Write signature payload
+    if let (Some((signer, mut hasher, mut encoder)), Some(digest)) = (signer, signed.hasher) {
+        let signature = signer(&digest.finalize().into());
+        let payload = encode_payload(InnerPayload::Signature(&[signature]), &mut hasher, &mut encoder)?;
+
+        payload.header.encode(writer)?;
+        writer.write_all(&payload.content)?;
     }
 
     writer.flush()?;
@@ -338,6 +456,141 @@ fn finalize<W: Write, B: Read + Seek>(
     Ok(())
 }
 
+/// Passes writes through, hashing them when the stone is signed
+struct SigningWriter<W> {
+    inner: W,
+    hasher: Option<Sha256>,
+}
+
+impl<W: Write> Write for SigningWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..written]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Encoder of the selected [`Compression`], shared by all payloads
+/// it's used for
+enum Encoder {
+    Plain,
+    Zstd(zstd::Encoder),
+    Lz4(lz4::Encoder),
+    Xz(xz::Encoder),
+}
+
+impl Encoder {
+    fn new(compression: Compression) -> Result<Self, Error> {
+        Ok(match compression {
+            Compression::None => Encoder::Plain,
+            Compression::Zstd => Encoder::Zstd(zstd::Encoder::new()?),
+            Compression::Lz4 => Encoder::Lz4(lz4::Encoder::new()),
+            Compression::Xz => Encoder::Xz(xz::Encoder::new()),
+        })
+    }
+
+    fn compression(&self) -> Compression {
+        match self {
+            Encoder::Plain => Compression::None,
+            Encoder::Zstd(_) => Compression::Zstd,
+            Encoder::Lz4(_) => Compression::Lz4,
+            Encoder::Xz(_) => Compression::Xz,
+        }
+    }
+
+    /// Let the encoder know of the final uncompressed size, if it makes use of it
+    fn set_pledged_size(&mut self, pledged_size: Option<u64>) -> io::Result<()> {
+        match self {
+            Encoder::Zstd(encoder) => encoder.set_pledged_size(pledged_size),
+            Encoder::Plain | Encoder::Lz4(_) | Encoder::Xz(_) => Ok(()),
+        }
+    }
+
+    fn set_num_workers(&mut self, num_workers: u32) -> io::Result<()> {
+        match self {
+            Encoder::Zstd(encoder) => encoder.set_num_workers(num_workers),
+            Encoder::Plain | Encoder::Lz4(_) | Encoder::Xz(_) => Ok(()),
+        }
+    }
+
+    fn writer<W: Write>(&mut self, writer: W) -> EncodingWriter<'_, W> {
+        match self {
+            Encoder::Plain => EncodingWriter::Plain { writer, plain_bytes: 0 },
+            Encoder::Zstd(encoder) => EncodingWriter::Zstd(zstd::Writer::new(writer, encoder)),
+            Encoder::Lz4(encoder) => EncodingWriter::Lz4(lz4::Writer::new(writer, encoder)),
+            Encoder::Xz(encoder) => EncodingWriter::Xz(xz::Writer::new(writer, encoder)),
+        }
+    }
+
+    /// Manually finish a frame to the provided writer
+    fn finish<W: Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        match self {
+            Encoder::Plain => Ok(()),
+            Encoder::Zstd(encoder) => encoder.finish(writer),
+            Encoder::Lz4(encoder) => encoder.finish(writer),
+            Encoder::Xz(encoder) => encoder.finish(writer),
+        }
+    }
+}
+
+enum EncodingWriter<'a, W: Write> {
+    Plain { writer: W, plain_bytes: usize },
+    Zstd(zstd::Writer<'a, W>),
+    Lz4(lz4::Writer<'a, W>),
+    Xz(xz::Writer<'a, W>),
+}
+
+impl<'a, W: Write> EncodingWriter<'a, W> {
+    fn plain_bytes(&self) -> usize {
+        match self {
+            EncodingWriter::Plain { plain_bytes, .. } => *plain_bytes,
+            EncodingWriter::Zstd(writer) => writer.plain_bytes,
+            EncodingWriter::Lz4(writer) => writer.plain_bytes,
+            EncodingWriter::Xz(writer) => writer.plain_bytes,
+        }
+    }
+
+    /// Finish a frame to the underlying writer
+    fn finish(self) -> io::Result<()> {
+        match self {
+            EncodingWriter::Plain { mut writer, .. } => writer.flush(),
+            EncodingWriter::Zstd(writer) => writer.finish(),
+            EncodingWriter::Lz4(writer) => writer.finish(),
+            EncodingWriter::Xz(writer) => writer.finish(),
+        }
+    }
+}
+
+impl<'a, W: Write> Write for EncodingWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            EncodingWriter::Plain { writer, plain_bytes } => {
+                let written = writer.write(buf)?;
+                *plain_bytes += written;
+                Ok(written)
+            }
+            EncodingWriter::Zstd(writer) => writer.write(buf),
+            EncodingWriter::Lz4(writer) => writer.write(buf),
+            EncodingWriter::Xz(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            EncodingWriter::Plain { writer, .. } => writer.flush(),
+            EncodingWriter::Zstd(writer) => writer.flush(),
+            EncodingWriter::Lz4(writer) => writer.flush(),
+            EncodingWriter::Xz(writer) => writer.flush(),
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("payload encode")]
@@ -32,9 +32,11 @@ where
     W: Write,
 {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.bytes += buf.len();
-        self.hasher.update(buf);
-        self.inner.write(buf)
+        // Only what the inner writer accepted, the rest is written again
+        let written = self.inner.write(buf)?;
+        self.bytes += written;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
@@ -0,0 +1,94 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Encoding of the [LZ4 frame format](https://github.com/lz4/lz4/blob/dev/doc/lz4_Frame_format.md)
+
+use std::io::{self, Result, Write};
+
+use lz4_flex::frame::{BlockMode, BlockSize, FrameEncoder, FrameInfo};
+
+/// Transparent encapsulation of LZ4 compression with the purpose
+/// of encoding moss (.stone) payloads to a stream
+pub struct Writer<'a, W: Write> {
+    writer: W,
+    encoder: &'a mut Encoder,
+    pub plain_bytes: usize,
+}
+
+impl<'a, W: Write> Writer<'a, W> {
+    pub fn new(writer: W, encoder: &'a mut Encoder) -> Self {
+        Self {
+            writer,
+            encoder,
+            plain_bytes: 0,
+        }
+    }
+
+    /// Finish a frame to this writer
+    pub fn finish(mut self) -> Result<()> {
+        self.encoder.finish(&mut self.writer)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<'a, W: Write> Write for Writer<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.plain_bytes += buf.len();
+        self.encoder.write(&mut self.writer, buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// A frame spanning any number of [`Writer`]s until finished
+///
+/// Compressed blocks are staged in memory until they're drained
+/// to whichever writer is currently in use
+pub struct Encoder {
+    frame: FrameEncoder<Vec<u8>>,
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Self { frame: frame() }
+    }
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn write<W: Write>(&mut self, writer: &mut W, buf: &[u8]) -> Result<()> {
+        self.frame.write_all(buf)?;
+        drain(self.frame.get_mut(), writer)
+    }
+
+    /// Manually finish a frame to the provided writer
+    pub fn finish<W: Write>(&mut self, writer: &mut W) -> Result<()> {
+        let mut staged = std::mem::replace(&mut self.frame, frame())
+            .finish()
+            .map_err(io::Error::other)?;
+        drain(&mut staged, writer)
+    }
+}
+
+/// A new frame of independent 4 MiB blocks, without checksums as payloads carry their own
+fn frame() -> FrameEncoder<Vec<u8>> {
+    let info = FrameInfo::new()
+        .block_size(BlockSize::Max4MB)
+        .block_mode(BlockMode::Independent);
+
+    FrameEncoder::with_frame_info(info, vec![])
+}
+
+fn drain<W: Write>(staged: &mut Vec<u8>, writer: &mut W) -> Result<()> {
+    writer.write_all(staged)?;
+    staged.clear();
+    Ok(())
+}
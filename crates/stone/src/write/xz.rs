@@ -0,0 +1,86 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::io::{Result, Write};
+
+use xz2::write::XzEncoder;
+
+/// Compression preset, favouring size as xz is chosen for it
+const LEVEL: u32 = 9;
+
+/// Transparent encapsulation of xz compression with the purpose
+/// of encoding moss (.stone) payloads to a stream
+pub struct Writer<'a, W: Write> {
+    writer: W,
+    encoder: &'a mut Encoder,
+    pub plain_bytes: usize,
+}
+
+impl<'a, W: Write> Writer<'a, W> {
+    pub fn new(writer: W, encoder: &'a mut Encoder) -> Self {
+        Self {
+            writer,
+            encoder,
+            plain_bytes: 0,
+        }
+    }
+
+    /// Finish a stream to this writer
+    pub fn finish(mut self) -> Result<()> {
+        self.encoder.finish(&mut self.writer)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<'a, W: Write> Write for Writer<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.plain_bytes += buf.len();
+        self.encoder.write(&mut self.writer, buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// A stream spanning any number of [`Writer`]s until finished
+///
+/// Compressed bytes are staged in memory until they're drained
+/// to whichever writer is currently in use
+pub struct Encoder {
+    stream: XzEncoder<Vec<u8>>,
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Self {
+            stream: XzEncoder::new(vec![], LEVEL),
+        }
+    }
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn write<W: Write>(&mut self, writer: &mut W, buf: &[u8]) -> Result<()> {
+        self.stream.write_all(buf)?;
+        drain(self.stream.get_mut(), writer)
+    }
+
+    /// Manually finish a stream to the provided writer
+    pub fn finish<W: Write>(&mut self, writer: &mut W) -> Result<()> {
+        let mut staged = std::mem::take(self).stream.finish()?;
+        drain(&mut staged, writer)
+    }
+}
+
+fn drain<W: Write>(staged: &mut Vec<u8>, writer: &mut W) -> Result<()> {
+    writer.write_all(staged)?;
+    staged.clear();
+    Ok(())
+}
@@ -48,6 +48,10 @@ pub struct Recipe {
     pub tuning: Vec<KeyValue<Tuning>>,
     #[serde(default, deserialize_with = "stringy_bool")]
     pub emul32: bool,
+    #[serde(default, deserialize_with = "sequence_of_key_value")]
+    pub permissions: Vec<KeyValue<Permission>>,
+    #[serde(default, deserialize_with = "sequence_of_key_value")]
+    pub hardening: Vec<KeyValue<Hardening>>,
 }
 
 #[derive(Debug, Clone)]
@@ -93,6 +97,8 @@ pub struct Options {
     pub strip: bool,
     #[serde(default, deserialize_with = "stringy_bool")]
     pub networking: bool,
+    #[serde(default = "default_umask", deserialize_with = "octal")]
+    pub umask: u32,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -105,6 +111,40 @@ pub struct Package {
     pub paths: Vec<Path>,
     #[serde(default)]
     pub conflicts: Vec<String>,
+    #[serde(default)]
+    pub replaces: Vec<String>,
+    #[serde(default)]
+    pub triggers: Triggers,
+}
+
+/// Triggers shipped within a package, in the same format as the
+/// trigger files moss loads from `/usr/share/moss/triggers`
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Triggers {
+    /// Run against the staged `/usr` of a transaction
+    #[serde(default)]
+    pub transaction: Vec<serde_yaml::Value>,
+    /// Run against the root once a transaction is activated
+    #[serde(default)]
+    pub system: Vec<serde_yaml::Value>,
+}
+
+/// Ownership & mode of a path, overriding the normalized defaults
+/// applied when packaging (`root:root` with the recipe `umask`)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct Permission {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    #[serde(default, deserialize_with = "optional_octal")]
+    pub mode: Option<u32>,
+}
+
+/// Toggle of a hardening feature, disabling one must be justified
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Hardening {
+    #[serde(deserialize_with = "stringy_bool")]
+    pub enabled: bool,
+    pub reason: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -116,6 +156,9 @@ pub enum Upstream {
         strip_dirs: Option<u8>,
         unpack: bool,
         unpack_dir: Option<PathBuf>,
+        /// Script run in the sandbox after fetching, transforming the
+        /// upstream before it's shared with the build
+        hook: Option<String>,
     },
     Git {
         uri: Url,
@@ -142,6 +185,7 @@ impl<'de> Deserialize<'de> for Upstream {
                 unpack: bool,
                 #[serde(rename = "unpackdir")]
                 unpack_dir: Option<PathBuf>,
+                hook: Option<String>,
             },
             Git {
                 #[serde(rename = "ref")]
@@ -192,6 +236,7 @@ impl<'de> Deserialize<'de> for Upstream {
                 strip_dirs: None,
                 unpack: default_true(),
                 unpack_dir: None,
+                hook: None,
             }),
             Some((Uri::Git(uri), Outer::String(ref_id))) => Ok(Upstream::Git {
                 uri,
@@ -207,6 +252,7 @@ impl<'de> Deserialize<'de> for Upstream {
                     strip_dirs,
                     unpack,
                     unpack_dir,
+                    hook,
                 }),
             )) => Ok(Upstream::Plain {
                 uri,
@@ -215,6 +261,7 @@ impl<'de> Deserialize<'de> for Upstream {
                 strip_dirs,
                 unpack,
                 unpack_dir,
+                hook,
             }),
             Some((
                 Uri::Git(uri),
@@ -290,6 +337,10 @@ fn default_true() -> bool {
     true
 }
 
+fn default_umask() -> u32 {
+    0o022
+}
+
 /// Deserialize a single value or sequence of values as a vec
 fn single_as_sequence<'de, T, D>(deserializer: D) -> Result<Vec<T>, D::Error>
 where
@@ -363,6 +414,25 @@ where
     }
 }
 
+/// Deserialize an octal number such as a mode or umask,
+/// i.e. `"0755"` or `755`
+fn octal<'de, D>(deserializer: D) -> Result<u32, D::Error>
+where
+    D: serde::de::Deserializer<'de>,
+{
+    let value = force_string(deserializer)?;
+
+    u32::from_str_radix(value.trim_start_matches("0o"), 8)
+        .map_err(|_| serde::de::Error::custom(format!("invalid octal number: {value}")))
+}
+
+fn optional_octal<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: serde::de::Deserializer<'de>,
+{
+    octal(deserializer).map(Some)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -379,4 +449,120 @@ mod test {
             dbg!(&recipe);
         }
     }
+
+    #[test]
+    fn permissions_and_hardening() {
+        let recipe = from_str(
+            r#"
+name: sudo
+version: 1.9.15
+release: 1
+homepage: https://www.sudo.ws
+license: ISC
+umask: "027"
+permissions:
+    - /usr/bin/sudo:
+        mode: "04755"
+    - /usr/lib/sudo/*:
+        gid: 10
+        mode: 750
+hardening:
+    - cf-protection:
+        enabled: false
+        reason: JIT emits code without landing pads
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(recipe.options.umask, 0o027);
+        assert_eq!(recipe.permissions[0].key, "/usr/bin/sudo");
+        assert_eq!(
+            recipe.permissions[0].value,
+            Permission {
+                mode: Some(0o4755),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            recipe.permissions[1].value,
+            Permission {
+                uid: None,
+                gid: Some(10),
+                mode: Some(0o750),
+            }
+        );
+        assert_eq!(recipe.hardening[0].key, "cf-protection");
+        assert_eq!(
+            recipe.hardening[0].value,
+            Hardening {
+                enabled: false,
+                reason: Some("JIT emits code without landing pads".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn upstream_hook() {
+        let recipe = from_str(
+            r#"
+name: firmware
+version: 1.0
+release: 1
+homepage: https://example.com
+license: MIT
+upstreams:
+    - https://example.com/firmware-1.0.tar.zst:
+        hash: 8c1c2f0b8d7f9e3c6a2b5d4e1f0a9b8c7d6e5f4a3b2c1d0e9f8a7b6c5d4e3f2a
+        hook: |
+            tar xf "$UPSTREAM"
+            rm -rf firmware-1.0/unused
+            tar cf "$OUTPUT" firmware-1.0
+"#,
+        )
+        .unwrap();
+
+        let Upstream::Plain { hook, unpack, .. } = &recipe.upstreams[0] else {
+            panic!("expected plain upstream");
+        };
+        assert!(unpack);
+        assert_eq!(
+            hook.as_deref(),
+            Some("tar xf \"$UPSTREAM\"\nrm -rf firmware-1.0/unused\ntar cf \"$OUTPUT\" firmware-1.0\n")
+        );
+    }
+
+    #[test]
+    fn package_triggers() {
+        let recipe = from_str(
+            r#"
+name: fontconfig
+version: 2.15.0
+release: 1
+homepage: https://www.freedesktop.org/wiki/Software/fontconfig
+license: MIT
+packages:
+    - "%(name)-bin":
+        triggers:
+            system:
+                - name: fc-cache
+                  description: Rebuild the font cache
+                  paths:
+                      "/usr/share/fonts/**":
+                          handlers:
+                              - fc-cache
+                  handlers:
+                      fc-cache:
+                          run: /usr/bin/fc-cache
+                          args: ["-s"]
+"#,
+        )
+        .unwrap();
+
+        assert!(recipe.package.triggers.system.is_empty());
+
+        let triggers = &recipe.sub_packages[0].value.triggers;
+        assert!(triggers.transaction.is_empty());
+        assert_eq!(triggers.system.len(), 1);
+        assert_eq!(triggers.system[0]["name"].as_str(), Some("fc-cache"));
+    }
 }
@@ -29,6 +29,9 @@ pub struct Macros {
     pub packages: Vec<KeyValue<Package>>,
     #[serde(default)]
     pub default_tuning_groups: Vec<String>,
+    /// Tuning groups providing hardening features, only
+    /// disabled by recipes with a justification
+    pub hardening_groups: Option<Vec<String>>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
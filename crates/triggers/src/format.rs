@@ -2,7 +2,7 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, fmt};
 
 use fnmatch::Pattern;
 use serde::Deserialize;
@@ -35,25 +35,34 @@ impl CompiledHandler {
 impl Handler {
     /// Substitute all paths using matched variables
     pub fn compiled(&self, with_match: &fnmatch::Match) -> CompiledHandler {
+        let substitute = |value: &String| {
+            let mut value = value.clone();
+            for (key, variable) in &with_match.variables {
+                value = value.replace(&format!("$({key})"), variable);
+            }
+            value
+        };
+
+        match self {
+            Handler::Run { run, args } => CompiledHandler(Handler::Run {
+                run: substitute(run),
+                args: args.iter().map(substitute).collect(),
+            }),
+            Handler::Delete { delete } => CompiledHandler(Handler::Delete {
+                delete: delete.iter().map(substitute).collect(),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Handler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Handler::Run { run, args } => {
-                let mut run = run.clone();
-                for (key, value) in &with_match.variables {
-                    run = run.replace(&format!("$({key})"), value);
-                }
-                let args = args
-                    .iter()
-                    .map(|a| {
-                        let mut a = a.clone();
-                        for (key, value) in &with_match.variables {
-                            a = a.replace(&format!("$({key})"), value);
-                        }
-                        a
-                    })
-                    .collect();
-                CompiledHandler(Handler::Run { run, args })
+                write!(f, "{run}")?;
+                args.iter().try_for_each(|arg| write!(f, " {arg}"))
             }
-            Handler::Delete { delete } => CompiledHandler(Handler::Delete { delete: delete.clone() }),
+            Handler::Delete { delete } => write!(f, "delete {}", delete.join(" ")),
         }
     }
 }
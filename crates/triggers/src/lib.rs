@@ -78,8 +78,9 @@ impl<'a> Collection<'a> {
         }
     }
 
-    /// Bake the trigger collection into a sane dependency order
-    pub fn bake(&mut self) -> Result<Vec<format::CompiledHandler>, Error> {
+    /// Bake the trigger collection into a sane dependency order, along
+    /// with the name of the trigger each handler belongs to
+    pub fn bake(&mut self) -> Result<Vec<(String, format::CompiledHandler)>, Error> {
         let mut graph = dag::Dag::new();
 
         // ensure all keys are in place
@@ -120,9 +121,92 @@ impl<'a> Collection<'a> {
         // Recollect in dependency order
         let results = graph
             .topo()
-            .filter_map(|i| self.hits.remove(i))
-            .flatten()
+            .filter_map(|i| self.hits.remove_entry(i))
+            .flat_map(|(id, handlers)| handlers.into_iter().map(move |handler| (id.clone(), handler)))
             .collect::<Vec<_>>();
         Ok(results)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn trigger(yaml: &str) -> Trigger {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn batched_in_dependency_order() {
+        let ldconfig = trigger(
+            r#"
+name: ldconfig
+description: Rebuild the linker cache
+before: icons
+paths:
+    "/usr/lib/*.so*":
+        handlers: [ldconfig]
+handlers:
+    ldconfig:
+        run: /usr/sbin/ldconfig
+        args: []
+"#,
+        );
+        let icons = trigger(
+            r#"
+name: icons
+description: Rebuild icon caches
+paths:
+    "/usr/share/icons/(theme:*)/*":
+        handlers: [cache]
+handlers:
+    cache:
+        run: /usr/bin/gtk-update-icon-cache
+        args: ["/usr/share/icons/$(theme)"]
+"#,
+        );
+        let daemon_reload = trigger(
+            r#"
+name: daemon-reload
+description: Reload systemd units
+after: icons
+paths:
+    "/usr/lib/systemd/system/*":
+        handlers: [reload]
+handlers:
+    reload:
+        run: /usr/bin/systemctl
+        args: ["daemon-reload"]
+"#,
+        );
+
+        let mut collection = Collection::new([&daemon_reload, &icons, &ldconfig]).unwrap();
+        collection.process_paths(
+            [
+                "/usr/lib/systemd/system/sshd.service",
+                "/usr/share/icons/hicolor/index.theme",
+                "/usr/lib/libz.so.1",
+                "/usr/share/icons/hicolor/icon-theme.cache",
+                "/usr/lib/libc.so.6",
+            ]
+            .into_iter()
+            .map(String::from),
+        );
+
+        let commands = collection
+            .bake()
+            .unwrap()
+            .iter()
+            .map(|(_, handler)| handler.handler().to_string())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            commands,
+            vec![
+                "/usr/sbin/ldconfig",
+                "/usr/bin/gtk-update-icon-cache /usr/share/icons/hicolor",
+                "/usr/bin/systemctl daemon-reload",
+            ]
+        );
+    }
+}
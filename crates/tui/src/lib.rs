@@ -2,11 +2,13 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-pub use self::styled::Styled;
+pub use self::progress::{draw_target, is_plain, is_quiet, set_plain, set_quiet, PLAIN_INTERVAL};
+pub use self::styled::{set_color, ColorChoice, Styled};
 pub use dialoguer;
 pub use indicatif::*;
 
 pub mod pretty;
+mod progress;
 mod styled;
 
 /// The size of a terminal emulator window.
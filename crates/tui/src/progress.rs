@@ -0,0 +1,259 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Progress output
+//!
+//! Bars are redrawn in place on a terminal. Anywhere else, such as CI logs,
+//! redraws would garble the output, so they're reported as plain lines
+//! every [`PLAIN_INTERVAL`] instead.
+
+use std::{
+    io::{self, stderr, stdout, IsTerminal, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use indicatif::{ProgressDrawTarget, TermLike};
+
+/// Whether progress output is suppressed, see [`set_quiet`]
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Whether plain line progress is forced, see [`set_plain`]
+static PLAIN: AtomicBool = AtomicBool::new(false);
+
+/// How often plain line progress reports the state of all bars
+pub const PLAIN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Width bars are rendered at as plain lines
+const PLAIN_WIDTH: u16 = 80;
+
+/// Suppress all progress output, i.e. for unattended runs
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// Whether progress output is suppressed
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Report progress as plain lines, even on a terminal
+pub fn set_plain(plain: bool) {
+    PLAIN.store(plain, Ordering::Relaxed);
+}
+
+/// Whether progress is reported as plain lines, either because
+/// it was [requested](set_plain) or we're not on a terminal
+pub fn is_plain() -> bool {
+    PLAIN.load(Ordering::Relaxed) || !stdout().is_terminal() || !stderr().is_terminal()
+}
+
+/// Where progress bars are drawn, nowhere when [quiet](set_quiet)
+///
+/// Bars added to a `MultiProgress` are drawn by it, so only it
+/// and standalone bars need to be created with this target
+pub fn draw_target() -> ProgressDrawTarget {
+    if is_quiet() {
+        ProgressDrawTarget::hidden()
+    } else if is_plain() {
+        ProgressDrawTarget::term_like(Box::new(PlainLines::default()))
+    } else {
+        ProgressDrawTarget::stderr()
+    }
+}
+
+/// Plain line progress on stderr
+///
+/// Emulates just enough of a terminal to tell the redrawn bars apart from
+/// lines printed above them. Those are printed once, as soon as a redraw
+/// no longer reaches them, while the bars are printed every [`PLAIN_INTERVAL`].
+#[derive(Debug, Default)]
+struct PlainLines(Mutex<Screen>);
+
+#[derive(Debug, Default)]
+struct Screen {
+    /// Rows which may still be redrawn
+    rows: Vec<String>,
+    cursor: usize,
+    /// Whether the next operation starts a redraw
+    flushed: bool,
+    /// When the bars were last printed, and how they looked
+    reported: Option<(Instant, Vec<String>)>,
+}
+
+impl Screen {
+    fn row(&mut self) -> &mut String {
+        if self.rows.len() <= self.cursor {
+            self.rows.resize(self.cursor + 1, String::new());
+        }
+        &mut self.rows[self.cursor]
+    }
+
+    /// Once a redraw starts at `top`, rows above it are final
+    /// and the ones below still show the previous state of the bars
+    fn redraw_from(&mut self, top: usize) -> io::Result<()> {
+        if !self.flushed {
+            return Ok(());
+        }
+        self.flushed = false;
+
+        let top = top.min(self.rows.len());
+        let finished = self.rows.drain(..top).collect::<Vec<_>>();
+        self.cursor -= top;
+        print(&finished)?;
+
+        if self
+            .reported
+            .as_ref()
+            .map_or(true, |(at, _)| at.elapsed() >= PLAIN_INTERVAL)
+        {
+            self.report()?;
+        }
+
+        Ok(())
+    }
+
+    /// Print the bars, unless they look the same as last time
+    fn report(&mut self) -> io::Result<()> {
+        let bars = visible(&self.rows);
+
+        if self.reported.as_ref().map_or(true, |(_, reported)| *reported != bars) {
+            print(&bars)?;
+        }
+        self.reported = Some((Instant::now(), bars));
+
+        Ok(())
+    }
+}
+
+impl TermLike for PlainLines {
+    fn width(&self) -> u16 {
+        PLAIN_WIDTH
+    }
+
+    fn move_cursor_up(&self, n: usize) -> io::Result<()> {
+        let mut screen = self.0.lock().expect("mutex lock");
+        // Rows are only finished once the cursor has moved above them, which
+        // also shifts the cursor along with the rows that remain
+        screen.cursor = screen.cursor.saturating_sub(n);
+        let cursor = screen.cursor;
+        screen.redraw_from(cursor)?;
+        Ok(())
+    }
+
+    fn move_cursor_down(&self, n: usize) -> io::Result<()> {
+        let mut screen = self.0.lock().expect("mutex lock");
+        let cursor = screen.cursor;
+        screen.redraw_from(cursor)?;
+        screen.cursor += n;
+        Ok(())
+    }
+
+    fn move_cursor_right(&self, _n: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn move_cursor_left(&self, _n: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_line(&self, s: &str) -> io::Result<()> {
+        self.write_str(s)?;
+        self.0.lock().expect("mutex lock").cursor += 1;
+        Ok(())
+    }
+
+    fn write_str(&self, s: &str) -> io::Result<()> {
+        let mut screen = self.0.lock().expect("mutex lock");
+        let cursor = screen.cursor;
+        screen.redraw_from(cursor)?;
+        screen.row().push_str(s);
+        Ok(())
+    }
+
+    fn clear_line(&self) -> io::Result<()> {
+        let mut screen = self.0.lock().expect("mutex lock");
+        let cursor = screen.cursor;
+        screen.redraw_from(cursor)?;
+        screen.row().clear();
+        Ok(())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.0.lock().expect("mutex lock").flushed = true;
+        Ok(())
+    }
+}
+
+impl Drop for PlainLines {
+    /// Whatever was drawn last is final
+    fn drop(&mut self) {
+        if let Ok(screen) = self.0.get_mut() {
+            let _ = screen.report();
+        }
+    }
+}
+
+/// Non-blank rows, without the padding bars are drawn with
+fn visible(rows: &[String]) -> Vec<String> {
+    rows.iter()
+        .map(|row| row.trim_end())
+        .filter(|row| !row.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn print(rows: &[String]) -> io::Result<()> {
+    let mut stderr = stderr().lock();
+
+    for row in visible(rows) {
+        writeln!(stderr, "{row}")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Draws like indicatif does, clearing the previous lines first
+    fn draw(term: &PlainLines, previous: usize, lines: &[&str]) {
+        term.move_cursor_up(previous.saturating_sub(1)).unwrap();
+        for i in 0..previous {
+            term.clear_line().unwrap();
+            if i + 1 != previous {
+                term.move_cursor_down(1).unwrap();
+            }
+        }
+        term.move_cursor_up(previous.saturating_sub(1)).unwrap();
+
+        for (i, line) in lines.iter().enumerate() {
+            if i != 0 {
+                term.write_line("").unwrap();
+            }
+            term.write_str(line).unwrap();
+        }
+        term.flush().unwrap();
+    }
+
+    #[test]
+    fn printed_lines_are_final() {
+        let term = PlainLines::default();
+
+        draw(&term, 0, &["bar 0%"]);
+        // A line printed above the bar, then redrawn bars below it
+        draw(&term, 1, &["Fetched a", "bar 50%"]);
+        draw(&term, 1, &["bar 60%"]);
+
+        let screen = term.0.lock().unwrap();
+        assert_eq!(screen.rows, vec!["bar 60%".to_string()]);
+        assert_eq!(screen.cursor, 0);
+        // Reported once when first redrawn, the interval hasn't passed since
+        assert_eq!(screen.reported.as_ref().unwrap().1, vec!["bar 0%".to_string()]);
+    }
+}
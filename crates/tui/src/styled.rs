@@ -1,11 +1,40 @@
-use std::io::stdout;
+use std::{
+    io::stdout,
+    sync::atomic::{AtomicU8, Ordering},
+};
 
 use crossterm::{style::Stylize, tty::IsTty};
 
+/// When output is styled, see [`set_color`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u8)]
+pub enum ColorChoice {
+    /// Only when stdout is a TTY
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+static COLOR: AtomicU8 = AtomicU8::new(ColorChoice::Auto as u8);
+
+/// Override when output is styled, by default only when stdout is a TTY
+pub fn set_color(choice: ColorChoice) {
+    COLOR.store(choice as u8, Ordering::Relaxed);
+}
+
+fn is_enabled() -> bool {
+    match COLOR.load(Ordering::Relaxed) {
+        color if color == ColorChoice::Always as u8 => true,
+        color if color == ColorChoice::Never as u8 => false,
+        _ => stdout().is_tty(),
+    }
+}
+
 macro_rules! impl_method {
     ($method:ident) => {
         fn $method(self) -> <Self as Stylize>::Styled {
-            if stdout().is_tty() {
+            if is_enabled() {
                 <Self as Stylize>::$method(self)
             } else {
                 self.stylize()
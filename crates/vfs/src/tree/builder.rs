@@ -74,8 +74,15 @@ impl<T: BlitFile> TreeBuilder<T> {
         }
     }
 
-    /// Generate the final tree by baking all inputs
+    /// Generate the final tree by baking all inputs. Entries at a path taken
+    /// already are skipped, keeping the first one
     pub fn tree(&self) -> Result<Tree<T>, Error> {
+        self.tree_checked(|_, _| false)
+    }
+
+    /// Generate the final tree like [`Self::tree`], failing with [`Error::Duplicates`]
+    /// if any skipped entry is `conflicting` with the one kept in its place
+    pub fn tree_checked(&self, conflicting: impl Fn(&T, &T) -> bool) -> Result<Tree<T>, Error> {
         // Chain all directories, replace implicits with explicit
         let all_dirs = self
             .explicit
@@ -118,29 +125,29 @@ impl<T: BlitFile> TreeBuilder<T> {
         full_set.sort_by(|a, b| sorted_paths(*a, *b));
 
         let mut tree: Tree<T> = Tree::new();
+        let mut duplicates = vec![];
 
         // Build the initial full tree now.
         for entry in full_set {
-            // New node for this guy
-            let path = entry.path();
-            let node = tree.new_node(entry.clone());
-
-            if let Some(parent) = path::parent(&path) {
-                tree.add_child_to_node(node, parent)?;
-            }
+            duplicates.extend(tree.insert(entry.clone(), &conflicting)?);
         }
 
         // Reparent any symlink redirects.
         for (source_tree, target_tree) in redirects {
-            tree.reparent(&source_tree, &target_tree)?;
+            duplicates.extend(tree.reparent(&source_tree, &target_tree, &conflicting)?);
         }
+
+        if !duplicates.is_empty() {
+            return Err(Error::Duplicates(duplicates));
+        }
+
         Ok(tree)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::tree::Kind;
+    use crate::tree::{Duplicate, Error, Kind};
 
     use super::{BlitFile, TreeBuilder};
 
@@ -220,4 +227,36 @@ mod tests {
         b.bake();
         b.tree().unwrap();
     }
+
+    #[test]
+    fn test_duplicates() {
+        let file = |path: &str, id: &str| CustomFile {
+            path: path.into(),
+            kind: Kind::Regular,
+            id: id.into(),
+        };
+
+        let mut b: TreeBuilder<CustomFile> = TreeBuilder::new();
+        b.push(file("/usr/bin/nano", "nano"));
+        b.push(file("/usr/bin/nano", "nano-tiny"));
+        b.push(file("/usr/bin/vi", "vim"));
+        b.push(file("/usr/bin/vi", "vim"));
+        b.bake();
+
+        // The first one is kept
+        let tree = b.tree().unwrap();
+        assert_eq!(tree.iter().filter(|f| f.path == "/usr/bin/nano").count(), 1);
+
+        let Err(Error::Duplicates(duplicates)) = b.tree_checked(|a, b| a.id != b.id) else {
+            panic!("expected duplicates");
+        };
+        assert_eq!(
+            duplicates,
+            vec![Duplicate {
+                path: "/usr/bin/nano".into(),
+                id: "nano-tiny".into(),
+                existing: "nano".into(),
+            }]
+        );
+    }
 }
@@ -4,7 +4,7 @@
 
 //! Virtual filesystem tree (optimise layout inserts)
 
-use core::fmt::Debug;
+use core::fmt::{self, Debug};
 use std::collections::BTreeMap;
 use std::vec;
 
@@ -83,51 +83,52 @@ impl<T: BlitFile> Tree<T> {
 
     /// Add a child to the given parent node
     fn add_child_to_node(&mut self, node_id: NodeId, parent: &str) -> Result<(), Error> {
-        let node = self.arena.get(node_id).unwrap();
         if let Some(parent_node) = self.map.get(parent) {
-            let others = parent_node
-                .children(&self.arena)
-                .filter_map(|n| self.arena.get(n))
-                .filter_map(|n| {
-                    if path::file_name(&n.get().path()) == path::file_name(&node.get().path()) {
-                        Some(n.get())
-                    } else {
-                        None
-                    }
-                })
-                .collect::<Vec<_>>();
-            if !others.is_empty() {
-                // TODO: Reenable
-                // Err(Error::Duplicate(
-                //     node.get().path(),
-                //     node.get().id(),
-                //     others.first().unwrap().id(),
-                // ))
-
-                // Report duplicate and skip for now
-                eprintln!(
-                    "error: {}",
-                    Error::Duplicate(node.get().path(), node.get().id(), others.first().unwrap().id())
-                );
-
-                Ok(())
-            } else {
-                parent_node.append(node_id, &mut self.arena);
-                Ok(())
-            }
+            parent_node.append(node_id, &mut self.arena);
+            Ok(())
         } else {
             Err(Error::MissingParent(parent.to_string()))
         }
     }
 
+    /// Insert `data` below its parent unless its path is taken already, in which
+    /// case it's skipped and returned as a [`Duplicate`] if it's `conflicting`
+    /// with the existing entry
+    fn insert(&mut self, data: T, conflicting: &impl Fn(&T, &T) -> bool) -> Result<Option<Duplicate>, Error> {
+        let path = data.path();
+
+        if let Some(existing) = self.resolve_node(&path) {
+            let existing = self.arena[*existing].get();
+
+            return Ok(conflicting(&data, existing).then(|| Duplicate {
+                path,
+                id: data.id(),
+                existing: existing.id(),
+            }));
+        }
+
+        let node = self.new_node(data);
+        if let Some(parent) = path::parent(&path) {
+            self.add_child_to_node(node, parent)?;
+        }
+
+        Ok(None)
+    }
+
     pub fn print(&self) {
         let root = self.resolve_node("/").unwrap();
         eprintln!("{:#?}", root.debug_pretty_print(&self.arena));
     }
 
     /// For all descendents of the given source tree, return a set of the reparented nodes,
-    /// and remove the originals from the tree
-    fn reparent(&mut self, source_path: &str, target_path: &str) -> Result<(), Error> {
+    /// and remove the originals from the tree. Those clashing with nodes of the target tree
+    /// are skipped, returning the `conflicting` ones
+    fn reparent(
+        &mut self,
+        source_path: &str,
+        target_path: &str,
+        conflicting: &impl Fn(&T, &T) -> bool,
+    ) -> Result<Vec<Duplicate>, Error> {
         let mut mutations = vec![];
         let mut orphans = vec![];
         if let Some(source) = self.map.get(source_path) {
@@ -150,19 +151,12 @@ impl<T: BlitFile> Tree<T> {
             }
         }
 
+        let mut duplicates = vec![];
         for orphan in orphans {
-            let path = orphan.path();
-            // Do we have this node already?
-            let node = match self.resolve_node(&path) {
-                Some(n) => *n,
-                None => self.new_node(orphan),
-            };
-            if let Some(parent) = path::parent(&path) {
-                self.add_child_to_node(node, parent)?;
-            }
+            duplicates.extend(self.insert(orphan, conflicting)?);
         }
 
-        Ok(())
+        Ok(duplicates)
     }
 
     /// Iterate using a TreeIterator, starting at the `/` node
@@ -223,11 +217,27 @@ impl<'a, T: BlitFile> Iterator for TreeIterator<'a, T> {
     }
 }
 
+/// An entry skipped as its path was taken by an `existing` one already
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Duplicate {
+    pub path: String,
+    /// [`BlitFile::id`] of the skipped entry
+    pub id: String,
+    /// [`BlitFile::id`] of the entry kept in the tree
+    pub existing: String,
+}
+
+impl fmt::Display for Duplicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} attempts to overwrite {}", self.path, self.id, self.existing)
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("missing parent: {0}")]
     MissingParent(String),
 
-    #[error("duplicate entry: {0} {1} attempts to overwrite {2}")]
-    Duplicate(String, String, String),
+    #[error("duplicate entries: {}", .0.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "))]
+    Duplicates(Vec<Duplicate>),
 }
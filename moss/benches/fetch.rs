@@ -0,0 +1,138 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Fetching many small files, as a transaction of small stones does, through
+//! the shared pooled client vs. a new connection for each request
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use futures::{stream, StreamExt, TryStreamExt};
+use moss::{request, runtime};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+use url::Url;
+
+const FILES: usize = 200;
+const FILE_SIZE: usize = 16 * 1024;
+
+/// Minimal keep-alive HTTP/1.1 server, counting the connections it accepted
+fn serve() -> (SocketAddr, Arc<AtomicUsize>) {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let connections = Arc::new(AtomicUsize::new(0));
+    let accepted = connections.clone();
+
+    thread::spawn(move || {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        runtime.block_on(async move {
+            let listener = TcpListener::from_std(listener).unwrap();
+
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                accepted.fetch_add(1, Ordering::Relaxed);
+
+                tokio::spawn(async move {
+                    let body = vec![0u8; FILE_SIZE];
+                    let mut buffer = vec![0u8; 4096];
+                    let mut request = vec![];
+
+                    loop {
+                        let Ok(read) = socket.read(&mut buffer).await else {
+                            return;
+                        };
+                        if read == 0 {
+                            return;
+                        }
+                        request.extend_from_slice(&buffer[..read]);
+
+                        // Answer each complete request on this connection
+                        while let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+                            request.drain(..end + 4);
+
+                            let header = format!("HTTP/1.1 200 OK\r\nContent-Length: {FILE_SIZE}\r\n\r\n");
+                            if socket.write_all(header.as_bytes()).await.is_err()
+                                || socket.write_all(&body).await.is_err()
+                            {
+                                return;
+                            }
+                        }
+                    }
+                });
+            }
+        });
+    });
+
+    (address, connections)
+}
+
+fn urls(address: SocketAddr) -> Vec<Url> {
+    (0..FILES)
+        .map(|i| format!("http://{address}/{i}.stone").parse().unwrap())
+        .collect()
+}
+
+async fn fetch_pooled(urls: &[Url]) {
+    stream::iter(urls.iter().cloned())
+        .map(|url| async move {
+            let mut stream = request::get(url).await.unwrap();
+            while stream.try_next().await.unwrap().is_some() {}
+        })
+        .buffer_unordered(request::concurrency())
+        .collect::<()>()
+        .await;
+}
+
+async fn fetch_unpooled(urls: &[Url]) {
+    stream::iter(urls.iter().cloned())
+        .map(|url| async move {
+            let client = reqwest::Client::builder().pool_max_idle_per_host(0).build().unwrap();
+            client.get(url).send().await.unwrap().bytes().await.unwrap();
+        })
+        .buffer_unordered(request::concurrency())
+        .collect::<()>()
+        .await;
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let _guard = runtime::init();
+    let (address, connections) = serve();
+    let urls = urls(address);
+
+    for (name, pooled) in [("fetch pooled", true), ("fetch unpooled", false)] {
+        connections.store(0, Ordering::Relaxed);
+        let mut iterations = 0;
+
+        c.bench_function(name, |b| {
+            b.iter(|| {
+                iterations += 1;
+                if pooled {
+                    runtime::block_on(fetch_pooled(&urls));
+                } else {
+                    runtime::block_on(fetch_unpooled(&urls));
+                }
+            })
+        });
+
+        println!(
+            "{name}: {} connections per {FILES} files",
+            connections.load(Ordering::Relaxed) / iterations.max(1)
+        );
+    }
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
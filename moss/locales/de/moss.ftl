@@ -0,0 +1,33 @@
+# Deutsche Meldungen der moss CLI
+
+error = Fehler
+prompt-continue = Möchten Sie fortfahren?
+
+already-installed = Die folgenden Pakete sind bereits installiert:
+will-install = Die folgenden Pakete werden installiert:
+will-remove-conflicting = Die folgenden in Konflikt stehenden Pakete werden entfernt:
+will-remove = Die folgenden Pakete werden entfernt:
+required-by-removed = Die folgenden Pakete hängen von den zu entfernenden Paketen ab:
+use-cascade = Mit { $flag } werden sie ebenfalls entfernt
+removed = Entfernt
+
+refresh-local-offline = Offline werden nur lokale Repositorys aktualisiert
+sync-nothing = Keine Pakete zu synchronisieren
+sync-held = Die folgenden gehaltenen Pakete werden nicht synchronisiert:
+will-sync = Die folgenden Pakete werden synchronisiert:
+will-install-new = Die folgenden neuen Pakete werden installiert:
+will-replace = Die folgenden Pakete werden ersetzt:
+will-remove-orphaned = Die folgenden verwaisten Pakete werden entfernt:
+summary = Zusammenfassung
+sync-summary = { $synced } synchronisiert, { $new } neu, { $replaced } ersetzt, { $removed } entfernt
+
+plan-nothing-to-do = Nichts zu tun
+plan-would-install = Die folgenden Pakete würden installiert:
+plan-would-remove = Die folgenden Pakete würden entfernt:
+plan-source-cached = zwischengespeichert
+plan-source-local = lokal
+
+size-nothing-to-download = Kein Download nötig
+size-need-to-download = { $size } müssen heruntergeladen werden
+size-change = installierte Größe ändert sich um { $change }
+size-change-unknown = installierte Größe erst nach dem Download bekannt
@@ -0,0 +1,35 @@
+# Messages of the moss CLI, see crates/i18n
+#
+# Every message must be defined here, translations fall back to it.
+
+error = Error
+prompt-continue = Do you wish to continue?
+
+already-installed = The following package(s) are already installed:
+will-install = The following package(s) will be installed:
+will-remove-conflicting = The following conflicting package(s) will be removed:
+will-remove = The following package(s) will be removed:
+required-by-removed = The following package(s) depend on the packages being removed:
+use-cascade = Use { $flag } to remove them as well
+removed = Removed
+
+refresh-local-offline = Only refreshing local repositories in offline mode
+sync-nothing = No packages to sync
+sync-held = The following held package(s) will not be sync'd:
+will-sync = The following package(s) will be sync'd:
+will-install-new = The following new package(s) will be installed:
+will-replace = The following package(s) will be replaced:
+will-remove-orphaned = The following orphaned package(s) will be removed:
+summary = Summary
+sync-summary = { $synced } sync'd, { $new } new, { $replaced } replaced, { $removed } removed
+
+plan-nothing-to-do = Nothing to do
+plan-would-install = The following package(s) would be installed:
+plan-would-remove = The following package(s) would be removed:
+plan-source-cached = cached
+plan-source-local = local
+
+size-nothing-to-download = Nothing to download
+size-need-to-download = Need to download { $size }
+size-change = { $change } installed size change
+size-change-unknown = installed size change unknown until downloaded
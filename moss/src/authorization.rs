@@ -0,0 +1,185 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Polkit authorization of requests made to a privileged moss service
+//!
+//! Requests are classified into an [`Action`] of increasing privilege,
+//! declared in `data/polkit/com.serpentos.moss.policy`. By default anyone
+//! may query, active local users may refresh to check for updates, and
+//! transactions require administrator authentication.
+
+use std::{fs, io, process};
+
+use thiserror::Error;
+
+/// Privilege class of a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, strum::Display, strum::EnumIter)]
+#[strum(serialize_all = "lowercase")]
+pub enum Action {
+    /// Read-only access to installed & available packages
+    Query,
+    /// Refresh repository indices
+    Refresh,
+    /// Anything altering the installed system, such as
+    /// installing, removing, syncing or activating states
+    Transaction,
+}
+
+impl Action {
+    /// The polkit action id
+    pub fn id(&self) -> String {
+        format!("com.serpentos.moss.{self}")
+    }
+}
+
+/// The requester to authorize
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Subject {
+    /// A local process, identified by its start time to avoid pid reuse races
+    Process { pid: u32, start_time: u64, uid: u32 },
+    /// The unique name of a system bus connection, i.e. `:1.42`
+    BusName(String),
+}
+
+impl Subject {
+    /// Identify the running process `pid` through `/proc`
+    pub fn process(pid: u32) -> Result<Self, Error> {
+        let stat = fs::read_to_string(format!("/proc/{pid}/stat")).map_err(Error::ReadProc)?;
+        let status = fs::read_to_string(format!("/proc/{pid}/status")).map_err(Error::ReadProc)?;
+
+        Ok(Self::Process {
+            pid,
+            start_time: parse_start_time(&stat).ok_or(Error::ParseProc(pid))?,
+            uid: parse_uid(&status).ok_or(Error::ParseProc(pid))?,
+        })
+    }
+
+    fn is_root(&self) -> bool {
+        matches!(self, Subject::Process { uid: 0, .. })
+    }
+
+    fn pkcheck_args(&self) -> [String; 2] {
+        match self {
+            Subject::Process { pid, start_time, uid } => ["--process".into(), format!("{pid},{start_time},{uid}")],
+            Subject::BusName(name) => ["--system-bus-name".into(), name.clone()],
+        }
+    }
+}
+
+/// Outcome of an authorization check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Authorization {
+    Authorized,
+    Denied,
+    /// Authentication is needed, but no agent was available or interaction wasn't allowed
+    ChallengeRequired,
+    /// The user dismissed the authentication dialog
+    Dismissed,
+}
+
+impl Authorization {
+    pub fn is_authorized(&self) -> bool {
+        matches!(self, Authorization::Authorized)
+    }
+
+    /// Map a `pkcheck` exit code
+    fn from_exit_code(code: i32) -> Option<Self> {
+        match code {
+            0 => Some(Self::Authorized),
+            1 => Some(Self::Denied),
+            2 => Some(Self::ChallengeRequired),
+            3 => Some(Self::Dismissed),
+            _ => None,
+        }
+    }
+}
+
+/// Check whether `subject` may perform `action` through `pkcheck`, optionally
+/// allowing polkit to interactively authenticate the user. Root processes are
+/// always authorized.
+pub fn authorize(action: Action, subject: &Subject, allow_interaction: bool) -> Result<Authorization, Error> {
+    if subject.is_root() {
+        return Ok(Authorization::Authorized);
+    }
+
+    let mut command = process::Command::new("pkcheck");
+    command.arg("--action-id").arg(action.id()).args(subject.pkcheck_args());
+    if allow_interaction {
+        command.arg("--allow-user-interaction");
+    }
+
+    let output = command.output().map_err(Error::Pkcheck)?;
+
+    output
+        .status
+        .code()
+        .and_then(Authorization::from_exit_code)
+        .ok_or_else(|| Error::Failed(String::from_utf8_lossy(&output.stderr).trim().to_string()))
+}
+
+/// Start time (field 22) of `/proc/<pid>/stat`, in clock ticks since boot.
+/// The command name (field 2) may contain spaces, so fields are counted from
+/// its closing parenthesis.
+fn parse_start_time(stat: &str) -> Option<u64> {
+    let (_, rest) = stat.rsplit_once(')')?;
+    rest.split_whitespace().nth(19)?.parse().ok()
+}
+
+/// Real uid from the `Uid:` line of `/proc/<pid>/status`
+fn parse_uid(status: &str) -> Option<u32> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Uid:"))?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("read process info")]
+    ReadProc(#[source] io::Error),
+    #[error("malformed process info for pid {0}")]
+    ParseProc(u32),
+    #[error("run pkcheck")]
+    Pkcheck(#[source] io::Error),
+    #[error("authorization check failed: {0}")]
+    Failed(String),
+}
+
+#[cfg(test)]
+mod test {
+    use strum::IntoEnumIterator;
+
+    use super::*;
+
+    #[test]
+    fn policy_declares_actions() {
+        let policy = include_str!("../data/polkit/com.serpentos.moss.policy");
+
+        for action in Action::iter() {
+            assert!(policy.contains(&format!("<action id=\"{}\">", action.id())));
+        }
+    }
+
+    #[test]
+    fn parse_proc() {
+        let stat = "4242 (my (odd) cmd) S 1 4242 4242 0 -1 4194560 100 0 0 0 1 2 0 0 20 0 1 0 987654 1000 100";
+        assert_eq!(parse_start_time(stat), Some(987654));
+
+        let status = "Name:\tcat\nUmask:\t0022\nUid:\t1000\t1000\t1000\t1000\nGid:\t1000\t1000\t1000\t1000\n";
+        assert_eq!(parse_uid(status), Some(1000));
+
+        let root = Subject::Process {
+            pid: 1,
+            start_time: 0,
+            uid: 0,
+        };
+        assert_eq!(
+            authorize(Action::Transaction, &root, false).unwrap(),
+            Authorization::Authorized
+        );
+    }
+}
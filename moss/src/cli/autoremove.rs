@@ -0,0 +1,121 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::collections::BTreeSet;
+
+use clap::{ArgMatches, Command};
+use thiserror::Error;
+
+use moss::{
+    client::{
+        self,
+        plan::{self, Plan},
+        Client,
+    },
+    environment,
+    package::Flags,
+    prompt,
+    registry::transaction,
+    Installation, Package,
+};
+use tui::{pretty::autoprint_columns, Styled};
+
+pub fn command() -> Command {
+    Command::new("autoremove")
+        .about("Remove unneeded dependencies")
+        .long_about(
+            "Remove packages that were only installed as dependencies and \
+             are no longer required by any explicitly installed package",
+        )
+        .args(super::dry_run_args())
+        .arg(super::skip_checks_arg())
+}
+
+/// Handle execution of `moss autoremove`
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let yes = *args.get_one::<bool>("yes").unwrap();
+
+    let mut client = Client::new(environment::NAME, installation)?;
+    client.skip_checks(args.get_flag("skip-checks"));
+
+    let Some(active) = client.installation.active_state else {
+        println!("No packages to remove");
+        return Ok(());
+    };
+    let selections = client.state_db.get(active)?.selections;
+
+    // Everything reachable from the explicit (or held) packages is still needed
+    let holds = client.holds();
+    let held = client
+        .registry
+        .list_installed(Flags::default())
+        .filter(|p| holds.contains_key(&p.meta.name.to_string()))
+        .map(|p| p.id)
+        .collect::<BTreeSet<_>>();
+    let explicit = selections
+        .iter()
+        .filter(|s| s.explicit || held.contains(&s.package))
+        .map(|s| s.package.clone())
+        .collect::<Vec<_>>();
+    let transaction = client.registry.transaction_with_installed(explicit)?;
+    let needed = transaction.finalize().cloned().collect::<BTreeSet<_>>();
+
+    let orphaned = client.resolve_packages(selections.iter().map(|s| &s.package).filter(|id| !needed.contains(*id)))?;
+
+    if orphaned.is_empty() {
+        println!("No packages to remove");
+        return Ok(());
+    }
+
+    if let Some(format) = super::dry_run(args) {
+        Plan::new(&client, &[] as &[Package], &orphaned).print(format)?;
+        return Ok(());
+    }
+
+    println!("The following package(s) are no longer needed and will be removed:");
+    println!();
+    autoprint_columns(&orphaned);
+    println!();
+    Plan::new(&client, &[] as &[Package], &orphaned).print_sizes();
+    println!();
+
+    let result = prompt::confirm(yes)?;
+    if !result {
+        return Err(Error::Cancelled);
+    }
+
+    for package in &orphaned {
+        println!("{} {}", "Removed".red(), package.meta.name.to_string().bold());
+    }
+
+    let new_state_pkgs = selections
+        .into_iter()
+        .filter(|s| needed.contains(&s.package))
+        .collect::<Vec<_>>();
+
+    client.new_state(&new_state_pkgs, "Autoremove")?;
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("cancelled")]
+    Cancelled,
+
+    #[error("plan")]
+    Plan(#[from] plan::Error),
+
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error("transaction")]
+    Transaction(#[from] transaction::Error),
+
+    #[error("db")]
+    DB(#[from] moss::db::Error),
+
+    #[error("string processing")]
+    Dialog(#[from] tui::dialoguer::Error),
+}
@@ -0,0 +1,75 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use clap::{arg, ArgMatches, Command};
+use moss::{
+    client::{self, Client},
+    environment, Installation,
+};
+use thiserror::Error;
+use tui::Styled;
+
+pub fn command() -> Command {
+    Command::new("boot")
+        .about("Manage boot entries")
+        .long_about(
+            "Manage the boot entries on the EFI system partition. Besides the entries of the \
+             active state, each archived state shipping a kernel gets an entry of its own, \
+             so older states remain bootable.",
+        )
+        .subcommand_required(true)
+        .subcommand(Command::new("list").about("List boot entries"))
+        .subcommand(
+            Command::new("set-default")
+                .about("Set the default boot entry")
+                .arg(arg!(<ENTRY> "Id of the entry, as shown by `moss boot list`")),
+        )
+}
+
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let client = Client::new(environment::NAME, installation)?;
+
+    match args.subcommand() {
+        Some(("list", _)) => list(&client),
+        Some(("set-default", args)) => set_default(args, &client),
+        _ => unreachable!(),
+    }
+}
+
+/// List all boot entries, marking the default one
+fn list(client: &Client) -> Result<(), Error> {
+    for entry in client.boot_entries()? {
+        let state = entry.state.map(|id| format!(" (state {id})")).unwrap_or_default();
+
+        println!(
+            "{}{}{} {}",
+            entry.id.bold(),
+            state.dim(),
+            if entry.default {
+                " (default)".green().to_string()
+            } else {
+                String::new()
+            },
+            entry.title.or(entry.version).unwrap_or_default()
+        );
+    }
+
+    Ok(())
+}
+
+fn set_default(args: &ArgMatches, client: &Client) -> Result<(), Error> {
+    let id = args.get_one::<String>("ENTRY").unwrap();
+
+    client.set_default_boot_entry(id)?;
+
+    println!("{} {} by default", "Booting".green(), id.bold());
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("client")]
+    Client(#[from] client::Error),
+}
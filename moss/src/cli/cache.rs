@@ -0,0 +1,91 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use clap::{ArgMatches, Command};
+use moss::{
+    client::{self, Client},
+    environment, Installation,
+};
+use thiserror::Error;
+use tui::{HumanBytes, Styled};
+
+pub fn command() -> Command {
+    Command::new("cache")
+        .about("Manage the download cache")
+        .long_about(
+            "Manage the cache of fetched stones. These are kept so reinstalling packages \
+             or activating older states doesn't require fetching them again. Configure \
+             `prune_above_mib` in /etc/moss/cache.d/ to prune it automatically after \
+             each transaction.",
+        )
+        .subcommand_required(true)
+        .subcommand(Command::new("list").about("List cached stones"))
+        .subcommand(Command::new("size").about("Show the total size of the cache"))
+        .subcommand(Command::new("clean").about("Remove all cached stones"))
+        .subcommand(Command::new("prune").about("Remove cached stones which aren't referenced by any state"))
+}
+
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let client = Client::new(environment::NAME, installation)?;
+
+    match args.subcommand() {
+        Some(("list", _)) => list(&client),
+        Some(("size", _)) => size(&client),
+        Some(("clean", _)) => clean(&client),
+        Some(("prune", _)) => prune(&client),
+        _ => unreachable!(),
+    }
+}
+
+/// List all cached stones along with their size
+fn list(client: &Client) -> Result<(), Error> {
+    for file in client.cached_downloads()? {
+        let kind = match &file.hash {
+            Some(_) => String::default(),
+            None if file.path.extension().is_some_and(|extension| extension == "part") => " (partial)".to_string(),
+            None => " (sideloaded)".to_string(),
+        };
+
+        println!(
+            "{} {}{}",
+            file.path.display(),
+            HumanBytes(file.size).to_string().dim(),
+            kind.dim()
+        );
+    }
+
+    Ok(())
+}
+
+/// Print the total size of the cache
+fn size(client: &Client) -> Result<(), Error> {
+    let files = client.cached_downloads()?;
+    let total = files.iter().map(|file| file.size).sum::<u64>();
+
+    println!("{} in {} file(s)", HumanBytes(total).to_string().bold(), files.len());
+
+    Ok(())
+}
+
+fn clean(client: &Client) -> Result<(), Error> {
+    let freed = client.clean_cache()?;
+
+    println!("{} {}", "Removed".green(), HumanBytes(freed).to_string().bold());
+
+    Ok(())
+}
+
+fn prune(client: &Client) -> Result<(), Error> {
+    let freed = client.prune_cache()?;
+
+    println!("{} {}", "Pruned".green(), HumanBytes(freed).to_string().bold());
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("client")]
+    Client(#[from] client::Error),
+}
@@ -0,0 +1,97 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::collections::BTreeMap;
+
+use clap::{arg, ArgMatches, Command};
+use thiserror::Error;
+
+use moss::{output, settings, Installation};
+use tui::Styled;
+
+pub fn command() -> Command {
+    Command::new("config")
+        .about("Manage global settings")
+        .long_about(
+            "Manage global settings. Vendor defaults from /usr/share/moss/config.d are \
+             overridden by /etc/moss/config.d, which is where changes are written to.",
+        )
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("get")
+                .about("Print the effective value of a setting")
+                .arg(arg!(<KEY> "setting to print").value_parser(settings::KEYS.to_vec())),
+        )
+        .subcommand(
+            Command::new("set")
+                .about("Change a setting")
+                .arg(arg!(<KEY> "setting to change").value_parser(settings::KEYS.to_vec()))
+                .arg(arg!(<VALUE> "new value")),
+        )
+        .subcommand(
+            Command::new("unset")
+                .about("Restore a setting to its vendor default")
+                .arg(arg!(<KEY> "setting to restore").value_parser(settings::KEYS.to_vec())),
+        )
+        .subcommand(Command::new("list").about("List all effective settings"))
+}
+
+/// Handle execution of `moss config`
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let config = config::Manager::system(&installation.root, "moss");
+
+    match args.subcommand() {
+        Some(("get", cmd_args)) => {
+            let key = cmd_args.get_one::<String>("KEY").unwrap();
+
+            if let Some(value) = settings::Settings::load(&config).get(key)? {
+                println!("{value}");
+            }
+        }
+        Some(("set", cmd_args)) => {
+            let key = cmd_args.get_one::<String>("KEY").unwrap();
+            let value = cmd_args.get_one::<String>("VALUE").unwrap();
+
+            let mut admin = settings::Settings::load_admin(&config);
+            admin.set(key, value)?;
+            admin.save_admin(&config)?;
+
+            println!("{} {key}", "Set".green());
+        }
+        Some(("unset", cmd_args)) => {
+            let key = cmd_args.get_one::<String>("KEY").unwrap();
+
+            let mut admin = settings::Settings::load_admin(&config);
+            if admin.unset(key)? {
+                admin.save_admin(&config)?;
+                println!("{} {key}", "Unset".green());
+            } else {
+                println!("{key} isn't set in /etc/moss/config.d/{}.yaml", settings::ADMIN_NAME);
+            }
+        }
+        Some(("list", _)) => {
+            let listed = settings::Settings::load(&config).list()?;
+
+            if output::is_json() {
+                let data = listed.into_iter().collect::<BTreeMap<_, _>>();
+                output::print_json("settings", data)?;
+            } else {
+                for (key, value) in listed {
+                    println!("{} = {value}", key.bold());
+                }
+            }
+        }
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("settings")]
+    Settings(#[from] settings::Error),
+    #[error("json")]
+    Json(#[from] serde_json::Error),
+}
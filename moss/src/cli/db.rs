@@ -0,0 +1,128 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use clap::{ArgMatches, Command};
+use moss::{
+    client::{self, rebuild, Client},
+    environment, Installation,
+};
+use thiserror::Error;
+use tui::{HumanBytes, Styled};
+
+pub fn command() -> Command {
+    Command::new("db")
+        .about("Manage the databases of the root")
+        .subcommand_required(true)
+        .subcommand(Command::new("rebuild").about("Rebuild corrupted databases").long_about(
+            "Rebuild the meta db of each repository from its cached index, then the install & \
+                     layout dbs from the cached stones of all packages selected by a state. The state \
+                     db itself can't be rebuilt.",
+        ))
+        .subcommand(
+            Command::new("check")
+                .about("Check the integrity of all databases")
+                .long_about("Run sqlite's integrity check on every repository, install, layout & state db"),
+        )
+        .subcommand(
+            Command::new("vacuum")
+                .about("Reclaim unused space from all databases")
+                .long_about("Rebuild every repository, install, layout & state db into the minimum amount of space"),
+        )
+}
+
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    match args.subcommand() {
+        Some(("rebuild", _)) => rebuild(installation),
+        Some(("check", _)) => check(installation),
+        Some(("vacuum", _)) => vacuum(installation),
+        _ => unreachable!(),
+    }
+}
+
+fn rebuild(installation: Installation) -> Result<(), Error> {
+    let report = rebuild::rebuild(&installation)?;
+
+    println!("{} {} package(s)", "Recovered".green(), report.recovered);
+
+    if !report.missing_indices.is_empty() {
+        println!();
+        println!(
+            "{} repositories without a cached index, run `moss repo update` to populate them:",
+            "Warning:".yellow()
+        );
+        for id in &report.missing_indices {
+            println!("  {id}");
+        }
+    }
+
+    if !report.missing_packages.is_empty() {
+        println!();
+        println!(
+            "{} packages selected by a state which aren't in the download cache:",
+            "Unrecoverable".red()
+        );
+        for id in &report.missing_packages {
+            println!("  {id}");
+        }
+    }
+
+    Ok(())
+}
+
+fn check(installation: Installation) -> Result<(), Error> {
+    let client = Client::new(environment::NAME, installation)?;
+
+    let mut corrupted = vec![];
+
+    for (name, problems) in client.check_databases()? {
+        if problems.is_empty() {
+            println!("{} {name}", "OK".green());
+            continue;
+        }
+
+        println!("{} {name}", "Corrupted".red());
+        for problem in &problems {
+            println!("  {problem}");
+        }
+        corrupted.push(name);
+    }
+
+    if corrupted.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Corrupted(corrupted))
+    }
+}
+
+fn vacuum(installation: Installation) -> Result<(), Error> {
+    let client = Client::new(environment::NAME, installation)?;
+
+    let vacuumed = client.vacuum_databases()?;
+    let total = vacuumed.iter().map(|(_, bytes)| bytes).sum::<u64>();
+
+    for (name, bytes) in vacuumed {
+        println!(
+            "{} {name} {}",
+            "Vacuumed".green(),
+            format!("({})", HumanBytes(bytes)).dim()
+        );
+    }
+
+    println!();
+    println!("{} {}", "Reclaimed".bold(), HumanBytes(total));
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error("integrity check failed for {}, try `moss db rebuild`", .0.join(", "))]
+    Corrupted(Vec<String>),
+
+    #[error("rebuild")]
+    Rebuild(#[from] rebuild::Error),
+}
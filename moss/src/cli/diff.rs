@@ -0,0 +1,150 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{fmt, path::Path};
+
+use clap::{arg, ArgMatches, Command};
+use moss::{
+    package::diff::{self, Changes, Contents, Diff, File, FileChange},
+    request,
+};
+use thiserror::Error;
+use tui::{HumanBytes, Styled};
+
+pub fn command() -> Command {
+    Command::new("diff")
+        .about("Compare two stone files")
+        .long_about(
+            "Compare the metadata, dependencies and file lists of two `.stone` files, \
+             i.e. to review the impact of a rebuilt package before publishing it. \
+             Either may be a http(s) url, of which only the metadata is fetched \
+             where the server supports range requests",
+        )
+        .arg(arg!(<OLD> "original stone").value_parser(clap::value_parser!(String)))
+        .arg(arg!(<NEW> "updated stone").value_parser(clap::value_parser!(String)))
+}
+
+/// Print the differences between both stones, for `moss diff` & `moss inspect diff`
+pub fn handle(args: &ArgMatches) -> Result<(), Error> {
+    let old_path = args.get_one::<String>("OLD").unwrap();
+    let new_path = args.get_one::<String>("NEW").unwrap();
+
+    let old = read(old_path)?;
+    let new = read(new_path)?;
+    let diff = Diff::new(&old, &new);
+
+    println!("{} {old_path}", "---".red());
+    println!("{} {new_path}", "+++".green());
+
+    if diff.is_empty() {
+        println!();
+        println!("No differences");
+        return Ok(());
+    }
+
+    if !diff.fields.is_empty() {
+        println!();
+        println!("{}", "Metadata".bold());
+        for field in &diff.fields {
+            println!(
+                "  {}: {} → {}",
+                field.name,
+                field.old.as_str().red(),
+                field.new.as_str().green()
+            );
+        }
+    }
+
+    print_changes("Dependencies", &diff.dependencies);
+    print_changes("Providers", &diff.providers);
+    print_changes("Conflicts", &diff.conflicts);
+
+    if !diff.files.is_empty() {
+        println!();
+        println!("{}", "Files".bold());
+        for change in &diff.files {
+            let path = format!("/usr/{}", change.path());
+
+            match change {
+                FileChange::Added(_, file) => println!("  {} {path}{}", "+".green(), describe(file)),
+                FileChange::Removed(_, file) => println!("  {} {path}{}", "-".red(), describe(file)),
+                FileChange::Changed { old, new, .. } => {
+                    let delta = change.size_delta();
+                    let delta = (delta != 0)
+                        .then(|| format!(" ({})", SizeDelta(delta)))
+                        .unwrap_or_default();
+                    println!("  {} {path}{} →{}{delta}", "~".yellow(), describe(old), describe(new));
+                }
+            }
+        }
+    }
+
+    let count = |f: fn(&FileChange) -> bool| diff.files.iter().filter(|c| f(c)).count();
+
+    println!();
+    println!(
+        "{} {} added, {} removed, {} changed, {} installed size",
+        "Summary".bold(),
+        count(|c| matches!(c, FileChange::Added(..))),
+        count(|c| matches!(c, FileChange::Removed(..))),
+        count(|c| matches!(c, FileChange::Changed { .. })),
+        SizeDelta(diff.size_delta()),
+    );
+
+    Ok(())
+}
+
+/// Read the stone at `path`, a local path or remote url
+fn read(path: &str) -> Result<Contents, Error> {
+    match super::inspect::remote_url(path) {
+        Some(url) => Ok(Contents::decode(request::RangedReader::open(url)?)?),
+        None => Ok(Contents::read(Path::new(path))?),
+    }
+}
+
+fn print_changes<T: fmt::Display>(title: &str, changes: &Changes<T>) {
+    if changes.is_empty() {
+        return;
+    }
+
+    println!();
+    println!("{}", title.bold());
+    for added in &changes.added {
+        println!("  {} {added}", "+".green());
+    }
+    for removed in &changes.removed {
+        println!("  {} {removed}", "-".red());
+    }
+}
+
+/// Short description of a file, following its path
+fn describe(file: &File) -> String {
+    match file {
+        File::Regular { hash, size } => {
+            let size = size.map(|size| format!(", {}", HumanBytes(size))).unwrap_or_default();
+            format!(" [{hash:032x}{size}]")
+        }
+        File::Symlink(source) => format!(" -> {source}"),
+        File::Directory => " [directory]".to_string(),
+        File::Special => " [special]".to_string(),
+    }
+}
+
+/// Signed, human readable size delta
+struct SizeDelta(i64);
+
+impl fmt::Display for SizeDelta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "+" };
+        write!(f, "{sign}{}", HumanBytes(self.0.unsigned_abs()))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("read stone")]
+    Diff(#[from] diff::Error),
+    #[error("request")]
+    Request(#[from] request::Error),
+}
@@ -0,0 +1,484 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use clap::{arg, Arg, ArgAction, ArgMatches, Command};
+use hash::{Algorithm, Digest};
+use moss::{
+    client::{self, Client},
+    environment, state, Installation, State,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+const OCI_LAYOUT_VERSION: &str = "1.0.0";
+const MEDIA_TYPE_INDEX: &str = "application/vnd.oci.image.index.v1+json";
+const MEDIA_TYPE_MANIFEST: &str = "application/vnd.oci.image.manifest.v1+json";
+const MEDIA_TYPE_CONFIG: &str = "application/vnd.oci.image.config.v1+json";
+const MEDIA_TYPE_LAYER: &str = "application/vnd.oci.image.layer.v1.tar";
+const ANNOTATION_REF_NAME: &str = "org.opencontainers.image.ref.name";
+
+pub fn command() -> Command {
+    Command::new("export")
+        .about("Export a state as a container image")
+        .long_about(
+            "Export the files of a state as an OCI image layout or a plain tarball, \
+             i.e. to turn a package set into a container image in CI.\n\n\
+             Only `/usr` belongs to a state, so it's exported along with the usual links \
+             into it such as `/bin`, but without `/etc` or any other files of the root",
+        )
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("oci")
+                .about("Export as an OCI image layout")
+                .long_about(
+                    "Export as an OCI image layout directory, tagged TAG, which can be pushed with \
+                     i.e. `skopeo copy oci:DIR:TAG docker://...`. Exporting to an existing layout \
+                     adds the image to it, replacing any image of the same tag.\n\n\
+                     The state & its packages are recorded as `com.serpentos.moss.*` annotations \
+                     of the image manifest",
+                )
+                .arg(arg!(<TAG> "Tag of the image, i.e. `latest`").value_parser(clap::value_parser!(String)))
+                .args(common_args("DIR")),
+        )
+        .subcommand(
+            Command::new("tar")
+                .about("Export as a root filesystem tarball")
+                .long_about(
+                    "Export as an uncompressed root filesystem tarball, i.e. for \
+                     `docker import`. Pass `-` as FILE to write it to stdout",
+                )
+                .args(common_args("FILE")),
+        )
+}
+
+fn common_args(output: &'static str) -> [Arg; 2] {
+    [
+        Arg::new("output")
+            .short('o')
+            .long("output")
+            .value_name(output)
+            .help("Where to write the export")
+            .required(true)
+            .action(ArgAction::Set)
+            .value_parser(clap::value_parser!(PathBuf)),
+        Arg::new("state")
+            .long("state")
+            .value_name("ID")
+            .help("Export this state rather than the active one")
+            .action(ArgAction::Set)
+            .value_parser(clap::value_parser!(u64)),
+    ]
+}
+
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let client = Client::new(environment::NAME, installation)?;
+
+    match args.subcommand() {
+        Some(("oci", args)) => {
+            let state = selected_state(&client, args)?;
+            let tag = args.get_one::<String>("TAG").unwrap();
+            let output = args.get_one::<PathBuf>("output").unwrap();
+
+            let digest = export_oci(&client, &state, tag, output)?;
+
+            println!("Exported state {} as {tag} ({digest})", state.id);
+        }
+        Some(("tar", args)) => {
+            let state = selected_state(&client, args)?;
+            let output = args.get_one::<PathBuf>("output").unwrap();
+
+            if output.as_os_str() == "-" {
+                write_tar(&client.installation, &state, io::stdout().lock())?.flush()?;
+            } else {
+                let file = write_tar(&client.installation, &state, BufWriter::new(File::create(output)?))?
+                    .into_inner()
+                    .map_err(io::IntoInnerError::into_error)?;
+                file.sync_all()?;
+
+                println!("Exported state {} to {output:?}", state.id);
+            }
+        }
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
+/// The state requested with `--state`, otherwise the active one
+fn selected_state(client: &Client, args: &ArgMatches) -> Result<State, Error> {
+    let id = match args.get_one::<u64>("state") {
+        Some(id) => state::Id::from(*id as i32),
+        None => client.installation.active_state.ok_or(Error::NoActiveState)?,
+    };
+
+    client.state_db.get(id).map_err(|_| Error::StateDoesntExist(id))
+}
+
+/// Write the files of `state` as a tar archive to `writer`, returning it
+fn write_tar<W: Write>(installation: &Installation, state: &State, writer: W) -> Result<W, Error> {
+    // Archived states keep their `/usr` within the moss tree
+    let usr = if installation.active_state == Some(state.id) {
+        installation.root.join("usr")
+    } else {
+        installation.root_path(state.id.to_string()).join("usr")
+    };
+    if !usr.is_dir() {
+        return Err(Error::MissingTree(state.id, usr));
+    }
+
+    let mut builder = tar::Builder::new(writer);
+
+    append_tree(&mut builder, &usr, Path::new("usr"))?;
+
+    for (source, target) in client::ROOT_LINKS {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_mode(0o777);
+        header.set_size(0);
+        builder.append_link(&mut header, target, source)?;
+    }
+
+    Ok(builder.into_inner()?)
+}
+
+/// Append the tree at `path` to `builder` as `name`
+///
+/// Modes are kept as is, including setuid & setgid bits, while timestamps & ownership
+/// are left out so the same state always exports the same layer
+fn append_tree<W: Write>(builder: &mut tar::Builder<W>, path: &Path, name: &Path) -> Result<(), Error> {
+    let metadata = fs::symlink_metadata(path)?;
+
+    let mut header = tar::Header::new_gnu();
+    header.set_metadata_in_mode(&metadata, tar::HeaderMode::Complete);
+    header.set_mtime(0);
+    header.set_uid(0);
+    header.set_gid(0);
+
+    if metadata.is_symlink() {
+        header.set_size(0);
+        builder.append_link(&mut header, name, fs::read_link(path)?)?;
+    } else if metadata.is_dir() {
+        header.set_size(0);
+        builder.append_data(&mut header, name, io::empty())?;
+
+        // Sorted, as directory order differs between otherwise identical trees
+        let mut entries = fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort();
+
+        for entry in entries {
+            append_tree(builder, &path.join(&entry), &name.join(&entry))?;
+        }
+    } else if metadata.is_file() {
+        builder.append_data(&mut header, name, File::open(path)?)?;
+    } else {
+        // Device nodes & fifos have no contents to archive
+        header.set_size(0);
+        builder.append_data(&mut header, name, io::empty())?;
+    }
+
+    Ok(())
+}
+
+/// Export `state` to the OCI image layout at `output` as `tag`,
+/// returning the digest of its manifest
+fn export_oci(client: &Client, state: &State, tag: &str, output: &Path) -> Result<String, Error> {
+    let blobs = output.join("blobs").join("sha256");
+    fs::create_dir_all(&blobs)?;
+
+    let layer_part = blobs.join("layer.part");
+    let file = write_tar(&client.installation, state, BufWriter::new(File::create(&layer_part)?))?
+        .into_inner()
+        .map_err(io::IntoInnerError::into_error)?;
+    file.sync_all()?;
+    let layer = commit_blob(&blobs, &layer_part, MEDIA_TYPE_LAYER)?;
+
+    let config = write_blob(
+        &blobs,
+        MEDIA_TYPE_CONFIG,
+        &ImageConfig {
+            created: state.created.to_rfc3339(),
+            architecture: architecture(),
+            os: "linux",
+            config: RuntimeConfig {
+                env: vec!["PATH=/usr/bin:/usr/sbin".to_string()],
+            },
+            rootfs: RootFs {
+                kind: "layers",
+                diff_ids: vec![layer.digest.clone()],
+            },
+        },
+    )?;
+
+    let manifest = write_blob(
+        &blobs,
+        MEDIA_TYPE_MANIFEST,
+        &Manifest {
+            schema_version: 2,
+            media_type: MEDIA_TYPE_MANIFEST,
+            config,
+            layers: vec![layer],
+            annotations: annotations(client, state)?,
+        },
+    )?;
+    let digest = manifest.digest.clone();
+
+    let index_path = output.join("index.json");
+    let index = match fs::read(&index_path) {
+        Ok(bytes) => Some(serde_json::from_slice(&bytes)?),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => None,
+        Err(error) => return Err(error.into()),
+    };
+    fs::write(&index_path, serde_json::to_vec(&tag_image(index, manifest, tag))?)?;
+    fs::write(
+        output.join("oci-layout"),
+        format!("{{\"imageLayoutVersion\":\"{OCI_LAYOUT_VERSION}\"}}"),
+    )?;
+
+    Ok(digest)
+}
+
+/// Manifest annotations recording `state` & its packages
+fn annotations(client: &Client, state: &State) -> Result<BTreeMap<String, String>, Error> {
+    let mut packages = state
+        .selections
+        .iter()
+        .map(|selection| {
+            let meta = client.install_db.get(&selection.package)?;
+            Ok(format!(
+                "{}={}-{}",
+                meta.name, meta.version_identifier, meta.source_release
+            ))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    packages.sort();
+
+    let mut annotations = BTreeMap::from([
+        (
+            "org.opencontainers.image.created".to_string(),
+            state.created.to_rfc3339(),
+        ),
+        ("com.serpentos.moss.state".to_string(), state.id.to_string()),
+        ("com.serpentos.moss.packages".to_string(), packages.join(",")),
+    ]);
+    if let Some(summary) = &state.summary {
+        annotations.insert("org.opencontainers.image.description".to_string(), summary.clone());
+    }
+
+    Ok(annotations)
+}
+
+/// Add the image `manifest` to `index` as `tag`, replacing any image already tagged so
+fn tag_image(index: Option<Index>, mut manifest: Descriptor, tag: &str) -> Index {
+    let mut index = index.unwrap_or_else(|| Index {
+        schema_version: 2,
+        media_type: Some(MEDIA_TYPE_INDEX.to_string()),
+        manifests: vec![],
+    });
+
+    index
+        .manifests
+        .retain(|existing| existing.annotations.get(ANNOTATION_REF_NAME).map(String::as_str) != Some(tag));
+
+    manifest
+        .annotations
+        .insert(ANNOTATION_REF_NAME.to_string(), tag.to_string());
+    index.manifests.push(manifest);
+
+    index
+}
+
+/// Write `value` as a JSON blob
+fn write_blob(blobs: &Path, media_type: &str, value: &impl Serialize) -> Result<Descriptor, Error> {
+    let part = blobs.join("blob.part");
+    fs::write(&part, serde_json::to_vec(value)?)?;
+
+    commit_blob(blobs, &part, media_type)
+}
+
+/// Move the blob at `part` to its content addressed path
+fn commit_blob(blobs: &Path, part: &Path, media_type: &str) -> Result<Descriptor, Error> {
+    let mut hasher = Digest::new(Algorithm::Sha256);
+    let size = io::copy(&mut File::open(part)?, &mut hasher)?;
+    let hash = hasher.finalize_hex();
+
+    fs::rename(part, blobs.join(&hash))?;
+
+    Ok(Descriptor {
+        media_type: media_type.to_string(),
+        digest: format!("sha256:{hash}"),
+        size,
+        annotations: BTreeMap::new(),
+    })
+}
+
+/// The OCI name of the architecture we're running on
+fn architecture() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        arch => arch,
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Descriptor {
+    media_type: String,
+    digest: String,
+    size: u64,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    annotations: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Index {
+    schema_version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    media_type: Option<String>,
+    manifests: Vec<Descriptor>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    schema_version: u32,
+    media_type: &'static str,
+    config: Descriptor,
+    layers: Vec<Descriptor>,
+    annotations: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ImageConfig {
+    created: String,
+    architecture: &'static str,
+    os: &'static str,
+    config: RuntimeConfig,
+    rootfs: RootFs,
+}
+
+#[derive(Debug, Serialize)]
+struct RuntimeConfig {
+    #[serde(rename = "Env")]
+    env: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct RootFs {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    diff_ids: Vec<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("root must have an active state")]
+    NoActiveState,
+
+    #[error("state {0} doesn't exist")]
+    StateDoesntExist(state::Id),
+
+    #[error("files of state {0} are missing from {1:?}, it may have been pruned")]
+    MissingTree(state::Id, PathBuf),
+
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error("db")]
+    DB(#[from] moss::db::Error),
+
+    #[error("json")]
+    Json(#[from] serde_json::Error),
+
+    #[error("io")]
+    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn descriptor(digest: &str) -> Descriptor {
+        Descriptor {
+            media_type: MEDIA_TYPE_MANIFEST.to_string(),
+            digest: digest.to_string(),
+            size: 42,
+            annotations: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn retag_image() {
+        let index = tag_image(None, descriptor("sha256:aa"), "latest");
+        let index = tag_image(Some(index), descriptor("sha256:bb"), "stable");
+        let index = tag_image(Some(index), descriptor("sha256:cc"), "latest");
+
+        let tagged = index
+            .manifests
+            .iter()
+            .map(|manifest| {
+                (
+                    manifest.annotations[ANNOTATION_REF_NAME].as_str(),
+                    manifest.digest.as_str(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(tagged, vec![("stable", "sha256:bb"), ("latest", "sha256:cc")]);
+
+        let json = serde_json::to_string(&index).unwrap();
+        assert!(json.starts_with(r#"{"schemaVersion":2,"mediaType":"application/vnd.oci.image.index.v1+json""#));
+    }
+
+    #[test]
+    fn archived_modes() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("bin")).unwrap();
+        fs::write(dir.join("bin/su"), "su").unwrap();
+        fs::set_permissions(dir.join("bin/su"), fs::Permissions::from_mode(0o4755)).unwrap();
+        symlink("su", dir.join("bin/sudo")).unwrap();
+
+        let archive = || {
+            let mut builder = tar::Builder::new(vec![]);
+            append_tree(&mut builder, dir, Path::new("usr")).unwrap();
+            builder.into_inner().unwrap()
+        };
+        let layer = archive();
+
+        let mut entries = tar::Archive::new(layer.as_slice());
+        let entries = entries
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let header = entry.unwrap().header().clone();
+                (
+                    header.path().unwrap().to_string_lossy().into_owned(),
+                    header.mode().unwrap() & 0o7777,
+                    header.mtime().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(entries[2], ("usr/bin/su".to_string(), 0o4755, 0));
+        assert_eq!(entries[3].0, "usr/bin/sudo");
+
+        // Timestamps don't change the layer
+        File::open(dir.join("bin/su"))
+            .unwrap()
+            .set_modified(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap();
+        assert_eq!(archive(), layer);
+    }
+}
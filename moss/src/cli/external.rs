@@ -0,0 +1,158 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! External subcommands
+//!
+//! Like git & cargo, `moss foo` runs a `moss-foo` executable found on `PATH`,
+//! so other tools can extend the CLI. Its arguments are passed through as-is,
+//! i.e. a `--json` flag is left for the plugin to honour. The global flags are
+//! passed via `MOSS_*` environment variables instead, as a plugin may not
+//! accept them as arguments.
+
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::OsString,
+    fs, io,
+    os::unix::{fs::PermissionsExt, process::CommandExt},
+    path::{Path, PathBuf},
+    process,
+};
+
+use clap::ArgMatches;
+use moss::request;
+use thiserror::Error;
+
+/// File name prefix of external subcommands
+const PREFIX: &str = "moss-";
+
+/// All external subcommands on `PATH` by name. The first
+/// one found wins, matching how it would be invoked
+pub fn discover() -> BTreeMap<String, PathBuf> {
+    let mut found = BTreeMap::new();
+
+    for dir in search_path() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Some(name) = path
+                .file_name()
+                .and_then(|name| name.to_str()?.strip_prefix(PREFIX))
+                .filter(|name| !name.is_empty())
+            else {
+                continue;
+            };
+
+            if is_executable(&path) && !found.contains_key(name) {
+                found.insert(name.to_string(), path);
+            }
+        }
+    }
+
+    found
+}
+
+/// Names & paths of all external subcommands, for the help output.
+/// Scans every `PATH` directory, so only call it when the help is shown
+pub fn help() -> Option<String> {
+    let found = discover();
+
+    if found.is_empty() {
+        return None;
+    }
+
+    let width = found.keys().map(String::len).max().unwrap_or_default();
+    let lines = found
+        .iter()
+        .map(|(name, path)| format!("  {name:width$}  {}", path.display()))
+        .collect::<Vec<_>>();
+
+    Some(format!("External commands:\n{}", lines.join("\n")))
+}
+
+/// Replace the current process with the external subcommand `name`
+pub fn handle(name: &str, args: &ArgMatches, globals: &ArgMatches) -> Result<(), Error> {
+    let path = search_path()
+        .map(|dir| dir.join(format!("{PREFIX}{name}")))
+        .find(|path| is_executable(path))
+        .ok_or_else(|| Error::NotFound(name.to_string()))?;
+
+    let passed = args
+        .get_many::<OsString>("")
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let mut command = process::Command::new(&path);
+    command.args(passed).envs(environment(globals));
+
+    // Only returns on failure
+    Err(Error::Exec(path, command.exec()))
+}
+
+/// Global flags for the external subcommand, as `MOSS_*` environment variables
+fn environment(globals: &ArgMatches) -> Vec<(&'static str, OsString)> {
+    let mut vars = vec![];
+
+    if let Some(root) = globals.get_one::<PathBuf>("root") {
+        vars.push(("MOSS_ROOT", root.into()));
+    }
+    if let Some(cache) = globals.get_one::<PathBuf>("cache") {
+        vars.push(("MOSS_CACHE", cache.into()));
+    }
+    if let Some(format) = globals.get_one::<String>("format") {
+        vars.push(("MOSS_FORMAT", format.into()));
+    }
+    if let Some(rate) = globals.get_one::<request::Rate>("limit-rate") {
+        vars.push(("MOSS_LIMIT_RATE", rate.to_string().into()));
+    }
+    if let Some(file) = globals.get_one::<PathBuf>("log-file") {
+        vars.push(("MOSS_LOG_FILE", file.into()));
+    }
+    if globals.get_count("verbose") > 0 {
+        vars.push(("MOSS_VERBOSE", globals.get_count("verbose").to_string().into()));
+    }
+
+    for (flag, var) in [
+        ("quiet", "MOSS_QUIET"),
+        ("no-progress", "MOSS_NO_PROGRESS"),
+        ("journald", "MOSS_JOURNALD"),
+        ("offline", "MOSS_OFFLINE"),
+        ("read-only", "MOSS_READ_ONLY"),
+        ("ignore-disk-space", "MOSS_IGNORE_DISK_SPACE"),
+        ("skip-triggers", "MOSS_SKIP_TRIGGERS"),
+        ("no-verify", "MOSS_NO_VERIFY"),
+        ("yes", "MOSS_YES"),
+    ] {
+        if globals.get_flag(flag) {
+            vars.push((var, "1".into()));
+        }
+    }
+
+    vars
+}
+
+fn search_path() -> impl Iterator<Item = PathBuf> {
+    env::var_os("PATH")
+        .map(|path| env::split_paths(&path).collect::<Vec<_>>())
+        .unwrap_or_default()
+        .into_iter()
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("no such command `{0}`, and no `moss-{0}` found on PATH")]
+    NotFound(String),
+
+    #[error("run {0:?}")]
+    Exec(PathBuf, #[source] io::Error),
+}
@@ -3,10 +3,10 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use std::{
-    fs::{create_dir_all, hard_link, remove_dir_all, remove_file, File},
+    fs::{create_dir_all, hard_link, remove_dir_all, remove_file, set_permissions, File, Permissions},
     io::{copy, Read, Seek, SeekFrom},
-    os::unix::fs::symlink,
-    path::PathBuf,
+    os::unix::fs::{symlink, PermissionsExt},
+    path::{Component, Path, PathBuf},
 };
 
 use clap::{arg, ArgMatches, Command};
@@ -19,8 +19,19 @@ use tui::{ProgressBar, ProgressStyle};
 pub fn command() -> Command {
     Command::new("extract")
         .about("Extract a `.stone` content to disk")
-        .long_about("For all valid content-bearing archives, extract to disk")
+        .long_about(
+            "For all valid content-bearing archives, extract to disk, applying their layout. \
+             Neither the databases nor the root are touched, i.e. to inspect or recover files",
+        )
         .arg(arg!(<PATH> ... "files to inspect").value_parser(clap::value_parser!(PathBuf)))
+        .arg(
+            arg!(-o --output <DIR> "Extract into this directory")
+                .long_help(
+                    "Extract into this directory, rather than a directory named after \
+                     each package within the current directory",
+                )
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
 }
 
 /// Handle the `extract` command
@@ -31,11 +42,14 @@ pub fn handle(args: &ArgMatches) -> Result<(), Error> {
         .flatten()
         .cloned()
         .collect::<Vec<_>>();
+    let output = args.get_one::<PathBuf>("output");
 
-    // Begin unpack
-    create_dir_all(".stoneStore")?;
-
-    let content_store = PathBuf::from(".stoneStore");
+    // Begin unpack, within the output so files can be hardlinked from the store
+    let content_store = output
+        .map(PathBuf::as_path)
+        .unwrap_or(Path::new("."))
+        .join(".stoneStore");
+    create_dir_all(&content_store)?;
 
     for path in paths {
         println!("Extract: {:?}", path);
@@ -49,12 +63,19 @@ pub fn handle(args: &ArgMatches) -> Result<(), Error> {
         let meta = payloads.iter().find_map(PayloadKind::meta).ok_or(Error::MissingMeta)?;
 
         let pkg = package::Meta::from_stone_payload(&meta.body).map_err(Error::MalformedMeta)?;
-        let extraction_root = PathBuf::from(pkg.id().to_string());
+        let extraction_root = match output {
+            Some(dir) => dir.clone(),
+            None => {
+                let root = PathBuf::from(pkg.id().to_string());
+
+                // Cleanup old extraction root
+                if root.exists() {
+                    remove_dir_all(&root)?;
+                }
 
-        // Cleanup old extraction root
-        if extraction_root.exists() {
-            remove_dir_all(&extraction_root)?;
-        }
+                root
+            }
+        };
 
         if let Some(content) = content {
             let content_file = File::options()
@@ -62,13 +83,14 @@ pub fn handle(args: &ArgMatches) -> Result<(), Error> {
                 .write(true)
                 .create(true)
                 .truncate(true)
-                .open(".stoneContent")?;
-
-            let progress = ProgressBar::new(content.header.plain_size).with_style(
-                ProgressStyle::with_template("|{bar:20.cyan/bue}| {percent}%")
-                    .unwrap()
-                    .progress_chars("■≡=- "),
-            );
+                .open(content_store.join(".stoneContent"))?;
+
+            let progress = ProgressBar::with_draw_target(Some(content.header.plain_size), tui::draw_target())
+                .with_style(
+                    ProgressStyle::with_template("|{bar:20.cyan/bue}| {percent}%")
+                        .unwrap()
+                        .progress_chars("■≡=- "),
+                );
             reader.unpack_content(content, &mut progress.wrap_write(&content_file))?;
 
             // Extract all indices from the `.stoneContent` into hash-indexed unique files
@@ -82,7 +104,7 @@ pub fn handle(args: &ArgMatches) -> Result<(), Error> {
                     file.seek(SeekFrom::Start(idx.start))?;
                     let mut split_file = (&mut file).take(idx.end - idx.start);
 
-                    let mut output = File::create(format!(".stoneStore/{:02x}", idx.digest))?;
+                    let mut output = File::create(content_store.join(format!("{:02x}", idx.digest)))?;
 
                     copy(&mut split_file, &mut output)?;
 
@@ -90,26 +112,31 @@ pub fn handle(args: &ArgMatches) -> Result<(), Error> {
                 })
                 .collect::<Result<Vec<_>, Error>>()?;
 
-            remove_file(".stoneContent")?;
+            remove_file(content_store.join(".stoneContent"))?;
         }
 
         if let Some(layouts) = layouts {
+            // Applied last, so read-only directories can still be populated
+            let mut directories = vec![];
+
             for layout in &layouts.body {
+                let target = layout.entry.target();
+                let target_disk = extraction_root.join("usr").join(safe_path(target)?);
+                let mode = Permissions::from_mode(layout.mode & 0o7777);
+
                 match &layout.entry {
-                    layout::Entry::Regular(id, target) => {
+                    layout::Entry::Regular(id, _) => {
                         let store_path = content_store.join(format!("{:02x}", id));
-                        let target_disk = extraction_root.join("usr").join(target);
 
                         // drop it into a valid dir
-                        // TODO: Fix the permissions & mask
                         let directory_target = target_disk.parent().unwrap();
                         create_dir_all(directory_target)?;
 
                         // link from CA store
-                        hard_link(store_path, target_disk)?;
+                        hard_link(store_path, &target_disk)?;
+                        set_permissions(&target_disk, mode)?;
                     }
-                    layout::Entry::Symlink(source, target) => {
-                        let target_disk = extraction_root.join("usr").join(target);
+                    layout::Entry::Symlink(source, _) => {
                         let directory_target = target_disk.parent().unwrap();
 
                         // ensure dumping ground exists
@@ -118,14 +145,19 @@ pub fn handle(args: &ArgMatches) -> Result<(), Error> {
                         // join the link path to the directory target for relative joinery
                         symlink(source, target_disk)?;
                     }
-                    layout::Entry::Directory(target) => {
-                        let target_disk = extraction_root.join("usr").join(target);
-                        // TODO: Fix perms!
-                        create_dir_all(target_disk)?;
+                    layout::Entry::Directory(_) => {
+                        create_dir_all(&target_disk)?;
+                        directories.push((target_disk, mode));
                     }
-                    _ => unreachable!(),
+                    _ => println!("Skipping special file: /usr/{target}"),
                 }
             }
+
+            // Deepest first
+            directories.sort_by_key(|(directory, _)| std::cmp::Reverse(directory.components().count()));
+            for (directory, mode) in directories {
+                set_permissions(directory, mode)?;
+            }
         }
     }
 
@@ -135,6 +167,20 @@ pub fn handle(args: &ArgMatches) -> Result<(), Error> {
     Ok(())
 }
 
+/// Ensure a layout `target` stays within the extraction root
+fn safe_path(target: &str) -> Result<&Path, Error> {
+    let path = Path::new(target);
+
+    if path
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        Ok(path)
+    } else {
+        Err(Error::UnsafePath(target.to_string()))
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("Missing metadata")]
@@ -148,4 +194,7 @@ pub enum Error {
 
     #[error("stone format")]
     Format(#[from] stone::read::Error),
+
+    #[error("layout path escapes the extraction root: {0}")]
+    UnsafePath(String),
 }
@@ -0,0 +1,72 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use clap::{arg, builder::PossibleValuesParser, ArgMatches, Command};
+use thiserror::Error;
+
+use moss::{
+    client::{
+        self,
+        graph::{self, Graph},
+        Client,
+    },
+    environment, Installation, Provider,
+};
+
+pub fn command() -> Command {
+    Command::new("graph")
+        .about("Export the dependency graph")
+        .long_about(
+            "Export the dependency graph of the installed system, optionally limited \
+             to the dependency closure of the given packages. Edges are labelled with \
+             the provider satisfying the dependency.",
+        )
+        .arg(
+            arg!([NAME] ... "Only include the dependency closure of these packages")
+                .value_parser(clap::value_parser!(String)),
+        )
+        .arg(arg!(--installed "Graph installed packages (default)").conflicts_with("available"))
+        .arg(arg!(--available "Graph the closure of available packages, as they would be installed").requires("NAME"))
+        .arg(
+            arg!(-f --format <FORMAT> "Output format")
+                .value_parser(PossibleValuesParser::new(["dot", "json"]))
+                .default_value("dot"),
+        )
+}
+
+/// Handle execution of `moss graph` & `moss query graph`
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let roots = args
+        .get_many::<String>("NAME")
+        .into_iter()
+        .flatten()
+        .map(|name| Provider::from_name(name).map_err(|_| Error::InvalidName(name.clone())))
+        .collect::<Result<Vec<_>, _>>()?;
+    let format = match args.get_one::<String>("format").map(String::as_str) {
+        Some("json") => graph::Format::Json,
+        _ => graph::Format::Dot,
+    };
+
+    let client = Client::new(environment::NAME, installation)?;
+
+    let graph = if args.get_flag("available") {
+        Graph::available(&client.registry, &roots)?
+    } else {
+        Graph::installed(&client.registry, &roots)?
+    };
+
+    println!("{}", graph.render(format)?);
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("invalid package name: {0}")]
+    InvalidName(String),
+    #[error("client")]
+    Client(#[from] client::Error),
+    #[error("graph")]
+    Graph(#[from] graph::Error),
+}
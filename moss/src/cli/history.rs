@@ -0,0 +1,212 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use clap::{arg, ArgAction, ArgMatches, Command};
+use moss::{
+    client::{self, history, Client},
+    environment, output, package,
+    state::Transaction,
+    Installation,
+};
+use serde::Serialize;
+use thiserror::Error;
+use tui::Styled;
+
+pub fn command() -> Command {
+    Command::new("history")
+        .about("Inspect and revert past transactions")
+        .long_about(
+            "Inspect and revert individual transactions recorded in the state history. \
+             Without a subcommand, all recorded transactions are listed. Transactions are \
+             referred to by their number in that list, not the state they produced",
+        )
+        .subcommand(
+            Command::new("show").about("Show the details of a transaction").arg(
+                arg!(<N> "Transaction number to show, as listed by `moss history`")
+                    .action(ArgAction::Set)
+                    .value_parser(clap::value_parser!(u64)),
+            ),
+        )
+        .subcommand(
+            Command::new("undo")
+                .about("Undo a specific transaction")
+                .long_about(
+                    "Undo the given transaction by removing what it added and reinstalling what \
+                     it removed as a new transaction",
+                )
+                .arg(
+                    arg!(<N> "Transaction number to undo, as listed by `moss history`")
+                        .action(ArgAction::Set)
+                        .value_parser(clap::value_parser!(u64)),
+                ),
+        )
+}
+
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    match args.subcommand() {
+        None => list(installation),
+        Some(("show", args)) => show(args, installation),
+        Some(("undo", args)) => undo(args, installation),
+        _ => unreachable!(),
+    }
+}
+
+/// List all recorded transactions, oldest first
+pub fn list(installation: Installation) -> Result<(), Error> {
+    let client = Client::new(environment::NAME, installation)?;
+    let transactions = client.state_db.transactions()?;
+
+    if output::is_json() {
+        output::print_json(
+            "transactions",
+            transactions.iter().map(Listed::from).collect::<Vec<_>>(),
+        )?;
+        return Ok(());
+    }
+
+    if transactions.is_empty() {
+        println!("No transactions have been recorded");
+        return Ok(());
+    }
+
+    let summary_width = transactions
+        .iter()
+        .map(|t| t.summary.as_deref().unwrap_or_default().len())
+        .max()
+        .unwrap_or_default();
+
+    for transaction in &transactions {
+        let summary = transaction.summary.as_deref().unwrap_or_default();
+
+        println!(
+            "{:>4} {} {summary:summary_width$} {} {} {} {}",
+            transaction.id.to_string().bold(),
+            transaction.created.format("%Y-%m-%d %H:%M"),
+            format!("state {:<4}", transaction.state).dim(),
+            format!("+{}", transaction.added.len()).green(),
+            format!("-{}", transaction.removed.len()).red(),
+            transaction.command.as_str().dim(),
+        );
+    }
+
+    Ok(())
+}
+
+/// Show the command, states & package changes of a single transaction
+pub fn show(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let id = *args.get_one::<u64>("N").unwrap() as i32;
+
+    let client = Client::new(environment::NAME, installation)?;
+    let transaction = client
+        .state_db
+        .transaction(id)
+        .map_err(|_| Error::TransactionDoesntExist(id))?;
+
+    print_transaction(&client, &transaction);
+
+    Ok(())
+}
+
+fn print_transaction(client: &Client, transaction: &Transaction) {
+    let previous = transaction
+        .previous
+        .map(|id| id.to_string())
+        .unwrap_or_else(|| "none".to_string());
+
+    println!("{} {}", "Transaction:".bold(), transaction.id);
+    println!("{} {}", "Date:".bold(), transaction.created);
+    println!("{} {}", "Command:".bold(), transaction.command);
+    if let Some(summary) = &transaction.summary {
+        println!("{} {summary}", "Summary:".bold());
+    }
+    println!("{} {previous} → {}", "States:".bold(), transaction.state);
+
+    // Metadata for packages no longer installed or available may be gone,
+    // so fall back to their id
+    let describe = |id: &package::Id| match client.registry.by_id(id).next() {
+        Some(package) => format!(
+            "{} {}-{}",
+            package.meta.name, package.meta.version_identifier, package.meta.source_release
+        ),
+        None => id.to_string(),
+    };
+
+    if !transaction.added.is_empty() {
+        println!();
+        println!("{}", "Added".bold());
+        for id in &transaction.added {
+            println!("  {} {}", "+".green(), describe(id));
+        }
+    }
+    if !transaction.removed.is_empty() {
+        println!();
+        println!("{}", "Removed".bold());
+        for id in &transaction.removed {
+            println!("  {} {}", "-".red(), describe(id));
+        }
+    }
+}
+
+/// Undo a single transaction on top of the active state
+pub fn undo(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let id = *args.get_one::<u64>("N").unwrap() as i32;
+    let yes = args.get_flag("yes");
+
+    let client = Client::new(environment::NAME, installation)?;
+    let transaction = client
+        .state_db
+        .transaction(id)
+        .map_err(|_| Error::TransactionDoesntExist(id))?;
+
+    client.undo(&transaction, yes)?;
+
+    Ok(())
+}
+
+/// A transaction as listed with `--format json`
+#[derive(Serialize)]
+struct Listed<'a> {
+    id: i32,
+    created: String,
+    command: &'a str,
+    summary: Option<&'a str>,
+    /// State active before the transaction & the one it produced
+    previous: Option<i32>,
+    state: i32,
+    added: Vec<&'a str>,
+    removed: Vec<&'a str>,
+}
+
+impl<'a> From<&'a Transaction> for Listed<'a> {
+    fn from(transaction: &'a Transaction) -> Self {
+        Self {
+            id: transaction.id,
+            created: transaction.created.to_rfc3339(),
+            command: &transaction.command,
+            summary: transaction.summary.as_deref(),
+            previous: transaction.previous.map(Into::into),
+            state: transaction.state.into(),
+            added: transaction.added.iter().map(AsRef::<str>::as_ref).collect(),
+            removed: transaction.removed.iter().map(AsRef::<str>::as_ref).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("transaction {0} doesn't exist")]
+    TransactionDoesntExist(i32),
+
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error("db")]
+    DB(#[from] moss::db::Error),
+
+    #[error("undo")]
+    Undo(#[from] history::Error),
+
+    #[error("json")]
+    Json(#[from] serde_json::Error),
+}
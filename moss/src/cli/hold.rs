@@ -0,0 +1,100 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use clap::{arg, ArgMatches, Command};
+use thiserror::Error;
+
+use moss::{
+    client::{self, Client},
+    environment,
+    package::{self, Flags},
+    Installation,
+};
+use tui::Styled;
+
+pub fn command() -> Command {
+    Command::new("hold")
+        .about("Hold packages at their installed version")
+        .long_about(
+            "Exclude packages from sync, keeping them at their installed version. \
+             Without any names, list all held packages.",
+        )
+        .arg(arg!([NAME] ... "packages to hold").value_parser(clap::value_parser!(String)))
+        .arg(arg!(-r --reason <REASON> "Why the packages are held").value_parser(clap::value_parser!(String)))
+}
+
+pub fn unhold_command() -> Command {
+    Command::new("unhold")
+        .about("Release held packages")
+        .long_about("Release held packages so they're sync'd again")
+        .arg(arg!(<NAME> ... "packages to release").value_parser(clap::value_parser!(String)))
+}
+
+/// Handle execution of `moss hold`
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let names = args
+        .get_many::<String>("NAME")
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    let reason = args.get_one::<String>("reason").cloned();
+
+    let client = Client::new(environment::NAME, installation)?;
+
+    if names.is_empty() {
+        for (name, hold) in client.holds() {
+            let reason = hold.reason.map(|reason| format!(" - {reason}")).unwrap_or_default();
+            println!("{}{}", name.bold(), reason.dim());
+        }
+        return Ok(());
+    }
+
+    for name in names {
+        let package = client
+            .registry
+            .by_name(&package::Name::from(name.clone()), Flags::new().with_installed())
+            .next()
+            .ok_or_else(|| Error::NotInstalled(name.clone()))?;
+
+        client.hold(name, reason.clone())?;
+
+        println!(
+            "{} {} at {}-{}",
+            "Held".yellow(),
+            name.clone().bold(),
+            package.meta.version_identifier,
+            package.meta.source_release
+        );
+    }
+
+    Ok(())
+}
+
+/// Handle execution of `moss unhold`
+pub fn handle_unhold(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let names = args.get_many::<String>("NAME").into_iter().flatten();
+
+    let client = Client::new(environment::NAME, installation)?;
+
+    for name in names {
+        if !client.unhold(name)? {
+            return Err(Error::NotHeld(name.clone()));
+        }
+        println!("{} {}", "Released".green(), name.clone().bold());
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("package is not installed: {0}")]
+    NotInstalled(String),
+
+    #[error("package is not held: {0}")]
+    NotHeld(String),
+
+    #[error("client")]
+    Client(#[from] client::Error),
+}
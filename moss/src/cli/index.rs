@@ -8,73 +8,209 @@ use std::{
     time::Duration,
 };
 
-use clap::{arg, value_parser, ArgMatches, Command};
+use async_compression::{
+    tokio::write::{XzEncoder, ZstdEncoder},
+    Level,
+};
+use clap::{
+    arg,
+    builder::{PossibleValuesParser, TypedValueParser},
+    value_parser, Arg, ArgAction, ArgMatches, Command,
+};
+use hash::{Algorithm, Digest};
 use moss::{
     client,
     package::{self, Meta, MissingMetaFieldError},
+    repository::format,
+    request::Compression,
+    runtime,
 };
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
-use sha2::{Digest, Sha256};
 use thiserror::Error;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 use tui::{MultiProgress, ProgressBar, ProgressStyle, Styled};
 
 pub fn command() -> Command {
     Command::new("index")
         .visible_alias("ix")
         .about("Index a collection of packages")
+        .args_conflicts_with_subcommands(true)
         .arg(arg!(<INDEX_DIR> "directory of index files").value_parser(value_parser!(PathBuf)))
+        .arg(compression_arg())
+        .subcommand(
+            Command::new("add")
+                .about("Add packages to an existing index")
+                .long_about(
+                    "Add packages to an existing index without reading every stone of the \
+                     repository. New releases replace older ones of the same package and delta \
+                     packages are published alongside the release they produce.",
+                )
+                .arg(arg!(<STONE> ... "stones to add").value_parser(value_parser!(PathBuf)))
+                .arg(
+                    arg!(--"index-dir" <DIR> "directory of the index, defaults to the nearest containing the stones")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(compression_arg()),
+        )
+        .subcommand(
+            Command::new("delta")
+                .about("Produce a delta package between two releases")
+                .long_about(
+                    "Produce a delta package which turns the FROM release of a package into the TO \
+                     release, carrying only the content missing from FROM. Index it along with TO, \
+                     clients holding FROM then fetch the delta instead of the full package.",
+                )
+                .arg(arg!(<FROM> "stone of the older release").value_parser(value_parser!(PathBuf)))
+                .arg(arg!(<TO> "stone of the newer release").value_parser(value_parser!(PathBuf)))
+                .arg(
+                    arg!(-o --output <FILE> "where to write the delta, defaults to beside TO")
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        )
+}
+
+fn compression_arg() -> Arg {
+    Arg::new("compression")
+        .long("compression")
+        .help("Also write a compressed index, which clients fetch in favour of the plain one")
+        .long_help(
+            "Also write a compressed index, i.e. stone.index.zst, which clients fetch in favour of \
+             the plain one. May be repeated. Compressed indexes already present are always rewritten, \
+             so they never go stale",
+        )
+        .action(ArgAction::Append)
+        .value_parser(
+            PossibleValuesParser::new(Compression::ALL.map(|compression| compression.to_string()))
+                .map(|compression| compression.parse::<Compression>().expect("possible value")),
+        )
+}
+
+/// Compressed indexes requested in `args`
+fn compressions(args: &ArgMatches) -> Vec<Compression> {
+    args.get_many::<Compression>("compression")
+        .into_iter()
+        .flatten()
+        .copied()
+        .collect()
 }
 
 pub fn handle(args: &ArgMatches) -> Result<(), Error> {
+    match args.subcommand() {
+        Some(("add", args)) => return add(args),
+        Some(("delta", args)) => return delta(args),
+        _ => {}
+    }
+
     let dir = args.get_one::<PathBuf>("INDEX_DIR").unwrap().canonicalize()?;
 
     let stone_files = enumerate_stone_files(&dir)?;
 
     println!("Indexing {} files\n", stone_files.len());
 
-    let multi_progress = MultiProgress::new();
-
-    let total_progress = multi_progress.add(
-        ProgressBar::new(stone_files.len() as u64).with_style(
-            ProgressStyle::with_template("\n|{bar:20.cyan/blue}| {pos}/{len}")
-                .unwrap()
-                .progress_chars("■≡=- "),
-        ),
-    );
-    total_progress.tick();
+    let (multi_progress, total_progress) = progress(stone_files.len());
 
     let list = stone_files
         .par_iter()
         .map(|path| get_meta(path, &dir, &multi_progress, &total_progress))
         .collect::<Result<Vec<_>, _>>()?;
 
+    let (deltas, packages): (Vec<_>, Vec<_>) = list.into_iter().partition(|(_, from)| from.is_some());
+
     let mut map = BTreeMap::new();
 
     // Add each meta to the map, removing
     // dupes by keeping the latest release
-    for meta in list {
-        match map.entry(meta.name.clone()) {
-            btree_map::Entry::Vacant(entry) => {
-                entry.insert(meta);
-            }
-            btree_map::Entry::Occupied(mut entry) => {
-                match (entry.get().source_release, meta.source_release) {
-                    // Error if dupe is same version
-                    (prev, curr) if prev == curr => {
-                        return Err(Error::DuplicateRelease(meta.name.clone(), meta.source_release));
-                    }
-                    // Update if dupe is newer version
-                    (prev, curr) if prev < curr => {
-                        entry.insert(meta);
-                    }
-                    // Otherwise prev is more recent, don't replace
-                    _ => {}
-                }
-            }
+    for (meta, _) in packages {
+        insert_latest(&mut map, meta)?;
+    }
+
+    // Publish deltas alongside the release they produce,
+    // deltas to superseded releases are of no use
+    for (delta, from) in deltas {
+        insert_delta(&mut map, delta, from);
+    }
+
+    write_index(&dir, map, &compressions(args), &total_progress)?;
+
+    multi_progress.clear()?;
+
+    println!("\nIndex file written to {:?}", dir.join("stone.index").display());
+
+    Ok(())
+}
+
+/// Write a delta package between two releases
+fn delta(args: &ArgMatches) -> Result<(), Error> {
+    let from = args.get_one::<PathBuf>("FROM").unwrap();
+    let to = args.get_one::<PathBuf>("TO").unwrap();
+
+    let output = match args.get_one::<PathBuf>("output") {
+        Some(output) => output.clone(),
+        None => {
+            let stem = to.file_stem().unwrap_or_default().to_string_lossy();
+            let from_stem = from.file_stem().unwrap_or_default().to_string_lossy();
+            to.with_file_name(format!("{stem}.from-{from_stem}.delta.stone"))
+        }
+    };
+
+    let produced = package::delta::write(from, to, &output)?;
+
+    println!(
+        "{} {} carrying {} of {} contents",
+        "Produced".green(),
+        output.display().to_string().bold(),
+        produced.carried,
+        produced.carried + produced.reused
+    );
+
+    Ok(())
+}
+
+/// Update an existing index with the given stones, only reading those
+fn add(args: &ArgMatches) -> Result<(), Error> {
+    let stone_files = args
+        .get_many::<PathBuf>("STONE")
+        .into_iter()
+        .flatten()
+        .map(|path| path.canonicalize())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let dir = match args.get_one::<PathBuf>("index-dir") {
+        Some(dir) => dir.canonicalize()?,
+        None => stone_files
+            .first()
+            .and_then(|path| path.ancestors().find(|dir| dir.join("stone.index").is_file()))
+            .map(Path::to_path_buf)
+            .ok_or(Error::MissingIndex)?,
+    };
+
+    let mut map = read_index(&dir.join("stone.index"))?;
+
+    println!("Adding {} files to {} packages\n", stone_files.len(), map.len());
+
+    let (multi_progress, total_progress) = progress(stone_files.len());
+
+    let list = stone_files
+        .par_iter()
+        .map(|path| get_meta(path, &dir, &multi_progress, &total_progress))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let (deltas, packages): (Vec<_>, Vec<_>) = list.into_iter().partition(|(_, from)| from.is_some());
+
+    for (meta, _) in packages {
+        // Re-adding an already indexed stone is a no-op
+        let is_indexed = map.get(&meta.name).is_some_and(|indexed| indexed.hash == meta.hash);
+
+        if !is_indexed {
+            insert_latest(&mut map, meta)?;
         }
     }
 
-    write_index(&dir, map, &total_progress)?;
+    for (delta, from) in deltas {
+        insert_delta(&mut map, delta, from);
+    }
+
+    write_index(&dir, map, &compressions(args), &total_progress)?;
 
     multi_progress.clear()?;
 
@@ -83,7 +219,87 @@ pub fn handle(args: &ArgMatches) -> Result<(), Error> {
     Ok(())
 }
 
-fn write_index(dir: &Path, map: BTreeMap<package::Name, Meta>, total_progress: &ProgressBar) -> Result<(), Error> {
+fn progress(len: usize) -> (MultiProgress, ProgressBar) {
+    let multi_progress = MultiProgress::with_draw_target(tui::draw_target());
+
+    let total_progress = multi_progress.add(
+        ProgressBar::new(len as u64).with_style(
+            ProgressStyle::with_template("\n|{bar:20.cyan/blue}| {pos}/{len}")
+                .unwrap()
+                .progress_chars("■≡=- "),
+        ),
+    );
+    total_progress.tick();
+
+    (multi_progress, total_progress)
+}
+
+/// Add `meta` to the map unless a later release of the package is already present
+fn insert_latest(map: &mut BTreeMap<package::Name, Meta>, meta: Meta) -> Result<(), Error> {
+    match map.entry(meta.name.clone()) {
+        btree_map::Entry::Vacant(entry) => {
+            entry.insert(meta);
+        }
+        btree_map::Entry::Occupied(mut entry) => {
+            match (entry.get().source_release, meta.source_release) {
+                // Error if dupe is same version
+                (prev, curr) if prev == curr => {
+                    return Err(Error::DuplicateRelease(meta.name.clone(), meta.source_release));
+                }
+                // Update if dupe is newer version
+                (prev, curr) if prev < curr => {
+                    entry.insert(meta);
+                }
+                // Otherwise prev is more recent, don't replace
+                _ => {}
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Attach a delta package to the release it produces, if that's in the map
+fn insert_delta(map: &mut BTreeMap<package::Name, Meta>, delta: Meta, from: Option<String>) {
+    let Some(meta) = map.get_mut(&delta.name).filter(|meta| meta.id() == delta.id()) else {
+        return;
+    };
+
+    if let (Some(from), Some(hash), Some(size), Some(uri)) = (from, delta.hash, delta.download_size, delta.uri) {
+        meta.deltas.insert(package::Delta { from, hash, size, uri });
+    }
+}
+
+/// Read the packages of an existing index, along with their deltas
+fn read_index(path: &Path) -> Result<BTreeMap<package::Name, Meta>, Error> {
+    let mut file = fs::File::open(path)?;
+    let mut reader = stone::read(&mut file)?;
+
+    let mut map = BTreeMap::new();
+
+    for payload in reader.payloads()? {
+        let stone::read::PayloadKind::Meta(payload) = payload? else {
+            continue;
+        };
+
+        if let Some(format) = format::Format::from_stone_payload(&payload.body) {
+            format.negotiate()?;
+            continue;
+        }
+
+        let meta = Meta::from_stone_payload(&payload.body)?;
+        map.insert(meta.name.clone(), meta);
+    }
+
+    Ok(map)
+}
+
+fn write_index(
+    dir: &Path,
+    map: BTreeMap<package::Name, Meta>,
+    compressions: &[Compression],
+    total_progress: &ProgressBar,
+) -> Result<(), Error> {
     total_progress.set_message("Writing index file");
     total_progress.set_style(
         ProgressStyle::with_template("\n {spinner} {wide_msg}")
@@ -92,26 +308,73 @@ fn write_index(dir: &Path, map: BTreeMap<package::Name, Meta>, total_progress: &
     );
     total_progress.enable_steady_tick(Duration::from_millis(150));
 
-    let mut file = fs::File::create(dir.join("stone.index"))?;
+    // Written aside & moved into place, so the index is never seen half written
+    let partial = dir.join("stone.index.part");
+    let mut file = fs::File::create(&partial)?;
 
     let mut writer = stone::Writer::new(&mut file, stone::header::v1::FileType::Repository)?;
 
+    // Describe the index format first, so readers can bail early
+    writer.add_payload(format::Format::current().to_stone_payload().as_slice())?;
+
     for (_, meta) in map {
         let payload = meta.to_stone_payload();
         writer.add_payload(payload.as_slice())?;
     }
 
     writer.finalize()?;
+    file.sync_all()?;
+
+    fs::rename(partial, dir.join("stone.index"))?;
+
+    // Clients prefer compressed indexes, so an outdated one mustn't be left behind
+    for compression in Compression::ALL {
+        if compressions.contains(&compression) || compressed_path(dir, compression).exists() {
+            write_compressed(dir, compression)?;
+        }
+    }
 
     Ok(())
 }
 
+fn compressed_path(dir: &Path, compression: Compression) -> PathBuf {
+    dir.join(format!("stone.index.{}", compression.extension()))
+}
+
+/// Compress the written `stone.index` of `dir`
+fn write_compressed(dir: &Path, compression: Compression) -> Result<(), Error> {
+    let path = compressed_path(dir, compression);
+    let partial = path.with_extension(format!("{}.part", compression.extension()));
+
+    runtime::block_on(async {
+        let mut input = tokio::fs::File::open(dir.join("stone.index")).await?;
+        let mut output = tokio::fs::File::create(&partial).await?;
+
+        // Indexes are fetched far more often than written, so spend the time
+        let mut encoder: Box<dyn AsyncWrite + Unpin> = match compression {
+            Compression::Zstd => Box::new(ZstdEncoder::with_quality(&mut output, Level::Best)),
+            Compression::Xz => Box::new(XzEncoder::with_quality(&mut output, Level::Best)),
+        };
+        tokio::io::copy(&mut input, &mut encoder).await?;
+        encoder.shutdown().await?;
+        drop(encoder);
+
+        output.sync_all().await
+    })?;
+
+    fs::rename(partial, path)?;
+
+    Ok(())
+}
+
+/// Read the [`Meta`] of a stone, along with the hash of
+/// the release it applies to if it's a delta package
 fn get_meta(
     path: &Path,
     dir: &Path,
     multi_progress: &MultiProgress,
     total_progress: &ProgressBar,
-) -> Result<Meta, Error> {
+) -> Result<(Meta, Option<String>), Error> {
     let relative_path = format!("{}", path.strip_prefix(dir)?.display());
 
     let progress = multi_progress.insert_before(total_progress, ProgressBar::new_spinner());
@@ -136,8 +399,16 @@ fn get_meta(
         .ok_or(Error::MissingMetaPayload)?;
 
     let mut meta = Meta::from_stone_payload(&payload.body)?;
+    let delta_from = payload.body.iter().find_map(|record| match (record.tag, &record.kind) {
+        (stone::payload::meta::Tag::DeltaFrom, stone::payload::meta::Kind::String(from)) => Some(from.clone()),
+        _ => None,
+    });
     meta.hash = Some(hash);
     meta.download_size = Some(size);
+    meta.installed_size = payloads
+        .iter()
+        .find_map(|payload| payload.content())
+        .map(|content| content.header.plain_size);
     meta.uri = Some(relative_path.clone());
 
     progress.finish();
@@ -145,7 +416,7 @@ fn get_meta(
     multi_progress.println(format!("{} {}", "Indexed".green(), relative_path.bold()))?;
     total_progress.inc(1);
 
-    Ok(meta)
+    Ok((meta, delta_from))
 }
 
 fn stat_file(path: &Path, relative_path: &str, progress: &ProgressBar) -> Result<(u64, String), Error> {
@@ -160,10 +431,10 @@ fn stat_file(path: &Path, relative_path: &str, progress: &ProgressBar) -> Result
             .tick_chars("--=≡■≡=--"),
     );
 
-    let mut hasher = Sha256::new();
+    let mut hasher = Digest::new(Algorithm::Sha256);
     io::copy(&mut &file, &mut progress.wrap_write(&mut hasher))?;
 
-    let hash = hex::encode(hasher.finalize());
+    let hash = hasher.finalize_hex();
 
     Ok((size, hash))
 }
@@ -200,6 +471,12 @@ pub enum Error {
     #[error("package {0} has two files with the same release {1}")]
     DuplicateRelease(package::Name, u64),
 
+    #[error("no stone.index found, specify the index directory or create one with `moss index`")]
+    MissingIndex,
+
+    #[error("index format")]
+    Format(#[from] format::Error),
+
     #[error("meta payload missing")]
     MissingMetaPayload,
 
@@ -211,4 +488,7 @@ pub enum Error {
 
     #[error("client")]
     Client(#[from] client::Error),
+
+    #[error("delta")]
+    Delta(#[from] package::delta::Error),
 }
@@ -6,13 +6,17 @@ use clap::{arg, ArgMatches, Command};
 use itertools::Itertools;
 use moss::{
     client::{self, Client},
-    environment,
-    package::Flags,
-    Installation, Package, Provider,
+    environment, output,
+    package::{
+        diff::{self, Contents},
+        Flags,
+    },
+    request, Installation, Package, Provider,
 };
+use serde::Serialize;
 use stone::payload::layout;
 use thiserror::Error;
-use tui::Styled;
+use tui::{HumanBytes, Styled};
 use vfs::tree::BlitFile;
 
 const COLUMN_WIDTH: usize = 20;
@@ -20,8 +24,12 @@ const COLUMN_WIDTH: usize = 20;
 pub fn command() -> Command {
     Command::new("info")
         .about("Query packages")
-        .long_about("List detailed package information from all available sources")
-        .arg(arg!(<NAME> ... "Packages to query").value_parser(clap::value_parser!(String)))
+        .long_about(
+            "List detailed package information from all available sources. Remote `.stone` files \
+             are given as http(s) urls, of which only the metadata & layouts are fetched where \
+             the server supports range requests",
+        )
+        .arg(arg!(<NAME> ... "Packages or stone urls to query").value_parser(clap::value_parser!(String)))
         .arg(arg!(-f --files ... "Show files provided by package").action(clap::ArgAction::SetTrue))
 }
 
@@ -37,29 +45,139 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
 
     let client = Client::new(environment::NAME, installation)?;
 
+    let mut infos = vec![];
+
     for pkg in pkgs {
-        let lookup = Provider::from_name(&pkg).unwrap();
-        let resolved = client
-            .registry
-            .by_provider(&lookup, Flags::default())
-            .collect::<Vec<_>>();
-        if resolved.is_empty() {
-            return Err(Error::NotFound(pkg));
-        }
-        for candidate in resolved {
-            print_package(&candidate);
+        let found = match super::inspect::remote_url(&pkg) {
+            Some(url) => vec![remote(url, show_files)?],
+            None => {
+                let lookup = Provider::from_name(&pkg).unwrap();
+                let resolved = client
+                    .registry
+                    .by_provider(&lookup, Flags::default())
+                    .collect::<Vec<_>>();
+                if resolved.is_empty() {
+                    return Err(Error::NotFound(pkg));
+                }
+
+                resolved
+                    .into_iter()
+                    .map(|candidate| {
+                        let files = if candidate.flags.installed && show_files {
+                            Some(files(client.vfs([&candidate.id])?))
+                        } else {
+                            None
+                        };
+                        Ok((candidate, files))
+                    })
+                    .collect::<Result<Vec<_>, Error>>()?
+            }
+        };
+
+        for (candidate, files) in found {
+            if output::is_json() {
+                infos.push(Info::new(&client, &candidate, files));
+                continue;
+            }
+
+            print_package(&client, &candidate)?;
 
-            if candidate.flags.installed && show_files {
-                let vfs = client.vfs([&candidate.id])?;
-                print_files(vfs);
+            if let Some(files) = files {
+                print_files(files);
             }
             println!();
         }
     }
 
+    if output::is_json() {
+        output::print_json("packages", infos)?;
+    }
+
     Ok(())
 }
 
+/// A package as printed with `--format json`
+#[derive(Serialize)]
+struct Info {
+    id: String,
+    name: String,
+    version: String,
+    source_release: u64,
+    build_release: u64,
+    installed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    repository: Option<String>,
+    homepage: String,
+    licenses: Vec<String>,
+    download_size: Option<u64>,
+    /// Unless neither published by the repository nor known from a fetched package
+    installed_size: Option<u64>,
+    summary: String,
+    description: String,
+    dependencies: Vec<String>,
+    providers: Vec<String>,
+    /// Only with `--files`, for installed packages & remote stones
+    #[serde(skip_serializing_if = "Option::is_none")]
+    files: Option<Vec<String>>,
+}
+
+impl Info {
+    fn new(client: &Client, pkg: &Package, files: Option<Vec<(String, Option<String>)>>) -> Self {
+        let files = files.map(|files| files.into_iter().map(|(path, _)| path).collect());
+
+        Self {
+            id: pkg.id.to_string(),
+            name: pkg.meta.name.to_string(),
+            version: pkg.meta.version_identifier.clone(),
+            source_release: pkg.meta.source_release,
+            build_release: pkg.meta.build_release,
+            installed: pkg.flags.installed,
+            repository: client.repository_for(pkg).map(ToString::to_string),
+            homepage: pkg.meta.homepage.clone(),
+            licenses: pkg.meta.licenses.clone(),
+            download_size: pkg.meta.download_size,
+            installed_size: pkg.meta.installed_size,
+            summary: pkg.meta.summary.clone(),
+            description: pkg.meta.description.clone(),
+            dependencies: pkg.meta.dependencies.iter().map(ToString::to_string).sorted().collect(),
+            providers: pkg.meta.providers.iter().map(ToString::to_string).sorted().collect(),
+            files,
+        }
+    }
+}
+
+/// The package of the remote stone at `url`, with its files if `show_files`
+///
+/// Only the metadata & layout payloads are read, see [`request::RangedReader`]
+fn remote(url: url::Url, show_files: bool) -> Result<(Package, Option<Vec<(String, Option<String>)>>), Error> {
+    let contents = Contents::decode(request::RangedReader::open(url)?)?;
+
+    let files = show_files.then(|| {
+        contents
+            .files
+            .iter()
+            .filter_map(|(path, file)| {
+                let meta = match file {
+                    diff::File::Directory => return None,
+                    diff::File::Regular { hash, .. } => Some(format!(" ({hash:2x})")),
+                    diff::File::Symlink(source) => Some(format!(" -> {source}")),
+                    diff::File::Special => None,
+                };
+
+                Some((format!("/usr/{path}"), meta))
+            })
+            .collect()
+    });
+
+    let package = Package {
+        id: contents.meta.id().into(),
+        meta: contents.meta,
+        flags: Flags::default(),
+    };
+
+    Ok((package, files))
+}
+
 /// Print the title for each metadata section
 fn print_titled(title: &'static str) {
     let display_width = COLUMN_WIDTH - title.len();
@@ -78,13 +196,37 @@ fn print_paragraph(p: &str) {
 }
 
 /// Pretty print a package
-fn print_package(pkg: &Package) {
+fn print_package(client: &Client, pkg: &Package) -> Result<(), Error> {
     print_titled("Name");
     println!("{}", pkg.meta.name);
     print_titled("Version");
     println!("{}", pkg.meta.version_identifier);
+    print_titled("Release");
+    println!("{}-{}", pkg.meta.source_release, pkg.meta.build_release);
+    print_titled("Status");
+    if pkg.flags.installed {
+        println!("{}", "Installed".green());
+    } else {
+        println!("Available");
+    }
+    if let Some(repo) = client.repository_for(pkg) {
+        print_titled("Repository");
+        println!("{repo}");
+    }
     print_titled("Homepage");
     println!("{}", pkg.meta.homepage);
+    if !pkg.meta.licenses.is_empty() {
+        print_titled("License");
+        println!("{}", pkg.meta.licenses.join(", "));
+    }
+    if let Some(size) = pkg.meta.download_size {
+        print_titled("Download size");
+        println!("{}", HumanBytes(size));
+    }
+    if let Some(size) = pkg.meta.installed_size {
+        print_titled("Installed size");
+        println!("{}", HumanBytes(size));
+    }
     print_titled("Summary");
     println!("{}", pkg.meta.summary);
     print_titled("Description");
@@ -99,11 +241,25 @@ fn print_package(pkg: &Package) {
         let provs = pkg.meta.providers.iter().map(|p| p.to_string()).sorted().join("\n");
         print_paragraph(&provs);
     }
+
+    Ok(())
 }
 
-fn print_files(vfs: vfs::Tree<client::PendingFile>) {
-    let files = vfs
-        .iter()
+fn print_files(files: Vec<(String, Option<String>)>) {
+    if files.is_empty() {
+        return;
+    }
+
+    print_titled("Files");
+    println!();
+    for (path, meta) in files {
+        println!("  {path}{}", meta.unwrap_or_default().dim());
+    }
+}
+
+/// Paths of all files in `vfs`, with their hash or symlink target
+fn files(vfs: vfs::Tree<client::PendingFile>) -> Vec<(String, Option<String>)> {
+    vfs.iter()
         .filter_map(|file| {
             if matches!(file.kind(), vfs::tree::Kind::Directory) {
                 return None;
@@ -118,17 +274,7 @@ fn print_files(vfs: vfs::Tree<client::PendingFile>) {
 
             Some((path, meta))
         })
-        .collect::<Vec<_>>();
-
-    if files.is_empty() {
-        return;
-    }
-
-    print_titled("Files");
-    println!();
-    for (path, meta) in files {
-        println!("  {path}{}", meta.unwrap_or_default().dim());
-    }
+        .collect()
 }
 
 #[derive(Debug, Error)]
@@ -137,4 +283,10 @@ pub enum Error {
     NotFound(String),
     #[error("client")]
     Client(#[from] client::Error),
+    #[error("stone")]
+    Stone(#[from] diff::Error),
+    #[error("request")]
+    Request(#[from] request::Error),
+    #[error("json")]
+    Json(#[from] serde_json::Error),
 }
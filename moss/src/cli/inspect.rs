@@ -3,126 +3,378 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use clap::{arg, ArgMatches, Command};
+use moss::{environment, output, request};
+use serde::Serialize;
 use std::fs::File;
-use std::path::PathBuf;
-use stone::payload::layout;
-use stone::payload::meta;
+use std::io::{Read, Seek};
+use stone::payload::{layout, meta};
 use stone::read::PayloadKind;
 use thiserror::Error;
+use tui::{HumanBytes, Styled};
 
 const COLUMN_WIDTH: usize = 20;
 
 pub fn command() -> Command {
     Command::new("inspect")
         .about("Examine raw stone files")
-        .long_about("Show detailed (debug) information on a local `.stone` file")
-        .arg(arg!(<PATH> ... "files to inspect").value_parser(clap::value_parser!(PathBuf)))
+        .long_about(
+            "Show detailed (debug) information on a `.stone` file: its header, \
+             payloads & their compression, metadata records, layout and index entries. \
+             Remote files are given as http(s) urls, of which only the metadata is fetched \
+             where the server supports range requests",
+        )
+        .args_conflicts_with_subcommands(true)
+        .arg(arg!(<PATH> ... "files or urls to inspect").value_parser(clap::value_parser!(String)))
+        .arg(super::json_arg())
+        .subcommand(super::diff::command())
+}
+
+/// A stone file, as found on disk or remotely
+#[derive(Debug, Serialize)]
+struct Stone {
+    path: String,
+    version: u32,
+    file_type: String,
+    payloads: Vec<Payload>,
+}
+
+/// A single payload & its decoded records
+#[derive(Debug, Serialize)]
+struct Payload {
+    kind: String,
+    compression: String,
+    stored_size: u64,
+    plain_size: u64,
+    num_records: usize,
+    checksum: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    meta: Vec<MetaRecord>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    layout: Vec<LayoutEntry>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    index: Vec<IndexEntry>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    attributes: Vec<Attribute>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    signatures: Vec<SignatureEntry>,
+}
+
+#[derive(Debug, Serialize)]
+struct MetaRecord {
+    tag: String,
+    value: String,
+}
+
+#[derive(Debug, Serialize)]
+struct LayoutEntry {
+    path: String,
+    kind: &'static str,
+    mode: String,
+    uid: u32,
+    gid: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct IndexEntry {
+    digest: String,
+    start: u64,
+    end: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct Attribute {
+    key: String,
+    value: String,
+}
+
+#[derive(Debug, Serialize)]
+struct SignatureEntry {
+    algorithm: String,
+    public_key: String,
 }
 
 ///
 /// Inspect the given .stone files and print results
 ///
 pub fn handle(args: &ArgMatches) -> Result<(), Error> {
+    if let Some(("diff", args)) = args.subcommand() {
+        return super::diff::handle(args).map_err(Error::Diff);
+    }
+
     let paths = args
-        .get_many::<PathBuf>("PATH")
+        .get_many::<String>("PATH")
         .into_iter()
         .flatten()
         .cloned()
         .collect::<Vec<_>>();
 
-    // Process each input path in order.
-    for path in paths {
-        let mut file = File::open(&path)?;
-        let mut reader = stone::read(&mut file)?;
-
-        let header = reader.header;
-        let payloads = reader.payloads()?;
-
-        // Grab the header version
-        print!("{path:?} = stone container version {:?}", header.version());
-
-        for payload in payloads.flatten() {
-            let mut layouts = vec![];
-
-            // Grab deps/providers/conflicts
-            let mut deps = vec![];
-            let mut provs = vec![];
-            let mut cnfls = vec![];
-
-            match payload {
-                PayloadKind::Layout(l) => layouts = l.body,
-                PayloadKind::Meta(meta) => {
-                    println!();
-
-                    for record in meta.body {
-                        let name = format!("{:?}", record.tag);
-
-                        match &record.kind {
-                            meta::Kind::Provider(k, p) if record.tag == meta::Tag::Provides => {
-                                provs.push(format!("{}({})", k, p))
-                            }
-                            meta::Kind::Provider(k, p) if record.tag == meta::Tag::Conflicts => {
-                                cnfls.push(format!("{}({})", k, p))
-                            }
-                            meta::Kind::Dependency(k, d) => deps.push(format!("{}({})", k, d)),
-                            meta::Kind::String(s) => {
-                                println!("{:width$} : {}", name, s, width = COLUMN_WIDTH)
-                            }
-                            meta::Kind::Int64(i) => {
-                                println!("{:width$} : {}", name, i, width = COLUMN_WIDTH)
-                            }
-                            meta::Kind::Uint64(i) => {
-                                println!("{:width$} : {}", name, i, width = COLUMN_WIDTH)
-                            }
-                            _ => {
-                                println!("{:width$} : {:?}", name, record, width = COLUMN_WIDTH)
-                            }
-                        }
+    let stones = paths.into_iter().map(read).collect::<Result<Vec<_>, _>>()?;
+
+    if output::is_json() {
+        output::print_json("stones", &stones)?;
+        return Ok(());
+    }
+
+    for (i, stone) in stones.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        print(stone);
+    }
+
+    Ok(())
+}
+
+/// Decode every payload of the stone at `path`, a local path or remote url
+fn read(path: String) -> Result<Stone, Error> {
+    match remote_url(&path) {
+        Some(url) => decode(path, request::RangedReader::open(url)?),
+        None => {
+            let file = File::open(&path)?;
+            decode(path, file)
+        }
+    }
+}
+
+/// `path` as a url, if it refers to a remote stone
+pub fn remote_url(path: &str) -> Option<url::Url> {
+    let url = url::Url::parse(path).ok()?;
+    matches!(url.scheme(), "http" | "https").then_some(url)
+}
+
+fn decode(path: String, source: impl Read + Seek + Send + 'static) -> Result<Stone, Error> {
+    let stream = stone::stream_payloads(source, environment::PAYLOAD_READ_AHEAD)?;
+
+    let header = stream.header;
+    let stone::Header::V1(v1) = header;
+
+    let payloads = stream
+        .map(|payload| Ok(describe(payload?)))
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    Ok(Stone {
+        path,
+        version: header.version() as u32,
+        file_type: format!("{:?}", v1.file_type),
+        payloads,
+    })
+}
+
+fn describe(payload: PayloadKind) -> Payload {
+    let header = *payload.header();
+    let mut described = Payload {
+        kind: format!("{:?}", header.kind),
+        compression: format!("{:?}", header.compression),
+        stored_size: header.stored_size,
+        plain_size: header.plain_size,
+        num_records: header.num_records,
+        checksum: header.checksum.iter().map(|b| format!("{b:02x}")).collect(),
+        meta: vec![],
+        layout: vec![],
+        index: vec![],
+        attributes: vec![],
+        signatures: vec![],
+    };
+
+    match payload {
+        PayloadKind::Meta(meta) => {
+            described.meta = meta
+                .body
+                .into_iter()
+                .map(|record| MetaRecord {
+                    tag: format!("{:?}", record.tag),
+                    value: match record.kind {
+                        meta::Kind::Int8(i) => i.to_string(),
+                        meta::Kind::Uint8(i) => i.to_string(),
+                        meta::Kind::Int16(i) => i.to_string(),
+                        meta::Kind::Uint16(i) => i.to_string(),
+                        meta::Kind::Int32(i) => i.to_string(),
+                        meta::Kind::Uint32(i) => i.to_string(),
+                        meta::Kind::Int64(i) => i.to_string(),
+                        meta::Kind::Uint64(i) => i.to_string(),
+                        meta::Kind::String(s) => s,
+                        meta::Kind::Dependency(k, d) | meta::Kind::Provider(k, d) => format!("{k}({d})"),
+                    },
+                })
+                .collect();
+        }
+        PayloadKind::Layout(layouts) => {
+            described.layout = layouts
+                .body
+                .into_iter()
+                .map(|layout| {
+                    let (kind, hash, source) = match &layout.entry {
+                        layout::Entry::Regular(hash, _) => ("regular", Some(format!("{hash:032x}")), None),
+                        layout::Entry::Symlink(source, _) => ("symlink", None, Some(source.clone())),
+                        layout::Entry::Directory(_) => ("directory", None, None),
+                        layout::Entry::CharacterDevice(_) => ("character-device", None, None),
+                        layout::Entry::BlockDevice(_) => ("block-device", None, None),
+                        layout::Entry::Fifo(_) => ("fifo", None, None),
+                        layout::Entry::Socket(_) => ("socket", None, None),
+                    };
+
+                    LayoutEntry {
+                        path: format!("/usr/{}", layout.entry.target()),
+                        kind,
+                        mode: format!("{:o}", layout.mode),
+                        uid: layout.uid,
+                        gid: layout.gid,
+                        hash,
+                        source,
                     }
-                }
-                _ => {}
-            }
+                })
+                .collect();
+        }
+        PayloadKind::Index(index) => {
+            described.index = index
+                .body
+                .into_iter()
+                .map(|index| IndexEntry {
+                    digest: format!("{:032x}", index.digest),
+                    start: index.start,
+                    end: index.end,
+                })
+                .collect();
+        }
+        PayloadKind::Attributes(attributes) => {
+            described.attributes = attributes
+                .body
+                .into_iter()
+                .map(|attribute| Attribute {
+                    key: String::from_utf8_lossy(&attribute.key).into_owned(),
+                    value: String::from_utf8_lossy(&attribute.value).into_owned(),
+                })
+                .collect();
+        }
+        PayloadKind::Signature(signatures) => {
+            described.signatures = signatures
+                .body
+                .into_iter()
+                .map(|signature| SignatureEntry {
+                    algorithm: format!("{:?}", signature.algorithm),
+                    public_key: signature.public_key.iter().map(|b| format!("{b:02x}")).collect(),
+                })
+                .collect();
+        }
+        PayloadKind::Content(_) => {}
+    }
 
-            if !deps.is_empty() {
-                println!("\n{:width$} :", "Dependencies", width = COLUMN_WIDTH);
-                for dep in deps {
-                    println!("    - {dep}");
-                }
+    described
+}
+
+/// Print a human readable description of `stone`
+fn print(stone: &Stone) {
+    println!(
+        "{:?} = stone container version V{} ({}), {} payloads",
+        stone.path,
+        stone.version,
+        stone.file_type,
+        stone.payloads.len()
+    );
+
+    for payload in &stone.payloads {
+        let ratio = if payload.plain_size > 0 {
+            format!(
+                " ({:.1}%)",
+                payload.stored_size as f64 / payload.plain_size as f64 * 100.0
+            )
+        } else {
+            String::default()
+        };
+
+        println!();
+        println!(
+            "{} {} records, {}, {} stored, {} plain{ratio}, checksum {}",
+            format!("{} payload", payload.kind).bold(),
+            payload.num_records,
+            payload.compression.to_lowercase(),
+            HumanBytes(payload.stored_size),
+            HumanBytes(payload.plain_size),
+            payload.checksum.as_str().dim(),
+        );
+
+        print_meta(&payload.meta);
+
+        if !payload.layout.is_empty() {
+            println!("\n{:width$} :", "Layout entries", width = COLUMN_WIDTH);
+            for entry in &payload.layout {
+                let details = match (&entry.hash, &entry.source) {
+                    (Some(hash), _) => format!(" {hash}"),
+                    (_, Some(source)) => format!(" -> {source}"),
+                    _ => String::default(),
+                };
+                println!(
+                    "    - {}{details} [{}, {} {}:{}]",
+                    entry.path, entry.kind, entry.mode, entry.uid, entry.gid
+                );
             }
-            if !provs.is_empty() {
-                println!("\n{:width$} :", "Providers", width = COLUMN_WIDTH);
-                for prov in provs {
-                    println!("    - {prov}");
-                }
+        }
+
+        if !payload.index.is_empty() {
+            println!("\n{:width$} :", "Index entries", width = COLUMN_WIDTH);
+            for entry in &payload.index {
+                println!(
+                    "    - {} {}..{} ({})",
+                    entry.digest,
+                    entry.start,
+                    entry.end,
+                    HumanBytes(entry.end - entry.start)
+                );
             }
-            if !cnfls.is_empty() {
-                println!("\n{:width$} :", "Conflicts", width = COLUMN_WIDTH);
-                for cnfl in cnfls {
-                    println!("    - {cnfl}");
-                }
+        }
+
+        if !payload.attributes.is_empty() {
+            println!("\n{:width$} :", "Attributes", width = COLUMN_WIDTH);
+            for attribute in &payload.attributes {
+                println!("    - {} = {}", attribute.key, attribute.value);
             }
+        }
 
-            if !layouts.is_empty() {
-                println!("\n{:width$} :", "Layout entries", width = COLUMN_WIDTH);
-                for layout in layouts {
-                    match layout.entry {
-                        layout::Entry::Regular(hash, target) => {
-                            println!("    - /usr/{} - [Regular] {:032x}", target, hash)
-                        }
-                        layout::Entry::Directory(target) => {
-                            println!("    - /usr/{} [Directory]", target)
-                        }
-                        layout::Entry::Symlink(source, target) => {
-                            println!("    - /usr/{} -> {} [Symlink]", target, source)
-                        }
-                        _ => unreachable!(),
-                    };
-                }
+        if !payload.signatures.is_empty() {
+            println!("\n{:width$} :", "Signatures", width = COLUMN_WIDTH);
+            for signature in &payload.signatures {
+                println!("    - {} {}", signature.algorithm, signature.public_key);
             }
         }
     }
+}
 
-    Ok(())
+/// Print meta records, grouping dependencies, providers & conflicts
+fn print_meta(records: &[MetaRecord]) {
+    if records.is_empty() {
+        return;
+    }
+
+    let grouped = [
+        ("Dependencies", meta::Tag::Depends),
+        ("Build dependencies", meta::Tag::BuildDepends),
+        ("Providers", meta::Tag::Provides),
+        ("Conflicts", meta::Tag::Conflicts),
+    ]
+    .map(|(title, tag)| (title, format!("{tag:?}")));
+
+    println!();
+    for record in records {
+        if !grouped.iter().any(|(_, tag)| *tag == record.tag) {
+            println!("{:width$} : {}", record.tag, record.value, width = COLUMN_WIDTH);
+        }
+    }
+
+    for (title, tag) in &grouped {
+        let values = records.iter().filter(|r| r.tag == *tag).collect::<Vec<_>>();
+        if values.is_empty() {
+            continue;
+        }
+
+        println!("\n{:width$} :", title, width = COLUMN_WIDTH);
+        for record in values {
+            println!("    - {}", record.value);
+        }
+    }
 }
 
 #[derive(Debug, Error)]
@@ -132,4 +384,13 @@ pub enum Error {
 
     #[error("stone format")]
     Format(#[from] stone::read::Error),
+
+    #[error("json")]
+    Json(#[from] serde_json::Error),
+
+    #[error("request")]
+    Request(#[from] request::Error),
+
+    #[error("diff")]
+    Diff(#[from] super::diff::Error),
 }
@@ -2,19 +2,24 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::path::PathBuf;
+use std::{fs, io, path::PathBuf};
 
-use clap::{arg, value_parser, ArgMatches, Command};
-use moss::{client::Client, environment, Installation};
-
-pub use moss::client::install::Error;
+use clap::{arg, value_parser, Arg, ArgAction, ArgMatches, Command};
+use moss::{
+    client::{self, Client},
+    environment,
+    repository::{self, Priority},
+    runtime, Installation, Repository,
+};
+use thiserror::Error;
+use url::Url;
 
 pub fn command() -> Command {
     Command::new("install")
         .visible_alias("it")
         .about("Install packages")
         .long_about("Install the requested software to the local system")
-        .arg(arg!(<NAME> ... "packages to install").value_parser(value_parser!(String)))
+        .arg(arg!(<NAME> ... "packages, local `.stone` files or URLs to install").value_parser(value_parser!(String)))
         .arg(
             arg!(--to <blit_target> "Blit this install to the provided directory instead of the root")
                 .long_help(
@@ -24,6 +29,59 @@ pub fn command() -> Command {
                 )
                 .value_parser(value_parser!(PathBuf)),
         )
+        .arg(
+            arg!(--"with-repo" <uri> "Also install from this repository, for this transaction only")
+                .long_help(
+                    "Also install from this repository, for this transaction only. \n\
+                     \n\
+                     It takes precedence over all configured repositories and is never saved, \
+                     i.e. to try packages from a staging repository",
+                )
+                .action(ArgAction::Append)
+                .value_parser(value_parser!(Url)),
+        )
+        .arg(
+            Arg::new("bootstrap")
+                .long("bootstrap")
+                .help("Provision a new root at --directory, i.e. for containers & chroots")
+                .long_help(
+                    "Provision a new root at --directory, i.e. for containers & chroots. \n\
+                     \n\
+                     The root is created if it doesn't exist and needn't be managed by moss yet. \
+                     Repositories from --repo & --repos-from are added to it before installing",
+                )
+                .action(ArgAction::SetTrue)
+                .conflicts_with("to"),
+        )
+        .arg(
+            Arg::new("repo")
+                .long("repo")
+                .value_name("NAME=URI")
+                .help("Add this repository to the bootstrapped root")
+                .action(ArgAction::Append)
+                .value_parser(parse_repo)
+                .requires("bootstrap"),
+        )
+        .arg(
+            Arg::new("repos-from")
+                .long("repos-from")
+                .value_name("FILE")
+                .help("Add the repositories of this config file to the bootstrapped root")
+                .long_help(
+                    "Add the repositories of this config file to the bootstrapped root, \
+                     i.e. one of the host's `/etc/moss/repo.d/*.yaml`",
+                )
+                .action(ArgAction::Append)
+                .value_parser(value_parser!(PathBuf))
+                .requires("bootstrap"),
+        )
+        .args(super::dry_run_args())
+        .arg(super::skip_checks_arg())
+}
+
+/// Whether `moss install --bootstrap` was invoked, whose root needn't exist yet
+pub fn is_bootstrap(matches: &ArgMatches) -> bool {
+    matches!(matches.subcommand(), Some(("install", args)) if args.get_flag("bootstrap"))
 }
 
 /// Handle execution of `moss install`
@@ -36,15 +94,125 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
         .collect::<Vec<_>>();
     let yes = *args.get_one::<bool>("yes").unwrap();
 
+    if args.get_flag("bootstrap") {
+        bootstrap(args, &installation)?;
+    }
+
     // Grab a client for the root
     let mut client = Client::new(environment::NAME, installation)?;
+    client.skip_checks(args.get_flag("skip-checks"));
 
     // Make ephemeral if a blit target was provided
     if let Some(blit_target) = args.get_one::<PathBuf>("to").cloned() {
         client = client.ephemeral(blit_target)?;
     }
 
-    client.install(&pkgs, yes)?;
+    for uri in args.get_many::<Url>("with-repo").into_iter().flatten() {
+        runtime::block_on(client.add_transient_repository(uri.clone()))?;
+    }
+
+    // Ask which alternative to pick for ambiguous providers, unless told yes
+    client.prompt_alternatives(!yes);
+
+    client.install(&pkgs, yes, super::dry_run(args))?;
+
+    Ok(())
+}
+
+/// Add the requested repositories to a new root
+fn bootstrap(args: &ArgMatches, installation: &Installation) -> Result<(), Error> {
+    if installation.active_state.is_some() {
+        return Err(Error::Provisioned(installation.root.clone()));
+    }
+
+    let mut repositories = repository::Map::default();
+
+    for path in args.get_many::<PathBuf>("repos-from").into_iter().flatten() {
+        let contents = fs::read_to_string(path).map_err(|error| Error::ReadRepos(path.clone(), error))?;
+        let map = serde_yaml::from_str::<repository::Map>(&contents)
+            .map_err(|error| Error::ParseRepos(path.clone(), error))?;
+
+        for (id, repository) in map.iter() {
+            repositories.add(id.clone(), repository.clone());
+        }
+    }
+
+    for (name, uri) in args.get_many::<(String, Url)>("repo").into_iter().flatten() {
+        repositories.add(
+            repository::Id::new(name.clone()),
+            Repository {
+                description: "...".to_string(),
+                uri: uri.clone(),
+                priority: Priority::new(0),
+                quota: None,
+                trusted_keys: vec![],
+            },
+        );
+    }
+
+    let config = config::Manager::system(&installation.root, "moss");
+    let mut manager = repository::Manager::system(config, installation.clone())?;
+
+    // Nothing to install from
+    if repositories.iter().next().is_none() && manager.list().len() == 0 {
+        return Err(Error::NoRepositories);
+    }
+
+    for (id, repository) in repositories.iter() {
+        manager.add_repository(id.clone(), repository.clone())?;
+        println!("{id} added");
+    }
 
     Ok(())
 }
+
+/// Parse a `NAME=URI` repository
+fn parse_repo(value: &str) -> Result<(String, Url), String> {
+    let (name, uri) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=URI, got {value:?}"))?;
+    let uri = uri
+        .parse::<Url>()
+        .map_err(|error| format!("invalid uri {uri:?}: {error}"))?;
+
+    Ok((name.to_string(), uri))
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("{0:?} is already provisioned, install into it without --bootstrap")]
+    Provisioned(PathBuf),
+
+    #[error("no repositories to bootstrap from, add some with --repo or --repos-from")]
+    NoRepositories,
+
+    #[error("read {0:?}")]
+    ReadRepos(PathBuf, #[source] io::Error),
+
+    #[error("parse {0:?}")]
+    ParseRepos(PathBuf, #[source] serde_yaml::Error),
+
+    #[error("repository manager")]
+    RepositoryManager(#[from] repository::manager::Error),
+
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error(transparent)]
+    Install(#[from] client::install::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn repo_arg() {
+        let (name, uri) = parse_repo("volatile=https://example.org/volatile/x86_64/stone.index").unwrap();
+        assert_eq!(name, "volatile");
+        assert_eq!(uri.as_str(), "https://example.org/volatile/x86_64/stone.index");
+
+        assert!(parse_repo("https://example.org/stone.index").is_err());
+        assert!(parse_repo("volatile=not a uri").is_err());
+    }
+}
@@ -2,23 +2,32 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use clap::{arg, ArgMatches, Command};
+use std::collections::HashMap;
+
+use clap::{arg, ArgAction, ArgMatches, Command};
 use itertools::Itertools;
+use serde::Serialize;
 use thiserror::Error;
 
 use moss::{
     client::{self, Client},
-    environment,
+    environment, output,
     package::Flags,
     Installation,
 };
-use tui::Styled;
+use tui::{HumanBytes, Styled};
 
 pub fn command() -> Command {
     Command::new("list")
         .about("List packages")
         .long_about("List packages according to a filter")
         .subcommand_required(true)
+        .arg(super::json_arg().global(true))
+        .arg(
+            arg!(-s --sizes "Show the installed size of each package")
+                .global(true)
+                .action(ArgAction::SetTrue),
+        )
         .subcommand(
             Command::new("installed")
                 .about("List all installed packages")
@@ -45,6 +54,10 @@ enum Sync {
 
 /// Handle listing by filter
 pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let json = output::is_json();
+    // Sizes of older packages may have to be computed from their layouts
+    let sizes = args.get_flag("sizes") || json;
+
     let (filter_flags, sync) = match args.subcommand() {
         Some(("available", _)) => (Flags::new().with_available(), None),
         Some(("installed", args)) => {
@@ -67,94 +80,121 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
         _ => unreachable!(),
     };
 
-    // Grab a client for the target, enumerate packages
+    // Grab a client for the target
     let client = Client::new(environment::NAME, installation)?;
-    let pkgs = client.registry.list(filter_flags).collect::<Vec<_>>();
 
-    let sync_available = if sync.is_some() {
-        client.registry.list(Flags::new().with_available()).collect::<Vec<_>>()
-    } else {
-        vec![]
-    };
-
-    if pkgs.is_empty() {
-        return Err(Error::NoneFound);
+    // Available revision per name, first (priority based) wins
+    let mut sync_available = HashMap::<String, Revision>::new();
+    if sync.is_some() {
+        for page in client.registry.pages(None, Flags::new().with_available()) {
+            for u in page {
+                sync_available.entry(u.meta.name.to_string()).or_insert(Revision {
+                    version: u.meta.version_identifier,
+                    release: u.meta.source_release,
+                });
+            }
+        }
     }
 
-    // map to renderable state
-    let mut set = pkgs
-        .into_iter()
+    // Enumerate packages a page at a time, mapping to renderable state
+    let mut set = client
+        .registry
+        .pages(None, filter_flags)
+        .flatten()
         .map(|p| {
+            let name = p.meta.name.to_string();
             let sync = sync_available
-                .iter()
-                // Get first (priority based)
-                .find(|u| u.meta.name == p.meta.name)
+                .get(&name)
                 // Ensure it's an upgrade (if `upgrades-only`)
                 // otherwise check if it's a change
                 .filter(|u| {
                     if matches!(sync, Some(Sync::Upgrades)) {
-                        u.meta.source_release > p.meta.source_release
+                        u.release > p.meta.source_release
                     } else {
-                        u.meta.source_release != p.meta.source_release
+                        u.release != p.meta.source_release
                     }
                 })
-                .map(|u| Revision {
-                    version: u.meta.version_identifier.clone(),
-                    release: u.meta.source_release.to_string(),
-                });
+                .cloned();
 
-            Format {
-                name: p.meta.name.to_string(),
+            let installed_size = sizes.then_some(p.meta.installed_size).flatten();
+
+            Entry {
+                name,
                 revision: Revision {
                     version: p.meta.version_identifier,
-                    release: p.meta.source_release.to_string(),
+                    release: p.meta.source_release,
                 },
                 summary: p.meta.summary,
-                explicit: if filter_flags == Flags::new().with_installed() {
-                    p.flags.explicit
-                } else {
-                    true
-                },
+                explicit: (filter_flags == Flags::new().with_installed()).then_some(p.flags.explicit),
+                download_size: p.meta.download_size,
+                installed_size,
                 sync,
             }
         })
-        .filter(|item| if sync.is_some() { item.sync.is_some() } else { true })
         .collect_vec();
 
+    if set.is_empty() && !json {
+        return Err(Error::NoneFound);
+    }
+
+    if sync.is_some() {
+        set.retain(|item| item.sync.is_some());
+    }
+
     // Thanks to priorities, first in list is the winning candidate in list available.
     // Therefore sort by name and dedupe is safe as we mask the lower priority items out.
     set.sort_by_key(|s| s.name.clone());
     set.dedup_by_key(|s| s.name.clone());
 
-    // Grab maximum length
-    let max_length = set.iter().map(Format::size).max().unwrap_or_default();
+    if json {
+        output::print_json("packages", &set)?;
+        return Ok(());
+    }
+
+    // Grab maximum length of each column
+    let name_width = set.iter().map(|item| item.name.len()).max().unwrap_or_default();
+    let revision_width = set.iter().map(|item| item.revision.size()).max().unwrap_or_default();
+    let sync_width = set
+        .iter()
+        .filter_map(|item| item.sync.as_ref().map(Revision::size))
+        .max()
+        .unwrap_or_default();
 
     // render
     for item in set {
-        let width = max_length - item.size() + 2;
-        let name = if item.explicit {
-            item.name.bold()
+        let name = format!("{:name_width$}", item.name);
+        let name = if item.explicit.unwrap_or(true) {
+            name.bold()
         } else {
-            item.name.dim()
+            name.dim()
         };
-        print!("{} {:width$} ", name, " ", width = width);
+        print!("{name}  ");
 
-        let print_revision = |rev: Revision, is_sync| {
+        let print_revision = |rev: &Revision, is_sync, width: usize| {
             let version = if is_sync {
-                rev.version.green()
+                rev.version.clone().green()
             } else {
-                rev.version.magenta()
+                rev.version.clone().magenta()
             };
-            print!("{}-{}", version, rev.release.dim());
+            let padding = width - rev.size();
+            print!("{}-{}{:padding$}", version, rev.release.to_string().dim(), "");
         };
 
         // Print revision
-        print_revision(item.revision, false);
+        print_revision(&item.revision, false, revision_width);
 
         // Print sync version
-        if let Some(sync) = item.sync {
+        if let Some(sync) = &item.sync {
             print!(" => ");
-            print_revision(sync, true);
+            print_revision(sync, true, sync_width);
+        }
+
+        if sizes {
+            let size = item
+                .installed_size
+                .map(|size| HumanBytes(size).to_string())
+                .unwrap_or_else(|| "?".to_string());
+            print!("  {}", format!("{size:>10}").dim());
         }
 
         println!(" - {}", item.summary);
@@ -163,30 +203,33 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
     Ok(())
 }
 
-#[derive(Debug)]
-struct Format {
+#[derive(Debug, Serialize)]
+struct Entry {
     name: String,
     summary: String,
+    #[serde(flatten)]
     revision: Revision,
-    explicit: bool,
+    /// Only known for installed packages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    explicit: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    download_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    installed_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     sync: Option<Revision>,
 }
 
-impl Format {
-    fn size(&self) -> usize {
-        self.name.len() + self.revision.size() + self.sync.as_ref().map(Revision::size).unwrap_or_default()
-    }
-}
-
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 struct Revision {
     version: String,
-    release: String,
+    release: u64,
 }
 
 impl Revision {
+    /// Rendered width of `version-release`
     fn size(&self) -> usize {
-        self.version.len() + self.release.len()
+        self.version.len() + 1 + self.release.to_string().len()
     }
 }
 
@@ -196,4 +239,6 @@ pub enum Error {
     NoneFound,
     #[error("client")]
     Client(#[from] client::Error),
+    #[error("json")]
+    Json(#[from] serde_json::Error),
 }
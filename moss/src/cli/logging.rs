@@ -0,0 +1,128 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Diagnostic logging
+//!
+//! Events are written to stderr, by default only warnings & errors. `-v` adds
+//! moss' own debug events (network requests, resolver decisions, triggers) and
+//! `-vv` traces everything, including each SQL query & its timing. `MOSS_LOG`
+//! takes precedence over either, i.e. `MOSS_LOG=moss::db=trace`.
+//!
+//! For unattended runs, events can additionally be appended to a file or sent
+//! to the systemd journal, which always include `info` events.
+
+use std::{
+    env,
+    fs::File,
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use thiserror::Error;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::{
+    filter::ParseError,
+    fmt,
+    layer::SubscriberExt,
+    util::{SubscriberInitExt, TryInitError},
+    EnvFilter, Layer,
+};
+
+/// Environment variable overriding the filter, in [`EnvFilter`] syntax
+const ENV: &str = "MOSS_LOG";
+
+/// Where & how much to log
+#[derive(Debug)]
+pub struct Options<'a> {
+    /// Number of `-v` flags
+    pub verbosity: u8,
+    /// Only log errors to stderr
+    pub quiet: bool,
+    /// Also append to this file
+    pub file: Option<&'a Path>,
+    /// Also send to the systemd journal
+    pub journald: bool,
+}
+
+/// Install the global logger, once at startup
+pub fn init(options: Options<'_>) -> Result<(), Error> {
+    let stderr_level = if options.quiet {
+        LevelFilter::ERROR
+    } else {
+        LevelFilter::WARN
+    };
+
+    let stderr = fmt::layer()
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal())
+        .without_time()
+        .with_target(options.verbosity > 0)
+        .with_filter(filter(options.verbosity, stderr_level)?);
+
+    let file = match options.file {
+        Some(path) => {
+            let file = File::options()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|error| Error::File(path.to_owned(), error))?;
+
+            Some(
+                fmt::layer()
+                    .with_writer(Arc::new(file))
+                    .with_ansi(false)
+                    .with_filter(filter(options.verbosity, LevelFilter::INFO)?),
+            )
+        }
+        None => None,
+    };
+
+    let journald = match options.journald {
+        true => Some(
+            tracing_journald::layer()
+                .map_err(Error::Journald)?
+                .with_filter(filter(options.verbosity, LevelFilter::INFO)?),
+        ),
+        false => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(stderr)
+        .with(file)
+        .with(journald)
+        .try_init()?;
+
+    Ok(())
+}
+
+/// Filter for `verbosity`, logging at least `base` events
+fn filter(verbosity: u8, base: LevelFilter) -> Result<EnvFilter, Error> {
+    if let Ok(directives) = env::var(ENV) {
+        return Ok(EnvFilter::try_new(directives)?);
+    }
+
+    let directives = match verbosity {
+        0 => base.to_string(),
+        1 => format!("{base},moss=debug"),
+        _ => LevelFilter::TRACE.to_string(),
+    };
+
+    Ok(EnvFilter::new(directives))
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("open log file {0:?}")]
+    File(PathBuf, #[source] io::Error),
+
+    #[error("connect to journald")]
+    Journald(#[source] io::Error),
+
+    #[error("invalid MOSS_LOG filter")]
+    Filter(#[from] ParseError),
+
+    #[error("logger already installed")]
+    Init(#[from] TryInitError),
+}
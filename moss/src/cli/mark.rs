@@ -0,0 +1,101 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use clap::{arg, ArgMatches, Command};
+use thiserror::Error;
+
+use moss::{
+    client::{self, Client},
+    environment,
+    package::Flags,
+    state, Installation, Provider,
+};
+use tui::Styled;
+
+pub fn command() -> Command {
+    let names = || arg!(<NAME> ... "packages to mark").value_parser(clap::value_parser!(String));
+
+    Command::new("mark")
+        .about("Change why packages are installed")
+        .long_about(
+            "Mark installed packages as explicitly requested (manual), or as only \
+             installed to satisfy dependencies (auto), making them candidates for autoremove. The change is recorded as a new state",
+        )
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("manual")
+                .about("Mark packages as explicitly installed")
+                .arg(names()),
+        )
+        .subcommand(
+            Command::new("auto")
+                .about("Mark packages as installed as a dependency")
+                .arg(names()),
+        )
+}
+
+/// Handle execution of `moss mark`
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let (explicit, args) = match args.subcommand() {
+        Some(("manual", args)) => (true, args),
+        Some(("auto", args)) => (false, args),
+        _ => unreachable!(),
+    };
+
+    let client = Client::new(environment::NAME, installation)?;
+
+    let Some(active) = client.installation.active_state else {
+        return Err(Error::NoActiveState);
+    };
+
+    let names = args.get_many::<String>("NAME").into_iter().flatten();
+
+    let mut packages = vec![];
+    for name in names {
+        let provider = Provider::from_name(name).map_err(|_| Error::NotInstalled(name.clone()))?;
+        let package = client
+            .registry
+            .by_provider(&provider, Flags::new().with_installed())
+            .next()
+            .ok_or_else(|| Error::NotInstalled(name.clone()))?;
+
+        packages.push(package);
+    }
+
+    // Earlier states keep their selections, the change is recorded as a new state
+    let selections = client.state_db.get(active)?.selections;
+    let ids = packages.iter().map(|p| p.id.clone()).collect::<Vec<_>>();
+    let Some(selections) = state::mark(&selections, &ids, explicit) else {
+        println!("Nothing to mark");
+        return Ok(());
+    };
+
+    client.new_state(&selections, "Mark")?;
+
+    let kind = if explicit { "manual" } else { "auto" };
+    for package in packages {
+        println!(
+            "{} {} as {kind}",
+            "Marked".green(),
+            package.meta.name.to_string().bold()
+        );
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("no active state")]
+    NoActiveState,
+
+    #[error("package is not installed: {0}")]
+    NotInstalled(String),
+
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error("db")]
+    DB(#[from] moss::db::Error),
+}
@@ -2,35 +2,92 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::{env, path::PathBuf};
+use std::{env, ffi::OsString, fs, io, path::PathBuf};
 
-use clap::{Arg, ArgAction, Command};
-use moss::{installation, runtime, Installation};
+use clap::{builder::PossibleValuesParser, Arg, ArgAction, ArgMatches, Command};
+use moss::{
+    client::{self, plan, postblit, space},
+    installation, output, prompt, request, runtime, settings, signature, Installation,
+};
 use thiserror::Error;
 
+mod autoremove;
+mod boot;
+mod cache;
+mod config;
+mod db;
+mod diff;
+mod export;
+mod external;
 mod extract;
+mod graph;
+mod history;
+mod hold;
 mod index;
 mod info;
 mod inspect;
 mod install;
 mod list;
+mod logging;
+mod mark;
+mod provides;
+mod query;
+mod rdepends;
+mod refresh;
 mod remove;
 mod repo;
 mod search;
+#[cfg(feature = "service")]
+mod service;
+mod shell;
 mod state;
 mod sync;
+mod transaction;
 mod version;
 
 /// Generate the CLI command structure
 fn command() -> Command {
-    Command::new("moss")
+    let command = Command::new("moss")
         .about("Next generation package manager")
+        .allow_external_subcommands(true)
+        .external_subcommand_value_parser(clap::value_parser!(OsString))
         .arg(
             Arg::new("verbose")
                 .short('v')
                 .long("verbose")
                 .global(true)
-                .help("Prints additional information about what moss is doing")
+                .help("Prints additional information about what moss is doing, repeat (-vv) to trace everything")
+                .action(ArgAction::Count),
+        )
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .global(true)
+                .help("Suppress progress output & warnings")
+                .conflicts_with("verbose")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no-progress")
+                .long("no-progress")
+                .global(true)
+                .help("Report progress as plain lines rather than redrawn bars, as when not on a terminal")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("log-file")
+                .long("log-file")
+                .global(true)
+                .help("Also append logs to this file, i.e. for unattended runs")
+                .action(ArgAction::Set)
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("journald")
+                .long("journald")
+                .global(true)
+                .help("Also send logs to the systemd journal")
                 .action(ArgAction::SetTrue),
         )
         .arg(
@@ -51,36 +108,146 @@ fn command() -> Command {
                 .action(ArgAction::Set)
                 .value_parser(clap::value_parser!(PathBuf)),
         )
+        .arg(
+            Arg::new("offline")
+                .long("offline")
+                .global(true)
+                .help("Forbid network access, only using cached indices & packages")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .global(true)
+                .help("Output format, json prints a versioned document for scripts instead")
+                .action(ArgAction::Set)
+                .value_parser(PossibleValuesParser::new(["text", "json"])),
+        )
+        .arg(
+            Arg::new("limit-rate")
+                .long("limit-rate")
+                .global(true)
+                .help("Limit the aggregate download rate in bytes per second, i.e. 2M")
+                .action(ArgAction::Set)
+                .value_parser(clap::value_parser!(request::Rate)),
+        )
+        .arg(
+            Arg::new("read-only")
+                .long("read-only")
+                .global(true)
+                .help("Never write to the root, even with write access")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("ignore-disk-space")
+                .long("ignore-disk-space")
+                .global(true)
+                .help("Proceed even if a transaction appears not to fit on disk")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("skip-triggers")
+                .long("skip-triggers")
+                .global(true)
+                .help("Don't run triggers, i.e. to recover from one that keeps failing")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no-verify")
+                .long("no-verify")
+                .global(true)
+                .help("Install packages without verifying their signatures")
+                .action(ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("yes")
                 .short('y')
                 .long("yes-all")
+                .visible_alias("yes")
                 .global(true)
                 .help("Assume yes for all questions")
                 .action(ArgAction::SetTrue),
         )
         .arg_required_else_help(true)
+        .subcommand(autoremove::command())
+        .subcommand(boot::command())
+        .subcommand(cache::command())
+        .subcommand(config::command())
+        .subcommand(db::command())
+        .subcommand(diff::command())
+        .subcommand(export::command())
         .subcommand(extract::command())
+        .subcommand(graph::command())
+        .subcommand(history::command())
+        .subcommand(hold::command())
         .subcommand(index::command())
         .subcommand(info::command())
         .subcommand(inspect::command())
         .subcommand(install::command())
         .subcommand(list::command())
+        .subcommand(mark::command())
+        .subcommand(provides::command())
+        .subcommand(query::command())
+        .subcommand(rdepends::command())
+        .subcommand(refresh::command())
         .subcommand(remove::command())
         .subcommand(repo::command())
         .subcommand(search::command())
+        .subcommand(shell::command())
         .subcommand(state::command())
         .subcommand(sync::command())
-        .subcommand(version::command())
+        .subcommand(transaction::command())
+        .subcommand(hold::unhold_command())
+        .subcommand(version::command());
+
+    #[cfg(feature = "service")]
+    let command = command.subcommand(service::command());
+
+    command
+}
+
+/// `command` listing the external subcommands after its help. Finding
+/// them scans `PATH`, so this is only done when the help is shown
+fn with_external_help(command: Command) -> Command {
+    match external::help() {
+        Some(help) => command.after_help(help),
+        None => command,
+    }
 }
 
 /// Process all CLI arguments
 pub fn process() -> Result<(), Error> {
     let args = replace_aliases(env::args());
-    let matches = command().get_matches_from(args);
+    let cli = command();
+    let matches = match cli.clone().try_get_matches_from(&args) {
+        Ok(matches) => matches,
+        Err(error)
+            if matches!(
+                error.kind(),
+                clap::error::ErrorKind::DisplayHelp | clap::error::ErrorKind::DisplayHelpOnMissingArgumentOrSubcommand
+            ) =>
+        {
+            with_external_help(cli.clone()).get_matches_from(args)
+        }
+        Err(error) => error.exit(),
+    };
+
+    if matches.get_one::<String>("format").map(String::as_str) == Some("json") || is_json_alias(&matches) {
+        output::set_format(output::Format::Json);
+    }
+
+    logging::init(logging::Options {
+        verbosity: matches.get_count("verbose"),
+        quiet: matches.get_flag("quiet"),
+        file: matches.get_one::<PathBuf>("log-file").map(PathBuf::as_path),
+        journald: matches.get_flag("journald"),
+    })?;
+    // Progress would garble the JSON document
+    tui::set_quiet(matches.get_flag("quiet") || output::is_json());
+    tui::set_plain(matches.get_flag("no-progress"));
 
     // Print the version, but not if the user is using the version subcommand
-    if matches.get_flag("verbose") {
+    if matches.get_count("verbose") > 0 {
         if let Some(command) = matches.subcommand_name() {
             if command != "version" {
                 version::print();
@@ -91,26 +258,106 @@ pub fn process() -> Result<(), Error> {
     let root = matches.get_one::<PathBuf>("root").unwrap();
     let cache = matches.get_one::<PathBuf>("cache");
 
+    // Anything we don't know of may be provided by an external `moss-*` command
+    if let Some((name, args)) = matches.subcommand() {
+        if cli.find_subcommand(name).is_none() {
+            return external::handle(name, args, &matches).map_err(Error::External);
+        }
+    }
+
     // Make async runtime available to all of moss
     let _guard = runtime::init();
 
-    let mut installation = Installation::open(root)?;
-    if let Some(dir) = cache {
+    // A root being bootstrapped needn't exist yet
+    if install::is_bootstrap(&matches) {
+        fs::create_dir_all(root).map_err(|error| Error::CreateRoot(root.clone(), error))?;
+    }
+
+    let mut installation = if matches.get_flag("read-only") {
+        Installation::open_read_only(root)?
+    } else {
+        Installation::open(root)?
+    };
+
+    let config = ::config::Manager::system(&installation.root, "moss");
+    let settings = settings::Settings::load(&config);
+
+    if let Some(dir) = cache.or(settings.cache_dir.as_ref()) {
         installation = installation.with_cache_dir(dir)?;
     }
 
+    if let Some(color) = settings.color {
+        tui::set_color(color.into());
+    }
+    if let Some(concurrency) = settings.network_concurrency {
+        request::set_concurrency(concurrency);
+    }
+    if let Some(retries) = settings.network_retries {
+        request::set_retries(retries);
+    }
+    let limit_rate = matches.get_one::<request::Rate>("limit-rate").copied();
+    request::set_rate_limit(limit_rate.or(settings.limit_rate));
+    request::set_host_rate_limit(settings.limit_rate_per_host);
+    if let Some(workers) = settings.unpack_workers {
+        client::cache::set_unpack_workers(workers);
+    }
+
+    let network = config.load::<request::Settings>();
+    request::configure(&network)?;
+
+    // Offline if requested or configured for the root
+    request::set_offline(matches.get_flag("offline") || settings.offline.unwrap_or_default());
+
+    space::set_ignored(matches.get_flag("ignore-disk-space"));
+    postblit::set_skipped(matches.get_flag("skip-triggers"));
+    signature::set_verify(!matches.get_flag("no-verify"));
+
+    // Configured roots never prompt for confirmation
+    prompt::set_assume_yes(settings.assume_yes.unwrap_or_default());
+
+    // Fail early, rather than with some IO error halfway through
+    if installation.read_only() && requires_write_access(&matches) {
+        let command = subcommand_path(&matches).join(" ");
+
+        return Err(if matches.get_flag("read-only") {
+            Error::ReadOnly(command)
+        } else {
+            Error::RequiresPrivileges(command, installation.root)
+        });
+    }
+
     match matches.subcommand() {
+        Some(("autoremove", args)) => autoremove::handle(args, installation).map_err(Error::Autoremove),
+        Some(("boot", args)) => boot::handle(args, installation).map_err(Error::Boot),
+        Some(("cache", args)) => cache::handle(args, installation).map_err(Error::Cache),
+        Some(("config", args)) => config::handle(args, installation).map_err(Error::Config),
+        Some(("db", args)) => db::handle(args, installation).map_err(Error::Db),
+        Some(("diff", args)) => diff::handle(args).map_err(Error::Diff),
+        Some(("export", args)) => export::handle(args, installation).map_err(Error::Export),
         Some(("extract", args)) => extract::handle(args).map_err(Error::Extract),
+        Some(("graph", args)) => graph::handle(args, installation).map_err(Error::Graph),
+        Some(("history", args)) => history::handle(args, installation).map_err(Error::History),
+        Some(("hold", args)) => hold::handle(args, installation).map_err(Error::Hold),
         Some(("index", args)) => index::handle(args).map_err(Error::Index),
         Some(("info", args)) => info::handle(args, installation).map_err(Error::Info),
         Some(("inspect", args)) => inspect::handle(args).map_err(Error::Inspect),
         Some(("install", args)) => install::handle(args, installation).map_err(Error::Install),
         Some(("list", args)) => list::handle(args, installation).map_err(Error::List),
+        Some(("provides", args)) => provides::handle(args, installation).map_err(Error::Provides),
+        Some(("query", args)) => query::handle(args, installation).map_err(Error::Query),
+        Some(("rdepends", args)) => rdepends::handle(args, installation).map_err(Error::Rdepends),
+        Some(("refresh", args)) => refresh::handle(args, installation).map_err(Error::Refresh),
+        Some(("mark", args)) => mark::handle(args, installation).map_err(Error::Mark),
         Some(("remove", args)) => remove::handle(args, installation).map_err(Error::Remove),
         Some(("repo", args)) => repo::handle(args, installation).map_err(Error::Repo),
         Some(("search", args)) => search::handle(args, installation).map_err(Error::Search),
+        Some(("shell", args)) => shell::handle(args, installation).map_err(Error::Shell),
         Some(("state", args)) => state::handle(args, installation).map_err(Error::State),
         Some(("sync", args)) => sync::handle(args, installation).map_err(Error::Sync),
+        Some(("transaction", args)) => transaction::handle(args, installation).map_err(Error::Transaction),
+        #[cfg(feature = "service")]
+        Some(("system-service", args)) => service::handle(args, installation).map_err(Error::Service),
+        Some(("unhold", args)) => hold::handle_unhold(args, installation).map_err(Error::Hold),
         Some(("version", args)) => {
             version::handle(args);
             Ok(())
@@ -123,6 +370,85 @@ pub fn process() -> Result<(), Error> {
     }
 }
 
+/// Whether the invoked subcommand modifies the installation
+fn requires_write_access(matches: &ArgMatches) -> bool {
+    match matches.subcommand() {
+        Some((
+            "autoremove" | "install" | "mark" | "remove" | "shell" | "sync" | "system-service" | "transaction"
+            | "unhold",
+            _,
+        )) => true,
+        // Listing holds is fine
+        Some(("hold", args)) => args.contains_id("NAME"),
+        Some(("boot", args)) => args.subcommand_name() == Some("set-default"),
+        Some(("cache", args)) => matches!(args.subcommand_name(), Some("clean" | "prune")),
+        Some(("config", args)) => matches!(args.subcommand_name(), Some("set" | "unset")),
+        Some(("db", args)) => matches!(args.subcommand_name(), Some("rebuild" | "vacuum")),
+        Some(("history", args)) => args.subcommand_name() == Some("undo"),
+        Some(("refresh", args)) => !args.get_flag("status") && !args.contains_id("systemd-units"),
+        Some(("repo", args)) => !matches!(args.subcommand_name(), Some("info" | "list")),
+        Some(("state", args)) => !matches!(args.subcommand_name(), Some("active" | "describe" | "list")),
+        _ => false,
+    }
+}
+
+/// Names of the invoked (nested) subcommands, i.e. `["repo", "add"]`
+fn subcommand_path(matches: &ArgMatches) -> Vec<&str> {
+    let mut path = vec![];
+    let mut current = matches;
+
+    while let Some((name, args)) = current.subcommand() {
+        path.push(name);
+        current = args;
+    }
+
+    path
+}
+
+/// Arguments shared by all commands that mutate the root, allowing
+/// the computed transaction to be previewed instead of applied
+fn dry_run_args() -> [Arg; 2] {
+    [
+        Arg::new("dry-run")
+            .long("dry-run")
+            .help("Print the computed transaction without applying it")
+            .action(ArgAction::SetTrue),
+        json_arg(),
+    ]
+}
+
+/// Hidden `--json` alias of the global `--format json`, see [`is_json_alias`]
+fn json_arg() -> Arg {
+    Arg::new("json").long("json").hide(true).action(ArgAction::SetTrue)
+}
+
+/// Whether `--json` was passed to any of the invoked (nested) subcommands
+fn is_json_alias(matches: &ArgMatches) -> bool {
+    let mut current = matches;
+
+    while let Some((_, args)) = current.subcommand() {
+        if args.try_get_one::<bool>("json").ok().flatten() == Some(&true) {
+            return true;
+        }
+        current = args;
+    }
+
+    false
+}
+
+/// Override for transactions refused by a check, see [`moss::client::check`]
+fn skip_checks_arg() -> Arg {
+    Arg::new("skip-checks")
+        .long("skip-checks")
+        .help("Apply the transaction even if a check refuses it")
+        .action(ArgAction::SetTrue)
+}
+
+/// The requested [`plan::Format`] if this is a dry run
+fn dry_run(args: &ArgMatches) -> Option<plan::Format> {
+    args.get_flag("dry-run").then(output::format)
+}
+
 fn replace_aliases(args: env::Args) -> Vec<String> {
     const ALIASES: &[(&str, &[&str])] = &[
         ("li", &["list", "installed"]),
@@ -137,6 +463,7 @@ fn replace_aliases(args: env::Args) -> Vec<String> {
         ("it", &["install"]),
         ("rm", &["remove"]),
         ("up", &["sync"]),
+        ("upgrade", &["sync", "--update"]),
     ];
 
     let mut args = args.collect::<Vec<_>>();
@@ -156,24 +483,75 @@ fn replace_aliases(args: env::Args) -> Vec<String> {
 
 #[derive(Debug, Error)]
 pub enum Error {
+    #[error("history")]
+    History(#[from] history::Error),
+
     #[error("index")]
     Index(#[from] index::Error),
 
     #[error("info")]
     Info(#[from] info::Error),
 
+    #[error("graph")]
+    Graph(#[from] graph::Error),
+
+    #[error("hold")]
+    Hold(#[from] hold::Error),
+
     #[error("install")]
     Install(#[from] install::Error),
 
     #[error("list")]
     List(#[from] list::Error),
 
+    #[error("mark")]
+    Mark(#[from] mark::Error),
+
     #[error("inspect")]
     Inspect(#[from] inspect::Error),
 
+    #[error("autoremove")]
+    Autoremove(#[from] autoremove::Error),
+
+    #[error("boot")]
+    Boot(#[from] boot::Error),
+
+    #[error("cache")]
+    Cache(#[from] cache::Error),
+
+    #[error("logging")]
+    Logging(#[from] logging::Error),
+
+    #[error("config")]
+    Config(#[from] config::Error),
+
+    #[error("db")]
+    Db(#[from] db::Error),
+
+    #[error("diff")]
+    Diff(#[from] diff::Error),
+
+    #[error("export")]
+    Export(#[from] export::Error),
+
     #[error("extract")]
     Extract(#[from] extract::Error),
 
+    #[error("external command")]
+    External(#[from] external::Error),
+
+    #[error("provides")]
+    Provides(#[from] provides::Error),
+
+    #[error("query")]
+    Query(#[from] query::Error),
+
+    #[error("rdepends")]
+    Rdepends(#[from] rdepends::Error),
+
+    #[error("refresh")]
+    Refresh(#[from] refresh::Error),
+
     #[error("remove")]
     Remove(#[from] remove::Error),
 
@@ -183,12 +561,34 @@ pub enum Error {
     #[error("search")]
     Search(#[from] search::Error),
 
+    #[error("shell")]
+    Shell(#[from] shell::Error),
+
     #[error("state")]
     State(#[from] state::Error),
 
     #[error("sync")]
     Sync(#[from] sync::Error),
 
+    #[error("transaction")]
+    Transaction(#[from] transaction::Error),
+
+    #[cfg(feature = "service")]
+    #[error("system service")]
+    Service(#[from] service::Error),
+
     #[error("installation")]
     Installation(#[from] installation::Error),
+
+    #[error("create root {0:?}")]
+    CreateRoot(PathBuf, #[source] io::Error),
+
+    #[error("network")]
+    Network(#[from] request::Error),
+
+    #[error("`moss {0}` requires write access to {1:?}, try again with sudo")]
+    RequiresPrivileges(String, PathBuf),
+
+    #[error("`moss {0}` writes to the root, which --read-only forbids")]
+    ReadOnly(String),
 }
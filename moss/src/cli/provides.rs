@@ -0,0 +1,78 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use clap::{arg, ArgMatches, Command};
+use thiserror::Error;
+
+use moss::{
+    client::{self, Client},
+    environment, Installation,
+};
+use tui::Styled;
+
+pub fn command() -> Command {
+    Command::new("provides")
+        .about("Find packages owning a file")
+        .long_about(
+            "List the installed or cached packages owning each file path, \
+             given either absolute or relative to /usr",
+        )
+        .arg(arg!(<PATH> ... "File paths to query").value_parser(clap::value_parser!(String)))
+}
+
+/// Print the owners of each path
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let paths = args
+        .get_many::<String>("PATH")
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+    let client = Client::new(environment::NAME, installation)?;
+
+    let mut found = false;
+
+    for path in paths {
+        let owners = client.owners(path)?;
+
+        if owners.is_empty() {
+            eprintln!("{} no installed or cached package owns {path}", "Warning:".yellow());
+            continue;
+        }
+        found = true;
+
+        for package in owners {
+            let status = if package.flags.installed {
+                "installed".to_string()
+            } else {
+                client
+                    .repository_for(&package)
+                    .map(ToString::to_string)
+                    .unwrap_or_else(|| "cached".to_string())
+            };
+
+            println!(
+                "{path}: {} {}-{} {}",
+                package.meta.name.to_string().bold(),
+                package.meta.version_identifier,
+                package.meta.source_release,
+                format!("[{status}]").dim(),
+            );
+        }
+    }
+
+    if !found {
+        return Err(Error::NotFound);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("no owning package found")]
+    NotFound,
+    #[error("client")]
+    Client(#[from] client::Error),
+}
@@ -0,0 +1,171 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use clap::{arg, ArgMatches, Command};
+use serde::Serialize;
+use thiserror::Error;
+
+use moss::{
+    client::{self, Client},
+    environment, output, package, Installation, Provider,
+};
+use tui::{HumanBytes, Styled};
+
+pub fn command() -> Command {
+    Command::new("query")
+        .visible_alias("q")
+        .about("Query the package registry")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("providers")
+                .about("List all packages providing a capability")
+                .long_about(
+                    "List the installed and available packages providing a capability, such as \
+                     binary(cc), in the order they're considered when it's ambiguous. Shared \
+                     libraries match any ISA unless given, i.e. soname(libz.so.1)",
+                )
+                .arg(arg!(<CAPABILITY> "Provider to query").value_parser(clap::value_parser!(String))),
+        )
+        .subcommand(
+            Command::new("largest")
+                .about("List the installed packages taking up the most space")
+                .long_about(
+                    "List the installed packages with the largest installed size, largest first. \
+                     Files shared between packages count towards each of them",
+                )
+                .arg(
+                    arg!(-n --count <COUNT> "Number of packages to list")
+                        .default_value("20")
+                        .value_parser(clap::value_parser!(usize)),
+                ),
+        )
+        .subcommand(super::graph::command())
+}
+
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    match args.subcommand() {
+        Some(("providers", args)) => providers(args, installation),
+        Some(("largest", args)) => largest(args, installation),
+        Some(("graph", args)) => super::graph::handle(args, installation).map_err(Error::Graph),
+        _ => unreachable!(),
+    }
+}
+
+/// Print every candidate for a provider with its source & priority
+fn providers(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let capability = args.get_one::<String>("CAPABILITY").unwrap();
+    let provider = Provider::from_name(capability).map_err(|_| Error::InvalidProvider(capability.clone()))?;
+
+    let client = Client::new(environment::NAME, installation)?;
+
+    let candidates = client
+        .registry
+        .by_provider(&provider, package::Flags::default())
+        .collect::<Vec<_>>();
+
+    if candidates.is_empty() {
+        return Err(Error::NotFound(provider.to_string()));
+    }
+
+    let preferred = client.registry.alternatives().preferred(&provider);
+
+    for package in candidates {
+        let source = if package.flags.installed {
+            "installed".to_string()
+        } else if let Some(id) = client.repository_for(&package) {
+            let priority = client
+                .repository(id)
+                .map(|repo| repo.priority.to_string())
+                .unwrap_or_default();
+            format!("{id} [{priority}]")
+        } else {
+            "cached".to_string()
+        };
+        let preferred = if preferred == Some(&package.meta.name) {
+            format!(" {}", "(preferred)".green())
+        } else {
+            String::default()
+        };
+
+        println!(
+            "{} {}-{} {}{preferred}",
+            package.meta.name.to_string().bold(),
+            package.meta.version_identifier,
+            package.meta.source_release,
+            source.dim(),
+        );
+    }
+
+    Ok(())
+}
+
+/// Print the installed packages with the largest installed size
+fn largest(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let count = *args.get_one::<usize>("count").unwrap();
+
+    let client = Client::new(environment::NAME, installation)?;
+
+    let mut packages = client
+        .registry
+        .list_installed(package::Flags::default())
+        .map(|package| Largest {
+            installed_size: package.meta.installed_size.unwrap_or_default(),
+            name: package.meta.name.to_string(),
+            version: package.meta.version_identifier,
+            release: package.meta.source_release,
+        })
+        .collect::<Vec<_>>();
+    packages.sort_by(|a, b| b.installed_size.cmp(&a.installed_size).then(a.name.cmp(&b.name)));
+    packages.truncate(count);
+
+    if output::is_json() {
+        output::print_json("packages", &packages)?;
+        return Ok(());
+    }
+
+    let width = packages
+        .iter()
+        .map(|package| package.name.len())
+        .max()
+        .unwrap_or_default();
+
+    for package in packages {
+        println!(
+            "{:>10}  {} {}-{}",
+            HumanBytes(package.installed_size).to_string(),
+            format!("{:width$}", package.name).bold(),
+            package.version,
+            package.release.to_string().dim(),
+        );
+    }
+
+    Ok(())
+}
+
+/// An installed package as listed by `moss query largest`
+#[derive(Serialize)]
+struct Largest {
+    name: String,
+    version: String,
+    release: u64,
+    installed_size: u64,
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("invalid provider: {0}")]
+    InvalidProvider(String),
+
+    #[error("no package provides {0}")]
+    NotFound(String),
+
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error("graph")]
+    Graph(#[from] super::graph::Error),
+
+    #[error("json")]
+    Json(#[from] serde_json::Error),
+}
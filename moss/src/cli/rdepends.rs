@@ -0,0 +1,102 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use clap::{arg, ArgMatches, Command};
+use thiserror::Error;
+
+use moss::{
+    client::{self, Client},
+    environment, package, Dependency, Installation, Package, Provider,
+};
+use tui::Styled;
+
+pub fn command() -> Command {
+    Command::new("rdepends")
+        .visible_alias("rd")
+        .about("Show reverse dependencies")
+        .long_about(
+            "List the installed and available packages depending on a package \
+             (through any of its providers) or on a provider such as soname(libz.so.1(x86_64))",
+        )
+        .arg(arg!(<NAME> "Package name or provider to query").value_parser(clap::value_parser!(String)))
+        .arg(arg!(-i --installed "Only list installed packages"))
+}
+
+/// Handle execution of `moss rdepends`
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let name = args.get_one::<String>("NAME").unwrap();
+    let only_installed = args.get_flag("installed");
+
+    let lookup = Provider::from_name(name).map_err(|_| Error::InvalidName(name.clone()))?;
+
+    let client = Client::new(environment::NAME, installation)?;
+
+    // Depending on any provider of the matched packages counts, i.e.
+    // a `soname(..)` dependency on one of its libraries
+    let providers = client
+        .registry
+        .by_provider(&lookup, package::Flags::default())
+        .flat_map(|package| package.meta.providers)
+        .chain(Some(lookup.clone()))
+        .collect::<BTreeSet<_>>();
+
+    let dependents = |flags: package::Flags| {
+        let mut found = BTreeMap::<String, (Package, BTreeSet<String>)>::new();
+
+        for provider in &providers {
+            let dependency = Dependency {
+                kind: provider.kind,
+                name: provider.name.clone(),
+            };
+
+            for package in client.registry.by_dependency(&dependency, flags) {
+                // First (highest priority) candidate of each name wins
+                found
+                    .entry(package.meta.name.to_string())
+                    .or_insert_with(|| (package, BTreeSet::new()))
+                    .1
+                    .insert(provider.to_string());
+            }
+        }
+
+        found
+    };
+
+    let installed = dependents(package::Flags::new().with_installed());
+    print_section("Installed", &installed);
+
+    if !only_installed {
+        let available = dependents(package::Flags::new().with_available());
+        println!();
+        print_section("Available", &available);
+    }
+
+    Ok(())
+}
+
+fn print_section(title: &str, packages: &BTreeMap<String, (Package, BTreeSet<String>)>) {
+    println!("{} ({})", title.bold(), packages.len());
+
+    let width = packages.keys().map(String::len).max().unwrap_or_default();
+
+    for (name, (package, via)) in packages {
+        println!(
+            " {} {}-{} {}",
+            format!("{name:width$}").bold(),
+            package.meta.version_identifier,
+            package.meta.source_release,
+            format!("via {}", via.iter().cloned().collect::<Vec<_>>().join(", ")).dim(),
+        );
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("invalid package name: {0}")]
+    InvalidName(String),
+    #[error("client")]
+    Client(#[from] client::Error),
+}
@@ -0,0 +1,243 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use moss::{
+    repository::{
+        self,
+        refresh::{Age, Metered, Outcome, Schedule},
+    },
+    runtime, Installation,
+};
+use thiserror::Error;
+use tui::Styled;
+
+pub fn command() -> Command {
+    Command::new("refresh")
+        .about("Refresh repository indices")
+        .long_about(
+            "Refresh all repository indices, skipping the rebuild of any index that hasn't changed. \
+             Refreshes can be scheduled by running as a daemon, or through generated systemd units",
+        )
+        .arg(
+            Arg::new("daemon")
+                .long("daemon")
+                .help("Keep running, refreshing on a jittered schedule")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["scheduled", "status", "systemd-units"]),
+        )
+        .arg(
+            Arg::new("scheduled")
+                .long("scheduled")
+                .help("Refresh once as a scheduled run, skipped on metered connections")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["status", "systemd-units"]),
+        )
+        .arg(
+            Arg::new("allow-metered")
+                .long("allow-metered")
+                .help("Run scheduled refreshes on metered connections too")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("interval")
+                .long("interval")
+                .help("Hours between scheduled refreshes")
+                .action(ArgAction::Set)
+                .default_value("6")
+                .value_parser(clap::value_parser!(u64).range(1..)),
+        )
+        .arg(
+            Arg::new("jitter")
+                .long("jitter")
+                .help("Maximum random delay of scheduled refreshes, in minutes")
+                .action(ArgAction::Set)
+                .default_value("30")
+                .value_parser(clap::value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("status")
+                .long("status")
+                .help("Show when each repository was last refreshed")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("systemd-units"),
+        )
+        .arg(
+            Arg::new("systemd-units")
+                .long("systemd-units")
+                .value_name("DIR")
+                .help("Write a systemd service & timer for scheduled refreshes to DIR")
+                .action(ArgAction::Set)
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+}
+
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let allow_metered = args.get_flag("allow-metered");
+    let schedule = Schedule {
+        interval: Duration::from_secs(*args.get_one::<u64>("interval").unwrap() * 60 * 60),
+        jitter: Duration::from_secs(*args.get_one::<u64>("jitter").unwrap() * 60),
+    };
+
+    if args.get_flag("status") {
+        return status(installation);
+    }
+    if let Some(dir) = args.get_one::<PathBuf>("systemd-units") {
+        return systemd_units(dir, &installation, &schedule);
+    }
+
+    if args.get_flag("daemon") {
+        loop {
+            // Failures are reported & retried on the next run
+            if let Err(error) = scheduled(&installation, allow_metered) {
+                eprintln!("{} {error}", "Error".red());
+            }
+
+            let delay = schedule.next_delay();
+            println!("Next refresh in {}m", delay.as_secs() / 60);
+            thread::sleep(delay);
+        }
+    }
+
+    if args.get_flag("scheduled") {
+        return scheduled(&installation, allow_metered);
+    }
+
+    refresh(&installation)
+}
+
+/// Refresh unless on a metered connection
+fn scheduled(installation: &Installation, allow_metered: bool) -> Result<(), Error> {
+    if !allow_metered && Metered::detect() == Metered::Yes {
+        println!("Skipping refresh on metered connection");
+        return Ok(());
+    }
+
+    refresh(installation)
+}
+
+/// Refresh each repository, continuing past failures so one
+/// unreachable mirror doesn't hold back the others
+fn refresh(installation: &Installation) -> Result<(), Error> {
+    let config = config::Manager::system(&installation.root, "moss");
+    // Reload so config changes are picked up between daemon runs
+    let manager = repository::Manager::system(config, installation.clone())?;
+
+    let ids = manager.list().map(|(id, _)| id.clone()).collect::<Vec<_>>();
+    let mut failed = 0;
+
+    for id in ids {
+        match runtime::block_on(manager.refresh(&id)) {
+            Ok(Outcome::Updated) => println!("{} {id}", "Refreshed".green()),
+            Ok(Outcome::Unchanged) => println!("{} {id}{}", "Refreshed".green(), " (unchanged)".dim()),
+            Err(error) => {
+                failed += 1;
+                eprintln!("{} {id}: {error}", "Failed".red());
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(Error::Failed(failed));
+    }
+
+    Ok(())
+}
+
+/// Print the refresh status of all repositories
+fn status(installation: Installation) -> Result<(), Error> {
+    let config = config::Manager::system(&installation.root, "moss");
+    let manager = repository::Manager::system(config, installation)?;
+
+    for (id, _) in manager.list() {
+        let status = manager.refresh_status(id)?;
+
+        let refreshed = match status.last_success() {
+            Some(date) => format!("refreshed {}", Age::since(date)),
+            None => "never refreshed".to_string(),
+        };
+        println!("{} {}", id.to_string().bold(), refreshed);
+
+        if let Some(error) = &status.error {
+            let attempted = status
+                .last_attempt()
+                .map(|date| format!(" {}", Age::since(date)))
+                .unwrap_or_default();
+            println!("  {}{attempted}: {error}", "Failed".red());
+        }
+    }
+
+    Ok(())
+}
+
+/// Write a oneshot service & timer running scheduled refreshes of `installation`
+fn systemd_units(dir: &Path, installation: &Installation, schedule: &Schedule) -> Result<(), Error> {
+    let exe = env::current_exe().map_err(Error::CurrentExe)?;
+
+    let service = format!(
+        "[Unit]
+Description=Refresh moss repository indices
+Wants=network-online.target
+After=network-online.target
+
+[Service]
+Type=oneshot
+ExecStart={} -D {} refresh --scheduled
+Nice=19
+IOSchedulingClass=idle
+",
+        exe.display(),
+        installation.root.display(),
+    );
+    let timer = format!(
+        "[Unit]
+Description=Scheduled refresh of moss repository indices
+
+[Timer]
+OnBootSec=15min
+OnUnitActiveSec={}s
+RandomizedDelaySec={}s
+Persistent=true
+
+[Install]
+WantedBy=timers.target
+",
+        schedule.interval.as_secs(),
+        schedule.jitter.as_secs(),
+    );
+
+    fs::create_dir_all(dir).map_err(Error::WriteUnit)?;
+    for (name, content) in [("moss-refresh.service", service), ("moss-refresh.timer", timer)] {
+        let path = dir.join(name);
+        fs::write(&path, content).map_err(Error::WriteUnit)?;
+        println!("{} {}", "Wrote".green(), path.display());
+    }
+
+    println!();
+    println!("Enable with `systemctl enable --now moss-refresh.timer`");
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("repo manager")]
+    RepositoryManager(#[from] repository::manager::Error),
+
+    #[error("{0} repositories failed to refresh")]
+    Failed(usize),
+
+    #[error("locate moss executable")]
+    CurrentExe(#[source] io::Error),
+
+    #[error("write systemd unit")]
+    WriteUnit(#[source] io::Error),
+}
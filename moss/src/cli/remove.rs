@@ -2,31 +2,31 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use clap::{arg, ArgMatches, Command};
-use itertools::{Either, Itertools};
-use std::collections::BTreeSet;
+use clap::{arg, ArgAction, ArgMatches, Command};
 use thiserror::Error;
 
 use moss::{
-    client::{self, Client},
-    environment,
-    package::Flags,
-    registry::transaction,
-    state::Selection,
-    Installation, Provider,
-};
-use tui::{
-    dialoguer::{theme::ColorfulTheme, Confirm},
-    pretty::autoprint_columns,
-    Styled,
+    client::{
+        self,
+        plan::{self, Plan},
+        Client,
+    },
+    environment, messages, output, prompt, Installation, Package, Provider,
 };
+use tui::{pretty::autoprint_columns, Styled};
 
 pub fn command() -> Command {
     Command::new("remove")
         .visible_alias("rm")
         .about("Remove packages")
         .long_about("Remove packages by name")
-        .arg(arg!(<NAME> ... "packages to install").value_parser(clap::value_parser!(String)))
+        .arg(arg!(<NAME> ... "packages to remove").value_parser(clap::value_parser!(String)))
+        .arg(
+            arg!(--cascade "Also remove all packages that depend on the packages being removed")
+                .action(ArgAction::SetTrue),
+        )
+        .args(super::dry_run_args())
+        .arg(super::skip_checks_arg())
 }
 
 /// Handle execution of `moss remove`
@@ -38,97 +38,70 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
         .map(|name| Provider::from_name(name).unwrap())
         .collect::<Vec<_>>();
     let yes = *args.get_one::<bool>("yes").unwrap();
-
-    // Grab a client for the target, enumerate packages
-    let client = Client::new(environment::NAME, installation)?;
-
-    let installed = client.registry.list_installed(Flags::default()).collect::<Vec<_>>();
-    let installed_ids = installed.iter().map(|p| p.id.clone()).collect::<BTreeSet<_>>();
-
-    // Separate packages between installed / not installed (or invalid)
-    let (for_removal, not_installed): (Vec<_>, Vec<_>) = pkgs.iter().partition_map(|provider| {
-        installed
-            .iter()
-            .find(|i| i.meta.providers.contains(provider))
-            .map(|i| Either::Left(i.id.clone()))
-            .unwrap_or(Either::Right(provider.clone()))
-    });
-
-    // Bail if there's packages not installed
-    // TODO: Add error hookups
-    if !not_installed.is_empty() {
-        println!("Missing packages in lookup: {:?}", not_installed);
-        return Err(Error::NotImplemented);
+    let cascade = args.get_flag("cascade");
+
+    // Grab a client for the target
+    let mut client = Client::new(environment::NAME, installation)?;
+    client.skip_checks(args.get_flag("skip-checks"));
+
+    let resolution = client::remove::resolve(&client, &pkgs)?;
+    let removed = &resolution.removed;
+
+    // Refuse to break the system unless the user opted in
+    if !resolution.dependents.is_empty() && !cascade {
+        // Keep stdout a valid document, the error names the dependents regardless
+        if !output::is_json() {
+            println!("{}", messages::get("required-by-removed"));
+            println!();
+            autoprint_columns(&resolution.dependents);
+            println!();
+            println!(
+                "{}",
+                messages::format("use-cascade", &[("flag", "--cascade".bold().to_string().into())])
+            );
+        }
+
+        return Err(Error::RequiredBy(
+            resolution.dependents.iter().map(|p| p.meta.name.to_string()).collect(),
+        ));
     }
 
-    // Add all installed packages to transaction
-    let mut transaction = client
-        .registry
-        .transaction_with_installed(installed_ids.clone().into_iter().collect())?;
-
-    // Remove all pkgs for removal
-    transaction.remove(for_removal);
-
-    // Finalized tx has all reverse deps removed
-    let finalized = transaction.finalize().cloned().collect::<BTreeSet<_>>();
-
-    // Resolve all removed packages, where removed is (installed - finalized)
-    let removed = client.resolve_packages(installed_ids.difference(&finalized))?;
-
-    println!("The following package(s) will be removed:");
-    println!();
-    autoprint_columns(&removed);
-    println!();
+    if let Some(format) = super::dry_run(args) {
+        Plan::new(&client, &[] as &[Package], removed).print(format)?;
+        return Ok(());
+    }
 
-    let result = if yes {
-        true
+    if output::is_json() {
+        // The plan stands in for the human readable summary
+        Plan::new(&client, &[] as &[Package], removed).print(plan::Format::Json)?;
     } else {
-        Confirm::with_theme(&ColorfulTheme::default())
-            .with_prompt(" Do you wish to continue? ")
-            .default(false)
-            .interact()?
-    };
+        println!("{}", messages::get("will-remove"));
+        println!();
+        autoprint_columns(removed);
+        println!();
+        Plan::new(&client, &[] as &[Package], removed).print_sizes();
+        println!();
+    }
+
+    let result = prompt::confirm(yes)?;
     if !result {
         return Err(Error::Cancelled);
     }
 
     // Print each package to stdout
-    for package in removed {
-        println!("{} {}", "Removed".red(), package.meta.name.to_string().bold(),);
+    if !output::is_json() {
+        for package in removed {
+            println!(
+                "{} {}",
+                messages::get("removed").red(),
+                package.meta.name.to_string().bold()
+            );
+        }
     }
 
-    // Map finalized state to a [`Selection`] by referencing
-    // it's value from the previous state
-    let new_state_pkgs = {
-        let previous_selections = match client.installation.active_state {
-            Some(id) => client.state_db.get(id)?.selections,
-            None => vec![],
-        };
-
-        finalized
-            .into_iter()
-            .map(|id| {
-                previous_selections
-                    .iter()
-                    .find(|s| s.package == id)
-                    .cloned()
-                    // Should be unreachable since new state from removal
-                    // is always a subset of the previous state
-                    .unwrap_or_else(|| {
-                        eprintln!("Unreachable: previous selection not found during removal for package {id:?}, marking as not explicit");
-
-                        Selection {
-                            package: id,
-                            explicit: false,
-                            reason: None,
-                        }
-                    })
-            })
-            .collect::<Vec<_>>()
-    };
-
     // Apply state
-    client.new_state(&new_state_pkgs, "Remove")?;
+    let selections = client::remove::selections(&client, &resolution)?;
+    client.new_state(&selections, "Remove")?;
 
     Ok(())
 }
@@ -138,17 +111,17 @@ pub enum Error {
     #[error("cancelled")]
     Cancelled,
 
-    #[error("Not yet implemented")]
-    NotImplemented,
+    #[error("packages are required by: {}", .0.join(", "))]
+    RequiredBy(Vec<String>),
+
+    #[error("plan")]
+    Plan(#[from] plan::Error),
 
     #[error("client")]
     Client(#[from] client::Error),
 
-    #[error("transaction")]
-    Transaction(#[from] transaction::Error),
-
-    #[error("db")]
-    DB(#[from] moss::db::Error),
+    #[error("remove")]
+    Remove(#[from] client::remove::Error),
 
     #[error("io")]
     Io(#[from] std::io::Error),
@@ -2,23 +2,33 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::process;
+use std::{fmt, process};
 
+use chrono::Utc;
 use clap::{arg, Arg, ArgAction, ArgMatches, Command};
 use itertools::Itertools;
 use moss::{
-    repository::{self, Priority},
-    runtime, Installation, Repository,
+    output,
+    repository::{self, refresh::Age, Priority, Quota},
+    runtime,
+    signature::PublicKey,
+    Installation, Repository,
 };
+use serde::Serialize;
 use thiserror::Error;
+use tui::{HumanBytes, Styled};
 use url::Url;
 
+const COLUMN_WIDTH: usize = 12;
+
 /// Control flow for the subcommands
 enum Action {
     // Root
     List,
-    // Root, Id, Url, Comment
-    Add(String, Url, String, Priority),
+    // Root, Id, Repository
+    Add(String, Repository),
+    // Root, Id
+    Info(String),
     // Root, Id
     Remove(String),
     // Root, Id
@@ -51,8 +61,34 @@ pub fn command() -> Command {
                         .action(ArgAction::Set)
                         .default_value("0")
                         .value_parser(clap::value_parser!(u64)),
+                )
+                .arg(
+                    Arg::new("quota")
+                        .long("quota")
+                        .help("Monthly download quota in bytes")
+                        .action(ArgAction::Set)
+                        .value_parser(clap::value_parser!(u64)),
+                )
+                .arg(
+                    Arg::new("quota-confirm")
+                        .long("quota-confirm")
+                        .help("Require confirmation once the quota is exceeded, even with --yes-all")
+                        .requires("quota")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("trusted-key")
+                        .long("trusted-key")
+                        .help("Require packages to be signed by this hex encoded public key, may be repeated")
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(PublicKey)),
                 ),
         )
+        .subcommand(
+            Command::new("info")
+                .about("Show repository details and download usage")
+                .arg(arg!(<NAME> "repo name").value_parser(clap::value_parser!(String))),
+        )
         .subcommand(
             Command::new("list")
                 .visible_alias("lr")
@@ -81,10 +117,23 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
     let handler = match args.subcommand() {
         Some(("add", cmd_args)) => Action::Add(
             cmd_args.get_one::<String>("NAME").cloned().unwrap(),
-            cmd_args.get_one::<Url>("URI").cloned().unwrap(),
-            cmd_args.get_one::<String>("comment").cloned().unwrap(),
-            Priority::new(*cmd_args.get_one::<u64>("priority").unwrap()),
+            Repository {
+                description: cmd_args.get_one::<String>("comment").cloned().unwrap(),
+                uri: cmd_args.get_one::<Url>("URI").cloned().unwrap(),
+                priority: Priority::new(*cmd_args.get_one::<u64>("priority").unwrap()),
+                quota: cmd_args.get_one::<u64>("quota").map(|monthly| Quota {
+                    monthly: *monthly,
+                    confirm: cmd_args.get_flag("quota-confirm"),
+                }),
+                trusted_keys: cmd_args
+                    .get_many::<PublicKey>("trusted-key")
+                    .into_iter()
+                    .flatten()
+                    .copied()
+                    .collect(),
+            },
         ),
+        Some(("info", cmd_args)) => Action::Info(cmd_args.get_one::<String>("NAME").cloned().unwrap()),
         Some(("list", _)) => Action::List,
         Some(("remove", cmd_args)) => Action::Remove(cmd_args.get_one::<String>("NAME").cloned().unwrap()),
         Some(("update", cmd_args)) => Action::Update(cmd_args.get_one::<String>("NAME").cloned()),
@@ -94,33 +143,20 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
     // dispatch to runtime handler function
     match handler {
         Action::List => list(installation, config),
-        Action::Add(name, uri, comment, priority) => add(installation, config, name, uri, comment, priority),
+        Action::Add(name, repository) => add(installation, config, name, repository),
+        Action::Info(name) => info(installation, config, name),
         Action::Remove(name) => remove(installation, config, name),
         Action::Update(name) => update(installation, config, name),
     }
 }
 
 // Actual implementation of moss repo add
-fn add(
-    installation: Installation,
-    config: config::Manager,
-    name: String,
-    uri: Url,
-    comment: String,
-    priority: Priority,
-) -> Result<(), Error> {
+fn add(installation: Installation, config: config::Manager, name: String, repository: Repository) -> Result<(), Error> {
     let mut manager = repository::Manager::system(config, installation)?;
 
     let id = repository::Id::new(name);
 
-    manager.add_repository(
-        id.clone(),
-        Repository {
-            description: comment,
-            uri,
-            priority,
-        },
-    )?;
+    manager.add_repository(id.clone(), repository)?;
 
     runtime::block_on(manager.refresh(&id))?;
 
@@ -134,25 +170,105 @@ fn list(installation: Installation, config: config::Manager) -> Result<(), Error
     let manager = repository::Manager::system(config, installation)?;
 
     let configured_repos = manager.list();
+
+    if output::is_json() {
+        let repositories = configured_repos
+            .sorted_by(|(_, a), (_, b)| a.priority.cmp(&b.priority).reverse())
+            .map(|(id, repo)| {
+                Ok(Listed {
+                    id: id.to_string(),
+                    repository: repo,
+                    refreshed: manager.refresh_status(id)?.last_success().map(|date| date.to_rfc3339()),
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        output::print_json("repositories", repositories)?;
+        return Ok(());
+    }
+
     if configured_repos.len() == 0 {
         println!("No repositories have been configured yet");
         return Ok(());
     }
 
     for (id, repo) in configured_repos.sorted_by(|(_, a), (_, b)| a.priority.cmp(&b.priority).reverse()) {
-        println!(" - {} = {} [{}]", id, repo.uri, repo.priority);
+        let refreshed = match manager.refresh_status(id)?.last_success() {
+            Some(date) => format!("refreshed {}", Age::since(date)),
+            None => "never refreshed".to_string(),
+        };
+        println!(" - {} = {} [{}] {}", id, repo.uri, repo.priority, refreshed.dim());
+    }
+
+    Ok(())
+}
+
+/// Show details & download usage of a repository
+fn info(installation: Installation, config: config::Manager, name: String) -> Result<(), Error> {
+    let id = repository::Id::new(name);
+
+    let manager = repository::Manager::system(config, installation)?;
+
+    let Some(repo) = manager.get(&id) else {
+        println!("{id} not found");
+        process::exit(1);
+    };
+    let usage = manager.usage(&id)?;
+    let status = manager.refresh_status(&id)?;
+
+    println!("{}", id.to_string().bold());
+    print_titled("Description", &repo.description);
+    print_titled("URI", &repo.uri);
+    print_titled("Priority", repo.priority);
+    if let Some(quota) = repo.quota {
+        let action = if quota.confirm { "confirm" } else { "warn" };
+        print_titled(
+            "Quota",
+            format!("{} per month ({action} when exceeded)", HumanBytes(quota.monthly)),
+        );
+    }
+    match status.last_success() {
+        Some(date) => print_titled("Refreshed", Age::since(date)),
+        None => print_titled("Refreshed", "never"),
+    }
+    if let Some(error) = &status.error {
+        print_titled("Last error", error.as_str().red());
+    }
+    print_titled("This month", HumanBytes(usage.month(Utc::now())));
+    print_titled("Downloaded", HumanBytes(usage.total()));
+
+    if !usage.months.is_empty() {
+        println!();
+        for (month, bytes) in usage.months.iter().rev() {
+            println!(" - {month} {}", HumanBytes(*bytes));
+        }
     }
 
     Ok(())
 }
 
+/// A repository as listed with `--format json`
+#[derive(Serialize)]
+struct Listed<'a> {
+    id: String,
+    #[serde(flatten)]
+    repository: &'a Repository,
+    /// Time of the last successful refresh, if any
+    refreshed: Option<String>,
+}
+
+/// Print a bold title padded to a fixed column, followed by its value
+fn print_titled(title: &str, value: impl fmt::Display) {
+    let display_width = COLUMN_WIDTH - title.len();
+    println!("{}{:width$} {value}", title.bold(), " ", width = display_width);
+}
+
 /// Update specific repos or all
 fn update(installation: Installation, config: config::Manager, which: Option<String>) -> Result<(), Error> {
     let mut manager = repository::Manager::system(config, installation)?;
 
     runtime::block_on(async {
         match which {
-            Some(repo) => manager.refresh(&repository::Id::new(repo)).await,
+            Some(repo) => manager.refresh(&repository::Id::new(repo)).await.map(|_| ()),
             None => manager.refresh_all().await,
         }
     })?;
@@ -189,4 +305,7 @@ fn remove(installation: Installation, config: config::Manager, repo: String) ->
 pub enum Error {
     #[error("repo manager")]
     RepositoryManager(#[from] repository::manager::Error),
+
+    #[error("json")]
+    Json(#[from] serde_json::Error),
 }
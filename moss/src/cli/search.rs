@@ -2,24 +2,35 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
+use std::collections::BTreeMap;
+
 use clap::builder::NonEmptyStringValueParser;
-use clap::{Arg, ArgMatches, Command};
+use clap::{Arg, ArgAction, ArgMatches, Command};
 
 use moss::client;
-use moss::package::{self, Name};
-use moss::{environment, Client, Installation};
+use moss::package::{self, Keyword, Name, Rank};
+use moss::{environment, output, Client, Installation, Package};
+use serde::Serialize;
 use tui::pretty::{print_columns, ColumnDisplay};
 use tui::Styled;
 
 const ARG_KEYWORD: &str = "KEYWORD";
 const FLAG_INSTALLED: &str = "installed";
+const FLAG_REGEX: &str = "regex";
+const FLAG_FILE: &str = "file";
+const ARG_LIMIT: &str = "limit";
 
 /// Returns the Clap struct for this command.
 pub fn command() -> Command {
     Command::new("search")
         .visible_alias("sr")
         .about("Search packages")
-        .long_about("Search packages by looking into package names and summaries.")
+        .long_about(
+            "Search packages by looking into package names, providers, summaries and \
+             descriptions, or with --file, the packages owning a file path. Matches are \
+             ranked with exact name matches first, followed by name prefixes, other name \
+             matches, providers, summaries and finally descriptions.",
+        )
         .arg(
             Arg::new(ARG_KEYWORD)
                 .required(true)
@@ -33,27 +44,75 @@ pub fn command() -> Command {
                 .num_args(0)
                 .help("Search among installed packages only"),
         )
+        .arg(
+            Arg::new(FLAG_REGEX)
+                .short('r')
+                .long("regex")
+                .action(ArgAction::SetTrue)
+                .help("Treat the keyword as a regular expression"),
+        )
+        .arg(
+            Arg::new(FLAG_FILE)
+                .short('f')
+                .long("file")
+                .action(ArgAction::SetTrue)
+                .conflicts_with(FLAG_REGEX)
+                .help("Search for the installed or cached packages owning the file path KEYWORD"),
+        )
+        .arg(
+            Arg::new(ARG_LIMIT)
+                .short('n')
+                .long("limit")
+                .action(ArgAction::Set)
+                .value_parser(clap::value_parser!(usize))
+                .help("Only show the N most relevant results"),
+        )
 }
 
 pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
     let keyword = args.get_one::<String>(ARG_KEYWORD).unwrap();
     let only_installed = args.get_flag(FLAG_INSTALLED);
+    let limit = args.get_one::<usize>(ARG_LIMIT).copied();
 
     let client = Client::new(environment::NAME, installation)?;
-    let flags = if only_installed {
-        package::Flags::new().with_installed()
+
+    // Packages are reported by every source they're known to,
+    // merge those into a single entry per package
+    let mut matches = BTreeMap::<package::Id, Output>::new();
+
+    if args.get_flag(FLAG_FILE) {
+        let packages = client
+            .owners(keyword)?
+            .into_iter()
+            .filter(|pkg| !only_installed || pkg.flags.installed);
+        merge(&mut matches, &client, packages, None);
     } else {
-        package::Flags::new().with_available()
-    };
+        let keyword = if args.get_flag(FLAG_REGEX) {
+            Keyword::regex(keyword)?
+        } else {
+            Keyword::substring(keyword)
+        };
+        // Only matches are kept around, large repositories are searched page by page
+        for page in search(&client, &keyword, only_installed) {
+            merge(&mut matches, &client, page, Some(&keyword));
+        }
+    }
 
-    let output: Vec<Output> = client
-        .registry
-        .by_keyword(keyword, flags)
-        .map(|pkg| Output {
-            name: pkg.meta.name,
-            summary: pkg.meta.summary,
-        })
-        .collect();
+    // Most relevant first, preferring shorter names as they're
+    // closer to the keyword within the same rank
+    let mut output = matches.into_values().collect::<Vec<_>>();
+    output.sort_by(|a, b| {
+        let key = |output: &Output| (output.rank, output.name.as_ref().len());
+        key(a).cmp(&key(b)).then_with(|| a.name.cmp(&b.name))
+    });
+    if let Some(limit) = limit {
+        output.truncate(limit);
+    }
+
+    if output::is_json() {
+        output::print_json("search", output.iter().map(Json::from).collect::<Vec<_>>())?;
+        return Ok(());
+    }
 
     if output.is_empty() {
         return Ok(());
@@ -64,31 +123,110 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
     Ok(())
 }
 
+/// Pages of packages matching `keyword` by name, provider, summary or description
+fn search<'a>(
+    client: &'a Client,
+    keyword: &'a Keyword,
+    only_installed: bool,
+) -> impl Iterator<Item = Vec<Package>> + 'a {
+    let flags = if only_installed {
+        package::Flags::new().with_installed()
+    } else {
+        // Both installed & available
+        package::Flags::new()
+    };
+
+    client.registry.pages(Some(keyword), flags)
+}
+
+/// Merge `packages` into their [`Output`] entry in `matches`
+fn merge(
+    matches: &mut BTreeMap<package::Id, Output>,
+    client: &Client,
+    packages: impl IntoIterator<Item = Package>,
+    keyword: Option<&Keyword>,
+) {
+    for pkg in packages {
+        let repository = client.repository_for(&pkg).map(ToString::to_string);
+        let entry = matches.entry(pkg.id.clone()).or_insert_with(|| Output {
+            rank: keyword.and_then(|keyword| keyword.rank(&pkg.meta)),
+            name: pkg.meta.name.clone(),
+            summary: pkg.meta.summary.clone(),
+            installed: false,
+            repository: None,
+        });
+        entry.installed |= pkg.flags.installed;
+        entry.repository = entry.repository.take().or(repository);
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("client")]
     Client(#[from] client::Error),
+    #[error("invalid regex")]
+    Regex(#[from] regex::Error),
+    #[error("json")]
+    Json(#[from] serde_json::Error),
 }
 
 const COLUMN_SPACING: usize = 4;
 
 struct Output {
+    /// Where the keyword matched, if searching by keyword
+    rank: Option<Rank>,
     name: Name,
     summary: String,
+    installed: bool,
+    repository: Option<String>,
+}
+
+/// An [`Output`] as printed with `--format json`
+#[derive(Serialize)]
+struct Json<'a> {
+    name: String,
+    summary: &'a str,
+    installed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    repository: Option<&'a str>,
+}
+
+impl<'a> From<&'a Output> for Json<'a> {
+    fn from(output: &'a Output) -> Self {
+        Self {
+            name: output.name.to_string(),
+            summary: &output.summary,
+            installed: output.installed,
+            repository: output.repository.as_deref(),
+        }
+    }
+}
+
+impl Output {
+    /// Install status & origin shown after the name
+    fn origin(&self) -> String {
+        match (&self.repository, self.installed) {
+            (Some(repo), true) => format!(" [installed, {repo}]"),
+            (Some(repo), false) => format!(" [{repo}]"),
+            (None, true) => " [installed]".to_string(),
+            (None, false) => String::new(),
+        }
+    }
 }
 
 impl ColumnDisplay for Output {
     fn get_display_width(&self) -> usize {
         // TODO: calculate the number of graphemes, not bytes.
         // Now we are assuming name and summary are ASCII.
-        self.name.as_ref().len() + self.summary.len() + COLUMN_SPACING
+        self.name.as_ref().len() + self.origin().len() + self.summary.len() + COLUMN_SPACING
     }
 
     fn display_column(&self, writer: &mut impl std::io::prelude::Write, _col: tui::pretty::Column, width: usize) {
         let _ = write!(
             writer,
-            "{}{}{:width$}{}",
+            "{}{}{}{:width$}{}",
             self.name.to_string().bold(),
+            self.origin().dim(),
             " ".repeat(COLUMN_SPACING),
             " ",
             self.summary,
@@ -0,0 +1,37 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::future;
+
+use clap::{ArgMatches, Command};
+use moss::{runtime, service, Installation};
+use thiserror::Error;
+
+pub fn command() -> Command {
+    Command::new("system-service")
+        .about("Run the D-Bus system service")
+        .long_about(
+            "Serve moss operations on the system bus as `com.serpentos.moss`, for desktop frontends \
+             & installers. Callers are authorized through polkit.\n\n\
+             Normally started through D-Bus activation, see `data/dbus` for the bus policy",
+        )
+}
+
+/// Handle execution of `moss system-service`
+pub fn handle(_args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    runtime::block_on(async {
+        let _connection = service::serve(installation).await?;
+
+        // Calls are served in the background until we're killed
+        future::pending::<()>().await;
+
+        Ok(())
+    })
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("service")]
+    Service(#[from] service::Error),
+}
@@ -0,0 +1,128 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{fs, io, path::Path, process};
+
+use clap::{arg, value_parser, Arg, ArgMatches, Command};
+use container::Container;
+use nix::unistd::Pid;
+use thiserror::Error;
+use tracing::warn;
+
+use moss::{
+    client::{self, install, Client},
+    environment, Installation,
+};
+
+pub fn command() -> Command {
+    Command::new("shell")
+        .about("Try packages in a throwaway root")
+        .long_about(
+            "Install the requested packages and their dependencies to a throwaway root, \
+             sharing the system download & asset cache, then run a shell (or the given \
+             command) inside it. The system state is left untouched and the root is \
+             removed afterwards.",
+        )
+        .arg(arg!(<NAME> ... "packages to make available").value_parser(value_parser!(String)))
+        .arg(
+            Arg::new("command")
+                .help("Command to run instead of an interactive shell")
+                .value_name("COMMAND")
+                .num_args(1..)
+                .last(true)
+                .value_parser(value_parser!(String)),
+        )
+        .arg(arg!(-n --networking "Allow network access from within the shell"))
+}
+
+/// Handle execution of `moss shell`
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let pkgs = args
+        .get_many::<String>("NAME")
+        .into_iter()
+        .flatten()
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    let command = args
+        .get_many::<String>("command")
+        .map(|command| command.cloned().collect::<Vec<_>>())
+        .unwrap_or_else(|| vec!["/bin/sh".to_string()]);
+    let yes = *args.get_one::<bool>("yes").unwrap();
+    let networking = args.get_flag("networking");
+
+    // Unique per invocation so multiple shells can coexist
+    let root = installation.cache_path("shell").join(process::id().to_string());
+    if root.exists() {
+        fs::remove_dir_all(&root)?;
+    }
+    fs::create_dir_all(&root)?;
+
+    let result = run(&root, installation, &pkgs, &command, yes, networking);
+
+    // Always clean up the throwaway root, even if running failed, without
+    // hiding why it failed
+    if let Err(error) = fs::remove_dir_all(&root) {
+        match result {
+            Ok(()) => return Err(error.into()),
+            Err(_) => warn!("failed to remove shell root {}: {error}", root.display()),
+        }
+    }
+
+    result
+}
+
+fn run(
+    root: &Path,
+    installation: Installation,
+    pkgs: &[&str],
+    command: &[String],
+    yes: bool,
+    networking: bool,
+) -> Result<(), Error> {
+    let mut client = Client::new(environment::NAME, installation)?.ephemeral(root)?;
+
+    client.install(pkgs, yes, None)?;
+
+    fs::create_dir_all(root.join("root"))?;
+
+    Container::new(root)
+        .hostname("moss-shell")
+        .networking(networking)
+        .ignore_host_sigint(true)
+        .work_dir("/root")
+        .run(|| {
+            let mut child = process::Command::new(&command[0])
+                .args(&command[1..])
+                .env_clear()
+                .env("HOME", "/root")
+                .env("PATH", "/usr/bin:/usr/sbin")
+                .env("TERM", "xterm-256color")
+                .spawn()?;
+
+            // Forward SIGINT to the shell
+            container::forward_sigint(Pid::from_raw(child.id() as i32))?;
+
+            let status = child.wait()?;
+
+            if status.success() {
+                Ok(())
+            } else {
+                Err(io::Error::other(format!("{} exited with {status}", command[0])))
+            }
+        })?;
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("client")]
+    Client(#[from] client::Error),
+    #[error("install")]
+    Install(#[from] install::Error),
+    #[error("container")]
+    Container(#[from] container::Error),
+    #[error("io")]
+    Io(#[from] io::Error),
+}
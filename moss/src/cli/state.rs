@@ -4,11 +4,12 @@
 
 use clap::{arg, ArgAction, ArgMatches, Command};
 use moss::{
-    client::{self, prune, Client},
-    environment, state, Installation,
+    client::{self, history, prune, snapshot, Client},
+    environment, output, package, prompt, state, Installation,
 };
+use serde::Serialize;
 use thiserror::Error;
-use tui::Styled;
+use tui::{HumanBytes, Styled};
 
 pub fn command() -> Command {
     Command::new("state")
@@ -17,6 +18,18 @@ pub fn command() -> Command {
         .subcommand_required(true)
         .subcommand(Command::new("active").about("List the active state"))
         .subcommand(Command::new("list").about("List all states"))
+        .subcommand(
+            Command::new("describe")
+                .about("Describe a state")
+                .long_about(
+                    "Describe a state, along with the packages it added & removed relative to the state before it",
+                )
+                .arg(
+                    arg!(<ID> "State id to be described")
+                        .action(ArgAction::Set)
+                        .value_parser(clap::value_parser!(u64)),
+                ),
+        )
         .subcommand(
             Command::new("activate").about("Activate a state").arg(
                 arg!(<ID> "State id to be activated")
@@ -24,9 +37,40 @@ pub fn command() -> Command {
                     .value_parser(clap::value_parser!(u64)),
             ),
         )
+        .subcommand(
+            Command::new("rollback")
+                .about("Roll back to a previous state")
+                .long_about(
+                    "Roll back to a state older than the active one, after confirming the package \
+                     changes this will make. The previous `/usr` is swapped into place atomically \
+                     and the active state is archived, so the rollback can itself be undone with \
+                     `moss state activate`",
+                )
+                .arg(
+                    arg!(<ID> "State id to roll back to")
+                        .action(ArgAction::Set)
+                        .value_parser(clap::value_parser!(u64)),
+                )
+                .arg(
+                    arg!(--filesystem "Restore the filesystem snapshots taken while the state was active")
+                        .long_help(
+                            "Restore the btrfs or zfs snapshots taken while the state was active, \
+                             see /etc/moss/snapshot.d/. This rolls back everything within the \
+                             snapshotted subvolumes, not only packages, and can't be undone. zfs \
+                             datasets are rolled back in place while btrfs snapshots become the \
+                             default subvolume on next boot",
+                        )
+                        .action(ArgAction::SetTrue),
+                ),
+        )
         .subcommand(
             Command::new("prune")
                 .about("Prune archived states")
+                .long_about(
+                    "Prune the oldest archived states, along with the packages & assets only they \
+                     referred to. Set `keep_states` with `moss config set` to prune them \
+                     automatically after each transaction",
+                )
                 .arg(
                     arg!(-k --keep "Keep this many states")
                         .action(ArgAction::Set)
@@ -38,6 +82,16 @@ pub fn command() -> Command {
                         .action(ArgAction::SetTrue),
                 ),
         )
+        .subcommand(
+            Command::new("gc")
+                .about("Remove packages & assets no state refers to")
+                .long_about(
+                    "Files are stored once in the content addressed asset store and hardlinked \
+                     into each state. Remove packages which no state selects, i.e. those left \
+                     behind by an interrupted transaction, then every download & asset that is \
+                     no longer referenced",
+                ),
+        )
         .subcommand(
             Command::new("remove").about("Remove an archived state").arg(
                 arg!(<ID> "State id to be removed")
@@ -45,19 +99,22 @@ pub fn command() -> Command {
                     .value_parser(clap::value_parser!(u64)),
             ),
         )
-        .subcommand(
-            Command::new("verify")
-                .about("Verify TODO")
-                .arg(arg!(--verbose "Vebose output").action(ArgAction::SetTrue)),
-        )
+        .subcommand(Command::new("verify").about("Verify assets & states").long_about(
+            "Verify the integrity of all assets & the existence of all states, then \
+                     compare the live root against the active state, reporting modified and \
+                     untracked paths in /usr. Each checked item is listed with --verbose",
+        ))
 }
 
 pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
     match args.subcommand() {
         Some(("active", _)) => active(installation),
         Some(("list", _)) => list(installation),
+        Some(("describe", args)) => describe(args, installation),
         Some(("activate", args)) => activate(args, installation),
+        Some(("rollback", args)) => rollback(args, installation),
         Some(("prune", args)) => prune(args, installation),
+        Some(("gc", _)) => gc(installation),
         Some(("remove", args)) => remove(args, installation),
         Some(("verify", args)) => verify(args, installation),
         _ => unreachable!(),
@@ -71,7 +128,8 @@ pub fn active(installation: Installation) -> Result<(), Error> {
 
         let state = client.state_db.get(id)?;
 
-        print_state(state);
+        print_state(state, true);
+        println!();
     }
 
     Ok(())
@@ -79,17 +137,71 @@ pub fn active(installation: Installation) -> Result<(), Error> {
 
 /// List all known states, newest first
 pub fn list(installation: Installation) -> Result<(), Error> {
+    let active = installation.active_state;
     let client = Client::new(environment::NAME, installation)?;
 
-    let state_ids = client.state_db.list_ids()?;
+    let mut states = client.state_db.all()?;
+    states.sort_by_key(|state| state.id);
 
-    let mut states = state_ids
+    // Pair each state with the one preceding it
+    let previous = Some(None)
         .into_iter()
-        .map(|(id, _)| client.state_db.get(id).map_err(Error::DB))
-        .collect::<Result<Vec<_>, _>>()?;
+        .chain(states.iter().map(Some))
+        .collect::<Vec<_>>();
+    let changes = states
+        .iter()
+        .zip(previous)
+        .map(|(state, previous)| history::Changes::between(previous, state))
+        .collect::<Vec<_>>();
+
+    if output::is_json() {
+        let listed = states
+            .iter()
+            .zip(&changes)
+            .rev()
+            .map(|(state, changes)| Listed::new(state, changes, active == Some(state.id)))
+            .collect::<Vec<_>>();
+        output::print_json("states", listed)?;
+        return Ok(());
+    }
+
+    for (state, changes) in states.into_iter().zip(changes).rev() {
+        let is_active = active == Some(state.id);
+
+        print_state(state, is_active);
+        println!(
+            "{} {} {}",
+            "Changes:".bold(),
+            format!("+{}", changes.added.len()).green(),
+            format!("-{}", changes.removed.len()).red()
+        );
+        println!();
+    }
+
+    Ok(())
+}
+
+/// Describe a single state & its package changes
+pub fn describe(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let id = state::Id::from(*args.get_one::<u64>("ID").unwrap() as i32);
+    let active = installation.active_state;
+
+    let client = Client::new(environment::NAME, installation)?;
+    let state = client.state_db.get(id).map_err(|_| Error::StateDoesntExist(id))?;
+    let previous = history::previous(&client.state_db, id)?;
+
+    let changes = history::Changes::between(previous.as_ref(), &state);
+
+    print_state(state, active == Some(id));
+    print_snapshots(&client.state_db.snapshots(id)?);
+    println!();
+
+    match &previous {
+        Some(previous) => println!("{} state {}", "Changes since".bold(), previous.id),
+        None => println!("{}", "Changes".bold()),
+    }
+    print_changes(&client, &changes);
 
-    states.reverse();
-    states.into_iter().for_each(print_state);
     Ok(())
 }
 
@@ -108,6 +220,93 @@ pub fn activate(args: &ArgMatches, installation: Installation) -> Result<(), Err
     Ok(())
 }
 
+/// Activate a state older than the active one, confirming the changes first
+pub fn rollback(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let id = state::Id::from(*args.get_one::<u64>("ID").unwrap() as i32);
+    let yes = args.get_flag("yes");
+
+    let active = installation.active_state.ok_or(Error::NoActiveState)?;
+    if id >= active {
+        return Err(Error::NotPrevious(id, active));
+    }
+
+    let client = Client::new(environment::NAME, installation)?;
+    let target = client.state_db.get(id).map_err(|_| Error::StateDoesntExist(id))?;
+    let current = client.state_db.get(active)?;
+
+    // Only the newest snapshot of each subvolume
+    let mut snapshots = client.state_db.snapshots(id)?;
+    snapshots.sort_by(|a, b| a.subvolume.cmp(&b.subvolume).then(b.created.cmp(&a.created)));
+    snapshots.dedup_by(|a, b| a.subvolume == b.subvolume);
+
+    if args.get_flag("filesystem") {
+        return restore_snapshots(id, &snapshots, yes);
+    }
+
+    let changes = history::Changes::between(Some(&current), &target);
+
+    println!(
+        "Rolling back from state {} to {}",
+        active.to_string().bold(),
+        id.to_string().bold()
+    );
+    print_changes(&client, &changes);
+    println!();
+
+    if !prompt::confirm(yes)? {
+        return Err(Error::Cancelled);
+    }
+
+    client.activate_state(id)?;
+
+    println!(
+        "Rolled back to state {} {}",
+        id.to_string().bold(),
+        format!("({active} archived)").dim()
+    );
+    if !snapshots.is_empty() {
+        println!(
+            "{}",
+            format!("Filesystem snapshots of state {id} exist too, restore them with --filesystem").dim()
+        );
+    }
+
+    Ok(())
+}
+
+/// Restore the filesystem `snapshots` of state `id`, confirming first
+fn restore_snapshots(id: state::Id, snapshots: &[state::Snapshot], yes: bool) -> Result<(), Error> {
+    if snapshots.is_empty() {
+        return Err(Error::NoSnapshots(id));
+    }
+
+    println!("Restoring the filesystem snapshots of state {}", id.to_string().bold());
+    print_snapshots(snapshots);
+    println!(
+        "{}",
+        "Everything within these subvolumes is rolled back, not only packages".yellow()
+    );
+    println!();
+
+    if !prompt::confirm(yes)? {
+        return Err(Error::Cancelled);
+    }
+
+    for snapshot in snapshots {
+        match snapshot::restore(snapshot)? {
+            snapshot::Restored::InPlace => println!("{} {:?}", "Restored".green(), snapshot.subvolume),
+            snapshot::Restored::OnReboot(path) => println!(
+                "{} {:?} {}",
+                "Restored".green(),
+                snapshot.subvolume,
+                format!("(from {path:?}, on next boot)").dim()
+            ),
+        }
+    }
+
+    Ok(())
+}
+
 pub fn prune(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
     let keep = *args.get_one::<u64>("keep").unwrap();
     let include_newer = args.get_flag("include-newer");
@@ -119,6 +318,21 @@ pub fn prune(args: &ArgMatches, installation: Installation) -> Result<(), Error>
     Ok(())
 }
 
+pub fn gc(installation: Installation) -> Result<(), Error> {
+    let client = Client::new(environment::NAME, installation)?;
+    let collected = client.collect_garbage()?;
+
+    println!(
+        "{} {} package(s), {} file(s) {}",
+        "Removed".green(),
+        collected.packages,
+        collected.files,
+        format!("({})", HumanBytes(collected.bytes)).dim()
+    );
+
+    Ok(())
+}
+
 pub fn remove(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
     let id = *args.get_one::<u64>("ID").unwrap() as i32;
     let yes = args.get_flag("yes");
@@ -130,7 +344,7 @@ pub fn remove(args: &ArgMatches, installation: Installation) -> Result<(), Error
 }
 
 pub fn verify(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
-    let verbose = args.get_flag("verbose");
+    let verbose = args.get_count("verbose") > 0;
     let yes = args.get_flag("yes");
 
     let client = Client::new(environment::NAME, installation)?;
@@ -139,12 +353,50 @@ pub fn verify(args: &ArgMatches, installation: Installation) -> Result<(), Error
     Ok(())
 }
 
+/// A state as listed with `--format json`
+#[derive(Serialize)]
+struct Listed<'a> {
+    id: i32,
+    created: String,
+    summary: Option<&'a str>,
+    description: Option<&'a str>,
+    active: bool,
+    packages: usize,
+    /// Packages added & removed relative to the state before it
+    added: Vec<&'a str>,
+    removed: Vec<&'a str>,
+}
+
+impl<'a> Listed<'a> {
+    fn new(state: &'a state::State, changes: &'a history::Changes, active: bool) -> Self {
+        Self {
+            id: state.id.into(),
+            created: state.created.to_rfc3339(),
+            summary: state.summary.as_deref(),
+            description: state.description.as_deref(),
+            active,
+            packages: state.selections.len(),
+            added: changes.added.iter().map(|s| AsRef::<str>::as_ref(&s.package)).collect(),
+            removed: changes
+                .removed
+                .iter()
+                .map(|s| AsRef::<str>::as_ref(&s.package))
+                .collect(),
+        }
+    }
+}
+
 /// Emit a state description for the TUI
-fn print_state(state: state::State) {
+fn print_state(state: state::State, is_active: bool) {
     println!(
-        "State #{} - {}",
+        "State #{} - {}{}",
         state.id.to_string().bold(),
         state.summary.unwrap_or(String::from("system transaction")),
+        if is_active {
+            " (active)".green().to_string()
+        } else {
+            String::new()
+        },
     );
     println!("{} {}", "Created:".bold(), state.created);
     println!(
@@ -152,17 +404,81 @@ fn print_state(state: state::State) {
         "Description:".bold(),
         state.description.unwrap_or(String::from("no description"))
     );
-    // TODO: List packages?
-    // TODO: Start with normal list, compute diff, reverse to print ?
     println!("{} {}", "Packages:".bold(), state.selections.len());
-    println!();
+}
+
+/// Emit the filesystem snapshots recorded for a state, if any
+fn print_snapshots(snapshots: &[state::Snapshot]) {
+    if snapshots.is_empty() {
+        return;
+    }
+
+    println!("{}", "Snapshots:".bold());
+    for snapshot in snapshots {
+        println!(
+            "  {} {:?} {} {}",
+            snapshot.filesystem,
+            snapshot.subvolume,
+            snapshot.name,
+            format!("({})", snapshot.created).dim()
+        );
+    }
+}
+
+/// Emit the packages added & removed between two states
+fn print_changes(client: &Client, changes: &history::Changes) {
+    if changes.is_empty() {
+        println!("  no package changes");
+        return;
+    }
+
+    // Metadata for packages no longer installed or available may be gone,
+    // so fall back to their id
+    let describe = |id: &package::Id| match client.registry.by_id(id).next() {
+        Some(package) => format!(
+            "{} {}-{}",
+            package.meta.name, package.meta.version_identifier, package.meta.source_release
+        ),
+        None => id.to_string(),
+    };
+
+    for selection in &changes.added {
+        println!("  {} {}", "+".green(), describe(&selection.package));
+    }
+    for selection in &changes.removed {
+        println!("  {} {}", "-".red(), describe(&selection.package));
+    }
 }
 
 #[derive(Debug, Error)]
 pub enum Error {
+    #[error("cancelled")]
+    Cancelled,
+
+    #[error("root must have an active state")]
+    NoActiveState,
+
+    #[error("state {0} doesn't exist")]
+    StateDoesntExist(state::Id),
+
+    #[error("state {0} isn't older than the active state {1}, use `moss state activate` instead")]
+    NotPrevious(state::Id, state::Id),
+
+    #[error("no filesystem snapshots were taken while state {0} was active")]
+    NoSnapshots(state::Id),
+
+    #[error("snapshot")]
+    Snapshot(#[from] snapshot::Error),
+
+    #[error("string processing")]
+    Dialog(#[from] tui::dialoguer::Error),
+
     #[error("client")]
     Client(#[from] client::Error),
 
     #[error("db")]
     DB(#[from] moss::db::Error),
+
+    #[error("json")]
+    Json(#[from] serde_json::Error),
 }
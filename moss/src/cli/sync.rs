@@ -2,30 +2,41 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::borrow::Cow;
-use std::collections::BTreeSet;
 use std::path::PathBuf;
+use std::{process, thread, time::Duration};
 
-use clap::{arg, value_parser, ArgMatches, Command};
-use moss::registry::transaction;
-use moss::state::Selection;
+use chrono::{SecondsFormat, Utc};
+use clap::{arg, value_parser, Arg, ArgAction, ArgMatches, Command};
+use itertools::Itertools;
+use moss::registry::conflict;
+use moss::repository::refresh::{Metered, Schedule};
 use moss::{
-    client::{self, Client},
+    client::{
+        self,
+        plan::{self, Plan},
+        Client,
+    },
     package::{self},
     Package,
 };
-use moss::{environment, runtime, Installation};
+use moss::{environment, messages, output, runtime, Installation};
+use moss::{prompt, request};
+use serde::Serialize;
 use thiserror::Error;
 
-use tui::dialoguer::theme::ColorfulTheme;
-use tui::dialoguer::Confirm;
 use tui::pretty::autoprint_columns;
+use tui::Styled;
 
 pub fn command() -> Command {
     Command::new("sync")
         .visible_alias("up")
         .about("Sync packages")
-        .long_about("Sync package selections with candidates from the highest priority repository")
+        .long_about(
+            "Sync package selections with candidates from the highest priority repository\n\n\
+             With --check, only report the changes to sync, exiting with status 100 if there are any. \
+             With --watch, keep checking on a schedule for unattended update services, applying \
+             the changes too with --apply",
+        )
         .arg(arg!(-u --"update" "Update repositories before syncing"))
         .arg(arg!(--"upgrade-only" "Only sync packages that have a version upgrade"))
         .arg(
@@ -37,14 +48,91 @@ pub fn command() -> Command {
                 )
                 .value_parser(value_parser!(PathBuf)),
         )
+        .args(super::dry_run_args())
+        .arg(super::skip_checks_arg())
+        .arg(
+            Arg::new("check")
+                .long("check")
+                .help("Only report whether there are changes to sync, exiting with status 100 if so")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["to", "dry-run", "watch"]),
+        )
+        .arg(
+            Arg::new("watch")
+                .long("watch")
+                .help("Keep running, refreshing & reporting changes to sync on a jittered schedule")
+                .long_help(
+                    "Keep running, refreshing repositories & reporting changes to sync on a jittered \
+                     schedule. Each report is a line of text, or a `sync-check` JSON document with \
+                     --format json. Refreshes are skipped on metered connections",
+                )
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["to", "dry-run"]),
+        )
+        .arg(
+            Arg::new("apply")
+                .long("apply")
+                .help("Apply the changes found while watching, rather than only reporting them")
+                .action(ArgAction::SetTrue)
+                .requires("watch"),
+        )
+        .arg(
+            Arg::new("allow-metered")
+                .long("allow-metered")
+                .help("Refresh on metered connections too while watching")
+                .action(ArgAction::SetTrue)
+                .requires("watch"),
+        )
+        .arg(
+            Arg::new("interval")
+                .long("interval")
+                .help("Hours between checks while watching")
+                .action(ArgAction::Set)
+                .default_value("6")
+                .value_parser(clap::value_parser!(u64).range(1..)),
+        )
+        .arg(
+            Arg::new("jitter")
+                .long("jitter")
+                .help("Maximum random delay of checks while watching, in minutes")
+                .action(ArgAction::Set)
+                .default_value("30")
+                .value_parser(clap::value_parser!(u64)),
+        )
 }
 
+/// Exit status of `--check` when there are changes to sync
+const CHANGES_PENDING: i32 = 100;
+
 pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
     let yes_all = *args.get_one::<bool>("yes").unwrap();
     let update = *args.get_one::<bool>("update").unwrap();
     let upgrade_only = *args.get_one::<bool>("upgrade-only").unwrap();
 
+    if args.get_flag("watch") {
+        return watch(args, &installation);
+    }
+
+    if args.get_flag("check") {
+        let report = check(
+            &installation,
+            &Check {
+                upgrade_only,
+                refresh: update,
+                allow_metered: true,
+                apply: false,
+            },
+        )?;
+        report.print()?;
+
+        if !report.plan.is_empty() {
+            process::exit(CHANGES_PENDING);
+        }
+        return Ok(());
+    }
+
     let mut client = Client::new(environment::NAME, installation)?;
+    client.skip_checks(args.get_flag("skip-checks"));
 
     // Make ephemeral if a blit target was provided
     if let Some(blit_target) = args.get_one::<PathBuf>("to").cloned() {
@@ -53,28 +141,18 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
 
     // Update repos if requested
     if update {
+        if request::is_offline() {
+            println!("{}", messages::get("refresh-local-offline"));
+        }
         runtime::block_on(client.refresh_repositories())?;
     }
 
-    // Grab all the existing installed packages
-    let installed = client
-        .registry
-        .list_installed(package::Flags::default())
-        .collect::<Vec<_>>();
-    if installed.is_empty() {
-        return Err(Error::NoInstall);
-    }
-
-    // Resolve the finalized state w/ 2 passes.
-    //
-    // 1. Resolve a new state based on all explicit packages with sync applied
-    // 2. Resolve a new state based on `1`, this ensures applicable transitive
-    //    sync is applied
-    //
-    // By resolving only explicit first, this ensures any "orphaned" transitive deps
-    // are naturally dropped from the final state.
-    let first_pass = resolve_with_sync(&client, Resolution::Explicit, upgrade_only, &installed)?;
-    let finalized = resolve_with_sync(&client, Resolution::All, upgrade_only, &first_pass)?;
+    let client::sync::Resolved {
+        installed,
+        finalized,
+        conflicting,
+        holds,
+    } = client::sync::resolve(&client, upgrade_only)?;
 
     // Synced are packages are:
     //
@@ -89,135 +167,254 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
         .filter(|p| !finalized.iter().any(|f| f.meta.name == p.meta.name))
         .cloned()
         .collect::<Vec<_>>();
+    let replaced = removed
+        .iter()
+        .filter_map(|p| Some((p, finalized.iter().find(|f| conflict::replaces(f, p))?)))
+        .collect::<Vec<_>>();
 
-    if synced.is_empty() && removed.is_empty() {
-        println!("No packages to sync");
+    if let Some(format) = super::dry_run(args) {
+        Plan::new(&client, &synced, &removed).print(format)?;
         return Ok(());
     }
 
-    if !synced.is_empty() {
-        println!("The following packages will be sync'd: ");
-        println!();
-        autoprint_columns(synced.as_slice());
-        println!();
+    if synced.is_empty() && removed.is_empty() {
+        if output::is_json() {
+            Plan::new(&client, &synced, &removed).print(plan::Format::Json)?;
+        } else {
+            println!("{}", messages::get("sync-nothing"));
+        }
+        return Ok(());
     }
-    if !removed.is_empty() {
-        println!("The following orphaned packages will be removed: ");
-        println!();
-        autoprint_columns(removed.as_slice());
+
+    // Sync'd packages either replace an installed package of the same name
+    // or are newly pulled in
+    let (upgraded, new): (Vec<&Package>, Vec<&Package>) = synced
+        .iter()
+        .copied()
+        .filter(|p| !replaced.iter().any(|(_, r)| r.id == p.id))
+        .partition(|p| installed.iter().any(|i| i.meta.name == p.meta.name));
+    let orphaned = removed
+        .iter()
+        .filter(|p| !replaced.iter().any(|(r, _)| r.id == p.id) && !conflicting.iter().any(|c| c.id == p.id))
+        .collect::<Vec<_>>();
+
+    // Held packages which would otherwise have been sync'd
+    let held_back = installed
+        .iter()
+        .filter(|p| holds.contains(&p.meta.name.to_string()))
+        .filter(|p| {
+            client
+                .registry
+                .by_name(&p.meta.name, package::Flags::new().with_available())
+                .next()
+                .is_some_and(|lookup| lookup.id != p.id)
+        })
+        .collect::<Vec<_>>();
+    if output::is_json() {
+        // The plan stands in for the human readable summary
+        Plan::new(&client, &synced, &removed).print(plan::Format::Json)?;
+    } else {
+        if !held_back.is_empty() {
+            println!("{}", messages::get("sync-held"));
+            println!();
+            autoprint_columns(held_back.as_slice());
+            println!();
+        }
+
+        if !upgraded.is_empty() {
+            println!("{}", messages::get("will-sync"));
+            println!();
+            autoprint_columns(upgraded.as_slice());
+            println!();
+        }
+        if !new.is_empty() {
+            println!("{}", messages::get("will-install-new"));
+            println!();
+            autoprint_columns(new.as_slice());
+            println!();
+        }
+        if !replaced.is_empty() {
+            println!("{}", messages::get("will-replace"));
+            println!();
+            for (old, new) in &replaced {
+                println!(
+                    "  {} → {}",
+                    old.meta.name.to_string().bold(),
+                    new.meta.name.to_string().bold()
+                );
+            }
+            println!();
+        }
+        if !conflicting.is_empty() {
+            println!("{}", messages::get("will-remove-conflicting"));
+            println!();
+            autoprint_columns(conflicting.as_slice());
+            println!();
+        }
+        if !orphaned.is_empty() {
+            println!("{}", messages::get("will-remove-orphaned"));
+            println!();
+            autoprint_columns(orphaned.as_slice());
+            println!();
+        }
+
+        let superseded = installed
+            .iter()
+            .filter(|p| !finalized.iter().any(|f| f.id == p.id))
+            .collect::<Vec<_>>();
+        Plan::new(&client, &synced, &superseded).print_sizes();
         println!();
     }
 
+    // Metered repositories may insist on confirmation
+    let confirm_quota = client.warn_exceeded_quotas(&synced)?;
+
     // Must we prompt?
-    let result = if yes_all {
-        true
+    let result = if confirm_quota {
+        prompt::confirm_always()?
     } else {
-        Confirm::with_theme(&ColorfulTheme::default())
-            .with_prompt(" Do you wish to continue? ")
-            .default(false)
-            .interact()?
+        prompt::confirm(yes_all)?
     };
     if !result {
         return Err(Error::Cancelled);
     }
 
-    runtime::block_on(client.cache_packages(&synced))?;
-
-    // Map finalized state to a [`Selection`] by referencing
-    // it's value from the previous state
-    let new_selections = {
-        let previous_selections = match client.installation.active_state {
-            Some(id) => client.state_db.get(id)?.selections,
-            None => vec![],
-        };
-
-        finalized
-            .into_iter()
-            .map(|p| {
-                // Use old version id to lookup previous selection
-                let lookup_id = installed
-                    .iter()
-                    .find_map(|i| (i.meta.name == p.meta.name).then_some(&i.id))
-                    .unwrap_or(&p.id);
-
-                previous_selections
-                    .iter()
-                    .find(|s| s.package == *lookup_id)
-                    .cloned()
-                    // Use prev reason / explicit flag & new id
-                    .map(|s| Selection {
-                        package: p.id.clone(),
-                        ..s
-                    })
-                    // Must be transitive
-                    .unwrap_or(Selection {
-                        package: p.id,
-                        explicit: false,
-                        reason: None,
-                    })
-            })
-            .collect::<Vec<_>>()
-    };
+    let (num_upgraded, num_new, num_replaced) = (upgraded.len(), new.len(), replaced.len());
+    let new_selections = client::sync::selections(&client, &installed, finalized)?;
+
+    client.fetch_transaction(&synced, &new_selections, "Sync")?;
 
     // Perfect, apply state.
     client.new_state(&new_selections, "Sync")?;
 
+    if !output::is_json() {
+        println!(
+            "{} {}",
+            messages::get("summary").bold(),
+            messages::format(
+                "sync-summary",
+                &[
+                    ("synced", num_upgraded.into()),
+                    ("new", num_new.into()),
+                    ("replaced", num_replaced.into()),
+                    ("removed", (removed.len() - num_replaced).into()),
+                ]
+            )
+        );
+    }
+
     Ok(())
 }
 
-enum Resolution {
-    Explicit,
-    All,
+/// Check for changes to sync on a schedule, until killed
+fn watch(args: &ArgMatches, installation: &Installation) -> Result<(), Error> {
+    let options = Check {
+        upgrade_only: args.get_flag("upgrade-only"),
+        refresh: true,
+        allow_metered: args.get_flag("allow-metered"),
+        apply: args.get_flag("apply"),
+    };
+    let schedule = Schedule {
+        interval: Duration::from_secs(*args.get_one::<u64>("interval").unwrap() * 60 * 60),
+        jitter: Duration::from_secs(*args.get_one::<u64>("jitter").unwrap() * 60),
+    };
+
+    loop {
+        // Failures are reported & retried on the next run
+        match check(installation, &options) {
+            Ok(report) => report.print()?,
+            Err(error) => eprintln!("{} {error}", "Error".red()),
+        }
+
+        let delay = schedule.next_delay();
+        if !output::is_json() {
+            println!("Next check in {}m", delay.as_secs() / 60);
+        }
+        thread::sleep(delay);
+    }
 }
 
-/// Return a fully resolved package set w/ sync'd changes swapped in
-/// using the provided `packages` at the requested [`Resolution`]
-fn resolve_with_sync(
-    client: &Client,
-    resolution: Resolution,
+/// Options of a single [`check`]
+struct Check {
     upgrade_only: bool,
-    packages: &[Package],
-) -> Result<Vec<Package>, Error> {
-    let all_ids = packages.iter().map(|p| &p.id).collect::<BTreeSet<_>>();
+    /// Refresh repositories first
+    refresh: bool,
+    allow_metered: bool,
+    /// Apply the changes found
+    apply: bool,
+}
 
-    // For each package, replace it w/ it's sync'd change (if available)
-    // or return the original package
-    let with_sync = packages
-        .iter()
-        .filter(|p| match resolution {
-            Resolution::Explicit => p.flags.explicit,
-            Resolution::All => true,
-        })
-        .map(|p| {
-            // Get first available = use highest priority
-            if let Some(lookup) = client
-                .registry
-                .by_name(&p.meta.name, package::Flags::new().with_available())
-                .next()
-            {
-                let upgrade_check = if upgrade_only {
-                    lookup.meta.source_release > p.meta.source_release
-                } else {
-                    true
-                };
-
-                if !all_ids.contains(&lookup.id) && upgrade_check {
-                    Ok(Cow::Owned(lookup))
-                } else {
-                    Ok(Cow::Borrowed(p))
-                }
+/// Changes to sync found by a [`check`]
+#[derive(Debug, Serialize)]
+struct Report {
+    checked: String,
+    /// The changes were applied as a new state
+    applied: bool,
+    #[serde(flatten)]
+    plan: Plan,
+}
+
+impl Report {
+    fn print(&self) -> Result<(), Error> {
+        if output::is_json() {
+            output::print_json_line("sync-check", self)?;
+            return Ok(());
+        }
+
+        if self.plan.is_empty() {
+            println!("{} Nothing to sync", self.checked.as_str().dim());
+            return Ok(());
+        }
+
+        let status = if self.applied { "Sync'd" } else { "Pending" };
+        let names = self.plan.install.iter().map(|entry| &entry.name).join(", ");
+        println!(
+            "{} {} {} to install, {} to remove{}",
+            self.checked.as_str().dim(),
+            status.bold(),
+            self.plan.install.len(),
+            self.plan.remove.len(),
+            if names.is_empty() {
+                String::default()
             } else {
-                Err(Error::NameNotFound(p.meta.name.clone()))
-            }
-        })
-        .collect::<Result<Vec<_>, _>>()?;
+                format!(": {names}")
+            },
+        );
+
+        Ok(())
+    }
+}
+
+/// Find the changes to sync with a fresh client, picking up
+/// any changes to the root since the last check
+fn check(installation: &Installation, options: &Check) -> Result<Report, Error> {
+    // The active state changes with every sync applied
+    let mut installation = installation.clone();
+    installation.reload_active_state();
 
-    // Build a new tx from this sync'd package set
-    let mut tx = client.registry.transaction()?;
-    tx.add(with_sync.iter().map(|p| p.id.clone()).collect())?;
+    let mut client = Client::new(environment::NAME, installation)?;
+
+    if options.refresh && (options.allow_metered || Metered::detect() != Metered::Yes) {
+        runtime::block_on(client.refresh_repositories())?;
+    }
+
+    let resolved = client::sync::resolve(&client, options.upgrade_only)?;
+    let (synced, removed) = resolved.changes();
+    let plan = Plan::new(&client, &synced, &removed);
 
-    // Resolve the tx
-    Ok(client.resolve_packages(tx.finalize())?)
+    // Metered repositories insisting on confirmation are left to the user
+    let apply = options.apply && !plan.is_empty() && !client.warn_exceeded_quotas(&synced)?;
+    if apply {
+        let selections = client::sync::selections(&client, &resolved.installed, resolved.finalized.clone())?;
+        client.fetch_transaction(&synced, &selections, "Automatic sync")?;
+        client.new_state(&selections, "Automatic sync")?;
+    }
+
+    Ok(Report {
+        checked: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        applied: apply,
+        plan,
+    })
 }
 
 #[derive(Debug, Error)]
@@ -225,24 +422,21 @@ pub enum Error {
     #[error("cancelled")]
     Cancelled,
 
-    #[error("unknown package name")]
-    NameNotFound(package::Name),
-
-    #[error("no installation")]
-    NoInstall,
+    #[error("plan")]
+    Plan(#[from] plan::Error),
 
     #[error("client")]
     Client(#[from] client::Error),
 
-    #[error("db")]
-    DB(#[from] moss::db::Error),
-
     #[error("string processing")]
     Dialog(#[from] tui::dialoguer::Error),
 
-    #[error("transaction")]
-    Transaction(#[from] transaction::Error),
+    #[error("sync")]
+    Sync(#[from] client::sync::Error),
 
     #[error("io")]
     Io(#[from] std::io::Error),
+
+    #[error("json")]
+    Json(#[from] serde_json::Error),
 }
@@ -0,0 +1,99 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use clap::{ArgMatches, Command};
+use moss::{
+    client::{self, journal, Client},
+    environment, prompt, Installation,
+};
+use thiserror::Error;
+use tui::Styled;
+
+pub fn command() -> Command {
+    Command::new("transaction")
+        .about("Recover an interrupted transaction")
+        .long_about(
+            "Recover a transaction that was interrupted, i.e. by a power loss or moss being killed. \
+             No other transaction can run until it's resumed or aborted",
+        )
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("resume")
+                .about("Complete an interrupted transaction")
+                .long_about(
+                    "Complete an interrupted transaction. If its new state was already staged it's \
+                     swapped into place, otherwise missing packages are fetched & the state is \
+                     blitted anew",
+                ),
+        )
+        .subcommand(
+            Command::new("abort")
+                .about("Discard an interrupted transaction")
+                .long_about(
+                    "Discard a transaction that was interrupted before its new state was staged, \
+                     removing the partially blitted tree and leaving the active state as is",
+                ),
+        )
+}
+
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    match args.subcommand() {
+        Some(("resume", _)) => resume(installation),
+        Some(("abort", args)) => abort(args, installation),
+        _ => unreachable!(),
+    }
+}
+
+fn resume(installation: Installation) -> Result<(), Error> {
+    let client = Client::new(environment::NAME, installation)?;
+
+    match client.resume()? {
+        Some(state) => println!("State {} applied", state.id.to_string().bold()),
+        None => println!("No interrupted transaction to resume"),
+    }
+
+    Ok(())
+}
+
+fn abort(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let yes = args.get_flag("yes");
+
+    let Some(journal) = journal::load(&installation)? else {
+        println!("No interrupted transaction to abort");
+        return Ok(());
+    };
+
+    println!(
+        "The interrupted {} transaction of {} package(s) will be discarded",
+        journal.summary.bold(),
+        journal.selections.len()
+    );
+    println!();
+
+    if !prompt::confirm(yes)? {
+        return Err(Error::Cancelled);
+    }
+
+    let client = Client::new(environment::NAME, installation)?;
+    client.abort()?;
+
+    println!("{} the {} transaction", "Aborted".yellow(), journal.summary);
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("cancelled")]
+    Cancelled,
+
+    #[error("string processing")]
+    Dialog(#[from] tui::dialoguer::Error),
+
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error("io")]
+    Io(#[from] std::io::Error),
+}
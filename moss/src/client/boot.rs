@@ -3,9 +3,17 @@
 // SPDX-License-Identifier: MPL-2.0
 
 //! Boot management integration in moss
+//!
+//! The kernel & bootloader of the active state are synchronized to the ESP by
+//! [`blsforme`]. Each archived state that ships a kernel additionally gets its
+//! own [BLS] entry, passing its id to the initrd, so older states remain bootable.
+//!
+//! [BLS]: https://uapi-group.org/specifications/specs/boot_loader_specification/
 
 use std::{
+    collections::{BTreeMap, BTreeSet},
     fs, io,
+    os::unix::fs::MetadataExt,
     path::{Path, PathBuf},
     str::FromStr,
 };
@@ -14,8 +22,22 @@ use blsforme::os_release::{self, OsRelease};
 use fnmatch::Pattern;
 use stone::payload::{layout, Layout};
 use thiserror::{self, Error};
+use xxhash_rust::xxh3::xxh3_64;
 
-use crate::{package::Id, Installation};
+use crate::{client::space, package::Id, state, Installation};
+
+/// Directory of type #1 entries, relative to the ESP
+const ENTRIES_DIR: &str = "loader/entries";
+/// systemd-boot configuration, relative to the ESP
+const LOADER_CONF: &str = "loader/loader.conf";
+/// Kernels & initrds of archived states, relative to the ESP
+const ASSETS_DIR: &str = "EFI/moss";
+/// Prefix of the entries we manage for archived states
+const STATE_ENTRY_PREFIX: &str = "moss-state-";
+/// Kernel argument telling the initrd which state to activate
+const STATE_ARGUMENT: &str = "moss.fstx";
+/// Index of the kernels copied to the ESP, within the root directory
+const ASSET_INDEX: &str = "boot-assets";
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -34,6 +56,15 @@ pub enum Error {
 
     #[error("incomplete kernel tree: {0}")]
     IncompleteKernel(String),
+
+    #[error("no EFI system partition found")]
+    NoEsp,
+
+    #[error("unknown boot entry: {0}")]
+    UnknownEntry(String),
+
+    #[error("not enough space on the EFI system partition: {0}")]
+    NoSpace(space::Requirement),
 }
 
 /// Simple mapping type for kernel discovery paths, retaining the layout reference
@@ -151,3 +182,554 @@ pub fn synchronize(install: &Installation, layouts: &[(Id, Layout)]) -> Result<(
 
     Ok(())
 }
+
+/// Whether the kernel or bootloader files differ between two sets of layouts,
+/// i.e. from the previous & new state of a transaction
+pub fn changed(old: &[(Id, Layout)], new: &[(Id, Layout)]) -> Result<bool, Error> {
+    let kernel = fnmatch::Pattern::from_str("lib/kernel/*/*")?;
+    let systemd = fnmatch::Pattern::from_str("lib*/systemd/boot/efi/*.efi")?;
+
+    let boot_files = |layouts: &[(Id, Layout)]| {
+        layouts
+            .iter()
+            .filter_map(|(_, layout)| match &layout.entry {
+                layout::Entry::Regular(hash, target) => Some((*hash, target.clone())),
+                layout::Entry::Symlink(source, target) => Some((0, format!("{target} -> {source}"))),
+                _ => None,
+            })
+            .filter(|(_, target)| kernel.match_path(target).is_some() || systemd.match_path(target).is_some())
+            .collect::<BTreeSet<_>>()
+    };
+
+    Ok(boot_files(old) != boot_files(new))
+}
+
+/// A type #1 boot entry on the ESP
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Entry id, i.e. its file name without the `.conf` suffix
+    pub id: String,
+    /// Title shown by the boot menu
+    pub title: Option<String>,
+    /// Kernel version
+    pub version: Option<String>,
+    /// State booted by this entry, if it's one of ours
+    pub state: Option<state::Id>,
+    /// Whether the bootloader selects this entry by default
+    pub default: bool,
+}
+
+impl Entry {
+    /// Parse the contents of an entry `.conf` file
+    fn parse(id: &str, contents: &str) -> Self {
+        let mut entry = Self {
+            id: id.to_owned(),
+            title: None,
+            version: None,
+            state: None,
+            default: false,
+        };
+
+        for (key, value) in contents
+            .lines()
+            .filter_map(|line| line.trim().split_once(char::is_whitespace))
+        {
+            let value = value.trim();
+
+            match key {
+                "title" => entry.title = Some(value.to_owned()),
+                "version" => entry.version = Some(value.to_owned()),
+                "options" => {
+                    entry.state = value
+                        .split_whitespace()
+                        .filter_map(|arg| arg.strip_prefix(STATE_ARGUMENT)?.strip_prefix('='))
+                        .find_map(|id| id.parse::<i32>().ok())
+                        .map(state::Id::from)
+                }
+                _ => {}
+            }
+        }
+
+        entry
+    }
+}
+
+/// Locate the mounted EFI system partition of the installation
+pub fn esp(install: &Installation) -> Option<PathBuf> {
+    ["efi", "boot/efi", "boot"]
+        .into_iter()
+        .map(|dir| install.root.join(dir))
+        .find(|dir| dir.join("EFI").is_dir())
+}
+
+/// All boot entries on the ESP, sorted by id
+pub fn entries(install: &Installation) -> Result<Vec<Entry>, Error> {
+    let esp = esp(install).ok_or(Error::NoEsp)?;
+    let default = default_entry(&esp)?;
+
+    let mut entries = vec![];
+
+    for file in read_dir_if_exists(&esp.join(ENTRIES_DIR))? {
+        let path = file.path();
+
+        let Some(id) = path.file_name().and_then(|name| name.to_str()?.strip_suffix(".conf")) else {
+            continue;
+        };
+
+        let mut entry = Entry::parse(id, &fs::read_to_string(&path)?);
+        entry.default = default.as_deref() == Some(id);
+        entries.push(entry);
+    }
+
+    entries.sort_by(|a, b| a.id.cmp(&b.id));
+
+    Ok(entries)
+}
+
+/// Make the bootloader select entry `id` by default
+pub fn set_default(install: &Installation, id: &str) -> Result<(), Error> {
+    let esp = esp(install).ok_or(Error::NoEsp)?;
+
+    if !esp.join(ENTRIES_DIR).join(format!("{id}.conf")).exists() {
+        return Err(Error::UnknownEntry(id.to_owned()));
+    }
+
+    write_default_entry(&esp, Some(id))
+}
+
+/// Write an entry for each archived state shipping a kernel, and remove those
+/// (along with their kernels) for states which are no longer archived
+///
+/// Kernels are only copied to the ESP once they're known to fit, after the
+/// stale ones are removed. This is a no-op if there's no ESP, i.e. when
+/// installing to an image
+pub fn synchronize_states(install: &Installation, archived: &[state::Id]) -> Result<(), Error> {
+    let Some(esp) = esp(install) else {
+        return Ok(());
+    };
+
+    let entries_dir = esp.join(ENTRIES_DIR);
+    let assets_dir = esp.join(ASSETS_DIR);
+    fs::create_dir_all(&entries_dir)?;
+
+    let index_path = install.root_path(ASSET_INDEX);
+    let index = load_index(&index_path);
+
+    // Contents of each wanted entry, the source of each asset they refer to
+    // & the names of those sources for the next sync
+    let mut entries = BTreeMap::new();
+    let mut assets = BTreeMap::new();
+    let mut names = BTreeMap::new();
+
+    for id in archived {
+        let root = install.root_path(id.to_string());
+
+        for kernel in archived_kernels(&root)? {
+            let entry_id = format!("{STATE_ENTRY_PREFIX}{id}-{}", kernel.version);
+
+            let mut esp_paths = vec![];
+            for file in kernel.files() {
+                let source = Source::new(file)?;
+                let asset = asset_name(&source, &index)?;
+
+                esp_paths.push(format!("/{ASSETS_DIR}/{}/{asset}", kernel.version));
+                assets.insert(assets_dir.join(&kernel.version).join(&asset), source.clone());
+                names.insert(source, asset);
+            }
+
+            let mut options = kernel.cmdline.clone();
+            options.push(format!("{STATE_ARGUMENT}={id}"));
+
+            let title = os_name(&root).unwrap_or_else(|| "Serpent OS".to_owned());
+            let mut lines = vec![
+                format!("title {title} (state {id})"),
+                format!("version {}", kernel.version),
+                format!("linux {}", esp_paths[0]),
+            ];
+            lines.extend(esp_paths[1..].iter().map(|initrd| format!("initrd {initrd}")));
+            lines.push(format!("options {}", options.join(" ")));
+
+            entries.insert(entry_id, lines.join("\n") + "\n");
+        }
+    }
+
+    // Remove entries of active, pruned & removed states
+    for file in read_dir_if_exists(&entries_dir)? {
+        let path = file.path();
+        let Some(id) = path.file_name().and_then(|name| name.to_str()?.strip_suffix(".conf")) else {
+            continue;
+        };
+
+        if id.starts_with(STATE_ENTRY_PREFIX) && !entries.contains_key(id) {
+            fs::remove_file(&path)?;
+        }
+    }
+
+    // Followed by the kernels no entry refers to anymore, making room for new ones
+    for version in read_dir_if_exists(&assets_dir)? {
+        for file in read_dir_if_exists(&version.path())? {
+            if !assets.contains_key(&file.path()) {
+                fs::remove_file(file.path())?;
+            }
+        }
+        // Only succeeds once empty
+        let _ = fs::remove_dir(version.path());
+    }
+
+    // A copy of the same size is the one we wrote, as names are content addressed
+    let missing = assets
+        .iter()
+        .filter(|(target, source)| !target.metadata().is_ok_and(|metadata| metadata.len() == source.size))
+        .collect::<Vec<_>>();
+
+    let required = missing.iter().map(|(_, source)| source.size).sum::<u64>();
+    if required > 0 {
+        let available = space::available(&esp)?;
+        if required > available {
+            return Err(Error::NoSpace(space::Requirement {
+                path: esp,
+                required,
+                available,
+            }));
+        }
+    }
+
+    for (target, source) in missing {
+        install_asset(&source.path, target)?;
+    }
+    for (id, contents) in &entries {
+        fs::write(entries_dir.join(format!("{id}.conf")), contents)?;
+    }
+    write_index(&index_path, &names)?;
+
+    // Don't leave the bootloader defaulting to an entry we just removed
+    if let Some(default) = default_entry(&esp)? {
+        if default.starts_with(STATE_ENTRY_PREFIX) && !entries.contains_key(&default) {
+            write_default_entry(&esp, None)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Kernel tree found in an archived state
+#[derive(Debug)]
+struct ArchivedKernel {
+    version: String,
+    image: PathBuf,
+    initrds: Vec<PathBuf>,
+    cmdline: Vec<String>,
+}
+
+impl ArchivedKernel {
+    /// Kernel image followed by its initrds
+    fn files(&self) -> impl Iterator<Item = &Path> {
+        [self.image.as_path()]
+            .into_iter()
+            .chain(self.initrds.iter().map(PathBuf::as_path))
+    }
+}
+
+/// Kernels in `usr/lib/kernel/$version` of the archived state `root`
+fn archived_kernels(root: &Path) -> Result<Vec<ArchivedKernel>, Error> {
+    let mut kernels = vec![];
+
+    // Shared arguments, as used by `kernel-install`
+    let global_cmdline = fs::read_to_string(root.join("etc/kernel/cmdline")).unwrap_or_default();
+
+    for dir in read_dir_if_exists(&root.join("usr/lib/kernel"))? {
+        let dir = dir.path();
+        let image = dir.join("vmlinuz");
+
+        let Some(version) = dir.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        if !image.exists() {
+            continue;
+        }
+
+        let mut initrds = read_dir_if_exists(&dir)?
+            .into_iter()
+            .map(|file| file.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "initrd"))
+            .collect::<Vec<_>>();
+        initrds.sort();
+
+        let cmdline = global_cmdline
+            .split_whitespace()
+            .chain(
+                fs::read_to_string(dir.join("cmdline"))
+                    .unwrap_or_default()
+                    .split_whitespace(),
+            )
+            .map(str::to_owned)
+            .collect();
+
+        kernels.push(ArchivedKernel {
+            version: version.to_owned(),
+            image,
+            initrds,
+            cmdline,
+        });
+    }
+
+    Ok(kernels)
+}
+
+/// A kernel file of an archived state, as found when last synchronized
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Source {
+    path: PathBuf,
+    size: u64,
+    /// Modification time, in seconds & nanoseconds
+    modified: (i64, i64),
+}
+
+impl Source {
+    fn new(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            size: metadata.len(),
+            modified: (metadata.mtime(), metadata.mtime_nsec()),
+        })
+    }
+}
+
+/// Name of `source` within the ESP. Names are content addressed, so states
+/// sharing a kernel share its copy. Sources `index`ed with the same size &
+/// modification time keep their name rather than being read & hashed again
+fn asset_name(source: &Source, index: &BTreeMap<Source, String>) -> io::Result<String> {
+    if let Some(name) = index.get(source) {
+        return Ok(name.clone());
+    }
+
+    let stem = source
+        .path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("vmlinuz");
+
+    Ok(format!("{:016x}-{stem}", xxh3_64(&fs::read(&source.path)?)))
+}
+
+/// Copy `file` to `target` within the ESP
+fn install_asset(file: &Path, target: &Path) -> Result<(), Error> {
+    let (Some(dir), Some(name)) = (target.parent(), target.file_name()) else {
+        return Ok(());
+    };
+    fs::create_dir_all(dir)?;
+
+    // The ESP may lose power at any point, don't leave a truncated kernel behind
+    let partial = dir.join(format!(".{}.partial", name.to_string_lossy()));
+    fs::copy(file, &partial)?;
+    fs::rename(partial, target)?;
+
+    Ok(())
+}
+
+/// Asset names of the sources of the last sync, one tab separated
+/// line of size, modification time, name & path each
+fn load_index(path: &Path) -> BTreeMap<Source, String> {
+    let contents = fs::read_to_string(path).unwrap_or_default();
+
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(5, '\t');
+
+            let size = fields.next()?.parse().ok()?;
+            let modified = (fields.next()?.parse().ok()?, fields.next()?.parse().ok()?);
+            let name = fields.next()?.to_owned();
+            let path = PathBuf::from(fields.next()?);
+
+            Some((Source { path, size, modified }, name))
+        })
+        .collect()
+}
+
+fn write_index(path: &Path, names: &BTreeMap<Source, String>) -> io::Result<()> {
+    let contents = names
+        .iter()
+        .map(|(source, name)| {
+            format!(
+                "{}\t{}\t{}\t{name}\t{}\n",
+                source.size,
+                source.modified.0,
+                source.modified.1,
+                source.path.display()
+            )
+        })
+        .collect::<String>();
+
+    fs::write(path, contents)
+}
+
+/// `PRETTY_NAME` recorded in the os-release of a state root
+fn os_name(root: &Path) -> Option<String> {
+    let contents = fs::read_to_string(root.join("usr/lib/os-release")).ok()?;
+
+    contents.lines().find_map(|line| {
+        let name = line.strip_prefix("PRETTY_NAME=")?.trim_matches('"');
+        // The state is part of our entry title already
+        Some(name.split(" (fstx").next().unwrap_or(name).to_owned())
+    })
+}
+
+/// Entry the bootloader is configured to select by default
+fn default_entry(esp: &Path) -> Result<Option<String>, Error> {
+    let contents = match fs::read_to_string(esp.join(LOADER_CONF)) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error.into()),
+    };
+
+    Ok(parse_default(&contents))
+}
+
+fn parse_default(loader_conf: &str) -> Option<String> {
+    loader_conf.lines().find_map(|line| {
+        let (key, value) = line.trim().split_once(char::is_whitespace)?;
+        let value = value.trim();
+
+        (key == "default").then(|| value.strip_suffix(".conf").unwrap_or(value).to_owned())
+    })
+}
+
+/// Point the `default` of the bootloader to entry `id`, or drop it
+/// to let the bootloader pick one itself, retaining all other settings
+fn write_default_entry(esp: &Path, id: Option<&str>) -> Result<(), Error> {
+    let path = esp.join(LOADER_CONF);
+
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(error) => return Err(error.into()),
+    };
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, replace_default(&contents, id))?;
+
+    Ok(())
+}
+
+fn replace_default(loader_conf: &str, id: Option<&str>) -> String {
+    let is_default = |line: &str| line.split_whitespace().next() == Some("default");
+
+    let mut lines = id
+        .map(|id| format!("default {id}.conf"))
+        .into_iter()
+        .chain(loader_conf.lines().filter(|line| !is_default(line)).map(str::to_owned))
+        .collect::<Vec<_>>()
+        .join("\n");
+    lines.push('\n');
+
+    lines
+}
+
+fn read_dir_if_exists(dir: &Path) -> Result<Vec<fs::DirEntry>, Error> {
+    match fs::read_dir(dir) {
+        Ok(entries) => Ok(entries.collect::<Result<_, _>>()?),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(error) => Err(error.into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_entry() {
+        let entry = Entry::parse(
+            "moss-state-4-6.9.1",
+            "title Serpent OS (state 4)\nversion 6.9.1\nlinux /EFI/moss/6.9.1/0-vmlinuz\noptions quiet moss.fstx=4\n",
+        );
+
+        assert_eq!(entry.title.as_deref(), Some("Serpent OS (state 4)"));
+        assert_eq!(entry.version.as_deref(), Some("6.9.1"));
+        assert_eq!(entry.state, Some(state::Id::from(4)));
+
+        let entry = Entry::parse("other", "title Other\noptions quiet\n");
+        assert_eq!(entry.state, None);
+    }
+
+    #[test]
+    fn loader_default() {
+        assert_eq!(
+            parse_default("timeout 3\ndefault moss-state-2-6.9.conf\n").as_deref(),
+            Some("moss-state-2-6.9")
+        );
+        assert_eq!(parse_default("timeout 3\n"), None);
+
+        assert_eq!(
+            replace_default("timeout 3\ndefault old.conf\n", Some("new")),
+            "default new.conf\ntimeout 3\n"
+        );
+        assert_eq!(replace_default("default old.conf\ntimeout 3", None), "timeout 3\n");
+    }
+
+    #[test]
+    fn synchronize_archived() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("efi/EFI")).unwrap();
+        let install = Installation::open(root).unwrap();
+
+        let kernel = install.root_path("3/usr/lib/kernel/6.9.1");
+        fs::create_dir_all(&kernel).unwrap();
+        fs::write(kernel.join("vmlinuz"), b"kernel").unwrap();
+        fs::write(kernel.join("10-default.initrd"), b"initrd").unwrap();
+        fs::write(kernel.join("cmdline"), "quiet").unwrap();
+
+        // Left behind by a pruned state, and still the default
+        let esp = root.join("efi");
+        fs::create_dir_all(esp.join(ENTRIES_DIR)).unwrap();
+        fs::write(esp.join(ENTRIES_DIR).join("moss-state-1-6.8.conf"), "").unwrap();
+        fs::create_dir_all(esp.join(ASSETS_DIR).join("6.8")).unwrap();
+        fs::write(esp.join(ASSETS_DIR).join("6.8/0-vmlinuz"), b"old").unwrap();
+        write_default_entry(&esp, Some("moss-state-1-6.8")).unwrap();
+
+        synchronize_states(&install, &[3.into()]).unwrap();
+
+        let image = format!("{:016x}-vmlinuz", xxh3_64(b"kernel"));
+        let initrd = format!("{:016x}-10-default.initrd", xxh3_64(b"initrd"));
+        assert_eq!(
+            fs::read_to_string(esp.join(ENTRIES_DIR).join("moss-state-3-6.9.1.conf")).unwrap(),
+            format!(
+                "title Serpent OS (state 3)\nversion 6.9.1\nlinux /EFI/moss/6.9.1/{image}\n\
+                 initrd /EFI/moss/6.9.1/{initrd}\noptions quiet moss.fstx=3\n"
+            )
+        );
+        assert_eq!(
+            fs::read(esp.join(ASSETS_DIR).join("6.9.1").join(&image)).unwrap(),
+            b"kernel"
+        );
+        assert!(!esp.join(ENTRIES_DIR).join("moss-state-1-6.8.conf").exists());
+        assert!(!esp.join(ASSETS_DIR).join("6.8").exists());
+        assert_eq!(default_entry(&esp).unwrap(), None);
+
+        // Unchanged kernels keep their indexed name without being hashed again
+        let index_path = install.root_path(ASSET_INDEX);
+        let index = fs::read_to_string(&index_path).unwrap().replace(&image, "0-vmlinuz");
+        fs::write(&index_path, index).unwrap();
+        synchronize_states(&install, &[3.into()]).unwrap();
+        assert!(esp.join(ASSETS_DIR).join("6.9.1/0-vmlinuz").exists());
+        assert!(!esp.join(ASSETS_DIR).join("6.9.1").join(&image).exists());
+
+        // ... unlike changed ones
+        fs::write(kernel.join("vmlinuz"), b"rebuilt kernel").unwrap();
+        synchronize_states(&install, &[3.into()]).unwrap();
+        let rebuilt = format!("{:016x}-vmlinuz", xxh3_64(b"rebuilt kernel"));
+        assert!(esp.join(ASSETS_DIR).join("6.9.1").join(rebuilt).exists());
+        assert!(!esp.join(ASSETS_DIR).join("6.9.1/0-vmlinuz").exists());
+
+        // No archived states, no entries
+        synchronize_states(&install, &[]).unwrap();
+        assert!(read_dir_if_exists(&esp.join(ENTRIES_DIR)).unwrap().is_empty());
+        assert!(!esp.join(ASSETS_DIR).join("6.9.1").exists());
+    }
+}
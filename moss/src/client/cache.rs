@@ -3,25 +3,112 @@
 // SPDX-License-Identifier: MPL-2.0
 
 //! Cache management for unpacking remote assets (`.stone`, etc.)
+//!
+//! Fetched stones are kept in the download cache after unpacking, so reinstalling
+//! them or rolling back to a state referencing them doesn't hit the network again.
+//! It only grows over time though, see [`Settings`] to keep it in check.
 
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::{
-    io,
-    path::PathBuf,
-    sync::{Arc, Mutex},
+    env, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    thread,
 };
 
-use futures::StreamExt;
+use config::Config;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tokio::{
-    fs::{self, File},
-    io::AsyncWriteExt,
-};
+use tokio::fs;
 use url::Url;
+use xxhash_rust::xxh3::xxh3_64;
+
+use stone::{payload, read::PayloadKind, write::digest};
+
+use crate::{client::prune, environment, package, request, runtime, Installation};
+
+/// Download cache settings, stored as `etc/moss/cache.d/{name}.yaml`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Settings {
+    /// Once the download cache grows beyond this many MiB, prune it after
+    /// each transaction, keeping only stones referenced by a state
+    #[serde(default)]
+    pub prune_above_mib: Option<u64>,
+}
+
+impl Config for Settings {
+    fn domain() -> String {
+        "cache".into()
+    }
+}
+
+impl Settings {
+    /// Size beyond which the download cache is pruned, for the smallest configured threshold
+    pub fn prune_threshold(settings: &[Settings]) -> Option<u64> {
+        settings
+            .iter()
+            .filter_map(|settings| settings.prune_above_mib)
+            .min()
+            .map(|mib| mib * 1024 * 1024)
+    }
+}
+
+/// A file in the download cache
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cached {
+    pub path: PathBuf,
+    pub size: u64,
+    /// Hash of a completed download, [`None`] for interrupted
+    /// downloads & sideloaded stones
+    pub hash: Option<String>,
+}
+
+/// All files in the download cache, sorted by path
+pub fn cached(installation: &Installation) -> io::Result<Vec<Cached>> {
+    let downloads = installation.cache_path("downloads");
+    let sideloaded = installation.cache_path("sideloaded");
+
+    let mut files = prune::enumerate_files(&downloads)?
+        .into_iter()
+        .chain(prune::enumerate_files(&sideloaded)?)
+        .map(|path| {
+            let size = path.metadata()?.len();
+            let hash = (path.starts_with(&downloads) && path.extension().is_none())
+                .then(|| path.file_name().map(|name| name.to_string_lossy().into_owned()))
+                .flatten();
+
+            Ok(Cached { path, size, hash })
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(files)
+}
 
-use stone::{payload, read::PayloadKind};
+/// Remove `files` from the download cache along with any directories
+/// left empty, returning the number of bytes freed
+pub fn remove(installation: &Installation, files: &[Cached]) -> io::Result<u64> {
+    let root = installation.cache_path("");
+    let mut freed = 0;
+
+    for file in files {
+        match std::fs::remove_file(&file.path) {
+            Ok(()) => freed += file.size,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => return Err(error),
+        }
 
-use crate::{package, request, Installation};
+        if let Some(parent) = file.path.parent() {
+            let _ = prune::remove_empty_dirs(parent, &root);
+        }
+    }
+
+    Ok(freed)
+}
 
 /// Synchronized set of assets that are currently being
 /// unpacked. Used to prevent unpacking the same asset
@@ -44,6 +131,127 @@ impl UnpackingInProgress {
     }
 }
 
+/// Max number of threads unpacking content, see [`set_unpack_workers`]
+static UNPACK_WORKERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Environment variable overriding the configured [`unpack_workers`]
+pub const UNPACK_WORKERS_ENV: &str = "MOSS_UNPACK_WORKERS";
+
+/// Worker pool shared by all unpacking packages, so their concurrency
+/// isn't tied to that of the downloads
+static UNPACK_POOL: OnceLock<ThreadPool> = OnceLock::new();
+
+/// Limit the number of threads decompressing & extracting content,
+/// `0` to use all available cores. Must be called before the first unpack
+pub fn set_unpack_workers(workers: usize) {
+    UNPACK_WORKERS.store(workers, Ordering::Relaxed);
+}
+
+/// Max number of threads unpacking content, [`UNPACK_WORKERS_ENV`] taking
+/// precedence over [`set_unpack_workers`]
+pub fn unpack_workers() -> usize {
+    workers(
+        env::var(UNPACK_WORKERS_ENV).ok().as_deref(),
+        UNPACK_WORKERS.load(Ordering::Relaxed),
+    )
+}
+
+/// Number of unpack workers for the `overridden` & `configured` ones, `0` being all cores
+fn workers(overridden: Option<&str>, configured: usize) -> usize {
+    match overridden
+        .and_then(|workers| workers.trim().parse().ok())
+        .unwrap_or(configured)
+    {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        workers => workers,
+    }
+}
+
+fn unpack_pool() -> &'static ThreadPool {
+    UNPACK_POOL.get_or_init(|| {
+        ThreadPoolBuilder::new()
+            .num_threads(unpack_workers())
+            .thread_name(|i| format!("moss-unpack-{i}"))
+            .build()
+            .expect("build unpack worker pool")
+    })
+}
+
+/// Downloads are keyed purely by their content hash, so the same package
+/// advertised by multiple repositories maps to a single file in the cache.
+/// This lock table ensures concurrent fetches of one hash (i.e. from two
+/// different repositories in the same transaction) only hit the network once.
+static DOWNLOADS_IN_PROGRESS: OnceLock<Mutex<BTreeMap<String, Arc<tokio::sync::Mutex<()>>>>> = OnceLock::new();
+
+/// Returns the shared lock guarding downloads of the given hash
+fn download_lock(hash: &str) -> DownloadLock {
+    let lock = DOWNLOADS_IN_PROGRESS
+        .get_or_init(Default::default)
+        .lock()
+        .expect("mutex lock")
+        .entry(hash.to_string())
+        .or_default()
+        .clone();
+
+    DownloadLock {
+        hash: hash.to_string(),
+        lock,
+    }
+}
+
+/// Handle on the lock of a hash in [`DOWNLOADS_IN_PROGRESS`], removing
+/// it from the table once no other fetch holds or awaits it
+struct DownloadLock {
+    hash: String,
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl Drop for DownloadLock {
+    fn drop(&mut self) {
+        let mut downloads = DOWNLOADS_IN_PROGRESS
+            .get_or_init(Default::default)
+            .lock()
+            .expect("mutex lock");
+
+        // Handles are only taken out under the table's lock, so only
+        // the table's own reference may remain besides this one
+        if Arc::strong_count(&self.lock) == 2 {
+            downloads.remove(&self.hash);
+        }
+    }
+}
+
+/// Downloads up to this size are fetched ahead of larger ones
+const SMALL_DOWNLOAD: u64 = 512 * 1024;
+
+/// Order in which to fetch packages, given in install order as
+/// `(is_cached, download_size)`. Returns the indices of the packages.
+///
+/// Cached packages need no bandwidth and can be unpacked straight away,
+/// followed by small downloads which complete quickly. The rest keep
+/// their install order, so packages needed earliest arrive first.
+pub fn fetch_order(packages: impl IntoIterator<Item = (bool, Option<u64>)>) -> Vec<usize> {
+    let mut order = packages
+        .into_iter()
+        .enumerate()
+        .map(|(index, (is_cached, size))| {
+            let rank = if is_cached {
+                0
+            } else if size.is_some_and(|size| size <= SMALL_DOWNLOAD) {
+                1
+            } else {
+                2
+            };
+            (rank, index)
+        })
+        .collect::<Vec<_>>();
+
+    // Stable, so install order is kept within each rank
+    order.sort_by_key(|(rank, _)| *rank);
+
+    order.into_iter().map(|(_, index)| index).collect()
+}
+
 /// Per-package progress tracking for UI integration
 #[derive(Debug, Clone, Copy)]
 pub struct Progress {
@@ -68,55 +276,178 @@ pub async fn fetch(
     let url = meta.uri.as_ref().ok_or(Error::MissingUri)?.parse::<Url>()?;
     let hash = meta.hash.as_ref().ok_or(Error::MissingHash)?;
 
-    let download_path = download_path(installation, hash)?;
+    fetch_file(
+        meta.id().into(),
+        url,
+        hash,
+        meta.download_size,
+        installation,
+        on_progress,
+    )
+    .await
+}
 
-    if let Some(parent) = download_path.parent() {
-        fs::create_dir_all(parent).await?;
+/// Fetch the `delta` of a package with the provided [`package::Meta`] and return a [`Download`]
+/// which unpacks to the full package.
+///
+/// Fails with [`Error::DeltaIncomplete`] if content not carried by the delta is missing from
+/// the asset store (i.e. the release it applies to was pruned), in which case the full
+/// package must be fetched instead.
+pub async fn fetch_delta(
+    meta: &package::Meta,
+    delta: &package::Delta,
+    installation: &Installation,
+    on_progress: impl Fn(Progress),
+) -> Result<Download, Error> {
+    let url = delta.uri.parse::<Url>()?;
+
+    // Plan remote deltas against their index & layouts fetched with range
+    // requests, so an unusable one isn't downloaded in full
+    let remote =
+        matches!(url.scheme(), "http" | "https") && cached_download(installation, &delta.hash).await?.is_none();
+    if remote {
+        let applies = runtime::unblock({
+            let url = url.clone();
+            let installation = installation.clone();
+            move || delta_applies(request::RangedReader::open(url)?, &installation)
+        })
+        .await?;
+
+        if !applies {
+            return Err(Error::DeltaIncomplete);
+        }
+    }
+
+    let download = fetch_file(
+        meta.id().into(),
+        url,
+        &delta.hash,
+        Some(delta.size),
+        installation,
+        on_progress,
+    )
+    .await?;
+
+    if remote {
+        return Ok(download);
+    }
+
+    let applies = runtime::unblock({
+        let path = download.path.clone();
+        let installation = installation.clone();
+        move || delta_applies(std::fs::File::open(path)?, &installation)
+    })
+    .await?;
+
+    if applies {
+        Ok(download)
+    } else {
+        Err(Error::DeltaIncomplete)
     }
+}
+
+/// Fetch the file at `url` into the download cache, verifying it against `hash`
+async fn fetch_file(
+    id: package::Id,
+    url: Url,
+    hash: &str,
+    size: Option<u64>,
+    installation: &Installation,
+    on_progress: impl Fn(Progress),
+) -> Result<Download, Error> {
+    let download_path = download_path(installation, hash)?;
 
-    if fs::try_exists(&download_path).await? {
+    // Hold the lock for this hash until the download is complete, any other
+    // fetch of the same hash will then find it cached
+    let lock = download_lock(hash);
+    let _guard = lock.lock.lock().await;
+
+    if let Some(cached) = cached_download(installation, hash).await? {
         return Ok(Download {
-            id: meta.id().into(),
-            path: download_path,
+            id,
+            path: cached,
             installation: installation.clone(),
             was_cached: true,
+            downloaded: 0,
         });
     }
 
-    let mut bytes = request::get(url).await?;
-    let mut out = File::create(&download_path).await?;
-
-    let mut total = 0;
+    if let Some(parent) = download_path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
 
-    while let Some(chunk) = bytes.next().await {
-        let bytes = chunk?;
-        let delta = bytes.len() as u64;
-        total += delta;
-        out.write_all(&bytes).await?;
+    // Only verified downloads are promoted into the cache, an interrupted
+    // one is resumed by the next fetch
+    let completed = AtomicU64::new(0);
+    let verified = request::get_verified(url, Some(hash), &download_path, |delta| {
+        let completed = completed.fetch_add(delta, Ordering::Relaxed) + delta;
 
         (on_progress)(Progress {
             delta,
-            completed: total,
-            total: meta.download_size.unwrap_or(total),
+            completed,
+            total: size.unwrap_or(completed),
         });
-    }
-
-    out.flush().await?;
+    })
+    .await?;
 
     Ok(Download {
-        id: meta.id().into(),
+        id,
         path: download_path,
         installation: installation.clone(),
         was_cached: false,
+        downloaded: verified.size - verified.resumed,
     })
 }
 
+/// Fetch a stone which isn't part of any repository, i.e. to sideload it, into the cache.
+/// Without a known hash it's fetched again each time, rather than resuming a previous
+/// attempt which may have been of different contents. It's keyed by the full url, as
+/// different urls may well share the file name.
+pub async fn fetch_sideloaded(url: &Url, installation: &Installation) -> Result<PathBuf, Error> {
+    let name = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| Error::MissingFileName(url.clone()))?;
+
+    let dir = installation.cache_path("sideloaded");
+    fs::create_dir_all(&dir).await?;
+
+    let path = dir.join(format!("{:016x}-{name}", xxh3_64(url.as_str().as_bytes())));
+
+    match fs::remove_file(request::partial_path(&path)).await {
+        Ok(()) => {}
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+        Err(error) => return Err(error.into()),
+    }
+
+    request::get_verified(url.clone(), None, &path, |_| {}).await?;
+
+    Ok(path)
+}
+
+/// Returns the path of a previously completed download of the given hash, if any.
+///
+/// The download cache is shared by all repositories, so this may return a package
+/// originally fetched from a different repository than the one being consulted.
+pub async fn cached_download(installation: &Installation, hash: &str) -> Result<Option<PathBuf>, Error> {
+    let path = download_path(installation, hash)?;
+
+    if fs::try_exists(&path).await? {
+        Ok(Some(path))
+    } else {
+        Ok(None)
+    }
+}
+
 /// A package that has been downloaded to the installation
 pub struct Download {
     id: package::Id,
     path: PathBuf,
     installation: Installation,
     pub was_cached: bool,
+    /// Bytes fetched over the network for this download
+    pub downloaded: u64,
 }
 
 /// Upon fetch completion we have this unpacked asset bound with
@@ -126,15 +457,44 @@ pub struct UnpackedAsset {
 }
 
 impl Download {
+    /// A stone which is already available locally, i.e. sideloaded
+    pub fn local(id: package::Id, path: &Path, installation: &Installation) -> Self {
+        Self {
+            id,
+            path: path.to_path_buf(),
+            installation: installation.clone(),
+            was_cached: false,
+            downloaded: 0,
+        }
+    }
+
+    /// Path of the downloaded stone
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
     /// Unpack the downloaded package
+    ///
+    /// Runs on the shared unpack worker pool, see [`set_unpack_workers`]. The content
+    /// payload is a single compressed stream, so each asset is extracted & verified by
+    /// another worker as soon as its range is decompressed, rather than once all of it is.
     // TODO: Return an "Unpacked" struct which has a "blit" method on it?
     pub fn unpack(
         self,
         unpacking_in_progress: UnpackingInProgress,
         on_progress: impl Fn(Progress) + Send + 'static,
     ) -> Result<UnpackedAsset, Error> {
+        unpack_pool().install(|| self.unpack_inner(unpacking_in_progress, on_progress))
+    }
+
+    fn unpack_inner(
+        self,
+        unpacking_in_progress: UnpackingInProgress,
+        on_progress: impl Fn(Progress),
+    ) -> Result<UnpackedAsset, Error> {
+        use std::cell::RefCell;
         use std::fs::{create_dir_all, remove_file, File};
-        use std::io::{copy, Read, Seek, SeekFrom, Write};
+        use std::io::Write;
 
         struct ProgressWriter<'a, W> {
             writer: W,
@@ -179,9 +539,9 @@ impl Download {
 
         create_dir_all(&content_dir)?;
 
-        let mut reader = stone::read(File::open(&self.path)?)?;
-
-        let payloads = reader.payloads()?.collect::<Result<Vec<_>, _>>()?;
+        let mut stream = stone::stream_payloads(File::open(&self.path)?, environment::PAYLOAD_READ_AHEAD)?;
+        let payloads = stream.by_ref().collect::<Result<Vec<_>, _>>()?;
+        let mut reader = stream.into_reader();
         let indices = payloads
             .iter()
             .filter_map(PayloadKind::index)
@@ -206,53 +566,162 @@ impl Download {
             .truncate(true)
             .open(&content_path)?;
 
-        reader.unpack_content(
-            content,
-            &mut ProgressWriter::new(&content_file, content.header.plain_size, &on_progress),
-        )?;
+        // Extract assets in order of where they end, so each is handed to a worker as soon
+        // as it's fully decompressed, overlapping decompression with hashing & writing them out
+        let mut pending = indices;
+        pending.sort_by_key(|idx| idx.end);
+
+        let failed = Mutex::new(None);
+        let unpacked = rayon::in_place_scope(|scope| {
+            let pending = RefCell::new(pending.into_iter().peekable());
+            let (content_file, installation) = (&content_file, &self.installation);
+            let (unpacking_in_progress, failed) = (&unpacking_in_progress, &failed);
+
+            let dispatch = |written: u64| {
+                while let Some(idx) = pending.borrow_mut().next_if(|idx| idx.end <= written) {
+                    scope.spawn(move |_| {
+                        if let Err(error) = extract_asset(content_file, idx, installation, unpacking_in_progress) {
+                            failed.lock().expect("mutex lock").get_or_insert(error);
+                        }
+                    });
+                }
+            };
+            let on_progress = |progress: Progress| {
+                on_progress(progress);
+                dispatch(progress.completed);
+            };
+
+            let unpacked = reader.unpack_content(
+                content,
+                &mut ProgressWriter::new(content_file, content.header.plain_size, &on_progress),
+            );
+            // Anything left, i.e. when there was no content to decompress at all
+            if unpacked.is_ok() {
+                dispatch(u64::MAX);
+            }
 
-        indices
-            .into_iter()
-            .map(|idx| {
-                let path = asset_path(&self.installation, &format!("{:02x}", idx.digest));
+            unpacked
+        });
 
-                // If file is already being unpacked by another worker, skip
-                // to prevent clobbering IO
-                if !unpacking_in_progress.add(path.clone()) {
-                    return Ok(());
-                }
+        // Whatever was extracted early has been verified against its own digest
+        unpacked?;
+        if let Some(error) = failed.into_inner().expect("mutex lock") {
+            return Err(error);
+        }
 
-                // This asset already exists
-                if path.exists() {
-                    unpacking_in_progress.remove(&path);
-                    return Ok(());
-                }
+        remove_file(&content_path)?;
 
-                // Create parent dir
-                if let Some(parent) = path.parent() {
-                    create_dir_all(parent)?;
-                }
+        Ok(UnpackedAsset { payloads })
+    }
+}
+
+/// Extract the asset at `idx` of the unpacked `content`, unless it already
+/// exists or another worker is extracting it
+fn extract_asset(
+    content: &std::fs::File,
+    idx: &payload::Index,
+    installation: &Installation,
+    unpacking_in_progress: &UnpackingInProgress,
+) -> Result<(), Error> {
+    use std::fs::{create_dir_all, remove_file, rename, File};
+    use std::io::{copy, Read};
+    use std::os::unix::fs::FileExt;
+
+    /// Reads a range of a file shared by all workers, which
+    /// mustn't move its cursor from under the others
+    struct RangeReader<'a> {
+        file: &'a File,
+        offset: u64,
+        end: u64,
+    }
+
+    impl<'a> Read for RangeReader<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let remaining = (self.end - self.offset).min(buf.len() as u64) as usize;
+            if remaining == 0 {
+                return Ok(0);
+            }
+            let read = self.file.read_at(&mut buf[..remaining], self.offset)?;
+            self.offset += read as u64;
+            Ok(read)
+        }
+    }
 
-                // Split file reader over index range
-                let mut file = &content_file;
-                file.seek(SeekFrom::Start(idx.start))?;
-                let mut split_file = (&mut file).take(idx.end - idx.start);
+    let path = asset_path(installation, &format!("{:02x}", idx.digest));
 
-                let mut output = File::create(&path)?;
+    // If file is already being unpacked by another worker, skip
+    // to prevent clobbering IO
+    if !unpacking_in_progress.add(path.clone()) {
+        return Ok(());
+    }
 
-                copy(&mut split_file, &mut output)?;
+    let result = (|| {
+        // This asset already exists
+        if path.exists() {
+            return Ok(());
+        }
 
-                // Remove file from in-progress
-                unpacking_in_progress.remove(&path);
+        // Create parent dir
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
 
-                Ok(())
-            })
-            .collect::<Result<Vec<_>, Error>>()?;
+        // Only complete assets may appear at `path`, as existing ones are never rewritten
+        let partial_path = path.with_extension("tmp");
+        let mut output = File::create(&partial_path)?;
+        let mut hasher = digest::Hasher::new();
+
+        copy(
+            &mut RangeReader {
+                file: content,
+                offset: idx.start,
+                end: idx.end,
+            },
+            &mut digest::Writer::new(&mut output, &mut hasher),
+        )?;
+        drop(output);
 
-        remove_file(&content_path)?;
+        if hasher.digest128() != idx.digest {
+            remove_file(&partial_path)?;
+            return Err(Error::CorruptAsset(idx.digest));
+        }
 
-        Ok(UnpackedAsset { payloads })
-    }
+        rename(&partial_path, &path)?;
+
+        Ok(())
+    })();
+
+    // Remove file from in-progress
+    unpacking_in_progress.remove(&path);
+
+    result
+}
+
+/// Returns true if all content of the delta package read from `reader`
+/// is either carried by it, or already exists in the installation
+fn delta_applies<R>(reader: R, installation: &Installation) -> Result<bool, Error>
+where
+    R: io::Read + io::Seek + Send + 'static,
+{
+    let payloads = stone::stream_payloads(reader, environment::PAYLOAD_READ_AHEAD)?.collect::<Result<Vec<_>, _>>()?;
+
+    let carried = payloads
+        .iter()
+        .filter_map(PayloadKind::index)
+        .flat_map(|p| &p.body)
+        .map(|index| index.digest)
+        .collect::<BTreeSet<_>>();
+
+    Ok(payloads
+        .iter()
+        .filter_map(PayloadKind::layout)
+        .flat_map(|p| &p.body)
+        .all(|layout| match &layout.entry {
+            payload::layout::Entry::Regular(digest, _) => {
+                carried.contains(digest) || asset_path(installation, &format!("{digest:02x}")).exists()
+            }
+            _ => true,
+        }))
 }
 
 /// Returns true if all assets already exist in the installation
@@ -301,8 +770,14 @@ pub enum Error {
     MissingUri,
     #[error("Missing content payload")]
     MissingContent,
+    #[error("Delta package doesn't apply, content of the previous release is missing")]
+    DeltaIncomplete,
+    #[error("No file name in {0}")]
+    MissingFileName(Url),
     #[error("Malformed download hash: {0}")]
     MalformedHash(String),
+    #[error("Content of asset {0:02x} doesn't match its digest")]
+    CorruptAsset(u128),
     #[error("stone format")]
     Format(#[from] stone::read::Error),
     #[error("invalid url")]
@@ -312,3 +787,191 @@ pub enum Error {
     #[error("io")]
     Io(#[from] io::Error),
 }
+
+#[cfg(test)]
+mod test {
+    use xxhash_rust::xxh3::xxh3_128;
+
+    use super::*;
+    use crate::package::delta::test::write_stone;
+
+    #[test]
+    fn deltas() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let root = dir.join("root");
+        std::fs::create_dir_all(&root).unwrap();
+        let installation = Installation::open(&root).unwrap();
+
+        let (from, to, path) = (dir.join("old.stone"), dir.join("new.stone"), dir.join("delta.stone"));
+        write_stone(&from, "tool", 1, &[("bin/tool", b"old tool"), ("share/doc", b"docs")]);
+        write_stone(&to, "tool", 2, &[("bin/tool", b"new tool"), ("share/doc", b"docs")]);
+        let produced = package::delta::write(&from, &to, &path).unwrap();
+
+        let applies = || delta_applies(std::fs::File::open(&path).unwrap(), &installation).unwrap();
+        let asset = |content: &[u8]| asset_path(&installation, &format!("{:02x}", xxh3_128(content)));
+
+        // The docs the delta doesn't carry haven't been unpacked yet
+        assert!(!applies());
+
+        let docs = asset(b"docs");
+        std::fs::create_dir_all(docs.parent().unwrap()).unwrap();
+        std::fs::write(&docs, b"docs").unwrap();
+        assert!(applies());
+
+        let mut hasher = hash::Digest::new(hash::Algorithm::Sha256);
+        io::copy(&mut std::fs::File::open(&path).unwrap(), &mut hasher).unwrap();
+        let delta = package::Delta {
+            from: produced.from,
+            hash: hasher.finalize_hex(),
+            size: std::fs::metadata(&path).unwrap().len(),
+            uri: Url::from_file_path(&path).unwrap().to_string(),
+        };
+        let meta = package::diff::Contents::read(&to).unwrap().meta;
+
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+
+        let download = runtime
+            .block_on(fetch_delta(&meta, &delta, &installation, |_| {}))
+            .unwrap();
+        download.unpack(UnpackingInProgress::default(), |_| {}).unwrap();
+        assert!(asset(b"new tool").exists());
+
+        // Once the older release's content is gone, the full package must be fetched
+        std::fs::remove_file(&docs).unwrap();
+        let result = runtime.block_on(fetch_delta(&meta, &delta, &installation, |_| {}));
+        assert!(matches!(result, Err(Error::DeltaIncomplete)));
+    }
+
+    #[test]
+    fn sideloaded_by_url() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let root = dir.join("root");
+        std::fs::create_dir_all(&root).unwrap();
+        let installation = Installation::open(&root).unwrap();
+
+        // Same file name, different urls
+        let url = |name: &str, content: &[u8]| {
+            let source = dir.join(name).join("tool.stone");
+            std::fs::create_dir_all(source.parent().unwrap()).unwrap();
+            std::fs::write(&source, content).unwrap();
+            Url::from_file_path(source).unwrap()
+        };
+        let (a, b) = (url("a", b"first tool"), url("b", b"second tool"));
+
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let fetch = |url: &Url| runtime.block_on(fetch_sideloaded(url, &installation)).unwrap();
+
+        let (first, second) = (fetch(&a), fetch(&b));
+        assert_ne!(first, second);
+        assert_eq!(std::fs::read(&first).unwrap(), b"first tool");
+        assert_eq!(std::fs::read(&second).unwrap(), b"second tool");
+
+        // Nothing to verify a leftover part against, so it isn't resumed
+        std::fs::write(request::partial_path(&first), b"stale").unwrap();
+        assert_eq!(fetch(&a), first);
+        assert_eq!(std::fs::read(&first).unwrap(), b"first tool");
+    }
+
+    #[test]
+    fn unpacks_assets() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let root = dir.join("root");
+        std::fs::create_dir_all(&root).unwrap();
+        let installation = Installation::open(&root).unwrap();
+
+        let path = dir.join("tool.stone");
+        let files: &[(&str, &[u8])] = &[
+            ("bin/tool", b"tool"),
+            ("lib/libtool.so", &[0xab; 256 * 1024]),
+            ("share/doc", b"docs"),
+            ("share/empty", b""),
+        ];
+        write_stone(&path, "tool", 1, files);
+
+        let completed = Arc::new(AtomicU64::new(0));
+        let download = Download {
+            id: package::Id::from("tool".to_string()),
+            path,
+            installation: installation.clone(),
+            was_cached: false,
+            downloaded: 0,
+        };
+        download
+            .unpack(UnpackingInProgress::default(), {
+                let completed = completed.clone();
+                move |progress| completed.store(progress.completed, Ordering::Relaxed)
+            })
+            .unwrap();
+
+        for (_, content) in files {
+            let asset = asset_path(&installation, &format!("{:02x}", xxh3_128(content)));
+            assert_eq!(std::fs::read(asset).unwrap(), *content);
+        }
+        assert_eq!(completed.load(Ordering::Relaxed), 256 * 1024 + 8);
+        assert!(!installation.cache_path("content").join("tool").exists());
+    }
+
+    #[test]
+    fn unpack_workers_override() {
+        let all = thread::available_parallelism().map_or(1, |n| n.get());
+
+        assert_eq!(workers(None, 0), all);
+        assert_eq!(workers(None, 3), 3);
+        assert_eq!(workers(Some("2"), 3), 2);
+        assert_eq!(workers(Some("0"), 3), all);
+        assert_eq!(workers(Some("many"), 3), 3);
+    }
+
+    #[test]
+    fn download_locks_released() {
+        let in_progress = |hash: &str| {
+            DOWNLOADS_IN_PROGRESS
+                .get_or_init(Default::default)
+                .lock()
+                .unwrap()
+                .contains_key(hash)
+        };
+
+        let first = download_lock("released");
+        let second = download_lock("released");
+        assert!(Arc::ptr_eq(&first.lock, &second.lock));
+
+        drop(first);
+        assert!(in_progress("released"));
+        drop(second);
+        assert!(!in_progress("released"));
+    }
+
+    #[test]
+    fn order() {
+        let packages = [
+            (false, Some(40 * 1024 * 1024)),
+            (false, Some(12 * 1024)),
+            (true, Some(80 * 1024 * 1024)),
+            (false, None),
+            (false, Some(2 * 1024 * 1024)),
+            (false, Some(1024)),
+        ];
+
+        assert_eq!(fetch_order(packages), vec![2, 1, 5, 0, 3, 4]);
+    }
+
+    #[test]
+    fn prune_threshold() {
+        let settings = [
+            Settings { prune_above_mib: None },
+            Settings {
+                prune_above_mib: Some(512),
+            },
+            Settings {
+                prune_above_mib: Some(2048),
+            },
+        ];
+
+        assert_eq!(Settings::prune_threshold(&settings), Some(512 * 1024 * 1024));
+        assert_eq!(Settings::prune_threshold(&settings[..1]), None);
+    }
+}
@@ -0,0 +1,304 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Sanity checks run before a transaction is applied
+//!
+//! Each [`Check`] inspects the pending transaction and may veto it, with
+//! a human readable reason. Only packages whose name is gone from the new
+//! state count as removed, so upgrades are never vetoed. Vetoes can be
+//! overridden with `--skip-checks`. Built-in checks are always run, while additional
+//! checks can be registered by dropping yaml files into
+//! `/usr/share/moss/check.d/` or `/etc/moss/check.d/`:
+//!
+//! ```yaml
+//! name: display-manager
+//! reason: would remove the active display manager
+//! providers:
+//!   - binary(gdm)
+//! ```
+use std::{fmt, fs, str::FromStr};
+
+use serde::Deserialize;
+use stone::payload::Layout;
+use thiserror::Error;
+use tracing::warn;
+use tui::Styled;
+
+use crate::{output, package, Installation, Package, Provider};
+
+/// A user registered check, vetoing removal of any package providing
+/// one of the configured providers, unless another package still provides it
+#[derive(Debug, Clone, Deserialize)]
+pub struct Protected {
+    pub name: String,
+    pub reason: String,
+    #[serde(default)]
+    pub providers: Vec<String>,
+}
+
+impl config::Config for Protected {
+    fn domain() -> String {
+        "check".into()
+    }
+}
+
+/// A check which can veto a transaction before it's applied
+#[derive(Debug, Clone)]
+pub enum Check {
+    /// Refuse to remove the kernel that is currently booted
+    BootedKernel,
+    /// User registered check loaded from config
+    Protected(Protected),
+}
+
+impl Check {
+    /// Name of this check, shown alongside any veto it raises
+    pub fn name(&self) -> &str {
+        match self {
+            Check::BootedKernel => "booted-kernel",
+            Check::Protected(protected) => &protected.name,
+        }
+    }
+
+    /// Run this check against the transaction, returning all vetoes raised
+    pub fn run(&self, transaction: &Transaction<'_>) -> Vec<Veto> {
+        match self {
+            Check::BootedKernel => {
+                // Only meaningful when operating on the live system
+                if transaction.installation.root.to_string_lossy() != "/" {
+                    return vec![];
+                }
+                let Some(release) = booted_kernel() else {
+                    return vec![];
+                };
+
+                let kernel_dir = format!("lib/kernel/{release}/");
+                let modules_dir = format!("lib/modules/{release}/");
+
+                transaction
+                    .removed
+                    .iter()
+                    .filter(|package| {
+                        transaction.layouts.iter().any(|(id, layout)| {
+                            let target = layout.entry.target();
+                            *id == package.id && (target.starts_with(&kernel_dir) || target.starts_with(&modules_dir))
+                        })
+                    })
+                    .map(|package| self.veto(package, format!("kernel {release} is currently booted")))
+                    .collect()
+            }
+            Check::Protected(protected) => {
+                let providers = protected
+                    .providers
+                    .iter()
+                    .filter_map(|provider| match Provider::from_str(provider) {
+                        Ok(provider) => Some(provider),
+                        Err(error) => {
+                            warn!("invalid provider {provider:?} in check {}: {error}", protected.name);
+                            None
+                        }
+                    })
+                    .collect::<Vec<_>>();
+
+                // Providers which stay around, i.e. switching to another display manager
+                let remaining = |provider: &Provider| {
+                    transaction
+                        .installed
+                        .iter()
+                        .any(|package| package.meta.providers.contains(provider))
+                };
+
+                transaction
+                    .removed
+                    .iter()
+                    .filter(|package| {
+                        providers
+                            .iter()
+                            .any(|p| package.meta.providers.contains(p) && !remaining(p))
+                    })
+                    .map(|package| self.veto(package, protected.reason.clone()))
+                    .collect()
+            }
+        }
+    }
+
+    fn veto(&self, package: &Package, reason: String) -> Veto {
+        Veto {
+            check: self.name().to_string(),
+            package: package.meta.name.clone(),
+            reason,
+        }
+    }
+}
+
+/// The pending transaction presented to each [`Check`]
+pub struct Transaction<'a> {
+    pub installation: &'a Installation,
+    /// Packages which will no longer be installed once applied, i.e. no
+    /// package of the same name is in the new state
+    pub removed: &'a [Package],
+    /// Packages of the new state
+    pub installed: &'a [Package],
+    /// Layouts of the removed packages
+    pub layouts: &'a [(package::Id, Layout)],
+}
+
+/// Structured reason for a check refusing a transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Veto {
+    pub check: String,
+    pub package: package::Name,
+    pub reason: String,
+}
+
+impl fmt::Display for Veto {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}): {}", self.package, self.check, self.reason)
+    }
+}
+
+/// The `previous` packages which are gone once `installed`. Upgrades replace a
+/// package by another release of the same name, which isn't a removal
+pub fn removed(previous: Vec<Package>, installed: &[Package]) -> Vec<Package> {
+    previous
+        .into_iter()
+        .filter(|package| !installed.iter().any(|p| p.meta.name == package.meta.name))
+        .collect()
+}
+
+/// Load the built-in checks and all user registered checks
+pub fn load(config: &config::Manager) -> Vec<Check> {
+    [Check::BootedKernel]
+        .into_iter()
+        .chain(config.load::<Protected>().into_iter().map(Check::Protected))
+        .collect()
+}
+
+/// Run all checks against the transaction, failing with [`Error::Vetoed`]
+/// if any check objects to it, unless they're `skip`ped
+pub fn run(checks: &[Check], transaction: &Transaction<'_>, skip: bool) -> Result<(), Error> {
+    let vetoes = checks
+        .iter()
+        .flat_map(|check| check.run(transaction))
+        .collect::<Vec<_>>();
+
+    if vetoes.is_empty() {
+        return Ok(());
+    }
+
+    if skip {
+        for veto in &vetoes {
+            warn!("ignoring check {}", veto);
+        }
+        return Ok(());
+    }
+
+    if !output::is_json() {
+        println!("The transaction was refused by the following check(s):");
+        println!();
+        for veto in &vetoes {
+            println!(
+                " {} {} {}",
+                veto.package.to_string().bold(),
+                format!("({})", veto.check).dim(),
+                veto.reason
+            );
+        }
+        println!();
+    }
+
+    Err(Error::Vetoed(vetoes))
+}
+
+/// Release of the currently running kernel
+fn booted_kernel() -> Option<String> {
+    fs::read_to_string("/proc/sys/kernel/osrelease")
+        .ok()
+        .map(|release| release.trim().to_string())
+        .filter(|release| !release.is_empty())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("transaction vetoed by {} check(s)", .0.len())]
+    Vetoed(Vec<Veto>),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn package(name: &str, providers: &[&str]) -> Package {
+        Package {
+            id: package::Id::from(name.to_string()),
+            meta: package::Meta {
+                providers: providers.iter().map(|p| Provider::from_str(p).unwrap()).collect(),
+                ..package::meta::fixture(name)
+            },
+            flags: package::Flags::default(),
+        }
+    }
+
+    #[test]
+    fn upgrades_arent_removals() {
+        let upgraded = Package {
+            id: package::Id::from("gdm-2".to_string()),
+            ..package("gdm", &["binary(gdm)"])
+        };
+        let previous = vec![package("gdm", &["binary(gdm)"]), package("nano", &["binary(nano)"])];
+
+        let removed = removed(previous, &[upgraded]);
+        assert_eq!(
+            removed.iter().map(|p| p.meta.name.to_string()).collect::<Vec<_>>(),
+            vec!["nano"]
+        );
+    }
+
+    fn protected() -> Check {
+        Check::Protected(Protected {
+            name: "display-manager".into(),
+            reason: "would remove the active display manager".into(),
+            providers: vec!["binary(gdm)".into()],
+        })
+    }
+
+    #[test]
+    fn protected_provider_vetoes_removal() {
+        let installation = Installation {
+            root: "/tmp/moss-check".into(),
+            mutability: crate::installation::Mutability::ReadWrite,
+            active_state: None,
+            cache_dir: None,
+        };
+        let removed = vec![package("gdm", &["binary(gdm)"]), package("nano", &["binary(nano)"])];
+        let transaction = Transaction {
+            installation: &installation,
+            removed: &removed,
+            installed: &[],
+            layouts: &[],
+        };
+
+        let vetoes = protected().run(&transaction);
+        assert_eq!(vetoes.len(), 1);
+        assert_eq!(vetoes[0].package.to_string(), "gdm");
+        assert_eq!(vetoes[0].check, "display-manager");
+
+        assert!(matches!(
+            run(&[protected()], &transaction, false),
+            Err(Error::Vetoed(_))
+        ));
+        assert!(run(&[protected()], &transaction, true).is_ok());
+
+        // Booted kernel check is skipped for non-native roots
+        assert!(Check::BootedKernel.run(&transaction).is_empty());
+
+        // Another package still provides it
+        let installed = vec![package("gdm-next", &["binary(gdm)"])];
+        let transaction = Transaction {
+            installed: &installed,
+            ..transaction
+        };
+        assert!(protected().run(&transaction).is_empty());
+    }
+}
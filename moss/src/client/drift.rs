@@ -0,0 +1,242 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Drift of the live `/usr` tree from the state it was blitted from
+//!
+//! Everything within `/usr` is owned by the active state, so any path that isn't
+//! part of its layout, or no longer matches it, has drifted. i.e. a file edited
+//! in place or left behind by a `make install`. This is the basis for restoring
+//! a root to exactly what its state describes.
+
+use std::{
+    collections::BTreeMap,
+    fmt, fs, io,
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+use stone::{payload::layout, write::digest};
+use vfs::tree::BlitFile;
+
+use crate::{
+    client::{self, cache, PendingFile},
+    Client, State,
+};
+
+/// Paths written by moss itself, rather than provided by a package
+const GENERATED: &[&str] = &[".stateID", "lib/os-release"];
+
+/// How a path differs from its layout
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Modification {
+    Missing,
+    /// A different kind of file, i.e. a directory replaced by a symlink
+    Kind,
+    Contents,
+    Mode {
+        expected: u32,
+        actual: u32,
+    },
+    Target {
+        expected: String,
+        actual: String,
+    },
+}
+
+impl fmt::Display for Modification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Modification::Missing => write!(f, "missing"),
+            Modification::Kind => write!(f, "file type changed"),
+            Modification::Contents => write!(f, "contents changed"),
+            Modification::Mode { expected, actual } => write!(f, "mode {actual:o}, expected {expected:o}"),
+            Modification::Target { expected, actual } => write!(f, "points to {actual}, expected {expected}"),
+        }
+    }
+}
+
+/// Differences between the live `/usr` tree and a state
+#[derive(Debug, Clone, Default)]
+pub struct Drift {
+    /// Paths of the state which differ on disk
+    pub modified: Vec<(PathBuf, Modification)>,
+    /// Paths on disk which aren't part of the state
+    pub untracked: Vec<PathBuf>,
+}
+
+impl Drift {
+    pub fn is_empty(&self) -> bool {
+        self.modified.is_empty() && self.untracked.is_empty()
+    }
+}
+
+/// Compare the `/usr` tree of the installation root against `state`,
+/// which is expected to be the active state
+pub fn scan(client: &Client, state: &State) -> Result<Drift, client::Error> {
+    let vfs = client.vfs_excluding(
+        state.selections.iter().map(|s| &s.package),
+        &client.state_db.exclusions(state.id)?,
+    )?;
+    let usr = client.installation.root.join("usr");
+
+    let expected = vfs
+        .iter()
+        .filter_map(|file| {
+            let path = file.path();
+            let relative = path.strip_prefix("/usr/")?;
+            Some((PathBuf::from(relative), file))
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let mut hasher = digest::Hasher::new();
+    let mut modified = vec![];
+
+    for (relative, file) in &expected {
+        let path = usr.join(relative);
+
+        if let Some(modification) = compare(client, &path, file, &mut hasher)? {
+            modified.push((path, modification));
+        }
+    }
+
+    let mut untracked = vec![];
+    find_untracked(&usr, Path::new(""), &expected, &mut untracked)?;
+
+    Ok(Drift { modified, untracked })
+}
+
+/// Compare the path on disk to the layout of `file`
+fn compare(
+    client: &Client,
+    path: &Path,
+    file: &PendingFile,
+    hasher: &mut digest::Hasher,
+) -> io::Result<Option<Modification>> {
+    let metadata = match path.symlink_metadata() {
+        Ok(metadata) => metadata,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Some(Modification::Missing)),
+        Err(error) => return Err(error),
+    };
+
+    let modification = match &file.layout.entry {
+        layout::Entry::Regular(hash, _) => {
+            if !metadata.is_file() {
+                return Ok(Some(Modification::Kind));
+            }
+
+            // Still linked to its asset, any corruption is caught by verifying assets
+            let asset = cache::asset_path(&client.installation, &format!("{hash:02x}"));
+            let is_linked = fs::metadata(asset).is_ok_and(|a| a.dev() == metadata.dev() && a.ino() == metadata.ino());
+
+            let expected = file.layout.mode & 0o7777;
+            let actual = metadata.permissions().mode() & 0o7777;
+
+            if !is_linked && digest_file(path, hasher)? != *hash {
+                Some(Modification::Contents)
+            } else if expected != actual {
+                Some(Modification::Mode { expected, actual })
+            } else {
+                None
+            }
+        }
+        layout::Entry::Symlink(source, _) => {
+            if !metadata.is_symlink() {
+                return Ok(Some(Modification::Kind));
+            }
+
+            let actual = fs::read_link(path)?.to_string_lossy().into_owned();
+            (actual != *source).then(|| Modification::Target {
+                expected: source.clone(),
+                actual,
+            })
+        }
+        // Directory modes are subject to the umask when blitting
+        layout::Entry::Directory(_) => (!metadata.is_dir()).then_some(Modification::Kind),
+        _ => None,
+    };
+
+    Ok(modification)
+}
+
+fn digest_file(path: &Path, hasher: &mut digest::Hasher) -> io::Result<u128> {
+    hasher.reset();
+
+    let mut writer = digest::Writer::new(io::sink(), hasher);
+    io::copy(&mut fs::File::open(path)?, &mut writer)?;
+
+    Ok(hasher.digest128())
+}
+
+/// Collect paths within `relative` of `usr` that aren't `expected`. Untracked
+/// directories are reported as a whole, rather than each of their children.
+fn find_untracked(
+    usr: &Path,
+    relative: &Path,
+    expected: &BTreeMap<PathBuf, PendingFile>,
+    untracked: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    let mut entries = fs::read_dir(usr.join(relative))?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = relative.join(entry.file_name());
+
+        if GENERATED.iter().any(|generated| path == Path::new(generated)) {
+            continue;
+        }
+
+        // Parents of generated paths might not be provided by any package
+        let is_generated_parent = GENERATED
+            .iter()
+            .any(|generated| Path::new(generated).starts_with(&path));
+
+        match expected.get(&path) {
+            None if is_generated_parent && entry.file_type()?.is_dir() => {
+                find_untracked(usr, &path, expected, untracked)?;
+            }
+            None => untracked.push(usr.join(&path)),
+            Some(file) if matches!(file.layout.entry, layout::Entry::Directory(_)) && entry.file_type()?.is_dir() => {
+                find_untracked(usr, &path, expected, untracked)?;
+            }
+            Some(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn untracked() {
+        let tmp = tempfile::tempdir().unwrap();
+        let usr = tmp.path();
+        for dir in ["bin", "lib", "share/doc/local", "share/man"] {
+            fs::create_dir_all(usr.join(dir)).unwrap();
+        }
+        fs::write(usr.join("bin/nano"), "").unwrap();
+        fs::write(usr.join("lib/os-release"), "").unwrap();
+        fs::write(usr.join("lib/libcustom.so"), "").unwrap();
+        fs::write(usr.join("bin/custom"), "").unwrap();
+
+        let expected = ["bin", "bin/nano", "share", "share/doc", "share/man"]
+            .into_iter()
+            .map(|path| (PathBuf::from(path), PendingFile::from(path.to_string())))
+            .collect();
+
+        let mut untracked = vec![];
+        find_untracked(usr, Path::new(""), &expected, &mut untracked).unwrap();
+
+        assert_eq!(
+            untracked,
+            vec![
+                usr.join("bin/custom"),
+                usr.join("lib/libcustom.so"),
+                usr.join("share/doc/local")
+            ]
+        );
+    }
+}
@@ -0,0 +1,114 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Per-package path exclusions, leaving selected files of a package out
+//! of the blitted `/usr` tree. Exclusions are registered by dropping yaml
+//! files into `/etc/moss/exclusion.d/`:
+//!
+//! ```yaml
+//! package: systemd
+//! reason: units are provided by the image
+//! paths:
+//!   - /usr/lib/systemd/system/*.timer
+//! ```
+//!
+//! The excluded entries are recorded alongside each state, so verification
+//! doesn't consider them missing.
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+use stone::payload::{layout, Layout};
+
+use config::Config;
+
+/// Paths to leave out for a single package
+#[derive(Debug, Clone, Deserialize)]
+pub struct Exclusion {
+    /// Name of the package
+    pub package: String,
+    /// Glob patterns of the excluded paths, including the `/usr` prefix
+    #[serde(default)]
+    pub paths: Vec<fnmatch::Pattern>,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+impl Config for Exclusion {
+    fn domain() -> String {
+        "exclusion".into()
+    }
+}
+
+/// All configured exclusion patterns, by package name
+#[derive(Debug, Clone, Default)]
+pub struct Rules(BTreeMap<String, Vec<fnmatch::Pattern>>);
+
+impl Rules {
+    pub fn load(config: &config::Manager) -> Self {
+        Self(config.load::<Exclusion>().into_iter().fold(
+            BTreeMap::new(),
+            |mut rules: BTreeMap<_, Vec<_>>, exclusion| {
+                rules.entry(exclusion.package).or_default().extend(exclusion.paths);
+                rules
+            },
+        ))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Whether `package` has any exclusions configured
+    pub fn applies_to(&self, package: &str) -> bool {
+        self.0.contains_key(package)
+    }
+
+    /// Whether `layout` of `package` is excluded
+    ///
+    /// Directories are never excluded, since other entries
+    /// (possibly of other packages) may live within them
+    pub fn excludes(&self, package: &str, layout: &Layout) -> bool {
+        if matches!(layout.entry, layout::Entry::Directory(_)) {
+            return false;
+        }
+
+        let Some(patterns) = self.0.get(package) else {
+            return false;
+        };
+
+        let path = vfs::path::join("/usr", layout.entry.target());
+
+        patterns.iter().any(|pattern| pattern.match_path(&path).is_some())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn excludes() {
+        let rules = Rules(BTreeMap::from([(
+            "systemd".to_string(),
+            vec!["/usr/lib/systemd/system/*.timer".parse().unwrap()],
+        )]));
+
+        let entry = |entry| Layout {
+            uid: 0,
+            gid: 0,
+            mode: 0o644,
+            tag: 0,
+            entry,
+        };
+
+        let timer = entry(layout::Entry::Regular(0, "lib/systemd/system/fstrim.timer".into()));
+        let service = entry(layout::Entry::Regular(0, "lib/systemd/system/fstrim.service".into()));
+        let dir = entry(layout::Entry::Directory("lib/systemd/system/a.timer".into()));
+
+        assert!(rules.excludes("systemd", &timer));
+        assert!(!rules.excludes("systemd", &service));
+        assert!(!rules.excludes("systemd", &dir));
+        assert!(!rules.excludes("util-linux", &timer));
+    }
+}
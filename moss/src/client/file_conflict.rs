@@ -0,0 +1,183 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Files claimed by more than one package of a new state
+//!
+//! Before anything is blitted, the [`vfs`] tree of a new state refuses any
+//! [`conflicting`] entries involving the packages entering it. Blitting both
+//! would keep only one of them, so the transaction is refused with every
+//! conflicting path instead. Directories may be shared, as may identical
+//! files, i.e. with the same content hash or symlink target, mode & owner.
+use stone::payload::layout;
+use thiserror::Error;
+use tui::Styled;
+
+use super::PendingFile;
+use crate::{output, package};
+
+/// A path owned by two packages with different contents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// Target within `/usr`
+    pub path: String,
+    pub packages: (package::Id, package::Id),
+}
+
+/// Whether `file` can't be blitted along with `other` at the same path, where
+/// at least one of their owners is `incoming`. Conflicts among packages which
+/// stay installed aren't new, so they're tolerated
+pub fn conflicting(file: &PendingFile, other: &PendingFile, incoming: impl Fn(&package::Id) -> bool) -> bool {
+    let (a, b) = (&file.layout, &other.layout);
+
+    let shared = matches!(
+        (&a.entry, &b.entry),
+        (layout::Entry::Directory(_), layout::Entry::Directory(_))
+    ) || (a.entry == b.entry && a.mode == b.mode && a.uid == b.uid && a.gid == b.gid);
+
+    file.id != other.id && !shared && (incoming(&file.id) || incoming(&other.id))
+}
+
+/// The conflicts of the [`vfs::tree::Duplicate`]s refused as [`conflicting`]
+pub fn from_duplicates(duplicates: Vec<vfs::tree::Duplicate>) -> Vec<Conflict> {
+    duplicates
+        .into_iter()
+        .map(|duplicate| Conflict {
+            path: match duplicate.path.strip_prefix("/usr/") {
+                Some(path) => path.to_string(),
+                None => duplicate.path,
+            },
+            packages: (duplicate.existing.into(), duplicate.id.into()),
+        })
+        .collect()
+}
+
+/// Report the `conflicts` refusing the transaction, listing them by the package `name`s
+pub fn report(conflicts: &[Conflict], name: impl Fn(&package::Id) -> String) -> Error {
+    let conflicts = conflicts
+        .iter()
+        .map(|conflict| {
+            format!(
+                "file /usr/{} owned by both {} and {}",
+                conflict.path,
+                name(&conflict.packages.0),
+                name(&conflict.packages.1)
+            )
+        })
+        .collect::<Vec<_>>();
+
+    if !output::is_json() {
+        println!("The transaction was refused due to the following file conflict(s):");
+        println!();
+        for conflict in &conflicts {
+            println!(" {} {conflict}", "!".red());
+        }
+        println!();
+    }
+
+    Error::Conflicts(conflicts)
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("{}", summary(.0))]
+    Conflicts(Vec<String>),
+}
+
+/// The only conflict, or how many there are
+fn summary(conflicts: &[String]) -> String {
+    match conflicts {
+        [conflict] => conflict.clone(),
+        conflicts => format!("{} files owned by more than one package", conflicts.len()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use stone::payload::Layout;
+    use vfs::tree::builder::TreeBuilder;
+
+    use super::*;
+
+    fn file(package: &str, mode: u32, entry: layout::Entry) -> PendingFile {
+        PendingFile {
+            id: package::Id::from(package.to_string()),
+            layout: Layout {
+                uid: 0,
+                gid: 0,
+                mode,
+                tag: 0,
+                entry,
+            },
+        }
+    }
+
+    fn conflicts(files: &[PendingFile], incoming: &[&str]) -> Vec<Conflict> {
+        let mut builder = TreeBuilder::new();
+        for file in files {
+            builder.push(file.clone());
+        }
+        builder.bake();
+
+        let incoming = |id: &package::Id| incoming.contains(&id.to_string().as_str());
+        match builder.tree_checked(|file, other| conflicting(file, other, incoming)) {
+            Ok(_) => vec![],
+            Err(vfs::tree::Error::Duplicates(duplicates)) => from_duplicates(duplicates),
+            Err(error) => panic!("{error}"),
+        }
+    }
+
+    #[test]
+    fn conflicts_between_packages() {
+        let files = vec![
+            file("installed", 0o755, layout::Entry::Directory("bin".into())),
+            file("installed", 0o644, layout::Entry::Regular(1, "bin/tool".into())),
+            file("installed", 0o644, layout::Entry::Regular(2, "share/doc".into())),
+            file("a", 0o755, layout::Entry::Directory("bin".into())),
+            file("a", 0o644, layout::Entry::Regular(3, "bin/tool".into())),
+            file("a", 0o777, layout::Entry::Symlink("tool".into(), "bin/alias".into())),
+            file("b", 0o777, layout::Entry::Symlink("tool".into(), "bin/alias".into())),
+            file("b", 0o644, layout::Entry::Regular(4, "bin/other".into())),
+            file("c", 0o755, layout::Entry::Directory("bin/other".into())),
+            file("c", 0o644, layout::Entry::Regular(2, "share/doc".into())),
+        ];
+        let incoming = ["a", "b", "c"];
+
+        assert_eq!(
+            conflicts(&files, &incoming),
+            vec![
+                Conflict {
+                    path: "bin/other".into(),
+                    packages: ("c".to_string().into(), "b".to_string().into()),
+                },
+                Conflict {
+                    path: "bin/tool".into(),
+                    packages: ("installed".to_string().into(), "a".to_string().into()),
+                },
+            ]
+        );
+
+        // Conflicts among what's installed already aren't new
+        assert!(conflicts(&files, &[]).is_empty());
+
+        let error = report(&conflicts(&files[..5], &incoming), |id| id.to_string());
+        assert_eq!(error.to_string(), "file /usr/bin/tool owned by both installed and a");
+    }
+
+    #[test]
+    fn identical_contents_differing_mode() {
+        let files = vec![
+            file("installed", 0o644, layout::Entry::Regular(1, "bin/tool".into())),
+            file("a", 0o755, layout::Entry::Regular(1, "bin/tool".into())),
+        ];
+
+        assert_eq!(
+            conflicts(&files, &["a"]),
+            vec![Conflict {
+                path: "bin/tool".into(),
+                packages: ("installed".to_string().into(), "a".to_string().into()),
+            }]
+        );
+        assert!(conflicts(&files[..1], &["a"]).is_empty());
+    }
+}
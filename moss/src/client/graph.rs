@@ -0,0 +1,178 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Package dependency graphs, for visualization & analysis
+//! of the dependency structure of a system
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{package, Package, Provider, Registry};
+
+/// How a [`Graph`] is exported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Graphviz DOT document
+    Dot,
+    /// Machine readable JSON document
+    Json,
+}
+
+/// Dependency graph between packages, keyed by package name
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Graph {
+    pub nodes: BTreeMap<String, Node>,
+    pub edges: BTreeSet<Edge>,
+    /// Dependencies no package in the graph could satisfy, by package name
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub unresolved: BTreeMap<String, BTreeSet<String>>,
+}
+
+/// A package within the [`Graph`]
+#[derive(Debug, Clone, Serialize)]
+pub struct Node {
+    pub version: String,
+    pub release: u64,
+    pub installed: bool,
+}
+
+/// A dependency of one package in the [`Graph`] on another
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct Edge {
+    /// Name of the dependent package
+    pub from: String,
+    /// Name of the package satisfying the dependency
+    pub to: String,
+    /// The dependency, as provided by `to`
+    pub provider: String,
+}
+
+impl Graph {
+    /// Graph of all installed packages, or only the dependency closure
+    /// of the installed packages providing `roots`
+    pub fn installed(registry: &Registry, roots: &[Provider]) -> Result<Self, Error> {
+        let flags = package::Flags::new().with_installed();
+
+        let start = if roots.is_empty() {
+            registry.list_installed(package::Flags::default()).collect()
+        } else {
+            Self::resolve_roots(registry, flags, roots)?
+        };
+
+        Ok(Self::build(registry, flags, start))
+    }
+
+    /// Graph of the dependency closure of the available packages providing `roots`,
+    /// as it would be resolved for a fresh installation of them
+    pub fn available(registry: &Registry, roots: &[Provider]) -> Result<Self, Error> {
+        let flags = package::Flags::new().with_available();
+
+        let start = Self::resolve_roots(registry, flags, roots)?;
+
+        Ok(Self::build(registry, flags, start))
+    }
+
+    fn resolve_roots(registry: &Registry, flags: package::Flags, roots: &[Provider]) -> Result<Vec<Package>, Error> {
+        roots
+            .iter()
+            .map(|root| {
+                registry
+                    .by_provider(root, flags)
+                    .next()
+                    .ok_or_else(|| Error::NotFound(root.to_string()))
+            })
+            .collect()
+    }
+
+    /// Walk the dependencies of `start`, resolving them against packages matching `flags`
+    fn build(registry: &Registry, flags: package::Flags, start: Vec<Package>) -> Self {
+        let mut graph = Self::default();
+        let mut queue = VecDeque::from(start);
+
+        while let Some(package) = queue.pop_front() {
+            let name = package.meta.name.to_string();
+
+            if graph.nodes.contains_key(&name) {
+                continue;
+            }
+
+            graph.nodes.insert(
+                name.clone(),
+                Node {
+                    version: package.meta.version_identifier.clone(),
+                    release: package.meta.source_release,
+                    installed: package.flags.installed,
+                },
+            );
+
+            for dependency in &package.meta.dependencies {
+                let provider = Provider {
+                    kind: dependency.kind,
+                    name: dependency.name.clone(),
+                };
+
+                let resolved = registry.by_provider(&provider, flags).next();
+
+                match resolved {
+                    Some(resolved) => {
+                        graph.edges.insert(Edge {
+                            from: name.clone(),
+                            to: resolved.meta.name.to_string(),
+                            provider: provider.to_string(),
+                        });
+                        queue.push_back(resolved);
+                    }
+                    None => {
+                        graph
+                            .unresolved
+                            .entry(name.clone())
+                            .or_default()
+                            .insert(dependency.to_string());
+                    }
+                }
+            }
+        }
+
+        graph
+    }
+
+    /// Render as a Graphviz DOT document
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph dependencies {\n    node [shape=box];\n");
+
+        for (name, node) in &self.nodes {
+            let style = if node.installed { "" } else { ", style=dashed" };
+            dot.push_str(&format!(
+                "    \"{name}\" [label=\"{name}\\n{}-{}\"{style}];\n",
+                node.version, node.release
+            ));
+        }
+
+        for Edge { from, to, provider } in &self.edges {
+            let provider = provider.replace('"', "\\\"");
+            dot.push_str(&format!("    \"{from}\" -> \"{to}\" [label=\"{provider}\"];\n"));
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Render in the requested [`Format`]
+    pub fn render(&self, format: Format) -> Result<String, Error> {
+        match format {
+            Format::Dot => Ok(self.to_dot()),
+            Format::Json => Ok(serde_json::to_string_pretty(self)?),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("no package found for {0}")]
+    NotFound(String),
+    #[error("serialize graph")]
+    Json(#[from] serde_json::Error),
+}
@@ -0,0 +1,331 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Transaction history operations, such as undoing a single past transaction
+//! on top of the current state
+
+use std::collections::BTreeSet;
+
+use thiserror::Error;
+use tui::{pretty::autoprint_columns, Styled};
+
+use crate::{
+    client::{
+        self,
+        plan::{self, Plan},
+        Client,
+    },
+    db, output, package, prompt,
+    registry::transaction,
+    state::{self, Selection},
+    State,
+};
+
+/// The set of changes a single transaction applied to the state before it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Changes {
+    /// Packages that were added by the transaction
+    pub added: Vec<Selection>,
+    /// Packages that were removed by the transaction
+    pub removed: Vec<Selection>,
+}
+
+impl Changes {
+    /// Compute the changes applied to `previous` to produce `state`
+    pub fn between(previous: Option<&State>, state: &State) -> Self {
+        let previous = previous.map(|p| p.selections.as_slice()).unwrap_or_default();
+        let contains = |selections: &[Selection], id: &package::Id| selections.iter().any(|s| s.package == *id);
+
+        Self {
+            added: state
+                .selections
+                .iter()
+                .filter(|s| !contains(previous, &s.package))
+                .cloned()
+                .collect(),
+            removed: previous
+                .iter()
+                .filter(|s| !contains(&state.selections, &s.package))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// The changes recorded for `transaction`, with the selections as they were in
+    /// its `previous` & new `state`, as long as those haven't been pruned
+    pub fn recorded(transaction: &state::Transaction, previous: Option<&State>, state: Option<&State>) -> Self {
+        // A pruned state leaves only the package, which is kept as explicitly selected
+        let selection = |state: Option<&State>, id: &package::Id| {
+            state
+                .and_then(|state| state.selections.iter().find(|s| s.package == *id))
+                .cloned()
+                .unwrap_or_else(|| Selection::explicit(id.clone()))
+        };
+
+        Self {
+            added: transaction.added.iter().map(|id| selection(state, id)).collect(),
+            removed: transaction.removed.iter().map(|id| selection(previous, id)).collect(),
+        }
+    }
+
+    /// Returns true if the transaction didn't change any packages
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// The state preceding `id`, which the transaction producing it was applied to
+pub fn previous(state_db: &db::state::Database, id: state::Id) -> Result<Option<State>, db::Error> {
+    state_db
+        .list_ids()?
+        .into_iter()
+        .map(|(id, _)| id)
+        .filter(|prev| *prev < id)
+        .max()
+        .map(|prev| state_db.get(prev))
+        .transpose()
+}
+
+/// Reason an undo cannot be applied cleanly to the current state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Conflict {
+    /// A package added by the transaction has since been removed
+    NoLongerInstalled(package::Id),
+    /// A package removed by the transaction has since been installed again,
+    /// possibly at a different version
+    Reinstalled(package::Name),
+    /// A package installed later depends on a package we'd remove
+    RequiredBy(package::Id),
+}
+
+/// Undo the recorded `transaction`, by applying its inverse as a new
+/// transaction against the currently active state.
+///
+/// Packages the transaction added are removed and packages it removed are
+/// reinstalled, as recorded in the history rather than by comparing states,
+/// which may have been activated in another order or pruned since. If any
+/// later transaction has touched the same packages, the undo is refused
+/// with [`Error::Conflicts`].
+pub fn undo(client: &Client, transaction: &state::Transaction, yes: bool) -> Result<(), Error> {
+    let active = client.installation.active_state.ok_or(Error::NoActiveState)?;
+    let id = transaction.id;
+
+    let previous = transaction
+        .previous
+        .and_then(|previous| client.state_db.get(previous).ok());
+    let state = client.state_db.get(transaction.state).ok();
+
+    let changes = Changes::recorded(transaction, previous.as_ref(), state.as_ref());
+    if changes.is_empty() {
+        return Err(Error::NothingToUndo(id));
+    }
+
+    let current = client.state_db.get(active)?.selections;
+    let current_ids = current.iter().map(|s| s.package.clone()).collect::<BTreeSet<_>>();
+
+    let mut conflicts = vec![];
+
+    // Everything the transaction added must still be installed
+    let added = changes.added.iter().map(|s| s.package.clone()).collect::<BTreeSet<_>>();
+    conflicts.extend(
+        added
+            .iter()
+            .filter(|id| !current_ids.contains(*id))
+            .cloned()
+            .map(Conflict::NoLongerInstalled),
+    );
+
+    // Nothing the transaction removed may have been installed again since,
+    // other than the packages it added itself (i.e. undoing an upgrade)
+    let installed = client.resolve_packages(current_ids.iter().filter(|id| !added.contains(*id)))?;
+    let reinstall = client.resolve_packages(changes.removed.iter().map(|s| &s.package))?;
+    conflicts.extend(
+        reinstall
+            .iter()
+            .filter(|p| installed.iter().any(|i| i.meta.name == p.meta.name))
+            .map(|p| Conflict::Reinstalled(p.meta.name.clone())),
+    );
+
+    // Removing the added packages must not take any other package with it
+    let mut tx = client
+        .registry
+        .transaction_with_installed(current_ids.iter().cloned().collect())?;
+    tx.remove(added.iter().filter(|id| current_ids.contains(*id)).cloned().collect());
+    let finalized = tx.finalize().cloned().collect::<BTreeSet<_>>();
+    conflicts.extend(
+        current_ids
+            .difference(&finalized)
+            .filter(|id| !added.contains(*id))
+            .cloned()
+            .map(Conflict::RequiredBy),
+    );
+
+    if !conflicts.is_empty() {
+        return Err(Error::Conflicts(id, conflicts));
+    }
+
+    let remove = client.resolve_packages(added.iter())?;
+
+    if output::is_json() {
+        // The plan stands in for the human readable summary
+        Plan::new(client, &reinstall, &remove).print(plan::Format::Json)?;
+    }
+    if !remove.is_empty() && !output::is_json() {
+        println!("The following package(s) will be removed:");
+        println!();
+        autoprint_columns(&remove);
+        println!();
+    }
+    if !reinstall.is_empty() && !output::is_json() {
+        println!("The following package(s) will be reinstalled:");
+        println!();
+        autoprint_columns(&reinstall);
+        println!();
+    }
+
+    let result = prompt::confirm(yes)?;
+    if !result {
+        return Err(Error::Cancelled);
+    }
+
+    // New state is the current state minus what the transaction added,
+    // plus what it removed with the selection it had back then
+    let selections = current
+        .into_iter()
+        .filter(|s| !added.contains(&s.package))
+        .chain(changes.removed)
+        .collect::<Vec<_>>();
+    let summary = format!("Undo #{id}");
+
+    // Reinstalled packages may have been pruned from the cache since,
+    // already cached packages are validated & skipped
+    client.fetch_transaction(&reinstall, &selections, &summary)?;
+
+    for package in &remove {
+        println!("{} {}", "Removed".red(), package.meta.name.to_string().bold());
+    }
+
+    client.new_state(&selections, summary)?;
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The operation was explicitly cancelled at the user's request
+    #[error("cancelled")]
+    Cancelled,
+
+    /// Undo is always applied against the active state
+    #[error("root must have an active state")]
+    NoActiveState,
+
+    /// The transaction didn't change any packages
+    #[error("transaction #{0} made no package changes, nothing to undo")]
+    NothingToUndo(i32),
+
+    /// Later transactions have changed packages this undo would touch
+    #[error("cannot undo transaction #{0}: {}", format_conflicts(.1))]
+    Conflicts(i32, Vec<Conflict>),
+
+    /// An error originated in [`client`] module
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    /// A transaction specific error occurred
+    #[error("transaction")]
+    Transaction(#[from] transaction::Error),
+
+    /// A database specific error occurred
+    #[error("db")]
+    DB(#[from] crate::db::Error),
+
+    /// The plan couldn't be printed
+    #[error("plan")]
+    Plan(#[from] plan::Error),
+
+    /// Had issues processing user-provided string input
+    #[error("string processing")]
+    Dialog(#[from] tui::dialoguer::Error),
+}
+
+fn format_conflicts(conflicts: &[Conflict]) -> String {
+    conflicts
+        .iter()
+        .map(|conflict| match conflict {
+            Conflict::NoLongerInstalled(id) => format!("{id} has since been removed"),
+            Conflict::Reinstalled(name) => format!("{name} has since been reinstalled"),
+            Conflict::RequiredBy(id) => format!("{id} depends on packages being removed"),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+
+    use super::*;
+
+    fn state(id: i32, packages: &[&str]) -> State {
+        State {
+            id: id.into(),
+            summary: None,
+            description: None,
+            selections: packages
+                .iter()
+                .map(|p| Selection::explicit(package::Id::from(p.to_string())))
+                .collect(),
+            created: Utc::now(),
+            kind: state::Kind::Transaction,
+        }
+    }
+
+    #[test]
+    fn changes_between_states() {
+        let first = state(1, &["a", "b"]);
+        let second = state(2, &["b", "c"]);
+
+        let changes = Changes::between(Some(&first), &second);
+        assert_eq!(changes.added, state(0, &["c"]).selections);
+        assert_eq!(changes.removed, state(0, &["a"]).selections);
+
+        let initial = Changes::between(None, &first);
+        assert_eq!(initial.added, first.selections);
+        assert!(initial.removed.is_empty());
+
+        assert!(Changes::between(Some(&first), &first).is_empty());
+    }
+
+    #[test]
+    fn recorded_changes() {
+        let transaction = state::Transaction {
+            id: 7,
+            created: Utc::now(),
+            command: "moss install c".to_string(),
+            summary: None,
+            previous: Some(3.into()),
+            state: 5.into(),
+            added: vec![package::Id::from("c".to_string())],
+            removed: vec![package::Id::from("a".to_string())],
+        };
+
+        // Selections come from the recorded previous state, whichever states exist in between
+        let previous = State {
+            selections: vec![Selection {
+                package: package::Id::from("a".to_string()),
+                explicit: false,
+                reason: Some("runtime of b".to_string()),
+            }],
+            ..state(3, &[])
+        };
+        let changes = Changes::recorded(&transaction, Some(&previous), None);
+        assert_eq!(changes.added, state(0, &["c"]).selections);
+        assert_eq!(changes.removed, previous.selections);
+
+        // Once pruned, removed packages come back as explicitly selected
+        let changes = Changes::recorded(&transaction, None, None);
+        assert_eq!(changes.removed, state(0, &["a"]).selections);
+    }
+}
@@ -0,0 +1,26 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Package holds, pinning installed packages to their current
+//! version so they're excluded from sync transactions
+
+use serde::{Deserialize, Serialize};
+
+use config::Config;
+
+/// A held package, stored as `etc/moss/hold.d/{name}.yaml`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hold {
+    /// Name of the held package
+    pub name: String,
+    /// Why the package is held, shown when listing holds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl Config for Hold {
+    fn domain() -> String {
+        "hold".into()
+    }
+}
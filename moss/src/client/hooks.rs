@@ -0,0 +1,193 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Admin provided hooks run around each transaction
+//!
+//! Executables dropped into `/etc/moss/hooks/pre-transaction.d/` are run
+//! before a transaction is applied and those in `post-transaction.d/` after
+//! it, in lexical order. Each receives a `transaction` JSON document on stdin,
+//! i.e. to take snapshots, send notifications or warm caches.
+//!
+//! A failing pre-transaction hook aborts the transaction. Post-transaction
+//! hooks run once it's applied, so their failures are only warned about.
+
+use std::{
+    fs,
+    io::{self, Write},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+};
+
+use serde::Serialize;
+use thiserror::Error;
+use tracing::{debug, warn};
+
+use crate::{client::plan::Plan, output, Installation};
+
+/// When a hook runs, relative to the transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, strum::Display)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum Phase {
+    PreTransaction,
+    PostTransaction,
+}
+
+/// The transaction passed to hooks on stdin
+#[derive(Debug, Serialize)]
+pub struct Transaction<'a> {
+    pub phase: Phase,
+    pub summary: &'a str,
+    pub root: &'a Path,
+    /// Active state before the transaction, if any
+    pub previous_state: Option<i32>,
+    /// State the transaction creates or activates, only
+    /// known once it's been recorded
+    pub state: Option<i32>,
+    /// Packages the transaction installs & removes
+    #[serde(flatten)]
+    pub plan: Plan,
+}
+
+/// Executable hooks of `phase` within `installation`, in the order they run
+pub fn load(installation: &Installation, phase: Phase) -> Result<Vec<PathBuf>, Error> {
+    let dir = installation.root.join("etc/moss/hooks").join(format!("{phase}.d"));
+
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(error) => return Err(Error::ReadDir(dir, error)),
+    };
+
+    let mut hooks = vec![];
+    for entry in entries {
+        let entry = entry.map_err(|error| Error::ReadDir(dir.clone(), error))?;
+        let path = entry.path();
+
+        let is_hidden = entry.file_name().to_string_lossy().starts_with('.');
+        // Follows symlinks, so hooks can be linked in from elsewhere
+        let is_executable = fs::metadata(&path)
+            .map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+            .unwrap_or_default();
+
+        if is_hidden || !is_executable {
+            debug!("Skipping hook {path:?}, it isn't executable");
+            continue;
+        }
+
+        hooks.push(path);
+    }
+    hooks.sort();
+
+    Ok(hooks)
+}
+
+/// Run `hooks` for `transaction`, stopping at the first failure
+/// of a pre-transaction hook
+pub fn run(hooks: &[PathBuf], transaction: &Transaction<'_>) -> Result<(), Error> {
+    let document = output::to_json_line("transaction", transaction)?;
+
+    for hook in hooks {
+        debug!("Running {} hook {hook:?}", transaction.phase);
+
+        let result = run_hook(hook, transaction, &document);
+
+        match (transaction.phase, result) {
+            (_, Ok(())) => {}
+            (Phase::PreTransaction, Err(error)) => return Err(error),
+            (Phase::PostTransaction, Err(error)) => warn!("{error}"),
+        }
+    }
+
+    Ok(())
+}
+
+fn run_hook(hook: &Path, transaction: &Transaction<'_>, document: &str) -> Result<(), Error> {
+    let mut child = Command::new(hook)
+        .env("MOSS_ROOT", transaction.root)
+        .env("MOSS_HOOK_PHASE", transaction.phase.to_string())
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|error| Error::Spawn(hook.to_owned(), error))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // Hooks needn't read their input
+        match writeln!(stdin, "{document}") {
+            Err(error) if error.kind() != io::ErrorKind::BrokenPipe => {
+                return Err(Error::Spawn(hook.to_owned(), error));
+            }
+            _ => {}
+        }
+    }
+
+    let status = child.wait().map_err(|error| Error::Spawn(hook.to_owned(), error))?;
+    if !status.success() {
+        return Err(Error::Failed(hook.to_owned(), status));
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("read hooks from {0:?}")]
+    ReadDir(PathBuf, #[source] io::Error),
+    #[error("run hook {0:?}")]
+    Spawn(PathBuf, #[source] io::Error),
+    #[error("hook {0:?} failed with {1}")]
+    Failed(PathBuf, ExitStatus),
+    #[error("json")]
+    Json(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write_hook(dir: &Path, name: &str, script: &str, mode: u32) {
+        let path = dir.join(name);
+        fs::write(&path, format!("#!/bin/sh\n{script}\n")).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    #[test]
+    fn run_hooks() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let dir = root.join("etc/moss/hooks/pre-transaction.d");
+        fs::create_dir_all(&dir).unwrap();
+        let installation = Installation::open(root).unwrap();
+
+        write_hook(&dir, "20-record", "cat > \"$MOSS_ROOT/seen\"", 0o755);
+        write_hook(&dir, "10-ignored", "exit 1", 0o644);
+        write_hook(&dir, ".hidden", "exit 1", 0o755);
+
+        let hooks = load(&installation, Phase::PreTransaction).unwrap();
+        assert_eq!(hooks, vec![dir.join("20-record")]);
+        assert!(load(&installation, Phase::PostTransaction).unwrap().is_empty());
+
+        let mut transaction = Transaction {
+            phase: Phase::PreTransaction,
+            summary: "Install",
+            root,
+            previous_state: Some(1),
+            state: None,
+            plan: Plan::default(),
+        };
+        run(&hooks, &transaction).unwrap();
+
+        let seen = fs::read_to_string(root.join("seen")).unwrap();
+        assert!(seen.contains(r#""kind":"transaction""#));
+        assert!(seen.contains(r#""phase":"pre-transaction","summary":"Install""#));
+
+        write_hook(&dir, "30-veto", "exit 3", 0o755);
+        let hooks = load(&installation, Phase::PreTransaction).unwrap();
+        assert!(matches!(run(&hooks, &transaction), Err(Error::Failed(..))));
+
+        // Too late to veto
+        transaction.phase = Phase::PostTransaction;
+        run(&hooks, &transaction).unwrap();
+    }
+}
@@ -4,18 +4,21 @@
 
 //! Installation-specific code for several core moss operations
 
-use std::time::{Duration, Instant};
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
 
 use thiserror::Error;
-use tui::{
-    dialoguer::{theme::ColorfulTheme, Confirm},
-    pretty::autoprint_columns,
-};
+use tui::pretty::autoprint_columns;
+use url::Url;
 
 use crate::{
-    client::{self, Client},
+    client::{self, plan, Client},
+    messages, output,
     package::{self, Flags},
-    registry::transaction,
+    prompt,
+    registry::{conflict, transaction},
     runtime,
     state::Selection,
     Package, Provider,
@@ -24,48 +27,39 @@ use crate::{
 /// Install a set of packages
 /// If this call is successful a new State is recorded into the [`super::db::state::Database`].
 /// Upon completion the `/usr` tree is "hot swapped" with the staging tree through `renameat2` call.
-pub fn install(client: &mut Client, pkgs: &[&str], yes: bool) -> Result<Timing, Error> {
+///
+/// With `dry_run` set, only the computed [`plan::Plan`] is printed and the root is left untouched.
+pub fn install(client: &mut Client, pkgs: &[&str], yes: bool, dry_run: Option<plan::Format>) -> Result<Timing, Error> {
     let mut timing = Timing::default();
     let mut instant = Instant::now();
 
-    // Resolve input packages
-    let input = resolve_input(pkgs, client)?;
-
-    // Add all inputs
-    let mut tx = client.registry.transaction()?;
-
-    tx.add(input.clone())?;
-
-    // Resolve transaction to metadata
-    let resolved = client.resolve_packages(tx.finalize())?;
+    let resolution = resolve(client, pkgs)?;
+    let Resolution {
+        installed,
+        missing,
+        conflicting,
+        ..
+    } = &resolution;
 
-    // Get installed packages to check against
-    let installed = client.registry.list_installed(Flags::default()).collect::<Vec<_>>();
-    let is_installed = |p: &Package| installed.iter().any(|i| i.meta.name == p.meta.name);
+    timing.resolve = instant.elapsed();
 
-    // Get missing packages that are:
-    //
-    // Stateful: Not installed
-    // Ephemeral: all
-    let missing = resolved
-        .iter()
-        .filter(|p| client.is_ephemeral() || !is_installed(p))
-        .collect::<Vec<_>>();
+    if let Some(format) = dry_run {
+        plan::Plan::new(client, missing, conflicting).print(format)?;
+        return Ok(timing);
+    }
 
-    timing.resolve = instant.elapsed();
+    if output::is_json() {
+        // The plan stands in for the human readable summary
+        plan::Plan::new(client, missing, conflicting).print(plan::Format::Json)?;
+    }
 
     // If no new packages exist, exit and print
     // packages already installed
     if missing.is_empty() {
-        let installed = resolved
-            .iter()
-            .filter(|p| is_installed(p) && input.contains(&p.id))
-            .collect::<Vec<_>>();
-
-        if !installed.is_empty() {
-            println!("The following package(s) are already installed:");
+        if !installed.is_empty() && !output::is_json() {
+            println!("{}", messages::get("already-installed"));
             println!();
-            autoprint_columns(&installed);
+            autoprint_columns(installed);
         }
 
         return Ok(timing);
@@ -74,19 +68,31 @@ pub fn install(client: &mut Client, pkgs: &[&str], yes: bool) -> Result<Timing,
     // Testing panic for hyperfine benchmarking purposes (build flag tuning)
     // panic!();
 
-    println!("The following package(s) will be installed:");
-    println!();
-    autoprint_columns(&missing);
-    println!();
+    if !output::is_json() {
+        println!("{}", messages::get("will-install"));
+        println!();
+        autoprint_columns(missing);
+        println!();
+
+        if !conflicting.is_empty() {
+            println!("{}", messages::get("will-remove-conflicting"));
+            println!();
+            autoprint_columns(conflicting);
+            println!();
+        }
+
+        plan::Plan::new(client, missing, conflicting).print_sizes();
+        println!();
+    }
+
+    // Metered repositories may insist on confirmation
+    let confirm_quota = client.warn_exceeded_quotas(missing)?;
 
     // Must we prompt?
-    let result = if yes {
-        true
+    let result = if confirm_quota {
+        prompt::confirm_always()?
     } else {
-        Confirm::with_theme(&ColorfulTheme::default())
-            .with_prompt(" Do you wish to continue? ")
-            .default(false)
-            .interact()?
+        prompt::confirm(yes)?
     };
     if !result {
         return Err(Error::Cancelled);
@@ -94,30 +100,14 @@ pub fn install(client: &mut Client, pkgs: &[&str], yes: bool) -> Result<Timing,
 
     instant = Instant::now();
 
+    let new_state_pkgs = selections(client, &resolution)?;
+
     // Cache packages
-    runtime::block_on(client.cache_packages(&missing))?;
+    client.fetch_transaction(missing, &new_state_pkgs, "Install")?;
 
     timing.fetch = instant.elapsed();
     instant = Instant::now();
 
-    // Calculate the new state of packages (old_state + missing)
-    let new_state_pkgs = {
-        // Only use previous state in stateful mode
-        let previous_selections = match client.installation.active_state {
-            Some(id) if !client.is_ephemeral() => client.state_db.get(id)?.selections,
-            _ => vec![],
-        };
-        let missing_selections = missing.iter().map(|p| Selection {
-            package: p.id.clone(),
-            // Package is explicit if it was one of the input
-            // packages provided by the user
-            explicit: input.iter().any(|id| *id == p.id),
-            reason: None,
-        });
-
-        missing_selections.chain(previous_selections).collect::<Vec<_>>()
-    };
-
     // Perfect, apply state.
     client.new_state(&new_state_pkgs, "Install")?;
 
@@ -126,25 +116,149 @@ pub fn install(client: &mut Client, pkgs: &[&str], yes: bool) -> Result<Timing,
     Ok(timing)
 }
 
+/// The selections of the state installing a [`resolve`]d `resolution`,
+/// i.e. those of the active state with the missing packages added
+pub fn selections(client: &Client, resolution: &Resolution) -> Result<Vec<Selection>, Error> {
+    // Only use previous state in stateful mode
+    let previous_selections = match client.installation.active_state {
+        Some(id) if !client.is_ephemeral() => client.state_db.get(id)?.selections,
+        _ => vec![],
+    }
+    .into_iter()
+    .filter(|s| !resolution.conflicting.iter().any(|p| p.id == s.package));
+    let missing_selections = resolution.missing.iter().map(|p| Selection {
+        package: p.id.clone(),
+        // Package is explicit if it was one of the input
+        // packages provided by the user
+        explicit: resolution.input.iter().any(|id| *id == p.id),
+        reason: None,
+    });
+
+    Ok(missing_selections.chain(previous_selections).collect())
+}
+
+/// The packages affected by installing a set of packages, see [`resolve`]
+#[derive(Debug, Clone, Default)]
+pub struct Resolution {
+    /// Requested packages, with any sideloaded stones
+    pub input: Vec<package::Id>,
+    /// Requested packages which are already installed
+    pub installed: Vec<Package>,
+    /// Packages to install, including dependencies
+    pub missing: Vec<Package>,
+    /// Installed packages which conflict with, or are replaced by, `missing`
+    pub conflicting: Vec<Package>,
+}
+
+/// Resolve `pkgs` and their dependencies against the configured repositories,
+/// without changing the root. Local stones & URLs are sideloaded.
+pub fn resolve(client: &mut Client, pkgs: &[&str]) -> Result<Resolution, Error> {
+    // Ensure all repository indexes are available to resolve against
+    runtime::block_on(client.ensure_repos_initialized())?;
+
+    // Resolve input packages
+    let input = resolve_input(pkgs, client)?;
+
+    // Add all inputs
+    let mut tx = client.registry.transaction()?;
+
+    tx.add(input.clone())?;
+
+    // Resolve transaction to metadata
+    let resolved = client.resolve_packages(tx.finalize())?;
+
+    // Get installed packages to check against
+    let installed = client.registry.list_installed(Flags::default()).collect::<Vec<_>>();
+    let is_installed = |p: &Package| installed.iter().any(|i| i.meta.name == p.meta.name);
+
+    // Get missing packages that are:
+    //
+    // Stateful: Not installed
+    // Ephemeral: all
+    let missing = resolved
+        .iter()
+        .filter(|p| client.is_ephemeral() || !is_installed(p))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    // Installed packages which conflict with, or are replaced by, missing packages
+    let kept = if client.is_ephemeral() {
+        &[][..]
+    } else {
+        installed.as_slice()
+    };
+    let conflicting = conflict::resolve(&client.registry, missing.iter(), kept)?;
+
+    let violations = client.hold_violations(
+        &installed,
+        installed
+            .iter()
+            .filter(|p| !conflicting.iter().any(|c| c.id == p.id))
+            .map(|p| &p.id),
+    );
+    if !violations.is_empty() {
+        return Err(Error::Held(violations));
+    }
+
+    let already_installed = resolved
+        .into_iter()
+        .filter(|p| is_installed(p) && input.contains(&p.id))
+        .collect();
+
+    Ok(Resolution {
+        input,
+        installed: already_installed,
+        missing,
+        conflicting,
+    })
+}
+
 /// Resolves the package arguments as valid input packages. Returns an error
 /// if any args are invalid.
-fn resolve_input(pkgs: &[&str], client: &Client) -> Result<Vec<package::Id>, Error> {
-    // Parse pkg args into valid / invalid sets
-    let queried = pkgs.iter().map(|p| find_packages(p, client));
-
+///
+/// Paths to local `.stone` files & URLs are sideloaded, with their
+/// dependencies resolved against the configured repositories.
+fn resolve_input(pkgs: &[&str], client: &mut Client) -> Result<Vec<package::Id>, Error> {
     let mut results = vec![];
 
-    for (id, pkg) in queried {
-        if let Some(pkg) = pkg {
-            results.push(pkg.id)
-        } else {
-            return Err(Error::NoPackage(id));
+    for pkg in pkgs {
+        if let Some(id) = sideload(pkg, client)? {
+            results.push(id);
+            continue;
+        }
+
+        match find_packages(pkg, client) {
+            (_, Some(pkg)) => results.push(pkg.id),
+            (id, None) => return Err(Error::NoPackage(id)),
         }
     }
 
     Ok(results)
 }
 
+/// Sideload `arg` if it refers to a stone rather than a package name
+fn sideload(arg: &str, client: &mut Client) -> Result<Option<package::Id>, Error> {
+    if let Ok(url) = Url::parse(arg) {
+        return match url.scheme() {
+            "file" => {
+                let path = url.to_file_path().map_err(|_| Error::InvalidUrl(url.clone()))?;
+                Ok(Some(client.sideload(path, url)?))
+            }
+            "http" | "https" => Ok(Some(runtime::block_on(client.sideload_url(url))?)),
+            _ => Err(Error::InvalidUrl(url)),
+        };
+    }
+
+    let path = Path::new(arg);
+    if path.extension().is_some_and(|extension| extension == "stone") {
+        let path = path.canonicalize()?;
+        let source = path.display().to_string();
+        return Ok(Some(client.sideload(path, source)?));
+    }
+
+    Ok(None)
+}
+
 /// Resolve a package name to the first package
 fn find_packages(id: &str, client: &Client) -> (String, Option<Package>) {
     let provider = Provider::from_name(id).unwrap();
@@ -180,6 +294,22 @@ pub enum Error {
     #[error("no package found: {0}")]
     NoPackage(String),
 
+    /// Only local files & http(s) URLs can be sideloaded
+    #[error("unsupported url: {0}")]
+    InvalidUrl(Url),
+
+    /// Conflicts would remove held packages
+    #[error("install would remove held packages: {}", .0.join(", "))]
+    Held(Vec<String>),
+
+    /// Conflicts between packages couldn't be resolved
+    #[error("conflict")]
+    Conflict(#[from] conflict::Error),
+
+    /// Failed to print the transaction plan
+    #[error("plan")]
+    Plan(#[from] plan::Error),
+
     /// A transaction specific error occurred
     #[error("transaction")]
     Transaction(#[from] transaction::Error),
@@ -0,0 +1,214 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Journal of a transaction until its new state is staged
+//!
+//! The transaction is recorded before its packages are fetched and the journal
+//! is removed once the [`staging`](super::staging) marker takes over. A journal
+//! left behind means moss was interrupted (power loss, SIGKILL) while fetching
+//! or blitting, so the transaction can either be resumed from the recorded
+//! selections or aborted, discarding the half-written staging tree.
+
+use std::{fs, io, path::PathBuf};
+
+use super::staging::Pending;
+use crate::{
+    package,
+    state::{self, Selection},
+    Installation,
+};
+
+/// Name of the journal within the root directory, beside staging
+const FILE: &str = "transaction.journal";
+
+/// A transaction that hasn't been staged yet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Journal {
+    pub summary: String,
+    /// Active state when the transaction started
+    pub previous: Option<state::Id>,
+    /// State recorded for the transaction, once blitted
+    pub state: Option<state::Id>,
+    pub selections: Vec<Selection>,
+}
+
+/// Record `journal`, replacing any previous one, and flush it to disk
+pub(super) fn write(installation: &Installation, journal: &Journal) -> io::Result<()> {
+    let path = path(installation);
+    let partial = path.with_extension("partial");
+
+    fs::write(&partial, encode(journal))?;
+    fs::File::open(&partial)?.sync_all()?;
+    fs::rename(partial, path)?;
+
+    Ok(())
+}
+
+/// The journal of an interrupted transaction, if any
+pub fn load(installation: &Installation) -> io::Result<Option<Journal>> {
+    match fs::read_to_string(path(installation)) {
+        Ok(contents) => Ok(parse(&contents)),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Remove the journal once the transaction is staged or aborted
+pub(super) fn clear(installation: &Installation) -> io::Result<()> {
+    match fs::remove_file(path(installation)) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+/// Whether `journal` belongs to a transaction that was staged after all, i.e. if
+/// moss was interrupted right after marking it `pending` or it's `active` already.
+/// Such a journal must be cleared rather than resumed or aborted
+pub fn is_stale(journal: &Journal, pending: Option<&Pending>, active: Option<state::Id>) -> bool {
+    // No transaction starts while another is pending, so the marker is for the journaled one
+    pending.is_some() || (journal.state.is_some() && journal.state == active)
+}
+
+fn path(installation: &Installation) -> PathBuf {
+    installation.root_path(FILE)
+}
+
+/// The summary, previous & recorded state on their own lines, followed by
+/// a tab separated line per selection
+fn encode(journal: &Journal) -> String {
+    let id = |id: Option<state::Id>| id.map(|id| id.to_string()).unwrap_or_default();
+
+    let mut contents = format!(
+        "{}\n{}\n{}\n",
+        journal.summary.replace('\n', " "),
+        id(journal.previous),
+        id(journal.state)
+    );
+    for selection in &journal.selections {
+        contents.push_str(&format!(
+            "{}\t{}\t{}\n",
+            selection.package,
+            u8::from(selection.explicit),
+            selection
+                .reason
+                .as_deref()
+                .unwrap_or_default()
+                .replace(['\t', '\n'], " ")
+        ));
+    }
+
+    contents
+}
+
+fn parse(contents: &str) -> Option<Journal> {
+    let mut lines = contents.lines();
+
+    let id = |line: &str| -> Option<Option<state::Id>> {
+        match line.trim() {
+            "" => Some(None),
+            line => Some(Some(line.parse::<i32>().ok()?.into())),
+        }
+    };
+
+    let summary = lines.next()?.to_string();
+    let previous = id(lines.next()?)?;
+    let state = id(lines.next()?)?;
+
+    let selections = lines
+        .map(|line| {
+            let mut fields = line.split('\t');
+
+            let package = package::Id::from(fields.next().filter(|id| !id.is_empty())?.to_string());
+            let explicit = match fields.next()? {
+                "1" => true,
+                "0" => false,
+                _ => return None,
+            };
+            let reason = fields.next().filter(|reason| !reason.is_empty()).map(str::to_string);
+
+            Some(Selection {
+                package,
+                explicit,
+                reason,
+            })
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    Some(Journal {
+        summary,
+        previous,
+        state,
+        selections,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let journal = Journal {
+            summary: "Install".to_string(),
+            previous: Some(3.into()),
+            state: None,
+            selections: vec![
+                Selection::explicit(package::Id::from("a".to_string())),
+                Selection {
+                    package: package::Id::from("b".to_string()),
+                    explicit: false,
+                    reason: Some("runtime of a".to_string()),
+                },
+            ],
+        };
+        assert_eq!(parse(&encode(&journal)), Some(journal.clone()));
+
+        let journal = Journal {
+            previous: None,
+            state: Some(4.into()),
+            ..journal
+        };
+        assert_eq!(parse(&encode(&journal)), Some(journal));
+
+        assert_eq!(parse("Install\nthree\n\n"), None);
+        assert_eq!(parse("Install\n\n\na\tmaybe\t\n"), None);
+    }
+
+    #[test]
+    fn crash_windows() {
+        let active = Some(3.into());
+        let fetching = Journal {
+            summary: "Install".to_string(),
+            previous: active,
+            state: None,
+            selections: vec![],
+        };
+        let recorded = Journal {
+            state: Some(4.into()),
+            ..fetching.clone()
+        };
+        let staged = Pending {
+            state: 4.into(),
+            previous: active,
+            swapped: false,
+        };
+
+        // Interrupted while fetching or blitting, before & after the new state is recorded
+        assert!(!is_stale(&fetching, None, active));
+        assert!(!is_stale(&recorded, None, active));
+
+        // Interrupted between marking the staged tree & clearing the journal
+        assert!(is_stale(&recorded, Some(&staged), active));
+
+        // ... and again after swapping it into place
+        let swapped = Pending {
+            swapped: true,
+            ..staged
+        };
+        assert!(is_stale(&recorded, Some(&swapped), Some(4.into())));
+
+        // The marker is gone once resumed, but the journal was left behind
+        assert!(is_stale(&recorded, None, Some(4.into())));
+    }
+}
@@ -10,11 +10,16 @@
 
 use std::{
     borrow::Borrow,
-    fmt,
+    collections::{BTreeMap, BTreeSet},
+    env, fmt,
     fs::{self, create_dir_all},
     io,
     os::{fd::RawFd, unix::fs::symlink},
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
     time::Duration,
 };
 
@@ -29,27 +34,53 @@ use nix::{
 };
 use stone::{payload::layout, read::PayloadKind};
 use thiserror::Error;
-use tui::{MultiProgress, ProgressBar, ProgressStyle, Styled};
+use tracing::{debug, info, warn};
+use tui::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle, Styled};
+use url::Url;
 use vfs::tree::{builder::TreeBuilder, BlitFile, Element};
 
 use self::install::install;
 use self::prune::prune;
 use self::verify::verify;
 use crate::{
-    db, environment, installation, package,
-    registry::plugin::{self, Plugin},
-    repository, runtime,
+    db, environment, installation, output, package,
+    registry::{
+        alternatives::{self, Alternatives},
+        plugin::{self, Plugin},
+    },
+    repository, request, runtime, settings, signature,
     state::{self, Selection},
-    Installation, Package, Registry, State,
+    Installation, Package, Registry, Repository, State,
 };
 
 pub mod boot;
 pub mod cache;
+pub mod check;
+pub mod drift;
+pub mod exclusion;
+pub mod file_conflict;
+pub mod graph;
+pub mod history;
+pub mod hold;
+pub mod hooks;
 pub mod install;
-mod postblit;
+pub mod journal;
+pub mod multi_root;
+pub mod plan;
+pub mod postblit;
 pub mod prune;
+pub mod rebuild;
+pub mod remove;
+pub mod snapshot;
+pub mod space;
+pub mod staging;
+pub mod sync;
 mod verify;
 
+/// Called with each package [`Client::cache_packages`] has fetched & unpacked,
+/// along with how many of its packages are done & in total
+pub type OnCached = Arc<dyn Fn(&Package, usize, usize) + Send + Sync>;
+
 /// A Client is a connection to the underlying package management systems
 pub struct Client {
     pub name: String,
@@ -76,6 +107,21 @@ pub struct Client {
 
     /// Operational scope (real systems, ephemeral, etc)
     scope: Scope,
+
+    /// Ask which alternative to select for ambiguous providers
+    interactive_alternatives: bool,
+
+    /// Local stones made available for installation
+    sideloaded: plugin::Cobble,
+
+    /// Whether the [`journal`] on disk belongs to the transaction this client is applying
+    journaled: AtomicBool,
+
+    /// Reports each cached package, see [`Client::on_cached`]
+    on_cached: Option<OnCached>,
+
+    /// Apply transactions vetoed by a [`check::Check`], see [`Client::skip_checks`]
+    skip_checks: bool,
 }
 
 impl Client {
@@ -101,9 +147,18 @@ impl Client {
     ) -> Result<Client, Error> {
         let name = client_name.to_string();
         let config = config::Manager::system(&installation.root, "moss");
-        let install_db = db::meta::Database::new(installation.db_path("install").to_str().unwrap_or_default())?;
-        let state_db = db::state::Database::new(installation.db_path("state").to_str().unwrap_or_default())?;
-        let layout_db = db::layout::Database::new(installation.db_path("layout").to_str().unwrap_or_default())?;
+        let install_db = db::meta::Database::open(
+            installation.db_path("install").to_str().unwrap_or_default(),
+            installation.mutability,
+        )?;
+        let state_db = db::state::Database::open(
+            installation.db_path("state").to_str().unwrap_or_default(),
+            installation.mutability,
+        )?;
+        let layout_db = db::layout::Database::open(
+            installation.db_path("layout").to_str().unwrap_or_default(),
+            installation.mutability,
+        )?;
 
         let repositories = if let Some(repos) = repositories {
             repository::Manager::explicit(&name, repos, installation.clone())?
@@ -111,7 +166,16 @@ impl Client {
             repository::Manager::system(config.clone(), installation.clone())?
         };
 
-        let registry = build_registry(&installation, &repositories, &install_db, &state_db)?;
+        let alternatives = Alternatives::new(config.load::<alternatives::Preference>(), false);
+        let sideloaded = plugin::Cobble::default();
+        let registry = build_registry(
+            &installation,
+            &repositories,
+            &install_db,
+            &state_db,
+            &sideloaded,
+            alternatives,
+        )?;
 
         Ok(Client {
             name,
@@ -123,17 +187,90 @@ impl Client {
             state_db,
             layout_db,
             scope: Scope::Stateful,
+            interactive_alternatives: false,
+            sideloaded,
+            journaled: AtomicBool::new(false),
+            on_cached: None,
+            skip_checks: false,
         })
     }
 
+    /// Ask which package to select when differently named packages provide a
+    /// dependency, rather than picking the highest priority one. Configured
+    /// [`alternatives::Preference`]s always take precedence.
+    pub fn prompt_alternatives(&mut self, interactive: bool) {
+        self.interactive_alternatives = interactive;
+        self.registry.set_alternatives(self.alternatives());
+    }
+
+    /// Report each package cached by [`Self::cache_packages`] to `on_cached`, i.e. for
+    /// frontends drawing their own progress while [`tui::set_quiet`] is in effect
+    pub fn on_cached(&mut self, on_cached: Option<OnCached>) {
+        self.on_cached = on_cached;
+    }
+
+    /// Apply transactions even if a [`check::Check`] vetoes them, only
+    /// reporting the vetoes
+    pub fn skip_checks(&mut self, skip: bool) {
+        self.skip_checks = skip;
+    }
+
+    fn alternatives(&self) -> Alternatives {
+        Alternatives::new(
+            self.config.load::<alternatives::Preference>(),
+            self.interactive_alternatives,
+        )
+    }
+
     /// Returns `true` if this is an ephemeral client
     pub fn is_ephemeral(&self) -> bool {
         matches!(self.scope, Scope::Ephemeral { .. })
     }
 
     /// Perform an installation via [`install::install`]
-    pub fn install(&mut self, packages: &[&str], yes: bool) -> Result<install::Timing, install::Error> {
-        install(self, packages, yes)
+    ///
+    /// If `dry_run` is set, the computed [`plan::Plan`] is printed in that format
+    /// and nothing is applied
+    pub fn install(
+        &mut self,
+        packages: &[&str],
+        yes: bool,
+        dry_run: Option<plan::Format>,
+    ) -> Result<install::Timing, install::Error> {
+        install(self, packages, yes, dry_run)
+    }
+
+    /// Compute the [`plan::Plan`] for installing `packages`, without applying it
+    ///
+    /// See [`install::resolve`] for the full resolution
+    pub fn resolve(&mut self, packages: &[&str]) -> Result<plan::Plan, install::Error> {
+        let resolution = install::resolve(self, packages)?;
+        Ok(plan::Plan::new(self, &resolution.missing, &resolution.conflicting))
+    }
+
+    /// All installed packages matching `flags`, sorted by name
+    pub fn query_installed(&self, flags: package::Flags) -> Vec<Package> {
+        self.registry.list_installed(flags).collect()
+    }
+
+    /// All packages available from the configured repositories
+    /// & sideloaded stones matching `flags`, sorted by name
+    pub fn query_available(&self, flags: package::Flags) -> Vec<Package> {
+        self.registry.list_available(flags).collect()
+    }
+
+    /// All configured repositories
+    pub fn repositories(&self) -> impl ExactSizeIterator<Item = (&repository::Id, &Repository)> {
+        self.repositories.list()
+    }
+
+    /// Undo the recorded `transaction` via [`history::undo`]
+    pub fn undo(&self, transaction: &state::Transaction, yes: bool) -> Result<(), history::Error> {
+        if self.scope.is_ephemeral() {
+            return Err(Error::EphemeralProhibitedOperation.into());
+        }
+
+        history::undo(self, transaction, yes)
     }
 
     /// Transition to an ephemeral client that doesn't record state changes
@@ -161,12 +298,84 @@ impl Client {
     /// are downloaded and added to the meta db
     pub async fn ensure_repos_initialized(&mut self) -> Result<usize, Error> {
         let num_initialized = self.repositories.ensure_all_initialized().await?;
-        self.registry = build_registry(&self.installation, &self.repositories, &self.install_db, &self.state_db)?;
+        self.registry = build_registry(
+            &self.installation,
+            &self.repositories,
+            &self.install_db,
+            &self.state_db,
+            &self.sideloaded,
+            self.alternatives(),
+        )?;
         Ok(num_initialized)
     }
 
+    /// Use the repository at `uri` for the lifetime of this client, taking precedence over
+    /// all configured repositories. It's never persisted, i.e. for a one-off install from
+    /// a staging repository.
+    pub async fn add_transient_repository(&mut self, uri: Url) -> Result<repository::Id, Error> {
+        let priority = self
+            .repositories
+            .list()
+            .map(|(_, repo)| repo.priority)
+            .min()
+            .map_or(repository::Priority::new(0), |highest| {
+                repository::Priority::new(u64::from(highest) + 1)
+            });
+        let id = repository::Id::new(uri.to_string());
+
+        self.repositories.add_transient(
+            id.clone(),
+            Repository {
+                description: format!("Transient repository {uri}"),
+                uri,
+                priority,
+                quota: None,
+                trusted_keys: vec![],
+            },
+        )?;
+        self.repositories.refresh(&id).await?;
+
+        self.registry = build_registry(
+            &self.installation,
+            &self.repositories,
+            &self.install_db,
+            &self.state_db,
+            &self.sideloaded,
+            self.alternatives(),
+        )?;
+
+        Ok(id)
+    }
+
+    /// Make the local stone at `path` available for installation, resolving its
+    /// dependencies against the configured repositories. It's recorded as sideloaded
+    /// from `source` once installed.
+    pub fn sideload(&mut self, path: impl Into<PathBuf>, source: impl ToString) -> Result<package::Id, Error> {
+        let id = package::Id::from(self.sideloaded.add_package(path, source)?);
+
+        self.registry = build_registry(
+            &self.installation,
+            &self.repositories,
+            &self.install_db,
+            &self.state_db,
+            &self.sideloaded,
+            self.alternatives(),
+        )?;
+
+        Ok(id)
+    }
+
+    /// Download the stone at `url` to the cache, then [`Client::sideload`] it
+    pub async fn sideload_url(&mut self, url: Url) -> Result<package::Id, Error> {
+        let path = cache::fetch_sideloaded(&url, &self.installation).await?;
+
+        self.sideload(path, url)
+    }
+
     /// Reload all configured repositories and refreshes their index file, then update
     /// registry with all active repositories.
+    ///
+    /// Only local repositories are refreshed in offline mode.
     pub async fn refresh_repositories(&mut self) -> Result<(), Error> {
         // Reload manager if not explicit to pickup config changes
         // then refresh indexes
@@ -176,7 +385,14 @@ impl Client {
         self.repositories.refresh_all().await?;
 
         // Rebuild registry
-        self.registry = build_registry(&self.installation, &self.repositories, &self.install_db, &self.state_db)?;
+        self.registry = build_registry(
+            &self.installation,
+            &self.repositories,
+            &self.install_db,
+            &self.state_db,
+            &self.sideloaded,
+            self.alternatives(),
+        )?;
 
         Ok(())
     }
@@ -205,6 +421,154 @@ impl Client {
             &self.installation,
             yes,
         )?;
+
+        if let Some(active) = self.installation.active_state {
+            self.synchronize_boot_states(active)?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove packages which no state selects, along with any downloads &
+    /// assets nothing else refers to. See [`prune::collect_garbage`]
+    pub fn collect_garbage(&self) -> Result<prune::Collected, Error> {
+        if self.scope.is_ephemeral() {
+            return Err(Error::EphemeralProhibitedOperation);
+        }
+
+        Ok(prune::collect_garbage(
+            &self.state_db,
+            &self.install_db,
+            &self.layout_db,
+            &self.installation,
+        )?)
+    }
+
+    /// Boot entries on the ESP of the installation
+    pub fn boot_entries(&self) -> Result<Vec<boot::Entry>, Error> {
+        Ok(boot::entries(&self.installation)?)
+    }
+
+    /// Make the bootloader select entry `id` by default
+    pub fn set_default_boot_entry(&self, id: &str) -> Result<(), Error> {
+        if self.scope.is_ephemeral() {
+            return Err(Error::EphemeralProhibitedOperation);
+        }
+
+        Ok(boot::set_default(&self.installation, id)?)
+    }
+
+    /// Run an integrity check on every database, returning the problems found in each
+    pub fn check_databases(&self) -> Result<Vec<(String, Vec<String>)>, Error> {
+        let mut results = vec![
+            ("install".to_string(), self.install_db.check()?),
+            ("layout".to_string(), self.layout_db.check()?),
+            ("state".to_string(), self.state_db.check()?),
+        ];
+
+        for repo in self.repositories.active() {
+            results.push((format!("repository {}", repo.id), repo.db.check()?));
+        }
+
+        Ok(results)
+    }
+
+    /// Vacuum every database, returning the number of bytes reclaimed from each
+    pub fn vacuum_databases(&self) -> Result<Vec<(String, u64)>, Error> {
+        let mut results = vec![
+            ("install".to_string(), self.install_db.vacuum()?),
+            ("layout".to_string(), self.layout_db.vacuum()?),
+            ("state".to_string(), self.state_db.vacuum()?),
+        ];
+
+        for repo in self.repositories.active() {
+            results.push((format!("repository {}", repo.id), repo.db.vacuum()?));
+        }
+
+        Ok(results)
+    }
+
+    /// All files in the download cache
+    pub fn cached_downloads(&self) -> Result<Vec<cache::Cached>, Error> {
+        Ok(cache::cached(&self.installation)?)
+    }
+
+    /// Remove everything from the download cache, returning the number of bytes freed
+    pub fn clean_cache(&self) -> Result<u64, Error> {
+        Ok(cache::remove(&self.installation, &self.cached_downloads()?)?)
+    }
+
+    /// Remove all downloads which aren't referenced by any state, returning
+    /// the number of bytes freed
+    pub fn prune_cache(&self) -> Result<u64, Error> {
+        let packages = self
+            .state_db
+            .all()?
+            .into_iter()
+            .flat_map(|state| state.selections.into_iter().map(|s| s.package))
+            .collect::<BTreeSet<_>>();
+
+        let mut referenced = BTreeSet::new();
+        for package in &packages {
+            match self.install_db.get(package) {
+                Ok(meta) => referenced.extend(meta.hash),
+                Err(db::Error::Diesel(diesel::result::Error::NotFound)) => {}
+                Err(error) => return Err(error.into()),
+            }
+        }
+
+        let unreferenced = self
+            .cached_downloads()?
+            .into_iter()
+            .filter(|file| !file.hash.as_ref().is_some_and(|hash| referenced.contains(hash)))
+            .collect::<Vec<_>>();
+
+        Ok(cache::remove(&self.installation, &unreferenced)?)
+    }
+
+    /// Prune the oldest states beyond the configured amount to keep, once `active` was applied
+    fn auto_prune_states(&self, active: state::Id) -> Result<(), Error> {
+        let Some(keep) = settings::Settings::load(&self.config).keep_states else {
+            return Ok(());
+        };
+        // The active state is always kept
+        let keep = keep.max(1);
+
+        // Our installation still refers to the state which was active before the transaction
+        let installation = Installation {
+            active_state: Some(active),
+            ..self.installation.clone()
+        };
+
+        prune(
+            prune::Strategy::KeepRecent {
+                keep,
+                include_newer: false,
+            },
+            &self.state_db,
+            &self.install_db,
+            &self.layout_db,
+            &installation,
+            true,
+        )?;
+
+        self.synchronize_boot_states(active)?;
+
+        Ok(())
+    }
+
+    /// Prune the download cache if it grew beyond the configured threshold
+    fn auto_prune_cache(&self) -> Result<(), Error> {
+        let Some(threshold) = cache::Settings::prune_threshold(&self.config.load::<cache::Settings>()) else {
+            return Ok(());
+        };
+
+        let size = self.cached_downloads()?.iter().map(|file| file.size).sum::<u64>();
+        if size > threshold {
+            let freed = self.prune_cache()?;
+            info!("pruned {} from the download cache", HumanBytes(freed));
+        }
+
         Ok(())
     }
 
@@ -241,6 +605,12 @@ impl Client {
             return Err(Error::StateAlreadyActive(id));
         }
 
+        self.ensure_nothing_pending()?;
+
+        let summary = format!("Activate #{id}");
+        self.run_hooks(hooks::Phase::PreTransaction, &summary, Some(old), None, &new.selections)?;
+        self.snapshot(old)?;
+
         let staging_dir = self.installation.staging_dir();
 
         // Ensure staging dir exists
@@ -250,22 +620,33 @@ impl Client {
 
         // Move new (archived) state to staging
         fs::rename(self.installation.root_path(new.id.to_string()), &staging_dir)?;
-
-        // Promote staging
-        self.promote_staging()?;
-
-        // Archive old state
-        self.archive_state(old)?;
+        staging::mark(&self.installation, new.id, Some(old))?;
 
         // Build VFS from new state selections
         // to build triggers from
-        let fstree = self.vfs(new.selections.iter().map(|selection| &selection.package))?;
+        let fstree = self.vfs_excluding(
+            new.selections.iter().map(|selection| &selection.package),
+            &self.state_db.exclusions(new.id)?,
+        )?;
 
-        // Run system triggers
-        let sys_triggers =
-            postblit::triggers(postblit::TriggerScope::System(&self.installation, &self.scope), &fstree)?;
-        for trigger in sys_triggers {
-            trigger.execute()?;
+        // Promote staging, archive the old state & run system triggers
+        self.swap_staging(&fstree, Some(old), false)?;
+
+        let layouts = self.layout_db.query(new.selections.iter().map(|s| &s.package))?;
+        boot::synchronize(&self.installation, &layouts)?;
+        self.synchronize_boot_states(new.id)?;
+
+        self.record_transaction(&summary, Some(old), &new)?;
+
+        // The transaction is complete, so failing hooks aren't fatal
+        if let Err(error) = self.run_hooks(
+            hooks::Phase::PostTransaction,
+            &summary,
+            Some(old),
+            Some(new.id),
+            &new.selections,
+        ) {
+            warn!("failed to run post-transaction hooks: {error}");
         }
 
         Ok(old)
@@ -278,16 +659,66 @@ impl Client {
     /// Returns `None` if the client is ephemeral
     pub fn new_state(&self, selections: &[Selection], summary: impl ToString) -> Result<Option<State>, Error> {
         let old_state = self.installation.active_state;
+        let summary = summary.to_string();
 
-        let fstree = self.blit_root(selections.iter().map(|s| &s.package))?;
+        if !self.scope.is_ephemeral() {
+            self.ensure_nothing_pending()?;
+        }
+
+        // A refused transaction leaves nothing to resume, not even what was journaled while fetching
+        let (excluded, tree) = match self.prepare_transaction(old_state, selections, &summary) {
+            Ok(prepared) => prepared,
+            Err(error) => {
+                if self.journaled.load(Ordering::Relaxed) {
+                    self.clear_journal()?;
+                }
+                return Err(error);
+            }
+        };
+
+        // Until it's staged, an interrupted transaction can be resumed or aborted from its journal
+        let mut journal = journal::Journal {
+            summary: summary.clone(),
+            previous: old_state,
+            state: None,
+            selections: selections.to_vec(),
+        };
+        if let Scope::Stateful = &self.scope {
+            self.write_journal(&journal)?;
+        }
+
+        let fstree = self.blit_root(tree)?;
 
         match &self.scope {
             Scope::Stateful => {
                 // Add to db
-                let state = self.state_db.add(selections, Some(&summary.to_string()), None)?;
+                let state = self.state_db.add(selections, Some(&summary), None)?;
+                self.state_db.add_exclusions(state.id, &excluded)?;
+
+                journal.state = Some(state.id);
+                self.write_journal(&journal)?;
 
                 self.apply_stateful_blit(fstree, &state, old_state)?;
 
+                self.record_transaction(&summary, old_state, &state)?;
+
+                // The transaction is complete, so failing hooks or pruning isn't fatal
+                if let Err(error) = self.run_hooks(
+                    hooks::Phase::PostTransaction,
+                    &summary,
+                    old_state,
+                    Some(state.id),
+                    selections,
+                ) {
+                    warn!("failed to run post-transaction hooks: {error}");
+                }
+                if let Err(error) = self.auto_prune_states(state.id) {
+                    warn!("failed to prune old states: {error}");
+                }
+                if let Err(error) = self.auto_prune_cache() {
+                    warn!("failed to prune the download cache: {error}");
+                }
+
                 Ok(Some(state))
             }
             Scope::Ephemeral { blit_root } => {
@@ -298,6 +729,175 @@ impl Client {
         }
     }
 
+    /// Give all checks & pre-transaction hooks a chance to veto the transaction to
+    /// `selections` before we blit, returning the `(package, path)` entries to leave
+    /// out & the tree to blit
+    fn prepare_transaction(
+        &self,
+        old_state: Option<state::Id>,
+        selections: &[Selection],
+        summary: &str,
+    ) -> Result<(BTreeSet<(package::Id, String)>, vfs::Tree<PendingFile>), Error> {
+        let excluded = self.exclusions(selections.iter().map(|s| &s.package))?;
+
+        let tree = self.checked_vfs(old_state, selections, &excluded)?;
+        if let (Scope::Stateful, Some(old)) = (&self.scope, old_state) {
+            self.check_transaction(old, selections)?;
+        }
+        if !self.scope.is_ephemeral() {
+            self.run_hooks(hooks::Phase::PreTransaction, summary, old_state, None, selections)?;
+        }
+        if let (Scope::Stateful, Some(old)) = (&self.scope, old_state) {
+            self.snapshot(old)?;
+        }
+
+        Ok((excluded, tree))
+    }
+
+    /// Record the transition from `previous` to `state` in the transaction
+    /// history, along with the command line which applied it
+    fn record_transaction(&self, summary: &str, previous: Option<state::Id>, state: &State) -> Result<(), Error> {
+        let previous_state = previous.map(|id| self.state_db.get(id)).transpose()?;
+        let changes = history::Changes::between(previous_state.as_ref(), state);
+        let command = env::args().join(" ");
+
+        self.state_db.add_transaction(
+            &command,
+            Some(summary),
+            previous,
+            state.id,
+            changes
+                .added
+                .iter()
+                .map(|s| (&s.package, true))
+                .chain(changes.removed.iter().map(|s| (&s.package, false))),
+        )?;
+
+        Ok(())
+    }
+
+    /// Run the [`hooks`] of `phase` for the transaction from
+    /// the `previous` state to `selections`
+    fn run_hooks(
+        &self,
+        phase: hooks::Phase,
+        summary: &str,
+        previous: Option<state::Id>,
+        state: Option<state::Id>,
+        selections: &[Selection],
+    ) -> Result<(), Error> {
+        let hooks = hooks::load(&self.installation, phase)?;
+        if hooks.is_empty() {
+            return Ok(());
+        }
+
+        let previous_selections = match previous {
+            Some(id) => self.state_db.get(id)?.selections,
+            None => vec![],
+        };
+        let contains = |selections: &[Selection], id: &package::Id| selections.iter().any(|s| s.package == *id);
+
+        let added = self.resolve_packages(
+            selections
+                .iter()
+                .map(|s| &s.package)
+                .filter(|id| !contains(&previous_selections, id)),
+        )?;
+        let removed = self.resolve_packages(
+            previous_selections
+                .iter()
+                .map(|s| &s.package)
+                .filter(|id| !contains(selections, id)),
+        )?;
+
+        hooks::run(
+            &hooks,
+            &hooks::Transaction {
+                phase,
+                summary,
+                root: &self.installation.root,
+                previous_state: previous.map(i32::from),
+                state: state.map(i32::from),
+                plan: plan::Plan::new(self, &added, &removed),
+            },
+        )?;
+
+        Ok(())
+    }
+
+    /// Take the configured filesystem [`snapshot`]s before moving away from
+    /// the `active` state, recording them so it can be restored
+    fn snapshot(&self, active: state::Id) -> Result<(), Error> {
+        let Some(subvolumes) = snapshot::Settings::subvolumes(&self.config.load::<snapshot::Settings>()) else {
+            return Ok(());
+        };
+
+        let snapshots = snapshot::take(&self.installation, &subvolumes, active)?;
+        self.state_db.add_snapshots(&snapshots)?;
+
+        Ok(())
+    }
+
+    /// Build the [`vfs::Tree`] of `selections` to blit, refusing them if any of the
+    /// packages new to them would blit a file owned by another package, see [`file_conflict`]
+    fn checked_vfs(
+        &self,
+        old: Option<state::Id>,
+        selections: &[Selection],
+        excluded: &BTreeSet<(package::Id, String)>,
+    ) -> Result<vfs::Tree<PendingFile>, Error> {
+        let previous = match old {
+            Some(id) => self.state_db.get(id)?.selections,
+            None => vec![],
+        };
+        let incoming = |id: &package::Id| !previous.iter().any(|s| s.package == *id);
+
+        match self.build_vfs(selections.iter().map(|s| &s.package), excluded, |file, other| {
+            file_conflict::conflicting(file, other, incoming)
+        }) {
+            Err(Error::Filesystem(vfs::tree::Error::Duplicates(duplicates))) => Err(file_conflict::report(
+                &file_conflict::from_duplicates(duplicates),
+                |id| {
+                    self.install_db
+                        .get(id)
+                        .map(|meta| meta.name.to_string())
+                        .unwrap_or_else(|_| id.to_string())
+                },
+            )
+            .into()),
+            result => result,
+        }
+    }
+
+    /// Run all [`check::Check`]s against the packages that will no
+    /// longer be installed when moving from state `old` to `selections`
+    fn check_transaction(&self, old: state::Id, selections: &[Selection]) -> Result<(), Error> {
+        let previous = self.state_db.get(old)?.selections;
+        let previous = self.resolve_packages(previous.iter().map(|s| &s.package))?;
+        let installed = self.resolve_packages(selections.iter().map(|s| &s.package))?;
+
+        let removed = check::removed(previous, &installed);
+
+        if removed.is_empty() {
+            return Ok(());
+        }
+
+        let layouts = self.layout_db.query(removed.iter().map(|p| &p.id))?;
+
+        check::run(
+            &check::load(&self.config),
+            &check::Transaction {
+                installation: &self.installation,
+                removed: &removed,
+                installed: &installed,
+                layouts: &layouts,
+            },
+            self.skip_checks,
+        )?;
+
+        Ok(())
+    }
+
     pub fn apply_stateful_blit(
         &self,
         fstree: vfs::Tree<PendingFile>,
@@ -308,39 +908,230 @@ impl Client {
         record_os_release(&self.installation.staging_dir(), Some(state.id))?;
 
         // Run all of the transaction triggers
-        let triggers = postblit::triggers(
+        create_root_links(&self.installation.isolation_dir())?;
+        postblit::run(
             postblit::TriggerScope::Transaction(&self.installation, &self.scope),
             &fstree,
+            &self.install_db,
         )?;
-        create_root_links(&self.installation.isolation_dir())?;
-        for trigger in triggers {
-            trigger.execute()?;
+
+        // From here on the transaction can be resumed
+        staging::mark(&self.installation, state.id, old_state)?;
+        self.clear_journal()?;
+        self.swap_staging(&fstree, old_state, false)?;
+
+        // Last but not least, let us see some boot management on the current state,
+        // if the transaction touched the kernel or bootloader
+        let layouts = self.layout_db.query(state.selections.iter().map(|s| &s.package))?;
+        let boot_changed = match old_state {
+            Some(old) => {
+                let old_layouts = self
+                    .layout_db
+                    .query(self.state_db.get(old)?.selections.iter().map(|s| &s.package))?;
+                boot::changed(&old_layouts, &layouts)?
+            }
+            None => true,
+        };
+        if boot_changed {
+            boot::synchronize(&self.installation, &layouts)?;
         }
+
+        // The previous state was archived, so give it an entry of its own
+        self.synchronize_boot_states(state.id)?;
+
+        Ok(())
+    }
+
+    /// Keep every archived state bootable, see [`boot::synchronize_states`]
+    fn synchronize_boot_states(&self, active: state::Id) -> Result<(), Error> {
+        let archived = self
+            .state_db
+            .list_ids()?
+            .into_iter()
+            .map(|(id, _)| id)
+            .filter(|id| *id != active && self.installation.root_path(id.to_string()).join("usr").exists())
+            .collect::<Vec<_>>();
+
+        boot::synchronize_states(&self.installation, &archived)?;
+
+        Ok(())
+    }
+
+    /// Swap the completed staging tree into place, unless it already was, then
+    /// archive the `previous` state and run system triggers against the new one.
+    ///
+    /// Each step can be repeated, so this is safe to resume after an interruption
+    fn swap_staging(
+        &self,
+        fstree: &vfs::Tree<PendingFile>,
+        previous: Option<state::Id>,
+        swapped: bool,
+    ) -> Result<(), Error> {
         // Staging is only used with [`Scope::Stateful`]
-        self.promote_staging()?;
+        if !swapped {
+            self.promote_staging()?;
+        }
 
         // Now we got it staged, we need working rootfs
         create_root_links(&self.installation.root)?;
 
-        if let Some(id) = old_state {
+        if let Some(id) = previous {
             self.archive_state(id)?;
         }
+        staging::clear(&self.installation)?;
 
         // At this point we're allowed to run system triggers
-        let sys_triggers =
-            postblit::triggers(postblit::TriggerScope::System(&self.installation, &self.scope), &fstree)?;
-        for trigger in sys_triggers {
-            trigger.execute()?;
+        postblit::run(
+            postblit::TriggerScope::System(&self.installation, &self.scope),
+            fstree,
+            &self.install_db,
+        )?;
+
+        Ok(())
+    }
+
+    /// Complete an interrupted transaction, either swapping its staged state
+    /// into place or, if it never got that far, applying its [`journal`] anew
+    ///
+    /// Returns the applied state, or `None` if nothing was pending
+    pub fn resume(&self) -> Result<Option<State>, Error> {
+        if self.scope.is_ephemeral() {
+            return Err(Error::EphemeralProhibitedOperation);
         }
 
-        // Last but not least, let us see some boot management on the current state
+        let Some(pending) = staging::pending(&self.installation)? else {
+            return self.resume_journal();
+        };
+
+        let state = self.state_db.get(pending.state)?;
+        let fstree = self.vfs_excluding(
+            state.selections.iter().map(|selection| &selection.package),
+            &self.state_db.exclusions(state.id)?,
+        )?;
+
+        // A previous state that was never archived is still staged
+        let previous = pending
+            .previous
+            .filter(|id| !self.installation.root_path(id.to_string()).join("usr").exists());
+
+        self.swap_staging(&fstree, previous, pending.swapped)?;
+        // Left behind if interrupted right after marking the staged tree
+        self.clear_journal()?;
+
         let layouts = self.layout_db.query(state.selections.iter().map(|s| &s.package))?;
         boot::synchronize(&self.installation, &layouts)?;
+        self.synchronize_boot_states(state.id)?;
+
+        self.record_transaction(&format!("Resume #{}", state.id), pending.previous, &state)?;
+
+        Ok(Some(state))
+    }
+
+    /// Fetch whatever the journaled transaction is missing from
+    /// the cache & apply it from scratch
+    fn resume_journal(&self) -> Result<Option<State>, Error> {
+        let Some(journal) = self.load_journal()? else {
+            return Ok(None);
+        };
+
+        // Only discarded once fetched, so a failed download can be resumed again
+        let packages = self.resolve_packages(journal.selections.iter().map(|s| &s.package))?;
+        runtime::block_on(self.cache_packages(&packages))?;
+        self.discard_journal()?;
+
+        self.new_state(&journal.selections, &journal.summary)
+    }
+
+    /// Abandon a transaction interrupted before its new state was staged,
+    /// removing the partially blitted staging tree & any state recorded for it
+    ///
+    /// Returns the aborted transaction, or `None` if nothing was pending
+    pub fn abort(&self) -> Result<Option<journal::Journal>, Error> {
+        if self.scope.is_ephemeral() {
+            return Err(Error::EphemeralProhibitedOperation);
+        }
+
+        // Once staged the previous `/usr` may already be gone
+        if let Some(pending) = staging::pending(&self.installation)? {
+            return Err(Error::AlreadyStaged(pending.state));
+        }
+
+        self.discard_journal()
+    }
+
+    /// Remove the journal along with everything its transaction left behind
+    fn discard_journal(&self) -> Result<Option<journal::Journal>, Error> {
+        let Some(journal) = self.load_journal()? else {
+            return Ok(None);
+        };
+
+        let staging_dir = self.installation.staging_dir();
+        if staging_dir.exists() {
+            fs::remove_dir_all(&staging_dir)?;
+        }
+        if let Some(state) = journal.state {
+            self.state_db.remove(&state)?;
+        }
+        self.clear_journal()?;
+
+        Ok(Some(journal))
+    }
+
+    /// The journal of a transaction interrupted before it was staged, clearing
+    /// it instead if the transaction was staged after all, see [`journal::is_stale`]
+    fn load_journal(&self) -> Result<Option<journal::Journal>, Error> {
+        let Some(journal) = journal::load(&self.installation)? else {
+            return Ok(None);
+        };
+
+        let pending = staging::pending(&self.installation)?;
+        if journal::is_stale(&journal, pending.as_ref(), self.installation.active_state) {
+            self.clear_journal()?;
+            return Ok(None);
+        }
+
+        Ok(Some(journal))
+    }
+
+    fn write_journal(&self, journal: &journal::Journal) -> Result<(), Error> {
+        journal::write(&self.installation, journal)?;
+        self.journaled.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn clear_journal(&self) -> Result<(), Error> {
+        journal::clear(&self.installation)?;
+        self.journaled.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Refuse to start a transaction while another is waiting to be [resumed](Self::resume)
+    fn ensure_nothing_pending(&self) -> Result<(), Error> {
+        if let Some(pending) = staging::pending(&self.installation)? {
+            return Err(Error::PendingTransaction(pending.state));
+        }
+        // Unless it's the journal of our own transaction, written while fetching
+        if self.journaled.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        if let Some(journal) = self.load_journal()? {
+            return Err(Error::InterruptedTransaction(journal.summary));
+        }
 
         Ok(())
     }
 
     pub fn apply_ephemeral_blit(&self, fstree: vfs::Tree<PendingFile>, blit_root: &Path) -> Result<(), Error> {
+        self.apply_ephemeral_blit_scoped(fstree, blit_root, &self.scope)
+    }
+
+    /// Finalize an ephemeral `blit_root`, running its triggers within `scope`
+    fn apply_ephemeral_blit_scoped(
+        &self,
+        fstree: vfs::Tree<PendingFile>,
+        blit_root: &Path,
+        scope: &Scope,
+    ) -> Result<(), Error> {
         record_os_release(blit_root, None)?;
         create_root_links(blit_root)?;
         create_root_links(&self.installation.isolation_dir())?;
@@ -349,19 +1140,17 @@ impl Client {
         create_dir_all(etc)?;
 
         // ephemeral tx triggers
-        let triggers = postblit::triggers(
-            postblit::TriggerScope::Transaction(&self.installation, &self.scope),
+        postblit::run(
+            postblit::TriggerScope::Transaction(&self.installation, scope),
             &fstree,
+            &self.install_db,
         )?;
-        for trigger in triggers {
-            trigger.execute()?;
-        }
         // ephemeral system triggers
-        let sys_triggers =
-            postblit::triggers(postblit::TriggerScope::System(&self.installation, &self.scope), &fstree)?;
-        for trigger in sys_triggers {
-            trigger.execute()?;
-        }
+        postblit::run(
+            postblit::TriggerScope::System(&self.installation, scope),
+            &fstree,
+            &self.install_db,
+        )?;
 
         Ok(())
     }
@@ -430,13 +1219,267 @@ impl Client {
         Ok(())
     }
 
+    /// Warn about repositories whose monthly download quota would be exceeded
+    /// by caching `packages`.
+    ///
+    /// Returns `true` if any of those repositories require confirmation
+    pub fn warn_exceeded_quotas<T>(&self, packages: &[T]) -> Result<bool, Error>
+    where
+        T: Borrow<Package>,
+    {
+        let mut pending = BTreeMap::<repository::Id, u64>::new();
+
+        for package in packages {
+            let package: &Package = package.borrow();
+
+            // Already cached downloads don't count against the quota
+            if self.is_cached(package) {
+                continue;
+            }
+
+            if let Some(id) = self.repository_for(package) {
+                *pending.entry(id.clone()).or_default() += package.meta.download_size.unwrap_or_default();
+            }
+        }
+
+        let exceeded = self.repositories.exceeded_quotas(&pending)?;
+
+        // Kept off stdout when it's reserved for JSON
+        for quota in &exceeded {
+            let warning = format!(
+                "repository {} would exceed its monthly quota of {} ({} used, {} to download)",
+                quota.id.to_string().bold(),
+                HumanBytes(quota.quota.monthly),
+                HumanBytes(quota.used),
+                HumanBytes(quota.pending),
+            );
+            if output::is_json() {
+                warn!("{warning}");
+            } else {
+                println!("{} {warning}", "Warning:".yellow());
+            }
+        }
+        if !exceeded.is_empty() && !output::is_json() {
+            println!();
+        }
+
+        Ok(exceeded.iter().any(|quota| quota.quota.confirm))
+    }
+
+    /// All held packages, keyed by package name
+    pub fn holds(&self) -> BTreeMap<String, hold::Hold> {
+        self.config
+            .load::<hold::Hold>()
+            .into_iter()
+            .map(|hold| (hold.name.clone(), hold))
+            .collect()
+    }
+
+    /// Hold `name` at its installed version
+    pub fn hold(&self, name: &str, reason: Option<String>) -> Result<(), Error> {
+        let hold = hold::Hold {
+            name: name.to_string(),
+            reason,
+        };
+        self.config.save(name, &hold).map_err(Error::SaveHold)
+    }
+
+    /// Release the hold on `name`, returning `false` if it wasn't held
+    pub fn unhold(&self, name: &str) -> Result<bool, Error> {
+        if !self.holds().contains_key(name) {
+            return Ok(false);
+        }
+        self.config.delete::<hold::Hold>(name).map_err(Error::DeleteHold)?;
+        Ok(true)
+    }
+
+    /// Names of held packages from `installed` which would be
+    /// replaced or removed if `finalized` became the new state
+    pub fn hold_violations<'a>(
+        &self,
+        installed: &[Package],
+        finalized: impl IntoIterator<Item = &'a package::Id>,
+    ) -> Vec<String> {
+        let holds = self.holds();
+        let finalized = finalized.into_iter().collect::<BTreeSet<_>>();
+
+        installed
+            .iter()
+            .filter(|p| holds.contains_key(&p.meta.name.to_string()))
+            .filter(|p| !finalized.contains(&p.id))
+            .map(|p| p.meta.name.to_string())
+            .collect()
+    }
+
+    /// The configured [`Repository`] with the provided id
+    pub fn repository(&self, id: &repository::Id) -> Option<&Repository> {
+        self.repositories.get(id)
+    }
+
+    /// The configured repository serving this package, if any
+    pub fn repository_for(&self, package: &Package) -> Option<&repository::Id> {
+        package
+            .meta
+            .uri
+            .as_deref()
+            .and_then(|uri| self.repositories.serving(uri))
+    }
+
+    /// Revisions of all fetched repository indexes, see [`repository::Manager::index_revision`]
+    pub fn index_revisions(&self) -> Result<BTreeMap<repository::Id, String>, Error> {
+        let mut revisions = BTreeMap::new();
+
+        for (id, _) in self.repositories.list() {
+            if let Some(revision) = self.repositories.index_revision(id)? {
+                revisions.insert(id.clone(), revision);
+            }
+        }
+
+        Ok(revisions)
+    }
+
+    /// All packages owning `path`, either absolute or relative to `/usr`
+    ///
+    /// Layouts are only known once a package has been fetched, so this covers
+    /// installed packages and any available packages present in the cache.
+    /// Repository indexes carry no layouts, so other available packages are
+    /// never found. Installed packages are returned first.
+    pub fn owners(&self, path: &str) -> Result<Vec<Package>, Error> {
+        let ids = self
+            .layout_db
+            .owners(usr_relative(path))?
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<BTreeSet<_>>();
+
+        let mut packages = ids
+            .into_iter()
+            .map(|id| {
+                let known = self.registry.by_id(&id).collect::<Vec<_>>();
+
+                if let Some(package) = known.iter().find(|p| p.flags.installed).or(known.first()) {
+                    return Ok(package.clone());
+                }
+
+                // No longer known to any repository, but still cached
+                let meta = self.install_db.get(&id)?;
+
+                Ok(Package {
+                    id,
+                    meta,
+                    flags: package::Flags::default(),
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        packages.sort_by(|a, b| {
+            b.flags
+                .installed
+                .cmp(&a.flags.installed)
+                .then_with(|| a.meta.name.cmp(&b.meta.name))
+        });
+
+        Ok(packages)
+    }
+
+    /// Returns true if the package download is already in the cache
+    pub fn is_cached(&self, package: &Package) -> bool {
+        package
+            .meta
+            .hash
+            .as_ref()
+            .and_then(|hash| cache::download_path(&self.installation, hash).ok())
+            .is_some_and(|path| path.exists())
+    }
+
+    /// Returns true if the package needn't be fetched, as it's either
+    /// cached or a sideloaded local file
+    fn is_local(&self, package: &Package) -> bool {
+        self.is_cached(package) || self.sideloaded.path(&package.id).is_some()
+    }
+
+    /// A [`package::Delta`] of `package` applying to a previously installed release,
+    /// unless the full package is cached already
+    ///
+    /// Delta stones only carry content missing from the asset store and aren't signed,
+    /// so packages which must be verified against [`Self::trusted_keys`] are fetched in full
+    fn applicable_delta<'a>(&self, package: &'a Package) -> Option<&'a package::Delta> {
+        if self.is_cached(package) || self.trusted_keys(package).is_some() {
+            return None;
+        }
+
+        package
+            .meta
+            .deltas
+            .iter()
+            .find(|delta| self.install_db.get(&package::Id::from(delta.from.clone())).is_ok())
+    }
+
+    /// Keys the stone of `package` must be signed by, if its repository requires it
+    fn trusted_keys(&self, package: &Package) -> Option<Vec<signature::PublicKey>> {
+        // Sideloaded packages are trusted as provided
+        self.repository_for(package)
+            .and_then(|id| self.repository(id))
+            .map(|repo| repo.trusted_keys.clone())
+            .filter(|keys| signature::is_verify() && self.sideloaded.source(&package.id).is_none() && !keys.is_empty())
+    }
+
+    /// Download & unpack `packages` for the transaction to `selections`, see [`Self::cache_packages`]
+    ///
+    /// The transaction is journaled beforehand, so it can be resumed if interrupted
+    /// while fetching. Should fetching fail outright, the journal is dropped again
+    pub fn fetch_transaction<T>(
+        &self,
+        packages: &[T],
+        selections: &[Selection],
+        summary: impl ToString,
+    ) -> Result<(), Error>
+    where
+        T: Borrow<Package>,
+    {
+        if !self.scope.is_ephemeral() {
+            self.ensure_nothing_pending()?;
+            self.write_journal(&journal::Journal {
+                summary: summary.to_string(),
+                previous: self.installation.active_state,
+                state: None,
+                selections: selections.to_vec(),
+            })?;
+        }
+
+        let fetched = runtime::block_on(self.cache_packages(packages));
+        if fetched.is_err() && self.journaled.load(Ordering::Relaxed) {
+            self.clear_journal()?;
+        }
+
+        fetched
+    }
+
     /// Download & unpack the provided packages. Packages already cached will be validated & skipped.
     pub async fn cache_packages<T>(&self, packages: &[T]) -> Result<(), Error>
     where
         T: Borrow<Package>,
     {
+        // Only what's cached or served locally can be installed without network access
+        if request::is_offline() {
+            let missing = packages
+                .iter()
+                .map(Borrow::borrow)
+                .filter(|package| !self.is_local(package) && !is_served_locally(package))
+                .map(|package| package.meta.name.to_string())
+                .collect::<Vec<_>>();
+
+            if !missing.is_empty() {
+                return Err(Error::Offline(missing));
+            }
+        }
+
+        // Abort before fetching anything if it won't fit on disk
+        if let Some(requirement) = space::check(self, packages)? {
+            return Err(Error::InsufficientSpace(requirement));
+        }
+
         // Setup progress bar
-        let multi_progress = MultiProgress::new();
+        let multi_progress = MultiProgress::with_draw_target(tui::draw_target());
 
         // Add bar to track total package counts
         let total_progress = multi_progress.add(
@@ -449,11 +1492,25 @@ impl Client {
         total_progress.tick();
 
         let unpacking_in_progress = cache::UnpackingInProgress::default();
+        let downloaded = Mutex::new(BTreeMap::<repository::Id, u64>::new());
 
-        // Download and unpack each package
-        stream::iter(packages.iter().map(|package| async {
+        // Rather than FIFO, prioritize what can be unpacked soonest
+        let order = cache::fetch_order(packages.iter().map(|package| {
             let package: &Package = package.borrow();
+            let size = match self.applicable_delta(package) {
+                Some(delta) => Some(delta.size),
+                None => package.meta.download_size,
+            };
+            (self.is_local(package), size)
+        }));
+
+        let ordered = order
+            .into_iter()
+            .map(|index| packages[index].borrow())
+            .collect::<Vec<&Package>>();
 
+        // Download and unpack each package
+        stream::iter(ordered.into_iter().map(|package| async {
             // Setup the progress bar and set as downloading
             let progress_bar = multi_progress.insert_before(
                 &total_progress,
@@ -473,12 +1530,45 @@ impl Client {
             );
             progress_bar.enable_steady_tick(Duration::from_millis(150));
 
-            // Download and update progress
-            let download = cache::fetch(&package.meta, &self.installation, |progress| {
-                progress_bar.inc(progress.delta);
-            })
-            .await?;
+            // Download and update progress, preferring a delta
+            // against a previously installed release
+            let on_progress = |progress: cache::Progress| progress_bar.inc(progress.delta);
+            let download = match (self.sideloaded.path(&package.id), self.applicable_delta(package)) {
+                (Some(path), _) => cache::Download::local(package.id.clone(), path, &self.installation),
+                (None, Some(delta)) => {
+                    progress_bar.set_length(delta.size);
+
+                    match cache::fetch_delta(&package.meta, delta, &self.installation, on_progress).await {
+                        Ok(download) => download,
+                        Err(error) => {
+                            warn!(
+                                "delta of {} unusable, fetching full package: {error}",
+                                package.meta.name
+                            );
+
+                            progress_bar.set_length(package.meta.download_size.unwrap_or_default());
+                            progress_bar.set_position(0);
+
+                            cache::fetch(&package.meta, &self.installation, on_progress).await?
+                        }
+                    }
+                }
+                (None, None) => cache::fetch(&package.meta, &self.installation, on_progress).await?,
+            };
             let is_cached = download.was_cached;
+            if is_cached {
+                debug!("using cached download of {}", package.id);
+            }
+
+            // Account network usage to the serving repository
+            if let Some(id) = package
+                .meta
+                .uri
+                .as_deref()
+                .and_then(|uri| self.repositories.serving(uri))
+            {
+                *downloaded.lock().expect("mutex lock").entry(id.clone()).or_default() += download.downloaded;
+            }
 
             // Move rest of blocking code to threadpool
 
@@ -488,10 +1578,20 @@ impl Client {
             let layout_db = self.layout_db.clone();
             let install_db = self.install_db.clone();
             let package = (*package).clone();
+            let sideloaded = self.sideloaded.source(&package.id).map(ToString::to_string);
+            let trusted_keys = self.trusted_keys(&package);
+            let on_cached = self.on_cached.clone();
+            let total = packages.len();
 
             runtime::unblock(move || {
                 let package_name = package.meta.name.to_string();
 
+                if let Some(keys) = &trusted_keys {
+                    let signer = signature::verify(download.path(), keys)
+                        .map_err(|error| Error::Signature(package_name.clone(), error))?;
+                    debug!("{package_name} signed by {signer}");
+                }
+
                 // Set progress to unpacking
                 progress_bar.set_message(format!("{} {}", "Unpacking".yellow(), package_name.clone().bold(),));
                 progress_bar.set_length(1000);
@@ -526,6 +1626,9 @@ impl Client {
 
                 // Consume the package in the metadb
                 install_db.add(package.id.clone(), package.meta.clone())?;
+                if let Some(source) = &sideloaded {
+                    install_db.set_sideloaded(&package.id, source)?;
+                }
 
                 // Remove this progress bar
                 progress_bar.finish();
@@ -545,19 +1648,28 @@ impl Client {
 
                 // Inc total progress by 1
                 total_progress.inc(1);
+                if let Some(on_cached) = &on_cached {
+                    on_cached(&package, total_progress.position() as usize, total);
+                }
 
                 Ok(()) as Result<(), Error>
             })
             .await
         }))
         // Use max network concurrency since we download files here
-        .buffer_unordered(environment::MAX_NETWORK_CONCURRENCY)
+        .buffer_unordered(request::concurrency())
         .try_collect()
         .await?;
 
         // Remove progress
         multi_progress.clear()?;
 
+        for (id, bytes) in downloaded.into_inner().expect("mutex lock") {
+            if bytes > 0 {
+                self.repositories.record_usage(&id, bytes)?;
+            }
+        }
+
         Ok(())
     }
 
@@ -568,22 +1680,75 @@ impl Client {
     pub fn vfs<'a>(
         &self,
         packages: impl IntoIterator<Item = &'a package::Id>,
+    ) -> Result<vfs::Tree<PendingFile>, Error> {
+        self.vfs_excluding(packages, &BTreeSet::new())
+    }
+
+    /// Build a [`vfs::Tree`] for the specified package IDs, leaving out
+    /// the `excluded` `(package, path)` entries
+    pub fn vfs_excluding<'a>(
+        &self,
+        packages: impl IntoIterator<Item = &'a package::Id>,
+        excluded: &BTreeSet<(package::Id, String)>,
+    ) -> Result<vfs::Tree<PendingFile>, Error> {
+        self.build_vfs(packages, excluded, |_, _| false)
+    }
+
+    /// Build a [`vfs::Tree`] like [`Self::vfs_excluding`], failing if any two
+    /// entries at the same path are `conflicting`, see [`TreeBuilder::tree_checked`]
+    fn build_vfs<'a>(
+        &self,
+        packages: impl IntoIterator<Item = &'a package::Id>,
+        excluded: &BTreeSet<(package::Id, String)>,
+        conflicting: impl Fn(&PendingFile, &PendingFile) -> bool,
     ) -> Result<vfs::Tree<PendingFile>, Error> {
         let mut tbuild = TreeBuilder::new();
         let layouts = self.layout_db.query(packages)?;
         for (id, layout) in layouts {
+            if !excluded.is_empty() && excluded.contains(&(id.clone(), layout.entry.target().to_string())) {
+                continue;
+            }
             tbuild.push(PendingFile { id: id.clone(), layout });
         }
         tbuild.bake();
-        let tree = tbuild.tree()?;
+        let tree = tbuild.tree_checked(conflicting)?;
         Ok(tree)
     }
 
+    /// Resolve the configured [`exclusion::Rules`] against the layouts of
+    /// `packages`, returning all `(package, path)` entries to leave out
+    pub fn exclusions<'a>(
+        &self,
+        packages: impl IntoIterator<Item = &'a package::Id>,
+    ) -> Result<BTreeSet<(package::Id, String)>, Error> {
+        let rules = exclusion::Rules::load(&self.config);
+
+        if rules.is_empty() {
+            return Ok(BTreeSet::new());
+        }
+
+        let mut names = BTreeMap::new();
+        for id in packages {
+            let name = self.install_db.get(id)?.name.to_string();
+            if rules.applies_to(&name) {
+                names.insert(id.clone(), name);
+            }
+        }
+
+        Ok(self
+            .layout_db
+            .query(names.keys())?
+            .into_iter()
+            .filter(|(id, layout)| rules.excludes(&names[id], layout))
+            .map(|(id, layout)| (id, layout.entry.target().to_string()))
+            .collect())
+    }
+
     /// Blit the packages to a filesystem root
     ///
     /// This functionality is core to all moss filesystem transactions, forming the entire
-    /// staging logic. For all the [`crate::package::Id`] present in the staging state,
-    /// query their stored [`stone::payload::Layout`] and cache into a [`vfs::Tree`].
+    /// staging logic. The [`vfs::Tree`] of the staging state is built beforehand from the
+    /// stored [`stone::payload::Layout`]s of its packages, see [`Self::prepare_transaction`].
     ///
     /// The new `/usr` filesystem is written in optimal order to a staging tree by making
     /// use of the "at" family of functions (`mkdirat`, `linkat`, etc) with relative directory
@@ -591,11 +1756,8 @@ impl Client {
     ///
     /// This provides a very quick means to generate a hardlinked "snapshot" on-demand,
     /// which can then be activated via [`Self::promote_staging`]
-    fn blit_root<'a>(
-        &self,
-        packages: impl IntoIterator<Item = &'a package::Id>,
-    ) -> Result<vfs::tree::Tree<PendingFile>, Error> {
-        let progress = ProgressBar::new(1).with_style(
+    fn blit_root(&self, tree: vfs::tree::Tree<PendingFile>) -> Result<vfs::tree::Tree<PendingFile>, Error> {
+        let progress = ProgressBar::with_draw_target(Some(1), tui::draw_target()).with_style(
             ProgressStyle::with_template("\n|{bar:20.red/blue}| {pos}/{len} {msg}")
                 .unwrap()
                 .progress_chars("■≡=- "),
@@ -604,34 +1766,43 @@ impl Client {
         progress.enable_steady_tick(Duration::from_millis(150));
         progress.tick();
 
-        let tree = self.vfs(packages)?;
+        let blit_target = match &self.scope {
+            Scope::Stateful => self.installation.staging_dir(),
+            Scope::Ephemeral { blit_root } => blit_root.to_owned(),
+        };
+
+        self.blit_to(tree, &blit_target, &progress)
+    }
 
+    /// Blit `tree` to `blit_target`, reporting to `progress`. See [`Self::blit_root`]
+    fn blit_to(
+        &self,
+        tree: vfs::tree::Tree<PendingFile>,
+        blit_target: &Path,
+        progress: &ProgressBar,
+    ) -> Result<vfs::tree::Tree<PendingFile>, Error> {
         progress.set_length(tree.len());
         progress.set_position(0_u64);
 
         let cache_dir = self.installation.assets_path("v2");
         let cache_fd = fcntl::open(&cache_dir, OFlag::O_DIRECTORY | OFlag::O_RDONLY, Mode::empty())?;
 
-        let blit_target = match &self.scope {
-            Scope::Stateful => self.installation.staging_dir(),
-            Scope::Ephemeral { blit_root } => blit_root.to_owned(),
-        };
-
         // undirt.
-        fs::remove_dir_all(&blit_target)?;
+        fs::remove_dir_all(blit_target)?;
 
         if let Some(root) = tree.structured() {
-            let _ = mkdir(&blit_target, Mode::from_bits_truncate(0o755));
-            let root_dir = fcntl::open(&blit_target, OFlag::O_DIRECTORY | OFlag::O_RDONLY, Mode::empty())?;
+            let _ = mkdir(blit_target, Mode::from_bits_truncate(0o755));
+            let root_dir = fcntl::open(blit_target, OFlag::O_DIRECTORY | OFlag::O_RDONLY, Mode::empty())?;
 
             if let Element::Directory(_, _, children) = root {
                 for child in children {
-                    self.blit_element(root_dir, cache_fd, child, &progress)?;
+                    self.blit_element(root_dir, cache_fd, child, progress)?;
                 }
             }
 
             close(root_dir)?;
         }
+        close(cache_fd)?;
 
         Ok(tree)
     }
@@ -726,17 +1897,28 @@ impl Client {
     }
 }
 
+/// `path` relative to `/usr`, given either absolute or relative to it
+fn usr_relative(path: &str) -> &str {
+    // Everything lives in `/usr`, the root level `/bin`, `/lib`, etc
+    // are merged into it
+    path.strip_prefix("/usr/")
+        .or_else(|| path.strip_prefix('/'))
+        .unwrap_or(path)
+        .trim_end_matches('/')
+}
+
+/// Links from the root into `/usr`, as `(source, target)`
+pub const ROOT_LINKS: [(&str, &str); 5] = [
+    ("usr/sbin", "sbin"),
+    ("usr/bin", "bin"),
+    ("usr/lib", "lib"),
+    ("usr/lib", "lib64"),
+    ("usr/lib32", "lib32"),
+];
+
 /// Add root symlinks & os-release file
 fn create_root_links(root: &Path) -> Result<(), io::Error> {
-    let links = vec![
-        ("usr/sbin", "sbin"),
-        ("usr/bin", "bin"),
-        ("usr/lib", "lib"),
-        ("usr/lib", "lib64"),
-        ("usr/lib32", "lib32"),
-    ];
-
-    'linker: for (source, target) in links.into_iter() {
+    'linker: for (source, target) in ROOT_LINKS {
         let final_target = root.join(target);
         let staging_target = root.join(format!("{target}.next"));
 
@@ -754,6 +1936,16 @@ fn create_root_links(root: &Path) -> Result<(), io::Error> {
     Ok(())
 }
 
+/// Whether `package` is fetched from a local `file://` url
+fn is_served_locally(package: &Package) -> bool {
+    package
+        .meta
+        .uri
+        .as_deref()
+        .and_then(|uri| Url::parse(uri).ok())
+        .is_some_and(|url| request::is_local(&url))
+}
+
 fn record_state_id(root: &Path, state: state::Id) -> Result<(), Error> {
     let usr = root.join("usr");
     fs::create_dir_all(&usr)?;
@@ -888,11 +2080,14 @@ impl fmt::Display for PendingFile {
 /// * `repositories` - Configured repositories to laoad [`crate::registry::Plugin::Repository`]
 /// * `installdb`    - Installation database opened in the installation tree
 /// * `statedb`      - State database opened in the installation tree
+/// * `alternatives` - Selection policy for providers with alternatives
 fn build_registry(
     installation: &Installation,
     repositories: &repository::Manager,
     installdb: &db::meta::Database,
     statedb: &db::state::Database,
+    sideloaded: &plugin::Cobble,
+    alternatives: Alternatives,
 ) -> Result<Registry, Error> {
     let state = match installation.active_state {
         Some(id) => Some(statedb.get(id)?),
@@ -900,8 +2095,9 @@ fn build_registry(
     };
 
     let mut registry = Registry::default();
+    registry.set_alternatives(alternatives);
 
-    registry.add_plugin(Plugin::Cobble(plugin::Cobble::default()));
+    registry.add_plugin(Plugin::Cobble(sideloaded.clone()));
     registry.add_plugin(Plugin::Active(plugin::Active::new(state, installdb.clone())));
 
     for repo in repositories.active() {
@@ -938,10 +2134,22 @@ pub enum Error {
     Prune(#[from] prune::Error),
     #[error("io")]
     Io(#[from] io::Error),
+    #[error("save hold")]
+    SaveHold(#[source] config::SaveError),
+    #[error("delete hold")]
+    DeleteHold(#[source] io::Error),
     #[error("filesystem")]
     Filesystem(#[from] vfs::tree::Error),
     #[error("blit")]
     Blit(#[from] Errno),
+    #[error("check")]
+    Check(#[from] check::Error),
+    #[error("file conflict")]
+    FileConflict(#[from] file_conflict::Error),
+    #[error("hook")]
+    Hook(#[from] hooks::Error),
+    #[error("snapshot")]
+    Snapshot(#[from] snapshot::Error),
     #[error("postblit")]
     PostBlit(#[from] postblit::Error),
     #[error("boot")]
@@ -952,4 +2160,34 @@ pub enum Error {
     /// The operation was explicitly cancelled at the user's request
     #[error("cancelled")]
     Cancelled,
+    #[error("offline, packages missing from the cache: {}", .0.join(", "))]
+    Offline(Vec<String>),
+    #[error("not enough disk space, {0} (use --ignore-disk-space to proceed anyway)")]
+    InsufficientSpace(space::Requirement),
+    #[error("sideload")]
+    Sideload(#[from] plugin::cobble::Error),
+    #[error("signature of {0} (use --no-verify to install anyway)")]
+    Signature(String, #[source] signature::Error),
+    #[error("the transaction to state #{0} was interrupted, run `moss transaction resume` to complete it")]
+    PendingTransaction(state::Id),
+    #[error(
+        "the {0:?} transaction was interrupted, run `moss transaction resume` to complete it \
+         or `moss transaction abort` to discard it"
+    )]
+    InterruptedTransaction(String),
+    #[error("the transaction to state #{0} is already staged, run `moss transaction resume` to complete it")]
+    AlreadyStaged(state::Id),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn owner_paths() {
+        assert_eq!(usr_relative("/usr/bin/nano"), "bin/nano");
+        assert_eq!(usr_relative("/bin/nano"), "bin/nano");
+        assert_eq!(usr_relative("bin/nano"), "bin/nano");
+        assert_eq!(usr_relative("/usr/share/doc/"), "share/doc");
+    }
 }
@@ -0,0 +1,133 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Apply one resolved package set to several ephemeral roots
+//!
+//! Packages are fetched & unpacked into the [`Client`] cache a single time,
+//! after which every root is blitted concurrently from the shared asset store.
+//! Triggers run in containers pivoting into the shared isolation directory,
+//! so they're executed one root at a time.
+
+use std::{fs, io, path::PathBuf, sync::Mutex, thread, time::Duration};
+
+use itertools::Itertools;
+use thiserror::Error;
+use tui::{MultiProgress, ProgressBar, ProgressStyle};
+
+use super::{Client, Scope};
+use crate::{runtime, Package};
+
+/// Drives a single transaction across multiple ephemeral roots, e.g.
+/// when building a number of identical container roots
+pub struct MultiRoot<'a> {
+    client: &'a Client,
+    roots: Vec<PathBuf>,
+}
+
+impl<'a> MultiRoot<'a> {
+    /// Target each of `roots`, creating them as needed
+    ///
+    /// The installation root of `client` is rejected as it must only
+    /// be modified via stateful transactions
+    pub fn new(client: &'a Client, roots: impl IntoIterator<Item = impl Into<PathBuf>>) -> Result<Self, Error> {
+        let installation_root = client.installation.root.canonicalize()?;
+
+        let mut unique = vec![];
+
+        for root in roots {
+            let root = root.into();
+            fs::create_dir_all(&root)?;
+            let root = root.canonicalize()?;
+
+            if root == installation_root {
+                return Err(Error::InstallationRoot(root));
+            }
+            if !unique.contains(&root) {
+                unique.push(root);
+            }
+        }
+
+        Ok(Self { client, roots: unique })
+    }
+
+    /// The (canonicalized) roots being targeted
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// Fetch `packages` once, then blit them to all roots concurrently
+    pub fn apply(&self, packages: &[Package]) -> Result<(), Error> {
+        runtime::block_on(self.client.cache_packages(packages))?;
+
+        let ids = packages.iter().map(|p| &p.id).collect::<Vec<_>>();
+        let excluded = self.client.exclusions(ids.iter().copied())?;
+
+        let multi_progress = MultiProgress::with_draw_target(tui::draw_target());
+        let triggers = Mutex::new(());
+
+        thread::scope(|scope| {
+            let handles = self
+                .roots
+                .iter()
+                .map(|root| {
+                    let progress = multi_progress.add(
+                        ProgressBar::new(1)
+                            .with_style(
+                                ProgressStyle::with_template("|{bar:20.red/blue}| {pos}/{len} {msg}")
+                                    .unwrap()
+                                    .progress_chars("■≡=- "),
+                            )
+                            .with_message(format!("Blitting {}", root.display())),
+                    );
+                    progress.enable_steady_tick(Duration::from_millis(150));
+
+                    let (ids, excluded, triggers) = (&ids, &excluded, &triggers);
+
+                    scope.spawn(move || {
+                        let tree = self.client.vfs_excluding(ids.iter().copied(), excluded)?;
+                        let fstree = self.client.blit_to(tree, root, &progress)?;
+                        progress.finish();
+
+                        let _guard = triggers.lock().expect("mutex lock");
+                        self.client.apply_ephemeral_blit_scoped(
+                            fstree,
+                            root,
+                            &Scope::Ephemeral {
+                                blit_root: root.clone(),
+                            },
+                        )?;
+
+                        Ok(())
+                    })
+                })
+                .collect_vec();
+
+            handles.into_iter().zip(&self.roots).try_for_each(|(handle, root)| {
+                handle
+                    .join()
+                    .expect("blit thread panicked")
+                    .map_err(|error| Error::Root(root.clone(), error))
+            })
+        })?;
+
+        multi_progress.clear()?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("refusing to target the installation root {0:?}")]
+    InstallationRoot(PathBuf),
+
+    #[error("blit {0:?}")]
+    Root(PathBuf, #[source] super::Error),
+
+    #[error("client")]
+    Client(#[from] super::Error),
+
+    #[error("io")]
+    Io(#[from] io::Error),
+}
@@ -0,0 +1,323 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Computed transaction plans, used to preview an operation
+//! (i.e. `--dry-run`) without touching the root
+
+use std::borrow::Borrow;
+
+use serde::Serialize;
+use thiserror::Error;
+use tui::{HumanBytes, Styled};
+
+use crate::{client::Client, messages, output, Package};
+
+/// How a [`Plan`] is presented to the user
+pub use crate::output::Format;
+
+/// The packages a transaction would install & remove
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Plan {
+    pub install: Vec<Entry>,
+    pub remove: Vec<Entry>,
+}
+
+/// A single package within a [`Plan`]
+#[derive(Debug, Clone, Serialize)]
+pub struct Entry {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    pub release: u64,
+    /// Repository the package is fetched from, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repository: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_size: Option<u64>,
+    /// Size of the installed files, i.e. the unpacked content of the
+    /// package, if published in its metadata
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub installed_size: Option<u64>,
+    /// Package is already in the download cache and won't be fetched
+    pub cached: bool,
+}
+
+impl Plan {
+    /// Compute the plan for installing `install` and removing `remove`
+    pub fn new<T, U>(client: &Client, install: &[T], remove: &[U]) -> Self
+    where
+        T: Borrow<Package>,
+        U: Borrow<Package>,
+    {
+        let entry = |package: &Package| Entry {
+            id: package.id.to_string(),
+            name: package.meta.name.to_string(),
+            version: package.meta.version_identifier.clone(),
+            release: package.meta.source_release,
+            repository: client.repository_for(package).map(ToString::to_string),
+            download_size: package.meta.download_size,
+            installed_size: package.meta.installed_size,
+            cached: client.is_cached(package),
+        };
+
+        Self {
+            install: install.iter().map(|p| entry(p.borrow())).collect(),
+            remove: remove.iter().map(|p| entry(p.borrow())).collect(),
+        }
+    }
+
+    /// Returns true if the plan doesn't change anything
+    pub fn is_empty(&self) -> bool {
+        self.install.is_empty() && self.remove.is_empty()
+    }
+
+    /// Total number of bytes which must be fetched to apply this plan
+    pub fn download_size(&self) -> u64 {
+        self.install
+            .iter()
+            .filter(|entry| !entry.cached)
+            .filter_map(|entry| entry.download_size)
+            .sum()
+    }
+
+    /// Total size of the files to install, if known for every package
+    pub fn installed_size(&self) -> Option<u64> {
+        self.install.iter().map(|entry| entry.installed_size).sum()
+    }
+
+    /// Total size of the files of all removed packages, if known for every package
+    pub fn freed_size(&self) -> Option<u64> {
+        self.remove.iter().map(|entry| entry.installed_size).sum()
+    }
+
+    /// Net change of the installed size once applied, if known for every package
+    pub fn size_change(&self) -> Option<i64> {
+        Some(self.installed_size()? as i64 - self.freed_size()? as i64)
+    }
+
+    /// Print the download size & installed size change to stdout,
+    /// i.e. "Need to download 120 MiB, +450 MiB installed size change"
+    pub fn print_sizes(&self) {
+        if let Some(sizes) = self.sizes() {
+            println!("{sizes}");
+        }
+    }
+
+    /// The line printed by [`Self::print_sizes`], unless the plan is empty
+    fn sizes(&self) -> Option<String> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let download = match self.download_size() {
+            0 => messages::get("size-nothing-to-download"),
+            size => messages::format(
+                "size-need-to-download",
+                &[("size", HumanBytes(size).to_string().bold().to_string().into())],
+            ),
+        };
+        let change = match self.size_change() {
+            Some(change) => messages::format("size-change", &[("change", signed(change).bold().to_string().into())]),
+            None => messages::get("size-change-unknown"),
+        };
+
+        Some(format!("{download}, {change}"))
+    }
+
+    /// Print the plan to stdout in the requested [`Format`]
+    pub fn print(&self, format: Format) -> Result<(), Error> {
+        match format {
+            Format::Text => self.print_text(),
+            Format::Json => output::print_json("plan", self)?,
+        }
+
+        Ok(())
+    }
+
+    fn print_text(&self) {
+        print!("{}", self.text());
+    }
+
+    /// The plan as printed for [`Format::Text`]
+    fn text(&self) -> String {
+        if self.is_empty() {
+            return format!("{}\n", messages::get("plan-nothing-to-do"));
+        }
+
+        let width = self
+            .install
+            .iter()
+            .chain(&self.remove)
+            .map(|entry| entry.name.len())
+            .max()
+            .unwrap_or_default();
+
+        let mut text = String::new();
+
+        if !self.install.is_empty() {
+            text.push_str(&format!("{}\n\n", messages::get("plan-would-install")));
+            for entry in &self.install {
+                let source = match (&entry.repository, entry.cached) {
+                    (_, true) => messages::get("plan-source-cached"),
+                    (Some(repo), false) => repo.clone(),
+                    (None, false) => messages::get("plan-source-local"),
+                };
+
+                text.push_str(&format!(
+                    " {} {}-{} {} {}\n",
+                    format!("{:width$}", entry.name).bold(),
+                    entry.version,
+                    entry.release,
+                    format!("({source})").dim(),
+                    HumanBytes(entry.download_size.unwrap_or_default()),
+                ));
+            }
+            text.push('\n');
+        }
+
+        if !self.remove.is_empty() {
+            text.push_str(&format!("{}\n\n", messages::get("plan-would-remove")));
+            for entry in &self.remove {
+                text.push_str(&format!(
+                    " {} {}-{}\n",
+                    format!("{:width$}", entry.name).bold(),
+                    entry.version,
+                    entry.release
+                ));
+            }
+            text.push('\n');
+        }
+
+        if let Some(sizes) = self.sizes() {
+            text.push_str(&format!("{sizes}\n"));
+        }
+
+        text
+    }
+}
+
+/// Render a size difference with its sign, i.e. `+450 MiB`
+fn signed(size: i64) -> String {
+    let sign = if size < 0 { '-' } else { '+' };
+    format!("{sign}{}", HumanBytes(size.unsigned_abs()))
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("serialize plan")]
+    Json(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(name: &str, download_size: u64, installed_size: Option<u64>) -> Entry {
+        Entry {
+            id: name.to_string(),
+            name: name.to_string(),
+            version: "1.0".to_string(),
+            release: 1,
+            repository: None,
+            download_size: Some(download_size),
+            installed_size,
+            cached: false,
+        }
+    }
+
+    #[test]
+    fn sizes() {
+        let mut plan = Plan {
+            install: vec![entry("a", 100, Some(400)), entry("b", 50, Some(200))],
+            remove: vec![entry("c", 10, Some(1000))],
+        };
+
+        assert_eq!(plan.download_size(), 150);
+        assert_eq!(plan.installed_size(), Some(600));
+        assert_eq!(plan.size_change(), Some(-400));
+        assert_eq!(signed(-400), "-400 B");
+
+        plan.remove.push(entry("e", 10, None));
+        assert_eq!(plan.freed_size(), None);
+        assert_eq!(plan.size_change(), None);
+
+        plan.remove.pop();
+        plan.install.push(entry("d", 10, None));
+        assert_eq!(plan.size_change(), None);
+    }
+
+    #[test]
+    fn text_output() {
+        let plan = Plan {
+            install: vec![
+                entry("a", 100, Some(400)),
+                Entry {
+                    repository: Some("volatile".to_string()),
+                    cached: true,
+                    ..entry("b", 50, Some(200))
+                },
+                Entry {
+                    repository: Some("volatile".to_string()),
+                    ..entry("c", 20, Some(100))
+                },
+            ],
+            remove: vec![entry("long-name", 10, Some(1000))],
+        };
+
+        let sizes = format!(
+            "{}, {}",
+            messages::format("size-need-to-download", &[("size", "120 B".into())]),
+            messages::format("size-change", &[("change", "-300 B".into())])
+        );
+        assert_eq!(plan.sizes(), Some(sizes.clone()));
+
+        let text = plan.text();
+        assert_eq!(
+            text.lines().collect::<Vec<_>>(),
+            vec![
+                messages::get("plan-would-install"),
+                String::new(),
+                format!(" {:9} 1.0-1 ({}) 100 B", "a", messages::get("plan-source-local")),
+                format!(" {:9} 1.0-1 ({}) 50 B", "b", messages::get("plan-source-cached")),
+                format!(" {:9} 1.0-1 (volatile) 20 B", "c"),
+                String::new(),
+                messages::get("plan-would-remove"),
+                String::new(),
+                " long-name 1.0-1".to_string(),
+                String::new(),
+                sizes,
+            ]
+        );
+
+        assert_eq!(Plan::default().sizes(), None);
+        assert_eq!(
+            Plan::default().text(),
+            format!("{}\n", messages::get("plan-nothing-to-do"))
+        );
+    }
+
+    #[test]
+    fn json_output() {
+        let plan = Plan {
+            install: vec![entry("a", 100, None)],
+            remove: vec![],
+        };
+
+        let json = serde_json::to_value(&plan).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "install": [{
+                    "id": "a",
+                    "name": "a",
+                    "version": "1.0",
+                    "release": 1,
+                    "download_size": 100,
+                    "cached": false,
+                }],
+                "remove": [],
+            })
+        );
+    }
+}
@@ -6,22 +6,41 @@
 //! Note that we support transaction scope and system scope triggers, invoked
 //! before `/usr` is activated and after, respectively.
 //!
-//! Note that currently we only load from `/usr/share/moss/triggers/{tx,sys.d}/*.yaml`
-//! and do not yet support local triggers
+//! Triggers are loaded from `/usr/share/moss/triggers/{tx,sys.d}/*.yaml`, along with
+//! those declared within the metadata of each package in the new state. Local triggers
+//! aren't supported yet. Triggers declared by packages are always sandboxed, even
+//! system triggers against the live root.
+//!
+//! Triggers are matched against every path of the new state, so each handler
+//! runs once per transaction no matter how many packages hit it, ordered by
+//! the `before` & `after` relations between triggers.
 use std::{
-    path::{Path, PathBuf},
+    collections::BTreeSet,
+    fs, io,
+    path::{Component, Path, PathBuf},
     process,
+    sync::atomic::{AtomicBool, Ordering},
 };
 
-use crate::Installation;
+use crate::{db, package, Installation};
 use container::Container;
 use itertools::Itertools;
 use serde::Deserialize;
 use thiserror::Error;
+use tracing::{debug, warn};
 use triggers::format::{CompiledHandler, Handler, Trigger};
 
 use super::PendingFile;
 
+/// Whether triggers are skipped, see [`set_skipped`]
+static SKIPPED: AtomicBool = AtomicBool::new(false);
+
+/// Skip running triggers, i.e. to recover a root where a trigger keeps failing.
+/// The triggers that would have run are logged so they can be run by hand.
+pub fn set_skipped(skipped: bool) {
+    SKIPPED.store(skipped, Ordering::Relaxed);
+}
+
 /// Transaction trigger wrapper
 /// These are loaded from `/usr/share/moss/triggers/tx.d/*.yaml`
 #[derive(Deserialize, Debug)]
@@ -103,17 +122,21 @@ impl<'a> TriggerScope<'a> {
 pub(super) struct TriggerRunner<'a> {
     scope: TriggerScope<'a>,
     trigger: CompiledHandler,
+    /// Declared by a package rather than shipped within `/usr/share/moss/triggers`
+    from_package: bool,
 }
 
 /// Load all triggers matching the given scope and staging filesystem
 ///
 /// # Arguments
 ///
-/// * `scope`  - Trigger execution scope
-/// * `fstree` - Virtual filesystem tree populated with records of the staging filesystem
+/// * `scope`      - Trigger execution scope
+/// * `fstree`     - Virtual filesystem tree populated with records of the staging filesystem
+/// * `install_db` - Metadata of the packages within `fstree`, which may declare their own triggers
 pub(super) fn triggers<'a>(
     scope: TriggerScope<'a>,
     fstree: &vfs::tree::Tree<PendingFile>,
+    install_db: &db::meta::Database,
 ) -> Result<Vec<TriggerRunner<'a>>, Error> {
     let trigger_root = Path::new("usr").join("share").join("moss").join("triggers");
 
@@ -130,6 +153,13 @@ pub(super) fn triggers<'a>(
             .map(|t| t.0)
             .collect_vec(),
     };
+    let package_triggers = package_triggers(scope, fstree, install_db)?;
+    // A package may declare a trigger of the same name, which then replaces the shipped one
+    let package_names = package_triggers
+        .iter()
+        .map(|trigger| trigger.name.clone())
+        .collect::<BTreeSet<_>>();
+    let triggers = triggers.into_iter().chain(package_triggers).collect_vec();
 
     // Load trigger collection, process all the paths, convert to scoped TriggerRunner vec
     let mut collection = triggers::Collection::new(triggers.iter())?;
@@ -137,19 +167,94 @@ pub(super) fn triggers<'a>(
     let computed_commands = collection
         .bake()?
         .into_iter()
-        .map(|trigger| TriggerRunner { scope, trigger })
+        .map(|(name, trigger)| TriggerRunner {
+            scope,
+            trigger,
+            from_package: package_names.contains(&name),
+        })
         .collect_vec();
     Ok(computed_commands)
 }
 
+/// Triggers of `scope` declared by the packages within `fstree`
+///
+/// Packages are built by anyone, so an invalid declaration only skips
+/// that trigger rather than failing the whole transaction
+fn package_triggers(
+    scope: TriggerScope<'_>,
+    fstree: &vfs::tree::Tree<PendingFile>,
+    install_db: &db::meta::Database,
+) -> Result<Vec<Trigger>, Error> {
+    let wanted = match scope {
+        TriggerScope::Transaction(..) => package::TriggerScope::Transaction,
+        TriggerScope::System(..) => package::TriggerScope::System,
+    };
+
+    // Directories created implicitly by the tree don't belong to a package
+    let packages = fstree
+        .iter()
+        .map(|file| file.id)
+        .filter(|id| !<package::Id as AsRef<str>>::as_ref(id).is_empty())
+        .collect::<BTreeSet<_>>();
+
+    let mut triggers = vec![];
+
+    for id in packages {
+        let meta = install_db.get(&id)?;
+
+        for declared in meta.triggers.iter().filter(|trigger| trigger.scope == wanted) {
+            let trigger = match serde_yaml::from_str::<Trigger>(&declared.definition) {
+                Ok(trigger) => trigger,
+                Err(error) => {
+                    warn!("ignoring invalid trigger of {}: {error}", meta.name);
+                    continue;
+                }
+            };
+
+            if let Err(error) = triggers::Collection::new([&trigger]) {
+                warn!("ignoring invalid trigger of {}: {error}", meta.name);
+                continue;
+            }
+
+            triggers.push(trigger);
+        }
+    }
+
+    Ok(triggers)
+}
+
+/// Load & execute all triggers of `scope` in dependency order, unless they're [skipped](set_skipped)
+pub(super) fn run(
+    scope: TriggerScope<'_>,
+    fstree: &vfs::tree::Tree<PendingFile>,
+    install_db: &db::meta::Database,
+) -> Result<(), Error> {
+    let triggers = triggers(scope, fstree, install_db)?;
+
+    if SKIPPED.load(Ordering::Relaxed) {
+        for trigger in &triggers {
+            warn!("skipping trigger: {}", trigger.trigger.handler());
+        }
+        return Ok(());
+    }
+
+    for trigger in triggers {
+        debug!("running trigger: {}", trigger.trigger.handler());
+        trigger.execute()?;
+    }
+
+    Ok(())
+}
+
 impl<'a> TriggerRunner<'a> {
     /// Execute a trigger, taking care to account for the transaction scope and client scope
     ///
     /// All transaction triggers are run via sandboxing ([`container::Container`]) to limit their
     /// system view, and limit write access.
-    /// System triggers will execute without any sandboxing when moss is used directly against the
-    /// live root filesystem, and will force sandboxing when using a non-`/` root (such as using the
-    /// `-D argument with `moss install`)
+    /// System triggers shipped within `/usr/share/moss/triggers` will execute without any sandboxing
+    /// when moss is used directly against the live root filesystem, and will force sandboxing when
+    /// using a non-`/` root (such as using the `-D` argument with `moss install`). Those declared by
+    /// packages are always sandboxed, with `/etc` & `/usr` of the target root bound read-write
     pub fn execute(&self) -> Result<(), Error> {
         match self.scope {
             TriggerScope::Transaction(install, _) => {
@@ -161,12 +266,13 @@ impl<'a> TriggerRunner<'a> {
                     .bind_rw(self.scope.guest_path("usr"), "/usr")
                     .work_dir("/");
 
-                Ok(isolation.run(|| execute_trigger_directly(&self.trigger))?)
+                Ok(isolation.run(|| execute_trigger_directly(&self.trigger, Path::new("/usr")))?)
             }
-            TriggerScope::System(install, _) => {
+            TriggerScope::System(install, scope) => {
                 // OK, if the root == `/` then we can run directly, otherwise we need to containerise with RW.
-                if install.root.to_string_lossy() == "/" {
-                    Ok(execute_trigger_directly(&self.trigger)?)
+                // Ephemeral roots never live at `/`, even if the client installation does
+                if install.root.to_string_lossy() == "/" && !scope.is_ephemeral() && !self.from_package {
+                    Ok(execute_trigger_directly(&self.trigger, &self.scope.guest_path("usr"))?)
                 } else {
                     let isolation = Container::new(install.isolation_dir())
                         .networking(false)
@@ -175,35 +281,84 @@ impl<'a> TriggerRunner<'a> {
                         .bind_rw(self.scope.guest_path("usr"), "/usr")
                         .work_dir("/");
 
-                    Ok(isolation.run(|| execute_trigger_directly(&self.trigger))?)
+                    Ok(isolation.run(|| execute_trigger_directly(&self.trigger, Path::new("/usr")))?)
                 }
             }
         }
     }
 }
 
-/// Internal executor for triggers.
-fn execute_trigger_directly(trigger: &CompiledHandler) -> Result<(), Error> {
+/// Internal executor for triggers, where `usr` is the `/usr` tree they apply to
+fn execute_trigger_directly(trigger: &CompiledHandler, usr: &Path) -> Result<(), Error> {
     match trigger.handler() {
         Handler::Run { run, args } => {
             let cmd = process::Command::new(run).args(args).current_dir("/").output()?;
 
             if let Some(code) = cmd.status.code() {
                 if code != 0 {
-                    eprintln!("Trigger exited with non-zero status code: {run} {args:?}");
-                    eprintln!("   Stdout: {}", String::from_utf8(cmd.stdout).unwrap());
-                    eprintln!("   Stderr: {}", String::from_utf8(cmd.stderr).unwrap());
+                    warn!(
+                        "trigger exited with status code {code}: {run} {args:?}\n   Stdout: {}\n   Stderr: {}",
+                        String::from_utf8_lossy(&cmd.stdout),
+                        String::from_utf8_lossy(&cmd.stderr),
+                    );
                 }
             } else {
-                eprintln!("Failed to execute trigger: {run} {args:?}");
+                warn!("trigger terminated by a signal: {run} {args:?}");
+            }
+        }
+        Handler::Delete { delete } => {
+            for path in delete {
+                // Packages declare triggers too, so deletions may never leave `/usr`
+                let target = match confine(path, usr) {
+                    Ok(target) => target,
+                    Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+                    Err(error) => {
+                        warn!("trigger refused to delete {path}: {error}");
+                        continue;
+                    }
+                };
+
+                let result = match fs::symlink_metadata(&target) {
+                    Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&target),
+                    Ok(_) => fs::remove_file(&target),
+                    Err(error) => Err(error),
+                };
+
+                match result {
+                    Ok(()) => {}
+                    Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+                    Err(error) => warn!("trigger failed to delete {path}: {error}"),
+                }
             }
         }
-        Handler::Delete { .. } => todo!(),
     }
 
     Ok(())
 }
 
+/// Resolve the absolute `path` of a trigger against `usr`, refusing anything outside of
+/// `/usr` whether it's named directly, reached via `..` or through a symlinked parent
+fn confine(path: &str, usr: &Path) -> io::Result<PathBuf> {
+    let outside = || io::Error::new(io::ErrorKind::PermissionDenied, "outside of /usr");
+
+    let relative = Path::new(path).strip_prefix("/usr").map_err(|_| outside())?;
+    if relative.as_os_str().is_empty()
+        || !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(outside());
+    }
+
+    let target = usr.join(relative);
+    let parent = target.parent().ok_or_else(outside)?.canonicalize()?;
+    if !parent.starts_with(usr.canonicalize()?) {
+        return Err(outside());
+    }
+
+    Ok(target)
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("container")]
@@ -212,6 +367,44 @@ pub enum Error {
     #[error("triggers")]
     Triggers(#[from] triggers::Error),
 
+    #[error("db")]
+    DB(#[from] db::meta::Error),
+
     #[error("io")]
     IO(#[from] std::io::Error),
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn confined_deletes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let usr = root.join("usr");
+        fs::create_dir_all(usr.join("lib/cache")).unwrap();
+        fs::create_dir_all(root.join("etc")).unwrap();
+        std::os::unix::fs::symlink("../etc", usr.join("escape")).unwrap();
+
+        assert_eq!(confine("/usr/lib/cache", &usr).unwrap(), usr.join("lib/cache"));
+
+        for path in [
+            "/etc/passwd",
+            "/usr",
+            "/usr/../etc",
+            "/usr/lib/../../etc",
+            "usr/lib",
+            "/usr/escape/passwd",
+        ] {
+            let error = confine(path, &usr).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::PermissionDenied, "{path}");
+        }
+
+        // Nothing to delete beneath a missing directory
+        assert_eq!(
+            confine("/usr/missing/file", &usr).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+}
@@ -7,6 +7,9 @@
 //! Quite simply this is a strategy based garbage collector for unused/unwanted
 //! system states (i.e. historical snapshots) that cleans up database entries
 //! and assets on disk by way of refcounting.
+//!
+//! States are only pruned on request, unless the global `keep_states`
+//! [setting](crate::settings::Settings) prunes them after each transaction.
 
 use std::collections::{BTreeMap, BTreeSet};
 use std::{
@@ -16,13 +19,14 @@ use std::{
 
 use itertools::Itertools;
 use thiserror::Error;
+use tracing::warn;
 
-use tui::{
-    dialoguer::{theme::ColorfulTheme, Confirm},
-    pretty::autoprint_columns,
-};
+use tui::pretty::autoprint_columns;
 
-use crate::{client::cache, db, environment, package, state, Installation, State};
+use crate::{
+    client::{cache, snapshot},
+    db, environment, package, prompt, state, Installation, State,
+};
 
 /// The prune strategy for removing old states
 #[derive(Debug, Clone, Copy)]
@@ -137,29 +141,119 @@ pub fn prune(
         .filter_map(|(pkg, count)| (count == 0).then_some(pkg))
         .collect::<Vec<_>>();
 
+    // Filesystem snapshots are removed along with their states
+    let snapshots = removals
+        .iter()
+        .map(|state| state_db.snapshots(state.id))
+        .flatten_ok()
+        .collect::<Result<Vec<_>, _>>()?;
+
     // Print out the states to be removed to the user
     println!("The following state(s) will be removed:");
     println!();
     autoprint_columns(&removals.iter().map(state::ColumnDisplay).collect::<Vec<_>>());
     println!();
+    if !snapshots.is_empty() {
+        println!("Along with their filesystem snapshot(s):");
+        println!();
+        for snapshot in &snapshots {
+            println!("  {}", snapshot.name);
+        }
+        println!();
+    }
 
-    let result = if yes {
-        true
-    } else {
-        Confirm::with_theme(&ColorfulTheme::default())
-            .with_prompt(" Do you wish to continue? ")
-            .default(false)
-            .interact()?
-    };
+    let result = prompt::confirm(yes)?;
     if !result {
         return Err(Error::Cancelled);
     }
 
+    // Their records are gone with the states, so a snapshot that
+    // can't be removed is left for the admin rather than failing
+    for snapshot in &snapshots {
+        if let Err(error) = snapshot::remove(snapshot) {
+            warn!("failed to remove snapshot {}: {error}", snapshot.name);
+        }
+    }
+
     // Prune these states / packages from all dbs
     prune_databases(&removals, &package_removals, state_db, install_db, layout_db)?;
 
+    // Remove downloads & assets no remaining package refers to
+    remove_orphans(install_db, layout_db, installation)?;
+
+    // Remove each state's archive folder
+    for state in removals {
+        let archive_path = installation.root_path(state.id.to_string());
+
+        if archive_path.exists() {
+            fs::remove_dir_all(&archive_path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// What was freed by [`collect_garbage`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Collected {
+    /// Packages no state referred to
+    pub packages: usize,
+    /// Downloads & assets only those packages referred to
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// Garbage collect packages that aren't selected by any state, such as those
+/// left behind by an interrupted transaction, along with all downloads & assets
+/// that are no longer referenced by a remaining package
+///
+/// Assets are shared by every package (and version) providing identical
+/// files, so one is only removed once nothing refers to it anymore.
+pub fn collect_garbage(
+    state_db: &db::state::Database,
+    install_db: &db::meta::Database,
+    layout_db: &db::layout::Database,
+    installation: &Installation,
+) -> Result<Collected, Error> {
+    // Roots without states (i.e. ephemeral build roots) only
+    // keep packages around as a cache, so nothing is garbage
+    if installation.active_state.is_none() {
+        return Err(Error::NoActiveState);
+    }
+
+    let referenced = state_db
+        .all()?
+        .into_iter()
+        .flat_map(|state| state.selections.into_iter().map(|selection| selection.package))
+        .collect::<BTreeSet<_>>();
+
+    let orphaned = install_db
+        .query(None)?
+        .into_iter()
+        .map(|(id, _)| id)
+        .filter(|id| !referenced.contains(id))
+        .collect::<Vec<_>>();
+
+    prune_databases(&[], &orphaned, state_db, install_db, layout_db)?;
+
+    let (files, bytes) = remove_orphans(install_db, layout_db, installation)?;
+
+    Ok(Collected {
+        packages: orphaned.len(),
+        files,
+        bytes,
+    })
+}
+
+/// Remove downloads & assets which no package in the databases refers to,
+/// returning how many files were removed and their size
+fn remove_orphans(
+    install_db: &db::meta::Database,
+    layout_db: &db::layout::Database,
+    installation: &Installation,
+) -> Result<(usize, u64), Error> {
     // Remove orphaned downloads
-    remove_orphaned_files(
+    let downloads = remove_orphaned_files(
         // root
         installation.cache_path("downloads").join("v1"),
         // final set of hashes to compare against
@@ -169,7 +263,7 @@ pub fn prune(
     )?;
 
     // Remove orphaned assets
-    remove_orphaned_files(
+    let assets = remove_orphaned_files(
         // root
         installation.assets_path("v2"),
         // final set of hashes to compare against
@@ -178,16 +272,7 @@ pub fn prune(
         |hash| Some(cache::asset_path(installation, &hash)),
     )?;
 
-    // Remove each state's archive folder
-    for state in removals {
-        let archive_path = installation.root_path(state.id.to_string());
-
-        if archive_path.exists() {
-            fs::remove_dir_all(&archive_path)?;
-        }
-    }
-
-    Ok(())
+    Ok((downloads.0 + assets.0, downloads.1 + assets.1))
 }
 
 /// Removes the provided states & packages from the databases
@@ -221,19 +306,28 @@ fn prune_databases(
         layout_db.batch_remove(chunk)?;
     }
 
+    // Give back the space of large removals
+    if packages.len() >= environment::DB_VACUUM_THRESHOLD {
+        install_db.vacuum()?;
+        layout_db.vacuum()?;
+    }
+
     Ok(())
 }
 
-/// Removes all files under `root` that no longer exist in the provided `final_hashes` set
+/// Removes all files under `root` that no longer exist in the provided `final_hashes` set,
+/// returning how many were removed and their size
 fn remove_orphaned_files(
     root: PathBuf,
     final_hashes: BTreeSet<String>,
     compute_path: impl Fn(String) -> Option<PathBuf>,
-) -> Result<(), Error> {
+) -> Result<(usize, u64), Error> {
     // Compute hashes to remove by (installed - final)
     let installed_hashes = enumerate_file_hashes(&root)?;
     let hashes_to_remove = installed_hashes.difference(&final_hashes);
 
+    let mut removed = (0, 0);
+
     // Remove each and it's parent dir if empty
     hashes_to_remove.into_iter().try_for_each(|hash| {
         // Compute path to file using hash
@@ -242,8 +336,10 @@ fn remove_orphaned_files(
         };
 
         // Remove if it exists
-        if file.exists() {
+        if let Ok(metadata) = file.symlink_metadata() {
             fs::remove_file(&file)?;
+            removed.0 += 1;
+            removed.1 += metadata.len();
         }
 
         // Try to remove leading parent dirs if they're
@@ -255,7 +351,7 @@ fn remove_orphaned_files(
         Ok(()) as Result<(), Error>
     })?;
 
-    Ok(())
+    Ok(removed)
 }
 
 /// Returns all nested files under `root` and parses the file name as a hash
@@ -273,7 +369,7 @@ fn enumerate_file_hashes(root: impl AsRef<Path>) -> Result<BTreeSet<String>, io:
 }
 
 /// Returns all nested files under `root`
-fn enumerate_files(root: impl AsRef<Path>) -> Result<Vec<PathBuf>, io::Error> {
+pub(super) fn enumerate_files(root: impl AsRef<Path>) -> Result<Vec<PathBuf>, io::Error> {
     use rayon::prelude::*;
 
     fn recurse(dir: impl AsRef<Path>) -> io::Result<Vec<PathBuf>> {
@@ -312,7 +408,7 @@ fn enumerate_files(root: impl AsRef<Path>) -> Result<Vec<PathBuf>, io::Error> {
 /// Remove all empty folders from `starting` and moving up until `root`
 ///
 /// `root` must be a prefix / ancestor of `starting`
-fn remove_empty_dirs(starting: &Path, root: &Path) -> Result<(), io::Error> {
+pub(super) fn remove_empty_dirs(starting: &Path, root: &Path) -> Result<(), io::Error> {
     if !starting.starts_with(root) || !starting.is_dir() || !root.is_dir() {
         return Ok(());
     }
@@ -0,0 +1,235 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Recovery of corrupted databases
+//!
+//! Repository meta dbs are rebuilt from their cached `stone.index`. The install &
+//! layout dbs are rebuilt from the stones in the download cache, for every package
+//! selected by a recorded state. The state db itself is the source of truth and
+//! can't be recovered this way.
+
+use std::{
+    collections::BTreeSet,
+    fs::{self, File},
+    io,
+    path::Path,
+};
+
+use stone::read::PayloadKind;
+use thiserror::Error;
+
+use crate::{
+    client::cache,
+    db, environment,
+    package::{self, Meta},
+    repository, Installation,
+};
+
+/// Outcome of [`rebuild`]
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Repositories without a cached index, empty until refreshed
+    pub missing_indices: Vec<repository::Id>,
+    /// Packages recovered into the install & layout dbs
+    pub recovered: usize,
+    /// Packages selected by a state, but not found in the download cache
+    pub missing_packages: Vec<package::Id>,
+}
+
+/// Rebuild all repository meta dbs, then the install & layout dbs
+pub fn rebuild(installation: &Installation) -> Result<Report, Error> {
+    let state_db = db::state::Database::open(
+        installation.db_path("state").to_str().unwrap_or_default(),
+        installation.mutability,
+    )
+    .map_err(Error::StateDb)?;
+
+    let config = config::Manager::system(&installation.root, "moss");
+    let (repositories, missing_indices) = repository::Manager::rebuild(config, installation.clone())?;
+
+    let mut wanted = state_db
+        .all()
+        .map_err(Error::StateDb)?
+        .into_iter()
+        .flat_map(|state| state.selections.into_iter().map(|selection| selection.package))
+        .collect::<BTreeSet<_>>();
+
+    // Build into separate files, only replacing the live dbs once complete
+    let install_path = installation.db_path("install.rebuild");
+    let layout_path = installation.db_path("layout.rebuild");
+    remove_db_files(&install_path)?;
+    remove_db_files(&layout_path)?;
+
+    let install_db = db::meta::Database::new(install_path.to_str().unwrap_or_default())?;
+    let layout_db = db::layout::Database::new(layout_path.to_str().unwrap_or_default())?;
+
+    let mut recovered = 0;
+
+    for file in cache::cached(installation)? {
+        // Interrupted downloads
+        if file.path.extension().is_some_and(|extension| extension == "part") {
+            continue;
+        }
+
+        let Some((meta, layouts)) = read_stone(&file.path)? else {
+            continue;
+        };
+
+        // Repository packages are identified by their hash, sideloaded ones by their name
+        let sideloaded = file.hash.is_none();
+        let id = match &file.hash {
+            Some(hash) => package::Id::from(hash.clone()),
+            None => meta.id(),
+        };
+
+        if !wanted.remove(&id) {
+            continue;
+        }
+
+        // Prefer the repository metadata, it knows where the package came from
+        let meta = repositories
+            .active()
+            .find_map(|repo| repo.db.get(&id).ok())
+            .unwrap_or(Meta {
+                hash: file.hash.clone(),
+                download_size: Some(file.size),
+                ..meta
+            });
+
+        for chunk in layouts.chunks(environment::DB_BATCH_SIZE) {
+            layout_db.batch_add(chunk.iter().map(|layout| (id.clone(), layout.clone())).collect())?;
+        }
+        install_db.add(id.clone(), meta)?;
+        if sideloaded {
+            install_db.set_sideloaded(&id, &file.path.display().to_string())?;
+        }
+
+        recovered += 1;
+    }
+
+    drop(install_db);
+    drop(layout_db);
+
+    swap_in(&install_path, &installation.db_path("install"))?;
+    swap_in(&layout_path, &installation.db_path("layout"))?;
+
+    Ok(Report {
+        missing_indices,
+        recovered,
+        missing_packages: wanted.into_iter().collect(),
+    })
+}
+
+/// Metadata & layouts of the stone at `path`, if it is one
+fn read_stone(path: &Path) -> Result<Option<(Meta, Vec<stone::payload::Layout>)>, Error> {
+    let Ok(mut reader) = stone::read(File::open(path)?) else {
+        return Ok(None);
+    };
+    let Ok(payloads) = reader.payloads()?.collect::<Result<Vec<_>, _>>() else {
+        return Ok(None);
+    };
+
+    let Some(meta) = payloads.iter().find_map(PayloadKind::meta) else {
+        return Ok(None);
+    };
+    let layouts = payloads
+        .iter()
+        .filter_map(PayloadKind::layout)
+        .flat_map(|payload| payload.body.iter().cloned())
+        .collect();
+
+    Ok(Meta::from_stone_payload(&meta.body).ok().map(|meta| (meta, layouts)))
+}
+
+/// Atomically replace the db at `target` with the one at `source`, so either
+/// is in place if interrupted
+///
+/// Any leftover journal of `target` is removed first, as it would otherwise be
+/// applied to the rebuilt db.
+fn swap_in(source: &Path, target: &Path) -> Result<(), Error> {
+    remove_sidecar_files(target)?;
+    fs::rename(source, target)?;
+    Ok(())
+}
+
+fn remove_db_files(path: &Path) -> io::Result<()> {
+    remove_file(path)?;
+    remove_sidecar_files(path)
+}
+
+/// Remove the journal, WAL & shared memory files of the db at `path`
+fn remove_sidecar_files(path: &Path) -> io::Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+    for suffix in ["-journal", "-wal", "-shm"] {
+        remove_file(&path.with_file_name(format!("{name}{suffix}")))?;
+    }
+
+    Ok(())
+}
+
+fn remove_file(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("state db can't be rebuilt, restore it from a backup")]
+    StateDb(#[source] db::Error),
+    #[error("db")]
+    Db(#[from] db::Error),
+    #[error("repository manager")]
+    Repository(#[from] repository::manager::Error),
+    #[error("stone read")]
+    Stone(#[from] stone::read::Error),
+    #[error("io")]
+    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::state::Selection;
+
+    #[test]
+    fn rebuild_corrupted() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let installation = Installation::open(root).unwrap();
+
+        let bash_completion = include_bytes!("../../../test/bash-completion-2.11-1-1-x86_64.stone");
+        let sideloaded = installation.cache_path("sideloaded");
+        fs::create_dir_all(&sideloaded).unwrap();
+        fs::write(sideloaded.join("bash-completion.stone"), bash_completion).unwrap();
+
+        let (meta, layouts) = read_stone(&sideloaded.join("bash-completion.stone")).unwrap().unwrap();
+        let id = meta.id();
+
+        let state_db = db::state::Database::new(installation.db_path("state").to_str().unwrap()).unwrap();
+        state_db.add(&[Selection::explicit(id.clone())], None, None).unwrap();
+
+        // Garbage in place of the install db, with a hot journal that would be rolled back into it
+        let install_path = installation.db_path("install");
+        fs::write(&install_path, "not a database").unwrap();
+        fs::write(installation.db_path("install-journal"), "not a journal").unwrap();
+        assert!(db::meta::Database::new(install_path.to_str().unwrap()).is_err());
+
+        let report = rebuild(&installation).unwrap();
+        assert_eq!(report.recovered, 1);
+        assert!(report.missing_packages.is_empty());
+
+        let install_db = db::meta::Database::new(install_path.to_str().unwrap()).unwrap();
+        assert_eq!(install_db.get(&id).unwrap().name, meta.name);
+        assert!(install_db.sideloaded(&id).unwrap().is_some());
+        assert!(install_db.check().unwrap().is_empty());
+
+        let layout_db = db::layout::Database::new(installation.db_path("layout").to_str().unwrap()).unwrap();
+        assert_eq!(layout_db.all().unwrap().len(), layouts.len());
+
+        assert!(!installation.db_path("install-journal").exists());
+        assert!(!installation.db_path("install.rebuild").exists());
+    }
+}
@@ -0,0 +1,126 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Resolution of the packages removed along with the requested ones
+
+use std::collections::BTreeSet;
+
+use thiserror::Error;
+use tracing::warn;
+
+use crate::{
+    client::{self, Client},
+    db,
+    package::{self, Flags},
+    registry::transaction,
+    state::Selection,
+    Package, Provider,
+};
+
+/// The packages affected by removing a set of packages, see [`resolve`]
+#[derive(Debug, Clone, Default)]
+pub struct Resolution {
+    /// Requested packages
+    pub requested: Vec<package::Id>,
+    /// Packages to remove, including those depending on the requested ones
+    pub removed: Vec<Package>,
+    /// Removed packages which weren't requested, but depend on those that were
+    pub dependents: Vec<Package>,
+    /// Installed packages staying installed
+    pub finalized: BTreeSet<package::Id>,
+}
+
+/// Resolve the installed packages providing `providers` and everything depending
+/// on them, without changing the root
+pub fn resolve(client: &Client, providers: &[Provider]) -> Result<Resolution, Error> {
+    let installed = client.registry.list_installed(Flags::default()).collect::<Vec<_>>();
+    let installed_ids = installed.iter().map(|p| p.id.clone()).collect::<BTreeSet<_>>();
+
+    let mut requested = vec![];
+    let mut not_installed = vec![];
+    for provider in providers {
+        match installed.iter().find(|i| i.meta.providers.contains(provider)) {
+            Some(package) => requested.push(package.id.clone()),
+            None => not_installed.push(provider.name.clone()),
+        }
+    }
+    if !not_installed.is_empty() {
+        return Err(Error::NotInstalled(not_installed));
+    }
+
+    // Add all installed packages to transaction
+    let mut transaction = client
+        .registry
+        .transaction_with_installed(installed_ids.iter().cloned().collect())?;
+
+    // Remove all requested packages, the finalized tx has all reverse deps removed
+    transaction.remove(requested.clone());
+    let finalized = transaction.finalize().cloned().collect::<BTreeSet<_>>();
+
+    // Resolve all removed packages, where removed is (installed - finalized)
+    let removed = client.resolve_packages(installed_ids.difference(&finalized))?;
+
+    let dependents = removed.iter().filter(|p| !requested.contains(&p.id)).cloned().collect();
+
+    let violations = client.hold_violations(&installed, &finalized);
+    if !violations.is_empty() {
+        return Err(Error::Held(violations));
+    }
+
+    Ok(Resolution {
+        requested,
+        removed,
+        dependents,
+        finalized,
+    })
+}
+
+/// The selections of the state applying a [`resolve`]d `resolution`, i.e.
+/// those of the active state without the removed packages
+pub fn selections(client: &Client, resolution: &Resolution) -> Result<Vec<Selection>, Error> {
+    let previous_selections = match client.installation.active_state {
+        Some(id) => client.state_db.get(id)?.selections,
+        None => vec![],
+    };
+
+    Ok(resolution
+        .finalized
+        .iter()
+        .map(|id| {
+            previous_selections
+                .iter()
+                .find(|s| s.package == *id)
+                .cloned()
+                // Should be unreachable since new state from removal
+                // is always a subset of the previous state
+                .unwrap_or_else(|| {
+                    warn!("previous selection not found during removal for package {id:?}, marking as not explicit");
+
+                    Selection {
+                        package: id.clone(),
+                        explicit: false,
+                        reason: None,
+                    }
+                })
+        })
+        .collect())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("packages aren't installed: {}", .0.join(", "))]
+    NotInstalled(Vec<String>),
+
+    #[error("removal would change held packages: {}", .0.join(", "))]
+    Held(Vec<String>),
+
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error("transaction")]
+    Transaction(#[from] transaction::Error),
+
+    #[error("db")]
+    Db(#[from] db::Error),
+}
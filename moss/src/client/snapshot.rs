@@ -0,0 +1,411 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Filesystem snapshots taken before each transaction
+//!
+//! Disabled unless [`Settings`] enable them within `/etc/moss/snapshot.d/`:
+//!
+//! ```yaml
+//! enabled: true
+//! subvolumes:
+//!   - /
+//!   - /home
+//! ```
+//!
+//! Before a transaction moves away from the active state, each listed
+//! subvolume (or only the root) on btrfs or zfs is snapshotted and the
+//! snapshot recorded against that state, so `moss state rollback --filesystem`
+//! can restore it later. Subvolumes on any other filesystem are skipped.
+//!
+//! Restored btrfs snapshots become the default subvolume, which is ignored by
+//! mounts with an explicit `subvol=` option, so restoring them is refused then.
+//! Snapshots are removed along with their state when it's pruned.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use chrono::Utc;
+use config::Config;
+use nix::sys::statfs::{statfs, FsType, BTRFS_SUPER_MAGIC};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::debug;
+
+use crate::{
+    state::{self, Filesystem, Snapshot},
+    Installation,
+};
+
+/// Directory within a btrfs subvolume holding its snapshots
+const SNAPSHOT_DIR: &str = ".snapshots";
+
+/// Not known to nix, see `ZFS_SUPER_MAGIC` in zfs
+const ZFS_SUPER_MAGIC: FsType = FsType(0x2fc12fc1);
+
+/// Snapshot settings, stored as `etc/moss/snapshot.d/{name}.yaml`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Settings {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Mount points to snapshot, relative to the root. Defaults to the root itself
+    #[serde(default)]
+    pub subvolumes: Vec<PathBuf>,
+}
+
+impl Config for Settings {
+    fn domain() -> String {
+        "snapshot".into()
+    }
+}
+
+impl Settings {
+    /// Subvolumes to snapshot, listed by any of the `settings`, or
+    /// `None` unless one of them enables snapshots
+    pub fn subvolumes(settings: &[Settings]) -> Option<Vec<PathBuf>> {
+        if !settings.iter().any(|settings| settings.enabled) {
+            return None;
+        }
+
+        let mut subvolumes = settings
+            .iter()
+            .flat_map(|settings| settings.subvolumes.iter().cloned())
+            .collect::<Vec<_>>();
+        if subvolumes.is_empty() {
+            subvolumes.push(PathBuf::from("/"));
+        }
+        subvolumes.sort();
+        subvolumes.dedup();
+
+        Some(subvolumes)
+    }
+}
+
+/// How a [`Snapshot`] was restored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Restored {
+    /// The subvolume was rolled back in place
+    InPlace,
+    /// A writable copy at this path becomes the default subvolume on next boot
+    OnReboot(PathBuf),
+}
+
+/// The filesystem `path` lives on, if it supports snapshots
+pub fn detect(path: &Path) -> Option<Filesystem> {
+    let kind = statfs(path).ok()?.filesystem_type();
+
+    if kind == BTRFS_SUPER_MAGIC {
+        Some(Filesystem::Btrfs)
+    } else if kind == ZFS_SUPER_MAGIC {
+        Some(Filesystem::Zfs)
+    } else {
+        None
+    }
+}
+
+/// Snapshot each of `subvolumes` within `installation` that supports
+/// it, returning the snapshots to record against the active `state`
+pub fn take(installation: &Installation, subvolumes: &[PathBuf], state: state::Id) -> Result<Vec<Snapshot>, Error> {
+    let created = Utc::now();
+    let name = format!("moss-{state}-{}", created.format("%Y%m%d%H%M%S"));
+
+    let mut snapshots = vec![];
+
+    for subvolume in subvolumes {
+        let subvolume = installation.root.join(subvolume.strip_prefix("/").unwrap_or(subvolume));
+
+        let Some(filesystem) = detect(&subvolume) else {
+            debug!("Not snapshotting {subvolume:?}, its filesystem doesn't support it");
+            continue;
+        };
+
+        let name = match filesystem {
+            Filesystem::Btrfs => {
+                let dir = subvolume.join(SNAPSHOT_DIR);
+                fs::create_dir_all(&dir).map_err(|error| Error::CreateDir(dir.clone(), error))?;
+
+                let path = dir.join(&name);
+                run(Command::new("btrfs")
+                    .args(["subvolume", "snapshot", "-r"])
+                    .arg(&subvolume)
+                    .arg(&path))?;
+
+                path.to_string_lossy().into_owned()
+            }
+            Filesystem::Zfs => {
+                let dataset = run(Command::new("zfs").args(["list", "-H", "-o", "name"]).arg(&subvolume))?;
+                let name = format!("{}@{name}", dataset.trim());
+                run(Command::new("zfs").args(["snapshot", &name]))?;
+
+                name
+            }
+        };
+        debug!("Snapshotted {subvolume:?} as {name}");
+
+        snapshots.push(Snapshot {
+            state,
+            filesystem,
+            subvolume,
+            name,
+            created,
+        });
+    }
+
+    Ok(snapshots)
+}
+
+/// Restore the subvolume of `snapshot` to it
+///
+/// zfs datasets are rolled back in place, destroying any newer snapshots.
+/// A mounted btrfs subvolume can't be replaced, so a writable copy of the
+/// snapshot is made the default subvolume instead. That's refused if the
+/// subvolume is mounted with an explicit `subvol=` option, which takes
+/// precedence over the default
+pub fn restore(snapshot: &Snapshot) -> Result<Restored, Error> {
+    if snapshot.filesystem == Filesystem::Btrfs {
+        let fstab = fs::read_to_string("/etc/fstab").unwrap_or_default();
+        let cmdline = fs::read_to_string("/proc/cmdline").unwrap_or_default();
+
+        if explicit_subvolume(&snapshot.subvolume, &fstab, &cmdline) {
+            return Err(Error::ExplicitSubvolume(snapshot.subvolume.clone()));
+        }
+    }
+
+    let (commands, restored) = restore_commands(snapshot, &Utc::now().format("%Y%m%d%H%M%S").to_string());
+    for mut command in commands {
+        run(&mut command)?;
+    }
+
+    Ok(restored)
+}
+
+/// Commands restoring `snapshot`, naming a btrfs copy after it with `suffix`
+fn restore_commands(snapshot: &Snapshot, suffix: &str) -> (Vec<Command>, Restored) {
+    match snapshot.filesystem {
+        Filesystem::Btrfs => {
+            let source = Path::new(&snapshot.name);
+            let path = source.with_file_name(format!(
+                "{}-restored-{suffix}",
+                source.file_name().unwrap_or_default().to_string_lossy(),
+            ));
+
+            let mut copy = Command::new("btrfs");
+            copy.args(["subvolume", "snapshot"]).arg(source).arg(&path);
+            let mut set_default = Command::new("btrfs");
+            set_default.args(["subvolume", "set-default"]).arg(&path);
+
+            (vec![copy, set_default], Restored::OnReboot(path))
+        }
+        Filesystem::Zfs => {
+            let mut rollback = Command::new("zfs");
+            rollback.args(["rollback", "-r", &snapshot.name]);
+
+            (vec![rollback], Restored::InPlace)
+        }
+    }
+}
+
+/// Remove `snapshot`, i.e. once its state is pruned. One that's gone already is ignored
+pub fn remove(snapshot: &Snapshot) -> Result<(), Error> {
+    match snapshot.filesystem {
+        Filesystem::Btrfs => {
+            if Path::new(&snapshot.name).exists() {
+                run(Command::new("btrfs").args(["subvolume", "delete"]).arg(&snapshot.name))?;
+            }
+        }
+        Filesystem::Zfs => {
+            // Never destroy the dataset itself
+            if !snapshot.name.contains('@') {
+                return Err(Error::NotASnapshot(snapshot.name.clone()));
+            }
+
+            let exists = Command::new("zfs")
+                .args(["list", "-H", "-t", "snapshot", &snapshot.name])
+                .output()
+                .is_ok_and(|output| output.status.success());
+            if exists {
+                run(Command::new("zfs").args(["destroy", &snapshot.name]))?;
+            }
+        }
+    }
+
+    debug!("Removed snapshot {}", snapshot.name);
+
+    Ok(())
+}
+
+/// Whether `mount_point` is mounted with an explicit `subvol=` or `subvolid=`
+/// option by `fstab`, or for the root by the `rootflags` of the kernel `cmdline`
+fn explicit_subvolume(mount_point: &Path, fstab: &str, cmdline: &str) -> bool {
+    let explicit = |options: &str| {
+        options
+            .split(',')
+            .any(|option| option.starts_with("subvol=") || option.starts_with("subvolid="))
+    };
+
+    let in_fstab = fstab
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('#').next().unwrap_or_default().split_whitespace().skip(1);
+            Some((fields.next()?, fields.nth(1)?))
+        })
+        .any(|(target, options)| Path::new(target) == mount_point && explicit(options));
+
+    let in_cmdline = mount_point == Path::new("/")
+        && cmdline
+            .split_whitespace()
+            .filter_map(|arg| arg.strip_prefix("rootflags="))
+            .any(explicit);
+
+    in_fstab || in_cmdline
+}
+
+/// Run `command`, returning its stdout
+fn run(command: &mut Command) -> Result<String, Error> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command.output().map_err(|error| Error::Spawn(program.clone(), error))?;
+
+    if !output.status.success() {
+        let command = Some(command.get_program())
+            .into_iter()
+            .chain(command.get_args())
+            .map(|arg| arg.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        return Err(Error::Failed(
+            command,
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("create {0:?}")]
+    CreateDir(PathBuf, #[source] io::Error),
+    #[error("run {0}")]
+    Spawn(String, #[source] io::Error),
+    #[error("`{0}` failed: {1}")]
+    Failed(String, String),
+    #[error("{0:?} is mounted with an explicit subvol= option, which a restored default subvolume doesn't apply to")]
+    ExplicitSubvolume(PathBuf),
+    #[error("{0} isn't a zfs snapshot")]
+    NotASnapshot(String),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn enabled_subvolumes() {
+        let vendor = Settings {
+            enabled: false,
+            subvolumes: vec!["/home".into()],
+        };
+        assert_eq!(Settings::subvolumes(&[vendor.clone()]), None);
+
+        let admin = Settings {
+            enabled: true,
+            subvolumes: vec![],
+        };
+        assert_eq!(Settings::subvolumes(&[admin.clone()]), Some(vec!["/".into()]));
+
+        let admin = Settings {
+            subvolumes: vec!["/".into(), "/home".into()],
+            ..admin
+        };
+        assert_eq!(
+            Settings::subvolumes(&[vendor, admin]),
+            Some(vec!["/".into(), "/home".into()])
+        );
+    }
+
+    fn args(command: &Command) -> Vec<String> {
+        Some(command.get_program())
+            .into_iter()
+            .chain(command.get_args())
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn take_unsupported() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        // Only meaningful where the temporary directory doesn't support snapshots
+        if detect(root).is_none() {
+            let installation = Installation::open(root).unwrap();
+            let snapshots = take(&installation, &["/".into()], 1.into()).unwrap();
+
+            assert!(snapshots.is_empty());
+            assert!(!root.join(SNAPSHOT_DIR).exists());
+        }
+    }
+
+    #[test]
+    fn restore_and_remove() {
+        let snapshot = |filesystem, name: &str| Snapshot {
+            state: 1.into(),
+            filesystem,
+            subvolume: "/".into(),
+            name: name.to_string(),
+            created: Utc::now(),
+        };
+
+        let (commands, restored) = restore_commands(&snapshot(Filesystem::Btrfs, "/.snapshots/moss-1-a"), "b");
+        assert_eq!(
+            commands.iter().map(args).collect::<Vec<_>>(),
+            vec![
+                vec![
+                    "btrfs",
+                    "subvolume",
+                    "snapshot",
+                    "/.snapshots/moss-1-a",
+                    "/.snapshots/moss-1-a-restored-b"
+                ],
+                vec!["btrfs", "subvolume", "set-default", "/.snapshots/moss-1-a-restored-b"],
+            ]
+        );
+        assert_eq!(restored, Restored::OnReboot("/.snapshots/moss-1-a-restored-b".into()));
+
+        let (commands, restored) = restore_commands(&snapshot(Filesystem::Zfs, "rpool/root@moss-1-a"), "b");
+        assert_eq!(
+            commands.iter().map(args).collect::<Vec<_>>(),
+            vec![vec!["zfs", "rollback", "-r", "rpool/root@moss-1-a"]]
+        );
+        assert_eq!(restored, Restored::InPlace);
+
+        assert!(matches!(
+            remove(&snapshot(Filesystem::Zfs, "rpool/root")),
+            Err(Error::NotASnapshot(_))
+        ));
+        // Gone already
+        assert!(remove(&snapshot(Filesystem::Btrfs, "/nonexistent/.snapshots/moss-1-a")).is_ok());
+    }
+
+    #[test]
+    fn explicit_subvolumes() {
+        let fstab = "\
+# <fs> <mount point> <type> <options> <dump> <pass>
+UUID=1234 / btrfs defaults,compress=zstd 0 0
+UUID=1234 /home btrfs subvol=@home,noatime 0 0
+";
+
+        assert!(!explicit_subvolume(Path::new("/"), fstab, "quiet root=UUID=1234"));
+        assert!(explicit_subvolume(Path::new("/home"), fstab, ""));
+        assert!(explicit_subvolume(
+            Path::new("/"),
+            fstab,
+            "root=UUID=1234 rootflags=subvol=@"
+        ));
+        assert!(!explicit_subvolume(Path::new("/var"), fstab, "rootflags=subvolid=256"));
+    }
+}
@@ -0,0 +1,163 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Disk space preflight checks
+//!
+//! Before fetching anything we estimate how much space a transaction needs on
+//! each filesystem involved, so it can be aborted up front rather than failing
+//! halfway through unpacking with `ENOSPC`.
+//!
+//! Downloads & the temporary content of a package being unpacked live in the
+//! cache, while the unpacked files go to the asset store. Installed files are
+//! hardlinked from the asset store, so they take no further space in the root.
+
+use std::{
+    borrow::Borrow,
+    collections::BTreeMap,
+    fmt, io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use nix::sys::statvfs::statvfs;
+use tui::HumanBytes;
+
+use crate::{client::Client, Package};
+
+/// Whether the preflight check is skipped, see [`set_ignored`]
+static IGNORED: AtomicBool = AtomicBool::new(false);
+
+/// Assumed ratio of unpacked to download size, for packages whose
+/// installed size isn't known until they've been fetched
+const ESTIMATED_UNPACK_RATIO: u64 = 3;
+
+/// Skip the preflight check, i.e. when the estimate is known to be off
+pub fn set_ignored(ignored: bool) {
+    IGNORED.store(ignored, Ordering::Relaxed);
+}
+
+/// Space required on a single filesystem
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Requirement {
+    /// Directory on the filesystem, used for reporting
+    pub path: PathBuf,
+    pub required: u64,
+    pub available: u64,
+}
+
+impl Requirement {
+    pub fn is_satisfied(&self) -> bool {
+        self.required <= self.available
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} required on {}, {} available",
+            HumanBytes(self.required),
+            self.path.display(),
+            HumanBytes(self.available)
+        )
+    }
+}
+
+/// Space required on each filesystem to fetch & unpack `packages`
+pub fn requirements<T>(client: &Client, packages: &[T]) -> io::Result<Vec<Requirement>>
+where
+    T: Borrow<Package>,
+{
+    let mut downloads = 0;
+    let mut unpacked = 0;
+    let mut largest = 0;
+
+    // Cached packages are already unpacked
+    for package in packages.iter().map(Borrow::borrow).filter(|p| !client.is_cached(p)) {
+        let download_size = package.meta.download_size.unwrap_or_default();
+        let unpacked_size = package
+            .meta
+            .installed_size
+            .unwrap_or(download_size * ESTIMATED_UNPACK_RATIO);
+
+        downloads += download_size;
+        unpacked += unpacked_size;
+        largest = largest.max(unpacked_size);
+    }
+
+    let cache = client.installation.cache_path("");
+    let assets = client.installation.assets_path("");
+
+    // The content of the largest package is held in the cache while unpacking
+    let mut by_filesystem = BTreeMap::<u64, Requirement>::new();
+    for (path, required) in [(cache, downloads + largest), (assets, unpacked)] {
+        if required == 0 {
+            continue;
+        }
+
+        let path = existing_ancestor(&path);
+        let device = path.metadata()?.dev();
+
+        by_filesystem
+            .entry(device)
+            .or_insert(Requirement {
+                available: available(&path)?,
+                path,
+                required: 0,
+            })
+            .required += required;
+    }
+
+    Ok(by_filesystem.into_values().collect())
+}
+
+/// Returns the first requirement which can't be satisfied, unless ignored
+pub fn check<T>(client: &Client, packages: &[T]) -> io::Result<Option<Requirement>>
+where
+    T: Borrow<Package>,
+{
+    if IGNORED.load(Ordering::Relaxed) {
+        return Ok(None);
+    }
+
+    Ok(requirements(client, packages)?
+        .into_iter()
+        .find(|requirement| !requirement.is_satisfied()))
+}
+
+/// Bytes available to unprivileged users on the filesystem containing `path`
+pub fn available(path: &Path) -> io::Result<u64> {
+    let stat = statvfs(path)?;
+    Ok(stat.blocks_available() as u64 * stat.fragment_size() as u64)
+}
+
+/// Directories are created lazily, so measure the closest one which exists
+fn existing_ancestor(path: &Path) -> PathBuf {
+    path.ancestors()
+        .find(|ancestor| ancestor.exists())
+        .unwrap_or(Path::new("/"))
+        .to_path_buf()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn measure() {
+        let dir = env!("CARGO_MANIFEST_DIR");
+        let missing = Path::new(dir).join("does/not/exist");
+
+        assert_eq!(existing_ancestor(&missing), Path::new(dir));
+        assert!(available(Path::new(dir)).unwrap() > 0);
+
+        let requirement = Requirement {
+            path: PathBuf::from(dir),
+            required: 2,
+            available: 1,
+        };
+        assert!(!requirement.is_satisfied());
+    }
+}
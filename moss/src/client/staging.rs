@@ -0,0 +1,100 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Resumable application of a staged state
+//!
+//! A new `/usr` is blitted to the staging tree and completed by transaction
+//! triggers before it's atomically swapped with the live one. Once complete, a
+//! marker records the transition, so a transaction interrupted during or after
+//! the swap can be resumed rather than leaving the previous `/usr` unarchived.
+
+use std::{fs, io};
+
+use nix::{
+    fcntl::{self, OFlag},
+    sys::stat::Mode,
+    unistd::{close, syncfs},
+};
+
+use crate::{state, Installation};
+
+/// Name of the marker within the staging directory
+const MARKER: &str = ".pending";
+
+/// A staged state that hasn't been fully applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pending {
+    /// State being applied
+    pub state: state::Id,
+    /// Previously active state, still to be archived
+    pub previous: Option<state::Id>,
+    /// Whether the staged `/usr` was already swapped into place
+    pub swapped: bool,
+}
+
+/// Record that the staged tree is complete and ready to be swapped
+/// into place, flushing it to disk beforehand
+pub(super) fn mark(installation: &Installation, state: state::Id, previous: Option<state::Id>) -> io::Result<()> {
+    let staging = installation.staging_dir();
+
+    let fd = fcntl::open(&staging, OFlag::O_DIRECTORY | OFlag::O_RDONLY, Mode::empty())?;
+    let synced = syncfs(fd);
+    close(fd)?;
+    synced?;
+
+    let previous = previous.map(|id| id.to_string()).unwrap_or_default();
+    fs::write(staging.join(MARKER), format!("{state}\n{previous}\n"))?;
+
+    Ok(())
+}
+
+/// The staged state pending application, if any
+pub fn pending(installation: &Installation) -> io::Result<Option<Pending>> {
+    let contents = match fs::read_to_string(installation.staging_path(MARKER)) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error),
+    };
+
+    Ok(parse(&contents).map(|(state, previous)| Pending {
+        state,
+        previous,
+        // The live `/usr` records the state it belongs to
+        swapped: installation.active_state == Some(state),
+    }))
+}
+
+/// Remove the marker once the previous `/usr` is archived
+pub(super) fn clear(installation: &Installation) -> io::Result<()> {
+    match fs::remove_file(installation.staging_path(MARKER)) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+fn parse(contents: &str) -> Option<(state::Id, Option<state::Id>)> {
+    let mut lines = contents.lines().map(str::trim);
+
+    let state = lines.next()?.parse::<i32>().ok()?;
+    let previous = match lines.next() {
+        Some(line) if !line.is_empty() => Some(line.parse::<i32>().ok()?.into()),
+        _ => None,
+    };
+
+    Some((state.into(), previous))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_marker() {
+        assert_eq!(parse("12\n11\n"), Some((12.into(), Some(11.into()))));
+        assert_eq!(parse("1\n\n"), Some((1.into(), None)));
+        assert_eq!(parse("1"), Some((1.into(), None)));
+        assert_eq!(parse("one\n"), None);
+        assert_eq!(parse("2\nzero\n"), None);
+    }
+}
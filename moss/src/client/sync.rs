@@ -0,0 +1,242 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Resolution of the state installed packages are sync'd to
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+
+use thiserror::Error;
+
+use crate::{
+    client::{self, Client},
+    db, package,
+    registry::{conflict, transaction},
+    state::Selection,
+    Package,
+};
+
+/// The installed packages and the state they're sync'd to, see [`resolve`]
+#[derive(Debug, Clone)]
+pub struct Resolved {
+    pub installed: Vec<Package>,
+    pub finalized: Vec<Package>,
+    /// Installed packages removed as they conflict with sync'd ones
+    pub conflicting: Vec<Package>,
+    /// Names of held packages
+    pub holds: BTreeSet<String>,
+}
+
+impl Resolved {
+    /// Packages to fetch & installed packages to remove when syncing the active state
+    pub fn changes(&self) -> (Vec<&Package>, Vec<&Package>) {
+        let synced = self
+            .finalized
+            .iter()
+            .filter(|p| !self.installed.iter().any(|i| i.id == p.id))
+            .collect();
+        let removed = self
+            .installed
+            .iter()
+            .filter(|p| !self.finalized.iter().any(|f| f.id == p.id))
+            .collect();
+
+        (synced, removed)
+    }
+}
+
+/// Resolve the state the installed packages are sync'd to, with only
+/// version upgrades if `upgrade_only`
+pub fn resolve(client: &Client, upgrade_only: bool) -> Result<Resolved, Error> {
+    // Grab all the existing installed packages
+    let installed = client
+        .registry
+        .list_installed(package::Flags::default())
+        .collect::<Vec<_>>();
+    if installed.is_empty() {
+        return Err(Error::NoInstall);
+    }
+
+    // Resolve the finalized state w/ 2 passes.
+    //
+    // 1. Resolve a new state based on all explicit packages with sync applied
+    // 2. Resolve a new state based on `1`, this ensures applicable transitive
+    //    sync is applied
+    //
+    // By resolving only explicit first, this ensures any "orphaned" transitive deps
+    // are naturally dropped from the final state.
+    //
+    // Held packages are fixed constraints, always part of the state as installed.
+    //
+    // Packages replaced by an available package are swapped for it.
+    let holds = client.holds().into_keys().collect::<BTreeSet<_>>();
+    let replacements = conflict::replacements(&client.registry, &installed);
+    let sync = Sync {
+        upgrade_only,
+        holds: &holds,
+        replacements: &replacements,
+    };
+    let first_pass = resolve_with_sync(client, Resolution::Explicit, &sync, &installed)?;
+    let mut finalized = resolve_with_sync(client, Resolution::All, &sync, &first_pass)?;
+
+    // Drop installed packages conflicting with sync'd ones
+    let (incoming, kept): (Vec<_>, Vec<_>) = finalized
+        .iter()
+        .cloned()
+        .partition(|p| !installed.iter().any(|i| i.id == p.id));
+    let conflicting = conflict::resolve(&client.registry, &incoming, &kept)?;
+    finalized.retain(|p| !conflicting.iter().any(|c| c.id == p.id));
+
+    let violations = client.hold_violations(&installed, finalized.iter().map(|p| &p.id));
+    if !violations.is_empty() {
+        return Err(Error::Held(violations));
+    }
+
+    Ok(Resolved {
+        installed,
+        finalized,
+        conflicting,
+        holds,
+    })
+}
+
+/// Map the `finalized` state to [`Selection`]s by referencing
+/// their value from the previous state
+pub fn selections(client: &Client, installed: &[Package], finalized: Vec<Package>) -> Result<Vec<Selection>, Error> {
+    let previous_selections = match client.installation.active_state {
+        Some(id) => client.state_db.get(id)?.selections,
+        None => vec![],
+    };
+
+    let selections = finalized
+        .into_iter()
+        .map(|p| {
+            // Use old version (or replaced package) id to lookup previous selection
+            let lookup_id = installed
+                .iter()
+                .find_map(|i| (i.meta.name == p.meta.name).then_some(&i.id))
+                .or_else(|| {
+                    installed
+                        .iter()
+                        .find_map(|i| conflict::replaces(&p, i).then_some(&i.id))
+                })
+                .unwrap_or(&p.id);
+
+            previous_selections
+                .iter()
+                .find(|s| s.package == *lookup_id)
+                .cloned()
+                // Use prev reason / explicit flag & new id
+                .map(|s| Selection {
+                    package: p.id.clone(),
+                    ..s
+                })
+                // Must be transitive
+                .unwrap_or(Selection {
+                    package: p.id,
+                    explicit: false,
+                    reason: None,
+                })
+        })
+        .collect::<Vec<_>>();
+
+    Ok(selections)
+}
+
+enum Resolution {
+    Explicit,
+    All,
+}
+
+/// Constraints applied when swapping in sync'd packages
+struct Sync<'a> {
+    upgrade_only: bool,
+    holds: &'a BTreeSet<String>,
+    /// Available packages replacing installed ones, by replaced name
+    replacements: &'a BTreeMap<package::Name, Package>,
+}
+
+/// Return a fully resolved package set w/ sync'd changes swapped in
+/// using the provided `packages` at the requested [`Resolution`]
+fn resolve_with_sync(
+    client: &Client,
+    resolution: Resolution,
+    sync: &Sync<'_>,
+    packages: &[Package],
+) -> Result<Vec<Package>, Error> {
+    let is_held = |p: &Package| sync.holds.contains(&p.meta.name.to_string());
+
+    let all_ids = packages.iter().map(|p| &p.id).collect::<BTreeSet<_>>();
+
+    // For each package, replace it w/ it's sync'd change (if available)
+    // or return the original package
+    let with_sync = packages
+        .iter()
+        .filter(|p| match resolution {
+            Resolution::Explicit => p.flags.explicit || is_held(p),
+            Resolution::All => true,
+        })
+        .map(|p| {
+            if is_held(p) {
+                return Ok(Cow::Borrowed(p));
+            }
+
+            if let Some(replacement) = sync.replacements.get(&p.meta.name) {
+                return Ok(Cow::Borrowed(replacement));
+            }
+
+            // Get first available = use highest priority
+            if let Some(lookup) = client
+                .registry
+                .by_name(&p.meta.name, package::Flags::new().with_available())
+                .next()
+            {
+                let upgrade_check = if sync.upgrade_only {
+                    lookup.meta.source_release > p.meta.source_release
+                } else {
+                    true
+                };
+
+                if !all_ids.contains(&lookup.id) && upgrade_check {
+                    Ok(Cow::Owned(lookup))
+                } else {
+                    Ok(Cow::Borrowed(p))
+                }
+            } else {
+                Err(Error::NameNotFound(p.meta.name.clone()))
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Build a new tx from this sync'd package set
+    let mut tx = client.registry.transaction()?;
+    tx.add(with_sync.iter().map(|p| p.id.clone()).collect())?;
+
+    // Resolve the tx
+    Ok(client.resolve_packages(tx.finalize())?)
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("unknown package name")]
+    NameNotFound(package::Name),
+
+    #[error("no installation")]
+    NoInstall,
+
+    #[error("sync would change held packages: {}", .0.join(", "))]
+    Held(Vec<String>),
+
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error("db")]
+    Db(#[from] db::Error),
+
+    #[error("transaction")]
+    Transaction(#[from] transaction::Error),
+
+    #[error("conflict")]
+    Conflict(#[from] conflict::Error),
+}
@@ -7,15 +7,12 @@ use std::{collections::BTreeSet, fmt, fs, io, path::PathBuf};
 use itertools::Itertools;
 
 use stone::{payload::layout, write::digest};
-use tui::{
-    dialoguer::{theme::ColorfulTheme, Confirm},
-    ProgressBar, ProgressStyle, Styled,
-};
+use tui::{ProgressBar, ProgressStyle, Styled};
 use vfs::tree::BlitFile;
 
 use crate::{
-    client::{self, cache},
-    package, runtime, state, Client,
+    client::{self, cache, drift},
+    package, prompt, runtime, state, Client,
 };
 
 pub fn verify(client: &Client, yes: bool, verbose: bool) -> Result<(), client::Error> {
@@ -39,7 +36,7 @@ pub fn verify(client: &Client, yes: bool, verbose: bool) -> Result<(), client::E
     let mut issues = vec![];
     let mut hasher = digest::Hasher::new();
 
-    let pb = ProgressBar::new(unique_assets.len() as u64)
+    let pb = ProgressBar::with_draw_target(Some(unique_assets.len() as u64), tui::draw_target())
         .with_message("Verifying")
         .with_style(
             ProgressStyle::with_template("\n|{bar:20.red/blue}| {pos}/{len} {wide_msg}")
@@ -119,7 +116,10 @@ pub fn verify(client: &Client, yes: bool, verbose: bool) -> Result<(), client::E
 
         let is_active = client.installation.active_state == Some(state.id);
 
-        let vfs = client.vfs(state.selections.iter().map(|s| &s.package))?;
+        let vfs = client.vfs_excluding(
+            state.selections.iter().map(|s| &s.package),
+            &client.state_db.exclusions(state.id)?,
+        )?;
 
         let base = if is_active {
             client.installation.root.join("usr")
@@ -156,8 +156,45 @@ pub fn verify(client: &Client, yes: bool, verbose: bool) -> Result<(), client::E
         }
     }
 
+    // Check the live root hasn't drifted from the active state
+    let mut untracked = vec![];
+    if let Some(active) = states
+        .iter()
+        .find(|state| client.installation.active_state == Some(state.id))
+    {
+        pb.set_message(format!("Verifying drift from state #{}", active.id));
+
+        let drift = drift::scan(client, active)?;
+
+        // Missing paths are covered above
+        issues.extend(
+            drift
+                .modified
+                .into_iter()
+                .filter(|(_, modification)| *modification != drift::Modification::Missing)
+                .map(|(path, modification)| Issue::ModifiedPath {
+                    path,
+                    modification,
+                    state: active.id,
+                }),
+        );
+        untracked = drift.untracked;
+    }
+
     pb.finish_and_clear();
 
+    if !untracked.is_empty() {
+        println!(
+            "Found {} untracked path{} in /usr, these are discarded if the active state is reblitted",
+            untracked.len(),
+            if untracked.len() == 1 { "" } else { "s" }
+        );
+
+        for path in &untracked {
+            println!(" {} {}", "?".dim(), path.display());
+        }
+    }
+
     if issues.is_empty() {
         println!("No issues found");
         return Ok(());
@@ -173,14 +210,10 @@ pub fn verify(client: &Client, yes: bool, verbose: bool) -> Result<(), client::E
         println!(" {} {issue}", "×".yellow());
     }
 
-    let result = if yes {
-        true
-    } else {
-        Confirm::with_theme(&ColorfulTheme::default())
-            .with_prompt(" Fixing issues, this will change your system state. Do you wish to continue? ")
-            .default(false)
-            .interact()?
-    };
+    let result = prompt::confirm_with(
+        " Fixing issues, this will change your system state. Do you wish to continue? ",
+        yes,
+    )?;
     if !result {
         return Err(client::Error::Cancelled);
     }
@@ -234,7 +267,10 @@ pub fn verify(client: &Client, yes: bool, verbose: bool) -> Result<(), client::E
         let is_active = client.installation.active_state == Some(state.id);
 
         // Blits to staging dir
-        let fstree = client.blit_root(state.selections.iter().map(|s| &s.package))?;
+        let fstree = client.blit_root(client.vfs_excluding(
+            state.selections.iter().map(|s| &s.package),
+            &client.state_db.exclusions(state.id)?,
+        )?)?;
 
         if is_active {
             // Override install root with the newly blitted active state
@@ -276,6 +312,11 @@ enum Issue {
         path: PathBuf,
         state: state::Id,
     },
+    ModifiedPath {
+        path: PathBuf,
+        modification: drift::Modification,
+        state: state::Id,
+    },
 }
 
 impl Issue {
@@ -283,21 +324,21 @@ impl Issue {
         match self {
             Issue::CorruptAsset { hash, .. } => Some(hash),
             Issue::MissingAsset { .. } => None,
-            Issue::MissingVFSPath { .. } => None,
+            Issue::MissingVFSPath { .. } | Issue::ModifiedPath { .. } => None,
         }
     }
 
     fn packages(&self) -> Option<&BTreeSet<package::Id>> {
         match self {
             Issue::CorruptAsset { packages, .. } | Issue::MissingAsset { packages, .. } => Some(packages),
-            Issue::MissingVFSPath { .. } => None,
+            Issue::MissingVFSPath { .. } | Issue::ModifiedPath { .. } => None,
         }
     }
 
     fn state(&self) -> Option<&state::Id> {
         match self {
             Issue::CorruptAsset { .. } | Issue::MissingAsset { .. } => None,
-            Issue::MissingVFSPath { state, .. } => Some(state),
+            Issue::MissingVFSPath { state, .. } | Issue::ModifiedPath { state, .. } => Some(state),
         }
     }
 }
@@ -308,6 +349,11 @@ impl fmt::Display for Issue {
             Issue::CorruptAsset { hash, files, .. } => write!(f, "Corrupt asset {hash} - {files:?}"),
             Issue::MissingAsset { hash, files, .. } => write!(f, "Missing asset {hash} - {files:?}"),
             Issue::MissingVFSPath { path, state } => write!(f, "Missing path {} in state #{state}", path.display()),
+            Issue::ModifiedPath {
+                path,
+                modification,
+                state,
+            } => write!(f, "Modified path {} in state #{state}, {modification}", path.display()),
         }
     }
 }
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+DROP INDEX IF EXISTS layout_path;
+
+ALTER TABLE layout DROP COLUMN path;
@@ -3,12 +3,12 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use diesel::prelude::*;
-use diesel::{Connection as _, SqliteConnection};
-use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations};
 use std::collections::BTreeSet;
 
 use stone::payload;
 
+use crate::installation::Mutability;
 use crate::package;
 
 use super::Connection;
@@ -25,15 +25,26 @@ pub struct Database {
 
 impl Database {
     pub fn new(url: &str) -> Result<Self, Error> {
-        let mut conn = SqliteConnection::establish(url)?;
-
-        conn.run_pending_migrations(MIGRATIONS).map_err(Error::Migration)?;
+        Self::open(url, Mutability::ReadWrite)
+    }
 
+    /// Open the database at `url`, read-only unless `mutability` allows writes
+    pub fn open(url: &str, mutability: Mutability) -> Result<Self, Error> {
         Ok(Database {
-            conn: Connection::new(conn),
+            conn: Connection::new(super::connect(url, mutability, MIGRATIONS)?),
         })
     }
 
+    /// Problems found by an integrity check, empty if there are none
+    pub fn check(&self) -> Result<Vec<String>, Error> {
+        self.conn.integrity_check()
+    }
+
+    /// Reclaim unused space, returning the number of bytes freed
+    pub fn vacuum(&self) -> Result<u64, Error> {
+        self.conn.vacuum()
+    }
+
     /// Retrieve all entries for a given package by ID
     pub fn query<'a>(
         &self,
@@ -61,6 +72,18 @@ impl Database {
         })
     }
 
+    /// Retrieve all packages with a layout entry at `path`, relative to `/usr`
+    pub fn owners(&self, path: &str) -> Result<Vec<(package::Id, payload::Layout)>, Error> {
+        self.conn.exec(|conn| {
+            model::layout::table
+                .select(model::Layout::as_select())
+                .filter(model::layout::path.eq(path))
+                .load_iter(conn)?
+                .map(map_layout)
+                .collect()
+        })
+    }
+
     pub fn file_hashes(&self) -> Result<BTreeSet<String>, Error> {
         self.conn.exec(|conn| {
             let hashes = model::layout::table
@@ -85,6 +108,7 @@ impl Database {
             let values = layouts
                 .into_iter()
                 .map(|(package_id, layout)| {
+                    let path = layout.entry.target().to_string();
                     let (entry_type, entry_value1, entry_value2) = encode_entry(layout.entry);
 
                     model::NewLayout {
@@ -96,6 +120,7 @@ impl Database {
                         entry_type,
                         entry_value1,
                         entry_value2,
+                        path,
                     }
                 })
                 .collect::<Vec<_>>();
@@ -208,6 +233,7 @@ mod model {
         pub entry_type: &'a str,
         pub entry_value1: Option<String>,
         pub entry_value2: Option<String>,
+        pub path: String,
     }
 }
 
@@ -241,5 +267,16 @@ mod test {
         let all = database.all().unwrap();
 
         assert_eq!(count, all.len());
+
+        let owners = database.owners("share/bash-completion/bash_completion").unwrap();
+        assert_eq!(owners.len(), 1);
+        assert_eq!(owners[0].0, package::Id::from("test".to_string()));
+
+        // Only fetched packages have layouts, so one that's merely available owns nothing
+        database.remove(&package::Id::from("test".to_string())).unwrap();
+        assert!(database
+            .owners("share/bash-completion/bash_completion")
+            .unwrap()
+            .is_empty());
     }
 }
@@ -11,5 +11,6 @@ diesel::table! {
         entry_type -> Text,
         entry_value1 -> Nullable<Text>,
         entry_value2 -> Nullable<Text>,
+        path -> Nullable<Text>,
     }
 }
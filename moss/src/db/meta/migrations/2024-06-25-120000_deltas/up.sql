@@ -0,0 +1,8 @@
+-- Your SQL goes here
+
+CREATE TABLE IF NOT EXISTS meta_deltas (
+    package TEXT NOT NULL,
+    delta TEXT NOT NULL,
+    PRIMARY KEY (package, delta),
+    FOREIGN KEY (package) REFERENCES meta(package) ON DELETE CASCADE
+);
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+
+CREATE TABLE IF NOT EXISTS meta_sideloaded (
+    package TEXT NOT NULL PRIMARY KEY,
+    source TEXT NOT NULL,
+    FOREIGN KEY (package) REFERENCES meta(package) ON DELETE CASCADE
+);
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+
+CREATE TABLE IF NOT EXISTS meta_triggers (
+    package TEXT NOT NULL,
+    scope TEXT NOT NULL,
+    definition TEXT NOT NULL,
+    PRIMARY KEY (package, scope, definition),
+    FOREIGN KEY (package) REFERENCES meta(package) ON DELETE CASCADE
+);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS meta_dependencies_dependency;
+DROP INDEX IF EXISTS meta_providers_provider;
+DROP INDEX IF EXISTS meta_name;
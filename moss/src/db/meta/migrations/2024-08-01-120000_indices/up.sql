@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+CREATE INDEX IF NOT EXISTS meta_name ON meta (name);
+CREATE INDEX IF NOT EXISTS meta_providers_provider ON meta_providers (provider);
+CREATE INDEX IF NOT EXISTS meta_dependencies_dependency ON meta_dependencies (dependency);
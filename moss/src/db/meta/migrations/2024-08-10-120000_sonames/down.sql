@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS meta_soname_dependencies;
+DROP TABLE IF EXISTS meta_soname_providers;
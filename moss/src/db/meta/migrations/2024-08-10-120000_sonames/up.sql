@@ -0,0 +1,41 @@
+-- Your SQL goes here
+
+CREATE TABLE IF NOT EXISTS meta_soname_providers (
+    package TEXT NOT NULL,
+    soname TEXT NOT NULL,
+    isa TEXT NOT NULL,
+    PRIMARY KEY (package, soname, isa),
+    FOREIGN KEY (package) REFERENCES meta(package) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS meta_soname_dependencies (
+    package TEXT NOT NULL,
+    soname TEXT NOT NULL,
+    isa TEXT NOT NULL,
+    PRIMARY KEY (package, soname, isa),
+    FOREIGN KEY (package) REFERENCES meta(package) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS meta_soname_providers_soname ON meta_soname_providers (soname);
+CREATE INDEX IF NOT EXISTS meta_soname_dependencies_soname ON meta_soname_dependencies (soname);
+
+-- Split existing `soname(libz.so.1(x86_64))` entries into soname & ISA
+INSERT OR IGNORE INTO meta_soname_providers (package, soname, isa)
+SELECT package,
+       substr(target, 1, instr(target, '(') - 1),
+       substr(target, instr(target, '(') + 1, length(target) - instr(target, '(') - 1)
+FROM (
+    SELECT package, substr(provider, 8, length(provider) - 8) AS target
+    FROM meta_providers
+    WHERE provider LIKE 'soname(%(%))'
+);
+
+INSERT OR IGNORE INTO meta_soname_dependencies (package, soname, isa)
+SELECT package,
+       substr(target, 1, instr(target, '(') - 1),
+       substr(target, instr(target, '(') + 1, length(target) - instr(target, '(') - 1)
+FROM (
+    SELECT package, substr(dependency, 8, length(dependency) - 8) AS target
+    FROM meta_dependencies
+    WHERE dependency LIKE 'soname(%(%))'
+);
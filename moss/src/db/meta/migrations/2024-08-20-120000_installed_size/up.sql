@@ -0,0 +1,3 @@
+-- Your SQL goes here
+
+ALTER TABLE meta ADD COLUMN installed_size BIGINT NULL;
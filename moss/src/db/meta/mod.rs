@@ -3,12 +3,18 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
 
 use diesel::prelude::*;
-use diesel::{Connection as _, SqliteConnection};
-use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use diesel::sql_types::Text;
+use diesel::sqlite::Sqlite;
+use diesel::{define_sql_function, Connection as _, SqliteConnection};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations};
+use regex::Regex;
 
 use crate::db::Connection;
+use crate::dependency::{self, Kind};
+use crate::installation::Mutability;
 use crate::package::{self, Meta};
 use crate::{Dependency, Provider};
 
@@ -24,7 +30,33 @@ pub enum Filter<'a> {
     Provider(Provider),
     Dependency(Dependency),
     Name(package::Name),
-    Keyword(&'a str),
+    Keyword(&'a package::Keyword),
+}
+
+define_sql_function! {
+    /// Backs the sqlite `REGEXP` operator, which has no built-in implementation
+    fn regexp(pattern: Text, value: Text) -> Bool;
+}
+
+/// Open a connection with all pending migrations applied & custom functions registered
+fn establish(url: &str, mutability: Mutability) -> Result<SqliteConnection, Error> {
+    let mut conn = super::connect(url, mutability, MIGRATIONS)?;
+
+    // The same pattern is evaluated for every row, so keep the last compiled regex around
+    let compiled = Mutex::new(None::<(String, Option<Regex>)>);
+    regexp_utils::register_impl(&mut conn, move |pattern: String, value: String| {
+        let mut compiled = compiled.lock().expect("mutex guard");
+        if compiled.as_ref().map(|(p, _)| p) != Some(&pattern) {
+            let regex = Regex::new(&pattern).ok();
+            *compiled = Some((pattern, regex));
+        }
+        compiled
+            .as_ref()
+            .and_then(|(_, regex)| regex.as_ref())
+            .is_some_and(|regex| regex.is_match(&value))
+    })?;
+
+    Ok(conn)
 }
 
 #[derive(Debug, Clone)]
@@ -34,21 +66,45 @@ pub struct Database {
 
 impl Database {
     pub fn new(url: &str) -> Result<Self, Error> {
-        let mut conn = SqliteConnection::establish(url)?;
-
-        conn.run_pending_migrations(MIGRATIONS).map_err(Error::Migration)?;
+        Self::open(url, Mutability::ReadWrite)
+    }
 
+    /// Open the database at `url`, read-only unless `mutability` allows writes
+    pub fn open(url: &str, mutability: Mutability) -> Result<Self, Error> {
         Ok(Database {
-            conn: Connection::new(conn),
+            conn: Connection::new(establish(url, mutability)?),
         })
     }
 
+    /// Reopen the database at `url`, i.e. after the file has been swapped
+    /// out. All clones of this [`Database`] will use the new connection.
+    pub fn reopen(&self, url: &str) -> Result<(), Error> {
+        self.conn.replace(establish(url, Mutability::ReadWrite)?);
+
+        Ok(())
+    }
+
+    /// Problems found by an integrity check, empty if there are none
+    pub fn check(&self) -> Result<Vec<String>, Error> {
+        self.conn.integrity_check()
+    }
+
+    /// Reclaim unused space, returning the number of bytes freed
+    pub fn vacuum(&self) -> Result<u64, Error> {
+        self.conn.vacuum()
+    }
+
     pub fn wipe(&self) -> Result<(), Error> {
         self.conn.exec(|conn| {
             // Cascading wipes other tables
             diesel::delete(model::meta::table).execute(conn)?;
-            Ok(())
-        })
+            Ok::<_, Error>(())
+        })?;
+
+        // Nothing's left, give the space back
+        self.vacuum()?;
+
+        Ok(())
     }
 
     pub fn get(&self, package: &package::Id) -> Result<Meta, Error> {
@@ -75,6 +131,21 @@ impl Database {
                 .load_iter(conn)?
                 .map(|p| Ok(p?.conflict))
                 .collect::<Result<_, Error>>()?;
+            let replaces = model::Replaces::belonging_to(&meta)
+                .select(model::Replaces::as_select())
+                .load_iter(conn)?
+                .map(|p| Ok(p?.replaces))
+                .collect::<Result<_, Error>>()?;
+            let deltas = model::Delta::belonging_to(&meta)
+                .select(model::Delta::as_select())
+                .load_iter(conn)?
+                .map(|d| Ok(d?.delta))
+                .collect::<Result<_, Error>>()?;
+            let triggers = model::Trigger::belonging_to(&meta)
+                .select(model::Trigger::as_select())
+                .load_iter(conn)?
+                .map(|t| Ok(t?.into()))
+                .collect::<Result<_, Error>>()?;
 
             Ok(Meta {
                 name: meta.name,
@@ -90,15 +161,27 @@ impl Database {
                 dependencies,
                 providers,
                 conflicts,
+                replaces,
                 uri: meta.uri,
                 hash: meta.hash,
                 download_size: meta.download_size.map(|size| size as u64),
+                installed_size: meta.installed_size.map(|size| size as u64),
+                deltas,
+                triggers,
             })
         })
     }
 
     pub fn provider_packages(&self, provider: &Provider) -> Result<Vec<package::Id>, Error> {
         self.conn.exec(|conn| {
+            if provider.kind == Kind::SharedLibrary {
+                return soname_providers(&provider.name)
+                    .distinct()
+                    .load_iter::<String, _>(conn)?
+                    .map(|result| Ok(result?.into()))
+                    .collect();
+            }
+
             model::meta_providers::table
                 .select(model::meta_providers::package)
                 .distinct()
@@ -112,124 +195,45 @@ impl Database {
         })
     }
 
+    /// All packages matching `filter`, ordered by id
     pub fn query(&self, filter: Option<Filter>) -> Result<Vec<(package::Id, Meta)>, Error> {
+        self.conn
+            .exec(|conn| load(conn, filtered(filter.as_ref()).order(model::meta::package)))
+    }
+
+    /// Up to `limit` packages matching `filter` with an id ordered after `after`.
+    /// Pass the last id of a page as `after` to fetch the next one.
+    pub fn query_page(
+        &self,
+        filter: Option<&Filter>,
+        after: Option<&package::Id>,
+        limit: usize,
+    ) -> Result<Vec<(package::Id, Meta)>, Error> {
         self.conn.exec(|conn| {
-            let map_row = |result| {
-                let meta: model::Meta = result?;
-
-                Ok((
-                    meta.package.into(),
-                    Meta {
-                        name: meta.name,
-                        version_identifier: meta.version_identifier,
-                        source_release: meta.source_release as u64,
-                        build_release: meta.build_release as u64,
-                        architecture: meta.architecture,
-                        summary: meta.summary,
-                        description: meta.description,
-                        source_id: meta.source_id,
-                        homepage: meta.homepage,
-                        licenses: Default::default(),
-                        dependencies: Default::default(),
-                        providers: Default::default(),
-                        conflicts: Default::default(),
-                        uri: meta.uri,
-                        hash: meta.hash,
-                        download_size: meta.download_size.map(|size| size as u64),
-                    },
-                ))
-            };
-
-            let mut entries: BTreeMap<package::Id, Meta> = match &filter {
-                Some(Filter::Provider(provider)) => model::meta::table
-                    .select(model::Meta::as_select())
-                    .inner_join(model::meta_providers::table)
-                    .filter(model::meta_providers::provider.eq(provider.to_string()))
-                    .load_iter::<model::Meta, _>(conn)?,
-                Some(Filter::Dependency(dependency)) => model::meta::table
-                    .select(model::Meta::as_select())
-                    .inner_join(model::meta_dependencies::table)
-                    .filter(model::meta_dependencies::dependency.eq(dependency.to_string()))
-                    .load_iter::<model::Meta, _>(conn)?,
-                Some(Filter::Name(name)) => model::meta::table
-                    .select(model::Meta::as_select())
-                    .filter(model::meta::name.eq(name.to_string()))
-                    .load_iter::<model::Meta, _>(conn)?,
-                Some(Filter::Keyword(keyword)) => {
-                    let pattern = format!("%{}%", keyword);
-                    model::meta::table
-                        .select(model::Meta::as_select())
-                        .filter(
-                            model::meta::name
-                                .like(pattern.clone())
-                                .or(model::meta::summary.like(pattern)),
-                        )
-                        .load_iter::<model::Meta, _>(conn)?
-                }
-                None => model::meta::table
-                    .select(model::Meta::as_select())
-                    .load_iter::<model::Meta, _>(conn)?,
-            }
-            .map(map_row)
-            .collect::<Result<_, Error>>()?;
-
-            let package_ids = entries
-                .keys()
-                .cloned()
-                .map(String::from)
-                .map(|id| model::PackageId { id })
-                .collect::<Vec<_>>();
+            let mut query = filtered(filter)
+                .order(model::meta::package)
+                .limit(limit.try_into().unwrap_or(i64::MAX));
 
-            for chunk in package_ids.chunks(MAX_VARIABLE_NUMBER) {
-                // Add licenses
-                model::License::belonging_to(chunk)
-                    .load_iter::<model::License, _>(conn)?
-                    .try_for_each::<_, Result<_, Error>>(|result| {
-                        let row = result?;
-                        if let Some(meta) = entries.get_mut(&row.package.into()) {
-                            meta.licenses.push(row.license);
-                        }
-                        Ok(())
-                    })?;
-
-                // Add dependencies
-                model::Dependency::belonging_to(chunk)
-                    .load_iter::<model::Dependency, _>(conn)?
-                    .try_for_each::<_, Result<_, Error>>(|result| {
-                        let row = result?;
-                        if let Some(meta) = entries.get_mut(&row.package.into()) {
-                            meta.dependencies.insert(row.dependency);
-                        }
-                        Ok(())
-                    })?;
-
-                // Add providers
-                model::Provider::belonging_to(chunk)
-                    .load_iter::<model::Provider, _>(conn)?
-                    .try_for_each::<_, Result<_, Error>>(|result| {
-                        let row = result?;
-                        if let Some(meta) = entries.get_mut(&row.package.into()) {
-                            meta.providers.insert(row.provider);
-                        }
-                        Ok(())
-                    })?;
-
-                // Add conflicts
-                model::Conflict::belonging_to(chunk)
-                    .load_iter::<model::Conflict, _>(conn)?
-                    .try_for_each::<_, Result<_, Error>>(|result| {
-                        let row = result?;
-                        if let Some(meta) = entries.get_mut(&row.package.into()) {
-                            meta.conflicts.insert(row.conflict);
-                        }
-                        Ok(())
-                    })?;
+            if let Some(after) = after {
+                query = query.filter(model::meta::package.gt(after.to_string()));
             }
 
-            Ok(entries.into_iter().collect())
+            load(conn, query)
         })
     }
 
+    /// Iterate all packages matching `filter` in pages of `page_size`,
+    /// so only a single page is held in memory at once
+    pub fn query_pages<'a>(&'a self, filter: Option<Filter<'a>>, page_size: usize) -> Pages<'a> {
+        Pages {
+            db: self,
+            filter,
+            after: None,
+            page_size,
+            done: false,
+        }
+    }
+
     pub fn file_hashes(&self) -> Result<BTreeSet<String>, Error> {
         self.conn.exec(|conn| {
             Ok(model::meta::table
@@ -246,89 +250,71 @@ impl Database {
     }
 
     pub fn batch_add(&self, packages: Vec<(package::Id, Meta)>) -> Result<(), Error> {
+        self.conn
+            .exec(|conn| conn.transaction(|conn| batch_add_impl(&packages, conn)))
+    }
+
+    /// Replace all packages in the database with those yielded by `batches`
+    /// in a single transaction.
+    ///
+    /// If any batch fails to be produced or stored, the transaction is rolled
+    /// back and the database retains its previous contents.
+    pub fn replace_all<E>(
+        &self,
+        batches: impl IntoIterator<Item = Result<Vec<(package::Id, Meta)>, E>>,
+    ) -> Result<(), E>
+    where
+        E: From<Error>,
+    {
         self.conn.exec(|conn| {
-            let ids = packages.iter().map(|(id, _)| id.as_ref()).collect::<Vec<_>>();
-            let entries = packages
-                .iter()
-                .map(|(package, meta)| model::NewMeta {
-                    package: package.as_ref(),
-                    name: meta.name.as_ref(),
-                    version_identifier: &meta.version_identifier,
-                    source_release: meta.source_release as i32,
-                    build_release: meta.build_release as i32,
-                    architecture: &meta.architecture,
-                    summary: &meta.summary,
-                    description: &meta.description,
-                    source_id: &meta.source_id,
-                    homepage: &meta.homepage,
-                    uri: meta.uri.as_deref(),
-                    hash: meta.hash.as_deref(),
-                    download_size: meta.download_size.map(|size| size as i64),
-                })
-                .collect::<Vec<_>>();
-            let licenses = packages
-                .iter()
-                .flat_map(|(package, meta)| {
-                    meta.licenses.iter().map(|license| {
-                        (
-                            model::meta_licenses::package.eq(<package::Id as AsRef<str>>::as_ref(package)),
-                            model::meta_licenses::license.eq(license),
-                        )
-                    })
-                })
-                .collect::<Vec<_>>();
-            let dependencies = packages
-                .iter()
-                .flat_map(|(package, meta)| {
-                    meta.dependencies.iter().map(|dependency| {
-                        (
-                            model::meta_dependencies::package.eq(<package::Id as AsRef<str>>::as_ref(package)),
-                            model::meta_dependencies::dependency.eq(dependency.to_string()),
-                        )
-                    })
-                })
-                .collect::<Vec<_>>();
-            let providers = packages
-                .iter()
-                .flat_map(|(package, meta)| {
-                    meta.providers.iter().map(|provider| {
-                        (
-                            model::meta_providers::package.eq(<package::Id as AsRef<str>>::as_ref(package)),
-                            model::meta_providers::provider.eq(provider.to_string()),
-                        )
-                    })
-                })
-                .collect::<Vec<_>>();
-            let conflicts = packages
-                .iter()
-                .flat_map(|(package, meta)| {
-                    meta.conflicts.iter().map(|conflict| {
-                        (
-                            model::meta_conflicts::package.eq(<package::Id as AsRef<str>>::as_ref(package)),
-                            model::meta_conflicts::conflict.eq(conflict.to_string()),
-                        )
-                    })
-                })
-                .collect::<Vec<_>>();
+            let mut batch_error = None;
+
+            let result = conn.transaction(|conn| {
+                // Cascading wipes other tables
+                diesel::delete(model::meta::table).execute(conn)?;
+
+                for batch in batches {
+                    match batch {
+                        Ok(packages) => batch_add_impl(&packages, conn)?,
+                        Err(error) => {
+                            batch_error = Some(error);
+                            return Err(Error::Diesel(diesel::result::Error::RollbackTransaction));
+                        }
+                    }
+                }
 
-            conn.transaction(|conn| {
-                batch_remove_impl(&ids, conn)?;
-
-                diesel::insert_into(model::meta::table).values(entries).execute(conn)?;
-                diesel::insert_into(model::meta_licenses::table)
-                    .values(licenses)
-                    .execute(conn)?;
-                diesel::insert_into(model::meta_dependencies::table)
-                    .values(dependencies)
-                    .execute(conn)?;
-                diesel::insert_into(model::meta_providers::table)
-                    .values(providers)
-                    .execute(conn)?;
-                diesel::insert_into(model::meta_conflicts::table)
-                    .values(conflicts)
-                    .execute(conn)?;
                 Ok(())
-            })
+            });
+
+            match batch_error {
+                Some(error) => Err(error),
+                None => result.map_err(E::from),
+            }
+        })
+    }
+
+    /// Record that `package` was sideloaded from `source`, a local path or URL,
+    /// rather than installed from a repository. Cleared once it's removed.
+    pub fn set_sideloaded(&self, package: &package::Id, source: &str) -> Result<(), Error> {
+        self.conn.exec(|conn| {
+            diesel::replace_into(model::meta_sideloaded::table)
+                .values((
+                    model::meta_sideloaded::package.eq(<package::Id as AsRef<str>>::as_ref(package)),
+                    model::meta_sideloaded::source.eq(source),
+                ))
+                .execute(conn)?;
+            Ok(())
+        })
+    }
+
+    /// The source `package` was sideloaded from, if any
+    pub fn sideloaded(&self, package: &package::Id) -> Result<Option<String>, Error> {
+        self.conn.exec(|conn| {
+            Ok(model::meta_sideloaded::table
+                .select(model::meta_sideloaded::source)
+                .find(<package::Id as AsRef<str>>::as_ref(package))
+                .first::<String>(conn)
+                .optional()?)
         })
     }
 
@@ -348,6 +334,420 @@ impl Database {
     }
 }
 
+/// Packages matching `filter`, without their relations
+fn filtered<'a>(filter: Option<&'a Filter<'_>>) -> model::meta::BoxedQuery<'a, Sqlite> {
+    let query = model::meta::table.into_boxed();
+
+    match filter {
+        Some(Filter::Provider(provider)) if provider.kind == Kind::SharedLibrary => {
+            query.filter(model::meta::package.eq_any(soname_providers(&provider.name)))
+        }
+        Some(Filter::Dependency(dependency)) if dependency.kind == Kind::SharedLibrary => {
+            query.filter(model::meta::package.eq_any(soname_dependencies(&dependency.name)))
+        }
+        Some(Filter::Provider(provider)) => query.filter(
+            model::meta::package.eq_any(
+                model::meta_providers::table
+                    .select(model::meta_providers::package)
+                    .filter(model::meta_providers::provider.eq(provider.to_string())),
+            ),
+        ),
+        Some(Filter::Dependency(dependency)) => query.filter(
+            model::meta::package.eq_any(
+                model::meta_dependencies::table
+                    .select(model::meta_dependencies::package)
+                    .filter(model::meta_dependencies::dependency.eq(dependency.to_string())),
+            ),
+        ),
+        Some(Filter::Name(name)) => query.filter(model::meta::name.eq(name.to_string())),
+        Some(Filter::Keyword(package::Keyword::Substring(keyword))) => {
+            let pattern = format!("%{}%", keyword);
+            query.filter(
+                model::meta::name
+                    .like(pattern.clone())
+                    .or(model::meta::summary.like(pattern.clone()))
+                    .or(model::meta::description.like(pattern.clone()))
+                    .or(model::meta::package.eq_any(
+                        model::meta_providers::table
+                            .select(model::meta_providers::package)
+                            .filter(model::meta_providers::provider.like(pattern)),
+                    )),
+            )
+        }
+        Some(Filter::Keyword(package::Keyword::Regex(regex))) => {
+            let pattern = regex.as_str();
+            query.filter(
+                regexp(pattern, model::meta::name)
+                    .or(regexp(pattern, model::meta::summary))
+                    .or(regexp(pattern, model::meta::description))
+                    .or(model::meta::package.eq_any(
+                        model::meta_providers::table
+                            .select(model::meta_providers::package)
+                            .filter(regexp(pattern, model::meta_providers::provider)),
+                    )),
+            )
+        }
+        None => query,
+    }
+}
+
+/// Packages providing the soname `target`, on any ISA unless it names one
+fn soname_providers(target: &str) -> model::meta_soname_providers::BoxedQuery<'_, Sqlite, Text> {
+    let (soname, isa) = dependency::split_soname(target);
+    let query = model::meta_soname_providers::table
+        .select(model::meta_soname_providers::package)
+        .filter(model::meta_soname_providers::soname.eq(soname))
+        .into_boxed();
+
+    match isa {
+        Some(isa) => query.filter(model::meta_soname_providers::isa.eq(isa)),
+        None => query,
+    }
+}
+
+/// Packages depending on the soname `target`, on any ISA unless it names one
+fn soname_dependencies(target: &str) -> model::meta_soname_dependencies::BoxedQuery<'_, Sqlite, Text> {
+    let (soname, isa) = dependency::split_soname(target);
+    let query = model::meta_soname_dependencies::table
+        .select(model::meta_soname_dependencies::package)
+        .filter(model::meta_soname_dependencies::soname.eq(soname))
+        .into_boxed();
+
+    match isa {
+        Some(isa) => query.filter(model::meta_soname_dependencies::isa.eq(isa)),
+        None => query,
+    }
+}
+
+/// Load the packages selected by `query` along with all their relations
+fn load(
+    conn: &mut SqliteConnection,
+    query: model::meta::BoxedQuery<'_, Sqlite>,
+) -> Result<Vec<(package::Id, Meta)>, Error> {
+    let mut entries = query
+        .load_iter::<model::Meta, _>(conn)?
+        .map(|result| {
+            let meta = result?;
+
+            Ok((
+                meta.package.into(),
+                Meta {
+                    name: meta.name,
+                    version_identifier: meta.version_identifier,
+                    source_release: meta.source_release as u64,
+                    build_release: meta.build_release as u64,
+                    architecture: meta.architecture,
+                    summary: meta.summary,
+                    description: meta.description,
+                    source_id: meta.source_id,
+                    homepage: meta.homepage,
+                    licenses: Default::default(),
+                    dependencies: Default::default(),
+                    providers: Default::default(),
+                    conflicts: Default::default(),
+                    replaces: Default::default(),
+                    uri: meta.uri,
+                    hash: meta.hash,
+                    download_size: meta.download_size.map(|size| size as u64),
+                    installed_size: meta.installed_size.map(|size| size as u64),
+                    deltas: Default::default(),
+                    triggers: Default::default(),
+                },
+            ))
+        })
+        .collect::<Result<BTreeMap<package::Id, Meta>, Error>>()?;
+
+    let package_ids = entries
+        .keys()
+        .cloned()
+        .map(String::from)
+        .map(|id| model::PackageId { id })
+        .collect::<Vec<_>>();
+
+    for chunk in package_ids.chunks(MAX_VARIABLE_NUMBER) {
+        // Add licenses
+        model::License::belonging_to(chunk)
+            .load_iter::<model::License, _>(conn)?
+            .try_for_each::<_, Result<_, Error>>(|result| {
+                let row = result?;
+                if let Some(meta) = entries.get_mut(&row.package.into()) {
+                    meta.licenses.push(row.license);
+                }
+                Ok(())
+            })?;
+
+        // Add dependencies
+        model::Dependency::belonging_to(chunk)
+            .load_iter::<model::Dependency, _>(conn)?
+            .try_for_each::<_, Result<_, Error>>(|result| {
+                let row = result?;
+                if let Some(meta) = entries.get_mut(&row.package.into()) {
+                    meta.dependencies.insert(row.dependency);
+                }
+                Ok(())
+            })?;
+
+        // Add providers
+        model::Provider::belonging_to(chunk)
+            .load_iter::<model::Provider, _>(conn)?
+            .try_for_each::<_, Result<_, Error>>(|result| {
+                let row = result?;
+                if let Some(meta) = entries.get_mut(&row.package.into()) {
+                    meta.providers.insert(row.provider);
+                }
+                Ok(())
+            })?;
+
+        // Add conflicts
+        model::Conflict::belonging_to(chunk)
+            .load_iter::<model::Conflict, _>(conn)?
+            .try_for_each::<_, Result<_, Error>>(|result| {
+                let row = result?;
+                if let Some(meta) = entries.get_mut(&row.package.into()) {
+                    meta.conflicts.insert(row.conflict);
+                }
+                Ok(())
+            })?;
+
+        // Add replaces
+        model::Replaces::belonging_to(chunk)
+            .load_iter::<model::Replaces, _>(conn)?
+            .try_for_each::<_, Result<_, Error>>(|result| {
+                let row = result?;
+                if let Some(meta) = entries.get_mut(&row.package.into()) {
+                    meta.replaces.insert(row.replaces);
+                }
+                Ok(())
+            })?;
+
+        // Add deltas
+        model::Delta::belonging_to(chunk)
+            .load_iter::<model::Delta, _>(conn)?
+            .try_for_each::<_, Result<_, Error>>(|result| {
+                let row = result?;
+                if let Some(meta) = entries.get_mut(&row.package.into()) {
+                    meta.deltas.insert(row.delta);
+                }
+                Ok(())
+            })?;
+
+        // Add triggers
+        model::Trigger::belonging_to(chunk)
+            .load_iter::<model::Trigger, _>(conn)?
+            .try_for_each::<_, Result<_, Error>>(|result| {
+                let row = result?;
+                if let Some(meta) = entries.get_mut(&row.package.clone().into()) {
+                    meta.triggers.insert(row.into());
+                }
+                Ok(())
+            })?;
+    }
+
+    Ok(entries.into_iter().collect())
+}
+
+/// Pages of packages, see [`Database::query_pages`]
+pub struct Pages<'a> {
+    db: &'a Database,
+    filter: Option<Filter<'a>>,
+    after: Option<package::Id>,
+    page_size: usize,
+    done: bool,
+}
+
+impl Iterator for Pages<'_> {
+    type Item = Result<Vec<(package::Id, Meta)>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self
+            .db
+            .query_page(self.filter.as_ref(), self.after.as_ref(), self.page_size)
+        {
+            Ok(page) => {
+                // A short page is the last one
+                self.done = page.len() < self.page_size;
+                self.after = page.last().map(|(id, _)| id.clone());
+
+                (!page.is_empty()).then_some(Ok(page))
+            }
+            Err(error) => {
+                self.done = true;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+fn batch_add_impl(packages: &[(package::Id, Meta)], conn: &mut SqliteConnection) -> Result<(), Error> {
+    let ids = packages.iter().map(|(id, _)| id.as_ref()).collect::<Vec<_>>();
+    let entries = packages
+        .iter()
+        .map(|(package, meta)| model::NewMeta {
+            package: package.as_ref(),
+            name: meta.name.as_ref(),
+            version_identifier: &meta.version_identifier,
+            source_release: meta.source_release as i32,
+            build_release: meta.build_release as i32,
+            architecture: &meta.architecture,
+            summary: &meta.summary,
+            description: &meta.description,
+            source_id: &meta.source_id,
+            homepage: &meta.homepage,
+            uri: meta.uri.as_deref(),
+            hash: meta.hash.as_deref(),
+            download_size: meta.download_size.map(|size| size as i64),
+            installed_size: meta.installed_size.map(|size| size as i64),
+        })
+        .collect::<Vec<_>>();
+    let licenses = packages
+        .iter()
+        .flat_map(|(package, meta)| {
+            meta.licenses.iter().map(|license| {
+                (
+                    model::meta_licenses::package.eq(<package::Id as AsRef<str>>::as_ref(package)),
+                    model::meta_licenses::license.eq(license),
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    let dependencies = packages
+        .iter()
+        .flat_map(|(package, meta)| {
+            meta.dependencies.iter().map(|dependency| {
+                (
+                    model::meta_dependencies::package.eq(<package::Id as AsRef<str>>::as_ref(package)),
+                    model::meta_dependencies::dependency.eq(dependency.to_string()),
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    let providers = packages
+        .iter()
+        .flat_map(|(package, meta)| {
+            meta.providers.iter().map(|provider| {
+                (
+                    model::meta_providers::package.eq(<package::Id as AsRef<str>>::as_ref(package)),
+                    model::meta_providers::provider.eq(provider.to_string()),
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    // Sonames are also split out, so they can be looked up regardless of ISA
+    let soname_providers = packages
+        .iter()
+        .flat_map(|(package, meta)| {
+            meta.providers
+                .iter()
+                .filter(|provider| provider.kind == Kind::SharedLibrary)
+                .map(|provider| {
+                    let (soname, isa) = dependency::split_soname(&provider.name);
+                    (
+                        model::meta_soname_providers::package.eq(<package::Id as AsRef<str>>::as_ref(package)),
+                        model::meta_soname_providers::soname.eq(soname),
+                        model::meta_soname_providers::isa.eq(isa.unwrap_or_default()),
+                    )
+                })
+        })
+        .collect::<Vec<_>>();
+    let soname_dependencies = packages
+        .iter()
+        .flat_map(|(package, meta)| {
+            meta.dependencies
+                .iter()
+                .filter(|dependency| dependency.kind == Kind::SharedLibrary)
+                .map(|dependency| {
+                    let (soname, isa) = dependency::split_soname(&dependency.name);
+                    (
+                        model::meta_soname_dependencies::package.eq(<package::Id as AsRef<str>>::as_ref(package)),
+                        model::meta_soname_dependencies::soname.eq(soname),
+                        model::meta_soname_dependencies::isa.eq(isa.unwrap_or_default()),
+                    )
+                })
+        })
+        .collect::<Vec<_>>();
+    let conflicts = packages
+        .iter()
+        .flat_map(|(package, meta)| {
+            meta.conflicts.iter().map(|conflict| {
+                (
+                    model::meta_conflicts::package.eq(<package::Id as AsRef<str>>::as_ref(package)),
+                    model::meta_conflicts::conflict.eq(conflict.to_string()),
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    let replaces = packages
+        .iter()
+        .flat_map(|(package, meta)| {
+            meta.replaces.iter().map(|replaces| {
+                (
+                    model::meta_replaces::package.eq(<package::Id as AsRef<str>>::as_ref(package)),
+                    model::meta_replaces::replaces.eq(replaces.to_string()),
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    let deltas = packages
+        .iter()
+        .flat_map(|(package, meta)| {
+            meta.deltas.iter().map(|delta| {
+                (
+                    model::meta_deltas::package.eq(<package::Id as AsRef<str>>::as_ref(package)),
+                    model::meta_deltas::delta.eq(delta.to_string()),
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    let triggers = packages
+        .iter()
+        .flat_map(|(package, meta)| {
+            meta.triggers.iter().map(|trigger| {
+                (
+                    model::meta_triggers::package.eq(<package::Id as AsRef<str>>::as_ref(package)),
+                    model::meta_triggers::scope.eq(trigger.scope.to_string()),
+                    model::meta_triggers::definition.eq(&trigger.definition),
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+
+    batch_remove_impl(&ids, conn)?;
+
+    diesel::insert_into(model::meta::table).values(entries).execute(conn)?;
+    diesel::insert_into(model::meta_licenses::table)
+        .values(licenses)
+        .execute(conn)?;
+    diesel::insert_into(model::meta_dependencies::table)
+        .values(dependencies)
+        .execute(conn)?;
+    diesel::insert_into(model::meta_providers::table)
+        .values(providers)
+        .execute(conn)?;
+    diesel::insert_into(model::meta_soname_providers::table)
+        .values(soname_providers)
+        .execute(conn)?;
+    diesel::insert_into(model::meta_soname_dependencies::table)
+        .values(soname_dependencies)
+        .execute(conn)?;
+    diesel::insert_into(model::meta_conflicts::table)
+        .values(conflicts)
+        .execute(conn)?;
+    diesel::insert_into(model::meta_replaces::table)
+        .values(replaces)
+        .execute(conn)?;
+    diesel::insert_into(model::meta_deltas::table)
+        .values(deltas)
+        .execute(conn)?;
+    diesel::insert_into(model::meta_triggers::table)
+        .values(triggers)
+        .execute(conn)?;
+    Ok(())
+}
+
 fn batch_remove_impl(packages: &[&str], conn: &mut SqliteConnection) -> Result<(), Error> {
     diesel::delete(model::meta::table.filter(model::meta::package.eq_any(packages))).execute(conn)?;
     Ok(())
@@ -361,7 +761,10 @@ mod model {
         Selectable,
     };
 
-    pub use crate::db::meta::schema::{meta, meta_conflicts, meta_dependencies, meta_licenses, meta_providers};
+    pub use crate::db::meta::schema::{
+        meta, meta_conflicts, meta_deltas, meta_dependencies, meta_licenses, meta_providers, meta_replaces,
+        meta_sideloaded, meta_soname_dependencies, meta_soname_providers, meta_triggers,
+    };
     use crate::package;
 
     #[derive(Queryable, Selectable, Identifiable)]
@@ -382,6 +785,7 @@ mod model {
         pub uri: Option<String>,
         pub hash: Option<String>,
         pub download_size: Option<i64>,
+        pub installed_size: Option<i64>,
     }
 
     #[derive(Queryable, Selectable, Identifiable)]
@@ -435,6 +839,49 @@ mod model {
         pub conflict: crate::Provider,
     }
 
+    #[derive(Queryable, Selectable, Identifiable, Associations)]
+    #[diesel(table_name = meta_replaces)]
+    #[diesel(primary_key(package, replaces))]
+    #[diesel(belongs_to(Meta, foreign_key = package))]
+    #[diesel(belongs_to(PackageId, foreign_key = package))]
+    pub struct Replaces {
+        pub package: String,
+        #[diesel(deserialize_as = String)]
+        pub replaces: crate::Provider,
+    }
+
+    #[derive(Queryable, Selectable, Identifiable, Associations)]
+    #[diesel(table_name = meta_deltas)]
+    #[diesel(primary_key(package, delta))]
+    #[diesel(belongs_to(Meta, foreign_key = package))]
+    #[diesel(belongs_to(PackageId, foreign_key = package))]
+    pub struct Delta {
+        pub package: String,
+        #[diesel(deserialize_as = String)]
+        pub delta: package::Delta,
+    }
+
+    #[derive(Queryable, Selectable, Identifiable, Associations)]
+    #[diesel(table_name = meta_triggers)]
+    #[diesel(primary_key(package, scope, definition))]
+    #[diesel(belongs_to(Meta, foreign_key = package))]
+    #[diesel(belongs_to(PackageId, foreign_key = package))]
+    pub struct Trigger {
+        pub package: String,
+        #[diesel(deserialize_as = String)]
+        pub scope: package::TriggerScope,
+        pub definition: String,
+    }
+
+    impl From<Trigger> for package::Trigger {
+        fn from(trigger: Trigger) -> Self {
+            Self {
+                scope: trigger.scope,
+                definition: trigger.definition,
+            }
+        }
+    }
+
     #[derive(Insertable)]
     #[diesel(table_name = meta)]
     pub struct NewMeta<'a> {
@@ -451,6 +898,7 @@ mod model {
         pub uri: Option<&'a str>,
         pub hash: Option<&'a str>,
         pub download_size: Option<i64>,
+        pub installed_size: Option<i64>,
     }
 }
 
@@ -458,21 +906,26 @@ mod model {
 mod test {
     use stone::read::PayloadKind;
 
-    use crate::dependency::Kind;
-
     use super::*;
 
+    /// Decode the meta payload of the stone `bytes`
+    fn read_meta(bytes: &[u8]) -> Meta {
+        let mut stone = stone::read_bytes(bytes).unwrap();
+
+        let payloads = stone.payloads().unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+        let meta_payload = payloads.iter().find_map(PayloadKind::meta).unwrap();
+        Meta::from_stone_payload(&meta_payload.body).unwrap()
+    }
+
+    fn bash_completion() -> Meta {
+        read_meta(include_bytes!("../../../../test/bash-completion-2.11-1-1-x86_64.stone"))
+    }
+
     #[test]
     fn create_insert_select() {
         let db = Database::new(":memory:").unwrap();
 
-        let bash_completion = include_bytes!("../../../../test/bash-completion-2.11-1-1-x86_64.stone");
-
-        let mut stone = stone::read_bytes(bash_completion).unwrap();
-
-        let payloads = stone.payloads().unwrap().collect::<Result<Vec<_>, _>>().unwrap();
-        let meta_payload = payloads.iter().find_map(PayloadKind::meta).unwrap();
-        let meta = Meta::from_stone_payload(&meta_payload.body).unwrap();
+        let meta = bash_completion();
 
         let id = package::Id::from("test".to_string());
 
@@ -501,6 +954,256 @@ mod test {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn check_and_vacuum() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("meta-vacuum.db");
+        let db = Database::new(path.to_str().unwrap()).unwrap();
+
+        let meta = bash_completion();
+
+        db.batch_add(
+            (0..500)
+                .map(|i| (package::Id::from(format!("test{i}")), meta.clone()))
+                .collect(),
+        )
+        .unwrap();
+        assert!(db.check().unwrap().is_empty());
+
+        db.batch_remove(
+            &(0..500)
+                .map(|i| package::Id::from(format!("test{i}")))
+                .collect::<Vec<_>>(),
+        )
+        .unwrap();
+        assert!(db.vacuum().unwrap() > 0);
+        assert_eq!(db.vacuum().unwrap(), 0);
+        assert!(db.check().unwrap().is_empty());
+
+        drop(db);
+    }
+
+    #[test]
+    fn keyword_query() {
+        let db = Database::new(":memory:").unwrap();
+
+        let meta = bash_completion();
+
+        db.add(package::Id::from("test".to_string()), meta).unwrap();
+
+        let query = |keyword: package::Keyword| db.query(Some(Filter::Keyword(&keyword))).unwrap().len();
+
+        assert_eq!(query(package::Keyword::substring("COMPLETION")), 1);
+        assert_eq!(query(package::Keyword::substring("zsh")), 0);
+        assert_eq!(query(package::Keyword::regex("^bash-c.*n$").unwrap()), 1);
+        assert_eq!(query(package::Keyword::regex("^completion").unwrap()), 0);
+        // Providers are searched too
+        assert_eq!(query(package::Keyword::substring("CMAKE(")), 1);
+        assert_eq!(query(package::Keyword::regex(r"^cmake\(bash").unwrap()), 1);
+    }
+
+    #[test]
+    fn paged_query() {
+        let db = Database::new(":memory:").unwrap();
+
+        let meta = bash_completion();
+
+        let ids = ["e", "a", "d", "b", "c"].map(|id| package::Id::from(id.to_string()));
+        db.batch_add(ids.iter().map(|id| (id.clone(), meta.clone())).collect())
+            .unwrap();
+
+        let pages = db
+            .query_pages(None, 2)
+            .map(|page| {
+                page.unwrap()
+                    .into_iter()
+                    .map(|(id, _)| String::from(id))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(pages, vec![vec!["a", "b"], vec!["c", "d"], vec!["e"]]);
+
+        // Relations are loaded for each page
+        let page = db
+            .query_page(
+                Some(&Filter::Name("bash-completion".to_string().into())),
+                Some(&ids[3]),
+                2,
+            )
+            .unwrap();
+        assert_eq!(page.len(), 2);
+        assert!(page.iter().all(|(_, meta)| !meta.providers.is_empty()));
+        assert_eq!(String::from(page[0].0.clone()), "c");
+
+        assert_eq!(db.query_pages(None, 5).count(), 1);
+    }
+
+    #[test]
+    fn replace_all_rolls_back_on_failure() {
+        let db = Database::new(":memory:").unwrap();
+
+        let meta = bash_completion();
+
+        let old = package::Id::from("old".to_string());
+        let new = package::Id::from("new".to_string());
+
+        db.add(old.clone(), meta.clone()).unwrap();
+
+        // Failed reload keeps previous contents
+        let result = db.replace_all([Ok(vec![(new.clone(), meta.clone())]), Err(Error::RowNotFound)]);
+        assert!(result.is_err());
+        assert!(db.get(&old).is_ok());
+        assert!(db.get(&new).is_err());
+
+        // Successful reload replaces them
+        db.replace_all::<Error>([Ok(vec![(new.clone(), meta.clone())])])
+            .unwrap();
+        assert!(db.get(&old).is_err());
+        assert!(db.get(&new).is_ok());
+    }
+
+    #[test]
+    fn deltas_round_trip() {
+        let db = Database::new(":memory:").unwrap();
+
+        let mut meta = bash_completion();
+
+        let delta = package::Delta {
+            from: "0123abcd".into(),
+            hash: "4567ef01".into(),
+            size: 1024,
+            uri: "b/bash-completion/bash-completion-2.11-1-1-x86_64.delta.stone".into(),
+        };
+        meta.deltas.insert(delta.clone());
+
+        // Survives the index encoding
+        let encoded = Meta::from_stone_payload(&meta.clone().to_stone_payload()).unwrap();
+        assert_eq!(encoded.deltas, meta.deltas);
+
+        let id = package::Id::from(meta.id());
+        db.add(id.clone(), meta).unwrap();
+
+        assert_eq!(
+            db.get(&id).unwrap().deltas.into_iter().collect::<Vec<_>>(),
+            vec![delta.clone()]
+        );
+        assert_eq!(
+            db.query(None).unwrap()[0].1.deltas.iter().collect::<Vec<_>>(),
+            vec![&delta]
+        );
+    }
+
+    #[test]
+    fn sizes_round_trip() {
+        let db = Database::new(":memory:").unwrap();
+
+        let mut meta = bash_completion();
+        assert_eq!(meta.installed_size, None);
+
+        meta.download_size = Some(1024);
+        meta.installed_size = Some(4096);
+
+        // Survives the index encoding
+        let encoded = Meta::from_stone_payload(&meta.clone().to_stone_payload()).unwrap();
+        assert_eq!(encoded.installed_size, Some(4096));
+
+        let id = package::Id::from(meta.id());
+        db.add(id.clone(), meta).unwrap();
+
+        let stored = db.get(&id).unwrap();
+        assert_eq!(stored.download_size, Some(1024));
+        assert_eq!(stored.installed_size, Some(4096));
+    }
+
+    #[test]
+    fn triggers_round_trip() {
+        let db = Database::new(":memory:").unwrap();
+
+        let mut meta = bash_completion();
+
+        let trigger = package::Trigger {
+            scope: package::TriggerScope::System,
+            definition: "name: bash-completion\n".into(),
+        };
+        meta.triggers.insert(trigger.clone());
+
+        // Survives the stone encoding
+        let encoded = Meta::from_stone_payload(&meta.clone().to_stone_payload()).unwrap();
+        assert_eq!(encoded.triggers, meta.triggers);
+
+        let id = package::Id::from(meta.id());
+        db.add(id.clone(), meta).unwrap();
+
+        assert_eq!(
+            db.get(&id).unwrap().triggers.into_iter().collect::<Vec<_>>(),
+            vec![trigger.clone()]
+        );
+        assert_eq!(
+            db.query(None).unwrap()[0].1.triggers.iter().collect::<Vec<_>>(),
+            vec![&trigger]
+        );
+    }
+
+    #[test]
+    fn sideloaded() {
+        let db = Database::new(":memory:").unwrap();
+
+        let meta = bash_completion();
+
+        let id = package::Id::from(meta.id());
+        db.add(id.clone(), meta.clone()).unwrap();
+        assert_eq!(db.sideloaded(&id).unwrap(), None);
+
+        db.set_sideloaded(&id, "/tmp/bash-completion.stone").unwrap();
+        assert_eq!(
+            db.sideloaded(&id).unwrap().as_deref(),
+            Some("/tmp/bash-completion.stone")
+        );
+
+        // Re-adding the package, i.e. from a repository, clears it
+        db.add(id.clone(), meta).unwrap();
+        assert_eq!(db.sideloaded(&id).unwrap(), None);
+    }
+
+    #[test]
+    fn soname_lookup() {
+        let db = Database::new(":memory:").unwrap();
+
+        let mut meta = bash_completion();
+        meta.providers
+            .insert(Provider::from_name("soname(libfoo.so.1(x86_64))").unwrap());
+        meta.dependencies
+            .insert(Dependency::from_name("soname(libc.so.6(x86_64))").unwrap());
+
+        let id = package::Id::from(meta.id());
+        db.add(id.clone(), meta).unwrap();
+
+        let providers = |name| {
+            let provider = Provider::from_name(name).unwrap();
+            let query = db.query(Some(Filter::Provider(provider.clone()))).unwrap();
+            let ids = db.provider_packages(&provider).unwrap();
+            assert_eq!(query.len(), ids.len());
+            ids
+        };
+
+        // With or without the ISA
+        assert_eq!(providers("soname(libfoo.so.1(x86_64))"), vec![id.clone()]);
+        assert_eq!(providers("soname(libfoo.so.1)"), vec![id.clone()]);
+        assert!(providers("soname(libfoo.so.1(aarch64))").is_empty());
+        assert!(providers("soname(libfoo.so.2)").is_empty());
+
+        let dependents = db
+            .query(Some(Filter::Dependency(
+                Dependency::from_name("soname(libc.so.6)").unwrap(),
+            )))
+            .unwrap();
+        assert_eq!(dependents.len(), 1);
+
+        // Removed along with the package
+        db.remove(&id).unwrap();
+        assert!(providers("soname(libfoo.so.1)").is_empty());
+    }
+
     #[test]
     fn test_conflict_is_recognized() {
         let db = Database::new(":memory:").unwrap();
@@ -513,11 +1216,7 @@ mod test {
             name: "pineapple".to_string(),
         };
 
-        let mut stone = stone::read_bytes(italian_pizza).unwrap();
-
-        let payloads = stone.payloads().unwrap().collect::<Result<Vec<_>, _>>().unwrap();
-        let meta_payload = payloads.iter().find_map(PayloadKind::meta).unwrap();
-        let meta = Meta::from_stone_payload(&meta_payload.body).unwrap();
+        let meta = read_meta(italian_pizza);
         db.add(package::Id::from(meta.id()), meta.clone()).unwrap();
 
         // Ensure we're parsing the correct package!
@@ -542,4 +1241,38 @@ mod test {
         // correctly.
         assert_eq!(retrieved_conflicts, vec![&pineapple_provider]);
     }
+
+    #[test]
+    fn migrate_existing() {
+        use diesel_migrations::MigrationHarness;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("meta.db");
+        let url = path.to_str().unwrap();
+
+        // A database created before any later migrations existed
+        let mut conn = SqliteConnection::establish(url).unwrap();
+        conn.run_next_migration(MIGRATIONS).unwrap();
+        diesel::sql_query(
+            "INSERT INTO meta VALUES ('test', 'nano', '8.0', 1, 1, 'x86_64', 'Editor', 'Editor', 'nano', '', NULL, NULL, NULL)",
+        )
+        .execute(&mut conn)
+        .unwrap();
+        drop(conn);
+
+        // Opening it applies the rest, keeping its contents
+        let db = Database::new(url).unwrap();
+        let meta = db.get(&package::Id::from("test".to_string())).unwrap();
+        assert_eq!(meta.name, "nano".to_string().into());
+        drop(db);
+
+        // Migrations we don't know of mean a newer moss changed the schema
+        let mut conn = SqliteConnection::establish(url).unwrap();
+        diesel::sql_query("INSERT INTO __diesel_schema_migrations (version) VALUES ('99990101000000')")
+            .execute(&mut conn)
+            .unwrap();
+        drop(conn);
+
+        assert!(matches!(Database::new(url), Err(Error::UnknownMigration(..))));
+    }
 }
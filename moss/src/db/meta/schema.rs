@@ -15,6 +15,7 @@ diesel::table! {
         uri -> Nullable<Text>,
         hash -> Nullable<Text>,
         download_size -> Nullable<BigInt>,
+        installed_size -> Nullable<BigInt>,
     }
 }
 
@@ -25,6 +26,13 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    meta_deltas (package, delta) {
+        package -> Text,
+        delta -> Text,
+    }
+}
+
 diesel::table! {
     meta_dependencies (package, dependency) {
         package -> Text,
@@ -46,9 +54,65 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    meta_replaces (package, replaces) {
+        package -> Text,
+        replaces -> Text,
+    }
+}
+
+diesel::table! {
+    meta_sideloaded (package) {
+        package -> Text,
+        source -> Text,
+    }
+}
+
+diesel::table! {
+    meta_soname_dependencies (package, soname, isa) {
+        package -> Text,
+        soname -> Text,
+        isa -> Text,
+    }
+}
+
+diesel::table! {
+    meta_soname_providers (package, soname, isa) {
+        package -> Text,
+        soname -> Text,
+        isa -> Text,
+    }
+}
+
+diesel::table! {
+    meta_triggers (package, scope, definition) {
+        package -> Text,
+        scope -> Text,
+        definition -> Text,
+    }
+}
+
 diesel::joinable!(meta_conflicts -> meta (package));
+diesel::joinable!(meta_deltas -> meta (package));
 diesel::joinable!(meta_dependencies -> meta (package));
 diesel::joinable!(meta_licenses -> meta (package));
 diesel::joinable!(meta_providers -> meta (package));
+diesel::joinable!(meta_replaces -> meta (package));
+diesel::joinable!(meta_sideloaded -> meta (package));
+diesel::joinable!(meta_soname_dependencies -> meta (package));
+diesel::joinable!(meta_soname_providers -> meta (package));
+diesel::joinable!(meta_triggers -> meta (package));
 
-diesel::allow_tables_to_appear_in_same_query!(meta, meta_conflicts, meta_dependencies, meta_licenses, meta_providers,);
+diesel::allow_tables_to_appear_in_same_query!(
+    meta,
+    meta_conflicts,
+    meta_deltas,
+    meta_dependencies,
+    meta_licenses,
+    meta_providers,
+    meta_replaces,
+    meta_sideloaded,
+    meta_soname_dependencies,
+    meta_soname_providers,
+    meta_triggers,
+);
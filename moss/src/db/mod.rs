@@ -2,14 +2,35 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
+//! Databases of a moss installation
+//!
+//! Each database embeds its ordered migration scripts (`<db>/migrations`). Applied
+//! versions are recorded within the database itself, and any pending ones are run
+//! when it's opened. Changing a schema therefore means adding a new migration, never
+//! editing a released one, so existing roots & repositories are upgraded in place.
+
 use std::{
+    collections::BTreeSet,
     fmt,
+    path::Path,
     sync::{Arc, Mutex},
+    time::Instant,
 };
 
 use chrono::{DateTime, Utc};
-use diesel::SqliteConnection;
+use diesel::{
+    connection::{Instrumentation, InstrumentationEvent},
+    migration::MigrationSource,
+    sql_query,
+    sql_types::{BigInt, Text},
+    sqlite::Sqlite,
+    Connection as _, QueryableByName, RunQueryDsl, SqliteConnection,
+};
+use diesel_migrations::{EmbeddedMigrations, MigrationHarness};
 use thiserror::Error;
+use tracing::trace;
+
+use crate::installation::Mutability;
 
 pub mod layout;
 pub mod meta;
@@ -32,6 +53,54 @@ impl Connection {
         let mut _guard = self.0.lock().expect("mutex guard");
         f(&mut _guard)
     }
+
+    /// Replace the underlying connection for all clones of this [`Connection`]
+    fn replace(&self, connection: SqliteConnection) {
+        *self.0.lock().expect("mutex guard") = connection;
+    }
+
+    /// Problems found by sqlite's integrity check, empty if there are none
+    fn integrity_check(&self) -> Result<Vec<String>, Error> {
+        #[derive(QueryableByName)]
+        struct Row {
+            #[diesel(sql_type = Text)]
+            integrity_check: String,
+        }
+
+        let rows = self.exec(|conn| sql_query("PRAGMA integrity_check").load::<Row>(conn))?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| row.integrity_check)
+            .filter(|message| message != "ok")
+            .collect())
+    }
+
+    /// Rebuild the database into the minimum amount of space,
+    /// returning the number of bytes reclaimed
+    fn vacuum(&self) -> Result<u64, Error> {
+        self.exec(|conn| {
+            let before = size(conn)?;
+            sql_query("VACUUM").execute(conn)?;
+            let after = size(conn)?;
+
+            Ok(before.saturating_sub(after))
+        })
+    }
+}
+
+/// Size of the database in bytes
+fn size(conn: &mut SqliteConnection) -> Result<u64, Error> {
+    #[derive(QueryableByName)]
+    struct Row {
+        #[diesel(sql_type = BigInt)]
+        size: i64,
+    }
+
+    let row = sql_query("SELECT page_count * page_size AS size FROM pragma_page_count(), pragma_page_size()")
+        .get_result::<Row>(conn)?;
+
+    Ok(row.size as u64)
 }
 
 impl fmt::Debug for Connection {
@@ -40,6 +109,133 @@ impl fmt::Debug for Connection {
     }
 }
 
+/// Connect to the database at `url` & apply all pending `migrations`
+///
+/// Without write access the file is opened read-only & never written to. A database
+/// which doesn't exist yet is treated as empty, and one requiring migration is
+/// migrated in memory.
+fn connect(url: &str, mutability: Mutability, migrations: EmbeddedMigrations) -> Result<SqliteConnection, Error> {
+    let url = match mutability {
+        Mutability::ReadWrite => url,
+        Mutability::ReadOnly if url == ":memory:" || !Path::new(url).exists() => ":memory:",
+        Mutability::ReadOnly => {
+            let mut conn = establish(&read_only_uri(url))?;
+
+            ensure_known_schema(&mut conn, url, migrations)?;
+
+            // Written by an older moss, migrate a copy rather than the file itself
+            if conn.has_pending_migration(migrations).map_err(Error::Migration)? {
+                conn = in_memory_copy(url)?;
+                conn.run_pending_migrations(migrations).map_err(Error::Migration)?;
+            }
+
+            return Ok(conn);
+        }
+    };
+
+    let mut conn = establish(url)?;
+
+    ensure_known_schema(&mut conn, url, migrations)?;
+    conn.run_pending_migrations(migrations).map_err(Error::Migration)?;
+
+    Ok(conn)
+}
+
+/// Refuse a database which had migrations applied that we don't know of,
+/// i.e. by a newer moss, as its schema may no longer match ours
+fn ensure_known_schema(conn: &mut SqliteConnection, url: &str, migrations: EmbeddedMigrations) -> Result<(), Error> {
+    let known = MigrationSource::<Sqlite>::migrations(&migrations)
+        .map_err(Error::Migration)?
+        .iter()
+        .map(|migration| migration.name().version().as_owned())
+        .collect::<BTreeSet<_>>();
+
+    let applied = conn.applied_migrations().map_err(Error::Migration)?;
+
+    match applied.into_iter().find(|version| !known.contains(version)) {
+        Some(unknown) => Err(Error::UnknownMigration(url.to_string(), unknown.to_string())),
+        None => Ok(()),
+    }
+}
+
+/// Open a connection, tracing each of its queries
+fn establish(url: &str) -> Result<SqliteConnection, Error> {
+    let mut conn = SqliteConnection::establish(url)?;
+    conn.set_instrumentation(QueryTimings::default());
+    Ok(conn)
+}
+
+/// Traces each query along with how long it took
+#[derive(Default)]
+struct QueryTimings {
+    started: Option<Instant>,
+}
+
+impl Instrumentation for QueryTimings {
+    fn on_connection_event(&mut self, event: InstrumentationEvent<'_>) {
+        match event {
+            InstrumentationEvent::StartQuery { .. } => self.started = Some(Instant::now()),
+            InstrumentationEvent::FinishQuery { query, error, .. } => {
+                let elapsed = self.started.take().map(|started| started.elapsed()).unwrap_or_default();
+
+                match error {
+                    Some(error) => trace!("{query} failed after {elapsed:?}: {error}"),
+                    None => trace!("{query} took {elapsed:?}"),
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Copy the database at `path` into a new in-memory database
+fn in_memory_copy(path: &str) -> Result<SqliteConnection, Error> {
+    #[derive(QueryableByName)]
+    struct Object {
+        #[diesel(sql_type = Text)]
+        kind: String,
+        #[diesel(sql_type = Text)]
+        name: String,
+        #[diesel(sql_type = Text)]
+        sql: String,
+    }
+
+    let mut conn = establish(":memory:")?;
+
+    sql_query("ATTACH DATABASE ? AS source")
+        .bind::<Text, _>(read_only_uri(path))
+        .execute(&mut conn)?;
+
+    // Tables in creation order, then their indices. sqlite's own
+    // tables are created along with the ones they belong to
+    let objects = sql_query(
+        "SELECT type AS kind, name, sql FROM source.sqlite_master \
+         WHERE sql IS NOT NULL AND name NOT LIKE 'sqlite_%' \
+         ORDER BY type = 'index', rowid",
+    )
+    .load::<Object>(&mut conn)?;
+
+    for object in objects {
+        sql_query(object.sql).execute(&mut conn)?;
+
+        if object.kind == "table" {
+            let name = object.name.replace('"', "\"\"");
+            sql_query(format!(r#"INSERT INTO main."{name}" SELECT * FROM source."{name}""#)).execute(&mut conn)?;
+        }
+    }
+
+    sql_query("DETACH DATABASE source").execute(&mut conn)?;
+
+    Ok(conn)
+}
+
+/// sqlite URI filename opening `path` without write access
+fn read_only_uri(path: &str) -> String {
+    let path = path.replace('%', "%25").replace('?', "%3f").replace('#', "%23");
+
+    format!("file:{path}?mode=ro")
+}
+
 pub struct Timestamp(pub DateTime<Utc>);
 
 impl TryFrom<i64> for Timestamp {
@@ -64,6 +260,8 @@ pub enum Error {
     Diesel(#[from] diesel::result::Error),
     #[error("diesel connection")]
     Connection(#[from] diesel::ConnectionError),
+    #[error("database {0} was migrated to {1} by a newer version of moss")]
+    UnknownMigration(String, String),
     #[error("diesel migration")]
     Migration(#[source] Box<dyn std::error::Error + Send + Sync + 'static>),
 }
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+
+CREATE TABLE IF NOT EXISTS state_exclusions (
+    state_id INTEGER NOT NULL,
+    package_id TEXT NOT NULL,
+    path TEXT NOT NULL,
+    PRIMARY KEY(state_id, package_id, path),
+    FOREIGN KEY(state_id) REFERENCES state(id) ON DELETE CASCADE
+);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS history_changes;
+DROP TABLE IF EXISTS history;
@@ -0,0 +1,18 @@
+-- Your SQL goes here
+
+CREATE TABLE IF NOT EXISTS history (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    created BIGINT NOT NULL DEFAULT (unixepoch()),
+    command TEXT NOT NULL,
+    summary TEXT NULL,
+    previous_state INTEGER NULL,
+    new_state INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS history_changes (
+    history_id INTEGER NOT NULL,
+    package_id TEXT NOT NULL,
+    added BOOLEAN NOT NULL,
+    PRIMARY KEY(history_id, package_id),
+    FOREIGN KEY(history_id) REFERENCES history(id) ON DELETE CASCADE
+);
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+
+CREATE TABLE IF NOT EXISTS state_snapshots (
+    state_id INTEGER NOT NULL,
+    created BIGINT NOT NULL DEFAULT (unixepoch()),
+    filesystem TEXT NOT NULL,
+    subvolume TEXT NOT NULL,
+    name TEXT NOT NULL,
+    PRIMARY KEY(state_id, name),
+    FOREIGN KEY(state_id) REFERENCES state(id) ON DELETE CASCADE
+);
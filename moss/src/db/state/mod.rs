@@ -2,14 +2,18 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
+use std::collections::BTreeSet;
+
 use chrono::{DateTime, Utc};
 use diesel::prelude::*;
-use diesel::{Connection as _, SqliteConnection};
-use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use diesel::Connection as _;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations};
 use itertools::Itertools;
 
-use super::{Connection, Error};
-use crate::state::{self, Id, Selection};
+use super::{Connection, Error, MAX_VARIABLE_NUMBER};
+use crate::installation::Mutability;
+use crate::package;
+use crate::state::{self, Id, Selection, Snapshot, Transaction};
 use crate::State;
 
 const MIGRATIONS: EmbeddedMigrations = embed_migrations!("src/db/state/migrations");
@@ -23,15 +27,26 @@ pub struct Database {
 
 impl Database {
     pub fn new(url: &str) -> Result<Self, Error> {
-        let mut conn = SqliteConnection::establish(url)?;
-
-        conn.run_pending_migrations(MIGRATIONS).map_err(Error::Migration)?;
+        Self::open(url, Mutability::ReadWrite)
+    }
 
+    /// Open the database at `url`, read-only unless `mutability` allows writes
+    pub fn open(url: &str, mutability: Mutability) -> Result<Self, Error> {
         Ok(Database {
-            conn: Connection::new(conn),
+            conn: Connection::new(super::connect(url, mutability, MIGRATIONS)?),
         })
     }
 
+    /// Problems found by an integrity check, empty if there are none
+    pub fn check(&self) -> Result<Vec<String>, Error> {
+        self.conn.integrity_check()
+    }
+
+    /// Reclaim unused space, returning the number of bytes freed
+    pub fn vacuum(&self) -> Result<u64, Error> {
+        self.conn.vacuum()
+    }
+
     pub fn list_ids(&self) -> Result<Vec<(Id, DateTime<Utc>)>, Error> {
         self.conn.exec(|conn| {
             model::state::table
@@ -153,6 +168,172 @@ impl Database {
             .and_then(|id| self.get(id))
     }
 
+    /// Record the `(package, path)` entries which were left out when blitting `state`
+    pub fn add_exclusions<'a>(
+        &self,
+        state: state::Id,
+        excluded: impl IntoIterator<Item = &'a (package::Id, String)>,
+    ) -> Result<(), Error> {
+        self.conn.exec(|conn| {
+            let exclusions = excluded
+                .into_iter()
+                .map(|(package, path)| model::Exclusion {
+                    state_id: i32::from(state),
+                    package_id: package.to_string(),
+                    path: path.clone(),
+                })
+                .collect::<Vec<_>>();
+
+            // Each row binds 3 variables, keep every statement below the limit
+            conn.transaction(|conn| {
+                for chunk in exclusions.chunks(MAX_VARIABLE_NUMBER / 3) {
+                    diesel::insert_or_ignore_into(model::state_exclusions::table)
+                        .values(chunk)
+                        .execute(conn)?;
+                }
+
+                Ok(())
+            })
+        })
+    }
+
+    /// All `(package, path)` entries left out when blitting `state`
+    pub fn exclusions(&self, state: state::Id) -> Result<BTreeSet<(package::Id, String)>, Error> {
+        self.conn.exec(|conn| {
+            Ok(model::state_exclusions::table
+                .select(model::Exclusion::as_select())
+                .filter(model::state_exclusions::state_id.eq(i32::from(state)))
+                .load(conn)?
+                .into_iter()
+                .map(|row| (package::Id::from(row.package_id), row.path))
+                .collect())
+        })
+    }
+
+    /// Record filesystem `snapshots` taken before a transaction
+    pub fn add_snapshots(&self, snapshots: &[Snapshot]) -> Result<(), Error> {
+        self.conn.exec(|conn| {
+            let snapshots = snapshots
+                .iter()
+                .map(|snapshot| model::NewSnapshot {
+                    state_id: i32::from(snapshot.state),
+                    created: snapshot.created.timestamp(),
+                    filesystem: snapshot.filesystem.to_string(),
+                    subvolume: snapshot.subvolume.to_string_lossy().into_owned(),
+                    name: &snapshot.name,
+                })
+                .collect::<Vec<_>>();
+
+            diesel::insert_or_ignore_into(model::state_snapshots::table)
+                .values(snapshots)
+                .execute(conn)?;
+
+            Ok(())
+        })
+    }
+
+    /// All filesystem snapshots taken while `state` was active, newest first
+    pub fn snapshots(&self, state: state::Id) -> Result<Vec<Snapshot>, Error> {
+        self.conn.exec(|conn| {
+            Ok(model::state_snapshots::table
+                .select(model::Snapshot::as_select())
+                .filter(model::state_snapshots::state_id.eq(i32::from(state)))
+                .order_by(model::state_snapshots::created.desc())
+                .load(conn)?
+                .into_iter()
+                .map(|row| Snapshot {
+                    state: row.state_id.into(),
+                    filesystem: row.filesystem,
+                    subvolume: row.subvolume.into(),
+                    name: row.name,
+                    created: row.created.0,
+                })
+                .collect())
+        })
+    }
+
+    /// Record a transaction from `previous` to `state` in the history,
+    /// where `changes` holds each package and whether it was added or removed
+    pub fn add_transaction<'a>(
+        &self,
+        command: &str,
+        summary: Option<&str>,
+        previous: Option<state::Id>,
+        state: state::Id,
+        changes: impl IntoIterator<Item = (&'a package::Id, bool)>,
+    ) -> Result<Transaction, Error> {
+        self.conn
+            .exec(|conn| {
+                conn.transaction(|conn| {
+                    let transaction = model::NewTransaction {
+                        command,
+                        summary,
+                        previous_state: previous.map(i32::from),
+                        new_state: i32::from(state),
+                    };
+
+                    let id = diesel::insert_into(model::history::table)
+                        .values(transaction)
+                        .returning(model::history::id)
+                        .get_result::<i32>(conn)?;
+
+                    let changes = changes
+                        .into_iter()
+                        .map(|(package, added)| model::Change {
+                            history_id: id,
+                            package_id: package.to_string(),
+                            added,
+                        })
+                        .collect::<Vec<_>>();
+
+                    diesel::insert_or_ignore_into(model::history_changes::table)
+                        .values(changes)
+                        .execute(conn)?;
+
+                    Ok(id)
+                })
+            })
+            .and_then(|id| self.transaction(id))
+    }
+
+    /// All recorded transactions, oldest first
+    pub fn transactions(&self) -> Result<Vec<Transaction>, Error> {
+        self.conn.exec(|conn| {
+            let transactions = model::history::table
+                .select(model::Transaction::as_select())
+                .order_by(model::history::id)
+                .load::<model::Transaction>(conn)?;
+            let mut changes = model::history_changes::table
+                .select(model::Change::as_select())
+                .load::<model::Change>(conn)?
+                .into_iter()
+                .map(|row| (row.history_id, row))
+                .into_group_map();
+
+            Ok(transactions
+                .into_iter()
+                .map(|transaction| {
+                    let changes = changes.remove(&transaction.id).unwrap_or_default();
+                    transaction.with_changes(changes)
+                })
+                .collect())
+        })
+    }
+
+    pub fn transaction(&self, id: i32) -> Result<Transaction, Error> {
+        self.conn.exec(|conn| {
+            let transaction = model::history::table
+                .select(model::Transaction::as_select())
+                .find(id)
+                .first(conn)?;
+            let changes = model::Change::belonging_to(&transaction)
+                .select(model::Change::as_select())
+                .load(conn)?;
+
+            Ok(transaction.with_changes(changes))
+        })
+    }
+
     pub fn remove(&self, state: &state::Id) -> Result<(), Error> {
         self.batch_remove(Some(state))
     }
@@ -170,6 +351,29 @@ impl Database {
     }
 }
 
+impl model::Transaction {
+    fn with_changes(self, changes: Vec<model::Change>) -> Transaction {
+        let (added, removed) = changes.into_iter().partition::<Vec<_>, _>(|change| change.added);
+        let ids = |changes: Vec<model::Change>| {
+            changes
+                .into_iter()
+                .map(|change| package::Id::from(change.package_id))
+                .collect()
+        };
+
+        Transaction {
+            id: self.id,
+            created: self.created.0,
+            command: self.command,
+            summary: self.summary,
+            previous: self.previous_state.map(state::Id::from),
+            state: self.new_state.into(),
+            added: ids(added),
+            removed: ids(removed),
+        }
+    }
+}
+
 mod model {
     use diesel::{
         associations::{Associations, Identifiable},
@@ -179,9 +383,13 @@ mod model {
         Selectable,
     };
 
-    use crate::{db::Timestamp, package, state::Kind};
+    use crate::{
+        db::Timestamp,
+        package,
+        state::{Filesystem, Kind},
+    };
 
-    pub use super::schema::{state, state_selections};
+    pub use super::schema::{history, history_changes, state, state_exclusions, state_selections, state_snapshots};
 
     #[derive(Queryable, Selectable, Identifiable)]
     #[diesel(table_name = state)]
@@ -234,6 +442,71 @@ mod model {
         pub explicit: bool,
         pub reason: Option<&'a str>,
     }
+
+    #[derive(Queryable, Selectable, Insertable)]
+    #[diesel(table_name = state_exclusions)]
+    #[diesel(check_for_backend(Sqlite))]
+    pub struct Exclusion {
+        pub state_id: i32,
+        pub package_id: String,
+        pub path: String,
+    }
+
+    #[derive(Queryable, Selectable)]
+    #[diesel(table_name = state_snapshots)]
+    #[diesel(check_for_backend(Sqlite))]
+    pub struct Snapshot {
+        pub state_id: i32,
+        #[diesel(deserialize_as = i64)]
+        pub created: Timestamp,
+        #[diesel(deserialize_as = String)]
+        pub filesystem: Filesystem,
+        pub subvolume: String,
+        pub name: String,
+    }
+
+    #[derive(Insertable)]
+    #[diesel(table_name = state_snapshots)]
+    pub struct NewSnapshot<'a> {
+        pub state_id: i32,
+        pub created: i64,
+        pub filesystem: String,
+        pub subvolume: String,
+        pub name: &'a str,
+    }
+
+    #[derive(Queryable, Selectable, Identifiable)]
+    #[diesel(table_name = history)]
+    #[diesel(check_for_backend(Sqlite))]
+    pub struct Transaction {
+        pub id: i32,
+        #[diesel(deserialize_as = i64)]
+        pub created: Timestamp,
+        pub command: String,
+        pub summary: Option<String>,
+        pub previous_state: Option<i32>,
+        pub new_state: i32,
+    }
+
+    #[derive(Insertable)]
+    #[diesel(table_name = history)]
+    pub struct NewTransaction<'a> {
+        pub command: &'a str,
+        pub summary: Option<&'a str>,
+        pub previous_state: Option<i32>,
+        pub new_state: i32,
+    }
+
+    #[derive(Queryable, Selectable, Insertable, Identifiable, Associations)]
+    #[diesel(table_name = history_changes)]
+    #[diesel(primary_key(history_id, package_id))]
+    #[diesel(belongs_to(Transaction, foreign_key = history_id))]
+    #[diesel(check_for_backend(Sqlite))]
+    pub struct Change {
+        pub history_id: i32,
+        pub package_id: String,
+        pub added: bool,
+    }
 }
 
 #[cfg(test)]
@@ -268,4 +541,127 @@ mod test {
 
         assert_eq!(state.selections, selections);
     }
+
+    #[test]
+    fn many_exclusions() {
+        let database = Database::new(":memory:").unwrap();
+
+        let state = database.add(&[], None, None).unwrap();
+
+        // More rows than fit a single statement
+        let excluded = (0..MAX_VARIABLE_NUMBER / 2)
+            .map(|i| (package::Id::from("pkg a".to_string()), format!("share/doc/{i}")))
+            .collect::<Vec<_>>();
+        database.add_exclusions(state.id, &excluded).unwrap();
+
+        assert_eq!(database.exclusions(state.id).unwrap().len(), excluded.len());
+    }
+
+    #[test]
+    fn history() {
+        let database = Database::new(":memory:").unwrap();
+
+        let a = package::Id::from("pkg a".to_string());
+        let b = package::Id::from("pkg b".to_string());
+
+        let first = database
+            .add_transaction("moss install a", Some("Install"), None, 1.into(), [(&a, true)])
+            .unwrap();
+        let second = database
+            .add_transaction(
+                "moss sync",
+                Some("Sync"),
+                Some(1.into()),
+                2.into(),
+                [(&b, true), (&a, false)],
+            )
+            .unwrap();
+
+        assert_eq!(first.id, 1);
+        assert_eq!(first.previous, None);
+        assert_eq!(first.added, vec![a.clone()]);
+
+        assert_eq!(second.command, "moss sync");
+        assert_eq!(second.previous, Some(1.into()));
+        assert_eq!(second.state, 2.into());
+        assert_eq!(second.added, vec![b]);
+        assert_eq!(second.removed, vec![a]);
+
+        assert_eq!(database.transactions().unwrap(), vec![first, second]);
+        assert!(database.transaction(3).is_err());
+    }
+
+    #[test]
+    fn snapshots() {
+        let database = Database::new(":memory:").unwrap();
+        let state = database.add(&[], None, None).unwrap();
+
+        let snapshot = |name: &str, created| Snapshot {
+            state: state.id,
+            filesystem: state::Filesystem::Zfs,
+            subvolume: "/".into(),
+            name: name.to_string(),
+            created: chrono::DateTime::from_timestamp(created, 0).unwrap(),
+        };
+        let older = snapshot("rpool/root@moss-1-a", 100);
+        let newer = snapshot("rpool/root@moss-1-b", 200);
+
+        database.add_snapshots(&[older.clone(), newer.clone()]).unwrap();
+        assert_eq!(database.snapshots(state.id).unwrap(), vec![newer, older]);
+
+        // Removed along with their state
+        database.remove(&state.id).unwrap();
+        assert!(database.snapshots(state.id).unwrap().is_empty());
+    }
+
+    #[test]
+    fn read_only() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("state.db");
+        let url = path.to_str().unwrap();
+
+        // Missing databases are empty
+        let database = Database::open(url, Mutability::ReadOnly).unwrap();
+        assert!(database.list_ids().unwrap().is_empty());
+        assert!(!path.exists());
+
+        let selections = vec![Selection::explicit(package::Id::from("pkg a".to_string()))];
+        Database::new(url).unwrap().add(&selections, None, None).unwrap();
+
+        let database = Database::open(url, Mutability::ReadOnly).unwrap();
+        assert_eq!(database.list_ids().unwrap().len(), 1);
+        assert!(database.add(&selections, None, None).is_err());
+    }
+
+    #[test]
+    fn read_only_migration() {
+        use diesel_migrations::MigrationHarness;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("state-old.db");
+        let url = path.to_str().unwrap();
+
+        // A database created before any later migrations existed
+        let mut conn = SqliteConnection::establish(url).unwrap();
+        conn.run_next_migration(MIGRATIONS).unwrap();
+        diesel::sql_query("INSERT INTO state (id, type) VALUES (1, 'transaction')")
+            .execute(&mut conn)
+            .unwrap();
+        diesel::sql_query("INSERT INTO state_selections VALUES (1, 'pkg a', 1, NULL)")
+            .execute(&mut conn)
+            .unwrap();
+        drop(conn);
+
+        // Migrated in memory, keeping its contents
+        let database = Database::open(url, Mutability::ReadOnly).unwrap();
+        let state = database.get(Id::from(1)).unwrap();
+        assert_eq!(state.selections.len(), 1);
+        assert!(database.transactions().unwrap().is_empty());
+        drop(database);
+
+        // The file itself is left as-is
+        let mut conn = SqliteConnection::establish(url).unwrap();
+        assert!(conn.has_pending_migration(MIGRATIONS).unwrap());
+        drop(conn);
+    }
 }
@@ -20,6 +20,53 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    state_exclusions (state_id, package_id, path) {
+        state_id -> Integer,
+        package_id -> Text,
+        path -> Text,
+    }
+}
+
+diesel::table! {
+    state_snapshots (state_id, name) {
+        state_id -> Integer,
+        created -> BigInt,
+        filesystem -> Text,
+        subvolume -> Text,
+        name -> Text,
+    }
+}
+
+diesel::table! {
+    history (id) {
+        id -> Integer,
+        created -> BigInt,
+        command -> Text,
+        summary -> Nullable<Text>,
+        previous_state -> Nullable<Integer>,
+        new_state -> Integer,
+    }
+}
+
+diesel::table! {
+    history_changes (history_id, package_id) {
+        history_id -> Integer,
+        package_id -> Text,
+        added -> Bool,
+    }
+}
+
+diesel::joinable!(history_changes -> history (history_id));
+diesel::joinable!(state_exclusions -> state (state_id));
 diesel::joinable!(state_selections -> state (state_id));
+diesel::joinable!(state_snapshots -> state (state_id));
 
-diesel::allow_tables_to_appear_in_same_query!(state, state_selections,);
+diesel::allow_tables_to_appear_in_same_query!(
+    history,
+    history_changes,
+    state,
+    state_exclusions,
+    state_selections,
+    state_snapshots,
+);
@@ -130,6 +130,11 @@ impl Dependency {
             })
         }
     }
+
+    /// Whether this dependency is matched by `query`, see [`split_soname`]
+    pub fn matches(&self, query: &Dependency) -> bool {
+        self.kind == query.kind && target_matches(self.kind, &self.name, &query.name)
+    }
 }
 
 /// Partial ordering comparator for dependencies
@@ -191,6 +196,11 @@ impl Provider {
             })
         }
     }
+
+    /// Whether this provider is matched by `query`, see [`split_soname`]
+    pub fn matches(&self, query: &Provider) -> bool {
+        self.kind == query.kind && target_matches(self.kind, &self.name, &query.name)
+    }
 }
 
 /// Partial ordering comparator for Provider
@@ -227,6 +237,29 @@ impl TryFrom<String> for Provider {
     }
 }
 
+/// Split the target of a [`Kind::SharedLibrary`] dependency or provider into
+/// its soname & the ISA it's built for, i.e. `libz.so.1(x86_64)`
+///
+/// Queries may leave out the ISA, i.e. `soname(libz.so.1)`, matching any ISA.
+pub fn split_soname(target: &str) -> (&str, Option<&str>) {
+    match target.strip_suffix(')').and_then(|rest| rest.rsplit_once('(')) {
+        Some((soname, isa)) => (soname, Some(isa)),
+        None => (target, None),
+    }
+}
+
+/// Whether `target` is matched by the `query` target, both of `kind`
+fn target_matches(kind: Kind, target: &str, query: &str) -> bool {
+    if target == query {
+        return true;
+    }
+
+    match (kind, split_soname(query)) {
+        (Kind::SharedLibrary, (soname, None)) => split_soname(target).0 == soname,
+        _ => false,
+    }
+}
+
 /// Parse the [`Kind`] of dependency or provider from the string
 fn parse(s: &str) -> Result<(Kind, String), ParseError> {
     let (kind, rest) = s.split_once('(').ok_or(ParseError(s.to_string()))?;
@@ -7,9 +7,15 @@ pub const NAME: &str = env!("CARGO_PKG_NAME");
 pub const MAX_DISK_CONCURRENCY: usize = 16;
 /// Max concurrency for network tasks
 pub const MAX_NETWORK_CONCURRENCY: usize = 8;
+/// Times a request is retried after a transient failure
+pub const NETWORK_RETRIES: u32 = 3;
 /// Buffer size used when reading a file, 4 MiB
 pub const FILE_READ_BUFFER_SIZE: usize = 4 * 1024 * 1024;
 /// Threshold to begin chunking file during read, 16 KiB
 pub const FILE_READ_CHUNK_THRESHOLD: usize = 16 * 1024;
 /// DB batch size
 pub const DB_BATCH_SIZE: usize = 1000;
+/// Packages removed at once from the install & layout dbs after which they're vacuumed
+pub const DB_VACUUM_THRESHOLD: usize = 100;
+/// Max number of stone payloads decoded ahead of their consumer
+pub const PAYLOAD_READ_AHEAD: usize = 64;
@@ -9,9 +9,9 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use log::{trace, warn};
 use nix::unistd::{access, AccessFlags, Uid};
 use thiserror::Error;
+use tracing::{trace, warn};
 
 use crate::state;
 
@@ -48,8 +48,17 @@ impl Installation {
     /// and determine the mutability per the current user identity
     /// and ACL permissions.
     pub fn open(root: impl Into<PathBuf>) -> Result<Self, Error> {
-        let root: PathBuf = root.into();
+        Self::open_with(root.into(), false)
+    }
+
+    /// Open a system root as a read-only Installation, regardless of
+    /// our access. Nothing is created or written within the root, i.e.
+    /// to query an image from CI without risk of modifying it.
+    pub fn open_read_only(root: impl Into<PathBuf>) -> Result<Self, Error> {
+        Self::open_with(root.into(), true)
+    }
 
+    fn open_with(root: PathBuf, read_only: bool) -> Result<Self, Error> {
         if !root.exists() || !root.is_dir() {
             return Err(Error::RootInvalid);
         }
@@ -67,10 +76,14 @@ impl Installation {
         // It's important we try this first in-case `root` needs to be created
         // as well, otherwise mutability will always be read-only
         // TODO: Should we instead fail if root doesn't exist?
-        ensure_dirs_exist(&root);
+        if !read_only {
+            ensure_dirs_exist(&root);
+        }
 
         // Root? Always RW. Otherwise, check access for W
-        let mutability = if Uid::effective().is_root() || access(&root, AccessFlags::W_OK).is_ok() {
+        let mutability = if read_only {
+            Mutability::ReadOnly
+        } else if Uid::effective().is_root() || access(&root, AccessFlags::W_OK).is_ok() {
             Mutability::ReadWrite
         } else {
             Mutability::ReadOnly
@@ -87,6 +100,12 @@ impl Installation {
         })
     }
 
+    /// Re-read the active state, i.e. for long running processes
+    /// outliving transactions which change it
+    pub fn reload_active_state(&mut self) {
+        self.active_state = read_state_id(&self.root);
+    }
+
     /// Construct an Installation with a specific cache directory
     ///
     /// This is useful when we wish to have a cache directory separate from the internal
@@ -10,14 +10,22 @@ pub use self::registry::Registry;
 pub use self::repository::Repository;
 pub use self::state::State;
 
+pub mod authorization;
 pub mod client;
 pub mod db;
 pub mod dependency;
 pub mod environment;
 pub mod installation;
+pub mod messages;
+pub mod output;
 pub mod package;
+pub mod prompt;
 pub mod registry;
 pub mod repository;
 pub mod request;
 pub mod runtime;
+#[cfg(feature = "service")]
+pub mod service;
+pub mod settings;
+pub mod signature;
 pub mod state;
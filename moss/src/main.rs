@@ -4,6 +4,7 @@
 
 use std::error::Error;
 
+use moss::messages;
 use tui::Styled;
 
 mod cli;
@@ -20,7 +21,7 @@ fn main() {
 fn report_error(error: cli::Error) {
     let sources = sources(&error);
     let error = sources.join(": ");
-    eprintln!("{}: {error}", "Error".red());
+    eprintln!("{}: {error}", messages::get("error").red());
 }
 
 /// Accumulate sources through error chains
@@ -0,0 +1,44 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Translated messages of moss, from the catalogs within `moss/locales/`
+//!
+//! The language follows `LC_ALL`, `LC_MESSAGES` or `LANG`, see [`i18n::locale`].
+//! Errors & logs aren't translated, see [`i18n`] for what is.
+
+use std::sync::OnceLock;
+
+use i18n::{Catalog, Value};
+
+/// `(language, catalog)` of every translation
+const CATALOGS: &[(&str, &str)] = &[
+    (i18n::FALLBACK, include_str!("../locales/en-US/moss.ftl")),
+    ("de", include_str!("../locales/de/moss.ftl")),
+];
+
+static CATALOG: OnceLock<Catalog> = OnceLock::new();
+
+fn catalog() -> &'static Catalog {
+    CATALOG.get_or_init(|| Catalog::new(CATALOGS, &i18n::locale()))
+}
+
+/// The message `id` in the user's language
+pub fn get(id: &str) -> String {
+    catalog().get(id)
+}
+
+/// The message `id` in the user's language, with its `$variables` taken from `args`
+pub fn format(id: &str, args: &[(&str, Value<'_>)]) -> String {
+    catalog().format(id, args)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn catalogs() {
+        i18n::assert_catalogs(CATALOGS);
+    }
+}
@@ -0,0 +1,88 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Machine readable output
+//!
+//! With [`Format::Json`] selected, commands print a single JSON [`Document`]
+//! instead of their human readable output. Its `version` is bumped whenever
+//! the shape of any document changes incompatibly, so scripts can detect it.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::Serialize;
+
+/// Version of the [`Document`] shapes
+pub const VERSION: u32 = 1;
+
+/// Whether JSON output was requested, see [`set_format`]
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// How output is presented to the user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Human readable output
+    Text,
+    /// Machine readable JSON document on stdout
+    Json,
+}
+
+/// A versioned JSON document
+#[derive(Debug, Serialize)]
+pub struct Document<'a, T> {
+    pub version: u32,
+    /// What `data` describes, i.e. `packages`
+    pub kind: &'a str,
+    pub data: T,
+}
+
+/// Set the output format for the lifetime of the process
+pub fn set_format(format: Format) {
+    JSON.store(format == Format::Json, Ordering::Relaxed);
+}
+
+/// The requested output format
+pub fn format() -> Format {
+    if JSON.load(Ordering::Relaxed) {
+        Format::Json
+    } else {
+        Format::Text
+    }
+}
+
+/// Returns true if JSON output was requested
+pub fn is_json() -> bool {
+    format() == Format::Json
+}
+
+/// Print `data` to stdout as a [`Document`] of `kind`
+pub fn print_json<T: Serialize>(kind: &str, data: T) -> Result<(), serde_json::Error> {
+    let document = Document {
+        version: VERSION,
+        kind,
+        data,
+    };
+
+    println!("{}", serde_json::to_string_pretty(&document)?);
+
+    Ok(())
+}
+
+/// Print `data` to stdout as a [`Document`] of `kind` on a single line,
+/// for commands which keep running and print a stream of them
+pub fn print_json_line<T: Serialize>(kind: &str, data: T) -> Result<(), serde_json::Error> {
+    println!("{}", to_json_line(kind, data)?);
+
+    Ok(())
+}
+
+/// Encode `data` as a [`Document`] of `kind` on a single line
+pub fn to_json_line<T: Serialize>(kind: &str, data: T) -> Result<String, serde_json::Error> {
+    let document = Document {
+        version: VERSION,
+        kind,
+        data,
+    };
+
+    serde_json::to_string(&document)
+}
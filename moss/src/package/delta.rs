@@ -0,0 +1,295 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Produce delta packages between two releases of a package
+//!
+//! A delta package has the metadata & layouts of the newer release, tagged
+//! with the hash of the release it applies to, but only carries the content
+//! missing from that release. Clients holding the older release's content
+//! unpack it like the full package, see [`crate::client::cache::fetch_delta`].
+
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use hash::{Algorithm, Digest};
+use stone::{
+    header::v1::FileType,
+    payload::{self, meta},
+    read::PayloadKind,
+};
+use thiserror::Error;
+
+/// Summary of a written delta package
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Produced {
+    /// Hash of the release the delta applies to
+    pub from: String,
+    /// Contents carried by the delta
+    pub carried: usize,
+    /// Contents taken from the older release instead
+    pub reused: usize,
+}
+
+/// Write a delta package to `out` which turns the stone at `from` into the one at `to`
+///
+/// Content is staged in files beside `out` until the delta is written.
+pub fn write(from: &Path, to: &Path, out: &Path) -> Result<Produced, Error> {
+    let from_hash = hash_file(from)?;
+
+    // Content the older release already provides
+    let mut reader = stone::read(fs::File::open(from)?)?;
+    let existing = reader
+        .payloads()?
+        .collect::<Result<Vec<_>, _>>()?
+        .iter()
+        .filter_map(PayloadKind::index)
+        .flat_map(|payload| &payload.body)
+        .map(|index| index.digest)
+        .collect::<BTreeSet<_>>();
+
+    let mut reader = stone::read(fs::File::open(to)?)?;
+    let payloads = reader.payloads()?.collect::<Result<Vec<_>, _>>()?;
+
+    let mut metadata = payloads
+        .iter()
+        .find_map(PayloadKind::meta)
+        .ok_or(Error::MissingMeta)?
+        .body
+        .iter()
+        .filter(|record| record.tag != meta::Tag::DeltaFrom)
+        .cloned()
+        .collect::<Vec<_>>();
+    metadata.push(payload::Meta {
+        tag: meta::Tag::DeltaFrom,
+        kind: meta::Kind::String(from_hash.clone()),
+    });
+
+    let mut carried = vec![];
+    let mut carried_digests = BTreeSet::new();
+    let mut reused = 0;
+    for index in payloads.iter().filter_map(PayloadKind::index).flat_map(|p| &p.body) {
+        if existing.contains(&index.digest) {
+            reused += 1;
+        } else if carried_digests.insert(index.digest) {
+            carried.push(index);
+        }
+    }
+
+    let staged = Staged::new(out);
+    let mut out_file = fs::File::create(out)?;
+    let mut writer = stone::Writer::new(&mut out_file, FileType::Binary)?;
+
+    writer.add_payload(metadata.as_slice())?;
+    if let Some(attributes) = payloads.iter().find_map(PayloadKind::attributes) {
+        writer.add_payload(attributes.body.as_slice())?;
+    }
+    if let Some(layouts) = payloads.iter().find_map(PayloadKind::layout) {
+        writer.add_payload(layouts.body.as_slice())?;
+    }
+
+    if carried.is_empty() {
+        writer.finalize()?;
+    } else {
+        let content = payloads
+            .iter()
+            .find_map(PayloadKind::content)
+            .ok_or(Error::MissingContent)?;
+
+        let mut unpacked = staged.file("unpacked")?;
+        reader.unpack_content(content, &mut unpacked)?;
+
+        let mut buffer = staged.file("content")?;
+        let size = carried.iter().map(|index| index.end - index.start).sum();
+        let mut writer = writer.with_content(&mut buffer, Some(size), 1)?;
+
+        for index in &carried {
+            unpacked.seek(SeekFrom::Start(index.start))?;
+            let digest = writer.add_content(&mut (&unpacked).take(index.end - index.start))?;
+
+            if digest != index.digest {
+                return Err(Error::CorruptContent(index.digest));
+            }
+        }
+
+        writer.finalize()?;
+    }
+
+    out_file.flush()?;
+
+    Ok(Produced {
+        from: from_hash,
+        carried: carried.len(),
+        reused,
+    })
+}
+
+/// Hex encoded sha256 digest of the file at `path`, as published in repository indexes
+fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Digest::new(Algorithm::Sha256);
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize_hex())
+}
+
+/// Staging files beside a delta package, removed once it's written
+struct Staged(PathBuf);
+
+impl Staged {
+    fn new(out: &Path) -> Self {
+        Self(out.to_path_buf())
+    }
+
+    fn path(&self, suffix: &str) -> PathBuf {
+        let mut name = self.0.file_name().unwrap_or_default().to_owned();
+        name.push(format!(".{suffix}"));
+        self.0.with_file_name(name)
+    }
+
+    fn file(&self, suffix: &str) -> io::Result<fs::File> {
+        fs::File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(self.path(suffix))
+    }
+}
+
+impl Drop for Staged {
+    fn drop(&mut self) {
+        for suffix in ["unpacked", "content"] {
+            let _ = fs::remove_file(self.path(suffix));
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("missing metadata")]
+    MissingMeta,
+    #[error("missing content payload")]
+    MissingContent,
+    #[error("content of {0:02x} doesn't match its digest")]
+    CorruptContent(u128),
+    #[error("read stone")]
+    Read(#[from] stone::read::Error),
+    #[error("write stone")]
+    Write(#[from] stone::write::Error),
+    #[error("io")]
+    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use stone::payload::{layout, Layout};
+
+    use super::*;
+
+    /// Write release `release` of a package named `name` to `path`, with a
+    /// file at each `(path, content)` of `files`
+    pub fn write_stone(path: &Path, name: &str, release: u64, files: &[(&str, &[u8])]) {
+        let mut file = fs::File::create(path).unwrap();
+        let mut writer = stone::Writer::new(&mut file, FileType::Binary).unwrap();
+
+        let string = |tag, value: &str| payload::Meta {
+            tag,
+            kind: meta::Kind::String(value.to_string()),
+        };
+        let metadata = [
+            string(meta::Tag::Name, name),
+            string(meta::Tag::Version, "1.0"),
+            payload::Meta {
+                tag: meta::Tag::Release,
+                kind: meta::Kind::Uint64(release),
+            },
+            payload::Meta {
+                tag: meta::Tag::BuildRelease,
+                kind: meta::Kind::Uint64(1),
+            },
+            string(meta::Tag::Architecture, "x86_64"),
+            string(meta::Tag::Summary, "summary"),
+            string(meta::Tag::Description, "description"),
+            string(meta::Tag::SourceID, name),
+            string(meta::Tag::Homepage, "https://serpentos.com"),
+        ];
+        writer.add_payload(metadata.as_slice()).unwrap();
+
+        let layouts = files
+            .iter()
+            .map(|(path, content)| Layout {
+                uid: 0,
+                gid: 0,
+                mode: 0o644,
+                tag: 0,
+                entry: layout::Entry::Regular(xxhash_rust::xxh3::xxh3_128(content), path.to_string()),
+            })
+            .collect::<Vec<_>>();
+        writer.add_payload(layouts.as_slice()).unwrap();
+
+        let mut buffer = tempfile(path, "content");
+        let mut writer = writer.with_content(&mut buffer, None, 1).unwrap();
+        for (_, content) in files {
+            writer.add_content(&mut &content[..]).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    fn tempfile(path: &Path, suffix: &str) -> fs::File {
+        fs::File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path.with_extension(suffix))
+            .unwrap()
+    }
+
+    #[test]
+    fn carries_changed_content() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+
+        let (from, to, out) = (dir.join("old.stone"), dir.join("new.stone"), dir.join("delta.stone"));
+        write_stone(&from, "tool", 1, &[("bin/tool", b"old tool"), ("share/doc", b"docs")]);
+        write_stone(&to, "tool", 2, &[("bin/tool", b"new tool"), ("share/doc", b"docs")]);
+
+        let produced = write(&from, &to, &out).unwrap();
+        assert_eq!(produced.from, hash_file(&from).unwrap());
+        assert_eq!((produced.carried, produced.reused), (1, 1));
+
+        let mut reader = stone::read(fs::File::open(&out).unwrap()).unwrap();
+        let payloads = reader.payloads().unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+
+        let delta_from = payloads
+            .iter()
+            .find_map(PayloadKind::meta)
+            .unwrap()
+            .body
+            .iter()
+            .find_map(|record| match (&record.tag, &record.kind) {
+                (meta::Tag::DeltaFrom, meta::Kind::String(from)) => Some(from.clone()),
+                _ => None,
+            });
+        assert_eq!(delta_from, Some(produced.from));
+
+        let carried = payloads
+            .iter()
+            .filter_map(PayloadKind::index)
+            .flat_map(|p| &p.body)
+            .map(|index| index.digest)
+            .collect::<Vec<_>>();
+        assert_eq!(carried, vec![xxhash_rust::xxh3::xxh3_128(b"new tool")]);
+
+        let layouts = payloads.iter().find_map(PayloadKind::layout).unwrap();
+        assert_eq!(layouts.body.len(), 2);
+
+        // Identical releases need no content at all
+        let produced = write(&to, &to, &out).unwrap();
+        assert_eq!((produced.carried, produced.reused), (0, 2));
+        assert!(!dir.join("delta.stone.content").exists());
+    }
+}
@@ -0,0 +1,305 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Compare the metadata & contents of two `.stone` packages, i.e. to
+//! review the impact of a rebuild before publishing it
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{self, Read, Seek},
+    path::Path,
+};
+
+use stone::{payload::layout, read::PayloadKind};
+use thiserror::Error;
+
+use super::{Meta, MissingMetaFieldError};
+use crate::environment;
+
+/// A file as laid out by a package, keyed by its path under `/usr`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum File {
+    Regular { hash: u128, size: Option<u64> },
+    Symlink(String),
+    Directory,
+    Special,
+}
+
+/// Metadata & file list of a stone package
+#[derive(Debug, Clone)]
+pub struct Contents {
+    pub meta: Meta,
+    pub files: BTreeMap<String, File>,
+}
+
+impl Contents {
+    /// Read the contents of the stone at `path`
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        Self::decode(fs::File::open(path)?)
+    }
+
+    /// Decode the contents of a stone from `source`, i.e. a remote
+    /// stone read with [`crate::request::RangedReader`]
+    pub fn decode(source: impl Read + Seek + Send + 'static) -> Result<Self, Error> {
+        let mut meta = None;
+        let mut layouts = vec![];
+        let mut sizes = BTreeMap::new();
+
+        for payload in stone::stream_payloads(source, environment::PAYLOAD_READ_AHEAD)? {
+            match payload? {
+                PayloadKind::Meta(payload) => meta = Some(Meta::from_stone_payload(&payload.body)?),
+                PayloadKind::Layout(payload) => layouts.extend(payload.body),
+                PayloadKind::Index(payload) => {
+                    sizes.extend(payload.body.iter().map(|index| (index.digest, index.end - index.start)))
+                }
+                _ => {}
+            }
+        }
+
+        let files = layouts
+            .into_iter()
+            .map(|layout| {
+                let file = match &layout.entry {
+                    layout::Entry::Regular(hash, _) => File::Regular {
+                        hash: *hash,
+                        size: sizes.get(hash).copied(),
+                    },
+                    layout::Entry::Symlink(source, _) => File::Symlink(source.clone()),
+                    layout::Entry::Directory(_) => File::Directory,
+                    _ => File::Special,
+                };
+                (layout.entry.target().to_string(), file)
+            })
+            .collect();
+
+        Ok(Self {
+            meta: meta.ok_or(Error::MissingMeta)?,
+            files,
+        })
+    }
+}
+
+/// A metadata field which differs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub name: &'static str,
+    pub old: String,
+    pub new: String,
+}
+
+/// Items only found on either side
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Changes<T> {
+    pub added: Vec<T>,
+    pub removed: Vec<T>,
+}
+
+impl<T> Changes<T> {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl<T: Ord + Clone> Changes<T> {
+    fn between(old: &BTreeSet<T>, new: &BTreeSet<T>) -> Self {
+        Self {
+            added: new.difference(old).cloned().collect(),
+            removed: old.difference(new).cloned().collect(),
+        }
+    }
+}
+
+/// A file differing between both packages
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileChange {
+    Added(String, File),
+    Removed(String, File),
+    Changed { path: String, old: File, new: File },
+}
+
+impl FileChange {
+    pub fn path(&self) -> &str {
+        match self {
+            FileChange::Added(path, _) | FileChange::Removed(path, _) => path,
+            FileChange::Changed { path, .. } => path,
+        }
+    }
+
+    /// Change in size of regular file contents, in bytes
+    pub fn size_delta(&self) -> i64 {
+        let size = |file: &File| match file {
+            File::Regular { size, .. } => size.unwrap_or_default() as i64,
+            _ => 0,
+        };
+
+        match self {
+            FileChange::Added(_, file) => size(file),
+            FileChange::Removed(_, file) => -size(file),
+            FileChange::Changed { old, new, .. } => size(new) - size(old),
+        }
+    }
+}
+
+/// Differences between two packages
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diff {
+    pub fields: Vec<Field>,
+    pub dependencies: Changes<crate::Dependency>,
+    pub providers: Changes<crate::Provider>,
+    pub conflicts: Changes<crate::Provider>,
+    pub files: Vec<FileChange>,
+}
+
+impl Diff {
+    /// Compare `old` to `new`
+    pub fn new(old: &Contents, new: &Contents) -> Self {
+        let (a, b) = (&old.meta, &new.meta);
+
+        let fields = [
+            ("name", a.name.to_string(), b.name.to_string()),
+            ("version", a.version_identifier.clone(), b.version_identifier.clone()),
+            (
+                "source-release",
+                a.source_release.to_string(),
+                b.source_release.to_string(),
+            ),
+            (
+                "build-release",
+                a.build_release.to_string(),
+                b.build_release.to_string(),
+            ),
+            ("architecture", a.architecture.clone(), b.architecture.clone()),
+            ("summary", a.summary.clone(), b.summary.clone()),
+            ("description", a.description.clone(), b.description.clone()),
+            ("source-id", a.source_id.clone(), b.source_id.clone()),
+            ("homepage", a.homepage.clone(), b.homepage.clone()),
+            ("licenses", a.licenses.join(", "), b.licenses.join(", ")),
+        ]
+        .into_iter()
+        .filter(|(_, old, new)| old != new)
+        .map(|(name, old, new)| Field { name, old, new })
+        .collect();
+
+        let paths = old.files.keys().chain(new.files.keys()).collect::<BTreeSet<_>>();
+        let files = paths
+            .into_iter()
+            .filter_map(|path| match (old.files.get(path), new.files.get(path)) {
+                (None, Some(file)) => Some(FileChange::Added(path.clone(), file.clone())),
+                (Some(file), None) => Some(FileChange::Removed(path.clone(), file.clone())),
+                (Some(old), Some(new)) if old != new => Some(FileChange::Changed {
+                    path: path.clone(),
+                    old: old.clone(),
+                    new: new.clone(),
+                }),
+                _ => None,
+            })
+            .collect();
+
+        Self {
+            fields,
+            dependencies: Changes::between(&a.dependencies, &b.dependencies),
+            providers: Changes::between(&a.providers, &b.providers),
+            conflicts: Changes::between(&a.conflicts, &b.conflicts),
+            files,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+            && self.dependencies.is_empty()
+            && self.providers.is_empty()
+            && self.conflicts.is_empty()
+            && self.files.is_empty()
+    }
+
+    /// Change in total size of regular file contents, in bytes
+    pub fn size_delta(&self) -> i64 {
+        self.files.iter().map(FileChange::size_delta).sum()
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("io")]
+    Io(#[from] io::Error),
+    #[error("read stone")]
+    Read(#[from] stone::read::Error),
+    #[error("missing meta payload")]
+    MissingMeta,
+    #[error("metadata")]
+    MissingMetaField(#[from] MissingMetaFieldError),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Dependency;
+
+    fn contents(files: &[(&str, File)], dependencies: &[&str]) -> Contents {
+        Contents {
+            meta: Meta {
+                version_identifier: "8.0".to_string(),
+                dependencies: dependencies
+                    .iter()
+                    .map(|name| Dependency::from_name(name).unwrap())
+                    .collect(),
+                ..super::meta::fixture("nano")
+            },
+            files: files
+                .iter()
+                .map(|(path, file)| (path.to_string(), file.clone()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn diff() {
+        let regular = |hash, size| File::Regular { hash, size: Some(size) };
+
+        let old = contents(
+            &[
+                ("bin/nano", regular(1, 100)),
+                ("share/doc/nano", File::Directory),
+                ("share/nano/old.nanorc", regular(2, 10)),
+            ],
+            &["soname(libc.so.6(x86_64))", "soname(libncursesw.so.6(x86_64))"],
+        );
+        let mut new = contents(
+            &[
+                ("bin/nano", regular(3, 150)),
+                ("bin/rnano", File::Symlink("nano".to_string())),
+                ("share/doc/nano", File::Directory),
+            ],
+            &["soname(libc.so.6(x86_64))", "soname(libmagic.so.1(x86_64))"],
+        );
+        new.meta.source_release = 2;
+
+        let diff = Diff::new(&old, &new);
+
+        assert_eq!(
+            diff.fields,
+            vec![Field {
+                name: "source-release",
+                old: "1".to_string(),
+                new: "2".to_string()
+            }]
+        );
+        assert_eq!(
+            diff.dependencies.added,
+            vec![Dependency::from_name("soname(libmagic.so.1(x86_64))").unwrap()]
+        );
+        assert_eq!(
+            diff.dependencies.removed,
+            vec![Dependency::from_name("soname(libncursesw.so.6(x86_64))").unwrap()]
+        );
+        assert_eq!(
+            diff.files.iter().map(FileChange::path).collect::<Vec<_>>(),
+            vec!["bin/nano", "bin/rnano", "share/nano/old.nanorc"]
+        );
+        assert_eq!(diff.size_delta(), 40);
+
+        assert!(Diff::new(&old, &old).is_empty());
+    }
+}
@@ -0,0 +1,135 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use regex::Regex;
+
+use super::Meta;
+
+/// A keyword to search package names, summaries & descriptions for
+#[derive(Debug, Clone)]
+pub enum Keyword {
+    /// Case insensitive substring match
+    Substring(String),
+    /// Regular expression match
+    Regex(Regex),
+}
+
+impl Keyword {
+    pub fn substring(keyword: impl ToString) -> Self {
+        Self::Substring(keyword.to_string())
+    }
+
+    pub fn regex(pattern: &str) -> Result<Self, regex::Error> {
+        Ok(Self::Regex(Regex::new(pattern)?))
+    }
+
+    /// Returns true if the keyword matches the package metadata
+    pub fn matches(&self, meta: &Meta) -> bool {
+        self.rank(meta).is_some()
+    }
+
+    /// How well the keyword matches the package metadata, if at all
+    pub fn rank(&self, meta: &Meta) -> Option<Rank> {
+        let name: &String = meta.name.as_ref();
+
+        let (exact, prefix) = match self {
+            Keyword::Substring(keyword) => {
+                let name = name.to_lowercase();
+                let keyword = keyword.to_lowercase();
+                (name == keyword, name.starts_with(&keyword))
+            }
+            Keyword::Regex(regex) => match regex.find(name) {
+                Some(found) => (found.range() == (0..name.len()), found.start() == 0),
+                None => (false, false),
+            },
+        };
+
+        if exact {
+            Some(Rank::ExactName)
+        } else if prefix {
+            Some(Rank::NamePrefix)
+        } else if self.is_match(name) {
+            Some(Rank::Name)
+        } else if meta
+            .providers
+            .iter()
+            .any(|provider| self.is_match(&provider.to_string()))
+        {
+            Some(Rank::Provider)
+        } else if self.is_match(&meta.summary) {
+            Some(Rank::Summary)
+        } else if self.is_match(&meta.description) {
+            Some(Rank::Description)
+        } else {
+            None
+        }
+    }
+
+    fn is_match(&self, field: &str) -> bool {
+        match self {
+            Keyword::Substring(keyword) => field.to_lowercase().contains(&keyword.to_lowercase()),
+            Keyword::Regex(regex) => regex.is_match(field),
+        }
+    }
+}
+
+/// Where a [`Keyword`] matched, ordered from the most to the least relevant
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Rank {
+    /// The whole name matched
+    ExactName,
+    /// The start of the name matched
+    NamePrefix,
+    /// Part of the name matched
+    Name,
+    /// One of the providers matched, i.e. a shared library or binary
+    Provider,
+    Summary,
+    Description,
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use super::*;
+    use crate::Provider;
+
+    fn meta(name: &str, providers: &[&str], summary: &str, description: &str) -> Meta {
+        Meta {
+            summary: summary.into(),
+            description: description.into(),
+            providers: providers
+                .iter()
+                .map(|provider| Provider::from_name(provider).unwrap())
+                .collect::<BTreeSet<_>>(),
+            ..super::meta::fixture(name)
+        }
+    }
+
+    #[test]
+    fn rank() {
+        let zlib = meta("zlib", &["soname(libz.so.1(x86_64))"], "Compression library", "");
+        let zlib_devel = meta("zlib-devel", &[], "Development files for zlib", "");
+        let curl = meta("curl", &[], "Transfer data with URLs", "Supports zlib compression");
+        let minizip = meta("minizip", &["binary(miniunzip)"], "Zip manipulation", "");
+
+        let keyword = Keyword::substring("ZLIB");
+        assert_eq!(keyword.rank(&zlib), Some(Rank::ExactName));
+        assert_eq!(keyword.rank(&zlib_devel), Some(Rank::NamePrefix));
+        assert_eq!(keyword.rank(&curl), Some(Rank::Description));
+        assert_eq!(keyword.rank(&minizip), None);
+
+        assert_eq!(Keyword::substring("libz.so").rank(&zlib), Some(Rank::Provider));
+        assert_eq!(Keyword::substring("unzip").rank(&minizip), Some(Rank::Provider));
+        assert_eq!(Keyword::substring("libdev").rank(&zlib_devel), None);
+        assert_eq!(Keyword::substring("devel").rank(&zlib_devel), Some(Rank::Name));
+        assert_eq!(Keyword::substring("urls").rank(&curl), Some(Rank::Summary));
+
+        let regex = Keyword::regex("^zlib").unwrap();
+        assert_eq!(regex.rank(&zlib), Some(Rank::ExactName));
+        assert_eq!(regex.rank(&zlib_devel), Some(Rank::NamePrefix));
+        assert_eq!(Keyword::regex("lib").unwrap().rank(&zlib), Some(Rank::Name));
+    }
+}
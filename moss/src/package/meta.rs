@@ -2,7 +2,7 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::collections::BTreeSet;
+use std::{collections::BTreeSet, fmt, str::FromStr};
 
 use derive_more::{AsRef, Display, From, Into};
 use stone::payload;
@@ -53,12 +53,99 @@ pub struct Meta {
     pub providers: BTreeSet<Provider>,
     /// All providers that conflict with this package
     pub conflicts: BTreeSet<Provider>,
+    /// Providers superseded by this package, installed packages
+    /// matching these are swapped for this one on sync
+    pub replaces: BTreeSet<Provider>,
     /// If relevant: uri to fetch from
     pub uri: Option<String>,
     /// If relevant: hash for the download
     pub hash: Option<String>,
     /// How big is this package in the repo..?
     pub download_size: Option<u64>,
+    /// Size of the package content once unpacked
+    pub installed_size: Option<u64>,
+    /// Delta packages to reconstruct this package from older releases
+    pub deltas: BTreeSet<Delta>,
+    /// Triggers shipped by this package, run alongside those of the system
+    pub triggers: BTreeSet<Trigger>,
+}
+
+/// A delta package, carrying only the content which changed since
+/// the release it applies to
+///
+/// Encoded as `{from} {hash} {size} {uri}` in the repository index
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Delta {
+    /// Hash of the package release this delta applies to
+    pub from: String,
+    /// Hash of the delta package itself
+    pub hash: String,
+    /// Download size of the delta package
+    pub size: u64,
+    /// Uri to fetch the delta package from
+    pub uri: String,
+}
+
+impl fmt::Display for Delta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {} {}", self.from, self.hash, self.size, self.uri)
+    }
+}
+
+impl FromStr for Delta {
+    type Err = ParseDeltaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.splitn(4, ' ');
+        let mut field = || fields.next().filter(|f| !f.is_empty()).ok_or(ParseDeltaError);
+
+        Ok(Self {
+            from: field()?.to_string(),
+            hash: field()?.to_string(),
+            size: field()?.parse().map_err(|_| ParseDeltaError)?,
+            uri: field()?.to_string(),
+        })
+    }
+}
+
+impl TryFrom<String> for Delta {
+    type Error = ParseDeltaError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("Invalid delta")]
+pub struct ParseDeltaError;
+
+/// When a [`Trigger`] runs, see [`crate::client::postblit`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, strum::Display, strum::EnumString)]
+#[strum(serialize_all = "kebab-case")]
+pub enum TriggerScope {
+    /// Run against the staged `/usr` before it's activated
+    Transaction,
+    /// Run against the root once the new `/usr` is activated
+    System,
+}
+
+impl TryFrom<String> for TriggerScope {
+    type Error = strum::ParseError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// A trigger declared by a package
+///
+/// The definition is kept in the same YAML format as the trigger files
+/// under `/usr/share/moss/triggers` and is only validated when run
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Trigger {
+    pub scope: TriggerScope,
+    pub definition: String,
 }
 
 impl Meta {
@@ -75,6 +162,7 @@ impl Meta {
         let uri = find_meta_string(payload, payload::meta::Tag::PackageURI).ok();
         let hash = find_meta_string(payload, payload::meta::Tag::PackageHash).ok();
         let download_size = find_meta_u64(payload, payload::meta::Tag::PackageSize).ok();
+        let installed_size = find_meta_u64(payload, payload::meta::Tag::InstalledSize).ok();
 
         let licenses = payload
             .iter()
@@ -91,6 +179,13 @@ impl Meta {
             }))
             .collect();
         let conflicts = payload.iter().filter_map(meta_conflict).collect();
+        let replaces = payload.iter().filter_map(meta_replaces).collect();
+        let deltas = payload
+            .iter()
+            .filter_map(|meta| meta_string(meta, payload::meta::Tag::Delta))
+            .filter_map(|delta| delta.parse().ok())
+            .collect();
+        let triggers = payload.iter().filter_map(meta_trigger).collect();
 
         Ok(Meta {
             name: Name::from(name),
@@ -106,9 +201,13 @@ impl Meta {
             dependencies,
             providers,
             conflicts,
+            replaces,
             uri,
             hash,
             download_size,
+            installed_size,
+            deltas,
+            triggers,
         })
     }
 
@@ -130,6 +229,7 @@ impl Meta {
         .chain(self.uri.map(|uri| (Tag::PackageURI, Kind::String(uri))))
         .chain(self.hash.map(|hash| (Tag::PackageHash, Kind::String(hash))))
         .chain(self.download_size.map(|size| (Tag::PackageSize, Kind::Uint64(size))))
+        .chain(self.installed_size.map(|size| (Tag::InstalledSize, Kind::Uint64(size))))
         .chain(
             self.licenses
                 .into_iter()
@@ -153,6 +253,23 @@ impl Meta {
                 // We re-add this on ingestion / it's implied
                 .map(|conflict| (Tag::Conflicts, Kind::Provider(conflict.kind.into(), conflict.name))),
         )
+        .chain(
+            self.replaces
+                .into_iter()
+                .map(|replaces| (Tag::Replaces, Kind::Provider(replaces.kind.into(), replaces.name))),
+        )
+        .chain(
+            self.deltas
+                .into_iter()
+                .map(|delta| (Tag::Delta, Kind::String(delta.to_string()))),
+        )
+        .chain(self.triggers.into_iter().map(|trigger| {
+            let tag = match trigger.scope {
+                TriggerScope::Transaction => Tag::TransactionTrigger,
+                TriggerScope::System => Tag::SystemTrigger,
+            };
+            (tag, Kind::String(trigger.definition))
+        }))
         .map(|(tag, kind)| payload::Meta { tag, kind })
         .collect()
     }
@@ -234,6 +351,57 @@ fn meta_conflict(meta: &payload::Meta) -> Option<Provider> {
     }
 }
 
+fn meta_replaces(meta: &payload::Meta) -> Option<Provider> {
+    match (meta.tag, meta.kind.clone()) {
+        (payload::meta::Tag::Replaces, payload::meta::Kind::Provider(kind, name)) => Some(Provider {
+            kind: dependency::Kind::from(kind),
+            name: name.clone(),
+        }),
+        _ => None,
+    }
+}
+
+fn meta_trigger(meta: &payload::Meta) -> Option<Trigger> {
+    let scope = match meta.tag {
+        payload::meta::Tag::TransactionTrigger => TriggerScope::Transaction,
+        payload::meta::Tag::SystemTrigger => TriggerScope::System,
+        _ => return None,
+    };
+
+    Some(Trigger {
+        scope,
+        definition: meta_string(meta, meta.tag)?,
+    })
+}
+
+/// Release 1 of a package named `name`, with nothing but its identity, for tests
+/// to fill in whatever else they need
+#[cfg(test)]
+pub(crate) fn fixture(name: &str) -> Meta {
+    Meta {
+        name: Name::from(name.to_string()),
+        version_identifier: "1.0".into(),
+        source_release: 1,
+        build_release: 1,
+        architecture: "x86_64".into(),
+        summary: Default::default(),
+        description: Default::default(),
+        source_id: name.into(),
+        homepage: Default::default(),
+        licenses: Default::default(),
+        dependencies: Default::default(),
+        providers: Default::default(),
+        conflicts: Default::default(),
+        replaces: Default::default(),
+        uri: None,
+        hash: None,
+        download_size: None,
+        installed_size: None,
+        deltas: Default::default(),
+        triggers: Default::default(),
+    }
+}
+
 #[derive(Debug, Error)]
 #[error("Missing metadata field: {0:?}")]
 pub struct MissingMetaFieldError(pub payload::meta::Tag);
@@ -5,8 +5,12 @@
 use derive_more::{AsRef, Display, From, Into};
 use itertools::Itertools;
 
-pub use self::meta::{Meta, MissingMetaFieldError, Name};
+pub use self::keyword::{Keyword, Rank};
+pub use self::meta::{Delta, Meta, MissingMetaFieldError, Name, Trigger, TriggerScope};
 
+pub mod delta;
+pub mod diff;
+pub mod keyword;
 pub mod meta;
 pub mod render;
 
@@ -0,0 +1,66 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Confirmation prompts before altering the system
+//!
+//! Prompting is skipped when `--yes` is passed, when configured to assume yes
+//! or when not attached to a terminal, so scripts never block on input.
+
+use std::{
+    io::{self, IsTerminal},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use tui::dialoguer::{theme::ColorfulTheme, Confirm};
+
+use crate::messages;
+
+/// Whether every question is answered with yes, see [`set_assume_yes`]
+static ASSUME_YES: AtomicBool = AtomicBool::new(false);
+
+/// Answer every confirmation with yes
+pub fn set_assume_yes(yes: bool) {
+    ASSUME_YES.store(yes, Ordering::Relaxed);
+}
+
+/// Whether we're attached to a terminal to ask questions on
+pub fn is_interactive() -> bool {
+    io::stdin().is_terminal() && io::stdout().is_terminal()
+}
+
+/// Ask whether to continue, unless `yes` or [`set_assume_yes`]
+/// were given or we're not running interactively
+pub fn confirm(yes: bool) -> Result<bool, tui::dialoguer::Error> {
+    confirm_with(&continue_prompt(), yes)
+}
+
+/// [`confirm`] with a custom prompt
+pub fn confirm_with(prompt: &str, yes: bool) -> Result<bool, tui::dialoguer::Error> {
+    if yes || ASSUME_YES.load(Ordering::Relaxed) || !is_interactive() {
+        return Ok(true);
+    }
+
+    ask(prompt)
+}
+
+/// Ask whether to continue, even when assuming yes. Declines when
+/// not running interactively, as nobody is there to confirm.
+pub fn confirm_always() -> Result<bool, tui::dialoguer::Error> {
+    if !is_interactive() {
+        return Ok(false);
+    }
+
+    ask(&continue_prompt())
+}
+
+fn continue_prompt() -> String {
+    format!(" {} ", messages::get("prompt-continue"))
+}
+
+fn ask(prompt: &str) -> Result<bool, tui::dialoguer::Error> {
+    Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .default(false)
+        .interact()
+}
@@ -0,0 +1,163 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Selection between alternative packages providing the same capability
+//!
+//! When a dependency can be satisfied by multiple, differently named packages
+//! (i.e. `binary(cc)`), a configured [`Preference`] decides. Without one, the
+//! user is asked if running interactively, otherwise the candidate from the
+//! highest priority source wins.
+
+use std::{collections::BTreeMap, sync::Mutex};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tui::dialoguer::{theme::ColorfulTheme, Select};
+
+use config::Config;
+
+use crate::{package, prompt, Package, Provider};
+
+/// A preferred package for a provider, stored as `etc/moss/alternative.d/{name}.yaml`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Preference {
+    /// Provider with alternatives, i.e. `binary(cc)`
+    pub provider: String,
+    /// Name of the package to select
+    pub package: String,
+}
+
+impl Config for Preference {
+    fn domain() -> String {
+        "alternative".into()
+    }
+}
+
+/// Selection policy for ambiguous providers
+#[derive(Debug, Default)]
+pub struct Alternatives {
+    preferences: BTreeMap<Provider, package::Name>,
+    /// Ask the user when there's no preference
+    interactive: bool,
+    /// Interactive choices, so each provider is only asked for once
+    chosen: Mutex<BTreeMap<Provider, package::Name>>,
+}
+
+impl Alternatives {
+    pub fn new(preferences: impl IntoIterator<Item = Preference>, interactive: bool) -> Self {
+        Self {
+            preferences: preferences
+                .into_iter()
+                .filter_map(|preference| {
+                    let provider = Provider::from_name(&preference.provider).ok()?;
+                    Some((provider, package::Name::from(preference.package)))
+                })
+                .collect(),
+            interactive,
+            chosen: Mutex::default(),
+        }
+    }
+
+    /// Name of the package preferred for `provider`, if configured
+    pub fn preferred(&self, provider: &Provider) -> Option<&package::Name> {
+        self.preferences.get(provider)
+    }
+
+    /// Select one of the `candidates` for `provider`, ordered by priority
+    pub fn select<'a>(&self, provider: &Provider, candidates: &'a [Package]) -> Result<Option<&'a Package>, Error> {
+        let by_name = |name: &package::Name| candidates.iter().find(|p| p.meta.name == *name);
+
+        if let Some(preferred) = self.preferred(provider).and_then(by_name) {
+            return Ok(Some(preferred));
+        }
+
+        if candidates.len() < 2 || !self.interactive || !prompt::is_interactive() {
+            return Ok(candidates.first());
+        }
+
+        let mut chosen = self.chosen.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(package) = chosen.get(provider).and_then(by_name) {
+            return Ok(Some(package));
+        }
+
+        let items = candidates
+            .iter()
+            .map(|p| {
+                format!(
+                    "{} {}-{}",
+                    p.meta.name, p.meta.version_identifier, p.meta.source_release
+                )
+            })
+            .collect::<Vec<_>>();
+        let index = Select::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!("Multiple packages provide {provider}, select one"))
+            .items(&items)
+            .default(0)
+            .interact()?;
+
+        let package = &candidates[index];
+        chosen.insert(provider.clone(), package.meta.name.clone());
+
+        Ok(Some(package))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("dialog")]
+    Dialog(#[from] tui::dialoguer::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn package(name: &str) -> Package {
+        Package {
+            id: package::Id::from(format!("{name}-id")),
+            meta: package::meta::fixture(name),
+            flags: package::Flags::new().with_available(),
+        }
+    }
+
+    #[test]
+    fn preference() {
+        let cc = Provider::from_name("binary(cc)").unwrap();
+        let candidates = [package("gcc"), package("clang")];
+
+        // Priority order by default
+        let alternatives = Alternatives::default();
+        assert_eq!(
+            alternatives
+                .select(&cc, &candidates)
+                .unwrap()
+                .map(|p| p.meta.name.to_string()),
+            Some("gcc".to_string())
+        );
+
+        let alternatives = Alternatives::new(
+            [Preference {
+                provider: "binary(cc)".to_string(),
+                package: "clang".to_string(),
+            }],
+            false,
+        );
+        assert_eq!(
+            alternatives
+                .select(&cc, &candidates)
+                .unwrap()
+                .map(|p| p.meta.name.to_string()),
+            Some("clang".to_string())
+        );
+
+        // Preferred package unavailable, fall back to priority
+        assert_eq!(
+            alternatives
+                .select(&cc, &candidates[..1])
+                .unwrap()
+                .map(|p| p.meta.name.to_string()),
+            Some("gcc".to_string())
+        );
+    }
+}
@@ -0,0 +1,228 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Conflict & replaces handling between incoming and installed packages
+//!
+//! A package conflicts with another when any of its `conflicts` or
+//! `replaces` providers is provided by the other package. Installed
+//! packages in conflict with incoming ones are dropped, along with
+//! everything depending on them, unless the incoming packages still
+//! require them.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use thiserror::Error;
+
+use crate::{
+    package::{self, Package},
+    registry::transaction,
+    Registry,
+};
+
+/// Whether `package` conflicts with, or replaces, `other`
+pub fn conflicts(package: &Package, other: &Package) -> bool {
+    package.meta.name != other.meta.name
+        && package
+            .meta
+            .conflicts
+            .iter()
+            .chain(&package.meta.replaces)
+            .any(|provider| other.meta.providers.contains(provider))
+}
+
+/// Whether `package` replaces `other`
+pub fn replaces(package: &Package, other: &Package) -> bool {
+    package.meta.name != other.meta.name
+        && package
+            .meta
+            .replaces
+            .iter()
+            .any(|provider| other.meta.providers.contains(provider))
+}
+
+/// Resolve conflicts of `incoming` packages with the `kept` packages of
+/// the current state, returning the kept packages which must be dropped
+pub fn resolve<'a>(
+    registry: &Registry,
+    incoming: impl IntoIterator<Item = &'a Package>,
+    kept: &[Package],
+) -> Result<Vec<Package>, Error> {
+    let incoming = incoming.into_iter().collect::<Vec<_>>();
+
+    // Nothing we can drop, give up on the first conflict
+    for (a, b) in incoming.iter().flat_map(|a| incoming.iter().map(move |b| (a, b))) {
+        if conflicts(a, b) {
+            return Err(Error::Incoming(a.meta.name.clone(), b.meta.name.clone()));
+        }
+    }
+
+    let conflicting = kept
+        .iter()
+        .filter(|k| incoming.iter().any(|i| conflicts(i, k) || conflicts(k, i)))
+        .map(|k| k.id.clone())
+        .collect::<Vec<_>>();
+
+    if conflicting.is_empty() {
+        return Ok(vec![]);
+    }
+
+    // Drop conflicting packages & everything depending on them
+    let mut tx = registry.transaction_with_installed(kept.iter().map(|p| p.id.clone()).collect())?;
+    tx.remove(conflicting);
+    let remaining = tx.finalize().cloned().collect::<BTreeSet<_>>();
+
+    let dropped = kept
+        .iter()
+        .filter(|p| !remaining.contains(&p.id))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    // Incoming packages may still need what we'd drop
+    let mut tx = registry.transaction()?;
+    tx.add(incoming.iter().map(|p| p.id.clone()).chain(remaining).collect())?;
+    let required = tx.finalize().collect::<BTreeSet<_>>();
+
+    let still_required = dropped
+        .iter()
+        .filter(|p| required.contains(&p.id))
+        .map(|p| p.meta.name.clone())
+        .collect::<Vec<_>>();
+    if !still_required.is_empty() {
+        return Err(Error::Required(still_required));
+    }
+
+    Ok(dropped)
+}
+
+/// Available packages replacing any of the `installed` packages, keyed
+/// by the name of the package they replace
+pub fn replacements(registry: &Registry, installed: &[Package]) -> BTreeMap<package::Name, Package> {
+    let mut replacements = BTreeMap::new();
+
+    for candidate in registry
+        .list_available(package::Flags::default())
+        .filter(|p| !p.meta.replaces.is_empty())
+    {
+        // Already installed, the replacement happened before
+        if installed.iter().any(|i| i.meta.name == candidate.meta.name) {
+            continue;
+        }
+
+        for replaced in installed.iter().filter(|i| replaces(&candidate, i)) {
+            // Highest priority candidate comes first
+            replacements
+                .entry(replaced.meta.name.clone())
+                .or_insert_with(|| candidate.clone());
+        }
+    }
+
+    replacements
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("{0} conflicts with {1}")]
+    Incoming(package::Name, package::Name),
+
+    #[error("conflicting packages are still required: {}", .0.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "))]
+    Required(Vec<package::Name>),
+
+    #[error("transaction")]
+    Transaction(#[from] transaction::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{registry::plugin, Dependency, Provider};
+
+    fn package(name: &str, flags: package::Flags) -> Package {
+        Package {
+            id: package::Id::from(format!("{name}-id")),
+            meta: package::Meta {
+                providers: BTreeSet::from([Provider::from_name(name).unwrap()]),
+                ..package::meta::fixture(name)
+            },
+            flags,
+        }
+    }
+
+    fn depends(mut package: Package, name: &str) -> Package {
+        package.meta.dependencies.insert(Dependency::from_name(name).unwrap());
+        package
+    }
+
+    fn names(packages: &[Package]) -> Vec<String> {
+        packages.iter().map(|p| p.meta.name.to_string()).collect()
+    }
+
+    #[test]
+    fn drops_conflicting_and_dependents() {
+        let installed = package::Flags::new().with_installed();
+        let available = package::Flags::new().with_available();
+
+        let pulseaudio = package("pulseaudio", installed);
+        let pavucontrol = depends(package("pavucontrol", installed), "pulseaudio");
+        let nano = package("nano", installed);
+        let kept = vec![pulseaudio, pavucontrol, nano];
+
+        let mut pipewire = package("pipewire", available);
+        pipewire
+            .meta
+            .conflicts
+            .insert(Provider::from_name("pulseaudio").unwrap());
+        let wireplumber = depends(package("wireplumber", available), "nano");
+        let mut editor = package("editor", available);
+        editor.meta.replaces.insert(Provider::from_name("nano").unwrap());
+
+        let mut registry = Registry::default();
+        registry.add_plugin(plugin::Plugin::Test(plugin::test::Test::new(
+            1,
+            kept.iter()
+                .cloned()
+                .chain([pipewire.clone(), wireplumber.clone(), editor.clone()])
+                .collect(),
+        )));
+
+        let dropped = resolve(&registry, [&pipewire], &kept).unwrap();
+        assert_eq!(names(&dropped), vec!["pulseaudio", "pavucontrol"]);
+
+        // Can't drop what incoming packages need
+        assert!(matches!(
+            resolve(&registry, [&wireplumber, &editor], &kept),
+            Err(Error::Required(names)) if names == vec![package::Name::from("nano".to_string())]
+        ));
+
+        // Nor resolve conflicts between incoming packages
+        let mut other = package("other", available);
+        other.meta.conflicts.insert(Provider::from_name("pipewire").unwrap());
+        assert!(matches!(
+            resolve(&registry, [&pipewire, &other], &kept),
+            Err(Error::Incoming(..))
+        ));
+    }
+
+    #[test]
+    fn replacements() {
+        let installed = package::Flags::new().with_installed();
+        let available = package::Flags::new().with_available();
+
+        let old = package("libfoo", installed);
+        let mut new = package("libfoo2", available);
+        new.meta.replaces.insert(Provider::from_name("libfoo").unwrap());
+
+        let mut registry = Registry::default();
+        registry.add_plugin(plugin::Plugin::Test(plugin::test::Test::new(
+            1,
+            vec![old.clone(), new.clone()],
+        )));
+
+        let replacements = super::replacements(&registry, std::slice::from_ref(&old));
+        assert_eq!(replacements.get(&old.meta.name).map(|p| &p.id), Some(&new.id));
+
+        // Replacing implies conflicting
+        assert!(conflicts(&new, &old));
+        assert!(!conflicts(&old, &new));
+    }
+}
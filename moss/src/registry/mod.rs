@@ -8,11 +8,14 @@
 use itertools::Itertools;
 
 use crate::package::{self, Package};
-use crate::Provider;
+use crate::{Dependency, Provider};
 
+pub use self::alternatives::Alternatives;
 pub use self::plugin::Plugin;
 pub use self::transaction::Transaction;
 
+pub mod alternatives;
+pub mod conflict;
 pub mod plugin;
 pub mod transaction;
 
@@ -22,6 +25,9 @@ pub mod transaction;
 pub struct Registry {
     /// Ordered set of plugins
     plugins: Vec<Plugin>,
+
+    /// Selection policy for providers with alternatives
+    alternatives: Alternatives,
 }
 
 impl Registry {
@@ -30,6 +36,16 @@ impl Registry {
         self.plugins.push(plugin);
     }
 
+    /// Set the [`Alternatives`] policy used by transactions
+    pub fn set_alternatives(&mut self, alternatives: Alternatives) {
+        self.alternatives = alternatives;
+    }
+
+    /// The [`Alternatives`] policy used by transactions
+    pub fn alternatives(&self) -> &Alternatives {
+        &self.alternatives
+    }
+
     fn query<'a, T, I>(&'a self, query: impl Fn(&'a Plugin) -> I + Copy + 'a) -> impl Iterator<Item = T> + 'a
     where
         I: IntoIterator<Item = T> + 'a,
@@ -58,6 +74,15 @@ impl Registry {
         self.query(move |plugin| plugin.query_provider_id_only(provider, flags))
     }
 
+    /// Return a sorted stream of [`Package`] depending on `dependency`
+    pub fn by_dependency<'a>(
+        &'a self,
+        dependency: &'a Dependency,
+        flags: package::Flags,
+    ) -> impl Iterator<Item = Package> + 'a {
+        self.query(move |plugin| plugin.query_dependency(dependency, flags))
+    }
+
     /// Return a sorted stream of [`Package`] by name
     pub fn by_name<'a>(
         &'a self,
@@ -72,10 +97,27 @@ impl Registry {
         self.query(move |plugin| plugin.package(id))
     }
 
-    pub fn by_keyword<'a>(&'a self, keyword: &'a str, flags: package::Flags) -> impl Iterator<Item = Package> + 'a {
+    /// Return a sorted stream of [`Package`] matching the [`package::Keyword`]
+    pub fn by_keyword<'a>(
+        &'a self,
+        keyword: &'a package::Keyword,
+        flags: package::Flags,
+    ) -> impl Iterator<Item = Package> + 'a {
         self.query(move |plugin| plugin.query_keyword(keyword, flags))
     }
 
+    /// Return a stream of [`Package`] pages matching the given [`Flags`], and
+    /// `keyword` if set, without loading every package up front
+    ///
+    /// [`Flags`]: package::Flags
+    pub fn pages<'a>(
+        &'a self,
+        keyword: Option<&'a package::Keyword>,
+        flags: package::Flags,
+    ) -> impl Iterator<Item = Vec<Package>> + 'a {
+        self.query(move |plugin| plugin.pages(keyword, flags))
+    }
+
     /// Return a sorted stream of [`Package`] matching the given [`Flags`]
     ///
     /// [`Flags`]: package::Flags
@@ -120,22 +162,8 @@ mod test {
         let package = |id: &str, release| Package {
             id: package::Id::from(id.to_string()),
             meta: package::Meta {
-                name: package::Name::from(id.to_string()),
-                version_identifier: Default::default(),
                 source_release: release,
-                build_release: Default::default(),
-                architecture: Default::default(),
-                summary: Default::default(),
-                description: Default::default(),
-                source_id: Default::default(),
-                homepage: Default::default(),
-                licenses: Default::default(),
-                dependencies: Default::default(),
-                providers: Default::default(),
-                conflicts: Default::default(),
-                uri: Default::default(),
-                hash: Default::default(),
-                download_size: Default::default(),
+                ..package::meta::fixture(id)
             },
             flags: package::Flags::default(),
         };
@@ -174,24 +202,7 @@ mod test {
 
         let package = |id: &str, flags| Package {
             id: package::Id::from(id.to_string()),
-            meta: package::Meta {
-                name: package::Name::from(id.to_string()),
-                version_identifier: Default::default(),
-                source_release: Default::default(),
-                build_release: Default::default(),
-                architecture: Default::default(),
-                summary: Default::default(),
-                description: Default::default(),
-                source_id: Default::default(),
-                homepage: Default::default(),
-                licenses: Default::default(),
-                dependencies: Default::default(),
-                providers: Default::default(),
-                conflicts: Default::default(),
-                uri: Default::default(),
-                hash: Default::default(),
-                download_size: Default::default(),
-            },
+            meta: package::meta::fixture(id),
             flags,
         };
 
@@ -2,9 +2,10 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use log::warn;
+use tracing::warn;
 
-use crate::{db, package, Package, Provider, State};
+use super::PAGE_SIZE;
+use crate::{db, package, Dependency, Package, Provider, State};
 
 // TODO:
 #[derive(Debug, Clone)]
@@ -53,26 +54,49 @@ impl Active {
                 }
             };
 
-            packages
-                .into_iter()
-                .filter_map(|(id, meta)| {
-                    self.installed_package(id)
-                        .map(|(id, flags)| Package { id, meta, flags })
-                })
-                // Filter for explicit only packages, if applicable
-                .filter(|package| if flags.explicit { package.flags.explicit } else { true })
-                .collect()
+            self.restrict(packages, flags)
         } else {
             vec![]
         }
     }
 
+    /// Query in pages of [`PAGE_SIZE`], restricted to state
+    pub fn pages<'a>(
+        &'a self,
+        flags: package::Flags,
+        filter: Option<db::meta::Filter<'a>>,
+    ) -> impl Iterator<Item = Vec<Package>> + 'a {
+        (flags.installed || flags == package::Flags::default())
+            .then(|| self.db.query_pages(filter, PAGE_SIZE))
+            .into_iter()
+            .flatten()
+            .map_while(move |page| match page {
+                Ok(packages) => Some(self.restrict(packages, flags)),
+                Err(error) => {
+                    warn!("failed to query repository packages: {error}");
+                    None
+                }
+            })
+    }
+
+    fn restrict(&self, packages: Vec<(package::Id, package::Meta)>, flags: package::Flags) -> Vec<Package> {
+        packages
+            .into_iter()
+            .filter_map(|(id, meta)| {
+                self.installed_package(id)
+                    .map(|(id, flags)| Package { id, meta, flags })
+            })
+            // Filter for explicit only packages, if applicable
+            .filter(|package| if flags.explicit { package.flags.explicit } else { true })
+            .collect()
+    }
+
     /// List, restricted to state
     pub fn list(&self, flags: package::Flags) -> Vec<Package> {
         self.query(flags, None)
     }
 
-    pub fn query_keyword(&self, keyword: &str, flags: package::Flags) -> Vec<Package> {
+    pub fn query_keyword(&self, keyword: &package::Keyword, flags: package::Flags) -> Vec<Package> {
         self.query(flags, Some(db::meta::Filter::Keyword(keyword)))
     }
 
@@ -81,6 +105,11 @@ impl Active {
         self.query(flags, Some(db::meta::Filter::Provider(provider.clone())))
     }
 
+    /// Query all packages depending on the given dependency
+    pub fn query_dependency(&self, dependency: &Dependency, flags: package::Flags) -> Vec<Package> {
+        self.query(flags, Some(db::meta::Filter::Dependency(dependency.clone())))
+    }
+
     /// Query matching by name
     pub fn query_name(&self, package_name: &package::Name, flags: package::Flags) -> Vec<Package> {
         self.query(flags, Some(db::meta::Filter::Name(package_name.clone())))
@@ -5,16 +5,16 @@
 use std::collections::BTreeMap;
 use std::fs::File;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use thiserror::Error;
 
 use stone::read::PayloadKind;
 
 use crate::package::{self, meta, Meta, MissingMetaFieldError, Package};
-use crate::Provider;
+use crate::{Dependency, Provider};
 
-// TODO:
+/// Local `.stone` files, i.e. sideloaded for installation
 #[derive(Default, Debug, Clone, PartialEq, Eq)]
 pub struct Cobble {
     // Storage of local packages
@@ -22,30 +22,37 @@ pub struct Cobble {
 }
 
 impl Cobble {
-    /// Add a package to the cobble set
-    pub fn add_package(&mut self, path: impl Into<PathBuf>) -> Result<meta::Id, Error> {
+    /// Add a package to the cobble set, obtained from `source`
+    /// (i.e. the URL it was downloaded from)
+    pub fn add_package(&mut self, path: impl Into<PathBuf>, source: impl ToString) -> Result<meta::Id, Error> {
         let path = path.into();
         let mut file = File::open(&path)?;
         let mut reader = stone::read(&mut file)?;
-        let mut payloads = reader.payloads()?;
+        let payloads = reader.payloads()?.flatten().collect::<Vec<_>>();
 
         // Grab the metapayload
         let metadata = payloads
-            .find_map(|result| {
-                if let Ok(PayloadKind::Meta(meta)) = result {
-                    Some(meta)
-                } else {
-                    None
-                }
-            })
+            .iter()
+            .find_map(PayloadKind::meta)
             .ok_or(Error::MissingMetaPayload)?;
 
         // Whack it into the cobbler
-        let meta = Meta::from_stone_payload(&metadata.body)?;
+        let mut meta = Meta::from_stone_payload(&metadata.body)?;
+        meta.installed_size = payloads
+            .iter()
+            .find_map(PayloadKind::content)
+            .map(|content| content.header.plain_size);
         let id = meta.id();
         let ret = id.clone();
 
-        self.packages.insert(id, State { path, meta });
+        self.packages.insert(
+            id,
+            State {
+                path,
+                source: source.to_string(),
+                meta,
+            },
+        );
 
         Ok(ret)
     }
@@ -56,6 +63,20 @@ impl Cobble {
         self.packages.get(&meta_id).map(|state| state.package(id.clone()))
     }
 
+    /// Local path of the package with `id`
+    pub fn path(&self, id: &package::Id) -> Option<&Path> {
+        self.state(id).map(|state| state.path.as_path())
+    }
+
+    /// Where the package with `id` was obtained from
+    pub fn source(&self, id: &package::Id) -> Option<&str> {
+        self.state(id).map(|state| state.source.as_str())
+    }
+
+    fn state(&self, id: &package::Id) -> Option<&State> {
+        self.packages.get(&meta::Id::from(id.clone()))
+    }
+
     fn query(&self, flags: package::Flags, filter: impl Fn(&Meta) -> bool) -> Vec<Package> {
         if flags.available {
             self.packages
@@ -72,14 +93,16 @@ impl Cobble {
         self.query(flags, |_| true)
     }
 
-    pub fn query_keyword(&self, keyword: &str, flags: package::Flags) -> Vec<Package> {
-        self.query(flags, |meta| {
-            meta.name.contains(keyword) || meta.summary.contains(keyword)
-        })
+    pub fn query_keyword(&self, keyword: &package::Keyword, flags: package::Flags) -> Vec<Package> {
+        self.query(flags, |meta| keyword.matches(meta))
     }
 
     pub fn query_provider(&self, provider: &Provider, flags: package::Flags) -> Vec<Package> {
-        self.query(flags, |meta| meta.providers.contains(provider))
+        self.query(flags, |meta| meta.providers.iter().any(|p| p.matches(provider)))
+    }
+
+    pub fn query_dependency(&self, dependency: &Dependency, flags: package::Flags) -> Vec<Package> {
+        self.query(flags, |meta| meta.dependencies.iter().any(|d| d.matches(dependency)))
     }
 
     pub fn query_name(&self, package_name: &package::Name, flags: package::Flags) -> Vec<Package> {
@@ -94,6 +117,7 @@ impl Cobble {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct State {
     path: PathBuf,
+    source: String,
     meta: Meta,
 }
 
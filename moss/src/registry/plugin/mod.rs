@@ -11,7 +11,7 @@
 //! [`Registry`]: super::Registry
 
 use crate::registry::package::{self, Package};
-use crate::Provider;
+use crate::{db, Dependency, Provider};
 
 pub use self::active::Active;
 pub use self::cobble::Cobble;
@@ -20,9 +20,12 @@ pub use self::repository::Repository;
 pub use self::test::Test;
 
 mod active;
-mod cobble;
+pub mod cobble;
 mod repository;
 
+/// Number of packages loaded at once by [`Plugin::pages`]
+const PAGE_SIZE: usize = 1000;
+
 /// A [`Registry`] plugin that enables querying [`Package`] information.
 ///
 /// [`Registry`]: super::Registry
@@ -62,7 +65,8 @@ impl Plugin {
         })
     }
 
-    pub fn query_keyword(&self, keyword: &str, flags: package::Flags) -> package::Sorted<Vec<Package>> {
+    /// Returns a list of packages whose name, summary or description match `keyword`
+    pub fn query_keyword(&self, keyword: &package::Keyword, flags: package::Flags) -> package::Sorted<Vec<Package>> {
         package::Sorted::new(match self {
             Plugin::Active(plugin) => plugin.query_keyword(keyword, flags),
             Plugin::Cobble(plugin) => plugin.query_keyword(keyword, flags),
@@ -73,6 +77,32 @@ impl Plugin {
         })
     }
 
+    /// Returns packages with matching `flags`, and `keyword` if set, in pages so
+    /// large databases don't have to be loaded at once
+    pub fn pages<'a>(
+        &'a self,
+        keyword: Option<&'a package::Keyword>,
+        flags: package::Flags,
+    ) -> Box<dyn Iterator<Item = Vec<Package>> + 'a> {
+        let filter = keyword.map(db::meta::Filter::Keyword);
+
+        match self {
+            Plugin::Active(plugin) => Box::new(plugin.pages(flags, filter)),
+            Plugin::Repository(plugin) => Box::new(plugin.pages(flags, filter)),
+            // Held in memory already
+            Plugin::Cobble(plugin) => Box::new(std::iter::once(match keyword {
+                Some(keyword) => plugin.query_keyword(keyword, flags),
+                None => plugin.list(flags),
+            })),
+
+            #[cfg(test)]
+            Plugin::Test(plugin) => Box::new(std::iter::once(match keyword {
+                Some(keyword) => plugin.query_keyword(keyword, flags),
+                None => plugin.list(flags),
+            })),
+        }
+    }
+
     /// Returns a list of packages with matching `provider` and `flags`
     pub fn query_provider(&self, provider: &Provider, flags: package::Flags) -> package::Sorted<Vec<Package>> {
         package::Sorted::new(match self {
@@ -104,6 +134,18 @@ impl Plugin {
         })
     }
 
+    /// Returns a list of packages depending on `dependency` with matching `flags`
+    pub fn query_dependency(&self, dependency: &Dependency, flags: package::Flags) -> package::Sorted<Vec<Package>> {
+        package::Sorted::new(match self {
+            Plugin::Active(plugin) => plugin.query_dependency(dependency, flags),
+            Plugin::Cobble(plugin) => plugin.query_dependency(dependency, flags),
+            Plugin::Repository(plugin) => plugin.query_dependency(dependency, flags),
+
+            #[cfg(test)]
+            Plugin::Test(plugin) => plugin.query_dependency(dependency, flags),
+        })
+    }
+
     /// Returns a list of packages with matching `package_name` and `flags`
     pub fn query_name(&self, package_name: &package::Name, flags: package::Flags) -> package::Sorted<Vec<Package>> {
         package::Sorted::new(match self {
@@ -158,10 +200,10 @@ pub mod test {
                 .collect()
         }
 
-        pub fn query_keyword(&self, keyword: &str, _flags: package::Flags) -> Vec<Package> {
+        pub fn query_keyword(&self, keyword: &package::Keyword, _flags: package::Flags) -> Vec<Package> {
             self.packages
                 .iter()
-                .filter(|pkg| pkg.meta.name.contains(keyword) || pkg.meta.summary.contains(keyword))
+                .filter(|pkg| keyword.matches(&pkg.meta))
                 .cloned()
                 .collect()
         }
@@ -183,6 +225,14 @@ pub mod test {
                 .collect()
         }
 
+        pub fn query_dependency(&self, dependency: &Dependency, flags: package::Flags) -> Vec<Package> {
+            self.packages
+                .iter()
+                .filter(|p| p.meta.dependencies.contains(dependency) && p.flags.contains(flags))
+                .cloned()
+                .collect()
+        }
+
         pub fn query_name(&self, package_name: &package::Name, flags: package::Flags) -> Vec<Package> {
             self.packages
                 .iter()
@@ -2,12 +2,13 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use log::warn;
+use tracing::warn;
 
+use super::PAGE_SIZE;
 use crate::{
     db,
     package::{self, Package},
-    repository, Provider,
+    repository, Dependency, Provider,
 };
 
 #[derive(Debug)]
@@ -37,6 +38,17 @@ impl Repository {
                         .uri
                         .and_then(|relative| self.active.repository.uri.join(&relative).ok())
                         .map(|url| url.to_string()),
+                    deltas: meta
+                        .deltas
+                        .into_iter()
+                        .filter_map(|delta| {
+                            let url = self.active.repository.uri.join(&delta.uri).ok()?;
+                            Some(package::Delta {
+                                uri: url.to_string(),
+                                ..delta
+                            })
+                        })
+                        .collect(),
                     ..meta
                 },
                 flags: package::Flags::new().with_available(),
@@ -60,24 +72,36 @@ impl Repository {
                 }
             };
 
-            packages
-                .into_iter()
-                .map(|(id, meta)| Package {
-                    id,
-                    meta,
-                    flags: package::Flags::new().with_available(),
-                })
-                .collect()
+            available(packages)
         } else {
             vec![]
         }
     }
 
+    /// Query in pages of [`PAGE_SIZE`]
+    pub fn pages<'a>(
+        &'a self,
+        flags: package::Flags,
+        filter: Option<db::meta::Filter<'a>>,
+    ) -> impl Iterator<Item = Vec<Package>> + 'a {
+        (flags.available || flags == package::Flags::default())
+            .then(|| self.active.db.query_pages(filter, PAGE_SIZE))
+            .into_iter()
+            .flatten()
+            .map_while(|page| match page {
+                Ok(packages) => Some(available(packages)),
+                Err(error) => {
+                    warn!("failed to query repository packages: {error}");
+                    None
+                }
+            })
+    }
+
     pub fn list(&self, flags: package::Flags) -> Vec<Package> {
         self.query(flags, None)
     }
 
-    pub fn query_keyword(&self, keyword: &str, flags: package::Flags) -> Vec<Package> {
+    pub fn query_keyword(&self, keyword: &package::Keyword, flags: package::Flags) -> Vec<Package> {
         self.query(flags, Some(db::meta::Filter::Keyword(keyword)))
     }
 
@@ -86,6 +110,11 @@ impl Repository {
         self.query(flags, Some(db::meta::Filter::Provider(provider.clone())))
     }
 
+    /// Query all packages depending on the given dependency
+    pub fn query_dependency(&self, dependency: &Dependency, flags: package::Flags) -> Vec<Package> {
+        self.query(flags, Some(db::meta::Filter::Dependency(dependency.clone())))
+    }
+
     pub fn query_name(&self, package_name: &package::Name, flags: package::Flags) -> Vec<Package> {
         self.query(flags, Some(db::meta::Filter::Name(package_name.clone())))
     }
@@ -106,6 +135,17 @@ impl Repository {
     }
 }
 
+fn available(packages: Vec<(package::Id, package::Meta)>) -> Vec<Package> {
+    packages
+        .into_iter()
+        .map(|(id, meta)| Package {
+            id,
+            meta,
+            flags: package::Flags::new().with_available(),
+        })
+        .collect()
+}
+
 impl PartialEq for Repository {
     fn eq(&self, other: &Self) -> bool {
         self.active.id.eq(&other.active.id)
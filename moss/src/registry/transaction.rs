@@ -3,9 +3,11 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use dag::Dag;
+use itertools::Itertools;
 use thiserror::Error;
+use tracing::{debug, trace};
 
-use crate::{package, Provider, Registry};
+use crate::{package, registry::alternatives, Provider, Registry};
 
 enum ProviderFilter {
     /// Must be installed
@@ -100,10 +102,12 @@ impl<'a> Transaction<'a> {
                     };
 
                     // Now get it resolved
+                    let required = provider.to_string();
                     let search = match lookup {
                         Lookup::Global => self.resolve_installation_provider(provider)?,
                         Lookup::InstalledOnly => self.resolve_provider(ProviderFilter::InstalledOnly(provider))?,
                     };
+                    trace!("{check_id} requires {required}, resolved to {search}");
 
                     // Add dependency node
                     let need_search = !self.packages.node_exists(&search);
@@ -127,11 +131,32 @@ impl<'a> Transaction<'a> {
     /// Attempt to resolve the filterered provider
     fn resolve_provider(&self, filter: ProviderFilter) -> Result<package::Id, Error> {
         match filter {
-            ProviderFilter::All(provider) => self
-                .registry
-                .by_provider_id_only(&provider, package::Flags::new().with_available())
-                .next()
-                .ok_or(Error::NoCandidate(provider.to_string())),
+            ProviderFilter::All(provider) => {
+                let ids = self
+                    .registry
+                    .by_provider_id_only(&provider, package::Flags::new().with_available())
+                    .collect::<Vec<_>>();
+
+                // Differently named packages provide this, select one
+                if ids.len() > 1 {
+                    let candidates = ids
+                        .iter()
+                        .filter_map(|id| self.registry.by_id(id).next())
+                        .unique_by(|p| p.meta.name.to_string())
+                        .collect::<Vec<_>>();
+
+                    if let Some(selected) = self.registry.alternatives().select(&provider, &candidates)? {
+                        debug!(
+                            "selected {} for {provider} out of {} alternatives",
+                            selected.meta.name,
+                            candidates.len()
+                        );
+                        return Ok(selected.id.clone());
+                    }
+                }
+
+                ids.into_iter().next().ok_or(Error::NoCandidate(provider.to_string()))
+            }
             ProviderFilter::InstalledOnly(provider) => self
                 .registry
                 .by_provider_id_only(&provider, package::Flags::new().with_installed())
@@ -163,4 +188,7 @@ pub enum Error {
 
     #[error("meta db")]
     Database(#[from] crate::db::meta::Error),
+
+    #[error("alternatives")]
+    Alternatives(#[from] alternatives::Error),
 }
@@ -0,0 +1,124 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Versioning of the repository index format
+//!
+//! Indexes start with a meta payload describing their [`Format`], so a moss
+//! too old to understand an index can say so instead of failing to parse it.
+//! Indexes without one predate versioning and are treated as version `0`.
+
+use stone::payload::meta;
+use thiserror::Error;
+
+/// Index format version written & understood by this moss
+///
+/// Bump this whenever indexes gain meta tags or payload kinds older stone
+/// decoders can't skip. Version `2` added the format, delta, replaces,
+/// trigger & installed size tags as well as signature payloads
+pub const VERSION: u32 = 2;
+
+/// Oldest moss release able to read [`VERSION`] indexes
+pub const MINIMUM_VERSION: &str = "0.2.0";
+
+/// Format of a repository index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Format {
+    pub version: u32,
+    pub minimum_version: String,
+}
+
+impl Format {
+    /// The format written by this moss
+    pub fn current() -> Self {
+        Self {
+            version: VERSION,
+            minimum_version: MINIMUM_VERSION.to_string(),
+        }
+    }
+
+    /// Parse the format from a meta payload, returning `None`
+    /// if the payload doesn't describe one (i.e. it's a package)
+    pub fn from_stone_payload(payload: &[meta::Meta]) -> Option<Self> {
+        let version = payload.iter().find_map(|meta| match (meta.tag, &meta.kind) {
+            (meta::Tag::RepositoryFormat, meta::Kind::Uint32(version)) => Some(*version),
+            _ => None,
+        })?;
+        let minimum_version = payload
+            .iter()
+            .find_map(|meta| match (meta.tag, &meta.kind) {
+                (meta::Tag::MinimumVersion, meta::Kind::String(version)) => Some(version.clone()),
+                _ => None,
+            })
+            .unwrap_or_default();
+
+        Some(Self {
+            version,
+            minimum_version,
+        })
+    }
+
+    pub fn to_stone_payload(&self) -> Vec<meta::Meta> {
+        vec![
+            meta::Meta {
+                tag: meta::Tag::RepositoryFormat,
+                kind: meta::Kind::Uint32(self.version),
+            },
+            meta::Meta {
+                tag: meta::Tag::MinimumVersion,
+                kind: meta::Kind::String(self.minimum_version.clone()),
+            },
+        ]
+    }
+
+    /// Ensure this moss can read indexes of this format
+    pub fn negotiate(&self) -> Result<(), Error> {
+        if self.version > VERSION {
+            return Err(Error::RequiresNewer {
+                version: self.version,
+                minimum_version: self.minimum_version.clone(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(
+        "this repository requires a newer moss (index format {version}, moss {minimum_version} or later), \
+         supported up to index format {VERSION}"
+    )]
+    RequiresNewer { version: u32, minimum_version: String },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn negotiate() {
+        let current = Format::current();
+        assert_eq!(
+            Format::from_stone_payload(&current.to_stone_payload()),
+            Some(current.clone())
+        );
+        assert!(current.negotiate().is_ok());
+
+        let newer = Format {
+            version: VERSION + 1,
+            minimum_version: "9.9.9".into(),
+        };
+        let error = newer.negotiate().unwrap_err().to_string();
+        assert!(error.contains("requires a newer moss"));
+        assert!(error.contains("9.9.9"));
+
+        // Package payloads don't describe a format
+        let package = vec![meta::Meta {
+            tag: meta::Tag::Name,
+            kind: meta::Kind::String("nano".into()),
+        }];
+        assert_eq!(Format::from_stone_payload(&package), None);
+    }
+}
@@ -6,20 +6,44 @@ use std::collections::BTreeMap;
 use std::fs::{self, File};
 use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
 use std::time::Duration;
 
+use chrono::Utc;
 use futures::{stream, StreamExt, TryStreamExt};
+use hash::{Algorithm, Digest};
 use itertools::Itertools;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use thiserror::Error;
 use xxhash_rust::xxh3::xxh3_64;
 
 use tui::{MultiProgress, ProgressBar, ProgressStyle, Styled};
 
 use crate::db::meta;
-use crate::repository::{self, Repository};
-use crate::{environment, runtime};
+use crate::repository::{
+    self,
+    format::{self, Format},
+    refresh, usage, Repository,
+};
+use crate::{environment, request, runtime};
 use crate::{package, Installation};
 
+/// File name of the repository meta db within its cache dir
+const META_DB: &str = "db";
+
+/// File name of the cached stone index within its cache dir
+const INDEX: &str = "stone.index";
+
+/// Number of parsed batches allowed to queue up for the db writer
+const PARSED_BATCH_BACKLOG: usize = 4;
+
+/// Name of the persisted [`repository::Usage`] file
+const USAGE_NAME: &str = "downloads";
+
+/// Name of the persisted [`refresh::Status`] file
+const REFRESH_NAME: &str = "status";
+
 enum Source {
     System(config::Manager),
     Explicit { identifier: String, repos: repository::Map },
@@ -32,6 +56,21 @@ impl Source {
             Source::Explicit { identifier, .. } => identifier,
         }
     }
+
+    fn configs(&self) -> repository::Map {
+        match self {
+            Source::System(config) =>
+            // Load all configs, default if none exist
+            {
+                config
+                    .load::<repository::Map>()
+                    .into_iter()
+                    .reduce(repository::Map::merge)
+                    .unwrap_or_default()
+            }
+            Source::Explicit { repos, .. } => repos.clone(),
+        }
+    }
 }
 
 /// Manage a bunch of repositories
@@ -68,19 +107,47 @@ impl Manager {
         )
     }
 
-    fn new(source: Source, installation: Installation) -> Result<Self, Error> {
-        let configs = match &source {
-            Source::System(config) =>
-            // Load all configs, default if none exist
-            {
-                config
-                    .load::<repository::Map>()
-                    .into_iter()
-                    .reduce(repository::Map::merge)
-                    .unwrap_or_default()
+    /// Rebuild the meta db of every system repository from its cached index,
+    /// i.e. after one got corrupted. Those without a cached index are left
+    /// empty until refreshed and returned alongside the manager
+    pub fn rebuild(config: config::Manager, installation: Installation) -> Result<(Self, Vec<repository::Id>), Error> {
+        let source = Source::System(config);
+
+        // The existing dbs may not even open, so start from scratch
+        for (_, repository) in source.configs() {
+            let dir = cache_dir(source.identifier(), &repository, &installation);
+
+            for name in [
+                META_DB.to_string(),
+                format!("{META_DB}.new"),
+                format!("{META_DB}-journal"),
+            ] {
+                let result = fs::remove_file(dir.join(name));
+                if let Some(error) = result.err().filter(|error| error.kind() != io::ErrorKind::NotFound) {
+                    return Err(Error::RemoveStaging(error));
+                }
             }
-            Source::Explicit { repos, .. } => repos.clone(),
-        };
+        }
+
+        let manager = Self::new(source, installation)?;
+        let mut missing = vec![];
+
+        for (id, state) in &manager.repositories {
+            let index_path =
+                cache_dir(manager.source.identifier(), &state.repository, &manager.installation).join(INDEX);
+
+            if index_path.exists() {
+                update_meta_db(state, &index_path)?;
+            } else {
+                missing.push(id.clone());
+            }
+        }
+
+        Ok((manager, missing))
+    }
+
+    fn new(source: Source, installation: Installation) -> Result<Self, Error> {
+        let configs = source.configs();
 
         // Open all repo meta dbs and collect into hash map
         let repositories = configs
@@ -121,25 +188,77 @@ impl Manager {
         Ok(())
     }
 
+    /// Add a [`Repository`] for the lifetime of this manager only,
+    /// it's never saved to the configuration
+    pub fn add_transient(&mut self, id: repository::Id, repository: Repository) -> Result<(), Error> {
+        let db = open_meta_db(self.source.identifier(), &repository, &self.installation)?;
+
+        self.repositories
+            .insert(id.clone(), repository::Active { id, repository, db });
+
+        Ok(())
+    }
+
     /// Refresh a [`Repository`] by Id
-    pub async fn refresh(&self, id: &repository::Id) -> Result<(), Error> {
-        if let Some(repo) = self.repositories.get(id).cloned() {
-            let file = fetch_index(self.source.identifier(), &repo, &self.installation).await?;
-            runtime::unblock(move || update_meta_db(&repo, &file)).await?;
-            Ok(())
-        } else {
-            Err(Error::UnknownRepo(id.clone()))
+    ///
+    /// The meta db is only rebuilt when the fetched index differs from the
+    /// one it was last populated from. The outcome is recorded to the
+    /// [`refresh::Status`] of the repository.
+    pub async fn refresh(&self, id: &repository::Id) -> Result<refresh::Outcome, Error> {
+        let repo = self
+            .repositories
+            .get(id)
+            .cloned()
+            .ok_or_else(|| Error::UnknownRepo(id.clone()))?;
+
+        if request::is_offline() && !request::is_local(&repo.repository.uri) {
+            return Err(Error::Offline);
         }
+        let mut status = self.refresh_status(id)?;
+
+        let result = async {
+            let fetched = fetch_index(self.source.identifier(), &repo, &self.installation).await?;
+            let revision = hash_index(&fetched)?;
+
+            if status.revision.as_ref() == Some(&revision) {
+                promote_index(&fetched)?;
+                return Ok((refresh::Outcome::Unchanged, revision));
+            }
+
+            // Only an index we managed to parse replaces the cached one
+            runtime::unblock(move || match update_meta_db(&repo, &fetched) {
+                Ok(()) => promote_index(&fetched),
+                Err(error) => {
+                    let _ = fs::remove_file(&fetched);
+                    Err(error)
+                }
+            })
+            .await?;
+
+            Ok((refresh::Outcome::Updated, revision))
+        }
+        .await;
+
+        match &result {
+            Ok((_, revision)) => status.succeeded(Utc::now(), revision.clone()),
+            Err(error) => status.failed(Utc::now(), error),
+        }
+        self.save_refresh_status(id, &status)?;
+
+        result.map(|(outcome, _)| outcome)
     }
 
     /// Refresh all [`Repository`]'s by fetching it's latest index
     /// file and updating it's associated meta database
+    ///
+    /// Only local repositories are refreshed in offline mode.
     pub async fn refresh_all(&mut self) -> Result<(), Error> {
-        let mpb = MultiProgress::new();
+        let mpb = MultiProgress::with_draw_target(tui::draw_target());
 
         // Fetch index files asynchronously and then
         // update to DB
-        stream::iter(self.repositories.keys())
+        stream::iter(self.repositories.iter().filter(|(_, state)| is_reachable(state)))
+            .map(|(id, _)| id)
             .map(|id| async {
                 let pb = mpb.add(
                     ProgressBar::new_spinner()
@@ -152,13 +271,16 @@ impl Manager {
                 );
                 pb.enable_steady_tick(Duration::from_millis(150));
 
-                self.refresh(id).await?;
+                let unchanged = match self.refresh(id).await? {
+                    refresh::Outcome::Updated => String::default(),
+                    refresh::Outcome::Unchanged => format!("{}", " (unchanged)".dim()),
+                };
 
-                pb.println(format!("{} {}", "Refreshed".green(), *id));
+                pb.println(format!("{} {}{unchanged}", "Refreshed".green(), *id));
 
                 Ok(())
             })
-            .buffer_unordered(environment::MAX_NETWORK_CONCURRENCY)
+            .buffer_unordered(request::concurrency())
             .try_collect()
             .await
     }
@@ -167,14 +289,14 @@ impl Manager {
     /// populated.
     ///
     /// This is useful to call when initializing the moss client in-case users added configs
-    /// manually outside the CLI
+    /// manually outside the CLI. Only local repositories are initialized in offline mode.
     pub async fn ensure_all_initialized(&mut self) -> Result<usize, Error> {
         let uninitialized = self
             .repositories
             .iter()
+            .filter(|(_, state)| is_reachable(state))
             .filter_map(|(id, state)| {
-                let index_file =
-                    cache_dir(self.source.identifier(), &state.repository, &self.installation).join("stone.index");
+                let index_file = cache_dir(self.source.identifier(), &state.repository, &self.installation).join(INDEX);
 
                 if !index_file.exists() {
                     Some(id)
@@ -188,7 +310,7 @@ impl Manager {
             return Ok(0);
         }
 
-        let mpb = MultiProgress::new();
+        let mpb = MultiProgress::with_draw_target(tui::draw_target());
 
         // Fetch index files asynchronously and then
         // update to DB
@@ -211,7 +333,7 @@ impl Manager {
 
                 Ok(()) as Result<_, Error>
             })
-            .buffer_unordered(environment::MAX_NETWORK_CONCURRENCY)
+            .buffer_unordered(request::concurrency())
             .try_collect::<()>()
             .await?;
 
@@ -219,7 +341,7 @@ impl Manager {
     }
 
     /// Returns the active repositories held by this manager
-    pub(crate) fn active(&self) -> impl Iterator<Item = repository::Active> + '_ {
+    pub fn active(&self) -> impl Iterator<Item = repository::Active> + '_ {
         self.repositories.values().cloned()
     }
 
@@ -255,6 +377,117 @@ impl Manager {
     pub fn list(&self) -> impl ExactSizeIterator<Item = (&repository::Id, &Repository)> {
         self.repositories.iter().map(|(id, state)| (id, &state.repository))
     }
+
+    /// Returns the [`Repository`] with the provided id
+    pub fn get(&self, id: &repository::Id) -> Option<&Repository> {
+        self.repositories.get(id).map(|state| &state.repository)
+    }
+
+    /// Returns the id of the repository serving the package `uri`
+    pub fn serving(&self, uri: &str) -> Option<&repository::Id> {
+        self.repositories
+            .iter()
+            .find_map(|(id, state)| state.repository.serves(uri).then_some(id))
+    }
+
+    /// Revision of the locally cached index of a [`Repository`], as the sha256
+    /// digest of its index file. Returns `None` if the index hasn't been fetched yet.
+    pub fn index_revision(&self, id: &repository::Id) -> Result<Option<String>, Error> {
+        let repo = self
+            .repositories
+            .get(id)
+            .ok_or_else(|| Error::UnknownRepo(id.clone()))?;
+
+        let index_path = cache_dir(self.source.identifier(), &repo.repository, &self.installation).join(INDEX);
+        if !index_path.exists() {
+            return Ok(None);
+        }
+
+        Ok(Some(hash_index(&index_path)?))
+    }
+
+    /// Download [`repository::Usage`] recorded for a [`Repository`]
+    pub fn usage(&self, id: &repository::Id) -> Result<repository::Usage, Error> {
+        let repo = self
+            .repositories
+            .get(id)
+            .ok_or_else(|| Error::UnknownRepo(id.clone()))?;
+
+        Ok(
+            usage_config(self.source.identifier(), &repo.repository, &self.installation)
+                .load::<repository::Usage>()
+                .into_iter()
+                .next()
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Last [`refresh::Status`] recorded for a [`Repository`]
+    pub fn refresh_status(&self, id: &repository::Id) -> Result<refresh::Status, Error> {
+        let repo = self
+            .repositories
+            .get(id)
+            .ok_or_else(|| Error::UnknownRepo(id.clone()))?;
+
+        Ok(
+            usage_config(self.source.identifier(), &repo.repository, &self.installation)
+                .load::<refresh::Status>()
+                .into_iter()
+                .next()
+                .unwrap_or_default(),
+        )
+    }
+
+    fn save_refresh_status(&self, id: &repository::Id, status: &refresh::Status) -> Result<(), Error> {
+        let repo = self
+            .repositories
+            .get(id)
+            .ok_or_else(|| Error::UnknownRepo(id.clone()))?;
+
+        usage_config(self.source.identifier(), &repo.repository, &self.installation)
+            .save(REFRESH_NAME, status)
+            .map_err(Error::SaveRefreshStatus)
+    }
+
+    /// Record `bytes` downloaded from a [`Repository`] to its persisted usage
+    pub fn record_usage(&self, id: &repository::Id, bytes: u64) -> Result<(), Error> {
+        let repo = self
+            .repositories
+            .get(id)
+            .ok_or_else(|| Error::UnknownRepo(id.clone()))?;
+
+        let mut usage = self.usage(id)?;
+        usage.record(Utc::now(), bytes);
+
+        usage_config(self.source.identifier(), &repo.repository, &self.installation)
+            .save(USAGE_NAME, &usage)
+            .map_err(Error::SaveUsage)
+    }
+
+    /// Returns all repositories whose monthly quota would be exceeded by
+    /// downloading the `pending` bytes for each repository
+    pub fn exceeded_quotas(&self, pending: &BTreeMap<repository::Id, u64>) -> Result<Vec<usage::Exceeded>, Error> {
+        let now = Utc::now();
+        let mut exceeded = vec![];
+
+        for (id, pending) in pending {
+            let Some(quota) = self.get(id).and_then(|repo| repo.quota) else {
+                continue;
+            };
+            let used = self.usage(id)?.month(now);
+
+            if used + pending > quota.monthly {
+                exceeded.push(usage::Exceeded {
+                    id: id.clone(),
+                    quota,
+                    used,
+                    pending: *pending,
+                });
+            }
+        }
+
+        Ok(exceeded)
+    }
 }
 
 /// Directory for the repo cached data (db & stone index), hashed by identifier & repo URI
@@ -263,20 +496,27 @@ fn cache_dir(identifier: &str, repo: &Repository, installation: &Installation) -
     installation.repo_path(hash)
 }
 
+/// Usage & refresh status are persisted alongside the repo cached data
+fn usage_config(identifier: &str, repo: &Repository, installation: &Installation) -> config::Manager {
+    config::Manager::custom(cache_dir(identifier, repo, installation))
+}
+
 /// Open the meta db file, ensuring it's
-/// directory exists
+/// directory exists if we have write access
 fn open_meta_db(identifier: &str, repo: &Repository, installation: &Installation) -> Result<meta::Database, Error> {
     let dir = cache_dir(identifier, repo, installation);
 
-    fs::create_dir_all(&dir).map_err(Error::CreateDir)?;
+    if !installation.read_only() {
+        fs::create_dir_all(&dir).map_err(Error::CreateDir)?;
+    }
 
-    let db = meta::Database::new(dir.join("db").to_str().unwrap_or_default())?;
+    let db = meta::Database::open(dir.join(META_DB).to_str().unwrap_or_default(), installation.mutability)?;
 
     Ok(db)
 }
 
-/// Fetches a stone index file from the repository URL
-/// and saves it to the repo installation path
+/// Fetches a stone index file from the repository URL and saves it
+/// next to the cached index, to be [promoted](promote_index) once validated
 async fn fetch_index(
     identifier: &str,
     state: &repository::Active,
@@ -286,7 +526,7 @@ async fn fetch_index(
 
     tokio::fs::create_dir_all(&out_dir).await.map_err(Error::CreateDir)?;
 
-    let out_path = out_dir.join("stone.index");
+    let out_path = out_dir.join(format!("{INDEX}.tmp"));
 
     // Fetch index & write to `out_path`
     repository::fetch_index(state.repository.uri.clone(), &out_path).await?;
@@ -294,62 +534,129 @@ async fn fetch_index(
     Ok(out_path)
 }
 
+/// Whether the index of `state` can be fetched, i.e. it's local when offline
+fn is_reachable(state: &repository::Active) -> bool {
+    !request::is_offline() || request::is_local(&state.repository.uri)
+}
+
+/// Atomically replace the cached index with the `fetched` one
+fn promote_index(fetched: &Path) -> Result<(), Error> {
+    let index_path = fetched.with_file_name(INDEX);
+    fs::rename(fetched, index_path).map_err(Error::PromoteIndex)
+}
+
+/// Sha256 of the index file, identifying its revision
+fn hash_index(path: &Path) -> Result<String, Error> {
+    let mut file = File::open(path).map_err(Error::OpenIndex)?;
+    let mut hasher = Digest::new(Algorithm::Sha256);
+    io::copy(&mut file, &mut hasher).map_err(Error::OpenIndex)?;
+
+    Ok(hasher.finalize_hex())
+}
+
 /// Updates a stones metadata into the meta db
+///
+/// The new metadata is built into a separate db file which atomically replaces the
+/// live db only on success, so concurrent readers never observe an empty repository
+/// and a failed refresh leaves the previous contents in place.
 fn update_meta_db(state: &repository::Active, index_path: &Path) -> Result<(), Error> {
-    // Wipe db since we're refreshing from a new index file
-    state.db.wipe()?;
+    let dir = index_path.parent().unwrap_or(Path::new("."));
+    let db_path = dir.join(META_DB);
+    let staging_path = dir.join(format!("{META_DB}.new"));
 
-    // Get a stream of payloads
-    let mut file = File::open(index_path).map_err(Error::OpenIndex)?;
-    let mut reader = stone::read(&mut file)?;
-    let payloads = reader.payloads()?;
+    // Remove leftovers of a previously failed refresh
+    if staging_path.exists() {
+        fs::remove_file(&staging_path).map_err(Error::RemoveStaging)?;
+    }
 
-    // Update each payload into the meta db
-    payloads
-        // Batch up to `DB_BATCH_SIZE` payloads
-        .chunks(environment::DB_BATCH_SIZE)
-        .into_iter()
-        // Transpose error for early bail
-        .map(|chunk| chunk.into_iter().collect::<Result<Vec<_>, _>>())
-        .try_for_each(|result| {
-            let chunk = result?;
-
-            // Construct Meta for each payload
-            let packages = chunk
-                .into_iter()
-                .filter_map(|payload| {
-                    if let stone::read::PayloadKind::Meta(meta) = payload {
-                        Some(meta)
-                    } else {
-                        None
-                    }
-                })
-                .map(|payload| {
-                    let meta = package::Meta::from_stone_payload(&payload.body)?;
-
-                    // Create id from hash of meta
-                    let hash = meta
-                        .hash
-                        .clone()
-                        .ok_or(Error::MissingMetaField(stone::payload::meta::Tag::PackageHash))?;
-                    let id = package::Id::from(hash);
-
-                    Ok((id, meta))
-                })
-                .collect::<Result<Vec<_>, Error>>()?;
-
-            // Batch add to db
-            //
-            // Sqlite supports up to 32k parametized query binds. Adding a
-            // package has 13 binds x 1k batch size = 17k. This leaves us
-            // overhead to add more binds in the future, otherwise we can
-            // lower the `DB_BATCH_SIZE`.
-            state.db.batch_add(packages).map_err(Error::Database)
-        })?;
+    let staging = meta::Database::new(staging_path.to_str().unwrap_or_default())?;
+    if let Err(error) = populate_meta_db(&staging, index_path) {
+        drop(staging);
+        let _ = fs::remove_file(&staging_path);
+        return Err(error);
+    }
+    drop(staging);
+
+    // Swap in the new db & point all users of the active db to it
+    fs::rename(&staging_path, &db_path).map_err(Error::SwapDatabase)?;
+    state.db.reopen(db_path.to_str().unwrap_or_default())?;
 
     Ok(())
 }
 
+/// Populate the meta db from the stone index file
+///
+/// Payloads are read in batches of `DB_BATCH_SIZE` and their metadata is parsed across
+/// a worker pool, feeding a single writer which stores them in one transaction.
+fn populate_meta_db(db: &meta::Database, index_path: &Path) -> Result<(), Error> {
+    // Get a stream of payloads, decoded while we're parsing the previous ones
+    let file = File::open(index_path).map_err(Error::OpenIndex)?;
+    let mut payloads = stone::stream_payloads(file, environment::PAYLOAD_READ_AHEAD)?.peekable();
+
+    // Make sure we understand the index before parsing any packages
+    if let Some(Ok(stone::read::PayloadKind::Meta(meta))) = payloads.peek() {
+        if let Some(format) = Format::from_stone_payload(&meta.body) {
+            format.negotiate()?;
+            payloads.next();
+        }
+    }
+
+    // Bounded so parsing can't run too far ahead of the writer
+    let (sender, receiver) = mpsc::sync_channel(PARSED_BATCH_BACKLOG);
+
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            for chunk in payloads.chunks(environment::DB_BATCH_SIZE).into_iter() {
+                let batch = chunk
+                    // Transpose error for early bail
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(Error::from)
+                    .and_then(parse_meta_batch);
+                let failed = batch.is_err();
+
+                // Writer hung up or we failed, either way we're done
+                if sender.send(batch).is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        // Batch add to db
+        //
+        // Sqlite supports up to 32k parametized query binds. Adding a
+        // package has 13 binds x 1k batch size = 17k. This leaves us
+        // overhead to add more binds in the future, otherwise we can
+        // lower the `DB_BATCH_SIZE`.
+        db.replace_all(receiver)
+    })
+}
+
+/// Construct [`package::Meta`] for each meta payload in parallel
+fn parse_meta_batch(payloads: Vec<stone::read::PayloadKind>) -> Result<Vec<(package::Id, package::Meta)>, Error> {
+    payloads
+        .into_par_iter()
+        .filter_map(|payload| {
+            if let stone::read::PayloadKind::Meta(meta) = payload {
+                Some(meta)
+            } else {
+                None
+            }
+        })
+        .map(|payload| {
+            let meta = package::Meta::from_stone_payload(&payload.body)?;
+
+            // Create id from hash of meta
+            let hash = meta
+                .hash
+                .clone()
+                .ok_or(Error::MissingMetaField(stone::payload::meta::Tag::PackageHash))?;
+            let id = package::Id::from(hash);
+
+            Ok((id, meta))
+        })
+        .collect()
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("Can't modify repos when using explicit configs")]
@@ -362,16 +669,30 @@ pub enum Error {
     RemoveDir(#[source] io::Error),
     #[error("fetch index file")]
     FetchIndex(#[from] repository::FetchError),
+    #[error("remove stale staging db")]
+    RemoveStaging(#[source] io::Error),
+    #[error("swap in refreshed db")]
+    SwapDatabase(#[source] io::Error),
+    #[error("swap in fetched index file")]
+    PromoteIndex(#[source] io::Error),
     #[error("open index file")]
     OpenIndex(#[source] io::Error),
     #[error("read index file")]
     ReadStone(#[from] stone::read::Error),
+    #[error("index format")]
+    Format(#[from] format::Error),
     #[error("meta db")]
     Database(#[from] meta::Error),
     #[error("save config")]
     SaveConfig(#[source] config::SaveError),
+    #[error("save usage")]
+    SaveUsage(#[source] config::SaveError),
+    #[error("save refresh status")]
+    SaveRefreshStatus(#[source] config::SaveError),
     #[error("unknown repo")]
     UnknownRepo(repository::Id),
+    #[error("can't refresh remote repositories in offline mode")]
+    Offline,
 }
 
 impl From<package::MissingMetaFieldError> for Error {
@@ -6,22 +6,22 @@ use std::collections::BTreeMap;
 use std::path::Path;
 
 use derive_more::{Display, From, Into};
-use futures::StreamExt;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tokio::{
-    fs::File,
-    io::{self, AsyncWriteExt},
-};
+use tracing::debug;
 use url::Url;
 
 use config::Config;
 
-use crate::{db::meta, request};
+use crate::{db::meta, request, signature};
 
 pub use self::manager::Manager;
+pub use self::usage::Usage;
 
+pub mod format;
 pub mod manager;
+pub mod refresh;
+pub mod usage;
 
 /// A unique [`Repository`] identifier
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Ord, PartialOrd, From, Display)]
@@ -45,6 +45,31 @@ pub struct Repository {
     pub description: String,
     pub uri: Url,
     pub priority: Priority,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota: Option<Quota>,
+    /// Keys packages from this repository must be signed by, if any
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trusted_keys: Vec<signature::PublicKey>,
+}
+
+impl Repository {
+    /// Returns true if the package `uri` is served from this repository
+    pub fn serves(&self, uri: &str) -> bool {
+        self.uri
+            .join(".")
+            .map(|base| uri.starts_with(base.as_str()))
+            .unwrap_or_default()
+    }
+}
+
+/// Monthly download quota of a [`Repository`], useful on metered mirrors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quota {
+    /// Bytes which may be downloaded per calendar month
+    pub monthly: u64,
+    /// Require confirmation once exceeded, rather than only warning
+    #[serde(default)]
+    pub confirm: bool,
 }
 
 /// An active repository that has been
@@ -119,24 +144,82 @@ impl Config for Map {
     }
 }
 
+/// Fetch the index at `url` to `out_path`
+///
+/// A repository may additionally publish its index compressed, as
+/// `{url}.zst` or `{url}.xz`. Those are preferred, falling back to the
+/// uncompressed one when not found, forbidden or failing on the server.
 async fn fetch_index(url: Url, out_path: impl AsRef<Path>) -> Result<(), FetchError> {
-    let mut stream = request::get(url).await?;
+    let candidates = index_candidates(&url);
+    let last = candidates.len() - 1;
+
+    for (i, candidate) in candidates.into_iter().enumerate() {
+        let stream = match request::get_decompressed(candidate.clone()).await {
+            Ok(stream) => stream,
+            Err(error) if i < last && error.is_unavailable() => {
+                debug!("{candidate} unavailable");
+                continue;
+            }
+            Err(error) => return Err(error.into()),
+        };
+
+        // Indices aren't pinned to a known digest, but are still only
+        // written to `out_path` once fully downloaded
+        request::write_verified(candidate, stream, None, out_path.as_ref(), |_| {}).await?;
+        break;
+    }
+
+    Ok(())
+}
 
-    let mut out = File::create(out_path).await?;
+/// Urls the index at `url` may be published at, in order of preference
+fn index_candidates(url: &Url) -> Vec<Url> {
+    let compressed = request::Compression::ALL
+        .iter()
+        .any(|compression| url.path().ends_with(&format!(".{}", compression.extension())));
 
-    while let Some(chunk) = stream.next().await {
-        out.write_all(&chunk?).await?;
+    // Explicitly configured to a compressed index
+    if compressed {
+        return vec![url.clone()];
     }
 
-    out.flush().await?;
-
-    Ok(())
+    request::Compression::ALL
+        .iter()
+        .map(|compression| {
+            let mut candidate = url.clone();
+            candidate.set_path(&format!("{}.{}", url.path(), compression.extension()));
+            candidate
+        })
+        .chain(Some(url.clone()))
+        .collect()
 }
 
 #[derive(Debug, Error)]
 pub enum FetchError {
     #[error("request")]
     Request(#[from] request::Error),
-    #[error("io")]
-    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn compressed_index_candidates() {
+        let url = "https://cdn.example.org/volatile/x86_64/stone.index"
+            .parse::<Url>()
+            .unwrap();
+        let candidates = index_candidates(&url).into_iter().map(String::from).collect::<Vec<_>>();
+        assert_eq!(
+            candidates,
+            [
+                "https://cdn.example.org/volatile/x86_64/stone.index.zst",
+                "https://cdn.example.org/volatile/x86_64/stone.index.xz",
+                "https://cdn.example.org/volatile/x86_64/stone.index",
+            ]
+        );
+
+        let url = "https://cdn.example.org/stone.index.xz".parse::<Url>().unwrap();
+        assert_eq!(index_candidates(&url), [url]);
+    }
 }
@@ -0,0 +1,187 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Refresh bookkeeping & scheduling of repository indices
+//!
+//! Each repository persists a [`Status`] next to its cached index, so
+//! other commands can report how fresh their view of a repository is
+//! and scheduled refreshes can skip rebuilding an unchanged index.
+
+use std::{fmt, process, time::Duration};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh3::xxh3_64;
+
+use config::Config;
+
+/// Refresh status of a repository
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Status {
+    /// Unix timestamp of the last refresh attempt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_attempt: Option<i64>,
+    /// Unix timestamp of the last successful refresh
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_success: Option<i64>,
+    /// Revision of the index the meta db was last populated from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<String>,
+    /// Error of the last attempt, if it failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Config for Status {
+    fn domain() -> String {
+        "refresh".into()
+    }
+}
+
+impl Status {
+    /// Record a successful refresh at `date` from the index `revision`
+    pub fn succeeded(&mut self, date: DateTime<Utc>, revision: String) {
+        self.last_attempt = Some(date.timestamp());
+        self.last_success = Some(date.timestamp());
+        self.revision = Some(revision);
+        self.error = None;
+    }
+
+    /// Record a failed refresh at `date`
+    pub fn failed(&mut self, date: DateTime<Utc>, error: impl ToString) {
+        self.last_attempt = Some(date.timestamp());
+        self.error = Some(error.to_string());
+    }
+
+    pub fn last_attempt(&self) -> Option<DateTime<Utc>> {
+        self.last_attempt.and_then(|secs| DateTime::from_timestamp(secs, 0))
+    }
+
+    pub fn last_success(&self) -> Option<DateTime<Utc>> {
+        self.last_success.and_then(|secs| DateTime::from_timestamp(secs, 0))
+    }
+}
+
+/// Result of refreshing a single repository
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// A new index was fetched & the meta db rebuilt
+    Updated,
+    /// The fetched index matches the one already in use
+    Unchanged,
+}
+
+/// Interval between scheduled refreshes, delayed by a random
+/// jitter so a fleet of machines doesn't hit mirrors in lockstep
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Schedule {
+    pub interval: Duration,
+    pub jitter: Duration,
+}
+
+impl Schedule {
+    /// Delay until the next refresh, with the jitter derived from `seed`
+    pub fn delay(&self, seed: u64) -> Duration {
+        let jitter = self.jitter.as_secs();
+        self.interval + Duration::from_secs(seed % (jitter + 1))
+    }
+
+    /// Delay until the next refresh, with a fresh random jitter
+    pub fn next_delay(&self) -> Duration {
+        let now = Utc::now();
+        let seed = xxh3_64(format!("{}-{}", now.timestamp_nanos_opt().unwrap_or_default(), process::id()).as_bytes());
+        self.delay(seed)
+    }
+}
+
+/// Metered state of the active network connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metered {
+    Yes,
+    No,
+    Unknown,
+}
+
+impl Metered {
+    /// Query NetworkManager over D-Bus for the metered state of the
+    /// primary connection. `Unknown` if NetworkManager isn't available.
+    pub fn detect() -> Self {
+        let output = process::Command::new("busctl")
+            .args([
+                "--system",
+                "get-property",
+                "org.freedesktop.NetworkManager",
+                "/org/freedesktop/NetworkManager",
+                "org.freedesktop.NetworkManager",
+                "Metered",
+            ])
+            .stderr(process::Stdio::null())
+            .output();
+
+        match output {
+            Ok(output) if output.status.success() => Self::parse(&String::from_utf8_lossy(&output.stdout)),
+            _ => Self::Unknown,
+        }
+    }
+
+    /// Parse the `NMMetered` value as printed by `busctl`, i.e. `u 1`
+    fn parse(value: &str) -> Self {
+        match value.trim().strip_prefix("u ").map(str::parse::<u32>) {
+            // NM_METERED_YES, NM_METERED_GUESS_YES
+            Some(Ok(1 | 3)) => Self::Yes,
+            // NM_METERED_NO, NM_METERED_GUESS_NO
+            Some(Ok(2 | 4)) => Self::No,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// Human readable age of a timestamp, i.e. `3h ago`
+pub struct Age(pub chrono::Duration);
+
+impl Age {
+    pub fn since(date: DateTime<Utc>) -> Self {
+        Self(Utc::now() - date)
+    }
+}
+
+impl fmt::Display for Age {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.0.num_seconds().max(0);
+
+        match secs {
+            0..=59 => write!(f, "just now"),
+            60..=3599 => write!(f, "{}m ago", secs / 60),
+            3600..=86399 => write!(f, "{}h ago", secs / 3600),
+            _ => write!(f, "{}d ago", secs / 86400),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn schedule_jitter() {
+        let schedule = Schedule {
+            interval: Duration::from_secs(3600),
+            jitter: Duration::from_secs(60),
+        };
+
+        assert_eq!(schedule.delay(0), Duration::from_secs(3600));
+        assert_eq!(schedule.delay(60), Duration::from_secs(3660));
+        assert_eq!(schedule.delay(61), Duration::from_secs(3600));
+        assert!((0..1000).all(|seed| schedule.delay(seed) <= Duration::from_secs(3660)));
+    }
+
+    #[test]
+    fn parse_metered() {
+        assert_eq!(Metered::parse("u 1\n"), Metered::Yes);
+        assert_eq!(Metered::parse("u 3"), Metered::Yes);
+        assert_eq!(Metered::parse("u 4"), Metered::No);
+        assert_eq!(Metered::parse("u 0"), Metered::Unknown);
+        assert_eq!(Metered::parse(""), Metered::Unknown);
+    }
+}
@@ -0,0 +1,82 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Per-repository download accounting, used to warn when a
+//! repository [`super::Quota`] is exceeded on metered mirrors
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use config::Config;
+
+use super::{Id, Quota};
+
+/// Bytes downloaded from a repository, keyed by calendar month (`YYYY-MM`)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    pub months: BTreeMap<String, u64>,
+}
+
+impl Config for Usage {
+    fn domain() -> String {
+        "usage".into()
+    }
+}
+
+impl Usage {
+    /// Record `bytes` downloaded at `date`
+    pub fn record(&mut self, date: DateTime<Utc>, bytes: u64) {
+        *self.months.entry(month(date)).or_default() += bytes;
+    }
+
+    /// Bytes downloaded during the month of `date`
+    pub fn month(&self, date: DateTime<Utc>) -> u64 {
+        self.months.get(&month(date)).copied().unwrap_or_default()
+    }
+
+    /// Bytes downloaded across all recorded months
+    pub fn total(&self) -> u64 {
+        self.months.values().sum()
+    }
+}
+
+/// A pending download which would take a repository over its monthly [`Quota`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exceeded {
+    pub id: Id,
+    pub quota: Quota,
+    /// Bytes already downloaded this month
+    pub used: u64,
+    /// Bytes about to be downloaded
+    pub pending: u64,
+}
+
+fn month(date: DateTime<Utc>) -> String {
+    date.format("%Y-%m").to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn monthly_usage() {
+        let june = Utc.with_ymd_and_hms(2024, 6, 1, 12, 0, 0).unwrap();
+        let late_june = Utc.with_ymd_and_hms(2024, 6, 30, 23, 59, 59).unwrap();
+        let july = Utc.with_ymd_and_hms(2024, 7, 1, 0, 0, 0).unwrap();
+
+        let mut usage = Usage::default();
+        usage.record(june, 100);
+        usage.record(late_june, 50);
+        usage.record(july, 25);
+
+        assert_eq!(usage.month(june), 150);
+        assert_eq!(usage.month(july), 25);
+        assert_eq!(usage.total(), 175);
+    }
+}
@@ -0,0 +1,158 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Transparent decompression of downloads
+//!
+//! Unlike `Content-Encoding`, which reqwest already negotiates, this covers
+//! resources which are published compressed, i.e. `stone.index.zst`. The
+//! format is detected from the leading magic bytes, so it doesn't matter
+//! what the resource is called.
+
+use std::io;
+
+use async_compression::tokio::bufread::{XzDecoder, ZstdDecoder};
+use bytes::{Bytes, BytesMut};
+use futures::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
+use tokio_util::io::{ReaderStream, StreamReader};
+
+use super::Error;
+use crate::environment;
+
+/// Bytes needed to tell all formats apart
+const MAGIC_LEN: usize = 6;
+
+/// A compression format a resource may be published in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display, strum::EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum Compression {
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+    pub const ALL: [Self; 2] = [Self::Zstd, Self::Xz];
+
+    /// File extension of resources in this format, without the leading dot
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::Zstd => "zst",
+            Compression::Xz => "xz",
+        }
+    }
+
+    /// Detect the format from the leading bytes of a resource
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Self::Zstd)
+        } else if bytes.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Self::Xz)
+        } else {
+            None
+        }
+    }
+}
+
+/// Decompress `stream` if it's in a known [`Compression`] format,
+/// otherwise pass it through as is
+pub async fn decompress(
+    mut stream: BoxStream<'static, Result<Bytes, Error>>,
+) -> Result<BoxStream<'static, Result<Bytes, Error>>, Error> {
+    let mut head = BytesMut::new();
+
+    while head.len() < MAGIC_LEN {
+        match stream.next().await {
+            Some(chunk) => head.extend_from_slice(&chunk?),
+            None => break,
+        }
+    }
+
+    let compression = Compression::detect(&head);
+    let stream = stream::once(async move { Ok(head.freeze()) }).chain(stream);
+
+    let Some(compression) = compression else {
+        return Ok(stream.boxed());
+    };
+
+    let reader = StreamReader::new(stream.map(|result| result.map_err(io::Error::other)));
+
+    let decoded = match compression {
+        Compression::Zstd => {
+            let mut decoder = ZstdDecoder::new(reader);
+            decoder.multiple_members(true);
+            ReaderStream::with_capacity(decoder, environment::FILE_READ_BUFFER_SIZE).boxed()
+        }
+        Compression::Xz => {
+            let mut decoder = XzDecoder::new(reader);
+            decoder.multiple_members(true);
+            ReaderStream::with_capacity(decoder, environment::FILE_READ_BUFFER_SIZE).boxed()
+        }
+    };
+
+    Ok(decoded
+        .map(move |result| result.map_err(|error| Error::Decompress(compression, error)))
+        .boxed())
+}
+
+#[cfg(test)]
+mod test {
+    use async_compression::tokio::bufread::{XzEncoder, ZstdEncoder};
+    use futures::TryStreamExt;
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    const CONTENT: &[u8] = b"stone index contents, repeated stone index contents";
+
+    /// Run `bytes` through [`decompress`], a few bytes at a time so
+    /// the magic spans several chunks
+    fn roundtrip(bytes: Vec<u8>) -> Vec<u8> {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+
+        runtime.block_on(async {
+            let chunks = bytes
+                .chunks(3)
+                .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+                .collect::<Vec<_>>();
+
+            decompress(stream::iter(chunks).boxed())
+                .await
+                .unwrap()
+                .try_fold(vec![], |mut out, chunk| async move {
+                    out.extend_from_slice(&chunk);
+                    Ok(out)
+                })
+                .await
+                .unwrap()
+        })
+    }
+
+    fn compress(compression: Compression) -> Vec<u8> {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+
+        runtime.block_on(async {
+            let mut out = vec![];
+            match compression {
+                Compression::Zstd => ZstdEncoder::new(CONTENT).read_to_end(&mut out).await,
+                Compression::Xz => XzEncoder::new(CONTENT).read_to_end(&mut out).await,
+            }
+            .unwrap();
+            out
+        })
+    }
+
+    #[test]
+    fn transparent() {
+        assert_eq!(roundtrip(CONTENT.to_vec()), CONTENT);
+        assert_eq!(roundtrip(vec![]), b"");
+
+        for compression in Compression::ALL {
+            let compressed = compress(compression);
+            assert_eq!(Compression::detect(&compressed), Some(compression));
+            assert_eq!(roundtrip(compressed), CONTENT, "{compression:?}");
+        }
+    }
+}
@@ -0,0 +1,234 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Download bandwidth limits
+//!
+//! All concurrent downloads share the same limits: the global one caps their
+//! aggregate rate, while the per host one caps the rate of each host.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    num::ParseIntError,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use serde::{de, Deserialize, Serialize};
+use thiserror::Error;
+
+/// Aggregate rate in bytes per second, `0` if unlimited
+static RATE: AtomicU64 = AtomicU64::new(0);
+
+/// Rate of each host in bytes per second, `0` if unlimited
+static HOST_RATE: AtomicU64 = AtomicU64::new(0);
+
+static GLOBAL: Mutex<Option<Bucket>> = Mutex::new(None);
+
+static HOSTS: Mutex<BTreeMap<String, Bucket>> = Mutex::new(BTreeMap::new());
+
+/// Limit the aggregate rate of all downloads
+pub fn set_rate_limit(rate: Option<Rate>) {
+    RATE.store(rate.map_or(0, |rate| rate.0), Ordering::Relaxed);
+}
+
+/// Limit the rate of downloads from each host
+pub fn set_host_rate_limit(rate: Option<Rate>) {
+    HOST_RATE.store(rate.map_or(0, |rate| rate.0), Ordering::Relaxed);
+}
+
+/// Delay each chunk of `stream`, downloaded from `host`, until it fits the limits
+pub(super) fn throttle<E>(
+    host: Option<String>,
+    stream: impl Stream<Item = Result<Bytes, E>>,
+) -> impl Stream<Item = Result<Bytes, E>> {
+    stream.then(move |result| {
+        let delay = match &result {
+            Ok(bytes) => delay(host.as_deref(), bytes.len()),
+            Err(_) => Duration::ZERO,
+        };
+
+        async move {
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+            result
+        }
+    })
+}
+
+/// How long to wait before `amount` bytes from `host` fit the limits
+fn delay(host: Option<&str>, amount: usize) -> Duration {
+    let global = match RATE.load(Ordering::Relaxed) {
+        0 => Duration::ZERO,
+        rate => GLOBAL
+            .lock()
+            .expect("mutex lock")
+            .get_or_insert_with(|| Bucket::new(rate))
+            .take(rate, amount),
+    };
+
+    let host = match (HOST_RATE.load(Ordering::Relaxed), host) {
+        (0, _) | (_, None) => Duration::ZERO,
+        (rate, Some(host)) => HOSTS
+            .lock()
+            .expect("mutex lock")
+            .entry(host.to_string())
+            .or_insert_with(|| Bucket::new(rate))
+            .take(rate, amount),
+    };
+
+    global.max(host)
+}
+
+/// Token bucket allowing bursts of up to a second worth of bytes
+#[derive(Debug)]
+struct Bucket {
+    /// Bytes which may be taken right away, negative if overdrawn
+    available: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(rate: u64) -> Self {
+        Self {
+            available: rate as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    /// Take `amount` bytes, returning how long until the bucket is no longer overdrawn
+    fn take(&mut self, rate: u64, amount: usize) -> Duration {
+        let now = Instant::now();
+        let rate = rate as f64;
+
+        self.available = (self.available + now.duration_since(self.updated).as_secs_f64() * rate).min(rate);
+        self.updated = now;
+        self.available -= amount as f64;
+
+        if self.available < 0.0 {
+            Duration::from_secs_f64(-self.available / rate)
+        } else {
+            Duration::ZERO
+        }
+    }
+}
+
+/// A rate in bytes per second, written with an optional binary
+/// unit suffix i.e. `512K` or `2M`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rate(pub u64);
+
+const UNITS: [(char, u64); 3] = [('G', 1024 * 1024 * 1024), ('M', 1024 * 1024), ('K', 1024)];
+
+impl FromStr for Rate {
+    type Err = ParseRateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        let (number, multiplier) = match s.chars().last().map(|unit| unit.to_ascii_uppercase()) {
+            Some(unit) => match UNITS.iter().find(|(known, _)| *known == unit) {
+                Some((_, multiplier)) => (&s[..s.len() - 1], *multiplier),
+                None => (s, 1),
+            },
+            None => (s, 1),
+        };
+
+        let rate = number
+            .parse::<u64>()
+            .map_err(|error| ParseRateError::Number(s.to_string(), error))?
+            .checked_mul(multiplier)
+            .ok_or_else(|| ParseRateError::TooLarge(s.to_string()))?;
+
+        if rate == 0 {
+            return Err(ParseRateError::Zero);
+        }
+
+        Ok(Self(rate))
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match UNITS.iter().find(|(_, multiplier)| self.0 % multiplier == 0) {
+            Some((unit, multiplier)) => write!(f, "{}{unit}", self.0 / multiplier),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+impl Serialize for Rate {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Rate {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = Rate;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("bytes per second, i.e. 2M")
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Rate, E> {
+                Rate::from_str(&v.to_string()).map_err(E::custom)
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Rate, E> {
+                Rate::from_str(v).map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ParseRateError {
+    #[error("invalid rate {0:?}, expected bytes per second with an optional K, M or G suffix")]
+    Number(String, #[source] ParseIntError),
+    #[error("rate {0:?} is too large")]
+    TooLarge(String),
+    #[error("rate must be above zero")]
+    Zero,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_rate() {
+        assert_eq!("2M".parse::<Rate>().unwrap(), Rate(2 * 1024 * 1024));
+        assert_eq!("512k".parse::<Rate>().unwrap(), Rate(512 * 1024));
+        assert_eq!("1000".parse::<Rate>().unwrap(), Rate(1000));
+        assert!("0".parse::<Rate>().is_err());
+        assert!("fast".parse::<Rate>().is_err());
+        assert!("99999999999G".parse::<Rate>().is_err());
+
+        assert_eq!(Rate(2 * 1024 * 1024).to_string(), "2M");
+        assert_eq!(Rate(1000).to_string(), "1000");
+    }
+
+    #[test]
+    fn bucket() {
+        let mut bucket = Bucket::new(1000);
+
+        // A second worth of bytes is allowed right away, beyond that we wait
+        assert_eq!(bucket.take(1000, 1000), Duration::ZERO);
+        let delay = bucket.take(1000, 500);
+        assert!(delay > Duration::from_millis(400) && delay <= Duration::from_millis(500));
+    }
+}
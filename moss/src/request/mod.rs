@@ -0,0 +1,766 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{
+    collections::hash_map::RandomState,
+    error::Error as _,
+    fs,
+    hash::{BuildHasher, Hasher},
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        OnceLock,
+    },
+    time::Duration,
+};
+
+use bytes::Bytes;
+use futures::{
+    stream::{self, BoxStream},
+    Stream, StreamExt,
+};
+use hash::{Algorithm, Digest};
+use reqwest::{header, Certificate, NoProxy, Proxy, StatusCode};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+use tokio_util::io::ReaderStream;
+use tracing::{debug, trace, warn};
+use url::Url;
+
+use config::Config;
+
+use crate::environment;
+
+pub use self::compression::Compression;
+pub use self::limit::{set_host_rate_limit, set_rate_limit, Rate};
+pub use self::ranged::RangedReader;
+
+mod compression;
+mod limit;
+mod ranged;
+
+/// Whether network access is forbidden, see [`set_offline`]
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+/// Max concurrent network tasks, see [`set_concurrency`]
+static CONCURRENCY: AtomicUsize = AtomicUsize::new(environment::MAX_NETWORK_CONCURRENCY);
+
+/// Times a request is retried, see [`set_retries`]
+static RETRIES: AtomicU32 = AtomicU32::new(environment::NETWORK_RETRIES);
+
+/// Delay before the first retry, doubled for each one after
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Upper bound of the delay between retries
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// How long idle connections are kept for reuse
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Network settings, stored as `etc/moss/network.d/{name}.yaml`
+///
+/// Without any proxy configured, the `http_proxy`, `https_proxy` &
+/// `no_proxy` environment variables are respected instead
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Settings {
+    /// Proxy for `http://` urls
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_proxy: Option<String>,
+    /// Proxy for `https://` urls
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub https_proxy: Option<String>,
+    /// Comma separated hosts & domains which bypass the proxy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_proxy: Option<String>,
+    /// Additional PEM encoded CA certificates (bundles) to trust, i.e. of a corporate proxy
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ca_certificates: Vec<PathBuf>,
+}
+
+impl Config for Settings {
+    fn domain() -> String {
+        "network".into()
+    }
+}
+
+/// Forbid all network access. Requests of anything but `file://` urls then
+/// fail immediately with [`Error::Offline`], rather than timing out
+pub fn set_offline(offline: bool) {
+    OFFLINE.store(offline, Ordering::Relaxed);
+}
+
+/// Whether network access is forbidden
+pub fn is_offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed)
+}
+
+/// Whether `url` is a local `file://` url, which can be fetched offline
+pub fn is_local(url: &Url) -> bool {
+    url_file(url).is_some()
+}
+
+/// Limit the number of concurrent network tasks, i.e. downloads
+pub fn set_concurrency(concurrency: usize) {
+    CONCURRENCY.store(concurrency.max(1), Ordering::Relaxed);
+}
+
+/// Max number of concurrent network tasks
+pub fn concurrency() -> usize {
+    CONCURRENCY.load(Ordering::Relaxed)
+}
+
+/// Retry requests this many times after a transient failure, such as a
+/// timeout or server error. Permanent ones, i.e. 404, fail immediately
+pub fn set_retries(retries: u32) {
+    RETRIES.store(retries, Ordering::Relaxed);
+}
+
+/// Times a request is retried after a transient failure
+pub fn retries() -> u32 {
+    RETRIES.load(Ordering::Relaxed)
+}
+
+/// Shared client for tcp socket reuse and connection limit
+///
+/// Its pool keeps a connection per concurrent task to each host, so the many
+/// small requests of a transaction don't each open their own. Servers speaking
+/// HTTP/2 multiplex all of them over a single connection instead.
+static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+fn get_client() -> &'static reqwest::Client {
+    CLIENT.get_or_init(|| build_client(&[]).expect("build reqwest client"))
+}
+
+/// Apply the proxies & CA certificates of all `settings` to every request
+///
+/// Proxies of later settings take precedence, while all certificates are
+/// trusted. Must be called before the first request, later calls have no effect
+pub fn configure(settings: &[Settings]) -> Result<(), Error> {
+    let client = build_client(settings)?;
+    let _ = CLIENT.set(client);
+    Ok(())
+}
+
+fn build_client(settings: &[Settings]) -> Result<reqwest::Client, Error> {
+    let mut builder = reqwest::ClientBuilder::new()
+        .user_agent(concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")))
+        .pool_max_idle_per_host(concurrency())
+        .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+        .tcp_keepalive(POOL_IDLE_TIMEOUT)
+        .http2_adaptive_window(true);
+
+    let latest = |field: fn(&Settings) -> Option<&String>| settings.iter().rev().find_map(field);
+
+    // Any configured proxy replaces the ones from the environment
+    let no_proxy = latest(|s| s.no_proxy.as_ref()).map_or_else(NoProxy::from_env, |hosts| NoProxy::from_string(hosts));
+    if let Some(url) = latest(|s| s.http_proxy.as_ref()) {
+        let proxy = Proxy::http(url.as_str()).map_err(|error| Error::Proxy(url.clone(), error))?;
+        builder = builder.proxy(proxy.no_proxy(no_proxy.clone()));
+    }
+    if let Some(url) = latest(|s| s.https_proxy.as_ref()) {
+        let proxy = Proxy::https(url.as_str()).map_err(|error| Error::Proxy(url.clone(), error))?;
+        builder = builder.proxy(proxy.no_proxy(no_proxy));
+    }
+
+    for path in settings.iter().flat_map(|s| &s.ca_certificates) {
+        let pem = fs::read(path).map_err(|error| Error::ReadCertificate(path.clone(), error))?;
+
+        for certificate in
+            Certificate::from_pem_bundle(&pem).map_err(|error| Error::InvalidCertificate(path.clone(), error))?
+        {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    builder.build().map_err(Error::Client)
+}
+
+/// Fetch a resource at the provided [`Url`] and stream response body as bytes
+pub async fn get(url: Url) -> Result<BoxStream<'static, Result<Bytes, Error>>, Error> {
+    match url_file(&url) {
+        Some(path) => read(path).await,
+        _ => Ok(fetch(url).await?.boxed()),
+    }
+}
+
+/// Fetch a resource at the provided [`Url`] and stream its response body,
+/// decompressed if it's published in a known [`Compression`] format
+pub async fn get_decompressed(url: Url) -> Result<BoxStream<'static, Result<Bytes, Error>>, Error> {
+    compression::decompress(get(url).await?).await
+}
+
+/// Response body of a resource, starting `offset` bytes into it
+pub struct Partial {
+    pub offset: u64,
+    pub stream: BoxStream<'static, Result<Bytes, Error>>,
+}
+
+/// Fetch a resource at the provided [`Url`] starting from byte `offset`, i.e. to resume
+/// an interrupted download
+///
+/// Not all servers support range requests, so [`Partial::offset`] is where the returned
+/// stream actually starts, which is `0` if the whole resource is sent instead.
+pub async fn get_from(url: Url, offset: u64) -> Result<Partial, Error> {
+    if offset == 0 {
+        return Ok(Partial {
+            offset,
+            stream: get(url).await?,
+        });
+    }
+
+    match url_file(&url) {
+        Some(path) => read_from(path, offset).await,
+        _ => fetch_from(url, offset).await,
+    }
+}
+
+/// A download which passed verification
+#[derive(Debug, Clone)]
+pub struct Verified {
+    /// Hex encoded sha256 digest of the download
+    pub hash: String,
+    pub size: u64,
+    /// Bytes of `size` left behind by an interrupted download, which were
+    /// resumed rather than fetched again
+    pub resumed: u64,
+}
+
+/// Download the resource at [`Url`] to `dest`, verifying its sha256 digest
+/// matches the hex encoded `expected` one, if any
+///
+/// The download is streamed to `{dest}.part` and only renamed to `dest` once
+/// verified, so anything found at `dest` is trusted to be complete & intact.
+/// A `{dest}.part` left behind by an interrupted download is resumed where the
+/// server supports range requests, as is a download interrupted mid-stream, up to
+/// [`retries`] times. `on_progress` is called with the size of each chunk as it's
+/// written, and the size of any resumed part up front.
+pub async fn get_verified(
+    url: Url,
+    expected: Option<&str>,
+    dest: &Path,
+    on_progress: impl Fn(u64),
+) -> Result<Verified, Error> {
+    let partial = partial_path(dest);
+    let on_progress = increments(on_progress);
+    let mut attempt = 0;
+
+    loop {
+        let existing = tokio::fs::metadata(&partial).await.map_or(0, |metadata| metadata.len());
+        let Partial { offset, stream } = get_from(url.clone(), existing).await?;
+
+        match download(stream, &partial, offset, &on_progress).await {
+            Ok(verified) => return promote(url, verified, expected, &partial, dest).await,
+            Err(error) if attempt < retries() && is_interrupted(&error) => {
+                let delay = backoff(attempt);
+                attempt += 1;
+
+                warn!(
+                    "{url}: download interrupted, resuming in {delay:?} ({attempt}/{})",
+                    retries()
+                );
+                tokio::time::sleep(delay).await;
+            }
+            // Keep what was written, so the next run can resume it
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Like [`get_verified`], for the already requested `stream` of `url`,
+/// i.e. one that's [decompressed](get_decompressed)
+pub async fn write_verified(
+    url: Url,
+    stream: impl Stream<Item = Result<Bytes, Error>>,
+    expected: Option<&str>,
+    dest: &Path,
+    on_progress: impl Fn(u64),
+) -> Result<Verified, Error> {
+    let partial = partial_path(dest);
+
+    let verified = match download(stream, &partial, 0, increments(on_progress)).await {
+        Ok(verified) => verified,
+        Err(error) => {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return Err(error);
+        }
+    };
+
+    promote(url, verified, expected, &partial, dest).await
+}
+
+/// Move the `verified` download at `partial` to `dest` if its hash is the `expected` one
+async fn promote(
+    url: Url,
+    verified: Verified,
+    expected: Option<&str>,
+    partial: &Path,
+    dest: &Path,
+) -> Result<Verified, Error> {
+    if let Some(expected) = expected.filter(|expected| !expected.eq_ignore_ascii_case(&verified.hash)) {
+        let _ = tokio::fs::remove_file(partial).await;
+
+        return Err(Error::HashMismatch {
+            url,
+            expected: expected.to_string(),
+            actual: verified.hash,
+        });
+    }
+
+    tokio::fs::rename(partial, dest)
+        .await
+        .map_err(|error| Error::Write(dest.to_owned(), error))?;
+
+    Ok(verified)
+}
+
+/// Write `stream` to `path`, hashing it along the way. If `offset` is
+/// non-zero, the stream continues the first `offset` bytes already at `path`.
+/// `on_position` is called with the bytes written so far, after each chunk
+async fn download(
+    stream: impl Stream<Item = Result<Bytes, Error>>,
+    path: &Path,
+    offset: u64,
+    on_position: impl Fn(u64),
+) -> Result<Verified, Error> {
+    let mut stream = std::pin::pin!(stream);
+
+    let write_error = |error| Error::Write(path.to_owned(), error);
+
+    let mut hasher = Digest::new(Algorithm::Sha256);
+    let mut out = if offset > 0 {
+        hash_file(path, offset, &mut hasher).await.map_err(write_error)?;
+        on_position(offset);
+
+        OpenOptions::new().append(true).open(path).await.map_err(write_error)?
+    } else {
+        File::create(path).await.map_err(write_error)?
+    };
+    let mut size = offset;
+
+    while let Some(chunk) = stream.next().await {
+        let bytes = chunk?;
+        size += bytes.len() as u64;
+        hasher.update(&bytes);
+        out.write_all(&bytes).await.map_err(write_error)?;
+        on_position(size);
+    }
+
+    out.flush().await.map_err(write_error)?;
+    out.sync_all().await.map_err(write_error)?;
+
+    Ok(Verified {
+        hash: hasher.finalize_hex(),
+        size,
+        resumed: offset,
+    })
+}
+
+/// Turn the positions of a download into the increments reported to `on_progress`.
+/// Bytes fetched again after a restarted attempt were reported already, so
+/// only those beyond the furthest position are
+fn increments(on_progress: impl Fn(u64)) -> impl Fn(u64) {
+    let furthest = AtomicU64::new(0);
+
+    move |position| {
+        let previous = furthest.fetch_max(position, Ordering::Relaxed);
+        if position > previous {
+            on_progress(position - previous);
+        }
+    }
+}
+
+/// Whether a download failing with `error` was cut off mid-stream,
+/// so it may be resumed from what was written
+fn is_interrupted(error: &Error) -> bool {
+    matches!(error, Error::Fetch(error) if error.is_body() || is_transient(error))
+}
+
+/// Feed the first `len` bytes of the file at `path` into `hasher`
+async fn hash_file(path: &Path, len: u64, hasher: &mut Digest) -> io::Result<()> {
+    let mut file = File::open(path).await?.take(len);
+    let mut buffer = vec![0; environment::FILE_READ_BUFFER_SIZE];
+
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        hasher.update(&buffer[..read]);
+    }
+}
+
+/// Where a download to `dest` is written until verified
+pub fn partial_path(dest: &Path) -> PathBuf {
+    let mut name = dest.file_name().unwrap_or_default().to_owned();
+    name.push(".part");
+    dest.with_file_name(name)
+}
+
+/// Internal range request helper for `get_from`
+async fn fetch_from(url: Url, offset: u64) -> Result<Partial, Error> {
+    if is_offline() {
+        return Err(Error::Offline(url));
+    }
+
+    debug!("GET {url} from byte {offset}");
+
+    let response = send(&url, || {
+        self::get_client()
+            .get(url.clone())
+            .header(header::RANGE, format!("bytes={offset}-"))
+            // Offsets refer to the encoded body, so it mustn't be transparently decoded
+            .header(header::ACCEPT_ENCODING, "identity")
+    })
+    .await?;
+
+    trace!("{} {url}", response.status());
+
+    let resumed = response.status() == StatusCode::PARTIAL_CONTENT
+        && response
+            .headers()
+            .get(header::CONTENT_RANGE)
+            .and_then(|range| range.to_str().ok())
+            .is_some_and(|range| range.starts_with(&format!("bytes {offset}-")));
+
+    if resumed {
+        return Ok(Partial {
+            offset,
+            stream: body(response).boxed(),
+        });
+    }
+
+    // Either ranges aren't supported, or what we have isn't a
+    // prefix of the resource anymore, so start over
+    if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        return Ok(Partial {
+            offset: 0,
+            stream: fetch(url).await?.boxed(),
+        });
+    }
+
+    Ok(Partial {
+        offset: 0,
+        stream: response.error_for_status().map(body).map_err(Error::Fetch)?.boxed(),
+    })
+}
+
+/// Internal fetch helper (sanity control) for `get`
+async fn fetch(url: Url) -> Result<impl Stream<Item = Result<Bytes, Error>>, Error> {
+    if is_offline() {
+        return Err(Error::Offline(url));
+    }
+
+    debug!("GET {url}");
+
+    let response = send(&url, || self::get_client().get(url.clone())).await?;
+
+    trace!("{} {url}", response.status());
+
+    response.error_for_status().map(body).map_err(Error::Fetch)
+}
+
+/// Response body, throttled to the configured [rate limits](set_rate_limit)
+fn body(response: reqwest::Response) -> impl Stream<Item = Result<Bytes, Error>> {
+    let host = response.url().host_str().map(str::to_owned);
+
+    limit::throttle(host, response.bytes_stream().map(|result| result.map_err(Error::Fetch)))
+}
+
+/// Send the (idempotent) request built by `request`, retrying
+/// transient failures with exponential backoff & jitter
+async fn send(url: &Url, request: impl Fn() -> reqwest::RequestBuilder) -> Result<reqwest::Response, Error> {
+    let mut attempt = 0;
+
+    loop {
+        let (reason, requested) = match request().send().await {
+            Ok(response) if attempt < retries() && is_transient_status(response.status()) => {
+                (response.status().to_string(), retry_after(&response))
+            }
+            Err(error) if attempt < retries() && is_transient(&error) => (error.to_string(), None),
+            result => return Ok(result?),
+        };
+
+        let delay = requested.unwrap_or_else(|| backoff(attempt)).min(MAX_RETRY_DELAY);
+        attempt += 1;
+
+        warn!("{url}: {reason}, retrying in {delay:?} ({attempt}/{})", retries());
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Whether a response with `status` may succeed when retried
+fn is_transient_status(status: StatusCode) -> bool {
+    status.is_server_error() || matches!(status, StatusCode::REQUEST_TIMEOUT | StatusCode::TOO_MANY_REQUESTS)
+}
+
+/// Whether a request failing with `error` may succeed when retried
+///
+/// Timeouts & interrupted connections are, while DNS and TLS failures aren't
+fn is_transient(error: &reqwest::Error) -> bool {
+    if let Some(status) = error.status() {
+        return is_transient_status(status);
+    }
+    if error.is_timeout() {
+        return true;
+    }
+
+    let mut source = error.source();
+    while let Some(inner) = source {
+        if let Some(error) = inner.downcast_ref::<io::Error>() {
+            return matches!(
+                error.kind(),
+                io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::TimedOut
+                    | io::ErrorKind::UnexpectedEof
+                    | io::ErrorKind::Interrupted
+            );
+        }
+        source = inner.source();
+    }
+
+    false
+}
+
+/// Delay requested by the server through the `Retry-After` header, in seconds
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    let seconds = response
+        .headers()
+        .get(header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(seconds))
+}
+
+/// Delay before retry number `attempt + 1`, randomly between half and all of
+/// the exponential delay so concurrent requests don't retry in lockstep
+fn backoff(attempt: u32) -> Duration {
+    let delay = exponential_delay(attempt);
+    let jitter = RandomState::new().build_hasher().finish() % 1000;
+
+    delay / 2 + delay / 2 * jitter as u32 / 1000
+}
+
+fn exponential_delay(attempt: u32) -> Duration {
+    RETRY_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(MAX_RETRY_DELAY)
+}
+
+/// Asynchronously read a filesystem path akin to the fetch API
+async fn read(path: PathBuf) -> Result<BoxStream<'static, Result<Bytes, Error>>, Error> {
+    trace!("read {}", path.display());
+
+    let mut file = File::open(path).await?;
+    let size = file.metadata().await?.len() as usize;
+
+    if size > environment::FILE_READ_CHUNK_THRESHOLD {
+        let stream = ReaderStream::with_capacity(file, environment::FILE_READ_BUFFER_SIZE);
+
+        Ok(stream.map(|result| result.map_err(Error::Read)).boxed())
+    } else {
+        let mut bytes = Vec::with_capacity(size);
+        file.read_to_end(&mut bytes).await?;
+
+        Ok(stream::once(async move { Ok(bytes.into()) }).boxed())
+    }
+}
+
+/// Read a filesystem path from `offset` onwards, akin to `fetch_from`
+async fn read_from(path: PathBuf, offset: u64) -> Result<Partial, Error> {
+    trace!("read {} from byte {offset}", path.display());
+
+    let mut file = File::open(path).await?;
+
+    let offset = if offset <= file.metadata().await?.len() {
+        offset
+    } else {
+        0
+    };
+    file.seek(io::SeekFrom::Start(offset)).await?;
+
+    Ok(Partial {
+        offset,
+        stream: ReaderStream::with_capacity(file, environment::FILE_READ_BUFFER_SIZE)
+            .map(|result| result.map_err(Error::Read))
+            .boxed(),
+    })
+}
+
+/// Specialise handling of `file://` URLs for fetching
+fn url_file(url: &Url) -> Option<PathBuf> {
+    if url.scheme() == "file" {
+        url.to_file_path().ok()
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("can't fetch {0} in offline mode")]
+    Offline(Url),
+    #[error("fetch")]
+    Fetch(#[from] reqwest::Error),
+    #[error("io")]
+    Read(#[from] io::Error),
+    #[error("invalid proxy {0}")]
+    Proxy(String, #[source] reqwest::Error),
+    #[error("read CA certificate {0:?}")]
+    ReadCertificate(PathBuf, #[source] io::Error),
+    #[error("invalid CA certificate {0:?}")]
+    InvalidCertificate(PathBuf, #[source] reqwest::Error),
+    #[error("build http client")]
+    Client(#[source] reqwest::Error),
+    #[error("write {0:?}")]
+    Write(PathBuf, #[source] io::Error),
+    #[error("checksum mismatch for {url}, expected {expected} got {actual}")]
+    HashMismatch { url: Url, expected: String, actual: String },
+    #[error("invalid range response from {0}")]
+    InvalidRange(Url),
+    #[error("decompress {0:?}")]
+    Decompress(Compression, #[source] io::Error),
+}
+
+impl Error {
+    /// Whether the requested resource doesn't exist
+    pub fn is_not_found(&self) -> bool {
+        match self {
+            Error::Fetch(error) => error.status() == Some(StatusCode::NOT_FOUND),
+            Error::Read(error) => error.kind() == io::ErrorKind::NotFound,
+            _ => false,
+        }
+    }
+
+    /// Whether the requested resource can't be fetched from where it was
+    /// requested, i.e. isn't found, is forbidden or the server keeps failing.
+    /// Another location of it may still be served
+    pub fn is_unavailable(&self) -> bool {
+        match self {
+            Error::Fetch(error) => error.status().is_some_and(is_unavailable_status),
+            _ => self.is_not_found(),
+        }
+    }
+}
+
+/// Whether a response with `status` means the resource isn't served there, see [`Error::is_unavailable`]
+fn is_unavailable_status(status: StatusCode) -> bool {
+    matches!(status, StatusCode::NOT_FOUND | StatusCode::FORBIDDEN) || status.is_server_error()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn backoff_bounds() {
+        for attempt in 0..20 {
+            let delay = exponential_delay(attempt);
+            let backoff = backoff(attempt);
+
+            assert!(
+                backoff >= delay / 2 && backoff <= delay,
+                "{backoff:?} for attempt {attempt}"
+            );
+        }
+    }
+
+    #[test]
+    fn transient_status() {
+        for status in [
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::BAD_GATEWAY,
+            StatusCode::SERVICE_UNAVAILABLE,
+            StatusCode::TOO_MANY_REQUESTS,
+        ] {
+            assert!(is_transient_status(status), "{status}");
+        }
+        for status in [StatusCode::NOT_FOUND, StatusCode::FORBIDDEN, StatusCode::OK] {
+            assert!(!is_transient_status(status), "{status}");
+        }
+    }
+
+    #[test]
+    fn unavailable_status() {
+        for status in [
+            StatusCode::NOT_FOUND,
+            StatusCode::FORBIDDEN,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::BAD_GATEWAY,
+        ] {
+            assert!(is_unavailable_status(status), "{status}");
+        }
+        for status in [StatusCode::UNAUTHORIZED, StatusCode::TOO_MANY_REQUESTS, StatusCode::OK] {
+            assert!(!is_unavailable_status(status), "{status}");
+        }
+    }
+
+    #[test]
+    fn local_urls() {
+        assert!(is_local(&Url::parse("file:///var/lib/repo/stone.index").unwrap()));
+        assert!(!is_local(
+            &Url::parse("https://cdn.serpentos.com/volatile/x86_64/stone.index").unwrap()
+        ));
+    }
+
+    #[test]
+    fn restarted_progress() {
+        let reported = AtomicU64::new(0);
+        let on_position = increments(|bytes| {
+            reported.fetch_add(bytes, Ordering::Relaxed);
+        });
+
+        for position in [5, 8, 3, 8, 11] {
+            on_position(position);
+        }
+
+        assert_eq!(reported.load(Ordering::Relaxed), 11);
+    }
+
+    #[test]
+    fn verified_download() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+
+        let source = dir.join("source");
+        fs::write(&source, b"hello world").unwrap();
+        let url = Url::from_file_path(&source).unwrap();
+        let hash = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+
+        let dest = dir.join("good");
+        let verified = runtime
+            .block_on(get_verified(url.clone(), Some(hash), &dest, |_| {}))
+            .unwrap();
+        assert_eq!(verified.hash, hash);
+        assert_eq!(verified.size, 11);
+        assert_eq!(fs::read(&dest).unwrap(), b"hello world");
+        assert!(!partial_path(&dest).exists());
+
+        // An interrupted download is resumed
+        let dest = dir.join("resumed");
+        fs::write(partial_path(&dest), b"hello").unwrap();
+        let verified = runtime
+            .block_on(get_verified(url.clone(), Some(hash), &dest, |_| {}))
+            .unwrap();
+        assert_eq!(verified.resumed, 5);
+        assert_eq!(fs::read(&dest).unwrap(), b"hello world");
+
+        let dest = dir.join("bad");
+        let result = runtime.block_on(get_verified(url, Some(&"0".repeat(64)), &dest, |_| {}));
+        assert!(matches!(result, Err(Error::HashMismatch { .. })));
+        assert!(!dest.exists());
+        assert!(!partial_path(&dest).exists());
+    }
+}
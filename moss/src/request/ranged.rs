@@ -0,0 +1,228 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Seekable reads of remote resources
+//!
+//! Reading a stone only seeks past its content payload, so fetching
+//! the blocks actually read with range requests gets its header & metadata
+//! without downloading the (much larger) content.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use bytes::{Bytes, BytesMut};
+use futures::StreamExt;
+use reqwest::{header, StatusCode};
+use tracing::{debug, trace};
+use url::Url;
+
+use super::{body, get_client, is_offline, send, Error};
+use crate::runtime;
+
+/// Bytes fetched per range request, enough for the header
+/// & metadata payloads of most stones in a single one
+const BLOCK_SIZE: u64 = 64 * 1024;
+
+/// A [`Read`] + [`Seek`] view of the remote resource at a [`Url`],
+/// fetching blocks with range requests as they're read
+///
+/// Reads block on the [`runtime`], so this must not be used from within it.
+/// Servers which don't support ranges send the whole resource, which is
+/// then read from memory instead.
+pub struct RangedReader {
+    url: Url,
+    len: u64,
+    position: u64,
+    /// Offset & bytes of the last fetched block
+    block: (u64, Bytes),
+    /// Total bytes fetched so far
+    fetched: u64,
+}
+
+impl RangedReader {
+    /// Open the resource at `url`, fetching its first block
+    pub fn open(url: Url) -> Result<Self, Error> {
+        let fetched = runtime::block_on(fetch_range(&url, 0, BLOCK_SIZE))?;
+
+        let len = match fetched.len {
+            Some(len) => len,
+            // Not a partial response, so we have all of it
+            None => fetched.bytes.len() as u64,
+        };
+
+        debug!("{url} is {len} bytes");
+
+        Ok(Self {
+            url,
+            len,
+            position: 0,
+            fetched: fetched.bytes.len() as u64,
+            block: (fetched.start, fetched.bytes),
+        })
+    }
+
+    /// Size of the resource in bytes
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Bytes fetched so far, which is the entire resource if
+    /// the server doesn't support range requests
+    pub fn fetched(&self) -> u64 {
+        self.fetched
+    }
+
+    /// The fetched block containing `position`, if any
+    fn buffered(&self) -> Option<&[u8]> {
+        let (start, bytes) = &self.block;
+        let offset = self.position.checked_sub(*start)?;
+
+        (offset < bytes.len() as u64).then(|| &bytes[offset as usize..])
+    }
+}
+
+impl Read for RangedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.position >= self.len {
+            return Ok(0);
+        }
+
+        if self.buffered().is_none() {
+            let end = (self.position + BLOCK_SIZE).min(self.len);
+            let fetched = runtime::block_on(fetch_range(&self.url, self.position, end - self.position))
+                .map_err(io::Error::other)?;
+
+            self.fetched += fetched.bytes.len() as u64;
+            self.block = (fetched.start, fetched.bytes);
+        }
+
+        let available = self
+            .buffered()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "range response ended early"))?;
+
+        let read = available.len().min(buf.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.position += read as u64;
+
+        Ok(read)
+    }
+}
+
+impl Seek for RangedReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        self.position =
+            position.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before start of resource"))?;
+
+        Ok(self.position)
+    }
+}
+
+/// A block of a resource, as sent by the server
+struct Fetched {
+    /// Offset of `bytes` within the resource
+    start: u64,
+    bytes: Bytes,
+    /// Size of the resource, if the response was partial
+    len: Option<u64>,
+}
+
+/// Fetch `len` bytes of `url` starting from `start`
+async fn fetch_range(url: &Url, start: u64, len: u64) -> Result<Fetched, Error> {
+    if is_offline() {
+        return Err(Error::Offline(url.clone()));
+    }
+
+    let range = format!("bytes={start}-{}", start + len.max(1) - 1);
+    debug!("GET {url} {range}");
+
+    let response = send(url, || {
+        get_client()
+            .get(url.clone())
+            .header(header::RANGE, range.as_str())
+            // Offsets refer to the encoded body, so it mustn't be transparently decoded
+            .header(header::ACCEPT_ENCODING, "identity")
+    })
+    .await?;
+
+    trace!("{} {url}", response.status());
+
+    let partial = match response.status() {
+        StatusCode::PARTIAL_CONTENT => Some(
+            response
+                .headers()
+                .get(header::CONTENT_RANGE)
+                .and_then(|range| range.to_str().ok())
+                .and_then(content_range)
+                .ok_or_else(|| Error::InvalidRange(url.clone()))?,
+        ),
+        _ => None,
+    };
+
+    let mut stream = body(response.error_for_status().map_err(Error::Fetch)?);
+    let mut bytes = BytesMut::new();
+    while let Some(chunk) = stream.next().await {
+        bytes.extend_from_slice(&chunk?);
+    }
+
+    let (start, len) = partial.map_or((0, None), |(start, len)| (start, Some(len)));
+
+    Ok(Fetched {
+        start,
+        bytes: bytes.freeze(),
+        len,
+    })
+}
+
+/// Start & total size from a `Content-Range` header, i.e. `bytes 0-1023/4096`
+fn content_range(value: &str) -> Option<(u64, u64)> {
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, _) = range.split_once('-')?;
+
+    Some((start.parse().ok()?, total.parse().ok()?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_content_range() {
+        assert_eq!(content_range("bytes 0-1023/4096"), Some((0, 4096)));
+        assert_eq!(content_range("bytes 65536-70000/70001"), Some((65536, 70001)));
+        // Total size unknown
+        assert_eq!(content_range("bytes 0-1023/*"), None);
+        assert_eq!(content_range("0-1023/4096"), None);
+    }
+
+    #[test]
+    fn seek_without_fetching() {
+        let mut reader = RangedReader {
+            url: "https://example.org/a.stone".parse().unwrap(),
+            len: 100,
+            position: 0,
+            block: (0, Bytes::from_static(&[1, 2, 3, 4])),
+            fetched: 4,
+        };
+
+        let mut buf = [0; 2];
+        reader.seek(SeekFrom::Start(2)).unwrap();
+        assert_eq!(reader.read(&mut buf).unwrap(), 2);
+        assert_eq!(buf, [3, 4]);
+
+        assert_eq!(reader.seek(SeekFrom::End(-10)).unwrap(), 90);
+        assert_eq!(reader.seek(SeekFrom::Current(10)).unwrap(), 100);
+        // At the end, nothing left to fetch
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+        assert!(reader.seek(SeekFrom::Current(-101)).is_err());
+    }
+}
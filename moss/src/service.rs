@@ -0,0 +1,482 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! D-Bus system service
+//!
+//! Exposes moss operations on the system bus as [`BUS_NAME`], so desktop
+//! frontends & installers can drive moss without a root shell. Every call
+//! is [`authorization::authorize`]d against the polkit [`Action`] it needs,
+//! and may interactively authenticate the caller.
+//!
+//! Plans are returned as the same versioned JSON [`output::Document`]s
+//! `moss --format json` prints, so frontends need only one parser.
+//!
+//! Only built with the `service` feature.
+
+use thiserror::Error;
+use tokio::sync::Mutex;
+use zbus::{fdo, interface, message::Header, Connection, SignalContext};
+
+use crate::{
+    authorization::{self, Action, Authorization, Subject},
+    client::{self, install, plan::Plan, remove, sync, Client},
+    environment, output, runtime, state, Installation, Package, Provider, State,
+};
+
+/// Well-known name of the service on the system bus
+pub const BUS_NAME: &str = "com.serpentos.moss";
+/// Path of the [`Service`] object
+pub const OBJECT_PATH: &str = "/com/serpentos/moss";
+
+/// Name of the [`Service`] interface
+pub const INTERFACE: &str = "com.serpentos.moss1";
+
+/// Stage of a transaction, emitted as `Progress` signals
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum Stage {
+    Resolving,
+    /// Fetching packages, with the `plan` document as message
+    Fetching,
+    /// Blitting the new state
+    Applying,
+}
+
+/// Decides whether a caller may perform an [`Action`], see [`authorization::authorize`]
+pub type Authorizer = fn(Action, &Subject, bool) -> Result<Authorization, authorization::Error>;
+
+/// The `com.serpentos.moss1` interface
+pub struct Service {
+    installation: Installation,
+    authorizer: Authorizer,
+    /// Held while changing the installation, so transactions don't interleave
+    transaction: Mutex<()>,
+}
+
+impl Service {
+    /// Serve `installation`, authorizing callers with `authorizer`
+    pub fn new(installation: Installation, authorizer: Authorizer) -> Self {
+        Self {
+            installation,
+            authorizer,
+            transaction: Mutex::new(()),
+        }
+    }
+}
+
+/// Serve [`Service`] for `installation` on the system bus until the
+/// returned connection is dropped
+pub async fn serve(installation: Installation) -> Result<Connection, Error> {
+    let service = Service::new(installation, authorization::authorize);
+
+    Ok(zbus::connection::Builder::system()?
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, service)?
+        .build()
+        .await?)
+}
+
+#[interface(name = "com.serpentos.moss1")]
+impl Service {
+    /// Configured repositories as `(id, uri, description, priority)`
+    async fn list_repos(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> fdo::Result<Vec<(String, String, String, u64)>> {
+        self.authorize(&header, connection, Action::Query).await?;
+
+        self.with_client(|client| {
+            Ok(client
+                .repositories()
+                .map(|(id, repo)| {
+                    (
+                        id.to_string(),
+                        repo.uri.to_string(),
+                        repo.description.clone(),
+                        repo.priority.into(),
+                    )
+                })
+                .collect())
+        })
+        .await
+    }
+
+    /// Refresh the indices of all repositories
+    async fn refresh(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> fdo::Result<()> {
+        self.authorize(&header, connection, Action::Refresh).await?;
+
+        self.with_client(|client| Ok(runtime::block_on(client.refresh_repositories())?))
+            .await
+    }
+
+    /// The `plan` document for installing `packages`, without applying it
+    async fn resolve(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+        packages: Vec<String>,
+    ) -> fdo::Result<String> {
+        self.authorize(&header, connection, Action::Query).await?;
+
+        self.with_client(move |client| {
+            let packages = packages.iter().map(String::as_str).collect::<Vec<_>>();
+            let plan = client.resolve(&packages)?;
+
+            Ok(output::to_json_line("plan", plan)?)
+        })
+        .await
+    }
+
+    /// Install `packages` and their dependencies, returning the id of
+    /// the new state, or 0 if they're installed already
+    async fn apply_transaction(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        packages: Vec<String>,
+    ) -> fdo::Result<u64> {
+        self.transaction(&header, connection, ctxt, move |client, progress| {
+            let packages = packages.iter().map(String::as_str).collect::<Vec<_>>();
+            let resolution = install::resolve(client, &packages)?;
+            if resolution.missing.is_empty() {
+                return Ok(None);
+            }
+
+            let plan = Plan::new(client, &resolution.missing, &resolution.conflicting);
+            progress(Stage::Fetching, output::to_json_line("plan", plan)?);
+            let selections = install::selections(client, &resolution)?;
+            client.fetch_transaction(&resolution.missing, &selections, "Install")?;
+
+            progress(Stage::Applying, String::default());
+            Ok(client.new_state(&selections, "Install")?)
+        })
+        .await
+    }
+
+    /// Remove `packages`, returning the id of the new state. Unless `cascade`
+    /// is set, packages depending on them are refused rather than removed
+    async fn remove(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        packages: Vec<String>,
+        cascade: bool,
+    ) -> fdo::Result<u64> {
+        self.transaction(&header, connection, ctxt, move |client, progress| {
+            let providers = packages
+                .iter()
+                .map(|name| Provider::from_name(name).map_err(|_| Error::InvalidName(name.clone())))
+                .collect::<Result<Vec<_>, _>>()?;
+            let resolution = remove::resolve(client, &providers)?;
+            if !resolution.dependents.is_empty() && !cascade {
+                return Err(Error::RequiredBy(
+                    resolution.dependents.iter().map(|p| p.meta.name.to_string()).collect(),
+                ));
+            }
+
+            let plan = Plan::new(client, &[] as &[Package], &resolution.removed);
+            progress(Stage::Fetching, output::to_json_line("plan", plan)?);
+            let selections = remove::selections(client, &resolution)?;
+
+            progress(Stage::Applying, String::default());
+            Ok(client.new_state(&selections, "Remove")?)
+        })
+        .await
+    }
+
+    /// Sync installed packages with the highest priority repository, returning
+    /// the id of the new state, or 0 if there's nothing to sync
+    async fn sync(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        upgrade_only: bool,
+    ) -> fdo::Result<u64> {
+        self.transaction(&header, connection, ctxt, move |client, progress| {
+            let resolved = sync::resolve(client, upgrade_only)?;
+            let (synced, removed) = resolved.changes();
+            if synced.is_empty() && removed.is_empty() {
+                return Ok(None);
+            }
+
+            let plan = Plan::new(client, &synced, &removed);
+            progress(Stage::Fetching, output::to_json_line("plan", plan)?);
+            let selections = sync::selections(client, &resolved.installed, resolved.finalized.clone())?;
+            client.fetch_transaction(&synced, &selections, "Sync")?;
+
+            progress(Stage::Applying, String::default());
+            Ok(client.new_state(&selections, "Sync")?)
+        })
+        .await
+    }
+
+    /// All states as `(id, summary, created, active)`, with
+    /// `created` in seconds since the epoch
+    async fn list_states(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> fdo::Result<Vec<(u64, String, i64, bool)>> {
+        self.authorize(&header, connection, Action::Query).await?;
+
+        self.with_client(|client| {
+            let active = client.installation.active_state;
+
+            Ok(client
+                .state_db
+                .all()?
+                .into_iter()
+                .map(|state| {
+                    (
+                        i32::from(state.id) as u64,
+                        state.summary.unwrap_or_default(),
+                        state.created.timestamp(),
+                        Some(state.id) == active,
+                    )
+                })
+                .collect())
+        })
+        .await
+    }
+
+    /// Activate the previous state `id`, archiving the active one
+    async fn rollback(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+        id: u64,
+    ) -> fdo::Result<()> {
+        self.authorize(&header, connection, Action::Transaction).await?;
+
+        let _transaction = self.transaction.lock().await;
+
+        self.with_client(move |client| {
+            let id = state::Id::from(id as i32);
+            let active = client.installation.active_state.ok_or(Error::NoActiveState)?;
+            if id >= active {
+                return Err(Error::NotPrevious(id, active));
+            }
+
+            client.activate_state(id)?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Emitted at each [`Stage`] of `ApplyTransaction`, `Remove` & `Sync`
+    #[zbus(signal)]
+    async fn progress(ctxt: &SignalContext<'_>, stage: &str, message: &str) -> zbus::Result<()>;
+}
+
+impl Service {
+    /// Run `f` with a fresh client on the blocking executor
+    async fn with_client<T, F>(&self, f: F) -> fdo::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Client) -> Result<T, Error> + Send + 'static,
+    {
+        let mut installation = self.installation.clone();
+
+        runtime::unblock(move || {
+            // Transactions made since we started change the active state
+            installation.reload_active_state();
+
+            let mut client = Client::new(environment::NAME, installation)?;
+            f(&mut client)
+        })
+        .await
+        .map_err(failed)
+    }
+
+    /// Run the transaction `f` once authorized & no other is running, returning the
+    /// id of the state it records or 0 if there's nothing to change. `f` is passed
+    /// a function emitting `Progress` signals
+    async fn transaction<F>(
+        &self,
+        header: &Header<'_>,
+        connection: &Connection,
+        ctxt: SignalContext<'_>,
+        f: F,
+    ) -> fdo::Result<u64>
+    where
+        F: FnOnce(&mut Client, &dyn Fn(Stage, String)) -> Result<Option<State>, Error> + Send + 'static,
+    {
+        self.authorize(header, connection, Action::Transaction).await?;
+
+        let _transaction = self.transaction.lock().await;
+        let ctxt = ctxt.to_owned();
+
+        self.with_client(move |client| {
+            let progress = |stage: Stage, message: String| {
+                // Frontends missing a signal isn't worth failing the transaction over
+                let _ = runtime::block_on(Service::progress(&ctxt, &stage.to_string(), &message));
+            };
+
+            progress(Stage::Resolving, String::default());
+            let state = f(client, &progress)?;
+
+            Ok(state.map_or(0, |state| i32::from(state.id) as u64))
+        })
+        .await
+    }
+
+    /// Authorize the sender of the call with `header` for `action`
+    async fn authorize(&self, header: &Header<'_>, connection: &Connection, action: Action) -> fdo::Result<()> {
+        let subject = match header.sender() {
+            Some(sender) => Subject::BusName(sender.to_string()),
+            // Peer to peer connections have no bus assigned sender, so the peer process is authorized instead
+            None => {
+                let credentials = connection.peer_credentials().await.map_err(failed)?;
+                let pid = credentials
+                    .process_id()
+                    .ok_or_else(|| fdo::Error::AccessDenied("unknown peer".to_string()))?;
+                Subject::process(pid).map_err(failed)?
+            }
+        };
+
+        let authorizer = self.authorizer;
+        let authorization = runtime::unblock(move || authorizer(action, &subject, true))
+            .await
+            .map_err(failed)?;
+
+        if authorization.is_authorized() {
+            Ok(())
+        } else {
+            Err(fdo::Error::AccessDenied(format!(
+                "{} not authorized ({authorization:?})",
+                action.id()
+            )))
+        }
+    }
+}
+
+/// Report `error` to the caller along with its sources
+fn failed(error: impl std::error::Error) -> fdo::Error {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        message.push_str(&format!(": {error}"));
+        source = error.source();
+    }
+
+    fdo::Error::Failed(message)
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("no active state to roll back from")]
+    NoActiveState,
+    #[error("state {0} is not older than the active state {1}")]
+    NotPrevious(state::Id, state::Id),
+    #[error("invalid package name: {0}")]
+    InvalidName(String),
+    #[error("packages are required by: {}", .0.join(", "))]
+    RequiredBy(Vec<String>),
+    #[error("client")]
+    Client(#[from] client::Error),
+    #[error("install")]
+    Install(#[from] install::Error),
+    #[error("remove")]
+    Remove(#[from] remove::Error),
+    #[error("sync")]
+    Sync(#[from] sync::Error),
+    #[error("db")]
+    Db(#[from] crate::db::Error),
+    #[error("authorization")]
+    Authorization(#[from] authorization::Error),
+    #[error("json")]
+    Json(#[from] serde_json::Error),
+    #[error("d-bus")]
+    Bus(#[from] zbus::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::net::UnixStream;
+    use zbus::{connection::Builder, Guid};
+
+    use super::*;
+
+    /// Authorize everything but refreshing
+    fn authorizer(
+        action: Action,
+        _subject: &Subject,
+        _interactive: bool,
+    ) -> Result<Authorization, authorization::Error> {
+        Ok(match action {
+            Action::Refresh => Authorization::Denied,
+            Action::Query | Action::Transaction => Authorization::Authorized,
+        })
+    }
+
+    async fn call<B>(client: &Connection, method: &str, body: &B) -> fdo::Result<zbus::Message>
+    where
+        B: serde::Serialize + zbus::zvariant::DynamicType,
+    {
+        client
+            .call_method(None::<&str>, OBJECT_PATH, Some(INTERFACE), method, body)
+            .await
+            .map_err(fdo::Error::from)
+    }
+
+    #[test]
+    fn dispatch() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let installation = Installation::open(root).unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let (server, client) = UnixStream::pair().unwrap();
+            let server = Builder::unix_stream(server)
+                .server(Guid::generate())
+                .unwrap()
+                .p2p()
+                .serve_at(OBJECT_PATH, Service::new(installation, authorizer))
+                .unwrap()
+                .build();
+            let client = Builder::unix_stream(client).p2p().build();
+            let (_server, client) = futures::try_join!(server, client).unwrap();
+
+            let repos = call(&client, "ListRepos", &()).await.unwrap();
+            assert!(repos
+                .body()
+                .deserialize::<Vec<(String, String, String, u64)>>()
+                .unwrap()
+                .is_empty());
+
+            let states = call(&client, "ListStates", &()).await.unwrap();
+            assert!(states
+                .body()
+                .deserialize::<Vec<(u64, String, i64, bool)>>()
+                .unwrap()
+                .is_empty());
+
+            assert!(matches!(
+                call(&client, "Refresh", &()).await,
+                Err(fdo::Error::AccessDenied(_))
+            ));
+            assert!(matches!(
+                call(&client, "Rollback", &1u64).await,
+                Err(fdo::Error::Failed(message)) if message == "no active state to roll back from"
+            ));
+            assert!(matches!(
+                call(&client, "Frobnicate", &()).await,
+                Err(fdo::Error::UnknownMethod(_))
+            ));
+        });
+    }
+}
@@ -0,0 +1,263 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Global moss settings
+//!
+//! Settings are layered from `usr/share/moss/config.d/*.yaml` (vendor) and then
+//! `etc/moss/config.d/*.yaml` (admin), each directory in file name order. Later
+//! layers override earlier ones key by key, i.e. an admin may only set `offline`
+//! and keep every other vendor default:
+//!
+//! ```yaml
+//! offline: true
+//! ```
+//!
+//! `moss config set` writes to [`ADMIN_NAME`] within the admin layer.
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+use thiserror::Error;
+
+use config::Config;
+
+use crate::request;
+
+/// Name of the admin layer file managed by `moss config`
+pub const ADMIN_NAME: &str = "moss";
+
+/// All known settings keys
+pub const KEYS: &[&str] = &[
+    "network_concurrency",
+    "network_retries",
+    "limit_rate",
+    "limit_rate_per_host",
+    "unpack_workers",
+    "cache_dir",
+    "color",
+    "keep_states",
+    "assume_yes",
+    "offline",
+];
+
+/// Global settings, stored as `etc/moss/config.d/{name}.yaml`
+///
+/// Unset keys fall back to their built-in default
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Settings {
+    /// Max number of concurrent downloads
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network_concurrency: Option<usize>,
+    /// Times a request is retried after a transient failure
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network_retries: Option<u32>,
+    /// Max aggregate download rate, i.e. `2M` bytes per second
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit_rate: Option<request::Rate>,
+    /// Max download rate from each host
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit_rate_per_host: Option<request::Rate>,
+    /// Max number of threads unpacking downloaded packages, `0` for all cores.
+    /// `MOSS_UNPACK_WORKERS` takes precedence
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unpack_workers: Option<usize>,
+    /// Download cache location, instead of the one within the root
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_dir: Option<PathBuf>,
+    /// When to style output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<Color>,
+    /// Prune old states after each transaction, keeping this many
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_states: Option<u64>,
+    /// Answer every confirmation with yes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub assume_yes: Option<bool>,
+    /// Forbid all network access
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offline: Option<bool>,
+}
+
+impl Config for Settings {
+    fn domain() -> String {
+        "config".into()
+    }
+}
+
+/// When output is styled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Color {
+    /// Only when attached to a terminal
+    Auto,
+    Always,
+    Never,
+}
+
+impl From<Color> for tui::ColorChoice {
+    fn from(color: Color) -> Self {
+        match color {
+            Color::Auto => tui::ColorChoice::Auto,
+            Color::Always => tui::ColorChoice::Always,
+            Color::Never => tui::ColorChoice::Never,
+        }
+    }
+}
+
+impl Settings {
+    /// Merge all layers, later ones overriding earlier ones key by key
+    pub fn load(config: &config::Manager) -> Self {
+        config.load::<Self>().into_iter().fold(Self::default(), Self::merge)
+    }
+
+    /// The admin layer file managed by `moss config`
+    pub fn load_admin(config: &config::Manager) -> Self {
+        config.load_saved(ADMIN_NAME).unwrap_or_default()
+    }
+
+    /// Save as the admin layer file managed by `moss config`
+    pub fn save_admin(&self, config: &config::Manager) -> Result<(), Error> {
+        Ok(config.save(ADMIN_NAME, self)?)
+    }
+
+    fn merge(self, other: Self) -> Self {
+        Self {
+            network_concurrency: other.network_concurrency.or(self.network_concurrency),
+            network_retries: other.network_retries.or(self.network_retries),
+            limit_rate: other.limit_rate.or(self.limit_rate),
+            limit_rate_per_host: other.limit_rate_per_host.or(self.limit_rate_per_host),
+            unpack_workers: other.unpack_workers.or(self.unpack_workers),
+            cache_dir: other.cache_dir.or(self.cache_dir),
+            color: other.color.or(self.color),
+            keep_states: other.keep_states.or(self.keep_states),
+            assume_yes: other.assume_yes.or(self.assume_yes),
+            offline: other.offline.or(self.offline),
+        }
+    }
+
+    /// All set keys & their values, in [`KEYS`] order
+    pub fn list(&self) -> Result<Vec<(&'static str, String)>, Error> {
+        let mapping = self.to_mapping()?;
+
+        KEYS.iter()
+            .filter_map(|key| Some((*key, mapping.get(*key)?)))
+            .map(|(key, value)| Ok((key, render(value)?)))
+            .collect()
+    }
+
+    /// The value of `key`, if set
+    pub fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        let key = known(key)?;
+
+        self.to_mapping()?.get(key).map(render).transpose()
+    }
+
+    /// Set `key` to `value`, parsed as yaml
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        let key = known(key)?;
+        let parsed = serde_yaml::from_str::<Value>(value)?;
+
+        let mut mapping = self.to_mapping()?;
+        mapping.insert(key.into(), parsed);
+
+        *self = serde_yaml::from_value(Value::Mapping(mapping))
+            .map_err(|error| Error::InvalidValue(key.to_string(), value.to_string(), error))?;
+
+        Ok(())
+    }
+
+    /// Remove `key`, restoring its default. Returns false if it wasn't set
+    pub fn unset(&mut self, key: &str) -> Result<bool, Error> {
+        let key = known(key)?;
+
+        let mut mapping = self.to_mapping()?;
+        let removed = mapping.remove(key).is_some();
+
+        *self = serde_yaml::from_value(Value::Mapping(mapping))?;
+
+        Ok(removed)
+    }
+
+    fn to_mapping(&self) -> Result<Mapping, Error> {
+        match serde_yaml::to_value(self)? {
+            Value::Mapping(mapping) => Ok(mapping),
+            _ => Ok(Mapping::default()),
+        }
+    }
+}
+
+fn known(key: &str) -> Result<&'static str, Error> {
+    KEYS.iter()
+        .copied()
+        .find(|known| *known == key)
+        .ok_or_else(|| Error::UnknownKey(key.to_string()))
+}
+
+/// Single line rendering of a yaml value
+fn render(value: &Value) -> Result<String, Error> {
+    Ok(serde_yaml::to_string(value)?.trim_end().to_string())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("unknown setting {0}, expected one of: {}", KEYS.join(", "))]
+    UnknownKey(String),
+    #[error("invalid value {1:?} for {0}")]
+    InvalidValue(String, String, #[source] serde_yaml::Error),
+    #[error("yaml")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("save config")]
+    Save(#[from] config::SaveError),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn layered() {
+        let vendor = Settings {
+            network_concurrency: Some(4),
+            offline: Some(false),
+            ..Default::default()
+        };
+        let admin = Settings {
+            offline: Some(true),
+            ..Default::default()
+        };
+
+        let merged = [vendor, admin].into_iter().fold(Settings::default(), Settings::merge);
+
+        assert_eq!(merged.network_concurrency, Some(4));
+        assert_eq!(merged.offline, Some(true));
+        assert_eq!(merged.color, None);
+    }
+
+    #[test]
+    fn get_set() {
+        let mut settings = Settings::default();
+
+        settings.set("color", "never").unwrap();
+        settings.set("keep_states", "5").unwrap();
+        assert_eq!(settings.color, Some(Color::Never));
+        assert_eq!(settings.get("keep_states").unwrap().as_deref(), Some("5"));
+        assert_eq!(settings.get("offline").unwrap(), None);
+        assert_eq!(
+            settings.list().unwrap(),
+            vec![("color", "never".to_string()), ("keep_states", "5".to_string())]
+        );
+
+        assert!(matches!(
+            settings.set("keep_states", "lots"),
+            Err(Error::InvalidValue(..))
+        ));
+        assert!(matches!(settings.set("colour", "never"), Err(Error::UnknownKey(_))));
+        assert_eq!(settings.keep_states, Some(5));
+
+        assert!(settings.unset("color").unwrap());
+        assert!(!settings.unset("color").unwrap());
+        assert_eq!(settings.color, None);
+    }
+}
@@ -0,0 +1,225 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Package signatures
+//!
+//! Stones are signed with an Ed25519 key at build time, see [`SigningKey`].
+//! Repositories list the public keys they trust, in which case every package
+//! installed from them must carry a valid signature by one of those keys.
+//! Repositories without any trusted keys aren't verified.
+
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use ed25519_dalek::{Signer, Verifier};
+use serde::{de, Deserialize, Serialize};
+use stone::payload::{self, signature::Algorithm};
+use thiserror::Error;
+
+/// Whether signatures are verified, see [`set_verify`]
+static VERIFY: AtomicBool = AtomicBool::new(true);
+
+/// Skip signature verification, i.e. to install locally built, unsigned
+/// packages from a repository which trusts keys during development
+pub fn set_verify(verify: bool) {
+    VERIFY.store(verify, Ordering::Relaxed);
+}
+
+/// Whether signatures are verified
+pub fn is_verify() -> bool {
+    VERIFY.load(Ordering::Relaxed)
+}
+
+/// Secret key packages are signed with
+///
+/// Stored as the hex encoded 32 byte seed, i.e. as generated with
+/// `head -c 32 /dev/urandom | od -An -tx1 | tr -d ' \n'`
+pub struct SigningKey(ed25519_dalek::SigningKey);
+
+impl SigningKey {
+    /// Read the key stored at `path`
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        let contents = fs::read_to_string(path).map_err(|error| Error::ReadKey(path.to_owned(), error))?;
+
+        let seed = decode_key(contents.trim()).ok_or_else(|| Error::InvalidKey(path.to_owned()))?;
+
+        Ok(Self(ed25519_dalek::SigningKey::from_bytes(&seed)))
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey(self.0.verifying_key())
+    }
+
+    /// Signer for [`stone::Writer::with_signer`]
+    pub fn signer(&self) -> impl Fn(&[u8; 32]) -> payload::Signature + Send + 'static {
+        let key = self.0.clone();
+
+        move |digest| payload::Signature {
+            algorithm: Algorithm::Ed25519,
+            public_key: key.verifying_key().to_bytes().to_vec(),
+            signature: key.sign(digest).to_bytes().to_vec(),
+        }
+    }
+}
+
+/// A trusted public key, written hex encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PublicKey(ed25519_dalek::VerifyingKey);
+
+impl PublicKey {
+    /// Whether `signature` is a valid signature of `digest` by this key
+    fn verifies(&self, digest: &[u8; 32], signature: &payload::Signature) -> bool {
+        let Ok(signature) = ed25519_dalek::Signature::from_slice(&signature.signature) else {
+            return false;
+        };
+
+        self.0.verify(digest, &signature).is_ok()
+    }
+}
+
+impl FromStr for PublicKey {
+    type Err = ParseKeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = decode_key(s).ok_or_else(|| ParseKeyError(s.to_string()))?;
+
+        ed25519_dalek::VerifyingKey::from_bytes(&bytes)
+            .map(Self)
+            .map_err(|_| ParseKeyError(s.to_string()))
+    }
+}
+
+impl fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0.as_bytes()))
+    }
+}
+
+impl Serialize for PublicKey {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for PublicKey {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+}
+
+/// 32 bytes from their hex encoding
+fn decode_key(s: &str) -> Option<[u8; 32]> {
+    hex::decode(s).ok()?.try_into().ok()
+}
+
+/// Verify the stone at `path` is signed by one of the `trusted` keys,
+/// returning the key which signed it
+pub fn verify(path: &Path, trusted: &[PublicKey]) -> Result<PublicKey, Error> {
+    let file = fs::File::open(path).map_err(|error| Error::Open(path.to_owned(), error))?;
+    let signed = stone::read(file)?.signed()?.ok_or(Error::Unsigned)?;
+
+    let mut signers = vec![];
+
+    for signature in &signed.signatures {
+        let key = match signature.algorithm {
+            Algorithm::Ed25519 => <[u8; 32]>::try_from(signature.public_key.as_slice())
+                .ok()
+                .and_then(|bytes| ed25519_dalek::VerifyingKey::from_bytes(&bytes).ok())
+                .map(PublicKey),
+        };
+        let Some(key) = key else {
+            continue;
+        };
+
+        if !trusted.contains(&key) {
+            signers.push(key.to_string());
+            continue;
+        }
+
+        return if key.verifies(&signed.digest, signature) {
+            Ok(key)
+        } else {
+            Err(Error::Invalid(key))
+        };
+    }
+
+    Err(Error::Untrusted(signers.join(", ")))
+}
+
+#[derive(Debug, Error)]
+#[error("invalid public key {0:?}, expected 64 hex characters")]
+pub struct ParseKeyError(String);
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("read signing key {0:?}")]
+    ReadKey(PathBuf, #[source] io::Error),
+    #[error("invalid signing key {0:?}, expected 64 hex characters")]
+    InvalidKey(PathBuf),
+    #[error("open {0:?}")]
+    Open(PathBuf, #[source] io::Error),
+    #[error("stone format")]
+    Format(#[from] stone::read::Error),
+    #[error("package is not signed")]
+    Unsigned,
+    #[error("package is signed by untrusted keys: {0}")]
+    Untrusted(String),
+    #[error("invalid signature by trusted key {0}, the package may have been tampered with")]
+    Invalid(PublicKey),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sign_and_verify() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+
+        let key_path = dir.join("key");
+        fs::write(&key_path, format!("{}\n", "42".repeat(32))).unwrap();
+        let key = SigningKey::read(&key_path).unwrap();
+        let public = key.public_key();
+
+        let stone_path = dir.join("signed.stone");
+        let mut writer = stone::Writer::new(
+            fs::File::create(&stone_path).unwrap(),
+            stone::header::v1::FileType::Binary,
+        )
+        .unwrap()
+        .with_signer(key.signer());
+        writer
+            .add_payload(
+                [stone::payload::Meta {
+                    tag: stone::payload::meta::Tag::Name,
+                    kind: stone::payload::meta::Kind::String("nano".into()),
+                }]
+                .as_slice(),
+            )
+            .unwrap();
+        writer.finalize().unwrap();
+
+        assert_eq!(verify(&stone_path, &[public]).unwrap(), public);
+
+        let other = SigningKey(ed25519_dalek::SigningKey::from_bytes(&[0x11; 32])).public_key();
+        assert!(matches!(verify(&stone_path, &[other]), Err(Error::Untrusted(_))));
+
+        // Flip a bit of the signed meta payload header
+        let tampered_path = dir.join("tampered.stone");
+        let mut tampered = fs::read(&stone_path).unwrap();
+        tampered[stone::Header::SIZE + 8] ^= 1;
+        fs::write(&tampered_path, tampered).unwrap();
+        assert!(matches!(verify(&tampered_path, &[public]), Err(Error::Invalid(key)) if key == public));
+
+        let unsigned = Path::new("../test/bash-completion-2.11-1-1-x86_64.stone");
+        assert!(matches!(verify(unsigned, &[public]), Err(Error::Unsigned)));
+
+        assert_eq!(public.to_string().parse::<PublicKey>().unwrap(), public);
+    }
+}
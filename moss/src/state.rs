@@ -2,7 +2,7 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::io::Write;
+use std::{io::Write, path::PathBuf};
 
 use chrono::{DateTime, Utc};
 use derive_more::{Display, From, Into};
@@ -94,6 +94,84 @@ impl Selection {
     }
 }
 
+/// `selections` with `packages` marked as explicitly requested or not, for
+/// a new state recording the change. `None` if none of them change
+pub fn mark(selections: &[Selection], packages: &[package::Id], explicit: bool) -> Option<Vec<Selection>> {
+    if !selections
+        .iter()
+        .any(|s| packages.contains(&s.package) && s.explicit != explicit)
+    {
+        return None;
+    }
+
+    Some(
+        selections
+            .iter()
+            .map(|selection| {
+                if packages.contains(&selection.package) {
+                    Selection {
+                        explicit,
+                        ..selection.clone()
+                    }
+                } else {
+                    selection.clone()
+                }
+            })
+            .collect(),
+    )
+}
+
+/// A recorded transaction which transitioned the system to a new [`State`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transaction {
+    /// Sequential identifier within the history
+    pub id: i32,
+    /// When the transaction was applied
+    pub created: DateTime<Utc>,
+    /// Command line which applied the transaction
+    pub command: String,
+    /// Quick summary of the transaction (optional)
+    pub summary: Option<String>,
+    /// State that was active before the transaction, if any
+    pub previous: Option<Id>,
+    /// State produced by the transaction
+    pub state: Id,
+    /// Packages added by the transaction
+    pub added: Vec<package::Id>,
+    /// Packages removed by the transaction
+    pub removed: Vec<package::Id>,
+}
+
+/// Filesystems which can be snapshotted before a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display, strum::EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum Filesystem {
+    Btrfs,
+    Zfs,
+}
+
+impl TryFrom<String> for Filesystem {
+    type Error = strum::ParseError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// A filesystem snapshot taken while a [`State`] was active, right
+/// before a transaction moved away from it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    /// State the snapshot restores
+    pub state: Id,
+    pub filesystem: Filesystem,
+    /// Mount point of the snapshotted subvolume or dataset
+    pub subvolume: PathBuf,
+    /// Path of a btrfs snapshot, or the `dataset@snapshot` name of a zfs one
+    pub name: String,
+    pub created: DateTime<Utc>,
+}
+
 /// Columnar display encapsulation for a [`State`]
 pub struct ColumnDisplay<'a>(pub &'a State);
 
@@ -106,3 +184,26 @@ impl<'a> pretty::ColumnDisplay for ColumnDisplay<'a> {
         let _ = write!(writer, "State {}{:width$}", self.0.id.to_string().bold(), " ",);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn marking() {
+        let selections = vec![
+            Selection::explicit(package::Id::from("pkg a".to_string())),
+            Selection::explicit(package::Id::from("pkg b".to_string())).reason("requested"),
+        ];
+
+        let marked = [package::Id::from("pkg b".to_string())];
+        let selections = mark(&selections, &marked, false).unwrap();
+        assert_eq!(
+            selections.iter().map(|s| s.explicit).collect::<Vec<_>>(),
+            vec![true, false]
+        );
+        assert_eq!(selections[1].reason.as_deref(), Some("requested"));
+
+        assert_eq!(mark(&selections, &marked, false), None);
+    }
+}